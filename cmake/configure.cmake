@@ -4,6 +4,7 @@
 #  - BUILD_NVRAM_EXPORT
 #  - BUILD_UDP_SEND (default OFF)
 #  - BUILD_UDP_RECV (default OFF)
+#  - BUILD_UDP_PROBE (default OFF)
 #  - ENABLE_LARGEFILE
 #  - ENABLE_LINUX_ETH
 #  - ENABLE_GEN_ETH
@@ -88,7 +89,8 @@ print_variables ( DYNAMIPS_RENAME DYNAMIPS_RENAME_TARGET )
 option ( BUILD_NVRAM_EXPORT "build the nvram_export executable" ON )
 option ( BUILD_UDP_SEND "build the udp_send executable" OFF )
 option ( BUILD_UDP_RECV "build the udp_recv executable" OFF )
-print_variables ( BUILD_NVRAM_EXPORT BUILD_UDP_SEND BUILD_UDP_RECV )
+option ( BUILD_UDP_PROBE "build the udp_probe executable" OFF )
+print_variables ( BUILD_NVRAM_EXPORT BUILD_UDP_SEND BUILD_UDP_RECV BUILD_UDP_PROBE )
 
 # ENABLE_LARGEFILE
 if ( LIBELF_LARGEFILE )
@@ -179,6 +181,7 @@ macro ( print_summary )
    message ( "  BUILD_NVRAM_EXPORT                 : ${BUILD_NVRAM_EXPORT}" )
    message ( "  BUILD_UDP_SEND                     : ${BUILD_UDP_SEND}" )
    message ( "  BUILD_UDP_RECV                     : ${BUILD_UDP_RECV}" )
+   message ( "  BUILD_UDP_PROBE                    : ${BUILD_UDP_PROBE}" )
    if ( DEFINED ENABLE_LARGEFILE )
       set ( _largefile "ENABLE_LARGEFILE=${ENABLE_LARGEFILE}" )
    else ()
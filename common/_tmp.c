@@ -41,21 +41,37 @@ char *dyn_sprintf(const char *fmt,...)
    }
 }
 
-/* Logging function */
-void m_flog(FILE *fd,char *module,char *fmt,va_list ap)
+/* Logging function with a level and the VM fields (vm_name can be NULL) */
+void m_flog_lvl(FILE *fd,int level,char *vm_name,int instance_id,
+                char *module,char *fmt,va_list ap)
 {
-   struct timespec spec;
-   struct tm tmn;
+   char buffer[512],*msg = buffer;
+   va_list aq;
+   int n;
 
-   if (fd != NULL) {
-      clock_gettime(CLOCK_REALTIME, &spec);
-      gmtime_r(&spec.tv_sec, &tmn);
+   if ((fd == NULL) || !m_log_enabled(level,module))
+      return;
 
-      // NOTE never use strftime for timestamps, it is crashy
-      fprintf(fd,"%d-%02d-%02dT%02d:%02d:%02d.%03dZ %s: ",tmn.tm_year+1900,tmn.tm_mon+1,tmn.tm_mday,tmn.tm_hour,tmn.tm_min,tmn.tm_sec,(int)(spec.tv_nsec/1000000),module);
-      vfprintf(fd,fmt,ap);
-      fflush(fd);
-   }
+   va_copy(aq,ap);
+   n = vsnprintf(buffer,sizeof(buffer),fmt,aq);
+   va_end(aq);
+
+   if (n < 0)
+      return;
+
+   if ((n >= sizeof(buffer)) && ((msg = malloc(n+1)) != NULL))
+      vsnprintf(msg,n+1,fmt,ap);
+
+   m_flog_msg(fd,level,vm_name,instance_id,module,msg ? msg : buffer);
+
+   if (msg != buffer)
+      free(msg);
+}
+
+/* Logging function */
+void m_flog(FILE *fd,char *module,char *fmt,va_list ap)
+{
+   m_flog_lvl(fd,M_LOG_INFO,NULL,-1,module,fmt,ap);
 }
 
 /* Logging function */
@@ -68,6 +84,16 @@ void m_log(char *module,char *fmt,...)
    va_end(ap);
 }
 
+/* Logging function with a level */
+void m_log_lvl(int level,char *module,char *fmt,...)
+{
+   va_list ap;
+
+   va_start(ap,fmt);
+   m_flog_lvl(log_file,level,NULL,-1,module,fmt,ap);
+   va_end(ap);
+}
+
 /* Equivalent to fprintf, but for a posix fd */
 ssize_t fd_printf(int fd,int flags,char *fmt,...)
 {
@@ -122,6 +122,46 @@ atmsw_vc_conn_t *atmsw_vc_lookup(atmsw_table_t *t,netio_desc_t *input,
    return NULL;
 }
 
+/* Check if VCCs are switched on a VPI (the switch ends the VP) */
+static int atmsw_vp_has_vcc(atmsw_table_t *t,netio_desc_t *input,u_int vpi)
+{
+   atmsw_vc_conn_t *swc;
+   int i;
+
+   for(i=0;i<ATMSW_VC_HASH_SIZE;i++)
+      for(swc=t->vc_table[i];swc;swc=swc->next)
+         if ((swc->input == input) && (swc->vpi_in == vpi))
+            return(TRUE);
+
+   return(FALSE);
+}
+
+/* Send an OAM cell built by the OAM module */
+static void atmsw_oam_send(netio_desc_t *nio,m_uint8_t *cell,
+                           atmsw_table_t *t)
+{
+   atm_insert_hec(cell);
+   netio_send(nio,cell,ATM_CELL_SIZE);
+}
+
+/* Release the output NIO of a connection when AIS stops */
+static void atmsw_oam_release(netio_desc_t *nio,atmsw_table_t *t)
+{
+   netio_release(nio->name);
+}
+
+/* Send AIS downstream of a deleted connection */
+static void atmsw_start_ais(atmsw_table_t *t,netio_desc_t *output,
+                            u_int vpi,int vci)
+{
+   /* the NIO reference is kept while AIS is sent */
+   if (!netio_acquire(output->name))
+      return;
+
+   if (atm_oam_start_ais(t->oam,output,vpi,vci) == -1)
+      netio_release(output->name);
+}
+
 /* VP switching */
 void atmsw_vp_switch(atmsw_vp_conn_t *vpc,m_uint8_t *cell)
 {
@@ -164,11 +204,13 @@ void atmsw_vc_switch(atmsw_vc_conn_t *vcc,m_uint8_t *cell)
 ssize_t atmsw_handle_cell(atmsw_table_t *t,netio_desc_t *input,
                           m_uint8_t *cell)
 {
+   m_uint8_t resp[ATM_CELL_SIZE];
    m_uint32_t atm_hdr,vpi,vci;
    netio_desc_t *output = NULL;
    atmsw_vp_conn_t *vpc;
-   atmsw_vc_conn_t *vcc;
+   atmsw_vc_conn_t *vcc = NULL;
    ssize_t len;
+   int conn = 0;
 
    /* Extract VPI/VCI information */
    atm_hdr = m_ntoh32(cell);
@@ -176,13 +218,31 @@ ssize_t atmsw_handle_cell(atmsw_table_t *t,netio_desc_t *input,
    vpi = (atm_hdr & ATM_HDR_VPI_MASK) >> ATM_HDR_VPI_SHIFT;
    vci = (atm_hdr & ATM_HDR_VCI_MASK) >> ATM_HDR_VCI_SHIFT;
 
+   if ((vpc = atmsw_vp_lookup(t,input,vpi)) != NULL)
+      conn |= ATM_OAM_CONN_VPC;
+   else if ((vcc = atmsw_vc_lookup(t,input,vpi,vci)) != NULL)
+      conn |= ATM_OAM_CONN_VCC;
+   else if (((vci == ATM_OAM_F4_SEG_VCI) || (vci == ATM_OAM_F4_E2E_VCI)) &&
+            atmsw_vp_has_vcc(t,input,vpi))
+      conn |= ATM_OAM_CONN_VP_END;
+
+   /* OAM cells are processed according to the connection state */
+   switch(atm_oam_handle_cell(t->oam,input,cell,conn,resp)) {
+      case ATM_OAM_ACTION_DROP:
+         return(0);
+
+      case ATM_OAM_ACTION_REPLY:
+         atmsw_oam_send(input,resp,t);
+         return(0);
+   }
+
    /* VP switching */
-   if ((vpc = atmsw_vp_lookup(t,input,vpi)) != NULL) {
+   if (vpc != NULL) {
       atmsw_vp_switch(vpc,cell);
       output = vpc->output;
    } else {  
       /* VC switching */
-      if ((vcc = atmsw_vc_lookup(t,input,vpi,vci)) != NULL) {
+      if (vcc != NULL) {
          atmsw_vc_switch(vcc,cell);
          output = vcc->output;
       }
@@ -241,6 +301,11 @@ atmsw_table_t *atmsw_create_table(char *name)
    if (!(t->name = mp_strdup(&t->mp,name)))
       goto err_name;
 
+   t->oam = atm_oam_create((atm_oam_send_cbk_t)atmsw_oam_send,
+                           (atm_oam_release_cbk_t)atmsw_oam_release,t);
+   if (!t->oam)
+      goto err_oam;
+
    /* Record this object in registry */
    if (registry_add(t->name,OBJ_TYPE_ATMSW,t) == -1) {
       fprintf(stderr,"atmsw_create_table: unable to create switch '%s'\n",
@@ -251,6 +316,8 @@ atmsw_table_t *atmsw_create_table(char *name)
    return t;
 
  err_reg:
+   atm_oam_free(t->oam);
+ err_oam:
  err_name:
    mp_free_pool(&t->mp);
    free(t);
@@ -321,6 +388,8 @@ int atmsw_create_vpc(atmsw_table_t *t,char *nio_input,u_int vpi_in,
    hbucket = atmsw_vpc_hash(vpi_in);
    swc->next = t->vp_table[hbucket];
    t->vp_table[hbucket] = swc;
+
+   atm_oam_stop_ais(t->oam,swc->output,vpi_out,ATM_OAM_VP);
    ATMSW_UNLOCK(t);
    return(0);
 
@@ -359,6 +428,7 @@ int atmsw_delete_vpc(atmsw_table_t *t,char *nio_input,u_int vpi_in,
       {
          /* found a matching VP, remove it */
          *swc = (*swc)->next;
+         atmsw_start_ais(t,p->output,p->vpi_out,ATM_OAM_VP);
          ATMSW_UNLOCK(t);
 
          atmsw_release_vpc(p);
@@ -414,6 +484,8 @@ int atmsw_create_vcc(atmsw_table_t *t,
    hbucket = atmsw_vcc_hash(vpi_in,vci_in);
    swc->next = t->vc_table[hbucket];
    t->vc_table[hbucket] = swc;
+
+   atm_oam_stop_ais(t->oam,swc->output,vpi_out,vci_out);
    ATMSW_UNLOCK(t);
    return(0);
 
@@ -449,6 +521,7 @@ int atmsw_delete_vcc(atmsw_table_t *t,
       {
          /* found a matching VP, remove it */
          *swc = (*swc)->next;
+         atmsw_start_ais(t,p->output,p->vpi_out,p->vci_out);
          ATMSW_UNLOCK(t);
 
          atmsw_release_vcc(p);
@@ -479,6 +552,7 @@ static int atmsw_free(void *data,void *arg)
       for(vc=t->vc_table[i];vc;vc=vc->next)
          atmsw_release_vcc(vc);
 
+   atm_oam_free(t->oam);
    mp_free_pool(&t->mp);
    free(t);
    return(TRUE);
@@ -496,6 +570,23 @@ int atmsw_delete_all(void)
    return(registry_delete_type(OBJ_TYPE_ATMSW,atmsw_free,NULL));
 }
 
+/* Send an OAM segment loopback request on a VP (vci ATM_OAM_VP) or a VC */
+int atmsw_oam_loopback(atmsw_table_t *t,char *nio_name,u_int vpi,int vci)
+{
+   netio_desc_t *nio;
+
+   ATMSW_LOCK(t);
+
+   if (!(nio = registry_exists(nio_name,OBJ_TYPE_NIO))) {
+      ATMSW_UNLOCK(t);
+      return(-1);
+   }
+
+   atm_oam_send_loopback(t->oam,nio,vpi,vci);
+   ATMSW_UNLOCK(t);
+   return(0);
+}
+
 /* Save the configuration of an ATM switch */
 void atmsw_save_config(atmsw_table_t *t,FILE *fd)
 {
@@ -641,10 +732,15 @@ int atmsw_cfg_create_vcc(atmsw_table_t *t,char **tokens,int count)
 int atmsw_handle_cfg_line(atmsw_table_t *t,char *str)
 {  
    char *tokens[ATMSW_MAX_TOKENS];
+   m_tok_error_t err;
    int count;
 
-   if ((count = m_strsplit(str,':',tokens,ATMSW_MAX_TOKENS)) <= 1)
+   if ((count = m_strsplit_tok(str,':',0,tokens,ATMSW_MAX_TOKENS,&err)) <= 1) {
+      if (err.code != M_TOK_ERR_NONE)
+         fprintf(stderr,"ATMSW: %s at column %d in \"%s\"\n",
+                 m_tok_strerror(err.code),err.col,str);
       return(-1);
+   }
 
    if (!strcmp(tokens[0],"IF"))
       return(atmsw_cfg_create_if(t,tokens,count));
@@ -64,6 +64,7 @@ struct atmsw_table {
    pthread_mutex_t lock;
    mempool_t mp;
    m_uint64_t cell_drop;
+   atm_oam_t *oam;
    atmsw_vp_conn_t *vp_table[ATMSW_VP_HASH_SIZE];
    atmsw_vc_conn_t *vc_table[ATMSW_VC_HASH_SIZE];
 };
@@ -114,6 +115,9 @@ int atmsw_delete_vcc(atmsw_table_t *t,
                      char *nio_input,u_int vpi_in,u_int vci_in,
                      char *nio_output,u_int vpi_out,u_int vci_out);
 
+/* Send an OAM segment loopback request on a VP (vci ATM_OAM_VP) or a VC */
+int atmsw_oam_loopback(atmsw_table_t *t,char *nio_name,u_int vpi,int vci);
+
 /* Save the configuration of an ATM switch */
 void atmsw_save_config(atmsw_table_t *t,FILE *fd);
 
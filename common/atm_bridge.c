@@ -309,10 +309,15 @@ int atm_bridge_cfg_setup(atm_bridge_t *t,char **tokens,int count)
 int atm_bridge_handle_cfg_line(atm_bridge_t *t,char *str)
 {  
    char *tokens[ATM_BRIDGE_MAX_TOKENS];
+   m_tok_error_t err;
    int count;
 
-   if ((count = m_strsplit(str,':',tokens,ATM_BRIDGE_MAX_TOKENS)) <= 1)
+   if ((count = m_strsplit_tok(str,':',0,tokens,ATM_BRIDGE_MAX_TOKENS,&err)) <= 1) {
+      if (err.code != M_TOK_ERR_NONE)
+         fprintf(stderr,"ATM_BRIDGE: %s at column %d in \"%s\"\n",
+                 m_tok_strerror(err.code),err.col,str);
       return(-1);
+   }
 
    if (!strcmp(tokens[0],"IF"))
       return(atm_bridge_cfg_create_if(t,tokens,count));
@@ -845,7 +845,7 @@ int vm_slot_cmd_create(vm_instance_t *vm,char *str)
    u_int slot_id,port_id;
 
    /* A port adapter description is like "1:0:NM-1FE" */
-   count = m_strsplit(str,':',tokens,SLOT_DESC_MAX_TOKENS);
+   count = m_strsplit_tok(str,':',0,tokens,SLOT_DESC_MAX_TOKENS,NULL);
 
    if ((count < 2) || (count > 3)) {
       vm_error(vm,"unable to parse slot description '%s'.\n",str);
@@ -879,10 +879,19 @@ int vm_slot_cmd_add_nio(vm_instance_t *vm,char *str)
    u_int slot_id,port_id;
    netio_desc_t *nio;
    char nio_name[128];
-
-   /* A NIO binding description is like "1:3:tap:tap0" */
-   if ((count = m_strsplit(str,':',tokens,SLOT_DESC_MAX_TOKENS)) < 3) {
-      vm_error(vm,"unable to parse NIO description '%s'.\n",str);
+   m_tok_error_t err;
+
+   /* 
+    * A NIO binding description is like "1:3:tap:tap0".
+    * IPv6 addresses are bracketed: "1:0:udp:10000:[::1]:10001".
+    */
+   if ((count = m_strsplit_tok(str,':',0,tokens,SLOT_DESC_MAX_TOKENS,&err)) < 3) {
+      if (err.code != M_TOK_ERR_NONE)
+         vm_error(vm,"unable to parse NIO description '%s' (%s at column %d).\n",
+                  str,m_tok_strerror(err.code),err.col);
+      else
+         vm_error(vm,"unable to parse NIO description '%s'.\n",str);
+      m_strsplit_free(tokens,count);
       return(-1);
    }
 
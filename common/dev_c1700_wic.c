@@ -219,17 +219,10 @@ dev_c1700_mb_wic1enet_init(vm_instance_t *vm,struct cisco_card *card)
    m_uint8_t eeprom_ver;
    size_t offset;
    n_eth_addr_t addr;
-   m_uint16_t pid;
-
-   pid = (m_uint16_t)getpid();
 
    /* Generate automatically the MAC address */
-   addr.eth_addr_byte[0] = vm_get_mac_addr_msb(vm);
-   addr.eth_addr_byte[1] = vm->instance_id & 0xFF;
-   addr.eth_addr_byte[2] = pid >> 8;
-   addr.eth_addr_byte[3] = pid & 0xFF;
+   hwid_alloc_mac(vm->name,vm->instance_id,vm_get_mac_addr_msb(vm),&addr);
    addr.eth_addr_byte[4] = card->subslot_id;
-   addr.eth_addr_byte[5] = 0x00;
 
    /* Set the EEPROM */
    cisco_card_set_eeprom(vm,card,cisco_eeprom_find_wic("WIC-1ENET"));
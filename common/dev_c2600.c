@@ -300,7 +300,11 @@ int c2600_set_system_id(c2600_t *router,char *id)
 
 int c2600_refresh_systemid(c2600_t *router)
 {
-  if (router->board_id[0] == 0x00) return(0);
+  /* Derive the board id from the instance name and ID if not set */
+  if ((router->board_id[0] == 0x00) &&
+      (hwid_board_id(router->vm->name,router->vm->instance_id,9,
+                     router->board_id,sizeof(router->board_id)) == -1))
+     return(0);
   m_uint8_t buf[11];
   parse_board_id(buf,router->board_id,9);
   // Does not use the cisco_eeprom libraries.. do it by hand
@@ -426,23 +430,15 @@ static void c2600_init_defaults(c2600_t *router)
 {   
    vm_instance_t *vm = router->vm;   
    n_eth_addr_t *m;
-   m_uint16_t pid;
 
    /* Set platform slots characteristics */
    vm->nr_slots   = C2600_MAX_NM_BAYS;
    vm->slots_type = CISCO_CARD_TYPE_NM;
    vm->slots_drivers = nm_drivers;
 
-   pid = (m_uint16_t)getpid();
-
-   /* Generate a chassis MAC address based on the instance ID */
+   /* Generate a chassis MAC address based on the instance name and ID */
    m = &router->mac_addr;
-   m->eth_addr_byte[0] = vm_get_mac_addr_msb(vm);
-   m->eth_addr_byte[1] = vm->instance_id & 0xFF;
-   m->eth_addr_byte[2] = pid >> 8;
-   m->eth_addr_byte[3] = pid & 0xFF;
-   m->eth_addr_byte[4] = 0x00;
-   m->eth_addr_byte[5] = 0x00;
+   hwid_alloc_mac(vm->name,vm->instance_id,vm_get_mac_addr_msb(vm),m);
    router->board_id[0] = 0x00;
 
    c2600_init_eeprom_groups(router);
@@ -256,7 +256,18 @@ int c3600_set_system_id(c3600_t *router,char *id)
 }
 int c3600_refresh_systemid(c3600_t *router)
 {
-  if (router->board_id[0] == 0x00) return(0);
+  int enc;
+
+  if (!router->chassis_driver)
+     return(0);
+
+  enc = !strcmp("3660",router->chassis_driver->chassis_type) ? 11 : 4;
+
+  /* Derive the board id from the instance name and ID if not set */
+  if ((router->board_id[0] == 0x00) &&
+      (hwid_board_id(router->vm->name,router->vm->instance_id,enc,
+                     router->board_id,sizeof(router->board_id)) == -1))
+     return(0);
   m_uint8_t buf[11];
 
   if (!strcmp("3660",router->chassis_driver->chassis_type)) {
@@ -272,28 +283,11 @@ int c3600_refresh_systemid(c3600_t *router)
 /* Set the base MAC address of the chassis */
 static int c3600_burn_mac_addr(c3600_t *router,n_eth_addr_t *addr)
 {
-   m_uint8_t eeprom_ver;
-   size_t offset;
-
-   /* Read EEPROM format version */
-   cisco_eeprom_get_byte(&router->mb_eeprom,0,&eeprom_ver);
-
-   switch(eeprom_ver) {
-      case 0:
-         cisco_eeprom_set_region(&router->mb_eeprom,2,addr->eth_addr_byte,6);
-         break;
-
-      case 4:
-         if (!cisco_eeprom_v4_find_field(&router->mb_eeprom,0xC3,&offset)) {
-            cisco_eeprom_set_region(&router->mb_eeprom,offset,
-                                    addr->eth_addr_byte,6);
-         }
-         break;
-
-      default:
-         vm_error(router->vm,"c3600_burn_mac_addr: unable to handle "
-                  "EEPROM version %u\n",eeprom_ver);
-         return(-1);
+   if (hwid_burn_eeprom(&router->mb_eeprom,addr,
+                        HWID_EEPROM_MAC_BLOCK_SIZE) == -1) {
+      vm_error(router->vm,"c3600_burn_mac_addr: unable to handle "
+               "EEPROM format\n");
+      return(-1);
    }
 
    return(0);
@@ -523,23 +517,15 @@ static void c3600_init_defaults(c3600_t *router)
 {   
    vm_instance_t *vm = router->vm;   
    n_eth_addr_t *m;
-   m_uint16_t pid;
 
    /* Set platform slots characteristics */
    vm->nr_slots   = C3600_MAX_NM_BAYS;
    vm->slots_type = CISCO_CARD_TYPE_NM;
    vm->slots_drivers = nm_drivers;
 
-   pid = (m_uint16_t)getpid();
-
-   /* Generate a chassis MAC address based on the instance ID */
+   /* Generate a chassis MAC address based on the instance name and ID */
    m = &router->mac_addr;
-   m->eth_addr_byte[0] = vm_get_mac_addr_msb(vm);
-   m->eth_addr_byte[1] = vm->instance_id & 0xFF;
-   m->eth_addr_byte[2] = pid >> 8;
-   m->eth_addr_byte[3] = pid & 0xFF;
-   m->eth_addr_byte[4] = 0x00;
-   m->eth_addr_byte[5] = 0x00;
+   hwid_alloc_mac(vm->name,vm->instance_id,vm_get_mac_addr_msb(vm),m);
    router->board_id[0] = 0x00;
 
    c3600_init_eeprom_groups(router);
@@ -386,23 +386,15 @@ static void c6msfc1_init_defaults(c6msfc1_t *router)
 {
    vm_instance_t *vm = router->vm;
    n_eth_addr_t *m;
-   m_uint16_t pid;
 
    /* Set platform slots characteristics */
    vm->nr_slots   = C6MSFC1_MAX_PA_BAYS;
    vm->slots_type = CISCO_CARD_TYPE_PA;
    vm->slots_drivers = pa_drivers;
       
-   pid = (m_uint16_t)getpid();
-
-   /* Generate a chassis MAC address based on the instance ID */
+   /* Generate a chassis MAC address based on the instance name and ID */
    m = &router->mac_addr;
-   m->eth_addr_byte[0] = vm_get_mac_addr_msb(vm);
-   m->eth_addr_byte[1] = vm->instance_id & 0xFF;
-   m->eth_addr_byte[2] = pid >> 8;
-   m->eth_addr_byte[3] = pid & 0xFF;
-   m->eth_addr_byte[4] = 0x00;
-   m->eth_addr_byte[5] = 0x00;
+   hwid_alloc_mac(vm->name,vm->instance_id,vm_get_mac_addr_msb(vm),m);
 
    /* Default slot: 1 */
    router->msfc_slot = 1;
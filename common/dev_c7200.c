@@ -699,7 +699,11 @@ int c7200_refresh_systemid(c7200_t *router)
   //fprintf(stderr,"Starting cpu dump\n");
   //cisco_eeprom_dump(&router->cpu_eeprom);
 
-  if (router->board_id[0] == 0x00) return(0);
+  /* Derive the board id from the instance name and ID if not set */
+  if ((router->board_id[0] == 0x00) &&
+      (hwid_board_id(router->vm->name,router->vm->instance_id,4,
+                     router->board_id,sizeof(router->board_id)) == -1))
+     return(0);
 
   m_uint8_t buf[11];
   if (  (!strcmp("npe-100",router->npe_driver->npe_type))
@@ -1379,23 +1383,15 @@ static void c7200_init_defaults(c7200_t *router)
 {
    vm_instance_t *vm = router->vm;
    n_eth_addr_t *m;
-   m_uint16_t pid;
 
    /* Set platform slots characteristics */
    vm->nr_slots   = C7200_MAX_PA_BAYS;
    vm->slots_type = CISCO_CARD_TYPE_PA;
    vm->slots_drivers = pa_drivers;
 
-   pid = (m_uint16_t)getpid();
-
-   /* Generate a chassis MAC address based on the instance ID */
+   /* Generate a chassis MAC address based on the instance name and ID */
    m = &router->mac_addr;
-   m->eth_addr_byte[0] = vm_get_mac_addr_msb(vm);
-   m->eth_addr_byte[1] = vm->instance_id & 0xFF;
-   m->eth_addr_byte[2] = pid >> 8;
-   m->eth_addr_byte[3] = pid & 0xFF;
-   m->eth_addr_byte[4] = 0x00;
-   m->eth_addr_byte[5] = 0x00;
+   hwid_alloc_mac(vm->name,vm->instance_id,vm_get_mac_addr_msb(vm),m);
 
    router->board_id[0] = 0x00;
 
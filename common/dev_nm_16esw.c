@@ -2436,17 +2436,10 @@ int dev_nm_16esw_burn_mac_addr(vm_instance_t *vm,u_int nm_bay,
    m_uint8_t eeprom_ver;
    size_t offset;
    n_eth_addr_t addr;
-   m_uint16_t pid;
-
-   pid = (m_uint16_t)getpid();
 
    /* Generate automatically the MAC address */
-   addr.eth_addr_byte[0] = vm_get_mac_addr_msb(vm);
-   addr.eth_addr_byte[1] = vm->instance_id & 0xFF;
-   addr.eth_addr_byte[2] = pid >> 8;
-   addr.eth_addr_byte[3] = pid & 0xFF;
+   hwid_alloc_mac(vm->name,vm->instance_id,vm_get_mac_addr_msb(vm),&addr);
    addr.eth_addr_byte[4] = 0xF0 + nm_bay;
-   addr.eth_addr_byte[5] = 0x00;
 
    /* Read EEPROM format version */
    cisco_eeprom_get_byte(eeprom,0,&eeprom_ver);
@@ -48,6 +48,7 @@
 static pthread_mutex_t vtty_list_mutex = PTHREAD_MUTEX_INITIALIZER;
 static vtty_t *vtty_list = NULL;
 static pthread_t vtty_thread;
+static ev_loop_t *vtty_ev_loop = NULL;
 
 #define VTTY_LIST_LOCK()   pthread_mutex_lock(&vtty_list_mutex);
 #define VTTY_LIST_UNLOCK() pthread_mutex_unlock(&vtty_list_mutex);
@@ -96,6 +97,13 @@ static void vtty_telnet_do_ttype(int fd)
    write(fd,cmd,sizeof(cmd));
 }
 
+/* Send Telnet command: send your terminal type */
+static void vtty_telnet_sb_ttype_send(int fd)
+{
+   u_char cmd[] = { IAC, SB, TELOPT_TTYPE, TELQUAL_SEND, IAC, SE };
+   write(fd,cmd,sizeof(cmd));
+}
+
 /* Restore TTY original settings */
 static void vtty_term_reset(void)
 {
@@ -257,14 +265,7 @@ error:
 /* Accept a TCP connection */
 static int vtty_tcp_conn_accept(vtty_t *vtty, int nsock)
 {
-   int fd,*fd_slot;
-   size_t i;
-   ssize_t n;
-   
-   if (fd_pool_get_free_slot(&vtty->fd_pool,&fd_slot) < 0) {
-      vm_error(vtty->vm,"unable to create a new VTTY TCP connection\n");
-      return(-1);
-   }
+   int fd;
    
    if ((fd = accept(vtty->fd_array[nsock],NULL,NULL)) < 0) {
       vm_error(vtty->vm,"vtty_tcp_conn_accept: accept on port %d failed %s\n",
@@ -272,8 +273,15 @@ static int vtty_tcp_conn_accept(vtty_t *vtty, int nsock)
       return(-1);
    }
 
+   /* Forget the clients closed on output errors, their FD can be reused */
+   vtty_mux_sync(vtty->mux,&vtty->fd_pool);
+
    /* Register the new FD */
-   *fd_slot = fd;
+   if (!fd_pool_add(&vtty->fd_pool,fd)) {
+      vm_error(vtty->vm,"unable to create a new VTTY TCP connection\n");
+      close(fd);
+      return(-1);
+   }
 
    vm_log(vtty->vm,"VTTY","%s is now connected (accept_fd=%d,conn_fd=%d)\n",
           vtty->name,vtty->fd_array[nsock],fd);
@@ -284,7 +292,6 @@ static int vtty_tcp_conn_accept(vtty_t *vtty, int nsock)
       vtty_telnet_will_echo(fd);
       vtty_telnet_will_suppress_go_ahead(fd);
       vtty_telnet_dont_linemode(fd);
-      vtty->input_state = VTTY_INPUT_TEXT;
    }
 
    if (telnet_message_ok == 1) {
@@ -293,28 +300,19 @@ static int vtty_tcp_conn_accept(vtty_t *vtty, int nsock)
                 "Press ENTER to get the prompt.\r\n", 
                 vtty->vm->name, vtty->vm->instance_id, vm_get_type(vtty->vm),
                 vtty->name);
-      /* replay old text */
-      if (vtty->replay_full) {
-         for (i = vtty->replay_ptr; i < VTTY_BUFFER_SIZE; i += (size_t)n) {
-            n = send(fd,&vtty->replay_buffer[i],VTTY_BUFFER_SIZE-i,0);
-            if (n < 0) {
-               perror("vtty_tcp_conn_accept: send");
-               break;
-            }
-         }
-      }
-      for (i = 0; i < vtty->replay_ptr; i += (size_t)n) {
-         n = send(fd,&vtty->replay_buffer[i],vtty->replay_ptr-i,0);
-         if (n < 0) {
-            perror("vtty_tcp_conn_accept: send");
-            break;
-         }
-      }
+      /* replay the scrollback */
+      if (vtty->rec && (vtty_rec_replay(vtty->rec,fd) < 0))
+         perror("vtty_tcp_conn_accept: send");
       /* warn if not running */
       if (vtty->vm->status != VM_STATUS_RUNNING)
          fd_printf(fd,0,"\r\n!!! WARNING - VM is not running, will be unresponsive (status=%d) !!!\r\n",vtty->vm->status);
       vtty_flush(vtty);
    }
+
+   /* The first client gets the control, the next ones are observers */
+   if (vtty_mux_add(vtty->mux,fd) == VTTY_MUX_OBSERVER)
+      vm_log(vtty->vm,"VTTY","%s: read-only client (conn_fd=%d)\n",
+             vtty->name,fd);
    return(0);
 }
 
@@ -326,10 +324,16 @@ static int vtty_tcp_conn_accept(vtty_t *vtty, int nsock)
 int vtty_parse_serial_option(vtty_serial_option_t *option, char *optarg)
 {
    char *array[6];
+   m_tok_error_t err;
    int count;
 
-   if ((count = m_strtok(optarg, ':', array, 6)) < 1) {
-      fprintf(stderr,"vtty_parse_serial_option: invalid string\n");
+   /* device paths with ':' must be quoted */
+   if ((count = m_strsplit_tok(optarg,':',M_TOK_SKIP_EMPTY,array,6,&err)) < 1) {
+      if (err.code != M_TOK_ERR_NONE)
+         fprintf(stderr,"vtty_parse_serial_option: %s at column %d\n",
+                 m_tok_strerror(err.code),err.col);
+      else
+         fprintf(stderr,"vtty_parse_serial_option: invalid string\n");
       return(-1);
    }
 
@@ -495,6 +499,77 @@ static int vtty_serial_setup(vtty_t *vtty, const vtty_serial_option_t *option)
    return(0);
 }
 
+static void vtty_read_and_store(vtty_t *vtty,int *fd_slot);
+static void vtty_tcp_input(int *fd_slot,void *opt);
+
+/* Event loop callback of the listening sockets */
+static void vtty_ev_accept(int fd,int events,void *opt)
+{
+   vtty_t *vtty = opt;
+   int i;
+
+   for(i=0;i<vtty->fd_count;i++)
+      if (vtty->fd_array[i] == fd) {
+         vtty_tcp_conn_accept(vtty,i);
+         break;
+      }
+}
+
+/* Event loop callback of the terminal and serial ports */
+static void vtty_ev_term(int fd,int events,void *opt)
+{
+   vtty_t *vtty = opt;
+
+   vtty_read_and_store(vtty,&vtty->fd_array[0]);
+   vtty->input_pending = TRUE;
+}
+
+/* Register the FDs of a VTTY in the event loop (VTTY list locked) */
+static void vtty_ev_register(vtty_t *vtty)
+{
+   int i;
+
+   switch(vtty->type) {
+      case VTTY_TYPE_TCP:
+         for(i=0;i<vtty->fd_count;i++)
+            if (vtty->fd_array[i] != -1)
+               ev_loop_add(vtty_ev_loop,vtty->fd_array[i],EV_READ,
+                           vtty_ev_accept,vtty);
+
+         fd_pool_attach(&vtty->fd_pool,vtty_ev_loop,vtty_tcp_input,vtty);
+         break;
+
+      case VTTY_TYPE_TERM:
+      case VTTY_TYPE_SERIAL:
+         if (vtty->fd_array[0] != -1)
+            ev_loop_add(vtty_ev_loop,vtty->fd_array[0],EV_READ,
+                        vtty_ev_term,vtty);
+         break;
+   }
+}
+
+/* Unregister the FDs of a VTTY from the event loop (VTTY list locked) */
+static void vtty_ev_unregister(vtty_t *vtty)
+{
+   int i;
+
+   switch(vtty->type) {
+      case VTTY_TYPE_TCP:
+         for(i=0;i<vtty->fd_count;i++)
+            if (vtty->fd_array[i] != -1)
+               ev_loop_del(vtty_ev_loop,vtty->fd_array[i]);
+
+         fd_pool_detach(&vtty->fd_pool);
+         break;
+
+      case VTTY_TYPE_TERM:
+      case VTTY_TYPE_SERIAL:
+         if (vtty->fd_array[0] != -1)
+            ev_loop_del(vtty_ev_loop,vtty->fd_array[0]);
+         break;
+   }
+}
+
 /* Create a virtual tty */
 vtty_t *vtty_create(vm_instance_t *vm,char *name,int type,int tcp_port,
                     const vtty_serial_option_t *option)
@@ -513,7 +588,7 @@ vtty_t *vtty_create(vm_instance_t *vm,char *name,int type,int tcp_port,
    vtty->fd_count = 0;
    pthread_mutex_init(&vtty->lock,NULL);
    vtty->terminal_support = 1;
-   vtty->input_state = VTTY_INPUT_TEXT;
+   vtty->input.state = VTTY_INPUT_TEXT;
    fd_pool_init(&vtty->fd_pool);
    for(i=0;i<VTTY_MAX_FD;i++)
        vtty->fd_array[i] = -1;
@@ -529,6 +604,7 @@ vtty_t *vtty_create(vm_instance_t *vm,char *name,int type,int tcp_port,
 
       case VTTY_TYPE_TCP:
          vtty->tcp_port = tcp_port;
+         vtty->mux = vtty_mux_create();
          vtty->fd_count = vtty_tcp_conn_wait(vtty);
          break;
 
@@ -556,6 +632,7 @@ vtty_t *vtty_create(vm_instance_t *vm,char *name,int type,int tcp_port,
 
    /* Add this new VTTY to the list */
    VTTY_LIST_LOCK();
+   vtty_ev_register(vtty);
    vtty->next = vtty_list;
    vtty->pprev = &vtty_list;
 
@@ -579,8 +656,12 @@ void vtty_delete(vtty_t *vtty)
             vtty->next->pprev = vtty->pprev;
          *(vtty->pprev) = vtty->next;
       }
+      vtty_ev_unregister(vtty);
       VTTY_LIST_UNLOCK();
 
+      if (vtty->rec)
+         vtty_rec_flush(vtty->rec);
+
       switch(vtty->type) {
            case VTTY_TYPE_TCP:
                
@@ -591,6 +672,7 @@ void vtty_delete(vtty_t *vtty)
                    }
 
            fd_pool_free(&vtty->fd_pool);
+           vtty_mux_free(vtty->mux);
            vtty->fd_count = 0;
            break;
         
@@ -911,13 +993,23 @@ static void remote_control(vtty_t *vtty,u_char c)
 /* Read a character (until one is available) and store it in buffer */
 static void vtty_read_and_store(vtty_t *vtty,int *fd_slot)
 {
+   vtty_input_t *in = &vtty->input;
+   int fd = *fd_slot;
    int c;
    
    /* wait until we get a character input */
    c = vtty_read(vtty,fd_slot);
   
-   /* if read error, do nothing */
-   if (c < 0) return;
+   /* if read error, do nothing (the TCP connection is closed) */
+   if (c < 0) {
+      if (vtty->mux)
+         vtty_mux_remove(vtty->mux,fd);
+      return;
+   }
+
+   /* Each TCP client has its own telnet state */
+   if (vtty->mux && !(in = vtty_mux_get_input(vtty->mux,fd)))
+      return;
 
    /* If something was read, make sure the handler is informed */
    vtty->input_pending = TRUE;  
@@ -927,24 +1019,30 @@ static void vtty_read_and_store(vtty_t *vtty,int *fd_slot)
       return;
    }
   
-   switch(vtty->input_state) {
+   switch(in->state) {
       case VTTY_INPUT_TEXT :
+         /* Keystrokes of the read-only clients don't reach the VM */
+         if (vtty->mux && (c != IAC) && !vtty_mux_is_owner(vtty->mux,fd)) {
+            vtty_mux_observer_input(vtty->mux,fd,c);
+            return;
+         }
+
          switch(c) {
             case 0x1b:
-               vtty->input_state = VTTY_INPUT_VT1;
+               in->state = VTTY_INPUT_VT1;
                return;
 
             /* Ctrl + ']' (0x1d, 29), or Alt-Gr + '*' (0xb3, 179) */
             case 0x1d:
             case 0xb3:
                if (ctrl_code_ok == 1) {
-                 vtty->input_state = VTTY_INPUT_REMOTE;
+                 in->state = VTTY_INPUT_REMOTE;
                } else {
                  vtty_store(vtty,c);
                }
                return;
             case IAC :
-               vtty->input_state = VTTY_INPUT_TELNET;
+               in->state = VTTY_INPUT_TELNET;
                return;
             case 0:  /* NULL - Must be ignored - generated by Linux telnet */
             case 10: /* LF (Line Feed) - Must be ignored on Windows platform */
@@ -958,13 +1056,13 @@ static void vtty_read_and_store(vtty_t *vtty,int *fd_slot)
       case VTTY_INPUT_VT1 :
          switch(c) {
             case 0x5b:
-               vtty->input_state = VTTY_INPUT_VT2;
+               in->state = VTTY_INPUT_VT2;
                return;
             default:
                vtty_store(vtty,0x1b);
                vtty_store(vtty,c);
          }
-         vtty->input_state = VTTY_INPUT_TEXT;
+         in->state = VTTY_INPUT_TEXT;
          return;
   
       case VTTY_INPUT_VT2 :
@@ -987,64 +1085,58 @@ static void vtty_read_and_store(vtty_t *vtty,int *fd_slot)
                vtty_store(vtty,c);
                break;
          }
-         vtty->input_state = VTTY_INPUT_TEXT;
+         in->state = VTTY_INPUT_TEXT;
          return;
   
       case VTTY_INPUT_REMOTE :
          remote_control(vtty, c);
-         vtty->input_state = VTTY_INPUT_TEXT;
+         in->state = VTTY_INPUT_TEXT;
          return;
   
       case VTTY_INPUT_TELNET :
-         vtty->telnet_cmd = c;
+         in->telnet_cmd = c;
          switch(c) {
             case WILL:
             case WONT:
             case DO:
             case DONT:
-               vtty->input_state = VTTY_INPUT_TELNET_IYOU;
+               in->state = VTTY_INPUT_TELNET_IYOU;
                return;
             case SB :
-               vtty->telnet_cmd = c;
-               vtty->input_state = VTTY_INPUT_TELNET_SB1;
+               in->telnet_cmd = c;
+               in->state = VTTY_INPUT_TELNET_SB1;
                return;
             case SE:
                break;
             case IAC :
-               vtty_store(vtty, IAC);
+               if (!vtty->mux || vtty_mux_is_owner(vtty->mux,fd))
+                  vtty_store(vtty, IAC);
                break;
          }
-         vtty->input_state = VTTY_INPUT_TEXT;
+         in->state = VTTY_INPUT_TEXT;
          return;
   
       case VTTY_INPUT_TELNET_IYOU :
-         vtty->telnet_opt = c;
+         in->telnet_opt = c;
          /* if telnet client can support ttype, ask it to send ttype string */
-         if ((vtty->telnet_cmd == WILL) && 
-             (vtty->telnet_opt == TELOPT_TTYPE)) 
-         {
-            vtty_put_char(vtty, IAC);
-            vtty_put_char(vtty, SB);
-            vtty_put_char(vtty, TELOPT_TTYPE);
-            vtty_put_char(vtty, TELQUAL_SEND);
-            vtty_put_char(vtty, IAC);
-            vtty_put_char(vtty, SE);
-         }
-         vtty->input_state = VTTY_INPUT_TEXT;
+         if ((in->telnet_cmd == WILL) && 
+             (in->telnet_opt == TELOPT_TTYPE)) 
+            vtty_telnet_sb_ttype_send(fd);
+         in->state = VTTY_INPUT_TEXT;
          return;
   
       case VTTY_INPUT_TELNET_SB1 :
-         vtty->telnet_opt = c;
-         vtty->input_state = VTTY_INPUT_TELNET_SB2;
+         in->telnet_opt = c;
+         in->state = VTTY_INPUT_TELNET_SB2;
          return;
   
       case VTTY_INPUT_TELNET_SB2 :
-         vtty->telnet_qual = c;
-         if ((vtty->telnet_opt == TELOPT_TTYPE) && 
-             (vtty->telnet_qual == TELQUAL_IS))
-            vtty->input_state = VTTY_INPUT_TELNET_SB_TTYPE;
+         in->telnet_qual = c;
+         if ((in->telnet_opt == TELOPT_TTYPE) && 
+             (in->telnet_qual == TELQUAL_IS))
+            in->state = VTTY_INPUT_TELNET_SB_TTYPE;
          else
-            vtty->input_state = VTTY_INPUT_TELNET_NEXT;
+            in->state = VTTY_INPUT_TELNET_NEXT;
          return;
   
       case VTTY_INPUT_TELNET_SB_TTYPE :
@@ -1053,13 +1145,13 @@ static void vtty_read_and_store(vtty_t *vtty,int *fd_slot)
          if ((c == 'x') || (c == 'X') || (c == 'v') || (c == 'V')) {
             fd_printf(*fd_slot,0,"\033]0;%s\07", vtty->vm->name);
          }
-         vtty->input_state = VTTY_INPUT_TELNET_NEXT;
+         in->state = VTTY_INPUT_TELNET_NEXT;
          return;
   
       case VTTY_INPUT_TELNET_NEXT :
          /* ignore all chars until next IAC */
          if (c == IAC)
-            vtty->input_state = VTTY_INPUT_TELNET;
+            in->state = VTTY_INPUT_TELNET;
          return;
    }
 }
@@ -1120,14 +1212,9 @@ void vtty_put_char(vtty_t *vtty, char ch)
          exit(1);
    }
 
-   /* store char for replay */
-   vtty->replay_buffer[vtty->replay_ptr] = ch;
-
-   ++vtty->replay_ptr;
-   if (vtty->replay_ptr == VTTY_BUFFER_SIZE) {
-      vtty->replay_ptr = 0;
-      vtty->replay_full = 1;
-   }
+   /* record char (scrollback and recordings) */
+   if (vtty->rec)
+      vtty_rec_put(vtty->rec,(u_char *)&ch,1);
 }
 
 /* Put a buffer to vtty */
@@ -1163,86 +1250,19 @@ static void vtty_tcp_input(int *fd_slot,void *opt)
 static void *vtty_thread_main(void *arg)
 {
    vtty_t *vtty;
-   struct timeval tv;
-   int fd_max,fd_tcp,res;
-   fd_set rfds;
-   int i;
 
    for(;;) {
-      VTTY_LIST_LOCK();
-
-      /* Build the FD set */
-      FD_ZERO(&rfds);
-      fd_max = -1;
-      for(vtty=vtty_list;vtty;vtty=vtty->next) {
-
-          switch(vtty->type) {
-              case VTTY_TYPE_TCP:
-
-                  for(i=0;i<vtty->fd_count;i++)
-                      if (vtty->fd_array[i] != -1) {
-                          FD_SET(vtty->fd_array[i],&rfds);
-                          if (vtty->fd_array[i] > fd_max)
-                              fd_max = vtty->fd_array[i];
-                      }
-
-                  fd_tcp = fd_pool_set_fds(&vtty->fd_pool,&rfds);
-                  fd_max = m_max(fd_tcp,fd_max);
-                  break;
-
-              default:
-                  if (vtty->fd_array[0] != -1) {
-                      FD_SET(vtty->fd_array[0],&rfds);
-                      fd_max = m_max(vtty->fd_array[0],fd_max);
-                  }
-          }
-
-      }
-      VTTY_LIST_UNLOCK();
-
       /* Wait for incoming data */
-      tv.tv_sec  = 0;
-      tv.tv_usec = 50 * 1000;  /* 50 ms */
-      res = select(fd_max+1,&rfds,NULL,NULL,&tv);
-
-      if (res == -1) {
-         if (errno != EINTR) {
-            perror("vtty_thread: select");
-         }
+      if (ev_loop_wait(vtty_ev_loop,50) == -1) {
+         usleep(50000);
          continue;
       }
 
-      /* Examine active FDs and call user handlers */
+      /* Call user handlers for the active FDs */
       VTTY_LIST_LOCK();
-      for(vtty=vtty_list;vtty;vtty=vtty->next) {
+      ev_loop_dispatch(vtty_ev_loop);
 
-         switch(vtty->type) {
-            case VTTY_TYPE_TCP:
-
-               /* check incoming connection */
-               for(i=0;i<vtty->fd_count;i++) {
-                   
-                   if (vtty->fd_array[i] == -1)
-                       continue;
-                   
-                   if (!FD_ISSET(vtty->fd_array[i],&rfds))
-                       continue;
-                   
-                   vtty_tcp_conn_accept(vtty, i);
-               }
-
-               /* check established connection */
-               fd_pool_check_input(&vtty->fd_pool,&rfds,vtty_tcp_input,vtty);
-               break;
-      
-            /* Term, Serial */
-            default:
-               if (vtty->fd_array[0] != -1 && FD_ISSET(vtty->fd_array[0],&rfds)) {
-                  vtty_read_and_store(vtty,&vtty->fd_array[0]);
-                  vtty->input_pending = TRUE;
-               }
-         }
-         
+      for(vtty=vtty_list;vtty;vtty=vtty->next) {
          if (vtty->input_pending) {
             if (vtty->read_notifier != NULL)
                vtty->read_notifier(vtty);
@@ -1253,6 +1273,14 @@ static void *vtty_thread_main(void *arg)
          /* Flush any pending output */
          if (!vtty->managed_flush)
             vtty_flush(vtty);
+
+         /* Write the recorded output */
+         if (vtty->rec)
+            vtty_rec_flush(vtty->rec);
+
+         /* Forget the clients closed on output errors */
+         if (vtty->mux)
+            vtty_mux_sync(vtty->mux,&vtty->fd_pool);
       }
       VTTY_LIST_UNLOCK();
    }
@@ -1263,6 +1291,11 @@ static void *vtty_thread_main(void *arg)
 /* Initialize the VTTY thread */
 int vtty_init(void)
 {
+   if (!(vtty_ev_loop = ev_loop_create())) {
+      fprintf(stderr,"vtty: unable to create the event loop\n");
+      return(-1);
+   }
+
    if (pthread_create(&vtty_thread,NULL,vtty_thread_main,NULL)) {
       perror("vtty: pthread_create");
       return(-1);
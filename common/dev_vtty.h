@@ -71,9 +71,8 @@ struct virtual_tty {
    int fd_count;
    int tcp_port;
    int terminal_support;
-   int input_state;
+   vtty_input_t input;    /* telnet input state (terminal/serial port) */
    int input_pending;
-   int telnet_cmd, telnet_opt, telnet_qual;
    int managed_flush;
    u_char buffer[VTTY_BUFFER_SIZE];
    u_int read_ptr,write_ptr;
@@ -84,14 +83,15 @@ struct virtual_tty {
 
    /* FD Pool (for TCP connections) */
    fd_pool_t fd_pool;
+
+   /* Console multiplexer (read-write owner and read-only observers, TCP) */
+   vtty_mux_t *mux;
    
    /* Read notification */
    void (*read_notifier)(vtty_t *);
 
-   /* Old text for replay */
-   u_char replay_buffer[VTTY_BUFFER_SIZE];
-   u_int replay_ptr;
-   u_char replay_full;
+   /* Console recorder (scrollback replay and recordings), owned by the VM */
+   vtty_rec_t *rec;
 };
 
 #define VTTY_LOCK(tty) pthread_mutex_lock(&(tty)->lock);
@@ -156,9 +156,13 @@ void dev_remove(vm_instance_t *vm,struct vdevice *dev)
 
       close(dev->fd);
    } else {
-      /* Use of malloc'ed host memory: free it */
-      if (dev->host_addr)
-         free((void *)dev->host_addr);
+      /* Use of malloc'ed or anonymous host memory: free it */
+      if (dev->host_addr) {
+         if (dev->flags & VDEVICE_FLAG_ANON_MMAP)
+            memzone_unmap((void *)dev->host_addr,dev->phys_len);
+         else
+            free((void *)dev->host_addr);
+      }
    }
 
    /* reinitialize the device to a clean state */
@@ -255,9 +259,13 @@ struct vdevice *dev_create_ram(vm_instance_t *vm,char *name,
             return NULL;
          }
       
+         memzone_advise(ram_ptr,dev->phys_len,dev->fd,MEMZONE_KIND_RAM,NULL);
          dev->host_addr = (m_iptr_t)ram_ptr;
       } else {
-         dev->host_addr = (m_iptr_t)m_memalign(4096,dev->phys_len);
+         /* Anonymous memory, can use explicit huge pages */
+         ram_ptr = memzone_map_anon(dev->phys_len,MEMZONE_KIND_RAM,NULL);
+         dev->host_addr = (m_iptr_t)ram_ptr;
+         dev->flags |= VDEVICE_FLAG_ANON_MMAP;
       }
    
       if (!dev->host_addr) {
@@ -299,6 +307,8 @@ dev_create_ghost_ram(vm_instance_t *vm,char *name,int sparse,char *filename,
          free(dev);
          return NULL;
       }
+
+      memzone_advise(ram_ptr,dev->phys_len,dev->fd,MEMZONE_KIND_RAM,NULL);
    } else {
       if (vm_ghost_image_get(filename,&ram_ptr,&dev->fd) == -1) {
          free(dev);
@@ -20,6 +20,7 @@
 #define VDEVICE_FLAG_SYNC         0x08  /* Forced sync */
 #define VDEVICE_FLAG_SPARSE       0x10  /* Sparse device */
 #define VDEVICE_FLAG_GHOST        0x20  /* Ghost device */
+#define VDEVICE_FLAG_ANON_MMAP    0x40  /* Anonymous memory mapping */
 
 #define VDEVICE_PTE_DIRTY  0x01
 
@@ -165,7 +165,7 @@ static void create_log_file(void)
       }
    }
 
-   if (!(log_file = fopen(log_file_name,"w"))) {
+   if (m_log_open(log_file_name) == -1) {
       fprintf(stderr,"Unable to create log file (%s).\n",strerror(errno));
       exit(EXIT_FAILURE);
    }
@@ -174,7 +174,7 @@ static void create_log_file(void)
 /* Close general log file */
 static void close_log_file(void)
 {
-   if (log_file) fclose(log_file);
+   m_log_close();
    free(log_file_name);
 
    log_file = NULL;
@@ -230,6 +230,7 @@ static void show_usage(vm_instance_t *vm,int argc,char *argv[])
           "  --notelnetmsg      : Disable message when using tcp console/aux\n"
           "  --filepid filename : Store dynamips pid in a file\n"
           "  --console-binding-addr: binding address for tcp console/aux\n"
+          "  --hash-seed <k0>:<k1>: Set the key of the seeded hash functions\n"
           "\n",
           LOGFILE_DEFAULT_NAME,VM_TIMER_IRQ_CHECK_ITV,
           vm->ram_size,vm->rom_size,vm->nvram_size,vm->conf_reg_setup,
@@ -361,6 +362,26 @@ static vm_platform_t *cli_get_platform_type(int argc,char *argv[])
    return platform;
 }
 
+/* Set the key of the seeded hash functions ("<k0>:<k1>") */
+static void cli_set_hash_seed(char *str)
+{
+   m_uint64_t k0,k1;
+   char *end,*k1_str;
+
+   k0 = strtoull(str,&end,0);
+
+   if ((end != str) && (*end == ':')) {
+      k1_str = end + 1;
+      k1 = strtoull(k1_str,&end,0);
+
+      if ((end != k1_str) && (*end == 0) && (hash_set_seed(k0,k1) == 0))
+         return;
+   }
+
+   fprintf(stderr,"Invalid hash seed '%s'\n",str);
+   exit(EXIT_FAILURE);
+}
+
 static struct option cmd_line_lopts[] = {
    { "disk0"      , 1, NULL, OPT_DISK0_SIZE },
    { "disk1"      , 1, NULL, OPT_DISK1_SIZE },
@@ -375,6 +396,7 @@ static struct option cmd_line_lopts[] = {
    { "startup-config", 1, NULL, OPT_STARTUP_CONFIG_FILE },
    { "private-config", 1, NULL, OPT_PRIVATE_CONFIG_FILE },
    { "console-binding-addr", 1, NULL, OPT_CONSOLE_BINDING_ADDR },
+   { "hash-seed"  , 1, NULL, OPT_HASH_SEED },
    { NULL         , 0, NULL, 0 },
 };
 
@@ -511,6 +533,11 @@ static int parse_std_cmd_line(int argc,char *argv[])
             printf("Console binding address set to %s\n", console_binding_addr);
             break;
 
+         /* Key of the seeded hash functions */
+         case OPT_HASH_SEED:
+            cli_set_hash_seed(optarg);
+            break;
+
          /* Use physical memory to emulate RAM (no-mapped file) */
          case 'X':
             vm->ram_mmap = 0;
@@ -837,6 +864,11 @@ static int run_hypervisor(int argc,char *argv[])
             printf("Console binding address set to %s\n", console_binding_addr);
             break;
 
+         /* Key of the seeded hash functions */
+         case OPT_HASH_SEED:
+            cli_set_hash_seed(optarg);
+            break;
+
          /* Oops ! */
          case '?':
             //show_usage(argc,argv,VM_TYPE_C7200);
@@ -77,6 +77,7 @@ extern char *console_binding_addr;
 #define OPT_STARTUP_CONFIG_FILE  0x140
 #define OPT_PRIVATE_CONFIG_FILE  0x141
 #define OPT_CONSOLE_BINDING_ADDR 0x150
+#define OPT_HASH_SEED   0x151
 
 /* Delete all objects */
 void dynamips_reset(void);
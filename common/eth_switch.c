@@ -36,63 +36,162 @@ void ethsw_debug(ethsw_table_t *t,char *fmt,...)
    }
 }
 
-/* Compute hash index on the specified MAC address and VLAN */
-static inline u_int ethsw_hash_index(n_eth_addr_t *addr,u_int vlan_id)
+/* MAC address table events (moves and port security) */
+static void ethsw_mac_event(int event,const mac_table_entry_t *entry,
+                            void *old_port,ethsw_table_t *t)
 {
-   u_int h_index;
+   netio_desc_t *nio = entry->port;
+   netio_desc_t *old_nio = old_port;
+   char mac[32];
 
-   h_index =  (addr->eth_addr_byte[0] << 8) | addr->eth_addr_byte[1];
-   h_index ^= (addr->eth_addr_byte[2] << 8) | addr->eth_addr_byte[3];
-   h_index ^= (addr->eth_addr_byte[4] << 8) | addr->eth_addr_byte[5];
-   h_index ^= vlan_id;
-   return(h_index & (ETHSW_HASH_SIZE - 1));
+   n_eth_ntoa(mac,(n_eth_addr_t *)&entry->mac_addr,1);
+
+   switch(event) {
+      case MAC_EVENT_MOVE:
+         m_log_lvl(M_LOG_WARN,"ETHSW","%s: MAC %s in VLAN %u moved "
+                   "from port %s to port %s\n",t->name,mac,entry->vlan_id,
+                   old_nio->name,nio->name);
+         break;
+
+      case MAC_EVENT_VIOLATION:
+         m_log_lvl(M_LOG_WARN,"ETHSW","%s: port security violation on "
+                   "port %s, MAC %s in VLAN %u\n",
+                   t->name,nio->name,mac,entry->vlan_id);
+         break;
+
+      case MAC_EVENT_SHUTDOWN:
+         m_log_lvl(M_LOG_ERROR,"ETHSW","%s: port %s err-disabled by "
+                   "port security\n",t->name,nio->name);
+         break;
+
+      /* the port may be gone, don't use it */
+      case MAC_EVENT_AGED:
+         ethsw_debug(t,"MAC %s in VLAN %u aged out.\n",mac,entry->vlan_id);
+         break;
+   }
 }
 
-/* Invalidate the whole MAC address table */
-static void ethsw_invalidate(ethsw_table_t *t)
+/* Find a port by its NIO name (with lock held) */
+static netio_desc_t *ethsw_find_port(ethsw_table_t *t,char *nio_name)
 {
-   memset(t->mac_addr_table,0,sizeof(t->mac_addr_table));
+   int i;
+
+   for(i=0;i<ETHSW_MAX_NIO;i++)
+      if (t->nio[i] && !strcmp(t->nio[i]->name,nio_name))
+         return(t->nio[i]);
+
+   return NULL;
 }
 
-/* Invalidate entry of the MAC address table referring to the specified NIO */
-static void ethsw_invalidate_port(ethsw_table_t *t,netio_desc_t *nio)
+/* Send a BPDU (with lock held) */
+static void ethsw_stp_tx(netio_desc_t *nio,u_char *pkt,size_t len,
+                         ethsw_table_t *t)
 {
-   ethsw_mac_entry_t *entry;
-   int i;
+   if (!span_is_destination(t->span,nio))
+      netio_send(nio,pkt,len);
+}
 
-   for(i=0;i<ETHSW_HASH_SIZE;i++) {
-      entry = &t->mac_addr_table[i];
-      if (entry->nio == nio) {
-         entry->nio = NULL;
-         entry->vlan_id = 0;
-      }
-   }
+/* Flush the MAC addresses of a port after a topology change */
+static void ethsw_stp_flush(netio_desc_t *nio,ethsw_table_t *t)
+{
+   mac_table_flush_port(t->mac_table,nio,FALSE);
 }
 
-/* Push a 802.1Q tag */
-static void dot1q_push_tag(m_uint8_t *pkt,ethsw_packet_t *sp,u_int vlan,m_uint16_t ethertype)
+/* Port role/state change */
+static void ethsw_stp_state(netio_desc_t *nio,int role,int state,
+                            ethsw_table_t *t)
 {
-   n_eth_dot1q_hdr_t *hdr;
+   m_log_lvl(M_LOG_INFO,"ETHSW","%s: STP port %s is %s, %s\n",
+             t->name,nio->name,rstp_role_str(role),rstp_state_str(state));
+}
+
+/* Spanning tree timer */
+static int ethsw_stp_timer(ethsw_table_t *t,timer_entry_t *timer)
+{
+   ETHSW_LOCK(t);
+   rstp_tick(t->rstp);
+   ETHSW_UNLOCK(t);
+   return(TRUE);
+}
+
+/*
+ * Bridge address of a switch: locally administered, derived from its name
+ * so the bridge ID doesn't change across restarts.
+ */
+static void ethsw_bridge_addr(char *name,n_eth_addr_t *addr)
+{
+   m_uint32_t crc;
+
+   crc = crc32_compute(0xFFFFFFFF,(m_uint8_t *)name,strlen(name));
 
-   memcpy(pkt,sp->pkt,(N_ETH_HLEN - 2));
+   addr->eth_addr_byte[0] = 0x02;
+   addr->eth_addr_byte[1] = 0x00;
+   addr->eth_addr_byte[2] = crc >> 24;
+   addr->eth_addr_byte[3] = crc >> 16;
+   addr->eth_addr_byte[4] = crc >> 8;
+   addr->eth_addr_byte[5] = crc;
+}
 
-   hdr = (n_eth_dot1q_hdr_t *)pkt;
-   hdr->type    = htons(ethertype);
-   hdr->vlan_id = htons(sp->input_vlan);
+/* Send a copy of a frame on a SPAN destination port (with lock held) */
+static void ethsw_span_tx(netio_desc_t *nio,u_char *pkt,size_t len,
+                          ethsw_table_t *t)
+{
+   netio_send(nio,pkt,len);
+}
 
-   memcpy(pkt + sizeof(n_eth_dot1q_hdr_t),
-          sp->pkt + (N_ETH_HLEN - 2),
-          sp->pkt_len - (N_ETH_HLEN - 2));
+/* Send a packet on an output port and mirror it */
+static void ethsw_send(ethsw_table_t *t,ethsw_packet_t *sp,
+                       netio_desc_t *op,u_char *pkt,size_t len)
+{
+   netio_send(op,pkt,len);
+   span_mirror(t->span,op,sp->input_vlan,SPAN_DIR_TX,pkt,len,&sp->span_mask);
+}
+
+/* Push a 802.1Q tag */
+static void dot1q_push_tag(m_uint8_t *pkt,ethsw_packet_t *sp,u_int vlan,m_uint16_t ethertype)
+{
+   vlan_push_tag(sp->pkt,sp->pkt_len,sp->input_vlan,ethertype,
+                 pkt,sp->pkt_len + VLAN_TAG_LEN);
 }
 
 /* Pop a 802.1Q tag */
 static void dot1q_pop_tag(m_uint8_t *pkt,ethsw_packet_t *sp)
 {
-   memcpy(pkt,sp->pkt,(N_ETH_HLEN - 2));
+   vlan_pop_tag(sp->pkt,sp->pkt_len,pkt,sp->pkt_len - VLAN_TAG_LEN);
+}
+
+/* 
+ * Send a packet on an ISL trunk port. The 802.1Q VLAN of a tagged packet
+ * is kept, an untagged packet is sent in the input VLAN. A packet coming
+ * from a QinQ port is sent in the tunnel VLAN with its customer tag.
+ */
+static void ethsw_send_isl(ethsw_table_t *t,ethsw_packet_t *sp,
+                           netio_desc_t *op)
+{
+   vlan_tag_t tag;
+   m_uint8_t *pkt;
+   ssize_t len;
+
+   memset(&tag,0,sizeof(tag));
+   tag.encap   = VLAN_ENCAP_ISL;
+   tag.vlan_id = sp->input_vlan;
+
+   len = sp->pkt_len + VLAN_ISL_HDR_LEN + VLAN_ISL_FCS_LEN;
 
-   memcpy(pkt + (N_ETH_HLEN - 2),
-          sp->pkt + sizeof(n_eth_dot1q_hdr_t),
-          sp->pkt_len - sizeof(n_eth_dot1q_hdr_t));
+   if (!(pkt = malloc(len))) {
+      perror("ethsw_send_isl");
+      return;
+   }
+
+   if (sp->input_port->vlan_port_type == ETHSW_PORT_TYPE_QINQ)
+      len = vlan_encap(sp->pkt,sp->pkt_len,&tag,pkt,len);
+   else
+      len = vlan_translate(sp->pkt,sp->pkt_len,&tag,pkt,len);
+
+   if (len != -1)
+      ethsw_send(t,sp,op,pkt,len);
+
+   free(pkt);
 }
 
 /* Input vector for ACCESS ports */
@@ -104,7 +203,7 @@ static void ethsw_iv_access(ethsw_table_t *t,ethsw_packet_t *sp,
    switch(op->vlan_port_type) {
       /* Access -> Access: no special treatment */
       case ETHSW_PORT_TYPE_ACCESS:
-         netio_send(op,sp->pkt,sp->pkt_len);
+         ethsw_send(t,sp,op,sp->pkt,sp->pkt_len);
          break;
 
       /* Access -> 802.1Q: push tag */
@@ -114,7 +213,7 @@ static void ethsw_iv_access(ethsw_table_t *t,ethsw_packet_t *sp,
           * forward the packet without adding the tag.
           */
          if (op->vlan_id == sp->input_vlan) {
-            netio_send(op,sp->pkt,sp->pkt_len);
+            ethsw_send(t,sp,op,sp->pkt,sp->pkt_len);
          } else {
             pkt = malloc(sp->pkt_len+4);
             if (pkt == NULL) {
@@ -123,11 +222,16 @@ static void ethsw_iv_access(ethsw_table_t *t,ethsw_packet_t *sp,
             }
             memset(pkt, 0, sp->pkt_len+4);
             dot1q_push_tag(pkt,sp,op->vlan_id,sp->input_port->ethertype);
-            netio_send(op,pkt,sp->pkt_len+4);
+            ethsw_send(t,sp,op,pkt,sp->pkt_len+4);
             free(pkt);
          }
          break;
 
+      /* Access -> ISL: encapsulate in the input VLAN */
+      case ETHSW_PORT_TYPE_ISL:
+         ethsw_send_isl(t,sp,op);
+         break;
+
       default:
          fprintf(stderr,"ethsw_iv_access: unknown port type %u\n",
                  op->vlan_port_type);
@@ -156,7 +260,7 @@ static void ethsw_iv_dot1q(ethsw_table_t *t,ethsw_packet_t *sp,
          }
          memset(pkt, 0, sp->pkt_len-4);
          dot1q_pop_tag(pkt,sp);
-         netio_send(op,pkt,sp->pkt_len-4);
+         ethsw_send(t,sp,op,pkt,sp->pkt_len-4);
          free(pkt);
          break;
 
@@ -170,10 +274,10 @@ static void ethsw_iv_dot1q(ethsw_table_t *t,ethsw_packet_t *sp,
             }
             memset(pkt, 0, sp->pkt_len-4);
             dot1q_pop_tag(pkt,sp);
-            netio_send(op,pkt,sp->pkt_len-4);
+            ethsw_send(t,sp,op,pkt,sp->pkt_len-4);
             free(pkt);
          } else {
-            netio_send(op,sp->pkt,sp->pkt_len);
+            ethsw_send(t,sp,op,sp->pkt,sp->pkt_len);
          }
          break;
 
@@ -190,11 +294,16 @@ static void ethsw_iv_dot1q(ethsw_table_t *t,ethsw_packet_t *sp,
             }
             memset(pkt, 0, sp->pkt_len-4);
             dot1q_pop_tag(pkt,sp);
-            netio_send(op,pkt,sp->pkt_len-4);
+            ethsw_send(t,sp,op,pkt,sp->pkt_len-4);
             free(pkt);
          }
          break;
 
+      /* 802.1Q -> ISL: translate the tag */
+      case ETHSW_PORT_TYPE_ISL:
+         ethsw_send_isl(t,sp,op);
+         break;
+
       default:
          fprintf(stderr,"ethsw_iv_dot1q: unknown port type %u\n",
                  op->vlan_port_type);
@@ -217,7 +326,7 @@ static void ethsw_iv_qinq(ethsw_table_t *t,ethsw_packet_t *sp,
          }
          memset(pkt, 0, sp->pkt_len+4);
          dot1q_push_tag(pkt,sp,sp->input_port->vlan_id,sp->input_port->ethertype);
-         netio_send(op,pkt,sp->pkt_len+4);
+         ethsw_send(t,sp,op,pkt,sp->pkt_len+4);
          free(pkt);
          break;
 
@@ -234,13 +343,18 @@ static void ethsw_iv_qinq(ethsw_table_t *t,ethsw_packet_t *sp,
             }
             memset(pkt, 0, sp->pkt_len-4);
             dot1q_pop_tag(pkt,sp);
-            netio_send(op,pkt,sp->pkt_len-4);
+            ethsw_send(t,sp,op,pkt,sp->pkt_len-4);
             free(pkt);
          }
          break;
 
+      /* QinQ -> ISL: send in the tunnel VLAN */
+      case ETHSW_PORT_TYPE_ISL:
+         ethsw_send_isl(t,sp,op);
+         break;
+
       default:
-         fprintf(stderr,"ethsw_iv_dot1q: unknown port type %u\n",
+         fprintf(stderr,"ethsw_iv_qinq: unknown port type %u\n",
                  op->vlan_port_type);
    }
 }
@@ -261,6 +375,18 @@ static void ethsw_flood(ethsw_table_t *t,ethsw_packet_t *sp)
       if (!op || (op == sp->input_port))
          continue;
 
+      /* skip ports err-disabled by port security */
+      if (mac_table_port_is_shutdown(t->mac_table,op))
+         continue;
+
+      /* skip SPAN destination ports */
+      if (span_is_destination(t->span,op))
+         continue;
+
+      /* skip ports blocked by the spanning tree */
+      if (rstp_port_state(t->rstp,op) != RSTP_STATE_FORWARDING)
+         continue;
+
       /* skip output port configured in access mode with a different vlan */
       if ((op->vlan_port_type == ETHSW_PORT_TYPE_ACCESS) &&
           (op->vlan_id != sp->input_vlan))
@@ -276,16 +402,19 @@ static void ethsw_forward(ethsw_table_t *t,ethsw_packet_t *sp)
 {
    n_eth_hdr_t *hdr = (n_eth_hdr_t *)sp->pkt;
    ethsw_input_vector_t input_vector;
-   ethsw_mac_entry_t *entry;
-   u_int h_index;
+   netio_desc_t *op;
 
-   /* Learn the source MAC address */
-   h_index = ethsw_hash_index(&hdr->saddr,sp->input_vlan);
-   entry = &t->mac_addr_table[h_index];
+   /* Learn the source MAC address, port security may drop the packet */
+   if (mac_table_learn(t->mac_table,&hdr->saddr,sp->input_vlan,
+                       sp->input_port) == MAC_LEARN_DROP)
+   {
+      ethsw_debug(t,"source address refused, dropping packet.\n");
+      return;
+   }
 
-   entry->nio      = sp->input_port;
-   entry->vlan_id  = sp->input_vlan;
-   entry->mac_addr = hdr->saddr;
+   /* Spanning tree learning state: no forwarding */
+   if (sp->input_state != RSTP_STATE_FORWARDING)
+      return;
 
    /* If we have a broadcast/multicast packet, flood it */
    if (eth_addr_is_mcast(&hdr->daddr)) {
@@ -295,25 +424,28 @@ static void ethsw_forward(ethsw_table_t *t,ethsw_packet_t *sp)
    }
 
    /* Lookup on the destination MAC address (unicast) */
-   h_index = ethsw_hash_index(&hdr->daddr,sp->input_vlan);
-   entry = &t->mac_addr_table[h_index];
+   op = mac_table_lookup(t->mac_table,&hdr->daddr,sp->input_vlan);
 
    /* If the dest MAC is unknown, flood the packet */
-   if (memcmp(&entry->mac_addr,&hdr->daddr,N_ETH_ALEN) ||
-       (entry->vlan_id != sp->input_vlan))
-   {
+   if (!op) {
       ethsw_debug(t,"unknown dest, flooding packet.\n");
       ethsw_flood(t,sp);
       return;
    }
 
    /* Forward the packet to the output port only */
-   if (entry->nio != sp->input_port) {
+   if (op == sp->input_port) {
+      ethsw_debug(t,"source and dest ports identical, dropping.\n");
+   } else if (mac_table_port_is_shutdown(t->mac_table,op)) {
+      ethsw_debug(t,"dest port err-disabled, dropping.\n");
+   } else if (span_is_destination(t->span,op)) {
+      ethsw_debug(t,"dest port is a SPAN destination, dropping.\n");
+   } else if (rstp_port_state(t->rstp,op) != RSTP_STATE_FORWARDING) {
+      ethsw_debug(t,"dest port blocked by spanning tree, dropping.\n");
+   } else {
       input_vector = sp->input_port->vlan_input_vector;
       assert(input_vector != NULL);
-      input_vector(t,sp,entry->nio);
-   } else {
-      ethsw_debug(t,"source and dest ports identical, dropping.\n");
+      input_vector(t,sp,op);
    }
 }
 
@@ -326,6 +458,7 @@ static inline int ethsw_receive(ethsw_table_t *t,netio_desc_t *nio,
    n_eth_isl_hdr_t *isl_hdr;
    n_eth_hdr_t *eth_hdr;
    n_eth_llc_hdr_t *llc_hdr;
+   u_char isl_pkt[ETHSW_MAX_PKT_SIZE];
    ethsw_packet_t sp;
    u_char *ptr;
 
@@ -333,11 +466,28 @@ static inline int ethsw_receive(ethsw_table_t *t,netio_desc_t *nio,
    sp.input_vlan = 0;
    sp.pkt        = pkt;
    sp.pkt_len    = pkt_len;
+   sp.span_mask  = 0;
 
    /* Skip runt packets */
    if (sp.pkt_len < N_ETH_HLEN)
       return(-1);
 
+   /* SPAN destination ports don't take part in the switching */
+   if (span_is_destination(t->span,nio))
+      return(-1);
+
+   /* Spanning tree BPDUs (flooded if the spanning tree is disabled) */
+   eth_hdr = (n_eth_hdr_t *)pkt;
+
+   if (rstp_is_enabled(t->rstp) && eth_addr_is_stp(&eth_hdr->daddr)) {
+      rstp_receive(t->rstp,nio,pkt,pkt_len);
+      return(0);
+   }
+
+   /* Drop everything else on ports discarding for the spanning tree */
+   if ((sp.input_state = rstp_port_state(t->rstp,nio)) == RSTP_STATE_DISCARDING)
+      return(-1);
+
    /* Determine the input VLAN */
    switch(nio->vlan_port_type) {
       case ETHSW_PORT_TYPE_ACCESS:
@@ -393,6 +543,13 @@ static inline int ethsw_receive(ethsw_table_t *t,netio_desc_t *nio,
                               sizeof(n_eth_llc_hdr_t));
          ptr = (u_char *)&isl_hdr->vlan;
          sp.input_vlan = (((u_int)ptr[0] << 8) | ptr[1]) >> 1;
+
+         /* Switch the encapsulated frame, as received on an access port */
+         if ((sp.pkt_len = vlan_decap(pkt,pkt_len,NULL,
+                                      isl_pkt,sizeof(isl_pkt))) == -1)
+            return(-1);
+
+         sp.pkt = isl_pkt;
          break;
 
       default:
@@ -401,8 +558,11 @@ static inline int ethsw_receive(ethsw_table_t *t,netio_desc_t *nio,
          return(-1);
    }
 
-   if (sp.input_vlan != 0)
+   if (sp.input_vlan != 0) {
+      span_mirror(t->span,nio,sp.input_vlan,SPAN_DIR_RX,pkt,pkt_len,
+                  &sp.span_mask);
       ethsw_forward(t,&sp);
+   }
    return(0);
 }
 
@@ -435,6 +595,15 @@ static void set_dot1q_port(netio_desc_t *nio,u_int native_vlan)
    nio->ethertype         = N_ETH_PROTO_DOT1Q;
 }
 
+/* Set a port as an ISL trunk port */
+static void set_isl_port(netio_desc_t *nio)
+{
+   nio->vlan_port_type    = ETHSW_PORT_TYPE_ISL;
+   nio->vlan_id           = 0;
+   nio->vlan_input_vector = ethsw_iv_access;
+   nio->ethertype         = N_ETH_PROTO_DOT1Q;
+}
+
 /* Set a port as a Q-in-Q trunk port */
 static void set_qinq_port(netio_desc_t *nio,u_int outer_vlan,m_uint16_t ethertype)
 {
@@ -459,6 +628,7 @@ int ethsw_release(char *name)
 /* Create a virtual ethernet switch */
 ethsw_table_t *ethsw_create(char *name)
 {
+   n_eth_addr_t bridge_addr;
    ethsw_table_t *t;
 
    /* Allocate a new switch structure */
@@ -471,6 +641,30 @@ ethsw_table_t *ethsw_create(char *name)
    if (!(t->name = strdup(name)))
       goto err_name;
 
+   if (!(t->mac_table = mac_table_create(MAC_TABLE_DEFAULT_AGING)))
+      goto err_table;
+
+   mac_table_set_event_cbk(t->mac_table,
+                           (mac_table_event_cbk_t)ethsw_mac_event,t);
+
+   ethsw_bridge_addr(name,&bridge_addr);
+   t->rstp = rstp_create(&bridge_addr,
+                         (rstp_tx_cbk_t)ethsw_stp_tx,
+                         (rstp_flush_cbk_t)ethsw_stp_flush,
+                         (rstp_state_cbk_t)ethsw_stp_state,t);
+
+   if (!t->rstp)
+      goto err_rstp;
+
+   t->stp_timer = timer_create_entry(RSTP_TICK_INTERVAL,FALSE,10,
+                                     (timer_proc)ethsw_stp_timer,t);
+   if (!t->stp_timer)
+      goto err_timer;
+
+   t->span = span_create((span_tx_cbk_t)ethsw_span_tx,t);
+   if (!t->span)
+      goto err_span;
+
    /* Record this object in registry */
    if (registry_add(t->name,OBJ_TYPE_ETHSW,t) == -1) {
       fprintf(stderr,"ethsw_create: unable to register switch '%s'\n",name);
@@ -480,6 +674,14 @@ ethsw_table_t *ethsw_create(char *name)
    return t;
 
  err_reg:
+   span_free(t->span);
+ err_span:
+   timer_remove(t->stp_timer);
+ err_timer:
+   rstp_free(t->rstp);
+ err_rstp:
+   mac_table_free(t->mac_table);
+ err_table:
    free(t->name);
  err_name:
    free(t);
@@ -511,6 +713,7 @@ int ethsw_add_netio(ethsw_table_t *t,char *nio_name)
    set_access_port(nio,1);
 
    t->nio[i] = nio;
+   rstp_add_port(t->rstp,nio,i+1);
    netio_rxl_add(nio,(netio_rx_handler_t)ethsw_recv_pkt,t,NULL);
    ETHSW_UNLOCK(t);
    return(0);
@@ -547,7 +750,9 @@ int ethsw_remove_netio(ethsw_table_t *t,char *nio_name)
       goto error;
 
    /* Invalidate this port in the MAC address table */
-   ethsw_invalidate_port(t,nio);
+   mac_table_remove_port(t->mac_table,nio);
+   rstp_remove_port(t->rstp,nio);
+   span_remove_port(t->span,nio);
    t->nio[i] = NULL;
 
    ETHSW_UNLOCK(t);
@@ -561,37 +766,319 @@ int ethsw_remove_netio(ethsw_table_t *t,char *nio_name)
    return(-1);
 }
 
-/* Clear the MAC address table */
+/* Clear the dynamic entries of the MAC address table */
 int ethsw_clear_mac_addr_table(ethsw_table_t *t)
 {
    ETHSW_LOCK(t);
-   ethsw_invalidate(t);
+   mac_table_flush(t->mac_table,FALSE);
    ETHSW_UNLOCK(t);
    return(0);
 }
 
+struct ethsw_iterate_arg {
+   ethsw_table_t *t;
+   ethsw_foreach_entry_t cb;
+   void *opt_arg;
+};
+
+static void ethsw_iterate_entry(const mac_table_entry_t *mte,
+                                struct ethsw_iterate_arg *arg)
+{
+   ethsw_mac_entry_t entry;
+
+   entry.nio      = mte->port;
+   entry.mac_addr = mte->mac_addr;
+   entry.vlan_id  = mte->vlan_id;
+   entry.type     = mte->type;
+   entry.age      = mte->age;
+   arg->cb(arg->t,&entry,arg->opt_arg);
+}
+
 /* Iterate over all entries of the MAC address table */
 int ethsw_iterate_mac_addr_table(ethsw_table_t *t,ethsw_foreach_entry_t cb,
                                  void *opt_arg)
 {
-   ethsw_mac_entry_t *entry;
+   struct ethsw_iterate_arg arg = { t, cb, opt_arg };
+
+   ETHSW_LOCK(t);
+   mac_table_foreach(t->mac_table,
+                     (mac_table_foreach_cbk_t)ethsw_iterate_entry,&arg);
+   ETHSW_UNLOCK(t);
+   return(0);
+}
+
+/* Set the MAC address aging time (in seconds, 0 to disable aging) */
+int ethsw_set_mac_aging(ethsw_table_t *t,u_int aging)
+{
+   ETHSW_LOCK(t);
+   mac_table_set_aging(t->mac_table,aging);
+   ETHSW_UNLOCK(t);
+   return(0);
+}
+
+/* Add a static MAC address */
+int ethsw_add_static_mac(ethsw_table_t *t,char *nio_name,
+                         n_eth_addr_t *mac,u_int vlan_id)
+{
+   netio_desc_t *nio;
+   int res = -1;
+
+   if (!vlan_id || (vlan_id > 4095))
+      return(-1);
+
+   ETHSW_LOCK(t);
+
+   if ((nio = ethsw_find_port(t,nio_name)) != NULL) {
+      mac_table_add_static(t->mac_table,mac,vlan_id,nio);
+      res = 0;
+   }
+
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Add a sticky MAC address (restored from a saved configuration) */
+int ethsw_add_sticky_mac(ethsw_table_t *t,char *nio_name,
+                         n_eth_addr_t *mac,u_int vlan_id)
+{
+   netio_desc_t *nio;
+   int res = -1;
+
+   if (!vlan_id || (vlan_id > 4095))
+      return(-1);
+
+   ETHSW_LOCK(t);
+
+   if ((nio = ethsw_find_port(t,nio_name)) != NULL) {
+      mac_table_add_sticky(t->mac_table,mac,vlan_id,nio);
+      res = 0;
+   }
+
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Remove a MAC address (static, sticky or dynamic) */
+int ethsw_remove_mac(ethsw_table_t *t,n_eth_addr_t *mac,u_int vlan_id)
+{
+   int res;
+
+   ETHSW_LOCK(t);
+   res = mac_table_remove(t->mac_table,mac,vlan_id);
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Configure port security (max_macs = 0: no limit) */
+int ethsw_set_port_security(ethsw_table_t *t,char *nio_name,u_int max_macs,
+                            int action,int sticky)
+{
+   netio_desc_t *nio;
+   int res = -1;
+
+   ETHSW_LOCK(t);
+
+   if ((nio = ethsw_find_port(t,nio_name)) != NULL)
+      res = mac_table_set_port_security(t->mac_table,nio,max_macs,
+                                        action,sticky);
+
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Recover a port err-disabled by port security */
+int ethsw_recover_port(ethsw_table_t *t,char *nio_name)
+{
+   netio_desc_t *nio;
+   int res = -1;
+
+   ETHSW_LOCK(t);
+
+   if ((nio = ethsw_find_port(t,nio_name)) != NULL)
+      res = mac_table_port_recover(t->mac_table,nio);
+
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Iterate over the port security status of all ports */
+int ethsw_iterate_port_security(ethsw_table_t *t,ethsw_foreach_port_t cb,
+                                void *opt_arg)
+{
+   mac_port_security_t status;
    int i;
 
    ETHSW_LOCK(t);
 
-   for(i=0;i<ETHSW_HASH_SIZE;i++) {
-      entry = &t->mac_addr_table[i];
+   for(i=0;i<ETHSW_MAX_NIO;i++) {
+      if (!t->nio[i])
+         continue;
+
+      mac_table_get_port_security(t->mac_table,t->nio[i],&status);
+      cb(t,t->nio[i],&status,opt_arg);
+   }
 
-      if (!entry->nio)
+   ETHSW_UNLOCK(t);
+   return(0);
+}
+
+/* Enable or disable the spanning tree */
+int ethsw_set_stp(ethsw_table_t *t,int enable)
+{
+   ETHSW_LOCK(t);
+   rstp_set_enabled(t->rstp,enable);
+   ETHSW_UNLOCK(t);
+   return(0);
+}
+
+/* Set the spanning tree bridge priority and times (in seconds) */
+int ethsw_set_stp_bridge(ethsw_table_t *t,u_int priority,u_int hello_time,
+                         u_int max_age,u_int fwd_delay)
+{
+   int res = -1;
+
+   ETHSW_LOCK(t);
+
+   if ((priority <= 0xFFFF) &&
+       !rstp_set_times(t->rstp,hello_time,max_age,fwd_delay))
+      res = rstp_set_bridge_priority(t->rstp,priority);
+
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Configure a spanning tree port (edge mode: RSTP_EDGE_*) */
+int ethsw_set_stp_port(ethsw_table_t *t,char *nio_name,u_int priority,
+                       u_int path_cost,int edge)
+{
+   netio_desc_t *nio;
+   int res = -1;
+
+   ETHSW_LOCK(t);
+
+   if ((nio = ethsw_find_port(t,nio_name)) != NULL)
+      res = rstp_set_port(t->rstp,nio,priority,path_cost,edge);
+
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Iterate over the spanning tree status of all ports */
+int ethsw_iterate_stp_ports(ethsw_table_t *t,ethsw_foreach_stp_port_t cb,
+                            void *opt_arg)
+{
+   rstp_port_info_t info;
+   int i;
+
+   ETHSW_LOCK(t);
+
+   for(i=0;i<ETHSW_MAX_NIO;i++) {
+      if (!t->nio[i] || (rstp_get_port_info(t->rstp,t->nio[i],&info) == -1))
          continue;
 
-      cb(t,entry,opt_arg);
+      cb(t,t->nio[i],&info,opt_arg);
    }
 
    ETHSW_UNLOCK(t);
    return(0);
 }
 
+/* Set the destination port of a SPAN session (rspan_vlan: 0 = none) */
+int ethsw_set_span_dest(ethsw_table_t *t,u_int id,char *nio_name,
+                        u_int rspan_vlan)
+{
+   netio_desc_t *nio;
+   int res = -1;
+
+   ETHSW_LOCK(t);
+
+   if ((nio = ethsw_find_port(t,nio_name)) != NULL)
+      res = span_set_destination(t->span,id,nio,rspan_vlan);
+
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Add a source port to a SPAN session (direction: SPAN_DIR_*) */
+int ethsw_add_span_port(ethsw_table_t *t,u_int id,char *nio_name,int dir)
+{
+   netio_desc_t *nio;
+   int res = -1;
+
+   ETHSW_LOCK(t);
+
+   if ((nio = ethsw_find_port(t,nio_name)) != NULL)
+      res = span_add_source_port(t->span,id,nio,dir);
+
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Remove a source port from a SPAN session */
+int ethsw_remove_span_port(ethsw_table_t *t,u_int id,char *nio_name)
+{
+   netio_desc_t *nio;
+   int res = -1;
+
+   ETHSW_LOCK(t);
+
+   if ((nio = ethsw_find_port(t,nio_name)) != NULL)
+      res = span_remove_source_port(t->span,id,nio);
+
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Add a source VLAN to a SPAN session (direction: SPAN_DIR_*) */
+int ethsw_add_span_vlan(ethsw_table_t *t,u_int id,u_int vlan_id,int dir)
+{
+   int res;
+
+   ETHSW_LOCK(t);
+   res = span_add_source_vlan(t->span,id,vlan_id,dir);
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Remove a source VLAN from a SPAN session */
+int ethsw_remove_span_vlan(ethsw_table_t *t,u_int id,u_int vlan_id)
+{
+   int res;
+
+   ETHSW_LOCK(t);
+   res = span_remove_source_vlan(t->span,id,vlan_id);
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Delete a SPAN session */
+int ethsw_delete_span(ethsw_table_t *t,u_int id)
+{
+   int res;
+
+   ETHSW_LOCK(t);
+   res = span_delete_session(t->span,id);
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Iterate over the SPAN sessions */
+int ethsw_iterate_span(ethsw_table_t *t,ethsw_foreach_span_t cb,
+                       void *opt_arg)
+{
+   span_session_info_t info;
+   u_int id;
+
+   ETHSW_LOCK(t);
+
+   for(id=1;id<=SPAN_MAX_SESSIONS;id++)
+      if (span_get_session(t->span,id,&info) != -1)
+         cb(t,&info,opt_arg);
+
+   ETHSW_UNLOCK(t);
+   return(0);
+}
+
 /* Set port as an access port */
 int ethsw_set_access_port(ethsw_table_t *t,char *nio_name,u_int vlan_id)
 {
@@ -653,10 +1140,66 @@ int ethsw_set_qinq_port(ethsw_table_t *t,char *nio_name,u_int outer_vlan,m_uint1
    return(res);
 }
 
+/* Set port as an ISL trunk port */
+int ethsw_set_isl_port(ethsw_table_t *t,char *nio_name)
+{
+   int i,res = -1;
+
+   ETHSW_LOCK(t);
+
+   for(i=0;i<ETHSW_MAX_NIO;i++)
+      if (t->nio[i] && !strcmp(t->nio[i]->name,nio_name)) {
+         set_isl_port(t->nio[i]);
+         res = 0;
+         break;
+      }
+
+   ETHSW_UNLOCK(t);
+   return(res);
+}
+
+/* Save a static or sticky MAC address */
+static void ethsw_save_mac(const mac_table_entry_t *entry,void **arg)
+{
+   ethsw_table_t *t = arg[0];
+   netio_desc_t *nio = entry->port;
+   char mac[32];
+
+   if (entry->type == MAC_ENTRY_DYNAMIC)
+      return;
+
+   n_eth_ntoa(mac,(n_eth_addr_t *)&entry->mac_addr,1);
+   fprintf(arg[1],"ethsw add_%s_mac %s %s %s %u\n",
+           (entry->type == MAC_ENTRY_STICKY) ? "sticky" : "static",
+           t->name,nio->name,mac,entry->vlan_id);
+}
+
+/* Save a SPAN source */
+static void ethsw_save_span_source(netio_desc_t *nio,u_int vlan_id,int dir,
+                                   void **arg)
+{
+   ethsw_table_t *t = arg[0];
+   u_int id = *(u_int *)arg[2];
+
+   if (nio != NULL)
+      fprintf(arg[1],"ethsw add_span_port %s %u %s %s\n",
+              t->name,id,nio->name,span_dir_str(dir));
+   else
+      fprintf(arg[1],"ethsw add_span_vlan %s %u %u %s\n",
+              t->name,id,vlan_id,span_dir_str(dir));
+}
+
 /* Save the configuration of a switch */
 void ethsw_save_config(ethsw_table_t *t,FILE *fd)
 {
+   mac_port_security_t status;
+   rstp_bridge_info_t stp;
+   rstp_port_info_t stp_port;
+   span_session_info_t span;
    netio_desc_t *nio;
+   void *arg[2] = { t, fd };
+   void *span_arg[3] = { t, fd, &span.id };
+   u_int aging,id;
    int i;
 
    fprintf(fd,"ethsw create %s\n",t->name);
@@ -665,7 +1208,8 @@ void ethsw_save_config(ethsw_table_t *t,FILE *fd)
 
    for(i=0;i<ETHSW_MAX_NIO;i++)
    {
-      nio = t->nio[i];
+      if (!(nio = t->nio[i]))
+         continue;
 
       fprintf(fd,"ethsw add_nio %s %s\n",t->name,nio->name);
 
@@ -685,10 +1229,59 @@ void ethsw_save_config(ethsw_table_t *t,FILE *fd)
                     t->name,nio->name,nio->vlan_id,nio->ethertype);
             break;
 
+         case ETHSW_PORT_TYPE_ISL:
+            fprintf(fd,"ethsw set_isl_port %s %s\n",t->name,nio->name);
+            break;
+
          default:
             fprintf(stderr,"ethsw_save_config: unknown port type %u\n",
                     nio->vlan_port_type);
       }
+
+      mac_table_get_port_security(t->mac_table,nio,&status);
+      if (status.max_macs || status.sticky)
+         fprintf(fd,"ethsw set_port_security %s %s %u %s%s\n",
+                 t->name,nio->name,status.max_macs,
+                 mac_violation_str(status.action),
+                 status.sticky ? " sticky" : "");
+
+      rstp_get_port_info(t->rstp,nio,&stp_port);
+      if ((stp_port.priority != RSTP_DEFAULT_PORT_PRIORITY) ||
+          (stp_port.path_cost != RSTP_DEFAULT_PATH_COST) ||
+          (stp_port.edge != RSTP_EDGE_AUTO))
+         fprintf(fd,"ethsw set_stp_port %s %s %u %u %s\n",
+                 t->name,nio->name,stp_port.priority,stp_port.path_cost,
+                 rstp_edge_str(stp_port.edge));
+   }
+
+   if ((aging = mac_table_get_aging(t->mac_table)) != MAC_TABLE_DEFAULT_AGING)
+      fprintf(fd,"ethsw set_mac_aging %s %u\n",t->name,aging);
+
+   mac_table_foreach(t->mac_table,
+                     (mac_table_foreach_cbk_t)ethsw_save_mac,arg);
+
+   rstp_get_bridge_info(t->rstp,&stp);
+
+   if ((stp.priority != RSTP_DEFAULT_BRIDGE_PRIORITY) ||
+       (stp.hello_time != RSTP_DEFAULT_HELLO_TIME) ||
+       (stp.max_age != RSTP_DEFAULT_MAX_AGE) ||
+       (stp.fwd_delay != RSTP_DEFAULT_FWD_DELAY))
+      fprintf(fd,"ethsw set_stp_bridge %s %u %u %u %u\n",
+              t->name,stp.priority,stp.hello_time,stp.max_age,stp.fwd_delay);
+
+   if (stp.enabled)
+      fprintf(fd,"ethsw set_stp %s 1\n",t->name);
+
+   for(id=1;id<=SPAN_MAX_SESSIONS;id++) {
+      if (span_get_session(t->span,id,&span) == -1)
+         continue;
+
+      span_foreach_source(t->span,id,
+                          (span_source_cbk_t)ethsw_save_span_source,span_arg);
+
+      if (span.dst != NULL)
+         fprintf(fd,"ethsw set_span_dest %s %u %s %u\n",t->name,id,
+                 ((netio_desc_t *)span.dst)->name,span.rspan_vlan);
    }
 
    ETHSW_UNLOCK(t);
@@ -713,6 +1306,11 @@ static int ethsw_free(void *data,void *arg)
    ethsw_table_t *t = data;
    int i;
 
+   timer_remove(t->stp_timer);
+   rstp_free(t->rstp);
+   span_free(t->span);
+   mac_table_free(t->mac_table);
+
    for(i=0;i<ETHSW_MAX_NIO;i++) {
       if (!t->nio[i])
          continue;
@@ -879,16 +1477,33 @@ static int ethsw_cfg_set_qinq_port(ethsw_table_t *t,char **tokens,int count)
    return(ethsw_set_qinq_port(t,tokens[1],atoi(tokens[2]),ethertype));
 }
 
+/* Set a port as an ISL trunk port */
+static int ethsw_cfg_set_isl_port(ethsw_table_t *t,char **tokens,int count)
+{
+   /* 2 parameters: "ISL", IF */
+   if (count != 2) {
+      fprintf(stderr,"ETHSW: invalid ISL port description.\n");
+      return(-1);
+   }
+
+   return(ethsw_set_isl_port(t,tokens[1]));
+}
+
 #define ETHSW_MAX_TOKENS  16
 
 /* Handle a ETHSW configuration line */
 static int ethsw_handle_cfg_line(ethsw_table_t *t,char *str)
 {
    char *tokens[ETHSW_MAX_TOKENS];
+   m_tok_error_t err;
    int count;
 
-   if ((count = m_strsplit(str,':',tokens,ETHSW_MAX_TOKENS)) <= 1)
+   if ((count = m_strsplit_tok(str,':',0,tokens,ETHSW_MAX_TOKENS,&err)) <= 1) {
+      if (err.code != M_TOK_ERR_NONE)
+         fprintf(stderr,"ETHSW: %s at column %d in \"%s\"\n",
+                 m_tok_strerror(err.code),err.col,str);
       return(-1);
+   }
 
    if (!strcmp(tokens[0],"IF"))
       return(ethsw_cfg_create_if(t,tokens,count));
@@ -898,6 +1513,8 @@ static int ethsw_handle_cfg_line(ethsw_table_t *t,char *str)
       return(ethsw_cfg_set_dot1q_port(t,tokens,count));
    else if (!strcmp(tokens[0],"QINQ"))
       return(ethsw_cfg_set_qinq_port(t,tokens,count));
+   else if (!strcmp(tokens[0],"ISL"))
+      return(ethsw_cfg_set_isl_port(t,tokens,count));
 
    fprintf(stderr,
            "ETHSW: Unknown statement \"%s\" (allowed: IF,ACCESS,TRUNK)\n",
@@ -14,16 +14,13 @@
 
 #include "net_io.h"
 
-/* Hash entries for the MAC address table */
-#define ETHSW_HASH_SIZE  4096
-
 /* Maximum port number */
 #define ETHSW_MAX_NIO    64
 
 /* Maximum packet size */
 #define ETHSW_MAX_PKT_SIZE  2048
 
-/* Port types: access, 802.1Q, 802.1Q tunnel (QinQ) */
+/* Port types: access, 802.1Q, 802.1Q tunnel (QinQ), ISL */
 enum {
    ETHSW_PORT_TYPE_ACCESS = 1,
    ETHSW_PORT_TYPE_DOT1Q,
@@ -39,6 +36,8 @@ struct ethsw_packet {
    netio_desc_t *input_port;
    u_int input_vlan;
    int input_tag;
+   int input_state;   /* spanning tree state of the input port */
+   m_uint32_t span_mask;  /* SPAN sessions that got a copy */
 };
 
 /* MAC address table entry */
@@ -47,6 +46,8 @@ struct ethsw_mac_entry {
    netio_desc_t *nio;
    n_eth_addr_t mac_addr;
    m_uint16_t vlan_id;
+   int type;     /* MAC_ENTRY_* */
+   u_int age;    /* seconds since last seen */
 };
 
 /* Virtual Ethernet switch */
@@ -59,8 +60,15 @@ struct ethsw_table {
    /* Virtual Ports */
    netio_desc_t *nio[ETHSW_MAX_NIO];
 
-   /* MAC address table */
-   ethsw_mac_entry_t mac_addr_table[ETHSW_HASH_SIZE];
+   /* MAC address table (aging, static entries, port security) */
+   mac_table_t *mac_table;
+
+   /* Spanning tree (disabled by default) */
+   rstp_t *rstp;
+   timer_id stp_timer;
+
+   /* Port mirroring sessions */
+   span_t *span;
 };
 
 /* Packet input vector */
@@ -72,6 +80,21 @@ typedef void (*ethsw_foreach_entry_t)(ethsw_table_t *t,
                                       ethsw_mac_entry_t *entry,
                                       void *opt);
 
+/* "foreach" vector for port security */
+typedef void (*ethsw_foreach_port_t)(ethsw_table_t *t,netio_desc_t *nio,
+                                     mac_port_security_t *status,
+                                     void *opt);
+
+/* "foreach" vector for spanning tree ports */
+typedef void (*ethsw_foreach_stp_port_t)(ethsw_table_t *t,netio_desc_t *nio,
+                                         rstp_port_info_t *info,
+                                         void *opt);
+
+/* "foreach" vector for SPAN sessions */
+typedef void (*ethsw_foreach_span_t)(ethsw_table_t *t,
+                                     span_session_info_t *info,
+                                     void *opt);
+
 #define ETHSW_LOCK(t)   pthread_mutex_lock(&(t)->lock)
 #define ETHSW_UNLOCK(t) pthread_mutex_unlock(&(t)->lock)
 #define ETHSW_TRYLOCK(t) pthread_mutex_trylock(&(t)->lock)
@@ -91,13 +114,76 @@ int ethsw_add_netio(ethsw_table_t *t,char *nio_name);
 /* Remove a NetIO descriptor from a virtual ethernet switch */
 int ethsw_remove_netio(ethsw_table_t *t,char *nio_name);
 
-/* Clear the MAC address table */
+/* Clear the dynamic entries of the MAC address table */
 int ethsw_clear_mac_addr_table(ethsw_table_t *t);
 
 /* Iterate over all entries of the MAC address table */
 int ethsw_iterate_mac_addr_table(ethsw_table_t *t,ethsw_foreach_entry_t cb,
                                  void *opt_arg);
 
+/* Set the MAC address aging time (in seconds, 0 to disable aging) */
+int ethsw_set_mac_aging(ethsw_table_t *t,u_int aging);
+
+/* Add a static MAC address */
+int ethsw_add_static_mac(ethsw_table_t *t,char *nio_name,
+                         n_eth_addr_t *mac,u_int vlan_id);
+
+/* Add a sticky MAC address (restored from a saved configuration) */
+int ethsw_add_sticky_mac(ethsw_table_t *t,char *nio_name,
+                         n_eth_addr_t *mac,u_int vlan_id);
+
+/* Remove a MAC address (static, sticky or dynamic) */
+int ethsw_remove_mac(ethsw_table_t *t,n_eth_addr_t *mac,u_int vlan_id);
+
+/* Configure port security (max_macs = 0: no limit) */
+int ethsw_set_port_security(ethsw_table_t *t,char *nio_name,u_int max_macs,
+                            int action,int sticky);
+
+/* Recover a port err-disabled by port security */
+int ethsw_recover_port(ethsw_table_t *t,char *nio_name);
+
+/* Iterate over the port security status of all ports */
+int ethsw_iterate_port_security(ethsw_table_t *t,ethsw_foreach_port_t cb,
+                                void *opt_arg);
+
+/* Enable or disable the spanning tree */
+int ethsw_set_stp(ethsw_table_t *t,int enable);
+
+/* Set the spanning tree bridge priority and times (in seconds) */
+int ethsw_set_stp_bridge(ethsw_table_t *t,u_int priority,u_int hello_time,
+                         u_int max_age,u_int fwd_delay);
+
+/* Configure a spanning tree port (edge mode: RSTP_EDGE_*) */
+int ethsw_set_stp_port(ethsw_table_t *t,char *nio_name,u_int priority,
+                       u_int path_cost,int edge);
+
+/* Iterate over the spanning tree status of all ports */
+int ethsw_iterate_stp_ports(ethsw_table_t *t,ethsw_foreach_stp_port_t cb,
+                            void *opt_arg);
+
+/* Set the destination port of a SPAN session (rspan_vlan: 0 = none) */
+int ethsw_set_span_dest(ethsw_table_t *t,u_int id,char *nio_name,
+                        u_int rspan_vlan);
+
+/* Add a source port to a SPAN session (direction: SPAN_DIR_*) */
+int ethsw_add_span_port(ethsw_table_t *t,u_int id,char *nio_name,int dir);
+
+/* Remove a source port from a SPAN session */
+int ethsw_remove_span_port(ethsw_table_t *t,u_int id,char *nio_name);
+
+/* Add a source VLAN to a SPAN session (direction: SPAN_DIR_*) */
+int ethsw_add_span_vlan(ethsw_table_t *t,u_int id,u_int vlan_id,int dir);
+
+/* Remove a source VLAN from a SPAN session */
+int ethsw_remove_span_vlan(ethsw_table_t *t,u_int id,u_int vlan_id);
+
+/* Delete a SPAN session */
+int ethsw_delete_span(ethsw_table_t *t,u_int id);
+
+/* Iterate over the SPAN sessions */
+int ethsw_iterate_span(ethsw_table_t *t,ethsw_foreach_span_t cb,
+                       void *opt_arg);
+
 /* Set port as an access port */
 int ethsw_set_access_port(ethsw_table_t *t,char *nio_name,u_int vlan_id);
 
@@ -107,6 +193,9 @@ int ethsw_set_dot1q_port(ethsw_table_t *t,char *nio_name,u_int native_vlan);
 /* Set port as a Q-in-Q port */
 int ethsw_set_qinq_port(ethsw_table_t *t,char *nio_name,u_int outer_vlan,m_uint16_t ethertype);
 
+/* Set port as an ISL trunk port */
+int ethsw_set_isl_port(ethsw_table_t *t,char *nio_name);
+
 /* Save the configuration of a switch */
 void ethsw_save_config(ethsw_table_t *t,FILE *fd);
 
@@ -25,11 +25,6 @@
 
 extern FILE *log_file;
 
-/* ANSI LMI packet header */   
-static const m_uint8_t lmi_ansi_hdr[] = { 
-   0x00, 0x01, 0x03, 0x08, 0x00, 0x75, 0x95,
-};
-
 /* DLCI hash function */
 static inline u_int frsw_dlci_hash(u_int dlci)
 {
@@ -48,131 +43,40 @@ frsw_conn_t *frsw_dlci_lookup(frsw_table_t *t,netio_desc_t *input,u_int dlci)
    return NULL;
 }
 
-/* Handle a ANSI LMI packet */
-ssize_t frsw_handle_lmi_ansi_pkt(frsw_table_t *t,netio_desc_t *input,
-                                 m_uint8_t *pkt,ssize_t len)
+/* Handle a LMI packet (ANSI, Q.933 Annex A or Cisco) */
+ssize_t frsw_handle_lmi_pkt(frsw_table_t *t,netio_desc_t *input,
+                            m_uint8_t *pkt,ssize_t len)
 {
-   m_uint8_t resp[FR_MAX_PKT_SIZE],*pres,*preq;
-   m_uint8_t itype,isize;
-   int msg_type,seq_ok;
+   fr_lmi_pvc_t pvcs[FR_MAX_DLCI];
+   m_uint8_t resp[FR_MAX_PKT_SIZE];
+   size_t pvc_count = 0;
    ssize_t rlen;
    frsw_conn_t *sc;
-   u_int dlci;
-
-   if ((len <= sizeof(lmi_ansi_hdr)) || 
-       memcmp(pkt,lmi_ansi_hdr,sizeof(lmi_ansi_hdr)))
-      return(-1);
 
 #if DEBUG_FRSW
-   m_log(input->name,"received an ANSI LMI packet:\n");
+   m_log(input->name,"received a LMI packet:\n");
    mem_dump(log_file,pkt,len);
 #endif
 
-   /* Prepare response packet */
-   memcpy(resp,lmi_ansi_hdr,sizeof(lmi_ansi_hdr));
-   resp[FR_LMI_ANSI_STATUS_OFFSET] = FR_LMI_ANSI_STATUS;
-
-   preq = &pkt[sizeof(lmi_ansi_hdr)];
-   pres = &resp[sizeof(lmi_ansi_hdr)];
-   
-   msg_type = -1;
-   seq_ok = FALSE;
-
-   while((preq + 2) < (pkt + len)) {
-      /* get item type and size */
-      itype = preq[0];
-      isize = preq[1];
-
-      /* check packet boundary */
-      if ((preq + isize + 2) > (pkt + len)) {
-         m_log(input->name,"invalid LMI packet:\n");
-         mem_dump(log_file,pkt,len);
-         return(-1);
-      }
-
-      switch(itype) {
-         case 0x01:   /* report information element */
-            if (isize != 1) {
-               m_log(input->name,"invalid LMI item size.\n");
-               return(-1);
-            }
-
-            if ((msg_type = preq[2]) > 1) {
-               m_log(input->name,"unknown LMI report type 0x%x.\n",msg_type);
-               return(-1);
-            }
-
-            pres[0] = 0x01;
-            pres[1] = 0x01;
-            pres[2] = msg_type;
-            pres += 3;
-            break;
-
-         case 0x03:   /* sequences */
-            if (isize != 2) {
-               m_log(input->name,"invalid LMI item size.\n");
-               return(-1);
-            }
-
-            pres[0] = 0x03;
-            pres[1] = 0x02;
-
-            if (input->fr_lmi_seq != preq[3]) {
-               m_log(input->name,"resynchronization with LMI sequence...\n");
-               input->fr_lmi_seq = preq[3];
-            } 
-
-            input->fr_lmi_seq++;
-            if (!input->fr_lmi_seq) input->fr_lmi_seq++;
-            pres[2] = input->fr_lmi_seq;
-            pres[3] = preq[2];
-
-#if DEBUG_FRSW
-            m_log(input->name,"iSSN=0x%x, iRSN=0x%x, oSSN=0x%x, oRSN=0x%x\n",
-                  preq[2],preq[3],pres[2],pres[3]);
-#endif
-            pres += 4;
-            seq_ok = TRUE;
-            break;
-            
-         default:
-            m_log(input->name,"unknown LMI item type %u\n",itype);
-            goto done;
-      }
-
-      /* proceed next item */
-      preq += isize + 2;
+   /* PVC status is reported from the VC table */
+   for(sc=input->fr_conn_list;sc && (pvc_count < FR_MAX_DLCI);sc=sc->next) {
+      pvcs[pvc_count].dlci = sc->dlci_in;
+      pvcs[pvc_count].output = sc->output;
+      pvc_count++;
    }
 
- done:
-   if ((msg_type == -1) || !seq_ok) {
-       m_log(input->name,"incomplete LMI packet.\n");
-       return(-1);
-   }
+   rlen = fr_lmi_handle_pkt(t->lmi,input,pkt,len,pvcs,pvc_count,
+                            resp,sizeof(resp));
 
-   /* full status, send DLCI info */
-   if (msg_type == 0) {
-#if DEBUG_FRSW
-      m_log(input->name,"LMI full status, advertising DLCIs\n");
-#endif
-      for(sc=input->fr_conn_list;sc;sc=sc->next) {
-         dlci = sc->dlci_in;
+   if (rlen == -1) {
 #if DEBUG_FRSW
-         m_log(input->name,"sending LMI adv for DLCI %u\n",dlci);
+      m_log(input->name,"invalid or ignored LMI packet.\n");
 #endif
-         pres[0] = 0x07;
-         pres[1] = 0x03;
-         pres[2] = dlci >> 4;
-         pres[3] = 0x80 | ((dlci & 0x0f) << 3);
-         pres[4] = 0x82;
-         pres += 5;
-      }
+      return(-1);
    }
 
-   rlen = pres - resp;
-
 #if DEBUG_FRSW
-   m_log(input->name,"sending ANSI LMI packet:\n");
+   m_log(input->name,"sending LMI packet:\n");
    mem_dump(log_file,resp,rlen);
 #endif
 
@@ -209,8 +113,8 @@ ssize_t frsw_handle_pkt(frsw_table_t *t,netio_desc_t *input,
 #endif
 
    /* LMI ? */
-   if (dlci == FR_DLCI_LMI_ANSI)
-      return(frsw_handle_lmi_ansi_pkt(t,input,pkt,len));
+   if ((dlci == FR_DLCI_LMI_ANSI) || (dlci == FR_DLCI_LMI_CISCO))
+      return(frsw_handle_lmi_pkt(t,input,pkt,len));
 
    /* DLCI switching */
    if ((vc = frsw_dlci_lookup(t,input,dlci)) != NULL) {
@@ -277,6 +181,9 @@ frsw_table_t *frsw_create_table(char *name)
    if (!(t->name = mp_strdup(&t->mp,name)))
       goto err_name;
 
+   if (!(t->lmi = fr_lmi_create()))
+      goto err_lmi;
+
    /* Record this object in registry */
    if (registry_add(t->name,OBJ_TYPE_FRSW,t) == -1) {
       fprintf(stderr,"frsw_create_table: unable to create switch '%s'\n",name);
@@ -286,6 +193,8 @@ frsw_table_t *frsw_create_table(char *name)
    return t;
 
  err_reg:
+   fr_lmi_free(t->lmi);
+ err_lmi:
  err_name:
    mp_free_pool(&t->mp);
    free(t);
@@ -331,6 +240,7 @@ static int frsw_free(void *data,void *arg)
       for(vc=t->dlci_table[i];vc;vc=vc->hash_next)
          frsw_release_vc(vc);
 
+   fr_lmi_free(t->lmi);
    mp_free_pool(&t->mp);
    free(t);
    return(TRUE);
@@ -434,6 +344,11 @@ int frsw_delete_vc(frsw_table_t *t,char *nio_input,u_int dlci_in,
          /* Found a matching VC, remove it */
          *vc = (*vc)->hash_next;
          frsw_unlink_vc(p);
+
+         /* The LMI state goes with the last VC of the port */
+         if (!input->fr_conn_list)
+            fr_lmi_remove_port(t->lmi,input);
+
          FRSW_UNLOCK(t);
 
          /* Release NIOs */
@@ -447,6 +362,14 @@ int frsw_delete_vc(frsw_table_t *t,char *nio_input,u_int dlci_in,
    return(-1);
 }
 
+/* Call a function for each port which polled the LMI */
+void frsw_foreach_lmi_port(frsw_table_t *t,fr_lmi_port_cbk_t cb,void *opt)
+{
+   FRSW_LOCK(t);
+   fr_lmi_foreach_port(t->lmi,cb,opt);
+   FRSW_UNLOCK(t);
+}
+
 /* Save the configuration of a Frame-Relay switch */
 void frsw_save_config(frsw_table_t *t,FILE *fd)
 {
@@ -570,10 +493,15 @@ int frsw_cfg_create_vc(frsw_table_t *t,char **tokens,int count)
 int frsw_handle_cfg_line(frsw_table_t *t,char *str)
 {  
    char *tokens[FRSW_MAX_TOKENS];
+   m_tok_error_t err;
    int count;
 
-   if ((count = m_strsplit(str,':',tokens,FRSW_MAX_TOKENS)) <= 1)
+   if ((count = m_strsplit_tok(str,':',0,tokens,FRSW_MAX_TOKENS,&err)) <= 1) {
+      if (err.code != M_TOK_ERR_NONE)
+         fprintf(stderr,"FRSW: %s at column %d in \"%s\"\n",
+                 m_tok_strerror(err.code),err.col,str);
       return(-1);
+   }
 
    if (!strcmp(tokens[0],"IF"))
       return(frsw_cfg_create_if(t,tokens,count));
@@ -18,9 +18,8 @@
 #define FR_DLCI_LMI_ANSI   0       /* ANSI LMI */
 #define FR_DLCI_LMI_CISCO  1023    /* Cisco LMI */
 
-#define FR_LMI_ANSI_STATUS_OFFSET   5
-#define FR_LMI_ANSI_STATUS_ENQUIRY  0x75   /* sent by user */
-#define FR_LMI_ANSI_STATUS          0x7d   /* sent by network */
+/* Maximum number of DLCIs on a port */
+#define FR_MAX_DLCI  1024
 
 /* Maximum packet size */
 #define FR_MAX_PKT_SIZE  2048
@@ -43,6 +42,7 @@ struct frsw_table {
    pthread_mutex_t lock;
    mempool_t mp;
    m_uint64_t drop;
+   fr_lmi_t *lmi;
    frsw_conn_t *dlci_table[FRSW_HASH_SIZE];
 };
 
@@ -72,6 +72,9 @@ int frsw_create_vc(frsw_table_t *t,char *nio_input,u_int dlci_in,
 int frsw_delete_vc(frsw_table_t *t,char *nio_input,u_int dlci_in,
                    char *nio_output,u_int dlci_out);
 
+/* Call a function for each port which polled the LMI */
+void frsw_foreach_lmi_port(frsw_table_t *t,fr_lmi_port_cbk_t cb,void *opt);
+
 /* Save the configuration of a Frame-Relay switch */
 void frsw_save_config(frsw_table_t *t,FILE *fd);
 
@@ -207,6 +207,90 @@ static int cmd_delete_vcc(hypervisor_conn_t *conn,int argc,char *argv[])
    return(0);
 }
 
+/*
+ * Send an OAM segment loopback request (F4 without VCI, F5 otherwise)
+ *
+ * Parameters: <atmsw_name> <nio> <vpi> [<vci>]
+ */
+static int cmd_oam_loopback(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   atmsw_table_t *t;
+   int vci = ATM_OAM_VP;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ATMSW)))
+      return(-1);
+
+   if (argc == 4)
+      vci = atoi(argv[3]);
+
+   if (atmsw_oam_loopback(t,argv[1],atoi(argv[2]),vci) == -1) {
+      atmsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "unable to send loopback on NIO '%s'",argv[1]);
+      return(-1);
+   }
+
+   atmsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OAM loopback sent");
+   return(0);
+}
+
+/* Show a connection with AIS sent */
+static void cmd_show_oam_ais(void *port,u_int vpi,int vci,void *opt)
+{
+   hypervisor_conn_t *conn = opt;
+   netio_desc_t *nio = port;
+
+   if (vci == ATM_OAM_VP)
+      hypervisor_send_reply(conn,HSC_INFO_MSG,0,"AIS %s %u",nio->name,vpi);
+   else
+      hypervisor_send_reply(conn,HSC_INFO_MSG,0,"AIS %s %u/%d",
+                            nio->name,vpi,vci);
+}
+
+/*
+ * Show the OAM counters and the deleted connections with AIS sent
+ *
+ * Parameters: <atmsw_name>
+ */
+static int cmd_show_oam(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   atm_oam_stats_t stats;
+   atmsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ATMSW)))
+      return(-1);
+
+   atm_oam_get_stats(t->oam,&stats);
+
+   hypervisor_send_reply(conn,HSC_INFO_MSG,0,
+                         "cells=%llu crc_errors=%llu dropped=%llu",
+                         (unsigned long long)stats.cells,
+                         (unsigned long long)stats.crc_errors,
+                         (unsigned long long)stats.dropped);
+   hypervisor_send_reply(conn,HSC_INFO_MSG,0,
+                         "loopback answered=%llu sent=%llu replies=%llu "
+                         "timeouts=%llu last_rtt=%llums",
+                         (unsigned long long)stats.lb_answered,
+                         (unsigned long long)stats.lb_sent,
+                         (unsigned long long)stats.lb_replies,
+                         (unsigned long long)stats.lb_timeouts,
+                         (unsigned long long)stats.lb_last_rtt);
+   hypervisor_send_reply(conn,HSC_INFO_MSG,0,
+                         "ais sent=%llu received=%llu "
+                         "rdi sent=%llu received=%llu",
+                         (unsigned long long)stats.ais_sent,
+                         (unsigned long long)stats.ais_received,
+                         (unsigned long long)stats.rdi_sent,
+                         (unsigned long long)stats.rdi_received);
+
+   atm_oam_foreach_ais(t->oam,cmd_show_oam_ais,conn);
+
+   atmsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
 /* Show info about a ATM switch object */
 static void cmd_show_list(registry_entry_t *entry,void *opt,int *err)
 {
@@ -232,6 +316,8 @@ static hypervisor_cmd_t atmsw_cmd_array[] = {
    { "delete_vpc", 5, 5, cmd_delete_vpc, NULL },
    { "create_vcc", 7, 7, cmd_create_vcc, NULL },
    { "delete_vcc", 7, 7, cmd_delete_vcc, NULL },
+   { "oam_loopback", 3, 4, cmd_oam_loopback, NULL },
+   { "show_oam", 1, 1, cmd_show_oam, NULL },
    { "list", 0, 0, cmd_list, NULL },
    { NULL, -1, -1, NULL, NULL },
 };
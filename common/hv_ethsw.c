@@ -231,6 +231,30 @@ static int cmd_set_qinq_port(hypervisor_conn_t *conn,int argc,char *argv[])
    return(0);
 }
 
+/*
+ * Set a port as a trunk (ISL) port.
+ *
+ * Parameters: <ethsw_name> <nio>
+ */
+static int cmd_set_isl_port(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   if (ethsw_set_isl_port(t,argv[1]) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "unable to apply port settings");
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"Port settings OK");
+   return(0);
+}
+
 /* Clear the MAC address table */
 static int cmd_clear_mac_addr_table(hypervisor_conn_t *conn,
                                    int argc,char *argv[])
@@ -251,7 +275,7 @@ static void cmd_show_mac_addr_entry(ethsw_table_t *t,ethsw_mac_entry_t *entry,
                                     hypervisor_conn_t *conn)
 {
    hypervisor_send_reply(conn,HSC_INFO_MSG,0,
-                         "%2.2x%2.2x.%2.2x%2.2x.%2.2x%2.2x  %u  %s",
+                         "%2.2x%2.2x.%2.2x%2.2x.%2.2x%2.2x  %u  %s  %s  %u",
                          entry->mac_addr.eth_addr_byte[0],
                          entry->mac_addr.eth_addr_byte[1],
                          entry->mac_addr.eth_addr_byte[2],
@@ -259,7 +283,9 @@ static void cmd_show_mac_addr_entry(ethsw_table_t *t,ethsw_mac_entry_t *entry,
                          entry->mac_addr.eth_addr_byte[4],
                          entry->mac_addr.eth_addr_byte[5],
                          entry->vlan_id,
-                         entry->nio->name);
+                         entry->nio->name,
+                         mac_table_entry_type_str(entry->type),
+                         entry->age);
 }
 
 static int cmd_show_mac_addr_table(hypervisor_conn_t *conn,
@@ -279,6 +305,606 @@ static int cmd_show_mac_addr_table(hypervisor_conn_t *conn,
    return(0);
 }
 
+/*
+ * Set the MAC address aging time.
+ *
+ * Parameters: <ethsw_name> <seconds> (0 disables aging)
+ */
+static int cmd_set_mac_aging(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   ethsw_set_mac_aging(t,atoi(argv[1]));
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/*
+ * Add a static MAC address.
+ *
+ * Parameters: <ethsw_name> <nio> <mac_addr> <VLAN>
+ */
+static int cmd_add_static_mac(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   ethsw_table_t *t;
+   n_eth_addr_t mac;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   if (parse_mac_addr(&mac,argv[2]) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "invalid MAC address '%s'",argv[2]);
+      return(-1);
+   }
+
+   if (ethsw_add_static_mac(t,argv[1],&mac,atoi(argv[3])) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "unable to add static MAC address");
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/*
+ * Add a sticky MAC address (restored from a saved configuration).
+ *
+ * Parameters: <ethsw_name> <nio> <mac_addr> <VLAN>
+ */
+static int cmd_add_sticky_mac(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   ethsw_table_t *t;
+   n_eth_addr_t mac;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   if (parse_mac_addr(&mac,argv[2]) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "invalid MAC address '%s'",argv[2]);
+      return(-1);
+   }
+
+   if (ethsw_add_sticky_mac(t,argv[1],&mac,atoi(argv[3])) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "unable to add sticky MAC address");
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/*
+ * Remove a MAC address.
+ *
+ * Parameters: <ethsw_name> <mac_addr> <VLAN>
+ */
+static int cmd_remove_mac(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   ethsw_table_t *t;
+   n_eth_addr_t mac;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   if (parse_mac_addr(&mac,argv[1]) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "invalid MAC address '%s'",argv[1]);
+      return(-1);
+   }
+
+   if (ethsw_remove_mac(t,&mac,atoi(argv[2])) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_NOT_FOUND,1,
+                            "MAC address '%s' not found",argv[1]);
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/*
+ * Configure port security.
+ *
+ * Parameters: <ethsw_name> <nio> <max_macs> [<protect|restrict|shutdown>
+ *             [sticky]]
+ */
+static int cmd_set_port_security(hypervisor_conn_t *conn,
+                                 int argc,char *argv[])
+{
+   int action = MAC_VIOLATION_SHUTDOWN;
+   int sticky = FALSE;
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   if ((argc >= 4) && ((action = mac_violation_parse(argv[3])) == -1)) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "invalid violation action '%s'",argv[3]);
+      return(-1);
+   }
+
+   if (argc == 5) {
+      if (strcmp(argv[4],"sticky")) {
+         ethsw_release(argv[0]);
+         hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                               "invalid parameter '%s'",argv[4]);
+         return(-1);
+      }
+      sticky = TRUE;
+   }
+
+   if (ethsw_set_port_security(t,argv[1],atoi(argv[2]),action,sticky) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "unable to apply port settings");
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"Port settings OK");
+   return(0);
+}
+
+/*
+ * Recover a port err-disabled by port security.
+ *
+ * Parameters: <ethsw_name> <nio>
+ */
+static int cmd_recover_port(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   if (ethsw_recover_port(t,argv[1]) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "port '%s' is not err-disabled",argv[1]);
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"Port '%s' recovered",argv[1]);
+   return(0);
+}
+
+/* Show the port security status */
+static void cmd_show_port_security_entry(ethsw_table_t *t,netio_desc_t *nio,
+                                         mac_port_security_t *status,
+                                         hypervisor_conn_t *conn)
+{
+   hypervisor_send_reply(conn,HSC_INFO_MSG,0,
+                         "%s  max=%u  count=%u  action=%s  sticky=%s  "
+                         "violations=%llu  state=%s",
+                         nio->name,status->max_macs,status->count,
+                         mac_violation_str(status->action),
+                         status->sticky ? "yes" : "no",
+                         (unsigned long long)status->violations,
+                         status->shutdown ? "err-disabled" : "up");
+}
+
+static int cmd_show_port_security(hypervisor_conn_t *conn,
+                                  int argc,char *argv[])
+{
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   ethsw_iterate_port_security(t,
+                               (ethsw_foreach_port_t)cmd_show_port_security_entry,
+                               conn);
+
+   hypervisor_send_reply(conn,HSC_INFO_MSG,0,"aging=%u  moves=%llu",
+                         mac_table_get_aging(t->mac_table),
+                         (unsigned long long)mac_table_get_moves(t->mac_table));
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/*
+ * Enable or disable the spanning tree.
+ *
+ * Parameters: <ethsw_name> <0|1>
+ */
+static int cmd_set_stp(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   ethsw_set_stp(t,atoi(argv[1]));
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/*
+ * Set the spanning tree bridge parameters.
+ *
+ * Parameters: <ethsw_name> <priority> [<hello_time> <max_age> <fwd_delay>]
+ */
+static int cmd_set_stp_bridge(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   rstp_bridge_info_t info;
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   rstp_get_bridge_info(t->rstp,&info);
+
+   if (argc == 5) {
+      info.hello_time = atoi(argv[2]);
+      info.max_age    = atoi(argv[3]);
+      info.fwd_delay  = atoi(argv[4]);
+   } else if (argc != 2) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "times must be hello_time, max_age "
+                            "and fwd_delay");
+      return(-1);
+   }
+
+   if (ethsw_set_stp_bridge(t,atoi(argv[1]),info.hello_time,
+                            info.max_age,info.fwd_delay) == -1)
+   {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "invalid spanning tree bridge parameters");
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/*
+ * Configure a spanning tree port.
+ *
+ * Parameters: <ethsw_name> <nio> <priority> <path_cost> [yes|no|auto]
+ */
+static int cmd_set_stp_port(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   int edge = RSTP_EDGE_AUTO;
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   if ((argc == 5) && ((edge = rstp_edge_parse(argv[4])) == -1)) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "invalid edge mode '%s'",argv[4]);
+      return(-1);
+   }
+
+   if (ethsw_set_stp_port(t,argv[1],atoi(argv[2]),atoi(argv[3]),edge) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "unable to apply port settings");
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"Port settings OK");
+   return(0);
+}
+
+/* Format a bridge ID (Cisco style) */
+static char *cmd_stp_id_str(char *buffer,m_uint64_t id)
+{
+   sprintf(buffer,"%4.4x.%4.4x.%4.4x.%4.4x",
+           (u_int)(id >> 48) & 0xFFFF,(u_int)(id >> 32) & 0xFFFF,
+           (u_int)(id >> 16) & 0xFFFF,(u_int)id & 0xFFFF);
+   return buffer;
+}
+
+/* Show the spanning tree status of a port */
+static void cmd_show_stp_port(ethsw_table_t *t,netio_desc_t *nio,
+                              rstp_port_info_t *info,
+                              hypervisor_conn_t *conn)
+{
+   char bridge[32];
+
+   hypervisor_send_reply(conn,HSC_INFO_MSG,0,
+                         "%s  port=%u  role=%s  state=%s  priority=%u  "
+                         "cost=%u  edge=%s%s  proto=%s  "
+                         "designated=%s/%4.4x  rx=%llu  tx=%llu",
+                         nio->name,info->port_no,
+                         rstp_role_str(info->role),
+                         rstp_state_str(info->state),
+                         info->priority,info->path_cost,
+                         rstp_edge_str(info->edge),
+                         info->oper_edge ? "(oper)" : "",
+                         info->rstp ? "rstp" : "stp",
+                         cmd_stp_id_str(bridge,info->designated_bridge),
+                         info->designated_port,
+                         (unsigned long long)info->rx_bpdus,
+                         (unsigned long long)info->tx_bpdus);
+}
+
+/*
+ * Show the spanning tree status.
+ *
+ * Parameters: <ethsw_name>
+ */
+static int cmd_show_stp(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   char bridge[32],root[32],*root_port = "none";
+   rstp_bridge_info_t info;
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   ETHSW_LOCK(t);
+   rstp_get_bridge_info(t->rstp,&info);
+   if (info.root_port && t->nio[info.root_port-1])
+      root_port = t->nio[info.root_port-1]->name;
+
+   hypervisor_send_reply(conn,HSC_INFO_MSG,0,
+                         "stp=%s  bridge=%s  root=%s  root_cost=%u  "
+                         "root_port=%s  hello=%u  max_age=%u  "
+                         "fwd_delay=%u  tc=%llu  last_tc=%llus",
+                         info.enabled ? "enabled" : "disabled",
+                         cmd_stp_id_str(bridge,info.bridge_id),
+                         cmd_stp_id_str(root,info.root_id),
+                         info.root_path_cost,root_port,
+                         info.hello_time,info.max_age,info.fwd_delay,
+                         (unsigned long long)info.tc_count,
+                         (unsigned long long)info.tc_last);
+   ETHSW_UNLOCK(t);
+
+   ethsw_iterate_stp_ports(t,(ethsw_foreach_stp_port_t)cmd_show_stp_port,
+                           conn);
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/*
+ * Set the destination port of a SPAN session, with an optional RSPAN VLAN.
+ *
+ * Parameters: <ethsw_name> <session_id> <nio> [<rspan_vlan>]
+ */
+static int cmd_set_span_dest(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   u_int rspan_vlan = 0;
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   if (argc == 4)
+      rspan_vlan = atoi(argv[3]);
+
+   if (ethsw_set_span_dest(t,atoi(argv[1]),argv[2],rspan_vlan) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "unable to set SPAN destination");
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Parse the optional direction of a SPAN source */
+static int cmd_span_dir(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   int dir = SPAN_DIR_BOTH;
+
+   if ((argc == 4) && ((dir = span_dir_parse(argv[3])) == -1))
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "invalid direction '%s'",argv[3]);
+   return(dir);
+}
+
+/*
+ * Add a source port to a SPAN session.
+ *
+ * Parameters: <ethsw_name> <session_id> <nio> [rx|tx|both]
+ */
+static int cmd_add_span_port(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   ethsw_table_t *t;
+   int dir;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   if ((dir = cmd_span_dir(conn,argc,argv)) == -1) {
+      ethsw_release(argv[0]);
+      return(-1);
+   }
+
+   if (ethsw_add_span_port(t,atoi(argv[1]),argv[2],dir) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "unable to add SPAN source port");
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/*
+ * Remove a source port from a SPAN session.
+ *
+ * Parameters: <ethsw_name> <session_id> <nio>
+ */
+static int cmd_remove_span_port(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   if (ethsw_remove_span_port(t,atoi(argv[1]),argv[2]) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "unable to remove SPAN source port");
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/*
+ * Add a source VLAN to a SPAN session.
+ *
+ * Parameters: <ethsw_name> <session_id> <vlan_id> [rx|tx|both]
+ */
+static int cmd_add_span_vlan(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   ethsw_table_t *t;
+   int dir;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   if ((dir = cmd_span_dir(conn,argc,argv)) == -1) {
+      ethsw_release(argv[0]);
+      return(-1);
+   }
+
+   if (ethsw_add_span_vlan(t,atoi(argv[1]),atoi(argv[2]),dir) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "unable to add SPAN source VLAN");
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/*
+ * Remove a source VLAN from a SPAN session.
+ *
+ * Parameters: <ethsw_name> <session_id> <vlan_id>
+ */
+static int cmd_remove_span_vlan(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   if (ethsw_remove_span_vlan(t,atoi(argv[1]),atoi(argv[2])) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "unable to remove SPAN source VLAN");
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/*
+ * Delete a SPAN session.
+ *
+ * Parameters: <ethsw_name> <session_id>
+ */
+static int cmd_delete_span(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   if (ethsw_delete_span(t,atoi(argv[1])) == -1) {
+      ethsw_release(argv[0]);
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "unknown SPAN session %s",argv[1]);
+      return(-1);
+   }
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Show a SPAN source */
+static void cmd_show_span_source(netio_desc_t *nio,u_int vlan_id,int dir,
+                                 hypervisor_conn_t *conn)
+{
+   if (nio != NULL)
+      hypervisor_send_reply(conn,HSC_INFO_MSG,0,"  port %s %s",
+                            nio->name,span_dir_str(dir));
+   else
+      hypervisor_send_reply(conn,HSC_INFO_MSG,0,"  vlan %u %s",
+                            vlan_id,span_dir_str(dir));
+}
+
+/* Show a SPAN session */
+static void cmd_show_span_session(ethsw_table_t *t,span_session_info_t *info,
+                                  hypervisor_conn_t *conn)
+{
+   netio_desc_t *dst = info->dst;
+
+   hypervisor_send_reply(conn,HSC_INFO_MSG,0,
+                         "session %u  dest=%s  rspan_vlan=%u  "
+                         "pkts=%llu  bytes=%llu",
+                         info->id,dst ? dst->name : "none",info->rspan_vlan,
+                         (unsigned long long)info->pkts,
+                         (unsigned long long)info->bytes);
+
+   span_foreach_source(t->span,info->id,
+                       (span_source_cbk_t)cmd_show_span_source,conn);
+}
+
+/*
+ * Show the SPAN sessions.
+ *
+ * Parameters: <ethsw_name>
+ */
+static int cmd_show_span(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   ethsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_ETHSW)))
+      return(-1);
+
+   ethsw_iterate_span(t,(ethsw_foreach_span_t)cmd_show_span_session,conn);
+
+   ethsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
 
 /* Show info about a ETHSW object */
 static void cmd_show_list(registry_entry_t *entry,void *opt,int *err)
@@ -306,8 +932,27 @@ static hypervisor_cmd_t ethsw_cmd_array[] = {
    { "set_access_port", 3, 3, cmd_set_access_port, NULL },
    { "set_dot1q_port", 3, 3, cmd_set_dot1q_port, NULL },
    { "set_qinq_port", 3, 4, cmd_set_qinq_port, NULL },
+   { "set_isl_port", 2, 2, cmd_set_isl_port, NULL },
    { "clear_mac_addr_table", 1, 1, cmd_clear_mac_addr_table, NULL },
    { "show_mac_addr_table", 1, 1, cmd_show_mac_addr_table, NULL },
+   { "set_mac_aging", 2, 2, cmd_set_mac_aging, NULL },
+   { "add_static_mac", 4, 4, cmd_add_static_mac, NULL },
+   { "add_sticky_mac", 4, 4, cmd_add_sticky_mac, NULL },
+   { "remove_mac", 3, 3, cmd_remove_mac, NULL },
+   { "set_port_security", 3, 5, cmd_set_port_security, NULL },
+   { "recover_port", 2, 2, cmd_recover_port, NULL },
+   { "show_port_security", 1, 1, cmd_show_port_security, NULL },
+   { "set_stp", 2, 2, cmd_set_stp, NULL },
+   { "set_stp_bridge", 2, 5, cmd_set_stp_bridge, NULL },
+   { "set_stp_port", 4, 5, cmd_set_stp_port, NULL },
+   { "show_stp", 1, 1, cmd_show_stp, NULL },
+   { "set_span_dest", 3, 4, cmd_set_span_dest, NULL },
+   { "add_span_port", 3, 4, cmd_add_span_port, NULL },
+   { "remove_span_port", 3, 3, cmd_remove_span_port, NULL },
+   { "add_span_vlan", 3, 4, cmd_add_span_vlan, NULL },
+   { "remove_span_vlan", 3, 3, cmd_remove_span_vlan, NULL },
+   { "delete_span", 2, 2, cmd_delete_span, NULL },
+   { "show_span", 1, 1, cmd_show_span, NULL },
    { "list", 0, 0, cmd_list, NULL },
    { NULL, -1, -1, NULL, NULL },
 };
@@ -153,6 +153,42 @@ static int cmd_delete_vc(hypervisor_conn_t *conn,int argc,char *argv[])
    return(0);
 }
 
+/* Show the LMI state of a port */
+static void cmd_show_lmi_port(void *port,fr_lmi_port_info_t *info,void *opt)
+{
+   hypervisor_conn_t *conn = opt;
+   netio_desc_t *nio = port;
+
+   hypervisor_send_reply(conn,HSC_INFO_MSG,0,
+                         "%s %s %s seq=%u enquiries=%llu full_status=%llu "
+                         "errors=%llu ignored=%llu",
+                         nio->name,fr_lmi_type_str(info->type),
+                         fr_lmi_state_str(info->state),info->seq,
+                         (unsigned long long)info->enquiries,
+                         (unsigned long long)info->full_status,
+                         (unsigned long long)info->errors,
+                         (unsigned long long)info->ignored);
+}
+
+/*
+ * Show the LMI state of the ports
+ *
+ * Parameters: <frsw_name>
+ */
+static int cmd_show_lmi(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   frsw_table_t *t;
+
+   if (!(t = hypervisor_find_object(conn,argv[0],OBJ_TYPE_FRSW)))
+      return(-1);
+
+   frsw_foreach_lmi_port(t,cmd_show_lmi_port,conn);
+
+   frsw_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
 /* Show info about a FRSW object */
 static void cmd_show_list(registry_entry_t *entry,void *opt,int *err)
 {
@@ -176,6 +212,7 @@ static hypervisor_cmd_t frsw_cmd_array[] = {
    { "delete", 1, 1, cmd_delete, NULL },
    { "create_vc", 5, 5, cmd_create_vc, NULL },
    { "delete_vc", 5, 5, cmd_delete_vc, NULL },
+   { "show_lmi", 1, 1, cmd_show_lmi, NULL },
    { "list", 0, 0, cmd_list, NULL },
    { NULL, -1, -1, NULL, NULL },
 };
@@ -0,0 +1,254 @@
+/*
+ * Cisco router simulation platform.
+ *
+ * Hypervisor metrics exporter (Prometheus text format over HTTP).
+ */
+
+#include "dynamips_c.h"
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <unistd.h>
+#include <string.h>
+#include <time.h>
+#include <sys/types.h>
+#include <assert.h>
+#include <pthread.h>
+
+#include "cpu.h"
+#include "vm.h"
+#include "dynamips.h"
+#include "eth_switch.h"
+#include "net_io.h"
+#include "get_cpu_time.h"
+#include "registry.h"
+#include "hypervisor.h"
+
+/* Exporter started with "metrics start" */
+static metrics_server_t *hv_metrics_server = NULL;
+static char *hv_metrics_addr = NULL;
+static pthread_mutex_t hv_metrics_mutex = PTHREAD_MUTEX_INITIALIZER;
+
+#define HV_METRICS_LOCK()   pthread_mutex_lock(&hv_metrics_mutex)
+#define HV_METRICS_UNLOCK() pthread_mutex_unlock(&hv_metrics_mutex)
+
+/* Add the counters of a NIO */
+static void hv_metrics_nio(registry_entry_t *entry,void *opt,int *err)
+{
+   metrics_t *m = opt;
+   netio_desc_t *nio = entry->data;
+   char *type = netio_get_type_name(nio->type);
+   char *lbl_in[] = { "nio", entry->name, "type", type,
+                      "direction", "in", NULL };
+   char *lbl_out[] = { "nio", entry->name, "type", type,
+                       "direction", "out", NULL };
+
+   metrics_add(m,METRICS_COUNTER,"dynamips_nio_packets_total",
+               "Packets received and sent by a NIO.",
+               lbl_in,(double)nio->stats_pkts_in);
+   metrics_add(m,METRICS_COUNTER,"dynamips_nio_packets_total",
+               "Packets received and sent by a NIO.",
+               lbl_out,(double)nio->stats_pkts_out);
+   metrics_add(m,METRICS_COUNTER,"dynamips_nio_bytes_total",
+               "Bytes received and sent by a NIO.",
+               lbl_in,(double)nio->stats_bytes_in);
+   metrics_add(m,METRICS_COUNTER,"dynamips_nio_bytes_total",
+               "Bytes received and sent by a NIO.",
+               lbl_out,(double)nio->stats_bytes_out);
+}
+
+/* Add the MAC address table size of an Ethernet switch */
+static void hv_metrics_ethsw(registry_entry_t *entry,void *opt,int *err)
+{
+   metrics_t *m = opt;
+   ethsw_table_t *t = entry->data;
+   char *lbl[] = { "ethsw", entry->name, NULL };
+
+   metrics_add(m,METRICS_GAUGE,"dynamips_ethsw_mac_entries",
+               "Number of entries in the MAC address table of a switch.",
+               lbl,(double)mac_table_count(t->mac_table));
+}
+
+/* Get the CPU time used by the thread of a virtual CPU */
+static int hv_metrics_cpu_time(cpu_gen_t *cpu,double *secs)
+{
+#if defined(_POSIX_THREAD_CPUTIME) && (_POSIX_THREAD_CPUTIME >= 0)
+   struct timespec ts;
+   clockid_t cid;
+
+   if (!cpu->cpu_thread_running ||
+       pthread_getcpuclockid(cpu->cpu_thread,&cid) ||
+       clock_gettime(cid,&ts))
+      return(-1);
+
+   *secs = (double)ts.tv_sec + (double)ts.tv_nsec / 1e9;
+   return(0);
+#else
+   return(-1);
+#endif
+}
+
+/* Add the status and the CPU counters of a VM */
+static void hv_metrics_vm(registry_entry_t *entry,void *opt,int *err)
+{
+   metrics_t *m = opt;
+   vm_instance_t *vm = entry->data;
+   char *lbl[] = { "vm", entry->name, "platform", vm_get_type(vm), NULL };
+   char *lbl_cpu[] = { "vm", entry->name, "cpu", NULL, NULL };
+   char cpu_id[16];
+   cpu_gen_t *cpu;
+   double secs;
+
+   metrics_add(m,METRICS_GAUGE,"dynamips_vm_status",
+               "Status of a VM (0: halted, 1: shutdown, 2: running, "
+               "3: suspended).",lbl,(double)vm->status);
+
+   /* The CPUs are freed when the VM is stopped (under the VM lock) */
+   VM_LOCK(vm);
+
+   if (((vm->status != VM_STATUS_RUNNING) &&
+        (vm->status != VM_STATUS_SUSPENDED)) || !vm->cpu_group)
+   {
+      VM_UNLOCK(vm);
+      return;
+   }
+
+   for(cpu=vm->cpu_group->cpu_list;cpu;cpu=cpu->next) {
+      snprintf(cpu_id,sizeof(cpu_id),"%u",cpu->id);
+      lbl_cpu[3] = cpu_id;
+
+      if (hv_metrics_cpu_time(cpu,&secs) != -1) {
+         metrics_add(m,METRICS_COUNTER,"dynamips_vm_cpu_seconds_total",
+                     "CPU time used by the thread of a virtual CPU.",
+                     lbl_cpu,secs);
+      }
+
+      metrics_add(m,METRICS_COUNTER,"dynamips_vm_cpu_dev_access_total",
+                  "Device accesses done by a virtual CPU.",
+                  lbl_cpu,(double)cpu->dev_access_counter);
+   }
+
+   VM_UNLOCK(vm);
+}
+
+/* Collect the metrics of the hypervisor objects (exporter thread) */
+static void hv_metrics_collect(metrics_t *m,void *opt)
+{
+   double usage;
+   int err = 0;
+
+   if ((usage = get_cpu_time()) != -1) {
+      metrics_add(m,METRICS_COUNTER,"process_cpu_seconds_total",
+                  "Total user and system CPU time spent in seconds.",
+                  NULL,usage);
+   }
+
+   registry_foreach_type(OBJ_TYPE_NIO,hv_metrics_nio,m,&err);
+   registry_foreach_type(OBJ_TYPE_ETHSW,hv_metrics_ethsw,m,&err);
+   registry_foreach_type(OBJ_TYPE_VM,hv_metrics_vm,m,&err);
+}
+
+/* Start the exporter */
+static int cmd_start(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   char *ip_addr = (argc == 2) ? argv[1] : NULL;
+   metrics_server_t *s;
+
+   HV_METRICS_LOCK();
+
+   if (hv_metrics_server != NULL) {
+      HV_METRICS_UNLOCK();
+      hypervisor_send_reply(conn,HSC_ERR_START,1,
+                            "metrics exporter already started");
+      return(-1);
+   }
+
+   if (!(s = metrics_server_start(ip_addr,atoi(argv[0]),
+                                  hv_metrics_collect,NULL)))
+   {
+      HV_METRICS_UNLOCK();
+      hypervisor_send_reply(conn,HSC_ERR_BINDING,1,
+                            "unable to start metrics exporter on port %s",
+                            argv[0]);
+      return(-1);
+   }
+
+   hv_metrics_server = s;
+   hv_metrics_addr = ip_addr ? strdup(ip_addr) : NULL;
+   HV_METRICS_UNLOCK();
+
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Stop the exporter */
+static int cmd_stop(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   HV_METRICS_LOCK();
+
+   if (hv_metrics_server == NULL) {
+      HV_METRICS_UNLOCK();
+      hypervisor_send_reply(conn,HSC_ERR_STOP,1,
+                            "metrics exporter not started");
+      return(-1);
+   }
+
+   metrics_server_stop(hv_metrics_server);
+   hv_metrics_server = NULL;
+   free(hv_metrics_addr);
+   hv_metrics_addr = NULL;
+   HV_METRICS_UNLOCK();
+
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Show the exporter status */
+static int cmd_show(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   HV_METRICS_LOCK();
+
+   if (hv_metrics_server != NULL) {
+      hypervisor_send_reply(conn,HSC_INFO_MSG,0,"%s port %d, %llu scrapes",
+                            hv_metrics_addr ? hv_metrics_addr : "*",
+                            metrics_server_get_port(hv_metrics_server),
+                            metrics_server_get_scrapes(hv_metrics_server));
+   } else {
+      hypervisor_send_reply(conn,HSC_INFO_MSG,0,"stopped");
+   }
+
+   HV_METRICS_UNLOCK();
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Metrics commands */
+static hypervisor_cmd_t metrics_cmd_array[] = {
+   { "start", 1, 2, cmd_start, NULL },
+   { "stop", 0, 0, cmd_stop, NULL },
+   { "show", 0, 0, cmd_show, NULL },
+   { NULL, -1, -1, NULL, NULL },
+};
+
+/* Stop the metrics exporter (hypervisor shutdown) */
+void hypervisor_metrics_shutdown(void)
+{
+   HV_METRICS_LOCK();
+   metrics_server_stop(hv_metrics_server);
+   hv_metrics_server = NULL;
+   free(hv_metrics_addr);
+   hv_metrics_addr = NULL;
+   HV_METRICS_UNLOCK();
+}
+
+/* Hypervisor metrics initialization */
+int hypervisor_metrics_init(void)
+{
+   hypervisor_module_t *module;
+
+   module = hypervisor_register_module("metrics",NULL);
+   assert(module != NULL);
+
+   hypervisor_register_cmd_array(module,metrics_cmd_array);
+   return(0);
+}
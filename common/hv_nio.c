@@ -35,6 +35,27 @@
 #include "registry.h"
 #include "hypervisor.h"
 
+/* 
+ * Send the error reply of a socket NIO, with the error of the socket layer.
+ * A port in use or a port range without free port is a binding error.
+ */
+static void hv_nio_socket_error(hypervisor_conn_t *conn,char *msg)
+{
+   switch(net_last_error()) {
+      case NET_ERR_NONE:
+         hypervisor_send_reply(conn,HSC_ERR_CREATE,1,"%s",msg);
+         break;
+      case NET_ERR_ADDR_IN_USE:
+      case NET_ERR_PORT_RANGE:
+         hypervisor_send_reply(conn,HSC_ERR_BINDING,1,"%s: %s",
+                               msg,net_last_error_str());
+         break;
+      default:
+         hypervisor_send_reply(conn,HSC_ERR_CREATE,1,"%s: %s",
+                               msg,net_last_error_str());
+   }
+}
+
 /* 
  * Create a UDP NIO
  *
@@ -44,11 +65,34 @@ static int cmd_create_udp(hypervisor_conn_t *conn,int argc,char *argv[])
 {   
    netio_desc_t *nio;
 
+   net_clear_error();
    nio = netio_desc_create_udp(argv[0],atoi(argv[1]),argv[2],atoi(argv[3]));
 
    if (!nio) {
-      hypervisor_send_reply(conn,HSC_ERR_CREATE,1,
-                            "unable to create UDP NIO");
+      hv_nio_socket_error(conn,"unable to create UDP NIO");
+      return(-1);
+   }
+
+   netio_release(argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"NIO '%s' created",argv[0]);
+   return(0);
+}
+
+/* 
+ * Create a UDP NIO bound to a local address
+ *
+ * Parameters: <nio_name> <local_addr> <local_port> <remote_host> <remote_port>
+ */
+static int cmd_create_udp_bind(hypervisor_conn_t *conn,int argc,char *argv[])
+{   
+   netio_desc_t *nio;
+
+   net_clear_error();
+   nio = netio_desc_create_udp_bind(argv[0],argv[1],atoi(argv[2]),
+                                    argv[3],atoi(argv[4]));
+
+   if (!nio) {
+      hv_nio_socket_error(conn,"unable to create UDP NIO");
       return(-1);
    }
 
@@ -67,11 +111,11 @@ static int cmd_create_udp_auto(hypervisor_conn_t *conn,int argc,char *argv[])
    netio_desc_t *nio;
    int local_port;
    
+   net_clear_error();
    nio = netio_desc_create_udp_auto(argv[0],argv[1],atoi(argv[2]),atoi(argv[3]));
    
    if (!nio) {
-      hypervisor_send_reply(conn,HSC_ERR_CREATE,1,
-                            "unable to create UDP Auto NIO");
+      hv_nio_socket_error(conn,"unable to create UDP Auto NIO");
       return(-1);
    }
    
@@ -95,6 +139,7 @@ static int cmd_connect_udp_auto(hypervisor_conn_t *conn,int argc,char *argv[])
    if (!(nio = hypervisor_find_object(conn,argv[0],OBJ_TYPE_NIO)))
       return(-1);
    
+   net_clear_error();
    res = netio_udp_auto_connect(nio,argv[1],atoi(argv[2]));
    netio_release(argv[0]);
    
@@ -102,7 +147,7 @@ static int cmd_connect_udp_auto(hypervisor_conn_t *conn,int argc,char *argv[])
       hypervisor_send_reply(conn,HSC_INFO_OK,1,"NIO '%s' connected",argv[0]);
       return(0);
    } else {
-      hypervisor_send_reply(conn,HSC_ERR_CREATE,1,"unable to connect NIO");
+      hv_nio_socket_error(conn,"unable to connect NIO");
       return(-1);
    }
 }
@@ -499,6 +544,7 @@ static int cmd_nio_list(hypervisor_conn_t *conn,int argc,char *argv[])
 /* NIO commands */
 static hypervisor_cmd_t nio_cmd_array[] = {
    { "create_udp", 4, 4, cmd_create_udp, NULL },
+   { "create_udp_bind", 5, 5, cmd_create_udp_bind, NULL },
    { "create_udp_auto", 4, 4, cmd_create_udp_auto, NULL },
    { "connect_udp_auto", 3, 3, cmd_connect_udp_auto, NULL },
    { "create_unix", 3, 3, cmd_create_unix, NULL },
@@ -86,16 +86,25 @@ static int so_delete_from_registry(char *name)
 static int cmd_write(hypervisor_conn_t *conn,int argc,char *argv[])
 {   
    struct store_object *so;
+   size_t inlen = strlen(argv[1]);
+   base64_dec_t dec;
+   m_uint64_t pos;
    u_char *buffer;
-   ssize_t len;
+   ssize_t len,res;
+   int err;
 
    /* Convert base64 input to standard text */
-   if (!(buffer = malloc(3 * strlen(argv[1]))))
+   base64_dec_init(&dec,0);
+
+   if (!(buffer = malloc(base64_dec_update_len(&dec,inlen) + 2)))
       goto err_alloc_base64;
 
-   if ((len = base64_decode(buffer,(u_char *)argv[1],0)) < 0)
+   if (((len = base64_dec_update(&dec,buffer,(u_char *)argv[1],inlen)) < 0) ||
+       ((res = base64_dec_finish(&dec,buffer+len)) < 0))
       goto err_decode_base64;
 
+   len += res;
+
    if (!(so = so_create(argv[0],buffer,len))) {
       free(buffer);
       hypervisor_send_reply(conn,HSC_ERR_CREATE,1,"unable to store object");
@@ -108,6 +117,12 @@ static int cmd_write(hypervisor_conn_t *conn,int argc,char *argv[])
 
  err_decode_base64:
    free(buffer);
+   err = base64_dec_error(&dec,&pos);
+   hypervisor_send_reply(conn,HSC_ERR_CREATE,1,
+                         "unable to decode base64 (%s at offset %llu)",
+                         base64_strerror(err),pos);
+   return(-1);
+
  err_alloc_base64:
    hypervisor_send_reply(conn,HSC_ERR_CREATE,1,"unable to decode base64");
    return(-1);
@@ -122,11 +137,8 @@ static int cmd_read(hypervisor_conn_t *conn,int argc,char *argv[])
    if (!(so = hypervisor_find_object(conn,argv[0],OBJ_TYPE_STORE)))
       return(-1);
 
-   /* 
-    * Convert data to base64. base64 is about 1/3 larger than input,
-    * let's be on the safe side with twice longer.
-    */
-   if (!(buffer = malloc(so->len * 2)))
+   /* Convert data to base64 */
+   if (!(buffer = malloc(base64_encoded_len(so->len,0,0) + 1)))
       goto err_alloc_base64;
 
    base64_encode(buffer,so->data,so->len);
@@ -101,6 +101,12 @@ extern int hypervisor_vm_debug_init(void);
 /* Hypervisor store initialization */
 extern int hypervisor_store_init(void);
 
+/* Hypervisor metrics initialization */
+extern int hypervisor_metrics_init(void);
+
+/* Stop the metrics exporter (hypervisor shutdown) */
+extern void hypervisor_metrics_shutdown(void);
+
 /* Send a reply */
 int hypervisor_send_reply(hypervisor_conn_t *conn,int code,int done,
                           char *format,...);
@@ -50,6 +50,7 @@ static struct netio_rx_listener *netio_rxl_add_list = NULL;
 static netio_desc_t *netio_rxl_remove_list = NULL;
 static pthread_t netio_rxl_thread;
 static pthread_cond_t netio_rxl_cond;
+static ev_loop_t *netio_rxl_ev_loop = NULL;
 
 #define NETIO_RXL_LOCK()   pthread_mutex_lock(&netio_rxl_mutex);
 #define NETIO_RXL_UNLOCK() pthread_mutex_unlock(&netio_rxl_mutex);
@@ -94,6 +95,15 @@ int netio_get_type(char *type)
    return(-1);
 }
 
+/* Get the name of a NETIO type */
+char *netio_get_type_name(u_int type)
+{
+   if (type >= NETIO_TYPE_MAX)
+      return("unknown");
+
+   return(netio_types[type].name);
+}
+
 /* Show the NETIO types */
 void netio_show_types(void)
 {
@@ -186,7 +196,7 @@ void netio_save_config_all(FILE *fd)
 /* Send a packet through a NetIO descriptor */
 ssize_t netio_send(netio_desc_t *nio,void *pkt,size_t len)
 {
-   int res;
+   int res,dup = FALSE;
 
    if (!nio)
       return(-1);
@@ -200,18 +210,37 @@ ssize_t netio_send(netio_desc_t *nio,void *pkt,size_t len)
    if (nio->tx_filter != NULL) {
       res = nio->tx_filter->pkt_handler(nio,pkt,len,nio->tx_filter_data);
 
+      if (res == NETIO_FILTER_ACTION_QUEUED)
+         return(len);
+
       if (res <= 0)
          return(-1);
+
+      dup |= (res == NETIO_FILTER_ACTION_DUPLICATE);
    }
 
    /* Apply the bidirectional filter */
    if (nio->both_filter != NULL) {
       res = nio->both_filter->pkt_handler(nio,pkt,len,nio->both_filter_data);
 
+      if (res == NETIO_FILTER_ACTION_QUEUED)
+         return(len);
+
       if (res == NETIO_FILTER_ACTION_DROP)
          return(-1);
+
+      dup |= (res == NETIO_FILTER_ACTION_DUPLICATE);
    }
 
+   if (dup && (netio_send_nofilter(nio,pkt,len) == -1))
+      return(-1);
+
+   return(netio_send_nofilter(nio,pkt,len));
+}
+
+/* Send a packet without applying the filters (packets delayed by a filter) */
+ssize_t netio_send_nofilter(netio_desc_t *nio,void *pkt,size_t len)
+{
    /* Update output statistics */
    nio->stats_pkts_out++;
    nio->stats_bytes_out += len;
@@ -221,12 +250,19 @@ ssize_t netio_send(netio_desc_t *nio,void *pkt,size_t len)
    return(nio->send(nio->dptr,pkt,len));
 }
 
-/* Receive a packet through a NetIO descriptor */
-ssize_t netio_recv(netio_desc_t *nio,void *pkt,size_t max_len)
+/*
+ * Receive a packet through a NetIO descriptor, the filter verdict is
+ * stored in action (NETIO_FILTER_ACTION_DUPLICATE if the packet must be
+ * handled twice).
+ */
+static ssize_t netio_recv_filtered(netio_desc_t *nio,void *pkt,size_t max_len,
+                                   int *action)
 {
    ssize_t len;
    int res;
 
+   *action = NETIO_FILTER_ACTION_PASS;
+
    if (!nio)
       return(-1);
 
@@ -244,16 +280,25 @@ ssize_t netio_recv(netio_desc_t *nio,void *pkt,size_t max_len)
    if (nio->rx_filter != NULL) {
       res = nio->rx_filter->pkt_handler(nio,pkt,len,nio->rx_filter_data);
 
-      if (res == NETIO_FILTER_ACTION_DROP)
+      /* A queued packet is injected later in the RX listener */
+      if ((res == NETIO_FILTER_ACTION_DROP) ||
+          (res == NETIO_FILTER_ACTION_QUEUED))
          return(-1);
+
+      if (res == NETIO_FILTER_ACTION_DUPLICATE)
+         *action = res;
    }
 
    /* Apply the bidirectional filter */
    if (nio->both_filter != NULL) {
       res = nio->both_filter->pkt_handler(nio,pkt,len,nio->both_filter_data);
 
-      if (res == NETIO_FILTER_ACTION_DROP)
+      if ((res == NETIO_FILTER_ACTION_DROP) ||
+          (res == NETIO_FILTER_ACTION_QUEUED))
          return(-1);
+
+      if (res == NETIO_FILTER_ACTION_DUPLICATE)
+         *action = res;
    }
 
    /* Update input statistics */
@@ -262,6 +307,14 @@ ssize_t netio_recv(netio_desc_t *nio,void *pkt,size_t max_len)
    return(len);
 }
 
+/* Receive a packet through a NetIO descriptor */
+ssize_t netio_recv(netio_desc_t *nio,void *pkt,size_t max_len)
+{
+   int action;
+
+   return(netio_recv_filtered(nio,pkt,max_len,&action));
+}
+
 /* Get a NetIO FD */
 int netio_get_fd(netio_desc_t *nio)
 {
@@ -903,6 +956,11 @@ netio_desc_t *netio_desc_create_tcp_ser(char *nio_name,char *port)
 /* Free a NetIO UDP descriptor */
 static void netio_udp_free(netio_inet_desc_t *nid)
 {
+   if (nid->local_addr) {
+      free(nid->local_addr);
+      nid->local_addr = NULL;
+   }
+
    if (nid->remote_host) {
       free(nid->remote_host);
       nid->remote_host = NULL;
@@ -928,6 +986,14 @@ static ssize_t netio_udp_recv(netio_inet_desc_t *nid,void *pkt,size_t max_len)
 static void netio_udp_save_cfg(netio_desc_t *nio,FILE *fd)
 {
    netio_inet_desc_t *nid = nio->dptr;
+
+   if (nid->local_addr != NULL) {
+      fprintf(fd,"nio create_udp_bind %s %s %d %s %d\n",nio->name,
+              nid->local_addr,nid->local_port,
+              nid->remote_host,nid->remote_port);
+      return;
+   }
+
    fprintf(fd,"nio create_udp %s %d %s %d\n",
            nio->name,nid->local_port,nid->remote_host,nid->remote_port);
 }
@@ -935,6 +1001,18 @@ static void netio_udp_save_cfg(netio_desc_t *nio,FILE *fd)
 /* Create a new NetIO descriptor with UDP method */
 netio_desc_t *netio_desc_create_udp(char *nio_name,int local_port,
                                     char *remote_host,int remote_port)
+{
+   return(netio_desc_create_udp_bind(nio_name,NULL,local_port,
+                                     remote_host,remote_port));
+}
+
+/* 
+ * Create a new NetIO descriptor with UDP method, bound to a local address
+ * (NULL for the wildcard address).
+ */
+netio_desc_t *netio_desc_create_udp_bind(char *nio_name,char *local_addr,
+                                         int local_port,
+                                         char *remote_host,int remote_port)
 {
    netio_inet_desc_t *nid;
    netio_desc_t *nio;
@@ -946,14 +1024,18 @@ netio_desc_t *netio_desc_create_udp(char *nio_name,int local_port,
    nid->local_port  = local_port;
    nid->remote_port = remote_port;
 
-   if (!(nid->remote_host = strdup(remote_host))) {
+   if (!(nid->remote_host = strdup(remote_host)) ||
+       (local_addr && !(nid->local_addr = strdup(local_addr)))) 
+   {
       fprintf(stderr,"netio_desc_create_udp: insufficient memory\n");
       goto error;
    }
 
-   if ((nid->fd = udp_connect(local_port,remote_host,remote_port)) < 0) {
-      fprintf(stderr,"netio_desc_create_udp: unable to connect to %s:%d\n",
-              remote_host,remote_port);
+   if ((nid->fd = udp_connect_bind(local_addr,local_port,
+                                   remote_host,remote_port)) < 0) 
+   {
+      fprintf(stderr,"netio_desc_create_udp: unable to connect to %s:%d "
+              "(%s)\n",remote_host,remote_port,net_last_error_str());
       goto error;
    }
 
@@ -1006,6 +1088,8 @@ int netio_udp_auto_connect(netio_desc_t *nio,char *host,int port)
    nid->remote_port = port;
    
    if (ip_connect_fd(nid->fd,nid->remote_host,nid->remote_port) < 0) {
+      fprintf(stderr,"netio_udp_auto_connect: unable to connect to %s:%d "
+              "(%s)\n",host,port,net_last_error_str());
       free(nid->remote_host);
       nid->remote_host = NULL;
       return(-1);
@@ -1034,8 +1118,8 @@ netio_desc_t *netio_desc_create_udp_auto(char *nio_name,char *local_addr,
    {
       fprintf(stderr,
               "netio_desc_create_udp_auto: unable to create socket "
-              "(addr=%s,port_start=%d,port_end=%d)\n",
-              local_addr,port_start,port_end);
+              "(addr=%s,port_start=%d,port_end=%d): %s\n",
+              local_addr,port_start,port_end,net_last_error_str());
       goto error;
    }
    
@@ -1552,6 +1636,8 @@ static int netio_rxl_remove_internal(netio_desc_t *nio)
          if (netio_get_fd(rxl->nio) == -1) {
             rxl->running = FALSE;
             pthread_join(rxl->spec_thread,NULL);
+         } else {
+            ev_loop_del(netio_rxl_ev_loop,netio_get_fd(rxl->nio));
          }
          
          free(rxl);
@@ -1563,10 +1649,13 @@ static int netio_rxl_remove_internal(netio_desc_t *nio)
    return(res);
 }
 
+static void netio_rxl_ev_input(int fd,int events,void *opt);
+
 /* Add a RXL listener to the listener list */
 static void netio_rxl_add_internal(struct netio_rx_listener *rxl)
 {  
    struct netio_rx_listener *tmp;
+   int fd;
    
    if ((tmp = netio_rxl_find(rxl->nio))) {
       tmp->ref_count++;
@@ -1576,35 +1665,55 @@ static void netio_rxl_add_internal(struct netio_rx_listener *rxl)
       rxl->next = netio_rxl_list;
       if (rxl->next) rxl->next->prev = rxl;
       netio_rxl_list = rxl;
+
+      /* FD NIO are handled by the general thread */
+      if ((fd = netio_get_fd(rxl->nio)) != -1)
+         ev_loop_add(netio_rxl_ev_loop,fd,EV_READ,netio_rxl_ev_input,rxl);
    }
 }
 
-/* RX Listener dedicated thread (for non-FD NIO) */
-static void *netio_rxl_spec_thread(void *arg)
+/* Receive a packet and pass it to the RX handler (twice if duplicated) */
+static void netio_rxl_process(struct netio_rx_listener *rxl)
 {
-   struct netio_rx_listener *rxl = arg;
    netio_desc_t *nio = rxl->nio;
    ssize_t pkt_len;
+   int action;
+
+   pkt_len = netio_recv_filtered(nio,nio->rx_pkt,sizeof(nio->rx_pkt),&action);
 
-   while(rxl->running) {
-      pkt_len = netio_recv(nio,nio->rx_pkt,sizeof(nio->rx_pkt));
+   if (pkt_len > 0) {
+      rxl->rx_handler(nio,nio->rx_pkt,pkt_len,rxl->arg1,rxl->arg2);
 
-      if (pkt_len > 0)
+      if (action == NETIO_FILTER_ACTION_DUPLICATE) {
+         nio->stats_pkts_in++;
+         nio->stats_bytes_in += pkt_len;
          rxl->rx_handler(nio,nio->rx_pkt,pkt_len,rxl->arg1,rxl->arg2);
+      }
    }
+}
+
+/* RX Listener dedicated thread (for non-FD NIO) */
+static void *netio_rxl_spec_thread(void *arg)
+{
+   struct netio_rx_listener *rxl = arg;
+
+   while(rxl->running)
+      netio_rxl_process(rxl);
 
    return NULL;
 }
 
+/* Event loop callback of the FD NIO (RXL list locked) */
+static void netio_rxl_ev_input(int fd,int events,void *opt)
+{
+   netio_rxl_process(opt);
+}
+
 /* RX Listener General Thread */
 void *netio_rxl_gen_thread(void *arg)
 { 
    struct netio_rx_listener *rxl;
-   ssize_t pkt_len;
    netio_desc_t *nio;
-   struct timeval tv;
-   int fd,fd_max,res;
-   fd_set rfds;
 
    for(;;) {
       NETIO_RXL_LOCK();
@@ -1626,47 +1735,17 @@ void *netio_rxl_gen_thread(void *arg)
 
       pthread_cond_broadcast(&netio_rxl_cond);
       NETIO_RXQ_UNLOCK();
-
-      /* Build the FD set */
-      FD_ZERO(&rfds);
-      fd_max = -1;
-      for(rxl=netio_rxl_list;rxl;rxl=rxl->next) {
-         if ((fd = netio_get_fd(rxl->nio)) == -1)
-            continue;
-
-         if (fd > fd_max) fd_max = fd;
-         FD_SET(fd,&rfds);
-      }
       NETIO_RXL_UNLOCK();
 
       /* Wait for incoming packets */
-      tv.tv_sec = 0;
-      tv.tv_usec = 20 * 1000;  /* 200 ms */
-      res = select(fd_max+1,&rfds,NULL,NULL,&tv);
-
-      if (res == -1) {
-         if (errno != EINTR)
-            perror("netio_rxl_thread: select");
+      if (ev_loop_wait(netio_rxl_ev_loop,20) == -1) {
+         usleep(20000);
          continue;
       }
 
-      /* Examine active FDs and call user handlers */
+      /* Call user handlers for the active FDs */
       NETIO_RXL_LOCK();
-
-      for(rxl=netio_rxl_list;rxl;rxl=rxl->next) {
-         nio = rxl->nio;
-
-         if ((fd = netio_get_fd(nio)) == -1)
-            continue;
-
-         if (FD_ISSET(fd,&rfds)) {
-            pkt_len = netio_recv(nio,nio->rx_pkt,sizeof(nio->rx_pkt));
-
-            if (pkt_len > 0)
-               rxl->rx_handler(nio,nio->rx_pkt,pkt_len,rxl->arg1,rxl->arg2);
-         }
-      }
-
+      ev_loop_dispatch(netio_rxl_ev_loop);
       NETIO_RXL_UNLOCK();
    }
    
@@ -1713,6 +1792,28 @@ int netio_rxl_add(netio_desc_t *nio,netio_rx_handler_t rx_handler,
    return(0);
 }
 
+/*
+ * Pass a packet to the RX handler of a NIO, without applying the filters
+ * (packets delayed by a filter).
+ */
+int netio_rxl_inject(netio_desc_t *nio,u_char *pkt,ssize_t pkt_len)
+{
+   struct netio_rx_listener *rxl;
+   int res = -1;
+
+   NETIO_RXL_LOCK();
+
+   if ((rxl = netio_rxl_find(nio)) != NULL) {
+      nio->stats_pkts_in++;
+      nio->stats_bytes_in += pkt_len;
+      rxl->rx_handler(nio,pkt,pkt_len,rxl->arg1,rxl->arg2);
+      res = 0;
+   }
+
+   NETIO_RXL_UNLOCK();
+   return(res);
+}
+
 /* Remove a NIO from the listener list */
 int netio_rxl_remove(netio_desc_t *nio)
 {
@@ -1731,6 +1832,11 @@ int netio_rxl_init(void)
 {
    pthread_cond_init(&netio_rxl_cond,NULL);
 
+   if (!(netio_rxl_ev_loop = ev_loop_create())) {
+      fprintf(stderr,"netio_rxl_init: unable to create the event loop\n");
+      return(-1);
+   }
+
    if (pthread_create(&netio_rxl_thread,NULL,netio_rxl_gen_thread,NULL)) {
       perror("netio_rxl_init: pthread_create");
       return(-1);
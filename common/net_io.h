@@ -52,6 +52,7 @@ enum {
    NETIO_FILTER_ACTION_PASS,
    NETIO_FILTER_ACTION_ALTER,
    NETIO_FILTER_ACTION_DUPLICATE,
+   NETIO_FILTER_ACTION_QUEUED,      /* kept by the filter, delivered later */
 };
 
 typedef struct netio_desc netio_desc_t;
@@ -96,7 +97,7 @@ struct netio_tap_desc {
 typedef struct netio_inet_desc netio_inet_desc_t;
 struct netio_inet_desc {
    int local_port,remote_port;
-   char *remote_host;
+   char *local_addr,*remote_host;
    int fd;
 };
 
@@ -163,7 +164,6 @@ struct netio_desc {
    int debug;
 
    /* Frame Relay specific information */
-   m_uint8_t fr_lmi_seq;
    void *fr_conn_list;
 
    /* Ethernet specific information */
@@ -236,6 +236,9 @@ struct netio_rx_listener {
 /* Get NETIO type given a description */
 int netio_get_type(char *type);
 
+/* Get the name of a NETIO type */
+char *netio_get_type_name(u_int type);
+
 /* Show the NETIO types */
 void netio_show_types(void);
 
@@ -258,6 +261,11 @@ netio_desc_t *netio_desc_create_tcp_ser(char *nio_name,char *port);
 netio_desc_t *netio_desc_create_udp(char *nio_name,int local_port,
                                     char *remote_host,int remote_port);
 
+/* Create a new NetIO descriptor with UDP method, bound to a local address */
+netio_desc_t *netio_desc_create_udp_bind(char *nio_name,char *local_addr,
+                                         int local_port,
+                                         char *remote_host,int remote_port);
+
 /* Get local port */
 int netio_udp_auto_get_local_port(netio_desc_t *nio);
 
@@ -308,6 +316,9 @@ void netio_save_config_all(FILE *fd);
 /* Send a packet through a NetIO descriptor */
 ssize_t netio_send(netio_desc_t *nio,void *pkt,size_t len);
 
+/* Send a packet without applying the filters (packets delayed by a filter) */
+ssize_t netio_send_nofilter(netio_desc_t *nio,void *pkt,size_t len);
+
 /* Receive a packet through a NetIO descriptor */
 ssize_t netio_recv(netio_desc_t *nio,void *pkt,size_t max_len);
 
@@ -336,6 +347,9 @@ int netio_rxl_enable(netio_desc_t *nio);
 int netio_rxl_add(netio_desc_t *nio,netio_rx_handler_t rx_handler,
                   void *arg1,void *arg2);
 
+/* Pass a packet to the RX handler of a NIO, without applying the filters */
+int netio_rxl_inject(netio_desc_t *nio,u_char *pkt,ssize_t pkt_len);
+
 /* Remove a NIO from the listener list */
 int netio_rxl_remove(netio_desc_t *nio);
 
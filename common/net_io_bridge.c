@@ -322,10 +322,15 @@ static int netio_bridge_cfg_create_if(netio_bridge_t *t,
 static int netio_bridge_handle_cfg_line(netio_bridge_t *t,char *str)
 {  
    char *tokens[NETIO_BRIDGE_MAX_TOKENS];
+   m_tok_error_t err;
    int count;
 
-   if ((count = m_strsplit(str,':',tokens,NETIO_BRIDGE_MAX_TOKENS)) <= 2)
+   if ((count = m_strsplit_tok(str,':',0,tokens,NETIO_BRIDGE_MAX_TOKENS,&err)) <= 2) {
+      if (err.code != M_TOK_ERR_NONE)
+         fprintf(stderr,"NETIO_BRIDGE: %s at column %d in \"%s\"\n",
+                 m_tok_strerror(err.code),err.col,str);
       return(-1);
+   }
 
    return(netio_bridge_cfg_create_if(t,tokens,count));
 }
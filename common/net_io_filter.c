@@ -140,6 +140,7 @@ int netio_filter_setup(netio_desc_t *nio,int direction,int argc,char *argv[])
 struct netio_filter_capture {
    pcap_t *desc;
    pcap_dumper_t *dumper;
+   pkt_filter_t *match;
    pthread_mutex_t lock;
 };
 
@@ -159,6 +160,7 @@ static void pf_capture_free(netio_desc_t *nio,void **opt)
       if (c->desc)
          pcap_close(c->desc);
 
+      pkt_filter_free(c->match);
       pthread_mutex_destroy(&c->lock);
 
       free(c);
@@ -173,8 +175,8 @@ static int pf_capture_setup(netio_desc_t *nio,void **opt,
    struct netio_filter_capture *c;
    int link_type;
    
-   /* We must have a link type and a filename */
-   if (argc != 2)
+   /* We must have a link type and a filename, then an optional expression */
+   if (argc < 2)
       return(-1);
 
    /* Free resources if something has already been done */
@@ -184,6 +186,14 @@ static int pf_capture_setup(netio_desc_t *nio,void **opt,
    if (!(c = malloc(sizeof(*c))))
       return(-1);
 
+   c->match = NULL;
+
+   if ((argc > 2) && !(c->match = pkt_filter_compile(argc-2,&argv[2]))) {
+      fprintf(stderr,"NIO %s: invalid capture expression\n",nio->name);
+      free(c);
+      return(-1);
+   }
+
    if (pthread_mutex_init(&c->lock,NULL)) {
       fprintf(stderr,"NIO %s: pthread_mutex_init failure (file %s)\n",
               nio->name,argv[0]); 
@@ -218,6 +228,7 @@ static int pf_capture_setup(netio_desc_t *nio,void **opt,
  pcap_open_err:
    pthread_mutex_destroy(&c->lock);
  pcap_lock_err:
+   pkt_filter_free(c->match);
    free(c);
    return(-1);
 }
@@ -229,7 +240,7 @@ static int pf_capture_pkt_handler(netio_desc_t *nio,void *pkt,size_t len,
    struct netio_filter_capture *c = opt;
    struct pcap_pkthdr pkt_hdr;
 
-   if (c != NULL) {
+   if ((c != NULL) && (!c->match || pkt_filter_match_pkt(c->match,pkt,len))) {
       gettimeofday(&pkt_hdr.ts,0);
       pkt_hdr.caplen = m_min(len, (u_int)pcap_snapshot(c->desc));
       pkt_hdr.len = len;
@@ -328,6 +339,173 @@ static netio_pktfilter_t pf_freqdrop_def = {
    NULL,
 };
 
+/* ======================================================================== */
+/* Expression matching ("match").                                           */
+/* ======================================================================== */
+
+struct pf_match_data {
+   int drop;
+   pkt_filter_t *expr;
+};
+
+/* Free ressources used by filter */
+static void pf_match_free(netio_desc_t *nio,void **opt)
+{
+   struct pf_match_data *data = *opt;
+
+   if (data != NULL) {
+      pkt_filter_free(data->expr);
+      free(data);
+   }
+
+   *opt = NULL;
+}
+
+/* Setup filter ressources: "drop" or "pass", then the expression */
+static int pf_match_setup(netio_desc_t *nio,void **opt,
+                          int argc,char *argv[])
+{
+   struct pf_match_data *data;
+   pkt_filter_t *expr;
+   int drop;
+
+   if (argc < 2)
+      return(-1);
+
+   if (!strcmp(argv[0],"drop"))
+      drop = TRUE;
+   else if (!strcmp(argv[0],"pass"))
+      drop = FALSE;
+   else {
+      fprintf(stderr,"NIO %s: match: unknown action '%s'\n",
+              nio->name,argv[0]);
+      return(-1);
+   }
+
+   if (!(expr = pkt_filter_compile(argc-1,&argv[1])))
+      return(-1);
+
+   if (!(data = malloc(sizeof(*data)))) {
+      pkt_filter_free(expr);
+      return(-1);
+   }
+
+   pf_match_free(nio,opt);
+   data->drop = drop;
+   data->expr = expr;
+   *opt = data;
+   return(0);
+}
+
+/* Packet handler: drop the matching packets, or all the others */
+static int pf_match_pkt_handler(netio_desc_t *nio,void *pkt,size_t len,
+                                void *opt)
+{
+   struct pf_match_data *data = opt;
+
+   if (data != NULL) {
+      if (pkt_filter_match_pkt(data->expr,pkt,len) == data->drop)
+         return(NETIO_FILTER_ACTION_DROP);
+   }
+
+   return(NETIO_FILTER_ACTION_PASS);
+}
+
+/* Packet dropping on a filter expression */
+static netio_pktfilter_t pf_match_def = {
+   "match",
+   pf_match_setup,
+   pf_match_free,
+   pf_match_pkt_handler,
+   NULL,
+};
+
+/* ======================================================================== */
+/* Link impairments ("netem").                                              */
+/* ======================================================================== */
+
+/* Deliver a delayed packet received on a NIO */
+static void pf_netem_deliver_rx(u_char *pkt,size_t len,void *opt)
+{
+   netio_desc_t *nio = opt;
+
+   netio_rxl_inject(nio,pkt,len);
+}
+
+/* Deliver a delayed packet sent on a NIO */
+static void pf_netem_deliver_tx(u_char *pkt,size_t len,void *opt)
+{
+   netio_desc_t *nio = opt;
+
+   netio_send_nofilter(nio,pkt,len);
+}
+
+/* Setup filter ressources */
+static int pf_netem_setup(netio_desc_t *nio,void **opt,
+                          int argc,char *argv[])
+{
+   netem_deliver_cbk_t deliver;
+
+   /* The delayed packets are delivered in the direction of the filter */
+   if (opt == &nio->rx_filter_data) {
+      deliver = pf_netem_deliver_rx;
+   } else if (opt == &nio->tx_filter_data) {
+      deliver = pf_netem_deliver_tx;
+   } else {
+      fprintf(stderr,"NIO %s: netem must be bound to the RX or TX "
+              "direction.\n",nio->name);
+      return(-1);
+   }
+
+   if (!*opt && !(*opt = netem_create(deliver,nio)))
+      return(-1);
+
+   return(netem_setup(*opt,argc,argv));
+}
+
+/* Free ressources used by filter */
+static void pf_netem_free(netio_desc_t *nio,void **opt)
+{
+   netem_stats_t stats;
+
+   if (*opt != NULL) {
+      netem_get_stats(*opt,&stats);
+      printf("NIO %s: netem: %llu packets, %llu lost, %llu over limit, "
+             "%llu duplicated, %llu corrupted, %llu reordered, "
+             "%llu delayed.\n",nio->name,
+             (unsigned long long)stats.pkts,
+             (unsigned long long)stats.lost,
+             (unsigned long long)stats.overlimit,
+             (unsigned long long)stats.duplicated,
+             (unsigned long long)stats.corrupted,
+             (unsigned long long)stats.reordered,
+             (unsigned long long)stats.delayed);
+      netem_free(*opt);
+   }
+
+   *opt = NULL;
+}
+
+/* Packet handler: apply the impairments */
+static int pf_netem_pkt_handler(netio_desc_t *nio,void *pkt,size_t len,
+                                void *opt)
+{
+   if (opt == NULL)
+      return(NETIO_FILTER_ACTION_PASS);
+
+   /* NETEM_ACTION_* values are the NETIO_FILTER_ACTION_* ones */
+   return(netem_pkt_handler(opt,pkt,len));
+}
+
+/* Latency, jitter, loss, duplication, corruption and reordering */
+static netio_pktfilter_t pf_netem_def = {
+   "netem",
+   pf_netem_setup,
+   pf_netem_free,
+   pf_netem_pkt_handler,
+   NULL,
+};
+
 /* ======================================================================== */
 /* Initialization of packet filters.                                        */
 /* ======================================================================== */
@@ -335,6 +513,8 @@ static netio_pktfilter_t pf_freqdrop_def = {
 void netio_filter_load_all(void)
 {
    netio_filter_add(&pf_freqdrop_def);
+   netio_filter_add(&pf_netem_def);
+   netio_filter_add(&pf_match_def);
 #ifdef GEN_ETH
    netio_filter_add(&pf_capture_def);
 #endif
@@ -0,0 +1,148 @@
+//! Speed and distribution of the hash functions on realistic keys.
+//!
+//! Run with `cargo bench -p dynamips-c --bench hash`.
+//! For each key set and hash function, prints the time per key and the
+//! distribution in a table of 4096 buckets: chi-square ratio (1.0 is a
+//! uniform distribution, higher is worse) and longest chain.
+
+use dynamips_c::crc::*;
+use dynamips_c::hash::*;
+use dynamips_c::sbox::*;
+use std::ffi::c_void;
+use std::hint::black_box;
+use std::time::Instant;
+
+const BUCKETS: usize = 4096;
+const KEYS: usize = 16384;
+const ROUNDS: usize = 50;
+
+struct Key {
+    /// MAC address + VLAN, big endian (8 bytes).
+    bytes: Vec<u8>,
+    /// NUL-terminated string form, for str_hash.
+    cstr: Vec<u8>,
+    /// Integer form, for the integer hashes.
+    int: u64,
+}
+
+impl Key {
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let int = u64::from_be_bytes(bytes[..8].try_into().unwrap());
+        let mut cstr: Vec<u8> = bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>().into_bytes();
+        cstr.push(0);
+        Self { bytes, cstr, int }
+    }
+
+    fn from_int(int: u64) -> Self {
+        let mut cstr = format!("{}", int).into_bytes();
+        cstr.push(0);
+        Self { bytes: int.to_be_bytes().to_vec(), cstr, int }
+    }
+}
+
+/// MAC addresses of the emulated routers (c2:01:xx:xx:00:00 + interface) on a few VLANs.
+fn keys_dynamips_mac() -> Vec<Key> {
+    (0..KEYS)
+        .map(|i| {
+            let (vm, intf, vlan) = (i / 64, i % 16, 1 + (i / 16) % 4);
+            Key::from_bytes(vec![0xc2, (vm >> 8) as u8, vm as u8, 0x12, 0x00, intf as u8, (vlan >> 8) as u8, vlan as u8])
+        })
+        .collect()
+}
+
+/// Sequential addresses of a single OUI on one VLAN (traffic generator).
+fn keys_sequential_mac() -> Vec<Key> {
+    (0..KEYS).map(|i| Key::from_bytes(vec![0x00, 0x0c, 0x29, (i >> 16) as u8, (i >> 8) as u8, i as u8, 0x00, 0x01])).collect()
+}
+
+/// Addresses crafted to collide with the XOR fold of the old switch table.
+fn keys_hostile_mac() -> Vec<Key> {
+    (0..KEYS)
+        .map(|i| {
+            let x = (i as u16).wrapping_mul(0x9e37);
+            Key::from_bytes(vec![(x >> 8) as u8, x as u8, (x >> 8) as u8, x as u8, 0x00, 0x00, 0x00, 0x01])
+        })
+        .collect()
+}
+
+/// Timer ids, allocated sequentially.
+fn keys_timer_ids() -> Vec<Key> {
+    (1..=KEYS as u64).map(Key::from_int).collect()
+}
+
+/// Heap addresses (16-byte aligned).
+fn keys_pointers() -> Vec<Key> {
+    (0..KEYS as u64).map(|i| Key::from_int(0x5555_0000_1000 + i * 48)).collect()
+}
+
+type HashFn = fn(&Key) -> u32;
+type KeySet = fn() -> Vec<Key>;
+
+fn xor_fold_mac(k: &Key) -> u32 {
+    let b = &k.bytes;
+    let h = ((b[0] as u32) << 8 | b[1] as u32) ^ ((b[2] as u32) << 8 | b[3] as u32) ^ ((b[4] as u32) << 8 | b[5] as u32);
+    h ^ ((b[6] as u32) << 8 | b[7] as u32)
+}
+
+fn hashes() -> Vec<(&'static str, HashFn)> {
+    vec![
+        ("xor_fold (old ethsw)", xor_fold_mac),
+        ("str_hash", |k| unsafe { str_hash(k.cstr.as_ptr() as *mut c_void) }),
+        ("int_hash", |k| unsafe { int_hash(k.int as usize as *mut c_void) }),
+        ("u64_hash", |k| unsafe { u64_hash(&k.int as *const u64 as *mut c_void) }),
+        ("ptr_hash", |k| unsafe { ptr_hash(k.int as usize as *mut c_void) }),
+        ("sbox_compute", |k| unsafe { sbox_compute(k.bytes.as_ptr() as *mut u8, k.bytes.len() as _) }),
+        ("sbox_u32", |k| unsafe { sbox_u32((k.int ^ (k.int >> 32)) as u32) }),
+        ("crc12_hash_u32", |k| unsafe { crc12_hash_u32((k.int ^ (k.int >> 32)) as u32) }),
+        ("crc16_hash_u32", |k| unsafe { crc16_hash_u32((k.int ^ (k.int >> 32)) as u32) }),
+        ("mem_hash_seeded", |k| unsafe { mem_hash_seeded(k.bytes.as_ptr().cast(), k.bytes.len()) }),
+        ("str_hash_seeded", |k| unsafe { str_hash_seeded(k.cstr.as_ptr() as *mut c_void) }),
+        ("u64_hash_seeded", |k| unsafe { u64_hash_seeded(&k.int as *const u64 as *mut c_void) }),
+    ]
+}
+
+/// Chi-square of the bucket counts divided by its expected value, and longest chain.
+fn distribution(keys: &[Key], f: HashFn) -> (f64, usize) {
+    let mut buckets = vec![0usize; BUCKETS];
+    for k in keys {
+        buckets[f(k) as usize % BUCKETS] += 1;
+    }
+    let expected = keys.len() as f64 / BUCKETS as f64;
+    let chi2: f64 = buckets.iter().map(|&n| (n as f64 - expected).powi(2) / expected).sum();
+    (chi2 / (BUCKETS - 1) as f64, *buckets.iter().max().unwrap())
+}
+
+fn speed(keys: &[Key], f: HashFn) -> f64 {
+    let start = Instant::now();
+    let mut acc = 0u32;
+    for _ in 0..ROUNDS {
+        for k in keys {
+            acc ^= f(black_box(k));
+        }
+    }
+    black_box(acc);
+    start.elapsed().as_nanos() as f64 / (ROUNDS * keys.len()) as f64
+}
+
+fn main() {
+    unsafe { crc_init() };
+
+    let sets: [(&str, KeySet); 5] = [
+        ("MAC+VLAN (emulated routers)", keys_dynamips_mac),
+        ("MAC+VLAN (sequential)", keys_sequential_mac),
+        ("MAC+VLAN (hostile)", keys_hostile_mac),
+        ("timer ids", keys_timer_ids),
+        ("pointers", keys_pointers),
+    ];
+
+    for (name, gen) in sets {
+        let keys = gen();
+        println!("{} ({} keys, {} buckets)", name, keys.len(), BUCKETS);
+        println!("  {:<22} {:>8} {:>10} {:>10}", "function", "ns/key", "chi2/df", "max chain");
+        for (fname, f) in hashes() {
+            let (chi2, max) = distribution(&keys, f);
+            println!("  {:<22} {:>8.2} {:>10.2} {:>10}", fname, speed(&keys, f), chi2, max);
+        }
+        println!();
+    }
+}
@@ -127,5 +127,6 @@ pub extern "C" fn _export(
     _: crate::utils::ppc_insn_t,
     _: u_long,
     _: crate::rommon_var::rommon_var_list,
+    _: crate::vlan::vlan_tag_t,
 ) {
 }
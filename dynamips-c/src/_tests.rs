@@ -1,5 +1,411 @@
 //! Tests
 
+use std::ffi::c_int;
+use std::ffi::c_void;
+
+// xorshift64, deterministic pseudo-random input
+fn rng(seed: u64) -> impl FnMut() -> u64 {
+    let mut x = seed;
+    move || {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        x
+    }
+}
+
+// Deterministic test data
+fn data(len: usize) -> Vec<u8> {
+    let mut next = rng(0x2545_f491);
+    (0..len).map(|_| next() as u8).collect()
+}
+
+// Opaque port of the switch tables
+fn port(n: usize) -> *mut c_void {
+    n as *mut c_void
+}
+
+// Connected pair of UNIX stream sockets
+fn socketpair() -> [c_int; 2] {
+    let mut fds: [c_int; 2] = [-1; 2];
+    assert_eq!(unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) }, 0);
+    fds
+}
+
+mod atm_oam {
+    use super::port;
+    use crate::atm_oam::*;
+    use crate::crc::*;
+    use std::ffi::c_void;
+
+    fn f5(vpi: u32, vci: u32, segment: bool) -> AtmOamFlow {
+        AtmOamFlow { vpi, vci: Some(vci), segment }
+    }
+
+    // Cell format and processing on the connection state
+    #[test]
+    fn test_atm_oam_handle() {
+        unsafe { crc_init() };
+        let mut oam = AtmOam::new();
+
+        // F5 end-to-end loopback request: answered while the VCC exists
+        let req = atm_oam_loopback_cell(&f5(1, 100, false), 0x1234);
+        assert!(atm_oam_crc_ok(&req));
+        assert_eq!(req[..4], [0x00, 0x10, 0x06, 0x4a]);
+        assert_eq!(AtmOamFlow::of_cell(&req), Some(f5(1, 100, false)));
+        match oam.handle(port(1), &req, ATM_OAM_CONN_VCC, 0) {
+            AtmOamVerdict::Reply(reply) => {
+                assert_eq!(reply[..5], req[..5]);
+                assert_eq!(reply[6] & 0x01, 0);
+                assert_eq!(reply[7..11], [0x00, 0x00, 0x12, 0x34]);
+                assert!(atm_oam_crc_ok(&reply));
+            }
+            v => panic!("{:?}", v),
+        }
+        assert_eq!(oam.handle(port(1), &req, 0, 0), AtmOamVerdict::Drop);
+
+        // F5 cells of a VPC and user cells are switched, bad CRC are dropped
+        assert_eq!(oam.handle(port(1), &req, ATM_OAM_CONN_VPC, 0), AtmOamVerdict::Switch);
+        let mut user = req;
+        user[3] = 0x00;
+        assert_eq!(oam.handle(port(1), &user, 0, 0), AtmOamVerdict::Switch);
+        let mut bad = req;
+        bad[20] ^= 0x01;
+        assert_eq!(oam.handle(port(1), &bad, ATM_OAM_CONN_VCC, 0), AtmOamVerdict::Drop);
+
+        // F4: end-to-end cells follow the VPC, segment ones end here
+        let f4 = AtmOamFlow { vpi: 2, vci: None, segment: false };
+        let ais = atm_oam_defect_cell(&f4, ATM_OAM_AIS);
+        assert_eq!(ais[..4], [0x00, 0x20, 0x00, 0x40]);
+        assert_eq!(oam.handle(port(1), &ais, ATM_OAM_CONN_VPC, 0), AtmOamVerdict::Switch);
+        assert_eq!(oam.handle(port(1), &ais, 0, 0), AtmOamVerdict::Drop);
+        let seg_ais = atm_oam_defect_cell(&AtmOamFlow { segment: true, ..f4 }, ATM_OAM_AIS);
+        match oam.handle(port(1), &seg_ais, ATM_OAM_CONN_VPC, 0) {
+            AtmOamVerdict::Reply(rdi) => {
+                assert_eq!(rdi[5], ATM_OAM_RDI);
+                assert_eq!(AtmOamFlow::of_cell(&rdi), Some(AtmOamFlow { segment: true, ..f4 }));
+            }
+            v => panic!("{:?}", v),
+        }
+
+        // the switch ends the VPs of its VCCs
+        let req = atm_oam_loopback_cell(&f4, 1);
+        assert!(matches!(oam.handle(port(1), &req, ATM_OAM_CONN_VP_END, 0), AtmOamVerdict::Reply(_)));
+
+        let stats = oam.stats();
+        assert_eq!((stats.cells, stats.crc_errors, stats.dropped), (7, 1, 2));
+        assert_eq!((stats.lb_answered, stats.ais_received, stats.rdi_sent), (2, 3, 1));
+    }
+
+    // AIS on deleted connections, loopbacks sent by the switch
+    #[test]
+    fn test_atm_oam_ais_loopback() {
+        unsafe { crc_init() };
+        let mut oam = AtmOam::new();
+
+        let ais = oam.start_ais(port(2), 1, Some(100)).unwrap();
+        assert_eq!((ais[5], AtmOamFlow::of_cell(&ais)), (ATM_OAM_AIS, Some(f5(1, 100, false))));
+        assert!(oam.start_ais(port(2), 1, Some(100)).is_none());
+        oam.start_ais(port(2), 3, None).unwrap();
+
+        let cells = oam.tick(1000);
+        assert_eq!(cells.len(), 2);
+        assert!(cells.iter().all(|(p, cell)| *p == port(2) && atm_oam_crc_ok(cell)));
+        assert!(oam.stop_ais(port(2), 1, Some(100)));
+        assert!(!oam.stop_ais(port(2), 1, Some(100)));
+        assert_eq!(oam.clear_ais(), [port(2)]);
+        assert!(oam.tick(2000).is_empty());
+        assert_eq!(oam.stats().ais_sent, 4);
+
+        // the reply to our request is consumed, on the same flow only
+        let mut reply = oam.loopback(port(1), 1, Some(100), 0);
+        assert_eq!(AtmOamFlow::of_cell(&reply), Some(f5(1, 100, true)));
+        reply[6] = 0;
+        atm_oam_insert_crc(&mut reply);
+        assert!(matches!(oam.handle(port(3), &reply, ATM_OAM_CONN_VCC, 20), AtmOamVerdict::Drop));
+        assert_eq!(oam.stats().lb_replies, 0);
+        assert_eq!(oam.handle(port(1), &reply, ATM_OAM_CONN_VCC, 20), AtmOamVerdict::Drop);
+        assert_eq!((oam.stats().lb_replies, oam.stats().lb_last_rtt), (1, 20));
+
+        oam.loopback(port(1), 1, None, 0);
+        oam.tick(ATM_OAM_LOOPBACK_TIMEOUT);
+        assert_eq!(oam.stats().lb_timeouts, 1);
+    }
+}
+
+mod base64 {
+    use super::data;
+    use crate::base64::*;
+
+    // RFC4648 section 10
+    const VECTORS: [(&str, &str); 7] = [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foob", "Zm9vYg=="), ("fooba", "Zm9vYmE="), ("foobar", "Zm9vYmFy")];
+
+    #[test]
+    fn test_base64_vectors() {
+        for (raw, enc) in VECTORS {
+            assert_eq!(base64_encode_all(raw.as_bytes(), 0, 0), enc.as_bytes());
+            assert_eq!(base64_decode_all(enc.as_bytes(), BASE64_STRICT), Ok(raw.as_bytes().to_vec()));
+            assert_eq!(base64_encoded_size(raw.len(), 0, 0), enc.len());
+
+            let nopad = enc.trim_end_matches('=');
+            assert_eq!(base64_encode_all(raw.as_bytes(), BASE64_NOPAD, 0), nopad.as_bytes());
+            assert_eq!(base64_decode_all(nopad.as_bytes(), BASE64_STRICT | BASE64_NOPAD), Ok(raw.as_bytes().to_vec()));
+            assert_eq!(base64_decode_all(nopad.as_bytes(), 0), Ok(raw.as_bytes().to_vec()));
+
+            // same result as the legacy encoder
+            let mut legacy = vec![0u8; enc.len() + 1];
+            unsafe { base64_encode(legacy.as_mut_ptr(), raw.as_ptr(), raw.len() as _) };
+            assert_eq!(&legacy[..enc.len()], enc.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_base64_streaming() {
+        let raw = data(1000);
+        for (flags, line_len) in [(0, 0), (BASE64_NOPAD, 0), (0, 76), (BASE64_CRLF, 64), (BASE64_URL | BASE64_NOPAD, 5)] {
+            let whole = base64_encode_all(&raw, flags, line_len);
+            assert_eq!(whole.len(), base64_encoded_size(raw.len(), flags, line_len));
+            assert_eq!(base64_decode_all(&whole, flags | BASE64_STRICT).as_deref(), Ok(&raw[..]));
+
+            for chunk in [1, 2, 3, 4, 7, 100] {
+                let mut enc = base64_enc::new(flags, line_len);
+                let mut out = Vec::new();
+                for part in raw.chunks(chunk) {
+                    let len = enc.update_len(part.len());
+                    let before = out.len();
+                    enc.update(part, &mut out);
+                    assert_eq!(out.len() - before, len);
+                }
+                let len = enc.finish_len();
+                enc.finish(&mut out);
+                assert_eq!(out, whole);
+                assert_eq!(len, whole.len() - (out.len() - len));
+
+                let mut dec = base64_dec::new(flags | BASE64_STRICT);
+                let mut decoded = Vec::new();
+                for part in whole.chunks(chunk) {
+                    let max = dec.update_len(part.len());
+                    assert!(dec.update(part, &mut decoded).expect("update") <= max);
+                }
+                dec.finish(&mut decoded).expect("finish");
+                assert_eq!(decoded, raw);
+            }
+        }
+
+        // wrapping
+        let enc = base64_encode_all(&data(60), BASE64_CRLF, 76);
+        let lines: Vec<&[u8]> = enc.split(|&c| c == b'\n').collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), 77);
+        assert_eq!(lines[1].len(), 4);
+        assert_eq!(base64_encoded_size(57, 0, 76), 76);
+    }
+
+    #[test]
+    fn test_base64_url() {
+        let raw = [0xfb, 0xff, 0xbf];
+        assert_eq!(base64_encode_all(&raw, 0, 0), b"+/+/");
+        assert_eq!(base64_encode_all(&raw, BASE64_URL, 0), b"-_-_");
+        assert_eq!(base64_decode_all(b"-_-_", BASE64_URL | BASE64_STRICT), Ok(raw.to_vec()));
+        // only the selected alphabet when strict
+        assert_eq!(base64_decode_all(b"+/+/", BASE64_URL | BASE64_STRICT), Err(Base64Error::Char { pos: 0, c: b'+' }));
+        assert_eq!(base64_decode_all(b"+/-_", BASE64_STRICT), Err(Base64Error::Char { pos: 2, c: b'-' }));
+        assert_eq!(base64_decode_all(b"+/-_", 0), Ok(raw.to_vec()));
+    }
+
+    #[test]
+    fn test_base64_errors() {
+        let strict = |s: &str| base64_decode_all(s.as_bytes(), BASE64_STRICT);
+        let lenient = |s: &str| base64_decode_all(s.as_bytes(), 0);
+
+        assert_eq!(strict("Zm9v Zg=="), Err(Base64Error::Char { pos: 4, c: b' ' }));
+        assert_eq!(lenient(" Zm9v\tZg==\r\n"), Ok(b"foof".to_vec()));
+        assert_eq!(strict("Zm9v\r\nZg=="), Ok(b"foof".to_vec()));
+        assert_eq!(lenient("Zm9*"), Err(Base64Error::Char { pos: 3, c: b'*' }));
+        assert_eq!(lenient("Z==="), Err(Base64Error::Padding { pos: 1 }));
+        assert_eq!(lenient("Zg=a"), Err(Base64Error::Padding { pos: 3 }));
+        assert_eq!(lenient("Zm9vZ"), Err(Base64Error::Length { pos: 5 }));
+        assert_eq!(strict("Zg"), Err(Base64Error::Padding { pos: 2 }));
+        assert_eq!(lenient("Zg="), Ok(b"f".to_vec()));
+        assert_eq!(strict("Zg="), Err(Base64Error::Padding { pos: 3 }));
+        assert_eq!(base64_decode_all(b"Zg==", BASE64_STRICT | BASE64_NOPAD), Err(Base64Error::Padding { pos: 2 }));
+        assert_eq!(strict("Zh=="), Err(Base64Error::Bits { pos: 1 }));
+        assert_eq!(lenient("Zh=="), Ok(b"f".to_vec()));
+        assert_eq!(strict("Zg==Zg=="), Err(Base64Error::Trailing { pos: 4 }));
+        assert_eq!(lenient("Zg==Zg=="), Ok(b"ff".to_vec()));
+
+        // the error is kept for the C API
+        let mut dec = base64_dec::new(BASE64_STRICT);
+        let mut out = [0u8; 16];
+        let mut pos = 0;
+        unsafe {
+            assert_eq!(base64_dec_update(&mut dec, out.as_mut_ptr(), b"Zm9v".as_ptr(), 4), 3);
+            assert_eq!(base64_dec_update(&mut dec, out.as_mut_ptr(), b"Z!".as_ptr(), 2), -1);
+            assert_eq!(base64_dec_finish(&mut dec, out.as_mut_ptr()), -1);
+            assert_eq!(base64_dec_error(&dec, &mut pos), BASE64_ERR_CHAR);
+            assert_eq!(std::ffi::CStr::from_ptr(base64_strerror(BASE64_ERR_CHAR)), c"invalid character");
+        }
+        assert_eq!(pos, 5);
+        assert_eq!(dec.error(), Some(Base64Error::Char { pos: 5, c: b'!' }));
+    }
+
+    #[test]
+    fn test_base64_c_api() {
+        let raw = data(100);
+        let mut enc = base64_enc::default();
+        unsafe {
+            base64_enc_init(&mut enc, 0, 16);
+            let total = base64_encoded_len(raw.len(), 0, 16);
+            let mut out = vec![0u8; total];
+            let mut len = 0;
+            for part in raw.chunks(10) {
+                len += base64_enc_update(&mut enc, out.as_mut_ptr().add(len), part.as_ptr(), part.len());
+            }
+            len += base64_enc_finish(&mut enc, out.as_mut_ptr().add(len));
+            assert_eq!(len, total);
+            assert_eq!(out, base64_encode_all(&raw, 0, 16));
+
+            assert_eq!(base64_decoded_len(out.as_ptr(), out.len(), 0), raw.len() as _);
+            assert_eq!(base64_decoded_len(b"Zm9*".as_ptr(), 4, 0), -1);
+        }
+    }
+}
+
+mod cisco_eeprom {
+    use crate::cisco_eeprom::*;
+    use crate::dynamips_common::*;
+    use crate::net::*;
+    use std::ptr::null_mut;
+
+    #[test]
+    fn test_cisco_eeprom_set_mac_addr() {
+        // v4: block size (0x43), chassis MAC (0xC3), base MAC (0xCF, unchanged)
+        let mut data: [m_uint16_t; 11] = [0x04FF, 0x4300, 0x10C3, 0x0600, 0x0000, 0x0000, 0x00CF, 0x0600, 0x0000, 0x0000, 0x00FF];
+        let mut eeprom = cisco_eeprom { name: null_mut(), data: data.as_mut_ptr(), len: data.len() };
+        let addr = n_eth_addr_t { eth_addr_byte: [0xc2, 0x01, 0x02, 0x03, 0x00, 0x00] };
+        let mut offset = 0;
+        let mut byte = 0;
+
+        unsafe {
+            assert_eq!(cisco_eeprom_v4_find_field(&mut eeprom, 0xCF, &mut offset), 0);
+            assert_eq!(offset, 15);
+            assert_eq!(cisco_eeprom_set_mac_addr(&mut eeprom, &addr), 0);
+            assert_eq!(cisco_eeprom_set_mac_block_size(&mut eeprom, 0x40), 0);
+            for (field, len) in [(0xC3, 6), (0xCF, 6), (0x43, 2)] {
+                assert_eq!(cisco_eeprom_v4_find_field(&mut eeprom, field, &mut offset), 0);
+                let got: Vec<m_uint8_t> = (0..len)
+                    .map(|i| {
+                        cisco_eeprom_get_byte(&mut eeprom, offset + i, &mut byte);
+                        byte
+                    })
+                    .collect();
+                match field {
+                    0x43 => assert_eq!(got, [0x00, 0x40]),
+                    0xCF => assert_eq!(got, [0x00; 6]),
+                    _ => assert_eq!(got, addr.eth_addr_byte),
+                }
+            }
+        }
+
+        // v4 without chassis MAC field: nothing to do
+        let mut data: [m_uint16_t; 3] = [0x04FF, 0x4300, 0x10FF];
+        let mut eeprom = cisco_eeprom { name: null_mut(), data: data.as_mut_ptr(), len: data.len() };
+        unsafe {
+            assert_eq!(cisco_eeprom_set_mac_addr(&mut eeprom, &addr), 0);
+        }
+        assert_eq!(data, [0x04FF, 0x4300, 0x10FF]);
+
+        // v0: stored at offset 2, no block size
+        let mut data: [m_uint16_t; 4] = [0x0000, 0x0000, 0x0000, 0x0000];
+        let mut eeprom = cisco_eeprom { name: null_mut(), data: data.as_mut_ptr(), len: data.len() };
+        unsafe {
+            assert_eq!(cisco_eeprom_set_mac_addr(&mut eeprom, &addr), 0);
+            assert_eq!(cisco_eeprom_set_mac_block_size(&mut eeprom, 0x40), -1);
+        }
+        assert_eq!(data, [0x0000, 0xc201, 0x0203, 0x0000]);
+    }
+}
+
+mod crc {
+    use super::data;
+    use crate::crc::*;
+
+    const CHECK: &[u8] = b"123456789";
+
+    /// Bit-at-a-time reflected CRC.
+    fn reference(poly: u32, mut crc: u32, data: &[u8]) -> u32 {
+        for &b in data {
+            crc ^= b as u32;
+            for _ in 0..8 {
+                crc = if (crc & 1) != 0 { (crc >> 1) ^ poly } else { crc >> 1 };
+            }
+        }
+        crc
+    }
+
+    // Catalogue check values (reveng.sourceforge.net)
+    #[test]
+    fn test_crc_check_values() {
+        unsafe {
+            crc_init();
+            let mut buf = CHECK.to_vec();
+            let p = buf.as_mut_ptr();
+            assert_eq!(crc32_compute(0xffffffff, p, 9), 0xcbf43926);
+            assert_eq!(crc32c_compute(0xffffffff, p, 9), 0xe3069283);
+            assert_eq!(crc16_ccitt_compute(0xffff, p, 9), 0x906e);
+            assert_eq!(crc10_compute(0, p, 9), 0x199);
+
+            // chained blocks
+            let crc = crc32_compute(0xffffffff, p, 4);
+            assert_eq!(crc32_compute(!crc, p.add(4), 5), 0xcbf43926);
+            let crc = crc16_ccitt_compute(0xffff, p, 4);
+            assert_eq!(crc16_ccitt_compute(!crc, p.add(4), 5), 0x906e);
+            assert_eq!(crc10_compute(crc10_compute(0, p, 4), p.add(4), 5), 0x199);
+        }
+
+        // a frame followed by its FCS gives the good residue
+        let mut frame = data(64);
+        let fcs = !crc16_ccitt_update(0xffff, &frame);
+        frame.extend(fcs.to_le_bytes());
+        assert_eq!(crc16_ccitt_update(0xffff, &frame), CRC16_CCITT_GOOD);
+
+        // CRC-10 of an OAM cell payload with its CRC field is zero
+        let mut cell: [u8; 48] = data(48).try_into().unwrap();
+        let crc = crc10_oam(&cell);
+        assert_eq!(crc, unsafe { crc10_oam_compute(cell.as_ptr()) });
+        cell[46] = (cell[46] & 0xfc) | (crc >> 8) as u8;
+        cell[47] = crc as u8;
+        assert_eq!(crc10_update(0, &cell), 0);
+    }
+
+    // Table and hardware implementations against a bitwise reference
+    #[test]
+    fn test_crc_implementations() {
+        unsafe { crc_init() };
+        let buf = data(4096 + 7);
+
+        for len in [0, 1, 7, 8, 9, 63, 64, 65, 1500, 4096] {
+            for offset in [0, 1, 3] {
+                let d = &buf[offset..offset + len];
+                let crc32 = reference(0xedb88320, 0xffffffff, d);
+                let crc32c = reference(0x82f63b78, 0xffffffff, d);
+
+                assert_eq!(crc32_update_sw(0xffffffff, d), crc32);
+                assert_eq!(crc32_update(0xffffffff, d), crc32);
+                assert_eq!(crc32c_update_sw(0xffffffff, d), crc32c);
+                assert_eq!(crc32c_update(0xffffffff, d), crc32c);
+                if let Some(crc) = crc32_update_hw(0xffffffff, d) {
+                    assert_eq!(crc, crc32);
+                }
+                if let Some(crc) = crc32c_update_hw(0xffffffff, d) {
+                    assert_eq!(crc, crc32c);
+                }
+                assert_eq!(crc16_ccitt_update(0xffff, d) as u32, reference(0x8408, 0xffff, d));
+            }
+        }
+    }
+}
+
 mod dynamips_common {
     use crate::dynamips_common::*;
 
@@ -91,11 +497,285 @@ mod dynamips_common {
     }
 }
 
+mod endian {
+    use crate::endian::*;
+    use crate::fs_nvram::*;
+    use crate::net::*;
+    use std::mem::offset_of;
+
+    // Endian types: layout and byte order
+    #[test]
+    fn test_endian_types() {
+        assert_eq!(size_of::<Be16>(), 2);
+        assert_eq!(size_of::<Be32>(), 4);
+        assert_eq!(size_of::<Le64>(), 8);
+        assert_eq!(Be16::new(0x1234).to_raw().to_ne_bytes(), [0x12, 0x34]);
+        assert_eq!(Be32::new(0x12345678).to_raw().to_ne_bytes(), [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(Le32::new(0x12345678).to_raw().to_ne_bytes(), [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(Be64::new(0x0102030405060708).to_raw().to_ne_bytes(), [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(Vm32::from_raw(crate::utils::htovm32(0xdeadbeef)).get(), 0xdeadbeef);
+        let mut x = Le16::default();
+        x.set(0xabcd);
+        assert_eq!(u16::from(x), 0xabcd);
+        assert_eq!(format!("{:?}", Be16::new(0x800)), "0x800");
+
+        // packed headers keep their C layout
+        assert_eq!(size_of::<fs_nvram_header_startup_config>(), 36);
+        assert_eq!(offset_of!(fs_nvram_header_startup_config, uncompressed_len), 32);
+        assert_eq!(size_of::<n_ip_hdr_t>(), 20);
+        assert_eq!(size_of::<n_tcp_hdr_t>(), 20);
+        assert_eq!(size_of::<n_arp_hdr_t>(), 28);
+    }
+
+    // GuestBuf: typed access at unaligned offsets
+    #[test]
+    fn test_guest_buf() {
+        let mut data = [0u8; 16];
+        let mut buf = GuestBuf::new(&mut data);
+        buf.set_be16(1, 0x0800);
+        buf.set_be32(3, 0xc0a80001);
+        buf.set_le32(7, 0x11223344);
+        buf.set_u8(15, 0xff);
+        assert_eq!(buf.get_be16(1), 0x0800);
+        assert_eq!(buf.get_vm32(3), 0xc0a80001);
+        assert_eq!(buf.get_le16(7), 0x3344);
+        assert_eq!(buf.sub(3, 4).get_be32(0), 0xc0a80001);
+        assert_eq!(data, [0, 8, 0, 0xc0, 0xa8, 0, 1, 0x44, 0x33, 0x22, 0x11, 0, 0, 0, 0, 0xff]);
+
+        // a header read through the view sees native values
+        let mut pkt = [0u8; 20];
+        pkt[..4].copy_from_slice(&[0x45, 0, 0, 84]);
+        pkt[12..].copy_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        let buf = GuestBuf::new(&mut pkt);
+        let ip: n_ip_hdr_t = unsafe { buf.read(0) };
+        assert_eq!(ip.tot_len.get(), 84);
+        assert_eq!(ip.saddr.get(), 0x0a000001);
+        assert_eq!(ip.daddr.get(), 0x0a000002);
+
+        let r = std::panic::catch_unwind(|| {
+            let mut data = [0u8; 4];
+            GuestBuf::new(&mut data).get_be32(1)
+        });
+        assert!(r.is_err());
+    }
+}
+
+mod evloop {
+    use super::socketpair;
+    use crate::evloop::*;
+    use std::ffi::c_int;
+    use std::ffi::c_void;
+    use std::ptr::addr_of_mut;
+
+    unsafe extern "C" fn count_cbk(_fd: c_int, events: c_int, opt: *mut c_void) {
+        assert_ne!(events & EV_READ, 0);
+        *opt.cast::<u32>() += 1;
+    }
+
+    #[test]
+    fn test_ev_loop_level() {
+        let ev = ev_loop::new().expect("ev_loop");
+        let fds = socketpair();
+        let mut calls = 0u32;
+
+        ev.add(fds[0], EV_READ, Some(count_cbk), addr_of_mut!(calls).cast::<_>()).expect("add");
+        assert_eq!(ev.len(), 1);
+        assert_eq!(ev.wait(0).expect("wait"), 0);
+
+        // level triggered: reported until the data is read
+        unsafe {
+            assert_eq!(libc::write(fds[1], b"x".as_ptr().cast::<_>(), 1), 1);
+            assert_eq!(ev.wait(1000).expect("wait"), 1);
+            assert_eq!(ev.dispatch(), 1);
+            assert_eq!(ev.wait(1000).expect("wait"), 1);
+            assert_eq!(ev.dispatch(), 1);
+        }
+        assert_eq!(calls, 2);
+
+        // unregistered after the wait: the pending event is dropped
+        assert_eq!(ev.wait(1000).expect("wait"), 1);
+        ev.del(fds[0]).expect("del");
+        assert_eq!(unsafe { ev.dispatch() }, 0);
+        assert!(ev.is_empty());
+        assert!(ev.del(fds[0]).is_err());
+
+        // registered again after the wait: the stale event is dropped too
+        assert_eq!(ev.wait(1000).expect("wait"), 0);
+        ev.add(fds[0], EV_READ, Some(count_cbk), addr_of_mut!(calls).cast::<_>()).expect("add");
+        assert_eq!(ev.wait(1000).expect("wait"), 1);
+        ev.del(fds[0]).expect("del");
+        ev.add(fds[0], EV_READ, Some(count_cbk), addr_of_mut!(calls).cast::<_>()).expect("add");
+        assert_eq!(unsafe { ev.dispatch() }, 0);
+        assert_eq!(calls, 2);
+
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_ev_loop_edge() {
+        let ev = ev_loop::new().expect("ev_loop");
+        let fds = socketpair();
+        let mut calls = 0u32;
+
+        ev.add(fds[0], EV_READ | EV_EDGE, Some(count_cbk), addr_of_mut!(calls).cast::<_>()).expect("add");
+        unsafe {
+            assert_eq!(libc::write(fds[1], b"x".as_ptr().cast::<_>(), 1), 1);
+            assert_eq!(ev.wait(1000).expect("wait"), 1);
+            assert_eq!(ev.dispatch(), 1);
+            // edge triggered: no new event without new data
+            assert_eq!(ev.wait(0).expect("wait"), 0);
+            assert_eq!(libc::write(fds[1], b"y".as_ptr().cast::<_>(), 1), 1);
+            assert_eq!(ev.wait(1000).expect("wait"), 1);
+            assert_eq!(ev.dispatch(), 1);
+        }
+        assert_eq!(calls, 2);
+
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+
+    struct DelCtx {
+        ev: *const ev_loop,
+        fds: [c_int; 2],
+        calls: u32,
+    }
+
+    unsafe extern "C" fn del_cbk(_fd: c_int, _events: c_int, opt: *mut c_void) {
+        let ctx = &mut *opt.cast::<DelCtx>();
+        ctx.calls += 1;
+        for fd in ctx.fds {
+            let _ = (*ctx.ev).del(fd);
+        }
+    }
+
+    #[test]
+    fn test_ev_loop_del_in_callback() {
+        let ev = ev_loop::new().expect("ev_loop");
+        let a = socketpair();
+        let b = socketpair();
+        let mut ctx = DelCtx { ev: &ev, fds: [a[0], b[0]], calls: 0 };
+
+        for fd in ctx.fds {
+            ev.add(fd, EV_READ, Some(del_cbk), addr_of_mut!(ctx).cast::<_>()).expect("add");
+        }
+        unsafe {
+            assert_eq!(libc::write(a[1], b"x".as_ptr().cast::<_>(), 1), 1);
+            assert_eq!(libc::write(b[1], b"x".as_ptr().cast::<_>(), 1), 1);
+            assert_eq!(ev.wait(1000).expect("wait"), 2);
+            // the first callback unregisters both FDs
+            assert_eq!(ev.dispatch(), 1);
+        }
+        assert_eq!(ctx.calls, 1);
+        assert!(ev.is_empty());
+
+        for fd in [a, b].iter().flatten() {
+            unsafe { libc::close(*fd) };
+        }
+    }
+}
+
+mod frame_relay {
+    use super::port;
+    use crate::dynamips_common::*;
+    use crate::frame_relay::*;
+    use std::ffi::c_void;
+
+    fn enquiry(kind: FrLmiType, report: u8, ssn: u8, rsn: u8) -> Vec<u8> {
+        match kind {
+            FrLmiType::Ansi => vec![0x00, 0x01, 0x03, 0x08, 0x00, 0x75, 0x95, 0x01, 0x01, report, 0x03, 0x02, ssn, rsn],
+            FrLmiType::Q933A => vec![0x00, 0x01, 0x03, 0x08, 0x00, 0x75, 0x51, 0x01, report, 0x53, 0x02, ssn, rsn],
+            FrLmiType::Cisco => vec![0xfc, 0xf1, 0x03, 0x09, 0x00, 0x75, 0x01, 0x01, report, 0x03, 0x02, ssn, rsn],
+        }
+    }
+
+    // Status of the three variants
+    #[test]
+    fn test_fr_lmi_status() {
+        let pvcs = [(100, port(9))];
+
+        let mut lmi = FrLmi::new();
+        let resp = lmi.handle(port(1), &enquiry(FrLmiType::Ansi, 0, 1, 0), &pvcs, 0).unwrap();
+        assert_eq!(resp, [0x00, 0x01, 0x03, 0x08, 0x00, 0x7d, 0x95, 0x01, 0x01, 0x00, 0x03, 0x02, 0x01, 0x01, 0x07, 0x03, 0x06, 0xa0, 0x8a]);
+
+        // link integrity only, the PVC is not new anymore
+        let resp = lmi.handle(port(1), &enquiry(FrLmiType::Ansi, 1, 2, 1), &pvcs, 0).unwrap();
+        assert_eq!(resp[7..], [0x01, 0x01, 0x01, 0x03, 0x02, 0x02, 0x02]);
+        let resp = lmi.handle(port(1), &enquiry(FrLmiType::Ansi, 0, 3, 2), &pvcs, 0).unwrap();
+        assert_eq!(resp[14..], [0x07, 0x03, 0x06, 0xa0, 0x82]);
+
+        let mut lmi = FrLmi::new();
+        let resp = lmi.handle(port(1), &enquiry(FrLmiType::Q933A, 0, 5, 0), &pvcs, 0).unwrap();
+        assert_eq!(resp, [0x00, 0x01, 0x03, 0x08, 0x00, 0x7d, 0x51, 0x01, 0x00, 0x53, 0x02, 0x01, 0x05, 0x57, 0x03, 0x06, 0xa0, 0x8a]);
+
+        let mut lmi = FrLmi::new();
+        let resp = lmi.handle(port(1), &enquiry(FrLmiType::Cisco, 0, 5, 0xff), &pvcs, 0).unwrap();
+        assert_eq!(resp, [0xfc, 0xf1, 0x03, 0x09, 0x00, 0x7d, 0x01, 0x01, 0x00, 0x03, 0x02, 0x01, 0x05, 0x07, 0x06, 0x06, 0xa0, 0x8a, 0, 0, 0]);
+        assert_eq!(lmi.port(port(1)).unwrap().kind(), Some(FrLmiType::Cisco));
+
+        // not status enquiries
+        let mut bad = enquiry(FrLmiType::Ansi, 0, 1, 0);
+        bad[5] = 0x7d;
+        assert_eq!(FrLmiEnquiry::parse(&bad), None);
+        assert_eq!(FrLmiEnquiry::parse(&enquiry(FrLmiType::Ansi, 2, 1, 0)), None);
+        assert_eq!(FrLmiEnquiry::parse(&enquiry(FrLmiType::Cisco, 0, 1, 0)[..12]), None);
+        assert_eq!(fr_addr_to_dlci(&fr_dlci_to_addr(1023)), 1023);
+    }
+
+    // Autodetection, polling verification and PVC status
+    #[test]
+    fn test_fr_lmi_polling() {
+        let mut lmi = FrLmi::new();
+        let (a, b) = (port(1), port(2));
+
+        // autosense: the first variant is kept while the DTE polls with it
+        assert!(lmi.handle(a, &enquiry(FrLmiType::Ansi, 0, 1, 0), &[(100, b)], 0).is_some());
+        assert!(lmi.handle(a, &enquiry(FrLmiType::Cisco, 0, 1, 0), &[(100, b)], 10).is_none());
+        assert!(lmi.handle(b, &enquiry(FrLmiType::Cisco, 0, 1, 0), &[(200, a)], 0).is_some());
+        assert_eq!(lmi.port(a).unwrap().info().ignored, 1);
+
+        // b stops polling: down after N392 errors, its PVCs are inactive on a
+        for t in 1..=FR_LMI_N392 as m_tmcnt_t {
+            lmi.handle(a, &enquiry(FrLmiType::Ansi, 1, 1, 0), &[(100, b)], t * FR_LMI_T392);
+            lmi.tick(t * FR_LMI_T392);
+        }
+        assert_eq!(lmi.port(b).unwrap().state(), FR_LMI_STATE_DOWN);
+        assert_eq!(lmi.port(a).unwrap().state(), FR_LMI_STATE_UP);
+        assert_eq!(lmi.port(b).unwrap().info().errors, FR_LMI_N392 as m_uint64_t);
+        let resp = lmi.handle(a, &enquiry(FrLmiType::Ansi, 0, 1, 0), &[(100, b)], 50000).unwrap();
+        assert_eq!(resp[14..], [0x07, 0x03, 0x06, 0xa0, 0x80]);
+
+        // b comes back with another variant
+        for t in 0..2 {
+            assert!(lmi.handle(b, &enquiry(FrLmiType::Q933A, 1, 1, 0), &[(200, a)], 50000 + t).is_some());
+        }
+        assert_eq!(lmi.port(b).unwrap().state(), FR_LMI_STATE_UP);
+        assert_eq!(lmi.port(b).unwrap().kind(), Some(FrLmiType::Q933A));
+
+        // a deleted PVC is reported once with ANSI
+        let resp = lmi.handle(a, &enquiry(FrLmiType::Ansi, 0, 1, 0), &[(101, b)], 50000).unwrap();
+        assert_eq!(resp[14..], [0x07, 0x03, 0x06, 0xa0, 0x84, 0x07, 0x03, 0x06, 0xa8, 0x8a]);
+        let resp = lmi.handle(a, &enquiry(FrLmiType::Ansi, 0, 1, 0), &[(101, b)], 50000).unwrap();
+        assert_eq!(resp[14..], [0x07, 0x03, 0x06, 0xa8, 0x82]);
+
+        // ports which never polled are not monitored
+        lmi.remove_port(b);
+        lmi.tick(1_000_000);
+        assert!(lmi.port(b).is_none() && lmi.is_active(b));
+    }
+}
+
 mod hash {
     use crate::_extra::*;
     use crate::hash::*;
     use std::ffi::c_int;
     use std::ffi::c_void;
+    use std::ptr::addr_of_mut;
 
     #[test]
     fn test_hash_table_create() {
@@ -105,124 +785,1251 @@ mod hash {
         extern "C" fn x_equal(x1: *mut c_void, x2: *mut c_void) -> c_int {
             (x1 == x2) as _
         }
-        const HASH_SIZE: c_int = 16;
+        const HASH_SIZE: c_int = 16;
+
+        unsafe {
+            let ht = hash_table_create(Some(x_hash), Some(x_equal), HASH_SIZE);
+            assert!(!ht.is_null());
+            hash_table_delete(ht);
+
+            let ht = hash_string_create!(HASH_SIZE);
+            assert!(!ht.is_null());
+            hash_table_delete(ht);
+
+            let ht = hash_int_create!(HASH_SIZE);
+            assert!(!ht.is_null());
+            hash_table_delete(ht);
+
+            let ht = hash_u64_create!(HASH_SIZE);
+            assert!(!ht.is_null());
+            hash_table_delete(ht);
+
+            let ht = hash_ptr_create!(HASH_SIZE);
+            assert!(!ht.is_null());
+            hash_table_delete(ht);
+        }
+    }
+
+    #[test]
+    fn test_macro_hash_table_foreach() {
+        unsafe {
+            const HASH_SIZE: c_int = 16;
+            let ht = hash_int_create!(HASH_SIZE);
+            assert!(!ht.is_null());
+            for i in 0..64 {
+                hash_table_insert(ht, i as _, i as _);
+            }
+
+            let mut found: u64 = 0;
+            HASH_TABLE_FOREACH!(i, ht, hn, {
+                assert!((*hn).key == (*hn).value);
+                found |= 1 << ((*hn).key as usize);
+            });
+            assert_eq!(found, !0_u64);
+
+            hash_table_delete(ht);
+        }
+    }
+
+    #[test]
+    fn test_siphash() {
+        // reference vectors of the SipHash paper (key 00..0f, message 00..len-1)
+        let key = (0x0706050403020100, 0x0f0e0d0c0b0a0908);
+        let msg: Vec<u8> = (0..64).collect();
+        assert_eq!(siphash::<2, 4>(key, &msg[..0]), 0x726fdb47dd0e0e31);
+        assert_eq!(siphash::<2, 4>(key, &msg[..8]), 0x93f5f5799a932462);
+        assert_eq!(siphash::<2, 4>(key, &msg[..15]), 0xa129ca6149be45e5);
+        assert_eq!(siphash::<2, 4>(key, &msg[..63]), 0x958a324ceb064572);
+        assert_ne!(siphash13(key, &msg[..15]), siphash13((key.0 ^ 1, key.1), &msg[..15]));
+    }
+
+    #[test]
+    fn test_hash_seeded() {
+        unsafe {
+            // stable for the whole process
+            let seed = hash_seed();
+            assert_eq!(hash_set_seed(1, 2), -1);
+            assert_eq!(hash_seed(), seed);
+
+            let mut v: u64 = 0x0123456789abcdef;
+            let p = addr_of_mut!(v).cast::<c_void>();
+            assert_eq!(u64_hash_seeded(p), u64_hash_seeded(p));
+            assert_eq!(u64_hash_seeded(p), mem_hash_seeded(p, 8));
+            assert_eq!(str_hash_seeded(c"R1".as_ptr() as *mut c_void), hash_seeded(b"R1"));
+
+            let ht = hash_int_create_seeded!(16);
+            for i in 1..64 {
+                hash_table_insert(ht, i as _, (i * 2) as _);
+            }
+            for i in 1..64 {
+                assert_eq!(hash_table_lookup(ht, i as _), (i * 2) as _);
+            }
+            hash_table_delete(ht);
+        }
+    }
+}
+
+mod hexdump {
+    use super::data;
+    use crate::dynamips_common::*;
+    use crate::hexdump::*;
+
+    #[test]
+    fn test_hexdump_format() {
+        let pkt = b"E\x00\x00\x54 hello, world!\xff";
+        let fmt = hexdump_fmt { offset_base: 0x1000, ..Default::default() };
+        assert_eq!(
+            hexdump_string(pkt, &fmt),
+            "00001000: 45 00 00 54 20 68 65 6c 6c 6f 2c 20 77 6f 72 6c  E..T hello, worl\n\
+             00001010: 64 21 ff                                         d!.\n"
+        );
+
+        let fmt = hexdump_fmt { group: 2, offset_width: 4, ..Default::default() };
+        assert_eq!(
+            hexdump_string(&pkt[..19], &fmt),
+            "0000: 4500 0054 2068 656c 6c6f 2c20 776f 726c  E..T hello, worl\n\
+             0010: 6421 ff                                  d!.\n"
+        );
+
+        let fmt = hexdump_fmt { group: 4, line_len: 8, offset_width: 0, ascii: FALSE, ..Default::default() };
+        assert_eq!(hexdump_string(&pkt[..10], &fmt), "45000054 2068656c\n6c6f\n");
+
+        let mut out = Vec::new();
+        hexdump_write(&mut out, pkt, &hexdump_fmt::default()).unwrap();
+        assert_eq!(out, hexdump_string(pkt, &hexdump_fmt::default()).as_bytes());
+    }
+
+    #[test]
+    fn test_hexdump_round_trip() {
+        for len in [0, 1, 15, 16, 17, 63, 100] {
+            for (group, line_len) in [(1, 16), (2, 16), (4, 16), (4, 32), (2, 7)] {
+                let d = data(len);
+                let fmt = hexdump_fmt { group, line_len, offset_base: 0x80000000, ..Default::default() };
+                let res = hexdump_decode(&hexdump_string(&d, &fmt), &fmt, None).unwrap();
+                assert_eq!(res.data, d, "len {} group {} line_len {}", len, group, line_len);
+                assert_eq!(res.start, if len == 0 { 0 } else { 0x80000000 });
+            }
+        }
+
+        // the ASCII column can look like hex
+        let d = b"0123456789abcdef0123".to_vec();
+        let fmt = hexdump_fmt::default();
+        assert_eq!(hexdump_decode(&hexdump_string(&d, &fmt), &fmt, None).unwrap().data, d);
+
+        // contiguous hex, gaps between offsets, old mem_dump format
+        assert_eq!(hexdump_decode("deadbeef\n", &fmt, None).unwrap().data, [0xde, 0xad, 0xbe, 0xef]);
+        let res = hexdump_decode("0010: 01 02\n0014: 03\n", &fmt, None).unwrap();
+        assert_eq!((res.start, res.data), (0x10, vec![1, 2, 0, 0, 3]));
+        let old = "0000: 30 31 32 33 34 35 36 37 38 39 61 62 63 64 65 66 0123456789abcdef\n0010: 41 42 43                                        ABC\n";
+        assert_eq!(hexdump_decode(old, &fmt, None).unwrap().data, b"0123456789abcdefABC");
+
+        assert_eq!(hexdump_decode("0000: 01 0g", &fmt, None).unwrap().data, [1]);
+        assert_eq!(hexdump_decode("0000: 0g", &fmt, None), Err(HexError::Digit { line: 1, col: 7 }));
+        assert_eq!(hexdump_decode("0010: 01\n0000: 02\n", &fmt, None), Err(HexError::Offset { line: 2, col: 1 }));
+        assert_eq!(hexdump_decode("01 02 03", &fmt, Some(2)), Err(HexError::Overflow { line: 1, col: 1 }));
+    }
+
+    #[test]
+    fn test_hexdump_c_api() {
+        unsafe {
+            let d = data(40);
+            let mut fmt = hexdump_fmt_t { line_len: 0, group: 0, offset_base: 0, offset_width: 0, ascii: 0 };
+            hexdump_fmt_init(&mut fmt);
+            fmt.group = 4;
+
+            let text = hexdump_sprint(d.as_ptr(), d.len(), &fmt);
+            let mut out = [0u8; 64];
+            let mut start = 1;
+            let mut err_line = 0;
+            assert_eq!(hexdump_decode_str(text, out.as_mut_ptr(), out.len(), &fmt, &mut start, &mut err_line), 40);
+            assert_eq!((&out[..40], start), (&d[..], 0));
+            assert_eq!(hexdump_decode_str(text, out.as_mut_ptr(), 20, &fmt, &mut start, &mut err_line), -1);
+            assert_eq!(err_line, 2);
+            libc::free(text.cast::<_>());
+        }
+    }
+}
+
+mod hwid {
+    use crate::cisco_eeprom::*;
+    use crate::dynamips_common::*;
+    use crate::hwid::*;
+    use crate::net::*;
+    use std::collections::BTreeSet;
+    use std::ptr::null_mut;
+
+    #[test]
+    fn test_hwid_alloc_mac() {
+        let seed = Some(hwid_seed_from_str("lab1"));
+        let mut a = HwidAllocator::new(seed);
+        let mut b = HwidAllocator::new(seed);
+
+        // deterministic, independent of the other instances
+        let mac = a.alloc_mac("R1", 1, 0xc2);
+        b.alloc_mac("R2", 2, 0xc2);
+        assert_eq!(b.alloc_mac("R1", 1, 0xc2), mac);
+        assert_eq!(a.alloc_mac("R1", 1, 0xc2), mac);
+        assert_eq!(mac.0[0], 0xc2);
+        assert_eq!(mac.0[4..], [0x00, 0x00]);
+
+        // another seed gives other addresses
+        let mut c = HwidAllocator::new(Some(hwid_seed_from_str("lab2")));
+        assert_ne!(c.alloc_mac("R1", 1, 0xc2), mac);
+
+        // no collision between the instances
+        let prefixes: BTreeSet<[u8; 4]> = (0..4096).map(|i| a.alloc_mac("R", i, 0xc2).0[..4].try_into().unwrap()).collect();
+        assert_eq!(prefixes.len(), 4096);
+
+        // a released block is reusable
+        a.release("R1", 1);
+        assert_eq!(a.alloc_mac("R1", 1, 0xc2), mac);
+
+        // legacy scheme without seed
+        let mut legacy = HwidAllocator::new(None);
+        let pid = (std::process::id() as u16).to_be_bytes();
+        assert_eq!(legacy.alloc_mac("R1", 5, 0xc0), EthAddr([0xc0, 0x05, pid[0], pid[1], 0x00, 0x00]));
+    }
+
+    #[test]
+    fn test_hwid_board_id() {
+        let a = HwidAllocator::new(Some(hwid_seed_from_str("lab1")));
+        assert!(HwidAllocator::new(None).board_id("R1", 1, 11).is_none());
+        assert!(a.board_id("R1", 1, 5).is_none());
+
+        for encode in [4, 9, 11] {
+            let id = a.board_id("R1", 1, encode).unwrap();
+            assert_eq!(a.board_id("R1", 1, encode), Some(id));
+            assert_ne!(a.board_id("R1", 2, encode), Some(id));
+            assert_eq!(id.encoded_len(), encode);
+            // must survive the string round trip done by the platforms
+            assert_eq!(BoardId::parse(&id.to_string(), encode), Ok(id));
+        }
+        assert!(a.board_id("R1", 1, 11).unwrap().to_string().starts_with("DYN"));
+    }
+
+    #[test]
+    fn test_hwid_burn_eeprom() {
+        // v4: chassis MAC (0xC3), block size (0x43)
+        let mut data: [m_uint16_t; 7] = [0x04FF, 0xC306, 0x0000, 0x0000, 0x0000, 0x4300, 0x10FF];
+        let mut eeprom = cisco_eeprom { name: null_mut(), data: data.as_mut_ptr(), len: data.len() };
+        let addr = n_eth_addr_t { eth_addr_byte: [0xc2, 0x01, 0x02, 0x03, 0x00, 0x00] };
+
+        unsafe {
+            assert_eq!(hwid_burn_eeprom(&mut eeprom, &addr, HWID_EEPROM_MAC_BLOCK_SIZE), 0);
+        }
+        assert_eq!(data, [0x04FF, 0xC306, 0xC201, 0x0203, 0x0000, 0x4300, 0x20FF]);
+
+        // unknown format
+        let mut data: [m_uint16_t; 2] = [0x07FF, 0xFFFF];
+        let mut eeprom = cisco_eeprom { name: null_mut(), data: data.as_mut_ptr(), len: data.len() };
+        unsafe {
+            assert_eq!(hwid_burn_eeprom(&mut eeprom, &addr, HWID_EEPROM_MAC_BLOCK_SIZE), -1);
+        }
+    }
+}
+
+mod mac_table {
+    use super::port;
+    use crate::mac_table::*;
+    use crate::net::*;
+    use std::ffi::c_void;
+
+    fn mac(n: u8) -> EthAddr {
+        EthAddr([0x00, 0x50, 0x79, 0x66, 0x68, n])
+    }
+
+    // Learning, moves and aging
+    #[test]
+    fn test_mac_table_learn_age() {
+        let mut t = MacTable::new(10);
+
+        assert_eq!(t.learn(&mac(1), 1, port(1), 0), MacLearn::Learned);
+        assert_eq!(t.learn(&mac(1), 1, port(1), 1000), MacLearn::Known);
+        assert_eq!(t.learn(&mac(1), 2, port(2), 1000), MacLearn::Learned);
+        assert_eq!(t.lookup(&mac(1), 1), Some(port(1)));
+        assert_eq!(t.lookup(&mac(1), 3), None);
+
+        // move
+        assert_eq!(t.learn(&mac(1), 1, port(3), 2000), MacLearn::Moved(port(1)));
+        assert_eq!(t.lookup(&mac(1), 1), Some(port(3)));
+        assert_eq!(t.moves(), 1);
+        assert_eq!(t.port_security(port(1)).count, 0);
+        assert_eq!(t.port_security(port(3)).count, 1);
+
+        // aging: the last time seen counts
+        assert!(t.age(10_999).is_empty());
+        assert_eq!(t.age(11_000).iter().map(|e| e.vlan_id).collect::<Vec<_>>(), [2]);
+        let aged = t.age(12_000);
+        assert_eq!(aged.len(), 1);
+        assert_eq!((aged[0].vlan_id, aged[0].port, aged[0].age), (1, port(3), 10));
+        assert_eq!(EthAddr::from(aged[0].mac_addr), mac(1));
+        assert!(t.is_empty());
+
+        // aging disabled
+        t.learn(&mac(1), 1, port(1), 12_000);
+        t.set_aging(0);
+        assert!(t.age(1_000_000).is_empty());
+        assert_eq!(t.len(), 1);
+    }
+
+    // Static entries
+    #[test]
+    fn test_mac_table_static() {
+        let mut t = MacTable::new(10);
+
+        t.learn(&mac(1), 1, port(1), 0);
+        t.add_static(&mac(1), 1, port(2), 0);
+        assert_eq!(t.get(&mac(1), 1, 0).map(|e| (e.port, e.r#type)), Some((port(2), MAC_ENTRY_STATIC)));
+        assert_eq!(t.port_security(port(1)).count, 0);
+
+        // no move, no aging, kept by a flush of the dynamic entries
+        assert_eq!(t.learn(&mac(1), 1, port(3), 0), MacLearn::Conflict);
+        assert_eq!(MacLearn::Conflict.code(), MAC_LEARN_DROP);
+        assert!(t.age(100_000).is_empty());
+        t.learn(&mac(2), 1, port(2), 0);
+        t.flush(false);
+        assert_eq!(t.len(), 1);
+        assert_eq!(t.port_security(port(2)).count, 1);
+
+        assert!(t.remove(&mac(1), 1));
+        assert!(!t.remove(&mac(1), 1));
+        assert!(t.is_empty());
+        assert_eq!(t.port_security(port(2)).count, 0);
+
+        // restored sticky address
+        t.add_sticky(&mac(3), 1, port(2), 0);
+        assert_eq!(t.get(&mac(3), 1, 0).map(|e| (e.port, e.r#type)), Some((port(2), MAC_ENTRY_STICKY)));
+        assert!(t.age(100_000).is_empty());
+        assert_eq!(t.port_security(port(2)).count, 1);
+    }
+
+    // Port security: limit, actions, sticky addresses, recovery
+    #[test]
+    fn test_mac_table_port_security() {
+        let mut t = MacTable::new(10);
+
+        assert!(!t.set_port_security(port(1), 1, 42, false));
+        assert!(t.set_port_security(port(1), 1, MAC_VIOLATION_PROTECT, false));
+        assert_eq!(t.learn(&mac(1), 1, port(1), 0), MacLearn::Learned);
+        assert_eq!(t.learn(&mac(2), 1, port(1), 0), MacLearn::Protect);
+        assert_eq!(t.port_security(port(1)).violations, 0);
+
+        t.set_port_security(port(1), 1, MAC_VIOLATION_RESTRICT, false);
+        assert_eq!(t.learn(&mac(2), 1, port(1), 0), MacLearn::Restrict);
+        assert_eq!(t.port_security(port(1)).violations, 1);
+        assert_eq!(t.lookup(&mac(2), 1), None);
+
+        // a move into a full port is a violation too
+        t.learn(&mac(3), 1, port(2), 0);
+        assert_eq!(t.learn(&mac(3), 1, port(1), 0), MacLearn::Restrict);
+        assert_eq!(t.lookup(&mac(3), 1), Some(port(2)));
+
+        // shutdown: dynamic addresses flushed, everything dropped until recovery
+        t.set_port_security(port(1), 1, MAC_VIOLATION_SHUTDOWN, false);
+        assert_eq!(t.learn(&mac(2), 1, port(1), 0), MacLearn::Shutdown);
+        assert!(t.port_is_shutdown(port(1)));
+        assert_eq!(t.lookup(&mac(1), 1), None);
+        assert_eq!(t.learn(&mac(1), 1, port(1), 0), MacLearn::Disabled);
+        assert!(t.port_recover(port(1)));
+        assert!(!t.port_recover(port(1)));
+        assert_eq!(t.learn(&mac(1), 1, port(1), 0), MacLearn::Learned);
+
+        // sticky: existing and new addresses don't age
+        t.set_port_security(port(1), 2, MAC_VIOLATION_SHUTDOWN, true);
+        t.learn(&mac(2), 1, port(1), 0);
+        assert!(t.entries(0).iter().filter(|e| e.port == port(1)).all(|e| e.r#type == MAC_ENTRY_STICKY));
+        assert_eq!(t.age(100_000).len(), 1); // mac(3) on port 2
+        assert_eq!(t.port_security(port(1)).count, 2);
+        t.set_port_security(port(1), 2, MAC_VIOLATION_SHUTDOWN, false);
+        assert_eq!(t.age(100_000).len(), 2);
+
+        t.learn(&mac(1), 1, port(1), 0);
+        t.remove_port(port(1));
+        assert!(t.is_empty());
+        assert_eq!(t.port_security(port(1)).max_macs, 0);
+    }
+}
+
+mod metrics {
+    use crate::mempool::*;
+    use crate::metrics::*;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    // Text exposition format
+    #[test]
+    fn test_metrics_render() {
+        let mut m = Metrics::new();
+        assert!(m.is_empty());
+        assert!(m.add(METRICS_COUNTER, "nio_bytes_total", "Bytes.\\n", &[("nio", "a\"b\\c\nd"), ("dir", "in")], 10.0));
+        assert!(m.add(METRICS_GAUGE, "up", "Up.", &[], 1.0));
+        assert!(m.add(METRICS_COUNTER, "nio_bytes_total", "Bytes.", &[("nio", "e"), ("dir", "in")], 0.5));
+        assert!(m.add(METRICS_COUNTER, "nio_bytes_total", "", &[("nio", "e"), ("dir", "in")], 2.0));
+        assert!(m.add(METRICS_GAUGE, "temp", "", &[], f64::NAN));
+        assert!(m.add(METRICS_GAUGE, "temp2", "", &[], f64::NEG_INFINITY));
+
+        // rejected samples
+        assert!(!m.add(METRICS_GAUGE, "nio_bytes_total", "", &[], 1.0));
+        assert!(!m.add(METRICS_GAUGE, "0up", "", &[], 1.0));
+        assert!(!m.add(METRICS_GAUGE, "up-time", "", &[], 1.0));
+        assert!(!m.add(METRICS_GAUGE, "up", "", &[("a:b", "x")], 1.0));
+        assert!(!m.add(METRICS_GAUGE, "up", "", &[("__name__", "x")], 1.0));
+        assert!(!m.add(7, "up", "", &[], 1.0));
+        assert_eq!(m.len(), 5);
+
+        let expected = "\
+# HELP nio_bytes_total Bytes.\\\\n
+# TYPE nio_bytes_total counter
+nio_bytes_total{nio=\"a\\\"b\\\\c\\nd\",dir=\"in\"} 10
+nio_bytes_total{nio=\"e\",dir=\"in\"} 2.5
+# HELP up Up.
+# TYPE up gauge
+up 1
+# HELP temp 
+# TYPE temp gauge
+temp NaN
+# HELP temp2 
+# TYPE temp2 gauge
+temp2 -Inf
+";
+        assert_eq!(m.render(), expected);
+    }
+
+    // HTTP requests, served from the exporter thread
+    #[test]
+    fn test_metrics_server() {
+        let scrape = || "x 1\n".to_string();
+        let r = metrics_http_response("GET /metrics?x=1 HTTP/1.1\r\nHost: a\r\n\r\n", scrape);
+        assert!(r.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4"));
+        assert!(r.contains("Content-Length: 4\r\n") && r.ends_with("\r\n\r\nx 1\n"));
+        let r = metrics_http_response("HEAD /metrics HTTP/1.0\r\n\r\n", scrape);
+        assert!(r.contains("Content-Length: 4\r\n") && r.ends_with("\r\n\r\n"));
+        assert!(metrics_http_response("GET / HTTP/1.1\r\n\r\n", scrape).starts_with("HTTP/1.1 404 "));
+        assert!(metrics_http_response("POST /metrics HTTP/1.1\r\n\r\n", scrape).contains("405 Method Not Allowed\r\nAllow: GET, HEAD\r\n"));
+        assert!(metrics_http_response("GET /metrics\r\n\r\n", scrape).starts_with("HTTP/1.1 400 "));
+
+        let mut pool: mempool_t = unsafe { std::mem::zeroed() };
+        unsafe { mp_create_fixed_pool(&mut pool, c"metrics test".as_ptr().cast_mut()) };
+        unsafe { mp_alloc(&mut pool, 100) };
+
+        let server = MetricsServer::start(Some(c"127.0.0.1"), 0, |m| {
+            m.add(METRICS_COUNTER, "dynamips_test_total", "Test.", &[("obj", "R1")], 42.0);
+        })
+        .unwrap();
+        assert!(server.port() > 0);
+
+        let get = |request: &str| {
+            let mut stream = TcpStream::connect(("127.0.0.1", server.port() as u16)).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let r = get("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(r.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(r.contains("# TYPE dynamips_test_total counter\ndynamips_test_total{obj=\"R1\"} 42\n"));
+        assert!(r.contains("dynamips_mempool_blocks{pool=\"metrics test\"} 1\n"));
+        assert!(r.contains("dynamips_mempool_bytes{pool=\"metrics test\"} 100\n"));
+        assert!(get("GET /nothing HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404 "));
+        assert_eq!(server.scrapes(), 2);
+        drop(server);
+
+        unsafe { mp_free_pool(&mut pool) };
+        assert!(!mp_pool_stats().iter().any(|(name, _, _)| name == "metrics test"));
+    }
+}
+
+mod net {
+    use super::rng;
+    use crate::_extra::*;
+    use crate::dynamips_common::*;
+    use crate::net::*;
+    use crate::utils::*;
+    use std::ffi::c_int;
+    use std::ffi::c_uint;
+
+    // Partial checksum test
+    #[test]
+    fn test_ip_cksum_partial() {
+        unsafe {
+            const N_BUF: usize = 4;
+            let mut buffer: [[m_uint8_t; 512]; N_BUF] = [[0; 512]; N_BUF];
+            let mut psum: [m_uint16_t; N_BUF] = [0; N_BUF];
+            let mut tmp: m_uint32_t;
+            let mut sum: m_uint32_t;
+            let gsum: m_uint32_t;
+
+            for i in 0..N_BUF {
+                m_randomize_block(buffer[i].as_mut_ptr(), size_of_val(&buffer[i]));
+                if false {
+                    mem_dump(c_stdout(), buffer[i].as_mut_ptr(), size_of_val(&buffer[i]) as u_int);
+                }
+
+                sum = ip_cksum_partial(buffer[i].as_mut_ptr(), size_of_val(&buffer[i]) as c_int);
+
+                while (sum >> 16) != 0 {
+                    sum = (sum & 0xFFFF) + (sum >> 16);
+                }
+
+                psum[i] = (!sum) as m_uint16_t;
+            }
+
+            // partial sums + accumulator
+            tmp = 0;
+            for i in 0..N_BUF {
+                if false {
+                    libc::printf(c"psum[%d] = 0x%4.4x\n".as_ptr(), i, psum[i] as c_uint);
+                }
+                tmp += !psum[i] as m_uint16_t as m_uint32_t;
+            }
+
+            // global sum
+            sum = ip_cksum_partial(buffer.as_mut_ptr().cast::<m_uint8_t>(), size_of_val(&buffer) as c_int);
+
+            while (sum >> 16) != 0 {
+                sum = (sum & 0xFFFF) + (sum >> 16);
+            }
+
+            gsum = sum;
+
+            // accumulator
+            while (tmp >> 16) != 0 {
+                tmp = (tmp & 0xFFFF) + (tmp >> 16);
+            }
+
+            if false {
+                libc::printf(c"gsum = 0x%4.4x, tmp = 0x%4.4x : %s\n".as_ptr(), gsum, tmp, if gsum == tmp { c"OK".as_ptr() } else { c"FAILURE".as_ptr() });
+            }
+
+            assert_eq!(tmp, gsum);
+        }
+    }
+    // Socket layer: port range allocation, port in use, resolution failure
+    #[test]
+    fn test_sock_listen_range() {
+        unsafe {
+            let opts = SockOpts::default();
+            let (fd, port) = sock_listen_range(c"127.0.0.1".as_ptr(), 20000, 20100, libc::SOCK_DGRAM, &opts).unwrap();
+            assert!((20000..=20100).contains(&port));
+
+            let err = sock_listen_range(c"127.0.0.1".as_ptr(), port, port, libc::SOCK_DGRAM, &opts).unwrap_err();
+            assert_eq!(err.code(), NET_ERR_PORT_RANGE);
+            assert!(err.to_string().contains(&format!("{}-{}", port, port)));
+
+            let err = sock_listen(c"127.0.0.1".as_ptr(), port, libc::SOCK_DGRAM, 1, &opts).unwrap_err();
+            assert_eq!(err.code(), NET_ERR_ADDR_IN_USE);
+
+            let err = sock_connect_fd(fd, c"host.invalid".as_ptr(), port).unwrap_err();
+            assert_eq!(err.code(), NET_ERR_RESOLVE);
+            libc::close(fd);
+        }
+    }
+
+    // Socket layer: two sockets bound to the same port with SO_REUSEPORT
+    #[test]
+    fn test_sock_reuse_port() {
+        unsafe {
+            let opts = SockOpts { reuse_port: true, ..Default::default() };
+            let (fd, port) = sock_listen_range(c"127.0.0.1".as_ptr(), 20200, 20300, libc::SOCK_DGRAM, &opts).unwrap();
+            let fds = sock_listen(c"127.0.0.1".as_ptr(), port, libc::SOCK_DGRAM, 1, &opts).unwrap();
+            assert_eq!(fds.len(), 1);
+
+            let err = sock_listen(c"127.0.0.1".as_ptr(), port, libc::SOCK_DGRAM, 1, &SockOpts::default()).unwrap_err();
+            assert_eq!(err.code(), NET_ERR_ADDR_IN_USE);
+
+            libc::close(fds[0]);
+            libc::close(fd);
+        }
+    }
+
+    // Socket layer: UDP socket bound to a local address
+    #[test]
+    fn test_sock_udp_connect() {
+        unsafe {
+            let opts = SockOpts { non_block: true, ..Default::default() };
+            let fd = sock_udp_connect(c"127.0.0.1".as_ptr(), 0, c"127.0.0.1".as_ptr(), 9, &opts).unwrap();
+            let mut sin: libc::sockaddr_in = std::mem::zeroed();
+            let mut len: libc::socklen_t = size_of::<libc::sockaddr_in>() as _;
+            assert_eq!(libc::getsockname(fd, std::ptr::addr_of_mut!(sin).cast::<_>(), &mut len), 0);
+            assert_eq!(u32::from_be(sin.sin_addr.s_addr), 0x7f000001);
+            assert_ne!(libc::fcntl(fd, libc::F_GETFL) & libc::O_NONBLOCK, 0);
+            libc::close(fd);
+
+            assert_eq!(udp_connect(0, c"host.invalid".as_ptr().cast_mut(), 9), -1);
+            assert_eq!(net_last_error(), NET_ERR_RESOLVE);
+            net_clear_error();
+            assert_eq!(net_last_error(), NET_ERR_NONE);
+            assert_eq!(std::ffi::CStr::from_ptr(net_last_error_str()).to_bytes(), b"");
+        }
+    }
+
+    #[test]
+    fn test_eth_addr_round_trip() {
+        let mut next = rng(0x5eed_0001);
+        for _ in 0..1000 {
+            let mac = EthAddr(next().to_be_bytes()[2..].try_into().unwrap());
+            for format in [EthAddrFormat::Colon, EthAddrFormat::Cisco, EthAddrFormat::Hyphen] {
+                assert_eq!(mac.format(format).parse::<EthAddr>(), Ok(mac));
+                assert_eq!(mac.format(format).to_uppercase().parse::<EthAddr>(), Ok(mac));
+            }
+            assert_eq!(EthAddr::from(n_eth_addr_t::from(mac)), mac);
+        }
+        assert_eq!("0:1:2:a:b:c".parse::<EthAddr>(), Ok(EthAddr([0, 1, 2, 0xa, 0xb, 0xc])));
+        assert_eq!("1.2.3".parse::<EthAddr>(), Ok(EthAddr([0, 1, 0, 2, 0, 3])));
+        for bad in ["", "00:01:02:03:04", "00:01:02:03:04:05:06", "000:01:02:03:04:05", "00:01-02:03:04:05", "0001.0203", "00g1.0203.0405"] {
+            assert!(bad.parse::<EthAddr>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_ip_net_round_trip() {
+        let mut next = rng(0x5eed_0002);
+        for _ in 0..1000 {
+            let v4 = Ipv4Net::new(std::net::Ipv4Addr::from(next() as u32), (next() % 33) as u8).unwrap();
+            assert_eq!(v4.to_string().parse::<Ipv4Net>(), Ok(v4));
+            let net = n_ip_network_t::from(v4);
+            assert_eq!(unsafe { ip_bits_mask(net.net_mask) }, v4.prefix_len() as c_int);
+            assert_eq!(Ipv4Net::try_from(net), Ok(v4));
+
+            let v6 = Ipv6Net::new(std::net::Ipv6Addr::from(((next() as u128) << 64) | next() as u128), (next() % 129) as u8).unwrap();
+            assert_eq!(v6.to_string().parse::<Ipv6Net>(), Ok(v6));
+            assert_eq!(Ipv6Net::try_from(n_ipv6_network_t::from(v6)), Ok(v6));
+        }
+        assert_eq!("10.1.2.3/8".parse::<Ipv4Net>().unwrap().network(), std::net::Ipv4Addr::new(10, 0, 0, 0));
+        assert!("10.0.0.0/33".parse::<Ipv4Net>().is_err());
+        assert!("::/129".parse::<Ipv6Net>().is_err());
+        assert!(Ipv4Net::try_from(n_ip_network_t { net_addr: 0, net_mask: 0xFF00FF00 }).is_err());
+
+        // C API
+        unsafe {
+            let (mut addr, mut mask): (n_ip_addr_t, n_ip_addr_t) = (0, 0);
+            assert_eq!(ip_parse_cidr(c"10.1.2.3/24".as_ptr().cast_mut(), &mut addr, &mut mask), 0);
+            assert_eq!((addr, mask), (0x0a010203, 0xffffff00));
+            assert_eq!(ip_parse_cidr(c"10.1.2.3".as_ptr().cast_mut(), &mut addr, &mut mask), -1);
+
+            let mut mac = n_eth_addr_t { eth_addr_byte: [0x00, 0x01, 0x0a, 0xbc, 0xde, 0xff] };
+            let mut buffer: [std::ffi::c_char; N_ETH_SLEN] = [0x7f; N_ETH_SLEN];
+            assert_eq!(std::ffi::CStr::from_ptr(n_eth_ntoa(buffer.as_mut_ptr(), &mut mac, 0)).to_bytes(), b"00:01:0a:bc:de:ff");
+            assert_eq!(std::ffi::CStr::from_ptr(n_eth_ntoa(buffer.as_mut_ptr(), &mut mac, 1)).to_bytes(), b"0001.0abc.deff");
+        }
+    }
+
+    #[test]
+    fn test_board_id_round_trip() {
+        unsafe {
+            let mut next = rng(0x5eed_0003);
+            let mut chars = || -> [u8; 11] { std::array::from_fn(|_| b'!' + (next() % 94) as u8) };
+            for _ in 0..1000 {
+                let text = BoardId::Text(chars());
+                assert_eq!(text.to_string().parse::<BoardId>(), Ok(text));
+
+                let c = chars();
+                let packed = BoardId::Packed([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7], c[8]]);
+                assert_eq!(BoardId::parse(&packed.to_string(), 9), Ok(packed));
+                assert_eq!(BoardId::decode(&packed.encode(), 9), Some(packed));
+
+                let numeric = BoardId::Numeric(u32::from_le_bytes(c[..4].try_into().unwrap()));
+                assert_eq!(numeric.to_string().parse::<BoardId>(), Ok(numeric));
+                assert_eq!(BoardId::decode(&numeric.encode(), 4), Some(numeric));
+            }
+
+            // the hex bytes of the 9 bytes encoding no longer depend on the host endianness
+            let mut buf: [m_uint8_t; 11] = [0xff; 11];
+            assert_eq!(parse_board_id(buf.as_mut_ptr(), c"FOC12abWXYZ".as_ptr(), 9), 0);
+            assert_eq!(buf, *b"FOC\x12\xabWXYZ\0\0");
+            assert_eq!(BoardId::Packed(buf[..9].try_into().unwrap()).to_string(), "FOC12abWXYZ");
+            assert_eq!(parse_board_id(buf.as_mut_ptr(), c"4279256517".as_ptr(), 4), 0);
+            assert_eq!(buf[..4], [0xff, 0x10, 0x45, 0xc5]);
+            assert_eq!(parse_board_id(buf.as_mut_ptr(), c"FOC".as_ptr(), 11), -1);
+
+            // an all-digit text id keeps its leading zeros
+            let text = "00012345678".parse::<BoardId>().unwrap();
+            assert_eq!(text, BoardId::Text(*b"00012345678"));
+            assert_eq!(text.to_string(), "00012345678");
+            assert!("+1234".parse::<BoardId>().is_err());
+        }
+    }
+}
+
+mod netem {
+    use crate::netem::*;
+
+    fn netem(args: &[&str]) -> Netem {
+        let mut n = Netem::new(0);
+        n.setup(args).unwrap();
+        n
+    }
+
+    // Setup arguments
+    #[test]
+    fn test_netem_setup() {
+        let mut n = netem(&["delay", "100", "20", "normal", "loss", "1.5%"]);
+        let c = n.config().clone();
+        assert_eq!((c.delay, c.jitter, c.dist), (100, 20, NETEM_DIST_NORMAL));
+        assert_eq!(c.loss, NetemLoss::Random(0.015));
+
+        // only the specified parameters change, errors leave the configuration untouched
+        n.setup(&["loss", "gemodel", "1", "20"]).unwrap();
+        assert_eq!(n.config().loss, NetemLoss::GilbertElliott { p: 0.01, r: 0.2, loss_bad: 1.0, loss_good: 0.0 });
+        assert_eq!(n.config().delay, 100);
+        assert!(n.setup(&["duplicate", "101"]).is_err());
+        assert!(n.setup(&["corrupt"]).is_err());
+        assert!(n.setup(&["bogus"]).is_err());
+        assert!(n.setup(&["delay", "0", "reorder", "10"]).is_err());
+        assert_eq!(n.config().delay, 100);
+
+        n.setup(&["reset"]).unwrap();
+        assert_eq!(*n.config(), NetemConfig::default());
+    }
+
+    // Random and bursty losses, reproducibility
+    #[test]
+    fn test_netem_loss() {
+        let run = |args: &[&str], seed: &str| {
+            let mut n = netem(args);
+            n.setup(&["seed", seed]).unwrap();
+            (0..10000).map(|_| n.handle(&mut [0u8; 64], 0) == NetemVerdict::Drop).collect::<Vec<bool>>()
+        };
+
+        let lost = run(&["loss", "10"], "1");
+        let count = lost.iter().filter(|&&x| x).count();
+        assert!((800..1200).contains(&count), "{} lost", count);
+        assert_eq!(run(&["loss", "10"], "1"), lost);
+        assert_ne!(run(&["loss", "10"], "2"), lost);
+
+        // same average loss, but in bursts: 1% to bad, 9% back to good
+        let lost = run(&["loss", "gemodel", "1", "9"], "1");
+        let count = lost.iter().filter(|&&x| x).count();
+        let bursts = lost.windows(2).filter(|w| !w[0] && w[1]).count();
+        assert!((500..1500).contains(&count), "{} lost", count);
+        assert!(bursts * 5 < count, "{} bursts for {} lost", bursts, count);
+    }
+
+    // Delay, jitter and reordering
+    #[test]
+    fn test_netem_delay() {
+        let mut n = netem(&["delay", "50"]);
+        assert_eq!(n.handle(&mut [1], 1000), NetemVerdict::Delayed);
+        assert_eq!(n.handle(&mut [2], 1010), NetemVerdict::Delayed);
+        assert!(n.take_due(1049).is_empty());
+        assert_eq!(n.take_due(1050), [vec![1]]);
+        assert_eq!(n.take_due(2000), [vec![2]]);
+        assert_eq!(NetemVerdict::Delayed.code(), NETEM_ACTION_QUEUED);
+
+        // the delays stay in [delay - jitter, delay + jitter]
+        let mut n = netem(&["delay", "50", "10", "seed", "3"]);
+        for i in 0..100 {
+            n.handle(&mut [i], 0);
+        }
+        assert!(n.take_due(39).is_empty());
+        let due = n.take_due(60);
+        assert_eq!(due.len(), 100);
+        assert!(due.windows(2).any(|w| w[0][0] > w[1][0]), "no reordering");
+
+        // reorder: some packets are not delayed
+        let mut n = netem(&["delay", "10", "reorder", "25", "seed", "4"]);
+        let passed = (0..1000).filter(|_| n.handle(&mut [0], 0) == NetemVerdict::Pass).count();
+        assert!((200..300).contains(&passed), "{} passed", passed);
+        assert_eq!(n.queued(), 1000 - passed);
+        assert_eq!(n.stats().reordered as usize, passed);
+
+        // limit
+        let mut n = netem(&["delay", "10", "limit", "2"]);
+        let verdicts: Vec<NetemVerdict> = (0..3).map(|_| n.handle(&mut [0], 0)).collect();
+        assert_eq!(verdicts, [NetemVerdict::Delayed, NetemVerdict::Delayed, NetemVerdict::Drop]);
+        assert_eq!(n.stats().overlimit, 1);
+    }
+
+    // Duplication and corruption
+    #[test]
+    fn test_netem_dup_corrupt() {
+        let mut n = netem(&["duplicate", "100"]);
+        assert_eq!(n.handle(&mut [0], 0), NetemVerdict::Duplicate);
+
+        let mut n = netem(&["corrupt", "100"]);
+        let mut pkt = [0u8; 32];
+        assert_eq!(n.handle(&mut pkt, 0), NetemVerdict::Alter);
+        assert_eq!(pkt.iter().map(|b| b.count_ones()).sum::<u32>(), 1);
+
+        // delayed duplicates are both queued
+        let mut n = netem(&["delay", "5", "duplicate", "100"]);
+        assert_eq!(n.handle(&mut [7], 0), NetemVerdict::Delayed);
+        assert_eq!(n.take_due(5), [vec![7], vec![7]]);
+    }
+}
+
+mod pkt_filter {
+    use crate::netem::*;
+    use crate::pkt_filter::*;
+
+    // Recompute the checksum of the IP header at offset l3
+    fn ip_cksum(pkt: &mut [u8], l3: usize) {
+        pkt[l3 + 10..l3 + 12].fill(0);
+        let sum: u32 = pkt[l3..l3 + 20].chunks(2).map(|w| u16::from_be_bytes([w[0], w[1]]) as u32).sum();
+        let sum = (sum & 0xffff) + (sum >> 16);
+        pkt[l3 + 10..l3 + 12].copy_from_slice(&(!(sum as u16)).to_be_bytes());
+    }
+
+    // Ethernet frame with an optional 802.1Q tag, IPv4 header and 20 bytes of L4 header
+    fn frame(vlan: Option<u16>, proto: u8, src: [u8; 4], dst: [u8; 4], sport: u16, dport: u16, tcp_flags: u8) -> Vec<u8> {
+        let mut pkt = vec![0xff; 6];
+        pkt.extend([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        if let Some(vlan) = vlan {
+            pkt.extend([0x81, 0x00]);
+            pkt.extend(vlan.to_be_bytes());
+        }
+        pkt.extend([0x08, 0x00]);
+
+        let l3 = pkt.len();
+        pkt.extend([0x45, 0, 0, 40, 0, 1, 0, 0, 64, proto, 0, 0]);
+        pkt.extend(src);
+        pkt.extend(dst);
+
+        let mut l4 = [0u8; 20];
+        l4[0..2].copy_from_slice(&sport.to_be_bytes());
+        l4[2..4].copy_from_slice(&dport.to_be_bytes());
+        l4[13] = tcp_flags;
+        pkt.extend(l4);
+        ip_cksum(&mut pkt, l3);
+        pkt
+    }
+
+    fn matches(expr: &str, pkt: &[u8]) -> bool {
+        PktFilter::compile(expr).unwrap().matches_frame(pkt)
+    }
+
+    // Syntax and errors
+    #[test]
+    fn test_pkt_filter_compile() {
+        use PktFilter::*;
+        let test = |t: PktTest| Box::new(Test(t));
+
+        // "and" binds tighter than "or", "not" tighter than "and"
+        assert_eq!(PktFilter::compile("arp or !vlan && ospf").unwrap(), Or(test(PktTest::EtherType(0x0806)), Box::new(And(Box::new(Not(test(PktTest::Vlan(None)))), test(PktTest::IpProto(89))))));
+        assert_eq!(PktFilter::compile("(tcp src port 179)").unwrap(), Test(PktTest::Port { dir: PktDir::Src, proto: Some(6), lo: 179, hi: 179 }));
+        assert_eq!(PktFilter::compile("dst net 10.1.2.3/16").unwrap(), Test(PktTest::Net { dir: PktDir::Dst, addr: 0x0a01_0000, mask: 0xffff_0000 }));
+        assert_eq!(PktFilter::compile("tcpflags syn/syn+ack").unwrap(), Test(PktTest::TcpFlags { value: 0x02, mask: 0x12 }));
+
+        for bad in ["", "tcp and", "(udp", "udp)", "port", "host 1.2.3", "net 10.0.0.0/33", "portrange 1", "tcpflags foo", "bogus", "vlan 10 20"] {
+            assert!(PktFilter::compile(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    // Matching on packets
+    #[test]
+    fn test_pkt_filter_match() {
+        let bgp = frame(Some(10), 6, [10, 0, 0, 1], [192, 168, 1, 2], 30000, 179, 0x02);
+        let dns = frame(None, 17, [192, 168, 1, 2], [8, 8, 8, 8], 5353, 53, 0);
+
+        assert!(matches("vlan 10 and ip and tcp", &bgp));
+        assert!(!matches("vlan 20", &bgp));
+        assert!(matches("not vlan and udp", &dns));
+        assert!(matches("ether proto 0x800", &dns));
+        assert!(matches("src net 10.0.0.0/8 and dst host 192.168.1.2", &bgp));
+        assert!(!matches("dst net 10.0.0.0/8", &bgp));
+        assert!(matches("host 192.168.1.2", &bgp) && matches("host 192.168.1.2", &dns));
+        assert!(matches("tcp dst port 179 and src portrange 1024-65535", &bgp));
+        assert!(!matches("udp port 179", &bgp));
+        assert!(matches("port 53 and proto udp", &dns));
+        assert!(matches("tcpflags syn/syn+ack", &bgp));
+        assert!(!matches("tcpflags ack", &bgp));
+        assert!(!matches("tcpflags syn", &dns));
+        assert!(matches("greater 58 and less 58", &bgp));
+
+        // bad checksum: no IP fields, runts match nothing but their length
+        let mut bad = dns.clone();
+        bad[24] ^= 1;
+        assert!(matches("ip", &bad) && !matches("udp", &bad));
+        assert!(!matches("ip or arp or vlan", &dns[..10]));
+        assert!(matches("less 10", &dns[..10]));
+
+        // non first fragments have no ports
+        let mut frag = dns.clone();
+        frag[20..22].copy_from_slice(&[0x00, 0x10]);
+        ip_cksum(&mut frag, 14);
+        assert!(matches("udp and host 8.8.8.8", &frag));
+        assert!(!matches("port 53", &frag));
+    }
+
+    // Impairment of the matching packets only
+    #[test]
+    fn test_pkt_filter_netem() {
+        let bgp = frame(None, 6, [10, 0, 0, 1], [10, 0, 0, 2], 30000, 179, 0x10);
+        let dns = frame(None, 17, [10, 0, 0, 1], [10, 0, 0, 2], 5353, 53, 0);
+
+        let mut n = Netem::new(0);
+        n.setup(&["loss", "100", "match", "tcp", "port", "179"]).unwrap();
+        assert_eq!(n.handle(&mut bgp.clone(), 0), NetemVerdict::Drop);
+        assert_eq!(n.handle(&mut dns.clone(), 0), NetemVerdict::Pass);
+        assert_eq!((n.stats().pkts, n.stats().lost), (1, 1));
+
+        assert!(n.setup(&["match", "tcp", "port"]).is_err());
+        n.setup(&["reset"]).unwrap();
+        assert_eq!(n.config().filter, None);
+    }
+}
+
+mod rstp {
+    use crate::net::*;
+    use crate::rstp::*;
+    use std::ffi::c_int;
+    use std::ffi::c_void;
+
+    fn handle(b: usize, port_no: u16) -> *mut c_void {
+        (b * 0x100 + port_no as usize) as *mut c_void
+    }
+
+    // Bridges linked point-to-point, BPDUs delivered at once
+    struct Lan {
+        bridges: Vec<Rstp>,
+        links: Vec<[(usize, u16); 2]>,
+    }
+
+    impl Lan {
+        fn new(priorities: &[u16], links: &[[(usize, u16); 2]]) -> Self {
+            let mut lan = Lan { bridges: Vec::new(), links: links.to_vec() };
+            for (b, &priority) in priorities.iter().enumerate() {
+                let mut bridge = Rstp::new(EthAddr([0x02, 0, 0, 0, 0, b as u8 + 1]));
+                bridge.set_priority(priority);
+                for &(lb, port_no) in links.iter().flatten() {
+                    if lb == b {
+                        bridge.add_port(handle(b, port_no), port_no);
+                    }
+                }
+                bridge.set_enabled(true);
+                lan.bridges.push(bridge);
+            }
+            lan
+        }
+
+        fn unlink(&mut self, link: usize) {
+            for (b, port_no) in self.links.remove(link) {
+                self.bridges[b].remove_port(handle(b, port_no));
+            }
+        }
+
+        fn deliver(&mut self) {
+            for _ in 0..100 {
+                let mut frames = Vec::new();
+                for (b, bridge) in self.bridges.iter_mut().enumerate() {
+                    for out in bridge.take_output() {
+                        if let RstpOutput::Tx(port, frame) = out {
+                            let peer = self.links.iter().find_map(|l| match l {
+                                [x, y] | [y, x] if handle(x.0, x.1) == port && x.0 == b => Some(*y),
+                                _ => None,
+                            });
+                            frames.extend(peer.map(|peer| (peer, frame)));
+                        }
+                    }
+                }
+                if frames.is_empty() {
+                    return;
+                }
+                for ((b, port_no), frame) in frames {
+                    assert!(self.bridges[b].receive(handle(b, port_no), &frame));
+                }
+            }
+            panic!("BPDU storm");
+        }
+
+        fn run(&mut self, ticks: usize) {
+            self.deliver();
+            for _ in 0..ticks {
+                for bridge in &mut self.bridges {
+                    bridge.tick();
+                }
+                self.deliver();
+            }
+        }
 
-        unsafe {
-            let ht = hash_table_create(Some(x_hash), Some(x_equal), HASH_SIZE);
-            assert!(!ht.is_null());
-            hash_table_delete(ht);
+        fn port(&self, b: usize, port_no: u16) -> (c_int, c_int) {
+            let info = self.bridges[b].port_info(handle(b, port_no)).unwrap();
+            (info.role, info.state)
+        }
+    }
 
-            let ht = hash_string_create!(HASH_SIZE);
-            assert!(!ht.is_null());
-            hash_table_delete(ht);
+    const ROOT: (c_int, c_int) = (RSTP_ROLE_ROOT, RSTP_STATE_FORWARDING);
+    const DESIGNATED: (c_int, c_int) = (RSTP_ROLE_DESIGNATED, RSTP_STATE_FORWARDING);
+    const ALTERNATE: (c_int, c_int) = (RSTP_ROLE_ALTERNATE, RSTP_STATE_DISCARDING);
 
-            let ht = hash_int_create!(HASH_SIZE);
-            assert!(!ht.is_null());
-            hash_table_delete(ht);
+    // Encoding and decoding of the BPDUs
+    #[test]
+    fn test_rstp_bpdu() {
+        let saddr = EthAddr([0x02, 0, 0, 0, 0, 1]);
+        let vector = PriorityVector { root_id: 0x1000_0200_0000_0001, root_cost: 19, bridge_id: 0x8000_0200_0000_0002, port_id: 0x8003 };
+        let times = RstpTimes { msg_age: 1, max_age: 20, hello: 2, fwd_delay: 15 };
 
-            let ht = hash_u64_create!(HASH_SIZE);
-            assert!(!ht.is_null());
-            hash_table_delete(ht);
+        let rst = Bpdu::Config { rst: true, flags: 0x3e, vector, times };
+        let frame = rst.build(&saddr);
+        assert_eq!(frame.len(), N_ETH_MIN_FRAME_LEN);
+        assert_eq!(frame[..14], [0x01, 0x80, 0xc2, 0, 0, 0, 0x02, 0, 0, 0, 0, 1, 0, 39]);
+        assert_eq!(frame[14..21], [0x42, 0x42, 0x03, 0, 0, 2, 2]);
+        assert_eq!(frame[21..30], [0x3e, 0x10, 0x00, 0x02, 0, 0, 0, 0, 1]);
+        assert_eq!(frame[44..53], [0x01, 0x00, 0x14, 0x00, 0x02, 0x00, 0x0f, 0x00, 0x00]);
+        assert_eq!(Bpdu::parse(&frame), Some(rst));
 
-            let ht = hash_ptr_create!(HASH_SIZE);
-            assert!(!ht.is_null());
-            hash_table_delete(ht);
-        }
+        let config = Bpdu::Config { rst: false, flags: 0x81, vector, times };
+        let frame = config.build(&saddr);
+        assert_eq!((frame[13], frame[19], frame[20]), (38, 0, 0));
+        assert_eq!(Bpdu::parse(&frame), Some(config));
+
+        let frame = Bpdu::Tcn.build(&saddr);
+        assert_eq!((frame[13], frame[20]), (7, 0x80));
+        assert_eq!(Bpdu::parse(&frame), Some(Bpdu::Tcn));
+
+        // not a BPDU: SNAP header, truncated
+        let mut frame = rst.build(&saddr);
+        frame[14..17].copy_from_slice(&[0xaa, 0xaa, 0x03]);
+        assert_eq!(Bpdu::parse(&frame), None);
+        assert_eq!(Bpdu::parse(&rst.build(&saddr)[..40]), None);
     }
 
+    // Rapid convergence of a ring and failover to the alternate port
     #[test]
-    fn test_macro_hash_table_foreach() {
-        unsafe {
-            const HASH_SIZE: c_int = 16;
-            let ht = hash_int_create!(HASH_SIZE);
-            assert!(!ht.is_null());
-            for i in 0..64 {
-                hash_table_insert(ht, i as _, i as _);
+    fn test_rstp_ring() {
+        let mut lan = Lan::new(&[4096, 32768, 32768], &[[(0, 1), (1, 1)], [(0, 2), (2, 1)], [(1, 2), (2, 2)]]);
+
+        // proposals and agreements, no forward delay
+        lan.run(1);
+        let root = lan.bridges[0].bridge_id();
+        assert!(lan.bridges.iter().all(|b| b.root().root_id == root));
+        assert_eq!(lan.bridges[0].root_port(), None);
+        assert_eq!(lan.bridges[2].root_port(), Some(handle(2, 1)));
+        assert_eq!([lan.port(0, 1), lan.port(0, 2)], [DESIGNATED, DESIGNATED]);
+        assert_eq!([lan.port(1, 1), lan.port(1, 2)], [ROOT, DESIGNATED]);
+        assert_eq!([lan.port(2, 1), lan.port(2, 2)], [ROOT, ALTERNATE]);
+        assert_eq!(lan.bridges[2].port_state(handle(2, 2)), RSTP_STATE_DISCARDING);
+
+        // stable
+        lan.run(30);
+        assert_eq!([lan.port(1, 1), lan.port(1, 2), lan.port(2, 1), lan.port(2, 2)], [ROOT, DESIGNATED, ROOT, ALTERNATE]);
+        assert!(lan.bridges[1].root_times().msg_age == 1);
+
+        // link failure: the ring opens on the alternate port
+        lan.unlink(0);
+        lan.run(1);
+        assert_eq!([lan.port(1, 2), lan.port(2, 1), lan.port(2, 2)], [ROOT, ROOT, DESIGNATED]);
+        assert_eq!(lan.bridges[1].root(), PriorityVector { root_id: root, root_cost: 38, bridge_id: lan.bridges[2].bridge_id(), port_id: 0x8002 });
+        assert!(lan.bridges[1].topology_changes().0 > 0);
+
+        // stopped: everything forwards
+        lan.bridges[2].set_enabled(false);
+        assert_eq!(lan.port(2, 2), (RSTP_ROLE_DISABLED, RSTP_STATE_FORWARDING));
+    }
+
+    // Legacy 802.1D peer (IOS bridge-group)
+    #[test]
+    fn test_rstp_legacy() {
+        let legacy = EthAddr([0x00, 0x00, 0x0c, 0, 0, 1]);
+        let vector = PriorityVector { root_id: 0x8000_0000_0c00_0001, root_cost: 0, bridge_id: 0x8000_0000_0c00_0001, port_id: 0x8001 };
+        let hello = |flags| Bpdu::Config { rst: false, flags, vector, times: RstpTimes::default() }.build(&legacy);
+        let sent = |b: &mut Rstp, port| {
+            b.take_output()
+                .into_iter()
+                .filter_map(|o| match o {
+                    RstpOutput::Tx(p, frame) if p == port => Bpdu::parse(&frame),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut b = Rstp::new(EthAddr([0x02, 0, 0, 0, 0, 1]));
+        b.add_port(handle(0, 1), 1);
+        b.add_port(handle(0, 2), 2);
+        assert!(b.set_port(handle(0, 1), 128, 19, RSTP_EDGE_NO));
+        assert!(!b.set_port(handle(0, 1), 128, 0, RSTP_EDGE_NO));
+        b.set_enabled(true);
+        for _ in 0..3 {
+            b.tick();
+        }
+
+        // nothing on port 2: edge port
+        assert_eq!(b.port_state(handle(0, 2)), RSTP_STATE_FORWARDING);
+        assert!(b.port_info(handle(0, 2)).unwrap().oper_edge != 0);
+
+        // legacy root on port 1: timer based transitions
+        assert!(b.receive(handle(0, 1), &hello(0)));
+        assert_eq!(b.port_info(handle(0, 1)).unwrap().rstp, 0);
+        assert_eq!(b.root().root_id, vector.root_id);
+        assert_eq!(b.port_state(handle(0, 1)), RSTP_STATE_DISCARDING);
+        b.take_output();
+
+        let mut tcn = 0;
+        for t in 1..=30 {
+            if t % 2 == 0 {
+                b.receive(handle(0, 1), &hello(0));
             }
+            b.tick();
+            tcn += sent(&mut b, handle(0, 1)).iter().filter(|&&bpdu| bpdu == Bpdu::Tcn).count();
+            let state = match t {
+                ..15 => RSTP_STATE_DISCARDING,
+                15..30 => RSTP_STATE_LEARNING,
+                _ => RSTP_STATE_FORWARDING,
+            };
+            assert_eq!(b.port_state(handle(0, 1)), state, "tick {}", t);
+        }
 
-            let mut found: u64 = 0;
-            HASH_TABLE_FOREACH!(i, ht, hn, {
-                assert!((*hn).key == (*hn).value);
-                found |= 1 << ((*hn).key as usize);
-            });
-            assert_eq!(found, !0_u64);
+        // topology change notified to the root until acknowledged
+        assert_eq!(tcn, 1);
+        b.tick();
+        b.tick();
+        assert_eq!(sent(&mut b, handle(0, 1)), [Bpdu::Tcn]);
+        b.receive(handle(0, 1), &hello(0x81));
+        b.tick();
+        b.tick();
+        assert_eq!(sent(&mut b, handle(0, 1)), []);
 
-            hash_table_delete(ht);
+        // inferior legacy bridge on port 2: answered with configuration BPDUs
+        let inferior = PriorityVector { root_id: 0x9000_0000_0c00_0002, root_cost: 0, bridge_id: 0x9000_0000_0c00_0002, port_id: 0x8001 };
+        let frame = Bpdu::Config { rst: false, flags: 0, vector: inferior, times: RstpTimes::default() }.build(&legacy);
+        assert!(b.receive(handle(0, 2), &frame));
+        match sent(&mut b, handle(0, 2))[..] {
+            [Bpdu::Config { rst: false, vector, .. }] => assert_eq!((vector.root_id, vector.root_cost), (b.root().root_id, 19)),
+            ref out => panic!("unexpected BPDUs {:?}", out),
         }
+        assert_eq!(b.port_info(handle(0, 2)).unwrap().role, RSTP_ROLE_DESIGNATED);
     }
 }
 
-mod net {
-    use crate::_extra::*;
-    use crate::dynamips_common::*;
-    use crate::net::*;
-    use crate::utils::*;
-    use std::ffi::c_int;
-    use std::ffi::c_uint;
+mod span {
+    use super::port;
+    use crate::span::*;
+    use std::ffi::c_void;
+    use std::ptr::addr_of_mut;
 
-    // Partial checksum test
+    // Sources, destinations and the per session mask
     #[test]
-    fn test_ip_cksum_partial() {
-        unsafe {
-            const N_BUF: usize = 4;
-            let mut buffer: [[m_uint8_t; 512]; N_BUF] = [[0; 512]; N_BUF];
-            let mut psum: [m_uint16_t; N_BUF] = [0; N_BUF];
-            let mut tmp: m_uint32_t;
-            let mut sum: m_uint32_t;
-            let gsum: m_uint32_t;
+    fn test_span_sessions() {
+        let mut t = SpanTable::new();
 
-            for i in 0..N_BUF {
-                m_randomize_block(buffer[i].as_mut_ptr(), size_of_val(&buffer[i]));
-                if false {
-                    mem_dump(c_stdout(), buffer[i].as_mut_ptr(), size_of_val(&buffer[i]) as u_int);
-                }
+        assert!(t.add_port(1, port(1), SPAN_DIR_RX));
+        assert!(t.add_port(1, port(2), SPAN_DIR_TX));
+        assert!(t.add_vlan(2, 10, SPAN_DIR_BOTH));
+        assert!(!t.add_vlan(2, 0, SPAN_DIR_BOTH));
+        assert!(!t.add_port(0, port(1), SPAN_DIR_RX));
+        assert!(!t.add_port(SPAN_MAX_SESSIONS + 1, port(1), SPAN_DIR_RX));
 
-                sum = ip_cksum_partial(buffer[i].as_mut_ptr(), size_of_val(&buffer[i]) as c_int);
+        // no destination yet: nothing mirrored
+        let mut mask = 0;
+        assert!(t.mirror(port(1), 10, SPAN_DIR_RX, 64, &mut mask).is_empty());
 
-                while (sum >> 16) != 0 {
-                    sum = (sum & 0xFFFF) + (sum >> 16);
-                }
+        // a source can't be a destination and a destination is for one session
+        assert!(!t.set_destination(1, port(2), 0));
+        assert!(t.set_destination(1, port(8), 0));
+        assert!(t.set_destination(2, port(9), 100));
+        assert!(!t.set_destination(2, port(8), 0));
+        assert!(!t.add_port(2, port(9), SPAN_DIR_RX));
+        assert!(t.is_destination(port(8)));
 
-                psum[i] = (!sum) as m_uint16_t;
-            }
+        // port 1 -> port 2 in VLAN 10: one copy per session
+        let mut mask = 0;
+        assert_eq!(t.mirror(port(1), 10, SPAN_DIR_RX, 64, &mut mask), [(port(8), 0), (port(9), 100)]);
+        assert!(t.mirror(port(2), 10, SPAN_DIR_TX, 64, &mut mask).is_empty());
+        assert_eq!(mask, 0b11);
 
-            // partial sums + accumulator
-            tmp = 0;
-            for i in 0..N_BUF {
-                if false {
-                    libc::printf(c"psum[%d] = 0x%4.4x\n".as_ptr(), i, psum[i] as c_uint);
-                }
-                tmp += !psum[i] as m_uint16_t as m_uint32_t;
-            }
+        // port 2 -> port 1 in VLAN 20: direction filter
+        let mut mask = 0;
+        assert!(t.mirror(port(2), 20, SPAN_DIR_RX, 64, &mut mask).is_empty());
+        assert!(t.mirror(port(1), 20, SPAN_DIR_TX, 64, &mut mask).is_empty());
+        assert_eq!(t.mirror(port(2), 20, SPAN_DIR_TX, 64, &mut mask), [(port(8), 0)]);
 
-            // global sum
-            sum = ip_cksum_partial(buffer.as_mut_ptr().cast::<m_uint8_t>(), size_of_val(&buffer) as c_int);
+        let s = t.session(1).unwrap();
+        assert_eq!((s.pkts, s.bytes), (2, 128));
 
-            while (sum >> 16) != 0 {
-                sum = (sum & 0xFFFF) + (sum >> 16);
-            }
+        // removal of the ports and sessions
+        assert!(t.remove_port(1, port(1)));
+        assert!(!t.remove_port(1, port(1)));
+        t.forget_port(port(8));
+        assert!(!t.is_destination(port(8)));
+        assert_eq!(t.session(1).unwrap().ports, [(port(2), SPAN_DIR_TX)]);
+        assert!(t.remove_vlan(2, 10));
+        assert!(t.delete(2));
+        assert!(!t.delete(2));
+        assert_eq!(t.sessions().count(), 1);
+    }
 
-            gsum = sum;
+    unsafe extern "C" fn tx(port: *mut c_void, pkt: *mut u8, len: usize, opt: *mut c_void) {
+        let out = &mut *opt.cast::<Vec<(*mut c_void, Vec<u8>)>>();
+        out.push((port, std::slice::from_raw_parts(pkt, len).to_vec()));
+    }
 
-            // accumulator
-            while (tmp >> 16) != 0 {
-                tmp = (tmp & 0xFFFF) + (tmp >> 16);
-            }
+    // RSPAN copies get a 802.1Q tag
+    #[test]
+    fn test_span_rspan() {
+        let mut out: Vec<(*mut c_void, Vec<u8>)> = Vec::new();
+        let mut frame: Vec<u8> = (0..60).collect();
 
-            if false {
-                libc::printf(c"gsum = 0x%4.4x, tmp = 0x%4.4x : %s\n".as_ptr(), gsum, tmp, if gsum == tmp { c"OK".as_ptr() } else { c"FAILURE".as_ptr() });
-            }
+        unsafe {
+            let s = span_create(Some(tx), addr_of_mut!(out).cast());
+            assert_eq!(span_add_source_port(s, 3, port(1), SPAN_DIR_BOTH), 0);
+            assert_eq!(span_set_destination(s, 3, port(5), 42), 0);
 
-            assert_eq!(tmp, gsum);
+            let mut mask = 0;
+            span_mirror(s, port(1), 1, SPAN_DIR_RX, frame.as_mut_ptr(), frame.len(), &mut mask);
+            assert_eq!(mask, 1 << 2);
+
+            let mut info = std::mem::zeroed::<span_session_info_t>();
+            assert_eq!(span_get_session(s, 3, &mut info), 0);
+            assert_eq!((info.pkts, info.bytes, info.src_ports), (1, 60, 1));
+            assert_eq!(span_get_session(s, 4, &mut info), -1);
+            span_free(s);
+        }
+
+        assert_eq!(out.len(), 1);
+        let (dst, copy) = &out[0];
+        assert_eq!(*dst, port(5));
+        assert_eq!(copy.len(), 64);
+        assert_eq!(copy[..12], frame[..12]);
+        assert_eq!(copy[12..16], [0x81, 0x00, 0x00, 42]);
+        assert_eq!(copy[16..], frame[12..]);
+    }
+}
+
+mod timer {
+    use crate::timer::*;
+    use std::ffi::c_int;
+    use std::ffi::c_void;
+    use std::sync::atomic::AtomicU32;
+    use std::sync::atomic::Ordering;
+
+    static TICKS: AtomicU32 = AtomicU32::new(0);
+
+    unsafe extern "C" fn tick(_arg: *mut c_void, _timer: *mut timer_entry_t) -> c_int {
+        TICKS.fetch_add(1, Ordering::SeqCst);
+        0
+    }
+
+    // timer_create_entry returned 0 for every allocated timer
+    #[test]
+    fn test_timer_create_entry() {
+        unsafe {
+            assert_eq!(timer_init(), 0);
+            let id = timer_create_entry(10, 0, 10, Some(tick), std::ptr::null_mut());
+            assert_ne!(id, 0);
+            for _ in 0..100 {
+                if TICKS.load(Ordering::SeqCst) > 0 {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            assert!(TICKS.load(Ordering::SeqCst) > 0);
+            assert_eq!(timer_remove(id), 0);
         }
     }
 }
 
 mod utils {
+    use super::socketpair;
+    use crate::_extra::*;
+    use crate::evloop::*;
     use crate::utils::*;
+    use libc::off_t;
     use std::ffi::c_char;
     use std::ffi::c_int;
+    use std::ffi::c_void;
     use std::ffi::CStr;
+    use std::ffi::CString;
     use std::ptr::addr_of_mut;
     use std::ptr::null_mut;
 
@@ -409,6 +2216,260 @@ mod utils {
         }
     }
 
+    #[test]
+    fn test_m_log_filters() {
+        let mut logger = Logger::new();
+        assert!(logger.enabled(M_LOG_INFO, "VM"));
+        assert!(!logger.enabled(M_LOG_DEBUG, "VM"));
+
+        logger.set_filters("warn, VM=debug, NIO*=error, NIO_UDP=info").expect("filters");
+        assert!(logger.enabled(M_LOG_DEBUG, "VM"));
+        assert!(!logger.enabled(M_LOG_INFO, "GENERAL"));
+        assert!(logger.enabled(M_LOG_INFO, "NIO_UDP"));
+        assert!(!logger.enabled(M_LOG_WARN, "NIO_TAP"));
+        assert_eq!(logger.filters(), "warn,NIO*=error,NIO_UDP=info,VM=debug");
+
+        // all or nothing
+        assert!(logger.set_filters("debug,VM=loud").is_err());
+        assert_eq!(logger.level(), M_LOG_WARN);
+
+        logger.set_filters("VM=default,NIO*=off").expect("filters");
+        assert!(!logger.enabled(M_LOG_DEBUG, "VM"));
+        assert!(!logger.enabled(M_LOG_ERROR, "NIO_TAP"));
+    }
+
+    #[test]
+    fn test_m_log_record() {
+        let mut logger = Logger::new();
+        assert!(logger.record(M_LOG_INFO, None, "VM", "msg\n").ends_with(" VM: msg\n"));
+        assert!(logger.record(M_LOG_WARN, None, "VM", "msg\n").ends_with(" [WARN] VM: msg\n"));
+
+        assert!(logger.set_format(M_LOG_FORMAT_JSON));
+        let rec = logger.record(M_LOG_ERROR, Some(("R\"1", 3)), "VM", "a\tb\n");
+        assert!(rec.starts_with("{\"ts\":\""));
+        assert!(rec.ends_with(",\"level\":\"error\",\"module\":\"VM\",\"vm\":\"R\\\"1\",\"instance\":3,\"msg\":\"a\\tb\"}\n"));
+        assert!(!logger.set_format(7));
+    }
+
+    #[test]
+    fn test_m_log_rotation() {
+        let dir = std::env::temp_dir().join(format!("dynamips_log_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let path = dir.join("log.txt");
+        let filename = std::ffi::CString::new(path.to_str().expect("path")).expect("CString");
+        let mut logger = Logger::new();
+
+        unsafe {
+            assert!(logger.open(&filename));
+            logger.set_rotation(100, 2);
+            let file = logger.file();
+            // 72 bytes per record, rotated every 2 records
+            for i in 0..9 {
+                logger.write(file, M_LOG_INFO, None, "TEST", &format!("{:040}\n", i));
+            }
+            // the FILE is reopened in place
+            assert_eq!(logger.file(), file);
+            logger.close();
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).expect("read");
+        assert!(read("log.txt.1").contains(&format!("{:040}", 7)));
+        assert!(read("log.txt.2").contains(&format!("{:040}", 5)));
+        assert!(read("log.txt").contains(&format!("{:040}", 8)));
+        assert!(!dir.join("log.txt.3").exists());
+
+        // the log goes to stderr when the file cannot be reopened
+        unsafe {
+            assert!(logger.open(&filename));
+            std::fs::remove_dir_all(&dir).expect("remove");
+            logger.rotate();
+            assert_eq!(logger.file(), c_stderr());
+            logger.write(logger.file(), M_LOG_INFO, None, "TEST", "after rotation failure\n");
+            logger.close();
+        }
+    }
+
+    #[test]
+    fn test_m_tokenize() {
+        let tok = |s: &str, flags| m_tokenize(s.as_bytes(), b':', flags, 16).map(|v| v.into_iter().map(|t| String::from_utf8(t).unwrap()).collect::<Vec<_>>());
+
+        assert_eq!(tok("IF:E0:udp:10000:127.0.0.1:10001", 0).unwrap(), ["IF", "E0", "udp", "10000", "127.0.0.1", "10001"]);
+        assert_eq!(tok("IF:E0:udp:10000:[::1]:10001", 0).unwrap(), ["IF", "E0", "udp", "10000", "::1", "10001"]);
+        assert_eq!(tok("[fe80::1%eth0]", 0).unwrap(), ["fe80::1%eth0"]);
+        assert_eq!(tok(r#"unix:"/tmp/a:b":/tmp/c\:d"#, 0).unwrap(), ["unix", "/tmp/a:b", "/tmp/c:d"]);
+        assert_eq!(tok(r#""say \"hi\" \\ \x":'it''s'"#, 0).unwrap(), [r#"say "hi" \ \x"#, "its"]);
+        assert_eq!(tok(r"gen_eth:\Device\NPF_{42}", 0).unwrap(), ["gen_eth", r"\Device\NPF_{42}"]);
+        assert_eq!(tok("a::b:", 0).unwrap(), ["a", "", "b", ""]);
+        assert_eq!(tok("a::b:", M_TOK_SKIP_EMPTY).unwrap(), ["a", "b"]);
+        assert_eq!(tok(r#"a:"":b"#, M_TOK_SKIP_EMPTY).unwrap(), ["a", "", "b"]);
+        assert_eq!(tok("", 0).unwrap(), [""]);
+
+        assert_eq!(tok(r#"IF:"E0:udp"#, 0), Err(TokError::Quote { col: 4 }));
+        assert_eq!(tok("udp:10000:[::1:10001", 0), Err(TokError::Bracket { col: 11 }));
+        assert_eq!(tok("udp:[::1]x:1", 0), Err(TokError::AfterBracket { col: 10 }));
+        assert_eq!(m_tokenize(b"a:b:c", b':', 0, 2), Err(TokError::Count { col: 5 }));
+        assert_eq!(TokError::Quote { col: 4 }.to_string(), "unterminated quote at column 4");
+    }
+
+    #[test]
+    fn test_m_strsplit_tok() {
+        unsafe {
+            let mut tokens: [*mut c_char; 4] = [null_mut(); 4];
+            let mut err = m_tok_error_t::default();
+
+            let count = m_strsplit_tok(c"1:0:udp:[::1]".as_ptr(), b':' as c_char, 0, tokens.as_mut_ptr(), 4, &mut err);
+            assert_eq!(count, 4);
+            assert_eq!(CStr::from_ptr(tokens[3]), c"::1");
+            m_strsplit_free(tokens.as_mut_ptr(), count);
+            assert!(tokens.iter().all(|t| t.is_null()));
+
+            assert_eq!(m_strsplit_tok(c"a:'b".as_ptr(), b':' as c_char, 0, tokens.as_mut_ptr(), 4, &mut err), -1);
+            assert_eq!((err.code, err.col), (M_TOK_ERR_QUOTE, 3));
+            assert!(tokens.iter().all(|t| t.is_null()));
+
+            // quoting round trip
+            for s in ["eth0", "::1", "fe80::1%eth0", "/tmp/a:b", r#"x"y\z:"#, "[x]", "it's", ""] {
+                let cs = CString::new(s).unwrap();
+                let quoted = m_strquote_tok(cs.as_ptr(), b':' as c_char);
+                assert_eq!(m_strsplit_tok(quoted, b':' as c_char, 0, tokens.as_mut_ptr(), 4, &mut err), 1, "{}", s);
+                assert_eq!(CStr::from_ptr(tokens[0]).to_bytes(), s.as_bytes());
+                m_strsplit_free(tokens.as_mut_ptr(), 1);
+                libc::free(quoted.cast::<_>());
+            }
+            assert_eq!(m_quote_token(b"::1", b':'), b"[::1]");
+            assert_eq!(m_quote_token(b"eth0", b':'), b"eth0");
+        }
+    }
+
+    #[test]
+    fn test_memzone_sparse_file() {
+        let dir = std::env::temp_dir().join(format!("dynamips_memzone_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let filename = std::ffi::CString::new(dir.join("ram").to_str().expect("path")).expect("CString");
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let len = 64 * page_size;
+        let mut ptr: *mut u_char = null_mut();
+
+        unsafe {
+            let fd = memzone_create_file(filename.as_ptr().cast_mut(), len, &mut ptr);
+            assert_ne!(fd, -1);
+            assert_eq!(memzone_allocated_size(fd), 0);
+
+            // dirty 8 pages, then clear 5 of them
+            for i in 0..8 {
+                *ptr.add(i * 4 * page_size + 1) = 0xAA;
+            }
+            for i in 0..5 {
+                *ptr.add(i * 4 * page_size + 1) = 0x00;
+            }
+            memzone_sync(ptr.cast::<_>(), len);
+            let used = memzone_allocated_size(fd);
+            assert!(used >= (8 * page_size) as off_t);
+
+            match memzone_punch_holes(fd, ptr, len) {
+                Ok(count) => {
+                    assert!(count >= 5);
+                    assert!(memzone_allocated_size(fd) < used);
+                    assert_eq!(*ptr.add(5 * 4 * page_size + 1), 0xAA);
+                    assert_eq!(*ptr.add(4 * page_size + 1), 0x00);
+                }
+                // hole punching is not supported by every filesystem
+                Err(err) => assert_eq!(err.raw_os_error(), Some(libc::EOPNOTSUPP)),
+            }
+
+            memzone_unmap(ptr.cast::<_>(), len);
+            libc::close(fd);
+        }
+        std::fs::remove_dir_all(&dir).expect("remove");
+    }
+
+    #[test]
+    fn test_memzone_delta() {
+        let dir = std::env::temp_dir().join(format!("dynamips_delta_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let base = dir.join("base");
+        let delta = dir.join("delta");
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let len = 16 * page_size;
+        let base_data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&base, &base_data).expect("write");
+        let base_name = std::ffi::CString::new(base.to_str().expect("path")).expect("CString");
+        let delta_name = std::ffi::CString::new(delta.to_str().expect("path")).expect("CString");
+        let mut ptr: *mut u_char = null_mut();
+
+        unsafe {
+            let fd = memzone_open_cow_file(base_name.as_ptr().cast_mut(), len, &mut ptr);
+            assert_ne!(fd, -1);
+            *ptr.add(3 * page_size) ^= 0xFF;
+            *ptr.add(len - 1) ^= 0xFF;
+            assert_eq!(memzone_delta_save(ptr, len, fd, &delta).expect("save"), 2);
+            assert_eq!(std::fs::metadata(&delta).expect("metadata").len() as usize, 32 + 2 * (8 + page_size));
+            let expected = std::slice::from_raw_parts(ptr, len).to_vec();
+            memzone_unmap(ptr.cast::<_>(), len);
+            libc::close(fd);
+
+            // base + delta
+            let fd = memzone_open_cow_file(base_name.as_ptr().cast_mut(), len, &mut ptr);
+            assert_ne!(fd, -1);
+            assert_eq!(memzone_restore_delta(ptr, len, delta_name.as_ptr().cast_mut()), 2);
+            assert!(std::slice::from_raw_parts(ptr, len) == expected.as_slice());
+            memzone_unmap(ptr.cast::<_>(), len);
+            libc::close(fd);
+
+            // against zeros, and the size is checked
+            let mut zone = vec![0_u8; len];
+            zone[page_size] = 1;
+            assert_eq!(memzone_delta_save(zone.as_ptr(), len, -1, &delta).expect("save"), 1);
+            assert!(memzone_delta_restore(zone.as_mut_ptr(), len - page_size, &delta).is_err());
+
+            // a bad record is detected before the first one is applied
+            zone[2 * page_size] = 2;
+            assert_eq!(memzone_delta_save(zone.as_ptr(), len, -1, &delta).expect("save"), 2);
+            let mut file = std::fs::read(&delta).expect("read");
+            let second = 32 + 8 + page_size;
+            for bad in [u64::MAX, len as u64] {
+                file[second..second + 8].copy_from_slice(&bad.to_be_bytes());
+                std::fs::write(&delta, &file).expect("write");
+                let mut restored = vec![0_u8; len];
+                let err = memzone_delta_restore(restored.as_mut_ptr(), len, &delta).expect_err("bad index");
+                assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+                assert!(restored.iter().all(|&b| b == 0));
+            }
+            std::fs::write(&delta, &file[..second + 8 + 1]).expect("write");
+            let mut restored = vec![0_u8; len];
+            let err = memzone_delta_restore(restored.as_mut_ptr(), len, &delta).expect_err("truncated");
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+            assert!(restored.iter().all(|&b| b == 0));
+        }
+        std::fs::remove_dir_all(&dir).expect("remove");
+    }
+
+    #[test]
+    fn test_memzone_opts() {
+        let mut opts = memzone_opts::default();
+        let mut used = memzone_opts::default();
+        let len = 4 << 20;
+
+        assert_eq!(memzone_set_opts(MEMZONE_KIND_MAX, MEMZONE_HUGE_NONE, MEMZONE_NUMA_ANY), -1);
+        assert_eq!(memzone_set_opts(MEMZONE_KIND_RAM, 3, MEMZONE_NUMA_ANY), -1);
+        assert_eq!(memzone_set_opts(MEMZONE_KIND_RAM, MEMZONE_HUGE_HUGETLB, 0), 0);
+        unsafe {
+            assert_eq!(memzone_get_opts(MEMZONE_KIND_RAM, &mut opts, null_mut()), 0);
+            assert_eq!(opts, memzone_opts { huge_pages: MEMZONE_HUGE_HUGETLB, numa_node: 0 });
+
+            // whatever the host supports, the mapping succeeds
+            let ptr = memzone_map_anon(len, MEMZONE_KIND_RAM, &mut used);
+            assert!(!ptr.is_null());
+            *ptr.add(len - 1) = 1;
+            assert!([MEMZONE_HUGE_NONE, MEMZONE_HUGE_THP, MEMZONE_HUGE_HUGETLB].contains(&used.huge_pages));
+            assert!([MEMZONE_NUMA_ANY, 0].contains(&used.numa_node));
+            assert_eq!(memzone_get_opts(MEMZONE_KIND_RAM, null_mut(), &mut opts), 0);
+            assert_eq!(opts, used);
+            memzone_unmap(ptr.cast::<_>(), len);
+        }
+        assert_eq!(memzone_set_opts(MEMZONE_KIND_RAM, MEMZONE_HUGE_NONE, MEMZONE_NUMA_ANY), 0);
+    }
+
     #[test]
     fn test_fd_printf() {
         unsafe {
@@ -429,4 +2490,287 @@ mod utils {
             libc::close(fds[1]);
         }
     }
+    unsafe extern "C" fn fd_pool_cbk(fd_slot: *mut c_int, opt: *mut c_void) {
+        let mut buf = [0u8; 16];
+        if libc::read(*fd_slot, buf.as_mut_ptr().cast::<_>(), buf.len()) <= 0 {
+            // closed by the peer
+            libc::close(*fd_slot);
+            *fd_slot = -1;
+        } else {
+            *opt.cast::<u32>() += 1;
+        }
+    }
+
+    #[test]
+    fn test_fd_pool_attach() {
+        unsafe {
+            let ev = ev_loop_create();
+            assert!(!ev.is_null());
+            let mut pool: fd_pool_t = std::mem::zeroed();
+            let mut calls = 0u32;
+            fd_pool_init(&mut pool);
+
+            // FDs added before and after the attach are both watched
+            let a = socketpair();
+            let b = socketpair();
+            assert!(!fd_pool_add(&mut pool, a[0]).is_null());
+            assert_eq!(fd_pool_attach(&mut pool, ev, Some(fd_pool_cbk), addr_of_mut!(calls).cast::<_>()), 0);
+            assert!(!fd_pool_add(&mut pool, b[0]).is_null());
+            assert_eq!(ev_loop_count(ev), 2);
+
+            assert_eq!(libc::write(a[1], b"x".as_ptr().cast::<_>(), 1), 1);
+            assert_eq!(libc::write(b[1], b"x".as_ptr().cast::<_>(), 1), 1);
+            assert_eq!(ev_loop_run_once(ev, 1000), 2);
+            assert_eq!(calls, 2);
+
+            // a FD closed by the callback is unregistered
+            libc::close(a[1]);
+            assert_eq!(ev_loop_run_once(ev, 1000), 1);
+            assert_eq!(ev_loop_count(ev), 1);
+
+            fd_pool_detach(&mut pool);
+            assert_eq!(ev_loop_count(ev), 0);
+            fd_pool_free(&mut pool);
+            libc::close(b[1]);
+            ev_loop_free(ev);
+        }
+    }
+}
+
+mod vlan {
+    use crate::crc::*;
+    use crate::net::*;
+    use crate::vlan::*;
+
+    fn frame() -> Vec<u8> {
+        let mut pkt = vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xc2, 0x01, 0x12, 0x34, 0x00, 0x00, 0x08, 0x00];
+        pkt.extend((0..46).map(|x| x as u8));
+        pkt
+    }
+
+    // Untagged -> 802.1Q/QinQ/ISL -> untagged
+    #[test]
+    fn test_vlan_round_trip() {
+        let pkt = frame();
+        let tags = [
+            vlan_tag_t { encap: VLAN_ENCAP_DOT1Q, vlan_id: 10, cos: 5, ..Default::default() },
+            vlan_tag_t { encap: VLAN_ENCAP_QINQ, ethertype: N_ETH_PROTO_DOT1Q_4, vlan_id: 100, inner_vlan_id: 10, ..Default::default() },
+            vlan_tag_t { encap: VLAN_ENCAP_ISL, vlan_id: 10, cos: 6, ..Default::default() },
+        ];
+
+        for tag in tags {
+            let mut out = [0u8; 256];
+            let len = vlan_build(&mut out, &pkt[..12], &pkt[12..], &tag).unwrap();
+            assert_eq!(len, vlan_encap_len(pkt.len(), &tag));
+
+            let parsed = vlan_parse(&out[..len]).unwrap();
+            let expected = vlan_tag_t { ethertype: if tag.encap == VLAN_ENCAP_ISL { 0 } else { tag.ethertype.max(N_ETH_PROTO_DOT1Q) }, ..tag };
+            assert_eq!(parsed.tag, expected);
+            assert_eq!([parsed.addrs, parsed.payload].concat(), pkt);
+        }
+    }
+
+    // ISL header and internal FCS
+    #[test]
+    fn test_vlan_isl() {
+        unsafe {
+            let mut pkt = frame();
+            let mut out = [0u8; 256];
+            let tag = vlan_tag_t { encap: VLAN_ENCAP_ISL, vlan_id: 0x123, ..Default::default() };
+            let len = vlan_encap(pkt.as_ptr(), pkt.len(), &tag, out.as_mut_ptr(), out.len()) as usize;
+            assert_eq!(len, 26 + pkt.len() + 4);
+            assert_eq!(out[..6], [0x01, 0x00, 0x0c, 0x00, 0x00, 0x00]);
+            assert_eq!(out[6..12], pkt[6..12]);
+            assert_eq!(u16::from_be_bytes([out[12], out[13]]) as usize, len - 14);
+            assert_eq!(out[17..20], pkt[6..9]);
+            assert_eq!(u16::from_be_bytes([out[20], out[21]]), 0x123 << 1);
+            let ifcs = crc32_compute(0xFFFFFFFF, pkt.as_mut_ptr(), pkt.len() as _);
+            assert_eq!(out[len - 4..len], ifcs.to_le_bytes());
+
+            // ISL -> 802.1Q keeps the VLAN
+            let mut dot1q = [0u8; 256];
+            let to = vlan_tag_t { encap: VLAN_ENCAP_DOT1Q, ..Default::default() };
+            let n = vlan_translate(out.as_ptr(), len, &to, dot1q.as_mut_ptr(), dot1q.len()) as usize;
+            assert_eq!(n, pkt.len() + 4);
+            assert_eq!(dot1q[12..16], [0x81, 0x00, 0x01, 0x23]);
+
+            // 802.1Q -> untagged
+            let mut untagged = [0u8; 256];
+            let mut tag = vlan_tag_t::default();
+            let n = vlan_decap(dot1q.as_ptr(), n, &mut tag, untagged.as_mut_ptr(), untagged.len()) as usize;
+            assert_eq!(untagged[..n], pkt[..]);
+            assert_eq!((tag.encap, tag.vlan_id), (VLAN_ENCAP_DOT1Q, 0x123));
+        }
+    }
+}
+
+mod vtty_mux {
+    use crate::utils::*;
+    use crate::vtty_mux::*;
+    use std::io::Read;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    // Console side and client side of a connection
+    fn client() -> (UnixStream, UnixStream) {
+        let (server, client) = UnixStream::pair().unwrap();
+        client.set_nonblocking(true).unwrap();
+        (server, client)
+    }
+
+    fn recv(sock: &mut UnixStream) -> String {
+        let mut buf = vec![0u8; 4096];
+        let n = sock.read(&mut buf).unwrap_or(0);
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    }
+
+    // Owner, observers and take-over handshake
+    #[test]
+    fn test_vtty_mux_takeover() {
+        let (s1, mut c1) = client();
+        let (s2, mut c2) = client();
+        let (s3, mut c3) = client();
+        let (fd1, fd2, fd3) = (s1.as_raw_fd(), s2.as_raw_fd(), s3.as_raw_fd());
+
+        let mut mux = VttyMux::new();
+        assert_eq!(mux.add(fd1, "student"), VTTY_MUX_OWNER);
+        assert_eq!(recv(&mut c1), "");
+        assert_eq!(mux.add(fd2, "instructor"), VTTY_MUX_OBSERVER);
+        assert_eq!(mux.add(fd3, "tutor"), VTTY_MUX_OBSERVER);
+        assert!(recv(&mut c1).contains("instructor connected (read-only)"));
+        assert!(recv(&mut c2).contains("Read-only console, student has control"));
+        recv(&mut c3);
+
+        // keystrokes of an observer are dropped, a take-over can be cancelled
+        mux.observer_input(fd2, b'a');
+        assert_eq!(recv(&mut c2), "");
+        mux.observer_input(fd2, VTTY_MUX_TAKEOVER_KEY);
+        assert!(recv(&mut c2).contains("Take over the console from student? [y/n]"));
+        mux.observer_input(fd2, b'n');
+        assert!(recv(&mut c2).contains("Take-over cancelled"));
+        assert_eq!(mux.role(fd1), Some(VTTY_MUX_OWNER));
+
+        // the owner is notified of a take-over and becomes an observer
+        mux.input(fd1).unwrap().state = 1;
+        mux.observer_input(fd2, VTTY_MUX_TAKEOVER_KEY);
+        mux.observer_input(fd2, b'y');
+        assert_eq!((mux.role(fd1), mux.role(fd2), mux.role(fd3)), (Some(VTTY_MUX_OBSERVER), Some(VTTY_MUX_OWNER), Some(VTTY_MUX_OBSERVER)));
+        assert_eq!(mux.input(fd1).unwrap().state, 0);
+        assert!(recv(&mut c1).contains("Console control taken over by instructor from student"));
+        assert!(recv(&mut c3).contains("Console control taken over by instructor from student"));
+
+        // the owner leaves, the console is free until taken
+        assert!(mux.remove(fd2));
+        assert!(recv(&mut c1).contains("Console released by instructor"));
+        mux.observer_input(fd3, VTTY_MUX_TAKEOVER_KEY);
+        assert!(recv(&mut c3).contains("Take control of the console? [y/n]"));
+        mux.observer_input(fd3, b'Y');
+        assert_eq!(mux.role(fd3), Some(VTTY_MUX_OWNER));
+        assert_eq!(mux.clients(), [(fd1, VTTY_MUX_OBSERVER, "student".to_string()), (fd3, VTTY_MUX_OWNER, "tutor".to_string())]);
+    }
+
+    // Clients closed by the FD pool
+    #[test]
+    fn test_vtty_mux_sync() {
+        unsafe {
+            let (s1, _c1) = client();
+            let (s2, mut c2) = client();
+            let (fd1, fd2) = (s1.as_raw_fd(), s2.as_raw_fd());
+
+            let mux = vtty_mux_create();
+            assert_eq!(vtty_mux_add(mux, fd1), VTTY_MUX_OWNER);
+            assert_eq!(vtty_mux_add(mux, fd2), VTTY_MUX_OBSERVER);
+            assert_eq!(vtty_mux_is_owner(mux, fd1), 1);
+            assert!(!vtty_mux_get_input(mux, fd2).is_null());
+            assert!(vtty_mux_get_input(mux, -1).is_null());
+            recv(&mut c2);
+
+            let mut pool: fd_pool_t = std::mem::zeroed();
+            fd_pool_init(&mut pool);
+            pool.fd[3] = fd2;
+            vtty_mux_sync(mux, &mut pool);
+            assert_eq!(vtty_mux_get_clients(mux, None, std::ptr::null_mut()), 1);
+            assert!(recv(&mut c2).contains(&format!("Console released by fd {}", fd1)));
+
+            // a new client with the FD of a closed one
+            assert_eq!(vtty_mux_add(mux, fd2), VTTY_MUX_OWNER);
+            assert_eq!(vtty_mux_get_clients(mux, None, std::ptr::null_mut()), 1);
+            vtty_mux_free(mux);
+        }
+    }
+}
+
+mod vtty_rec {
+    use crate::utils::*;
+    use crate::vtty_rec::*;
+
+    // Scrollback and last lines
+    #[test]
+    fn test_vtty_rec_scrollback() {
+        let mut rec = VttyRec::new(16);
+        rec.put(b"Router>\r\n", 0);
+        rec.put(b"Router>show ver\r\n\x07IOS", 0);
+        assert_eq!(rec.bytes(), 30);
+        assert_eq!(rec.scrollback(), b"r>show ver\r\n\x07IOS");
+        assert_eq!(rec.tail(5), ["r>show ver", "IOS"]);
+        assert_eq!(rec.tail(1), ["IOS"]);
+
+        rec.put(b"\r\n", 0);
+        assert_eq!(rec.tail(5), ["show ver", "IOS"]);
+
+        rec.set_scrollback_size(5);
+        assert_eq!(rec.scrollback(), b"IOS\r\n");
+        rec.set_scrollback_size(0);
+        rec.put(b"dropped", 0);
+        assert!(rec.scrollback().is_empty());
+    }
+
+    // asciicast and log recordings, with rotation
+    #[test]
+    fn test_vtty_rec_files() {
+        let dir = std::env::temp_dir().join(format!("dynamips_vtty_rec_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cast = dir.join("con.cast");
+        let log = dir.join("con.log");
+        let t0: u64 = 1_700_000_000_000_000;
+
+        let mut rec = VttyRec::new(VTTY_REC_DEFAULT_SCROLLBACK);
+        rec.start(VTTY_REC_ASCIICAST, cast.to_str().unwrap(), "R1 \"con\"", 0, 0, t0).unwrap();
+        rec.start(VTTY_REC_LOG, log.to_str().unwrap(), "R1", 200, 2, t0).unwrap();
+        assert_eq!(rec.filename(VTTY_REC_LOG), log.to_str());
+
+        // "é" split across two batches, the log line starts with the first one
+        rec.put(b"boot\r\n\xc3", t0 + 500_000);
+        rec.flush(t0 + 600_000);
+        rec.put(b"\xa9", t0 + 1_250_000);
+        rec.marker("VM stopped", t0 + 2_000_000);
+        assert!(rec.stop(VTTY_REC_ASCIICAST, t0 + 2_000_000));
+        assert!(!rec.stop(VTTY_REC_ASCIICAST, t0 + 2_000_000));
+
+        let text = std::fs::read_to_string(&cast).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "{\"version\": 2, \"width\": 80, \"height\": 24, \"timestamp\": 1700000000, \"title\": \"R1 \\\"con\\\"\"}",
+                "[0.500000, \"o\", \"boot\\r\\n\"]",
+                "[1.250000, \"o\", \"é\"]",
+                "[2.000000, \"m\", \"VM stopped\"]",
+            ]
+        );
+
+        let ts = |t| m_log_timestamp_usec(t);
+        let text = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(text, format!("{} --- R1 recording started ---\n{} boot\n{} é\n{} --- VM stopped ---\n", ts(t0), ts(t0 + 500_000), ts(t0 + 500_000), ts(t0 + 2_000_000)));
+
+        // the log reaches its maximum size and is rotated
+        rec.put(&[b'x'; 100], t0 + 3_000_000);
+        rec.flush(t0 + 3_000_000);
+        let rotated = dir.join("con.log.1");
+        assert_eq!(std::fs::read_to_string(&rotated).unwrap().lines().count(), 5);
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), format!("{} --- R1 recording started ---\n", ts(t0 + 3_000_000)));
+
+        assert!(rec.stop(VTTY_REC_LOG, t0 + 3_000_000));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
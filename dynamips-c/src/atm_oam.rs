@@ -0,0 +1,470 @@
+//! ATM switch OAM (ITU-T I.610 fault management).
+//!
+//! OAM cells of a virtual path (F4) use VCI 3 (segment) and 4 (end-to-end),
+//! those of a virtual channel (F5) use PTI 4 (segment) and 5 (end-to-end).
+//! The payload starts with the OAM/function type and ends with a CRC-10.
+//!
+//! The switch processes them according to the connection tables:
+//!
+//! - loopback requests are answered on the connections which exist (the
+//!   segment ones because the switch ends the segment, the end-to-end ones
+//!   on behalf of the far end, which keeps "oam-pvc manage" up),
+//! - AIS received on a segment is answered with RDI,
+//! - the other end-to-end cells follow their connection, F5 cells of a
+//!   switched VP are switched with it,
+//! - cells of unknown connections are dropped, so the PVCs go down.
+//!
+//! When a connection is deleted, AIS cells are sent every second downstream
+//! on its output until it is created again. The switch can also send its
+//! own segment loopback requests, the replies are matched on the
+//! correlation tag.
+//!
+//! The cells built here have no HEC, the switch inserts it before sending.
+
+use crate::_extra::*;
+use crate::crc::*;
+use crate::dynamips_common::*;
+use crate::timer::*;
+use crate::utils::*;
+use std::collections::HashMap;
+use std::ffi::c_int;
+use std::ffi::c_void;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+/// F4 segment VCI.
+pub const ATM_OAM_F4_SEG_VCI: u_int = 3;
+/// F4 end-to-end VCI.
+pub const ATM_OAM_F4_E2E_VCI: u_int = 4;
+/// F5 segment PTI.
+pub const ATM_OAM_F5_SEG_PTI: u_int = 4;
+/// F5 end-to-end PTI.
+pub const ATM_OAM_F5_E2E_PTI: u_int = 5;
+
+/// VCI of the F4 flow in the API (the VP itself).
+pub const ATM_OAM_VP: c_int = -1;
+
+// OAM type and function type
+pub const ATM_OAM_AIS: u8 = 0x10;
+pub const ATM_OAM_RDI: u8 = 0x11;
+pub const ATM_OAM_CC: u8 = 0x14;
+pub const ATM_OAM_LOOPBACK: u8 = 0x18;
+
+/// Interval of the AIS cells (ms).
+pub const ATM_OAM_AIS_INTERVAL: m_tmcnt_t = 1000;
+/// Time to wait for a loopback reply (ms).
+pub const ATM_OAM_LOOPBACK_TIMEOUT: m_tmcnt_t = 5000;
+
+// Connection state flags
+pub const ATM_OAM_CONN_VPC: c_int = 0x01;
+pub const ATM_OAM_CONN_VCC: c_int = 0x02;
+/// VCCs are switched on the VPI, the switch ends the VP.
+pub const ATM_OAM_CONN_VP_END: c_int = 0x04;
+
+// Verdicts on a cell
+pub const ATM_OAM_ACTION_SWITCH: c_int = 0;
+pub const ATM_OAM_ACTION_DROP: c_int = 1;
+pub const ATM_OAM_ACTION_REPLY: c_int = 2;
+
+const ATM_HDR_SIZE: usize = 5;
+const ATM_PAYLOAD_SIZE: usize = 48;
+const ATM_CELL_SIZE: usize = ATM_HDR_SIZE + ATM_PAYLOAD_SIZE;
+
+/// Unused octets.
+const ATM_OAM_FILL: u8 = 0x6a;
+/// Loopback indication bit, set in requests.
+const ATM_OAM_LB_REQUEST: u8 = 0x01;
+/// Location ID of the connection end point.
+const ATM_OAM_LOC_END_POINT: [u8; 16] = [0xff; 16];
+
+pub type AtmCell = [u8; ATM_CELL_SIZE];
+
+/// OAM flow of a cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtmOamFlow {
+    pub vpi: u_int,
+    /// None for F4.
+    pub vci: Option<u_int>,
+    pub segment: bool,
+}
+
+impl AtmOamFlow {
+    /// Flow of an OAM cell, None for a user cell.
+    pub fn of_cell(cell: &AtmCell) -> Option<Self> {
+        let hdr = m_uint32_t::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]);
+        let (vpi, vci, pti) = (hdr >> 20, (hdr >> 4) & 0xffff, (hdr >> 1) & 0x07);
+
+        match (vci, pti) {
+            (ATM_OAM_F4_SEG_VCI | ATM_OAM_F4_E2E_VCI, _) => Some(Self { vpi, vci: None, segment: vci == ATM_OAM_F4_SEG_VCI }),
+            (_, ATM_OAM_F5_SEG_PTI | ATM_OAM_F5_E2E_PTI) => Some(Self { vpi, vci: Some(vci), segment: pti == ATM_OAM_F5_SEG_PTI }),
+            _ => None,
+        }
+    }
+
+    /// Header of the flow cells (without HEC).
+    fn header(&self) -> [u8; ATM_HDR_SIZE] {
+        let (vci, pti) = match self.vci {
+            None => (if self.segment { ATM_OAM_F4_SEG_VCI } else { ATM_OAM_F4_E2E_VCI }, 0),
+            Some(vci) => (vci, if self.segment { ATM_OAM_F5_SEG_PTI } else { ATM_OAM_F5_E2E_PTI }),
+        };
+        let hdr = (self.vpi << 20) | ((vci & 0xffff) << 4) | (pti << 1);
+        let b = hdr.to_be_bytes();
+        [b[0], b[1], b[2], b[3], 0]
+    }
+}
+
+/// Check the CRC-10 of an OAM cell.
+pub fn atm_oam_crc_ok(cell: &AtmCell) -> bool {
+    crc10_update(0, &cell[ATM_HDR_SIZE..]) == 0
+}
+
+/// Insert the CRC-10 of an OAM cell.
+pub fn atm_oam_insert_crc(cell: &mut AtmCell) {
+    let payload: &mut [u8; ATM_PAYLOAD_SIZE] = (&mut cell[ATM_HDR_SIZE..]).try_into().unwrap();
+    payload[46] &= 0xfc;
+    payload[47] = 0;
+    let crc = crc10_oam(payload);
+    payload[46] |= (crc >> 8) as u8;
+    payload[47] = crc as u8;
+}
+
+/// Build an OAM cell: header, type and function-specific fields.
+fn atm_oam_cell(flow: &AtmOamFlow, kind: u8, fields: &[u8]) -> AtmCell {
+    let mut cell = [ATM_OAM_FILL; ATM_CELL_SIZE];
+    cell[..ATM_HDR_SIZE].copy_from_slice(&flow.header());
+    cell[ATM_HDR_SIZE] = kind;
+    cell[ATM_HDR_SIZE + 1..ATM_HDR_SIZE + 1 + fields.len()].copy_from_slice(fields);
+    atm_oam_insert_crc(&mut cell);
+    cell
+}
+
+/// Loopback request with a correlation tag.
+pub fn atm_oam_loopback_cell(flow: &AtmOamFlow, tag: m_uint32_t) -> AtmCell {
+    let mut fields = vec![ATM_OAM_LB_REQUEST];
+    fields.extend(tag.to_be_bytes());
+    fields.extend(ATM_OAM_LOC_END_POINT);
+    fields.extend(ATM_OAM_LOC_END_POINT);
+    atm_oam_cell(flow, ATM_OAM_LOOPBACK, &fields)
+}
+
+/// AIS or RDI cell (defect type and location not provided).
+pub fn atm_oam_defect_cell(flow: &AtmOamFlow, kind: u8) -> AtmCell {
+    atm_oam_cell(flow, kind, &[])
+}
+
+/// OAM counters of a switch.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct atm_oam_stats {
+    pub cells: m_uint64_t,
+    pub crc_errors: m_uint64_t,
+    /// Cells of unknown connections.
+    pub dropped: m_uint64_t,
+    pub lb_answered: m_uint64_t,
+    pub lb_sent: m_uint64_t,
+    pub lb_replies: m_uint64_t,
+    pub lb_timeouts: m_uint64_t,
+    /// Round trip time of the last loopback reply (ms).
+    pub lb_last_rtt: m_uint64_t,
+    pub ais_sent: m_uint64_t,
+    pub ais_received: m_uint64_t,
+    pub rdi_sent: m_uint64_t,
+    pub rdi_received: m_uint64_t,
+}
+pub type atm_oam_stats_t = atm_oam_stats;
+
+/// Verdict on a cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtmOamVerdict {
+    /// User cell or OAM cell following its connection.
+    Switch,
+    Drop,
+    /// Send the cell back on the input.
+    Reply(AtmCell),
+}
+
+/// Connection with AIS sent downstream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtmOamAis {
+    pub port: *mut c_void,
+    pub flow: AtmOamFlow,
+}
+
+/// OAM state of a switch, the ports are identified by their NIO.
+#[derive(Debug, Default)]
+pub struct AtmOam {
+    ais: Vec<AtmOamAis>,
+    /// Pending loopback requests by tag: port, flow, time sent.
+    loopbacks: HashMap<m_uint32_t, (*mut c_void, AtmOamFlow, m_tmcnt_t)>,
+    next_tag: m_uint32_t,
+    stats: atm_oam_stats_t,
+}
+
+impl AtmOam {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> atm_oam_stats_t {
+        self.stats
+    }
+
+    pub fn ais(&self) -> &[AtmOamAis] {
+        &self.ais
+    }
+
+    /// Process a cell received on a port, `conn` are the ATM_OAM_CONN_* flags
+    /// of its VPI/VCI.
+    pub fn handle(&mut self, port: *mut c_void, cell: &AtmCell, conn: c_int, now: m_tmcnt_t) -> AtmOamVerdict {
+        let flow = match AtmOamFlow::of_cell(cell) {
+            // the F5 cells of a switched VP are transparent
+            Some(AtmOamFlow { vci: Some(_), .. }) if (conn & ATM_OAM_CONN_VPC) != 0 => return AtmOamVerdict::Switch,
+            Some(flow) => flow,
+            None => return AtmOamVerdict::Switch,
+        };
+        self.stats.cells += 1;
+
+        if !atm_oam_crc_ok(cell) {
+            self.stats.crc_errors += 1;
+            return AtmOamVerdict::Drop;
+        }
+
+        // the switch ends the segments and the VPs of the switched VCCs
+        let (exists, end) = match flow.vci {
+            None if (conn & ATM_OAM_CONN_VPC) != 0 => (true, flow.segment),
+            None => ((conn & ATM_OAM_CONN_VP_END) != 0, true),
+            Some(_) => ((conn & ATM_OAM_CONN_VCC) != 0, flow.segment),
+        };
+
+        let kind = cell[ATM_HDR_SIZE];
+        match kind {
+            ATM_OAM_AIS => self.stats.ais_received += 1,
+            ATM_OAM_RDI => self.stats.rdi_received += 1,
+            _ => {}
+        }
+
+        // reply to our own loopback request
+        if kind == ATM_OAM_LOOPBACK && (cell[ATM_HDR_SIZE + 1] & ATM_OAM_LB_REQUEST) == 0 {
+            let tag = m_uint32_t::from_be_bytes(cell[ATM_HDR_SIZE + 2..ATM_HDR_SIZE + 6].try_into().unwrap());
+            if let Some(&(lb_port, lb_flow, sent)) = self.loopbacks.get(&tag) {
+                if lb_port == port && lb_flow == flow {
+                    self.loopbacks.remove(&tag);
+                    self.stats.lb_replies += 1;
+                    self.stats.lb_last_rtt = now.saturating_sub(sent) as m_uint64_t;
+                    return AtmOamVerdict::Drop;
+                }
+            }
+        }
+
+        if !exists {
+            self.stats.dropped += 1;
+            return AtmOamVerdict::Drop;
+        }
+
+        match kind {
+            ATM_OAM_LOOPBACK if (cell[ATM_HDR_SIZE + 1] & ATM_OAM_LB_REQUEST) != 0 => {
+                let mut reply = *cell;
+                reply[ATM_HDR_SIZE + 1] &= !ATM_OAM_LB_REQUEST;
+                atm_oam_insert_crc(&mut reply);
+                self.stats.lb_answered += 1;
+                AtmOamVerdict::Reply(reply)
+            }
+            ATM_OAM_AIS if end => {
+                self.stats.rdi_sent += 1;
+                AtmOamVerdict::Reply(atm_oam_defect_cell(&flow, ATM_OAM_RDI))
+            }
+            _ if end => AtmOamVerdict::Drop,
+            _ => AtmOamVerdict::Switch,
+        }
+    }
+
+    /// Loopback request to send on a segment, returns the cell.
+    pub fn loopback(&mut self, port: *mut c_void, vpi: u_int, vci: Option<u_int>, now: m_tmcnt_t) -> AtmCell {
+        let flow = AtmOamFlow { vpi, vci, segment: true };
+        self.next_tag = self.next_tag.wrapping_add(1);
+        self.loopbacks.insert(self.next_tag, (port, flow, now));
+        self.stats.lb_sent += 1;
+        atm_oam_loopback_cell(&flow, self.next_tag)
+    }
+
+    /// Start sending AIS on the output of a deleted connection, returns the
+    /// first cell or None if already sent.
+    pub fn start_ais(&mut self, port: *mut c_void, vpi: u_int, vci: Option<u_int>) -> Option<AtmCell> {
+        let ais = AtmOamAis { port, flow: AtmOamFlow { vpi, vci, segment: false } };
+        if self.ais.contains(&ais) {
+            return None;
+        }
+        self.ais.push(ais);
+        self.stats.ais_sent += 1;
+        Some(atm_oam_defect_cell(&ais.flow, ATM_OAM_AIS))
+    }
+
+    /// Stop sending AIS on a connection created again, returns true if it was.
+    pub fn stop_ais(&mut self, port: *mut c_void, vpi: u_int, vci: Option<u_int>) -> bool {
+        let len = self.ais.len();
+        self.ais.retain(|a| !(a.port == port && a.flow.vpi == vpi && a.flow.vci == vci));
+        self.ais.len() != len
+    }
+
+    /// Periodic processing: AIS cells to send, loopback timeouts.
+    pub fn tick(&mut self, now: m_tmcnt_t) -> Vec<(*mut c_void, AtmCell)> {
+        let before = self.loopbacks.len();
+        self.loopbacks.retain(|_, lb| now.saturating_sub(lb.2) < ATM_OAM_LOOPBACK_TIMEOUT);
+        self.stats.lb_timeouts += (before - self.loopbacks.len()) as m_uint64_t;
+
+        self.stats.ais_sent += self.ais.len() as m_uint64_t;
+        self.ais.iter().map(|a| (a.port, atm_oam_defect_cell(&a.flow, ATM_OAM_AIS))).collect()
+    }
+
+    /// Remove the AIS connections, returns their ports.
+    pub fn clear_ais(&mut self) -> Vec<*mut c_void> {
+        self.ais.drain(..).map(|a| a.port).collect()
+    }
+}
+
+/// Send a cell on a port (the HEC is not set).
+pub type atm_oam_send_cbk_t = Option<unsafe extern "C" fn(port: *mut c_void, cell: *mut u_char, opt: *mut c_void)>;
+/// Release the port of an AIS connection.
+pub type atm_oam_release_cbk_t = Option<unsafe extern "C" fn(port: *mut c_void, opt: *mut c_void)>;
+pub type atm_oam_ais_cbk_t = Option<unsafe extern "C" fn(port: *mut c_void, vpi: u_int, vci: c_int, opt: *mut c_void)>;
+
+pub struct atm_oam {
+    state: Mutex<AtmOam>,
+    timer: timer_id,
+    send: atm_oam_send_cbk_t,
+    release: atm_oam_release_cbk_t,
+    opt: *mut c_void,
+}
+pub type atm_oam_t = atm_oam;
+
+// the callback context is owned by the caller
+unsafe impl Send for atm_oam {}
+unsafe impl Sync for atm_oam {}
+
+impl atm_oam {
+    fn lock(&self) -> MutexGuard<'_, AtmOam> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    unsafe fn send(&self, port: *mut c_void, cell: &mut AtmCell) {
+        if let Some(send) = self.send {
+            send(port, cell.as_mut_ptr(), self.opt);
+        }
+    }
+
+    unsafe fn release(&self, port: *mut c_void) {
+        if let Some(release) = self.release {
+            release(port, self.opt);
+        }
+    }
+}
+
+fn atm_oam_vci(vci: c_int) -> Option<u_int> {
+    (vci >= 0).then_some(vci as u_int)
+}
+
+// Send the AIS cells
+unsafe extern "C" fn atm_oam_timer(opt: *mut c_void, _timer: *mut timer_entry_t) -> c_int {
+    let oam: *mut atm_oam_t = opt.cast::<_>();
+
+    // sent with the lock held, the ports may be released by atm_oam_stop_ais()
+    let mut state = (*oam).lock();
+    for (port, mut cell) in state.tick(m_gettime()) {
+        (*oam).send(port, &mut cell);
+    }
+    drop(state);
+    TRUE
+}
+
+// Create the OAM processing of an ATM switch
+#[no_mangle]
+pub unsafe extern "C" fn atm_oam_create(send: atm_oam_send_cbk_t, release: atm_oam_release_cbk_t, opt: *mut c_void) -> *mut atm_oam_t {
+    let oam = Box::into_raw(Box::new(atm_oam { state: Mutex::new(AtmOam::new()), timer: 0, send, release, opt }));
+
+    (*oam).timer = timer_create_entry(ATM_OAM_AIS_INTERVAL, FALSE, 10, Some(atm_oam_timer), oam.cast::<_>());
+    if (*oam).timer == 0 {
+        libc::fprintf(c_stderr(), c"atm_oam_create: unable to create AIS timer\n".as_ptr());
+        drop(Box::from_raw(oam));
+        return std::ptr::null_mut();
+    }
+    oam
+}
+
+// Free the OAM processing, the ports of the AIS connections are released
+#[no_mangle]
+pub unsafe extern "C" fn atm_oam_free(oam: *mut atm_oam_t) {
+    if !oam.is_null() {
+        timer_remove((*oam).timer);
+        let ports = (*oam).lock().clear_ais();
+        for port in ports {
+            (*oam).release(port);
+        }
+        drop(Box::from_raw(oam));
+    }
+}
+
+// Process a cell, returns an ATM_OAM_ACTION_* verdict (the reply is stored in resp, without HEC)
+#[no_mangle]
+pub unsafe extern "C" fn atm_oam_handle_cell(oam: *mut atm_oam_t, port: *mut c_void, cell: *const u_char, conn: c_int, resp: *mut u_char) -> c_int {
+    let cell = &*cell.cast::<AtmCell>();
+
+    // user cells do not need the lock
+    if AtmOamFlow::of_cell(cell).is_none() {
+        return ATM_OAM_ACTION_SWITCH;
+    }
+
+    match (*oam).lock().handle(port, cell, conn, m_gettime()) {
+        AtmOamVerdict::Switch => ATM_OAM_ACTION_SWITCH,
+        AtmOamVerdict::Drop => ATM_OAM_ACTION_DROP,
+        AtmOamVerdict::Reply(reply) => {
+            std::ptr::copy_nonoverlapping(reply.as_ptr(), resp, ATM_CELL_SIZE);
+            ATM_OAM_ACTION_REPLY
+        }
+    }
+}
+
+// Send a segment loopback request on a VP (vci ATM_OAM_VP) or a VC
+#[no_mangle]
+pub unsafe extern "C" fn atm_oam_send_loopback(oam: *mut atm_oam_t, port: *mut c_void, vpi: u_int, vci: c_int) {
+    let mut cell = (*oam).lock().loopback(port, vpi, atm_oam_vci(vci), m_gettime());
+    (*oam).send(port, &mut cell);
+}
+
+// Start sending AIS on the output of a deleted connection (the port reference is kept until released)
+#[no_mangle]
+pub unsafe extern "C" fn atm_oam_start_ais(oam: *mut atm_oam_t, port: *mut c_void, vpi: u_int, vci: c_int) -> c_int {
+    let cell = (*oam).lock().start_ais(port, vpi, atm_oam_vci(vci));
+    match cell {
+        Some(mut cell) => {
+            (*oam).send(port, &mut cell);
+            0
+        }
+        None => -1,
+    }
+}
+
+// Stop sending AIS on a connection created again, its port is released
+#[no_mangle]
+pub unsafe extern "C" fn atm_oam_stop_ais(oam: *mut atm_oam_t, port: *mut c_void, vpi: u_int, vci: c_int) {
+    if (*oam).lock().stop_ais(port, vpi, atm_oam_vci(vci)) {
+        (*oam).release(port);
+    }
+}
+
+// Call a function for each connection with AIS sent
+#[no_mangle]
+pub unsafe extern "C" fn atm_oam_foreach_ais(oam: *mut atm_oam_t, cb: atm_oam_ais_cbk_t, opt: *mut c_void) {
+    let ais = (*oam).lock().ais().to_vec();
+
+    if let Some(cb) = cb {
+        for a in ais {
+            cb(a.port, a.flow.vpi, a.flow.vci.map_or(ATM_OAM_VP, |vci| vci as c_int), opt);
+        }
+    }
+}
+
+// Get the OAM counters
+#[no_mangle]
+pub unsafe extern "C" fn atm_oam_get_stats(oam: *mut atm_oam_t, stats: *mut atm_oam_stats_t) {
+    *stats = (*oam).lock().stats();
+}
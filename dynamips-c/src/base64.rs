@@ -6,8 +6,15 @@
 //! This base 64 encoding is defined in RFC2045 section 6.8,
 //! "Base64 Content-Transfer-Encoding", but lines must not be broken in the
 //! scheme used here.
+//!
+//! The streaming encoder and decoder (base64_enc/base64_dec) work on chunks
+//! of any size, support line wrapping, the URL-safe alphabet of RFC4648
+//! section 5 and strict validation with the position of the first error.
 
 use crate::_extra::*;
+use crate::dynamips_common::*;
+use libc::size_t;
+use libc::ssize_t;
 use std::ffi::c_char;
 use std::ffi::c_int;
 use std::ffi::c_uchar;
@@ -144,3 +151,531 @@ pub unsafe extern "C" fn base64_decode(mut out: *mut c_uchar, mut in_: *const c_
 
     len
 }
+
+// Flags of the streaming encoder/decoder
+/// URL-safe alphabet ('-' and '_' instead of '+' and '/').
+pub const BASE64_URL: c_int = 0x01;
+/// Encoder: no padding. Strict decoder: padding is not allowed.
+pub const BASE64_NOPAD: c_int = 0x02;
+/// Decoder: reject anything but the canonical encoding (line breaks allowed).
+pub const BASE64_STRICT: c_int = 0x04;
+/// Encoder: wrap lines with CRLF instead of LF.
+pub const BASE64_CRLF: c_int = 0x08;
+
+// Decoding errors
+pub const BASE64_ERR_NONE: c_int = 0;
+pub const BASE64_ERR_CHAR: c_int = 1;
+pub const BASE64_ERR_PADDING: c_int = 2;
+pub const BASE64_ERR_TRAILING: c_int = 3;
+pub const BASE64_ERR_BITS: c_int = 4;
+pub const BASE64_ERR_LENGTH: c_int = 5;
+
+static base64urldigits: &[u8] = c"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_".to_bytes();
+
+fn alphabet(flags: c_int) -> &'static [u8] {
+    if (flags & BASE64_URL) != 0 {
+        base64urldigits
+    } else {
+        base64digits
+    }
+}
+
+/// Value of a digit, both alphabets are accepted unless `strict`.
+fn digit_value(c: u8, flags: c_int, strict: bool) -> u8 {
+    let url = (flags & BASE64_URL) != 0;
+    match c {
+        b'+' | b'/' if strict && url => BAD,
+        b'-' if !strict || url => 62,
+        b'_' if !strict || url => 63,
+        _ => DECODE64!(c),
+    }
+}
+
+/// Number of characters (padding included, line breaks excluded) for `len` bytes.
+fn encoded_chars(len: usize, flags: c_int) -> usize {
+    if (flags & BASE64_NOPAD) != 0 {
+        len / 3 * 4 + [0, 2, 3][len % 3]
+    } else {
+        len.div_ceil(3) * 4
+    }
+}
+
+/// Exact length of the encoding of `len` bytes (without NUL terminator).
+/// `line_len` is the line length, 0 to disable wrapping.
+pub fn base64_encoded_size(len: usize, flags: c_int, line_len: usize) -> usize {
+    base64_enc::new(flags, line_len).finish_len_after(len)
+}
+
+// Streaming base64 encoder
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct base64_enc {
+    pub flags: c_int,
+    /// Line length, 0 to disable wrapping.
+    pub line_len: size_t,
+    /// Characters on the current line.
+    pub col: size_t,
+    /// Bytes waiting for a complete group.
+    pub buf: [u8; 3],
+    pub buf_len: u8,
+}
+pub type base64_enc_t = base64_enc;
+
+impl base64_enc {
+    pub fn new(flags: c_int, line_len: usize) -> Self {
+        Self { flags, line_len, ..Default::default() }
+    }
+
+    fn eol(&self) -> &'static [u8] {
+        if (self.flags & BASE64_CRLF) != 0 {
+            b"\r\n"
+        } else {
+            b"\n"
+        }
+    }
+
+    /// Output length of `chars` characters with the line breaks.
+    fn wrapped_len(&self, chars: usize) -> usize {
+        if self.line_len == 0 || chars == 0 {
+            return chars;
+        }
+        chars + (self.col + chars - 1) / self.line_len * self.eol().len()
+    }
+
+    /// Exact output length of update() with `len` bytes.
+    pub fn update_len(&self, len: usize) -> usize {
+        self.wrapped_len((self.buf_len as usize + len) / 3 * 4)
+    }
+
+    /// Exact output length of finish().
+    pub fn finish_len(&self) -> usize {
+        self.wrapped_len(encoded_chars(self.buf_len as usize, self.flags))
+    }
+
+    /// Exact output length of update() with `len` bytes followed by finish().
+    pub fn finish_len_after(&self, len: usize) -> usize {
+        let len = self.buf_len as usize + len;
+        self.wrapped_len(len / 3 * 4 + encoded_chars(len % 3, self.flags))
+    }
+
+    fn put<F: FnMut(u8)>(&mut self, c: u8, out: &mut F) {
+        if self.line_len != 0 && self.col == self.line_len {
+            self.eol().iter().for_each(|&c| out(c));
+            self.col = 0;
+        }
+        out(c);
+        self.col += 1;
+    }
+
+    fn put_group<F: FnMut(u8)>(&mut self, group: &[u8], out: &mut F) {
+        let digits = alphabet(self.flags);
+        let b0 = group[0];
+        let b1 = group.get(1).copied().unwrap_or(0);
+        let b2 = group.get(2).copied().unwrap_or(0);
+        let chars = [digits[(b0 >> 2) as usize], digits[(((b0 << 4) & 0x30) | (b1 >> 4)) as usize], digits[(((b1 << 2) & 0x3c) | (b2 >> 6)) as usize], digits[(b2 & 0x3f) as usize]];
+        let count = group.len() + 1;
+
+        for &c in &chars[..count] {
+            self.put(c, out);
+        }
+        if (self.flags & BASE64_NOPAD) == 0 {
+            for _ in count..4 {
+                self.put(b'=', out);
+            }
+        }
+    }
+
+    /// Encode a chunk, the last incomplete group is kept for the next call.
+    pub fn update_with<F: FnMut(u8)>(&mut self, mut input: &[u8], mut out: F) {
+        if self.buf_len > 0 {
+            while self.buf_len < 3 && !input.is_empty() {
+                self.buf[self.buf_len as usize] = input[0];
+                self.buf_len += 1;
+                input = &input[1..];
+            }
+            if self.buf_len < 3 {
+                return;
+            }
+            let group = self.buf;
+            self.put_group(&group, &mut out);
+            self.buf_len = 0;
+        }
+
+        let mut groups = input.chunks_exact(3);
+        for group in &mut groups {
+            self.put_group(group, &mut out);
+        }
+        let rest = groups.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buf_len = rest.len() as u8;
+    }
+
+    /// Encode the last incomplete group and reset the line.
+    pub fn finish_with<F: FnMut(u8)>(&mut self, mut out: F) {
+        if self.buf_len > 0 {
+            let group = self.buf;
+            self.put_group(&group[..self.buf_len as usize], &mut out);
+        }
+        *self = Self::new(self.flags, self.line_len);
+    }
+
+    pub fn update(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        out.reserve(self.update_len(input.len()));
+        self.update_with(input, |c| out.push(c));
+    }
+
+    pub fn finish(&mut self, out: &mut Vec<u8>) {
+        out.reserve(self.finish_len());
+        self.finish_with(|c| out.push(c));
+    }
+}
+
+/// Error of the base64 decoder, `pos` is the offset in the whole input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Base64Error {
+    /// Character outside of the alphabet.
+    Char { pos: u64, c: u8 },
+    /// Misplaced, missing or unexpected padding.
+    Padding { pos: u64 },
+    /// Data after the padding (strict).
+    Trailing { pos: u64 },
+    /// Non-zero unused bits in the last digit (strict).
+    Bits { pos: u64 },
+    /// Truncated input (a single digit in the last group).
+    Length { pos: u64 },
+}
+
+impl Base64Error {
+    /// Error code for the C API (BASE64_ERR_*).
+    pub fn code(&self) -> c_int {
+        match self {
+            Base64Error::Char { .. } => BASE64_ERR_CHAR,
+            Base64Error::Padding { .. } => BASE64_ERR_PADDING,
+            Base64Error::Trailing { .. } => BASE64_ERR_TRAILING,
+            Base64Error::Bits { .. } => BASE64_ERR_BITS,
+            Base64Error::Length { .. } => BASE64_ERR_LENGTH,
+        }
+    }
+
+    pub fn pos(&self) -> u64 {
+        match *self {
+            Base64Error::Char { pos, .. } | Base64Error::Padding { pos } | Base64Error::Trailing { pos } | Base64Error::Bits { pos } | Base64Error::Length { pos } => pos,
+        }
+    }
+}
+
+impl std::fmt::Display for Base64Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Base64Error::Char { pos, c } => write!(f, "invalid character 0x{:02x} at offset {}", c, pos),
+            Base64Error::Padding { pos } => write!(f, "invalid padding at offset {}", pos),
+            Base64Error::Trailing { pos } => write!(f, "data after padding at offset {}", pos),
+            Base64Error::Bits { pos } => write!(f, "non-zero trailing bits at offset {}", pos),
+            Base64Error::Length { pos } => write!(f, "truncated input at offset {}", pos),
+        }
+    }
+}
+
+impl std::error::Error for Base64Error {}
+
+// Streaming base64 decoder
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct base64_dec {
+    pub flags: c_int,
+    /// Digit values of the current group.
+    pub group: [u8; 4],
+    pub group_len: u8,
+    /// Padding characters seen in the current group.
+    pub pad: u8,
+    /// The padding of the last group is complete.
+    pub done: u8,
+    /// Offset in the whole input.
+    pub pos: m_uint64_t,
+    /// Offset of the last digit.
+    pub last_pos: m_uint64_t,
+    /// First error (BASE64_ERR_*) and its offset.
+    pub err: c_int,
+    pub err_pos: m_uint64_t,
+    pub err_char: u8,
+}
+pub type base64_dec_t = base64_dec;
+
+impl base64_dec {
+    pub fn new(flags: c_int) -> Self {
+        Self { flags, ..Default::default() }
+    }
+
+    fn strict(&self) -> bool {
+        (self.flags & BASE64_STRICT) != 0
+    }
+
+    /// Upper bound of the output length of update() with `len` characters.
+    pub fn update_len(&self, len: usize) -> usize {
+        (self.group_len as usize + self.pad as usize + len) / 4 * 3
+    }
+
+    fn fail(&mut self, err: Base64Error) -> Result<usize, Base64Error> {
+        self.err = err.code();
+        self.err_pos = err.pos();
+        if let Base64Error::Char { c, .. } = err {
+            self.err_char = c;
+        }
+        Err(err)
+    }
+
+    /// Output the current group of `group_len` digits.
+    fn flush<F: FnMut(u8)>(&mut self, out: &mut F) -> Result<usize, Base64Error> {
+        let g = self.group;
+        let n = self.group_len as usize;
+
+        if n == 1 {
+            return self.fail(Base64Error::Length { pos: self.pos });
+        }
+        if self.strict() && ((n == 2 && (g[1] & 0x0f) != 0) || (n == 3 && (g[2] & 0x03) != 0)) {
+            return self.fail(Base64Error::Bits { pos: self.last_pos });
+        }
+
+        let bytes = [(g[0] << 2) | (g[1] >> 4), (g[1] << 4) | (g[2] >> 2), (g[2] << 6) | g[3]];
+        bytes[..n - 1].iter().for_each(|&b| out(b));
+        self.group = [0; 4];
+        self.group_len = 0;
+        Ok(n - 1)
+    }
+
+    /// Decode a chunk, returns the number of bytes written.
+    pub fn update_with<F: FnMut(u8)>(&mut self, input: &[u8], mut out: F) -> Result<usize, Base64Error> {
+        let strict = self.strict();
+        let mut count = 0;
+
+        if self.err != BASE64_ERR_NONE {
+            return Err(self.error().unwrap());
+        }
+
+        for &c in input {
+            let pos = self.pos;
+            self.pos += 1;
+
+            match c {
+                b'\r' | b'\n' => continue,
+                b' ' | b'\t' | b'\x0b' | b'\x0c' if !strict => continue,
+                b'=' => {
+                    if self.done != 0 || self.group_len < 2 || (strict && (self.flags & BASE64_NOPAD) != 0) {
+                        return self.fail(Base64Error::Padding { pos });
+                    }
+                    self.pad += 1;
+                    if self.group_len + self.pad == 4 {
+                        count += self.flush(&mut out)?;
+                        self.pad = 0;
+                        self.done = 1;
+                    }
+                }
+                _ => {
+                    let v = digit_value(c, self.flags, strict);
+                    if v == BAD {
+                        return self.fail(Base64Error::Char { pos, c });
+                    }
+                    if self.pad != 0 {
+                        return self.fail(Base64Error::Padding { pos });
+                    }
+                    if self.done != 0 {
+                        // concatenated encodings are accepted when lenient
+                        if strict {
+                            return self.fail(Base64Error::Trailing { pos });
+                        }
+                        self.done = 0;
+                    }
+                    self.group[self.group_len as usize] = v;
+                    self.group_len += 1;
+                    self.last_pos = pos;
+                    if self.group_len == 4 {
+                        count += self.flush(&mut out)?;
+                    }
+                }
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Check the end of input and output the last group (up to 2 bytes).
+    pub fn finish_with<F: FnMut(u8)>(&mut self, mut out: F) -> Result<usize, Base64Error> {
+        if self.err != BASE64_ERR_NONE {
+            return Err(self.error().unwrap());
+        }
+        if self.group_len == 0 {
+            return Ok(0);
+        }
+        // strict mode rejects a partial padding, and a missing one unless BASE64_NOPAD;
+        // lenient mode accepts both
+        if (self.pad != 0 && self.strict()) || (self.strict() && (self.flags & BASE64_NOPAD) == 0) {
+            return self.fail(Base64Error::Padding { pos: self.pos });
+        }
+        let count = self.flush(&mut out)?;
+        self.pad = 0;
+        self.done = 1;
+        Ok(count)
+    }
+
+    pub fn update(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<usize, Base64Error> {
+        out.reserve(self.update_len(input.len()));
+        self.update_with(input, |b| out.push(b))
+    }
+
+    pub fn finish(&mut self, out: &mut Vec<u8>) -> Result<usize, Base64Error> {
+        self.finish_with(|b| out.push(b))
+    }
+
+    /// First error, the decoder must be reset after an error.
+    pub fn error(&self) -> Option<Base64Error> {
+        let pos = self.err_pos;
+        match self.err {
+            BASE64_ERR_CHAR => Some(Base64Error::Char { pos, c: self.err_char }),
+            BASE64_ERR_PADDING => Some(Base64Error::Padding { pos }),
+            BASE64_ERR_TRAILING => Some(Base64Error::Trailing { pos }),
+            BASE64_ERR_BITS => Some(Base64Error::Bits { pos }),
+            BASE64_ERR_LENGTH => Some(Base64Error::Length { pos }),
+            _ => None,
+        }
+    }
+}
+
+/// Decode a whole buffer.
+pub fn base64_decode_all(input: &[u8], flags: c_int) -> Result<Vec<u8>, Base64Error> {
+    let mut dec = base64_dec::new(flags);
+    let mut out = Vec::with_capacity(input.len() / 4 * 3 + 2);
+    dec.update(input, &mut out)?;
+    dec.finish(&mut out)?;
+    Ok(out)
+}
+
+/// Encode a whole buffer.
+pub fn base64_encode_all(input: &[u8], flags: c_int, line_len: usize) -> Vec<u8> {
+    let mut enc = base64_enc::new(flags, line_len);
+    let mut out = Vec::with_capacity(base64_encoded_size(input.len(), flags, line_len));
+    enc.update(input, &mut out);
+    enc.finish(&mut out);
+    out
+}
+
+unsafe fn raw_writer(out: *mut c_uchar) -> impl FnMut(u8) {
+    let mut p = out;
+    move |b| {
+        *p = b;
+        p = p.add(1);
+    }
+}
+
+unsafe fn raw_input<'a>(in_: *const c_uchar, len: size_t) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(in_, len)
+    }
+}
+
+// Exact length of the base64 encoding of inlen bytes (without NUL terminator)
+#[no_mangle]
+pub extern "C" fn base64_encoded_len(inlen: size_t, flags: c_int, line_len: size_t) -> size_t {
+    base64_encoded_size(inlen, flags, line_len)
+}
+
+// Length of the data decoded from a base64 string, exact for valid input (-1 if invalid)
+#[no_mangle]
+pub unsafe extern "C" fn base64_decoded_len(in_: *const c_uchar, inlen: size_t, flags: c_int) -> ssize_t {
+    let mut dec = base64_dec::new(flags);
+    let mut len: ssize_t = 0;
+
+    if dec.update_with(raw_input(in_, inlen), |_| len += 1).is_err() || dec.finish_with(|_| len += 1).is_err() {
+        return -1;
+    }
+    len
+}
+
+// Initialize a base64 encoder, line_len is 0 to disable wrapping
+#[no_mangle]
+pub unsafe extern "C" fn base64_enc_init(ctx: *mut base64_enc_t, flags: c_int, line_len: size_t) {
+    *ctx = base64_enc::new(flags, line_len);
+}
+
+// Exact output length of base64_enc_update() with inlen bytes
+#[no_mangle]
+pub unsafe extern "C" fn base64_enc_update_len(ctx: *const base64_enc_t, inlen: size_t) -> size_t {
+    (*ctx).update_len(inlen)
+}
+
+// Encode a chunk, returns the number of characters written (not NUL-terminated)
+#[no_mangle]
+pub unsafe extern "C" fn base64_enc_update(ctx: *mut base64_enc_t, out: *mut c_uchar, in_: *const c_uchar, inlen: size_t) -> size_t {
+    let len = (*ctx).update_len(inlen);
+    (*ctx).update_with(raw_input(in_, inlen), raw_writer(out));
+    len
+}
+
+// Exact output length of base64_enc_finish()
+#[no_mangle]
+pub unsafe extern "C" fn base64_enc_finish_len(ctx: *const base64_enc_t) -> size_t {
+    (*ctx).finish_len()
+}
+
+// Terminate an encoding, returns the number of characters written (not NUL-terminated)
+#[no_mangle]
+pub unsafe extern "C" fn base64_enc_finish(ctx: *mut base64_enc_t, out: *mut c_uchar) -> size_t {
+    let len = (*ctx).finish_len();
+    (*ctx).finish_with(raw_writer(out));
+    len
+}
+
+// Initialize a base64 decoder
+#[no_mangle]
+pub unsafe extern "C" fn base64_dec_init(ctx: *mut base64_dec_t, flags: c_int) {
+    *ctx = base64_dec::new(flags);
+}
+
+// Maximum output length of base64_dec_update() with inlen characters
+#[no_mangle]
+pub unsafe extern "C" fn base64_dec_update_len(ctx: *const base64_dec_t, inlen: size_t) -> size_t {
+    (*ctx).update_len(inlen)
+}
+
+// Decode a chunk, returns the number of bytes written or -1 on error
+#[no_mangle]
+pub unsafe extern "C" fn base64_dec_update(ctx: *mut base64_dec_t, out: *mut c_uchar, in_: *const c_uchar, inlen: size_t) -> ssize_t {
+    match (*ctx).update_with(raw_input(in_, inlen), raw_writer(out)) {
+        Ok(len) => len as ssize_t,
+        Err(_) => -1,
+    }
+}
+
+// Terminate a decoding (writes up to 2 bytes), returns the number of bytes written or -1 on error
+#[no_mangle]
+pub unsafe extern "C" fn base64_dec_finish(ctx: *mut base64_dec_t, out: *mut c_uchar) -> ssize_t {
+    match (*ctx).finish_with(raw_writer(out)) {
+        Ok(len) => len as ssize_t,
+        Err(_) => -1,
+    }
+}
+
+// Get the error of a decoder (BASE64_ERR_*) and its offset in the input
+#[no_mangle]
+pub unsafe extern "C" fn base64_dec_error(ctx: *const base64_dec_t, pos: *mut m_uint64_t) -> c_int {
+    if !pos.is_null() {
+        *pos = (*ctx).err_pos;
+    }
+    (*ctx).err
+}
+
+// Get the message of a decoding error
+#[no_mangle]
+pub extern "C" fn base64_strerror(err: c_int) -> *const c_char {
+    let msg = match err {
+        BASE64_ERR_NONE => c"no error",
+        BASE64_ERR_CHAR => c"invalid character",
+        BASE64_ERR_PADDING => c"invalid padding",
+        BASE64_ERR_TRAILING => c"data after padding",
+        BASE64_ERR_BITS => c"non-zero trailing bits",
+        BASE64_ERR_LENGTH => c"truncated input",
+        _ => c"unknown error",
+    };
+    msg.as_ptr()
+}
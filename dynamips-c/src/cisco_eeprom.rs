@@ -4,6 +4,7 @@
 //! Cisco EEPROM manipulation functions.
 
 use crate::dynamips_common::*;
+use crate::net::*;
 use libc::size_t;
 use std::ffi::c_char;
 use std::ffi::c_int;
@@ -754,14 +755,14 @@ pub unsafe extern "C" fn cisco_eeprom_set_region(eeprom: *mut cisco_eeprom, offs
 
 // Get a field of a Cisco EEPROM v4
 #[no_mangle]
-pub unsafe extern "C" fn cisco_eeprom_v4_get_field(eeprom: *mut cisco_eeprom, type_: *mut m_uint8_t, len: *mut m_uint8_t, mut offset: *mut size_t) -> c_int {
+pub unsafe extern "C" fn cisco_eeprom_v4_get_field(eeprom: *mut cisco_eeprom, type_: *mut m_uint8_t, len: *mut m_uint8_t, offset: *mut size_t) -> c_int {
     let mut tmp: m_uint8_t;
 
     // Read field type
     if cisco_eeprom_get_byte(eeprom, *offset, type_) == -1 {
         return -1;
     }
-    offset = offset.add(1);
+    *offset += 1;
 
     // No more field
     if *type_ == 0xFF {
@@ -776,10 +777,7 @@ pub unsafe extern "C" fn cisco_eeprom_v4_get_field(eeprom: *mut cisco_eeprom, ty
         if cisco_eeprom_get_byte(eeprom, *offset, addr_of_mut!(tmp)) == -1 {
             return -1;
         }
-        #[allow(unused_assignments)]
-        {
-            offset = offset.add(1);
-        }
+        *offset += 1;
 
         *len = tmp & 0x0F;
     } else {
@@ -872,3 +870,44 @@ pub unsafe extern "C" fn cisco_eeprom_v4_find_field(eeprom: *mut cisco_eeprom, f
 
     -1
 }
+
+// Set the MAC address of an EEPROM.
+// Format version 0 stores it at offset 2, format version 4 in the chassis MAC address field
+// (an EEPROM without this field is left unchanged).
+#[no_mangle]
+pub unsafe extern "C" fn cisco_eeprom_set_mac_addr(eeprom: *mut cisco_eeprom, addr: *const n_eth_addr_t) -> c_int {
+    let mut eeprom_ver: m_uint8_t = 0;
+    let mut offset: size_t = 0;
+    let mut mac: [m_uint8_t; N_ETH_ALEN] = (*addr).eth_addr_byte;
+
+    if cisco_eeprom_get_byte(eeprom, 0, addr_of_mut!(eeprom_ver)) == -1 {
+        return -1;
+    }
+
+    match eeprom_ver {
+        0 => cisco_eeprom_set_region(eeprom, 2, mac.as_mut_ptr(), N_ETH_ALEN),
+        4 => {
+            if cisco_eeprom_v4_find_field(eeprom, 0xC3, addr_of_mut!(offset)) == 0 {
+                cisco_eeprom_set_region(eeprom, offset, mac.as_mut_ptr(), N_ETH_ALEN);
+            }
+            0
+        }
+        _ => -1,
+    }
+}
+
+// Set the MAC address block size of an EEPROM with format version 4
+#[no_mangle]
+pub unsafe extern "C" fn cisco_eeprom_set_mac_block_size(eeprom: *mut cisco_eeprom, size: m_uint16_t) -> c_int {
+    let mut eeprom_ver: m_uint8_t = 0;
+    let mut offset: size_t = 0;
+    let mut val: [m_uint8_t; 2] = size.to_be_bytes();
+
+    if cisco_eeprom_get_byte(eeprom, 0, addr_of_mut!(eeprom_ver)) == -1 || eeprom_ver != 4 {
+        return -1;
+    }
+    if cisco_eeprom_v4_find_field(eeprom, 0x43, addr_of_mut!(offset)) == -1 {
+        return -1;
+    }
+    cisco_eeprom_set_region(eeprom, offset, val.as_mut_ptr(), val.len())
+}
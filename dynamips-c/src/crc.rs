@@ -2,9 +2,15 @@
 //! Copyright (c) 2006 Christophe Fillot (cf@utc.fr)
 //!
 //! CRC functions.
+//!
+//! CRC-32 and CRC-32C use the CRC instructions of the CPU when available
+//! (SSE4.2 for CRC-32C, ARMv8 CRC for both), slicing-by-8 tables otherwise.
+//! The implementation is selected by crc_init().
 
 use crate::dynamips_common::*;
+use std::ffi::c_char;
 use std::ffi::c_int;
+use std::sync::Once;
 
 // Compute a CRC-12 hash on a 32-bit integer
 #[inline(always)]
@@ -38,23 +44,252 @@ pub unsafe extern "C" fn crc16_hash_u32(mut val: m_uint32_t) -> m_uint32_t {
 #[inline(always)]
 #[no_mangle]
 pub unsafe extern "C" fn crc32_compute(crc_accum: m_uint32_t, ptr: *mut m_uint8_t, len: c_int) -> m_uint32_t {
-    let mut c: m_uint32_t = crc_accum;
+    !crc32_update(crc_accum, block(ptr, len))
+}
+
+// Compute a CRC-32C (Castagnoli) on the specified block
+#[no_mangle]
+pub unsafe extern "C" fn crc32c_compute(crc_accum: m_uint32_t, ptr: *mut m_uint8_t, len: c_int) -> m_uint32_t {
+    !crc32c_update(crc_accum, block(ptr, len))
+}
+
+// Compute a CRC-16/CCITT (HDLC FCS, reflected) on the specified block.
+// Use 0xFFFF for the first block, the result is the FCS to append (LSB first).
+#[no_mangle]
+pub unsafe extern "C" fn crc16_ccitt_compute(crc_accum: m_uint16_t, ptr: *mut m_uint8_t, len: c_int) -> m_uint16_t {
+    !crc16_ccitt_update(crc_accum, block(ptr, len))
+}
+
+// Compute a CRC-10 (ATM OAM) on the specified block
+#[no_mangle]
+pub unsafe extern "C" fn crc10_compute(crc_accum: m_uint16_t, ptr: *mut m_uint8_t, len: c_int) -> m_uint16_t {
+    crc10_update(crc_accum, block(ptr, len))
+}
+
+// Compute the CRC-10 of a 48-byte ATM OAM cell payload (the last 10 bits
+// hold the CRC and are ignored)
+#[no_mangle]
+pub unsafe extern "C" fn crc10_oam_compute(payload: *const m_uint8_t) -> m_uint16_t {
+    crc10_oam(&*payload.cast::<[u8; 48]>())
+}
+
+// Name of the CRC-32 implementation selected by crc_init()
+#[no_mangle]
+pub unsafe extern "C" fn crc32_impl_name() -> *const c_char {
+    match crc32_impl {
+        CRC_IMPL_ARMV8 => c"armv8-crc".as_ptr(),
+        _ => c"slicing-by-8".as_ptr(),
+    }
+}
+
+// Name of the CRC-32C implementation selected by crc_init()
+#[no_mangle]
+pub unsafe extern "C" fn crc32c_impl_name() -> *const c_char {
+    match crc32c_impl {
+        CRC_IMPL_SSE42 => c"sse4.2".as_ptr(),
+        CRC_IMPL_ARMV8 => c"armv8-crc".as_ptr(),
+        _ => c"slicing-by-8".as_ptr(),
+    }
+}
+
+unsafe fn block<'a>(ptr: *const m_uint8_t, len: c_int) -> &'a [u8] {
+    if len <= 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, len as usize)
+    }
+}
+
+/// Update a CRC-32 register (no final inversion).
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    unsafe {
+        match crc32_impl {
+            #[cfg(target_arch = "aarch64")]
+            CRC_IMPL_ARMV8 => crc32_armv8(crc, data),
+            _ => crc_slice8(&*std::ptr::addr_of!(crc32_slice_array), crc, data),
+        }
+    }
+}
+
+/// Update a CRC-32C register (no final inversion).
+pub fn crc32c_update(crc: u32, data: &[u8]) -> u32 {
+    unsafe {
+        match crc32c_impl {
+            #[cfg(target_arch = "x86_64")]
+            CRC_IMPL_SSE42 => crc32c_sse42(crc, data),
+            #[cfg(target_arch = "aarch64")]
+            CRC_IMPL_ARMV8 => crc32c_armv8(crc, data),
+            _ => crc32c_update_sw(crc, data),
+        }
+    }
+}
+
+/// CRC-32 with the slicing-by-8 tables, whatever the selected implementation.
+pub fn crc32_update_sw(crc: u32, data: &[u8]) -> u32 {
+    unsafe { crc_slice8(&*std::ptr::addr_of!(crc32_slice_array), crc, data) }
+}
+
+/// CRC-32C with the slicing-by-8 tables, whatever the selected implementation.
+pub fn crc32c_update_sw(crc: u32, data: &[u8]) -> u32 {
+    unsafe { crc_slice8(&*std::ptr::addr_of!(crc32c_slice_array), crc, data) }
+}
+
+/// Update a CRC-16/CCITT register (reflected, no final inversion).
+pub fn crc16_ccitt_update(mut crc: u16, data: &[u8]) -> u16 {
+    for &b in data {
+        crc = (crc >> 8) ^ unsafe { crc16_ccitt_array[((crc ^ b as u16) & 0xff) as usize] };
+    }
+    crc
+}
+
+/// Update a CRC-10 register (MSB first).
+pub fn crc10_update(mut crc: u16, data: &[u8]) -> u16 {
+    for &b in data {
+        crc = ((crc << 8) ^ unsafe { crc10_array[(((crc >> 2) ^ b as u16) & 0xff) as usize] }) & 0x3ff;
+    }
+    crc
+}
+
+/// CRC-10 of an OAM cell payload, on the 374 bits before the CRC field.
+/// A payload with a valid CRC field gives 0 with crc10_update().
+pub fn crc10_oam(payload: &[u8; 48]) -> u16 {
+    let mut crc = crc10_update(0, &payload[..46]);
+
+    for i in (2..8).rev() {
+        let bit = ((payload[46] >> i) & 1) as u16;
+        let feedback = ((crc >> 9) & 1) ^ bit;
+        crc = (crc << 1) & 0x3ff;
+        if feedback != 0 {
+            crc ^= CRC10_POLY;
+        }
+    }
+    crc
+}
+
+/// Reflected CRC-32 with slicing-by-8 tables.
+fn crc_slice8(t: &[[u32; 256]; 8], mut crc: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(8);
+
+    for chunk in &mut chunks {
+        let lo = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) ^ crc;
+        let hi = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+
+        crc = t[7][(lo & 0xff) as usize]
+            ^ t[6][((lo >> 8) & 0xff) as usize]
+            ^ t[5][((lo >> 16) & 0xff) as usize]
+            ^ t[4][(lo >> 24) as usize]
+            ^ t[3][(hi & 0xff) as usize]
+            ^ t[2][((hi >> 8) & 0xff) as usize]
+            ^ t[1][((hi >> 16) & 0xff) as usize]
+            ^ t[0][(hi >> 24) as usize];
+    }
+
+    for &b in chunks.remainder() {
+        crc = t[0][((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn crc32c_sse42(crc: u32, data: &[u8]) -> u32 {
+    use std::arch::x86_64::*;
 
-    for n in 0..len {
-        c = crc32_array[((c ^ *ptr.offset(n as isize) as m_uint32_t) & 0xff) as usize] ^ (c >> 8);
+    let mut chunks = data.chunks_exact(8);
+    let mut c = crc as u64;
+
+    for chunk in &mut chunks {
+        c = _mm_crc32_u64(c, u64::from_le_bytes(chunk.try_into().unwrap()));
+    }
+
+    let mut c = c as u32;
+    for &b in chunks.remainder() {
+        c = _mm_crc32_u8(c, b);
+    }
+    c
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "crc")]
+unsafe fn crc32_armv8(mut crc: u32, data: &[u8]) -> u32 {
+    use std::arch::aarch64::*;
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        crc = __crc32d(crc, u64::from_le_bytes(chunk.try_into().unwrap()));
+    }
+    for &b in chunks.remainder() {
+        crc = __crc32b(crc, b);
+    }
+    crc
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "crc")]
+unsafe fn crc32c_armv8(mut crc: u32, data: &[u8]) -> u32 {
+    use std::arch::aarch64::*;
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        crc = __crc32cd(crc, u64::from_le_bytes(chunk.try_into().unwrap()));
+    }
+    for &b in chunks.remainder() {
+        crc = __crc32cb(crc, b);
+    }
+    crc
+}
+
+/// CRC-32C with the CPU instructions, None if not supported.
+pub fn crc32c_update_hw(crc: u32, data: &[u8]) -> Option<u32> {
+    #[cfg(target_arch = "x86_64")]
+    if std::arch::is_x86_feature_detected!("sse4.2") {
+        return Some(unsafe { crc32c_sse42(crc, data) });
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("crc") {
+        return Some(unsafe { crc32c_armv8(crc, data) });
     }
+    let _ = (crc, data);
+    None
+}
 
-    !c
+/// CRC-32 with the CPU instructions, None if not supported.
+pub fn crc32_update_hw(crc: u32, data: &[u8]) -> Option<u32> {
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("crc") {
+        return Some(unsafe { crc32_armv8(crc, data) });
+    }
+    let _ = (crc, data);
+    None
 }
 
 const CRC12_POLY: m_uint16_t = 0x0f01;
 const CRC16_POLY: m_uint16_t = 0xa001;
 const CRC32_POLY: m_uint32_t = 0xedb88320;
+const CRC32C_POLY: m_uint32_t = 0x82f63b78;
+const CRC16_CCITT_POLY: m_uint16_t = 0x8408;
+const CRC10_POLY: m_uint16_t = 0x233;
+
+/// Residue of a CRC-16/CCITT register after a frame with a good FCS.
+pub const CRC16_CCITT_GOOD: m_uint16_t = 0xf0b8;
+
+// CRC implementations
+pub const CRC_IMPL_TABLE: c_int = 0;
+pub const CRC_IMPL_SSE42: c_int = 1;
+pub const CRC_IMPL_ARMV8: c_int = 2;
 
 // CRC tables
 pub static mut crc12_array: [m_uint16_t; 256] = [0; 256];
 pub static mut crc16_array: [m_uint16_t; 256] = [0; 256];
 pub static mut crc32_array: [m_uint32_t; 256] = [0; 256];
+pub static mut crc16_ccitt_array: [m_uint16_t; 256] = [0; 256];
+pub static mut crc10_array: [m_uint16_t; 256] = [0; 256];
+static mut crc32_slice_array: [[m_uint32_t; 256]; 8] = [[0; 256]; 8];
+static mut crc32c_slice_array: [[m_uint32_t; 256]; 8] = [[0; 256]; 8];
+
+// Selected implementations
+static mut crc32_impl: c_int = CRC_IMPL_TABLE;
+static mut crc32c_impl: c_int = CRC_IMPL_TABLE;
 
 // Initialize CRC-12 algorithm
 unsafe fn crc12_init() {
@@ -94,25 +329,94 @@ unsafe fn crc16_init() {
     }
 }
 
-/* Initialize CRC-32 algorithm */
-unsafe fn crc32_init() {
+/* Build the slicing-by-8 tables of a reflected 32-bit CRC */
+fn crc32_slice_init(t: &mut [[m_uint32_t; 256]; 8], poly: m_uint32_t) {
     for n in 0..256 {
         let mut c: m_uint32_t = n as m_uint32_t;
         for _ in 0..8 {
             if (c & 1) != 0 {
-                c = CRC32_POLY ^ (c >> 1);
+                c = poly ^ (c >> 1);
             } else {
                 c >>= 1;
             }
         }
-        crc32_array[n] = c;
+        t[0][n] = c;
+    }
+
+    for n in 0..256 {
+        for k in 1..8 {
+            let c = t[k - 1][n];
+            t[k][n] = (c >> 8) ^ t[0][(c & 0xff) as usize];
+        }
+    }
+}
+
+/* Initialize CRC-32 algorithm */
+unsafe fn crc32_init() {
+    let t = &mut *std::ptr::addr_of_mut!(crc32_slice_array);
+    crc32_slice_init(t, CRC32_POLY);
+    crc32_array = t[0];
+
+    crc32_slice_init(&mut *std::ptr::addr_of_mut!(crc32c_slice_array), CRC32C_POLY);
+}
+
+/* Initialize CRC-16/CCITT algorithm */
+unsafe fn crc16_ccitt_init() {
+    for i in 0..256 {
+        let mut crc: m_uint16_t = i as m_uint16_t;
+
+        for _ in 0..8 {
+            if (crc & 0x0001) != 0 {
+                crc = (crc >> 1) ^ CRC16_CCITT_POLY;
+            } else {
+                crc >>= 1;
+            }
+        }
+
+        crc16_ccitt_array[i] = crc;
+    }
+}
+
+/* Initialize CRC-10 algorithm */
+unsafe fn crc10_init() {
+    for i in 0..256 {
+        let mut crc: m_uint16_t = (i as m_uint16_t) << 2;
+
+        for _ in 0..8 {
+            crc <<= 1;
+            if (crc & 0x400) != 0 {
+                crc ^= 0x400 | CRC10_POLY;
+            }
+        }
+
+        crc10_array[i] = crc;
+    }
+}
+
+/* Select the fastest implementations supported by the CPU */
+unsafe fn crc_select_impl() {
+    #[cfg(target_arch = "x86_64")]
+    if std::arch::is_x86_feature_detected!("sse4.2") {
+        crc32c_impl = CRC_IMPL_SSE42;
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("crc") {
+        crc32_impl = CRC_IMPL_ARMV8;
+        crc32c_impl = CRC_IMPL_ARMV8;
     }
 }
 
 /* Initialize CRC algorithms */
 #[no_mangle]
 pub unsafe extern "C" fn crc_init() {
-    crc12_init();
-    crc16_init();
-    crc32_init();
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        crc12_init();
+        crc16_init();
+        crc32_init();
+        crc16_ccitt_init();
+        crc10_init();
+        crc_select_impl();
+    });
 }
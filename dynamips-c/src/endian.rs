@@ -0,0 +1,238 @@
+//! Endianness-typed values and guest memory views.
+//!
+//! `Be16`/`Be32`/`Be64` and `Le16`/`Le32`/`Le64` hold a value in a fixed
+//! byte order. They have the layout of the plain integer (C sees them as
+//! `uint16_t`, ...) so they can be used as fields of packed headers that are
+//! mapped over packet or guest memory: reading a field with `get()` always
+//! returns the native value and writing it with `new()`/`set()` always stores
+//! the right byte order, so a swap can no longer be forgotten.
+//!
+//! `Vm16`/`Vm32`/`Vm64` are the byte order of the emulated guests, which is
+//! the one used by htovm16/vmtoh16 and friends.
+//!
+//! `GuestBuf` is a bounds-checked view over a byte buffer (a slice or a raw
+//! pointer into guest memory) with typed get/set at byte offsets.
+
+use crate::dynamips_common::*;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ptr::read_unaligned;
+use std::ptr::write_unaligned;
+
+// Methods of the endian types. The types themselves are declared outside of
+// the macro so cbindgen sees them.
+macro_rules! endian_impl {
+    ($name:ident, $ty:ty, $to:ident, $from:ident) => {
+        impl $name {
+            /// Value from a native integer.
+            #[inline(always)]
+            pub const fn new(x: $ty) -> Self {
+                Self(x.$to())
+            }
+            /// Value from the raw bits, already in the target byte order.
+            #[inline(always)]
+            pub const fn from_raw(raw: $ty) -> Self {
+                Self(raw)
+            }
+            /// Native integer.
+            #[inline(always)]
+            pub const fn get(self) -> $ty {
+                <$ty>::$from(self.0)
+            }
+            /// Store a native integer.
+            #[inline(always)]
+            pub fn set(&mut self, x: $ty) {
+                self.0 = x.$to();
+            }
+            /// Raw bits, in the target byte order.
+            #[inline(always)]
+            pub const fn to_raw(self) -> $ty {
+                self.0
+            }
+        }
+
+        impl From<$ty> for $name {
+            #[inline(always)]
+            fn from(x: $ty) -> Self {
+                Self::new(x)
+            }
+        }
+
+        impl From<$name> for $ty {
+            #[inline(always)]
+            fn from(x: $name) -> Self {
+                x.get()
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{:#x}", self.get())
+            }
+        }
+    };
+}
+
+/// 16-bit big endian (network byte order) value.
+#[repr(transparent)]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Be16(m_uint16_t);
+endian_impl!(Be16, m_uint16_t, to_be, from_be);
+
+/// 32-bit big endian (network byte order) value.
+#[repr(transparent)]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Be32(m_uint32_t);
+endian_impl!(Be32, m_uint32_t, to_be, from_be);
+
+/// 64-bit big endian (network byte order) value.
+#[repr(transparent)]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Be64(m_uint64_t);
+endian_impl!(Be64, m_uint64_t, to_be, from_be);
+
+/// 16-bit little endian value.
+#[repr(transparent)]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Le16(m_uint16_t);
+endian_impl!(Le16, m_uint16_t, to_le, from_le);
+
+/// 32-bit little endian value.
+#[repr(transparent)]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Le32(m_uint32_t);
+endian_impl!(Le32, m_uint32_t, to_le, from_le);
+
+/// 64-bit little endian value.
+#[repr(transparent)]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Le64(m_uint64_t);
+endian_impl!(Le64, m_uint64_t, to_le, from_le);
+
+/// 16-bit value in guest byte order (see htovm16/vmtoh16).
+pub type Vm16 = Be16;
+/// 32-bit value in guest byte order (see htovm32/vmtoh32).
+pub type Vm32 = Be32;
+/// 64-bit value in guest byte order (see htovm64/vmtoh64).
+pub type Vm64 = Be64;
+
+/// Bounds-checked view over a byte buffer with typed accessors.
+///
+/// Offsets are in bytes and need no alignment. An access outside of the
+/// buffer panics, like slice indexing.
+#[derive(Debug)]
+pub struct GuestBuf<'a> {
+    ptr: *mut u8,
+    len: usize,
+    _marker: PhantomData<&'a mut [u8]>,
+}
+
+macro_rules! guest_buf_accessors {
+    ($($get:ident, $set:ident, $ty:ty, $wrap:ident;)*) => {
+        $(
+            #[doc = concat!("Read a `", stringify!($wrap), "` at `off` and return the native value.")]
+            #[inline]
+            pub fn $get(&self, off: usize) -> $ty {
+                unsafe { self.read::<$wrap>(off).get() }
+            }
+            #[doc = concat!("Store a native value as `", stringify!($wrap), "` at `off`.")]
+            #[inline]
+            pub fn $set(&mut self, off: usize, x: $ty) {
+                unsafe { self.write::<$wrap>(off, $wrap::new(x)) }
+            }
+        )*
+    };
+}
+
+impl<'a> GuestBuf<'a> {
+    /// View over a slice.
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { ptr: buf.as_mut_ptr(), len: buf.len(), _marker: PhantomData }
+    }
+
+    /// View over `len` bytes at `ptr`.
+    ///
+    /// # Safety
+    /// `ptr` must be valid for reads and writes of `len` bytes for `'a`.
+    pub unsafe fn from_raw(ptr: *mut u8, len: usize) -> Self {
+        Self { ptr, len, _marker: PhantomData }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    /// Sub-view of `len` bytes at `off`.
+    pub fn sub(&mut self, off: usize, len: usize) -> GuestBuf<'_> {
+        self.check(off, len);
+        unsafe { GuestBuf::from_raw(self.ptr.add(off), len) }
+    }
+
+    /// Bytes of the view.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// Mutable bytes of the view.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+
+    #[inline]
+    fn check(&self, off: usize, size: usize) {
+        match off.checked_add(size) {
+            Some(end) if end <= self.len => {}
+            _ => panic!("GuestBuf: access of {} bytes at offset {} out of bounds (len {})", size, off, self.len),
+        }
+    }
+
+    /// Read a value (an endian type or a packed header) at `off`.
+    ///
+    /// # Safety
+    /// `T` must be valid for any bit pattern.
+    #[inline]
+    pub unsafe fn read<T: Copy>(&self, off: usize) -> T {
+        self.check(off, size_of::<T>());
+        read_unaligned(self.ptr.add(off).cast::<T>())
+    }
+
+    /// Write a value (an endian type or a packed header) at `off`.
+    ///
+    /// # Safety
+    /// `T` must have no padding bytes.
+    #[inline]
+    pub unsafe fn write<T: Copy>(&mut self, off: usize, val: T) {
+        self.check(off, size_of::<T>());
+        write_unaligned(self.ptr.add(off).cast::<T>(), val)
+    }
+
+    #[inline]
+    pub fn get_u8(&self, off: usize) -> m_uint8_t {
+        self.as_slice()[off]
+    }
+
+    #[inline]
+    pub fn set_u8(&mut self, off: usize, x: m_uint8_t) {
+        self.as_mut_slice()[off] = x;
+    }
+
+    guest_buf_accessors! {
+        get_be16, set_be16, m_uint16_t, Be16;
+        get_be32, set_be32, m_uint32_t, Be32;
+        get_be64, set_be64, m_uint64_t, Be64;
+        get_le16, set_le16, m_uint16_t, Le16;
+        get_le32, set_le32, m_uint32_t, Le32;
+        get_le64, set_le64, m_uint64_t, Le64;
+        get_vm16, set_vm16, m_uint16_t, Vm16;
+        get_vm32, set_vm32, m_uint32_t, Vm32;
+        get_vm64, set_vm64, m_uint64_t, Vm64;
+    }
+}
@@ -0,0 +1,358 @@
+//! Event loop.
+//!
+//! Calls a function when a registered FD is ready, without the FD_SETSIZE
+//! limit of select. The backend is epoll on Linux and poll elsewhere (poll
+//! has no edge trigger, EV_EDGE is ignored).
+//!
+//! Waiting and dispatching are separate steps, so the caller can hold its own
+//! lock while the callbacks run. An FD unregistered between the two steps is
+//! not dispatched, so its callback context can be freed right after the
+//! unregistration (if done under that same lock).
+
+use crate::_extra::*;
+use libc::size_t;
+use std::collections::HashMap;
+use std::ffi::c_int;
+use std::ffi::c_void;
+use std::ffi::CString;
+use std::io;
+use std::ptr::null_mut;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+// Events
+pub const EV_READ: c_int = 0x01;
+pub const EV_WRITE: c_int = 0x02;
+/// Error or hang up, always reported.
+pub const EV_ERROR: c_int = 0x04;
+/// Edge trigger (level trigger by default).
+pub const EV_EDGE: c_int = 0x08;
+
+/// Maximum number of events handled by a wait.
+pub const EV_LOOP_MAX_EVENTS: usize = 256;
+
+// Event callback
+pub type ev_callback_t = Option<unsafe extern "C" fn(fd: c_int, events: c_int, opt: *mut c_void)>;
+
+#[derive(Debug, Copy, Clone)]
+struct EvReg {
+    events: c_int,
+    cbk: ev_callback_t,
+    opt: *mut c_void,
+    /// Generation, ignores the pending events of a previous registration of the FD.
+    gen: u32,
+}
+
+#[derive(Debug, Default)]
+struct EvState {
+    regs: HashMap<c_int, EvReg>,
+    /// Ready events (fd, events, generation) waiting for a dispatch.
+    pending: Vec<(c_int, c_int, u32)>,
+    next_gen: u32,
+}
+
+// Event loop
+#[derive(Debug)]
+pub struct ev_loop {
+    #[cfg(target_os = "linux")]
+    epfd: c_int,
+    state: Mutex<EvState>,
+}
+pub type ev_loop_t = ev_loop;
+
+// the callback contexts are owned by the caller
+unsafe impl Send for ev_loop {}
+unsafe impl Sync for ev_loop {}
+
+impl ev_loop {
+    pub fn new() -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            let epfd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+            if epfd == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(ev_loop { epfd, state: Mutex::new(EvState::default()) })
+        }
+        #[cfg(not(target_os = "linux"))]
+        Ok(ev_loop { state: Mutex::new(EvState::default()) })
+    }
+
+    fn state(&self) -> MutexGuard<'_, EvState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Number of registered FDs.
+    pub fn len(&self) -> usize {
+        self.state().regs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[cfg(target_os = "linux")]
+    fn ctl(&self, op: c_int, fd: c_int, events: c_int, gen: u32) -> io::Result<()> {
+        let mut flags = libc::EPOLLERR | libc::EPOLLHUP;
+        if events & EV_READ != 0 {
+            flags |= libc::EPOLLIN | libc::EPOLLRDHUP;
+        }
+        if events & EV_WRITE != 0 {
+            flags |= libc::EPOLLOUT;
+        }
+        if events & EV_EDGE != 0 {
+            flags |= libc::EPOLLET;
+        }
+        let mut ev = libc::epoll_event { events: flags as u32, u64: ((gen as u64) << 32) | fd as u32 as u64 };
+        if unsafe { libc::epoll_ctl(self.epfd, op, fd, &mut ev) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Register an FD, replaces a previous registration.
+    pub fn add(&self, fd: c_int, events: c_int, cbk: ev_callback_t, opt: *mut c_void) -> io::Result<()> {
+        if fd < 0 || cbk.is_none() {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        let mut state = self.state();
+        let gen = state.next_gen;
+        #[cfg(target_os = "linux")]
+        {
+            // a closed FD disappears from epoll by itself, its number can be reused
+            let op = if state.regs.contains_key(&fd) { libc::EPOLL_CTL_MOD } else { libc::EPOLL_CTL_ADD };
+            match self.ctl(op, fd, events, gen) {
+                Err(err) if op == libc::EPOLL_CTL_MOD && err.raw_os_error() == Some(libc::ENOENT) => self.ctl(libc::EPOLL_CTL_ADD, fd, events, gen)?,
+                Err(err) if op == libc::EPOLL_CTL_ADD && err.raw_os_error() == Some(libc::EEXIST) => self.ctl(libc::EPOLL_CTL_MOD, fd, events, gen)?,
+                res => res?,
+            }
+        }
+        state.next_gen = gen.wrapping_add(1);
+        state.regs.insert(fd, EvReg { events, cbk, opt, gen });
+        Ok(())
+    }
+
+    /// Change the events of a registered FD.
+    pub fn modify(&self, fd: c_int, events: c_int) -> io::Result<()> {
+        let mut state = self.state();
+        let Some(reg) = state.regs.get_mut(&fd) else {
+            return Err(io::ErrorKind::NotFound.into());
+        };
+        #[cfg(target_os = "linux")]
+        self.ctl(libc::EPOLL_CTL_MOD, fd, events, reg.gen)?;
+        reg.events = events;
+        Ok(())
+    }
+
+    /// Unregister an FD, its pending events are dropped.
+    pub fn del(&self, fd: c_int) -> io::Result<()> {
+        let mut state = self.state();
+        if state.regs.remove(&fd).is_none() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        #[cfg(target_os = "linux")]
+        unsafe {
+            // fails if the FD is already closed
+            libc::epoll_ctl(self.epfd, libc::EPOLL_CTL_DEL, fd, null_mut());
+        }
+        Ok(())
+    }
+
+    /// Wait for events (timeout in milliseconds, -1 for infinite), returns the number of ready FDs.
+    /// An interrupted wait returns 0.
+    pub fn wait(&self, timeout: c_int) -> io::Result<usize> {
+        #[cfg(target_os = "linux")]
+        let ready: Vec<(c_int, c_int, u32)> = {
+            let mut events: Vec<libc::epoll_event> = vec![libc::epoll_event { events: 0, u64: 0 }; EV_LOOP_MAX_EVENTS];
+            let n = unsafe { libc::epoll_wait(self.epfd, events.as_mut_ptr(), events.len() as c_int, timeout) };
+            if n == -1 {
+                let err = io::Error::last_os_error();
+                return if err.kind() == io::ErrorKind::Interrupted { Ok(0) } else { Err(err) };
+            }
+            events[..n as usize]
+                .iter()
+                .map(|ev| {
+                    let flags = ev.events as c_int;
+                    let mut events = 0;
+                    if flags & (libc::EPOLLIN | libc::EPOLLRDHUP) != 0 {
+                        events |= EV_READ;
+                    }
+                    if flags & libc::EPOLLOUT != 0 {
+                        events |= EV_WRITE;
+                    }
+                    if flags & (libc::EPOLLERR | libc::EPOLLHUP) != 0 {
+                        events |= EV_ERROR;
+                    }
+                    let data = ev.u64;
+                    (data as u32 as c_int, events, (data >> 32) as u32)
+                })
+                .collect()
+        };
+
+        #[cfg(not(target_os = "linux"))]
+        let ready: Vec<(c_int, c_int, u32)> = {
+            let mut fds: Vec<(libc::pollfd, u32)> = self
+                .state()
+                .regs
+                .iter()
+                .map(|(fd, reg)| {
+                    let mut events = 0;
+                    if reg.events & EV_READ != 0 {
+                        events |= libc::POLLIN;
+                    }
+                    if reg.events & EV_WRITE != 0 {
+                        events |= libc::POLLOUT;
+                    }
+                    (libc::pollfd { fd: *fd, events, revents: 0 }, reg.gen)
+                })
+                .collect();
+            let mut pfds: Vec<libc::pollfd> = fds.iter().map(|x| x.0).collect();
+            let n = unsafe { libc::poll(pfds.as_mut_ptr(), pfds.len() as libc::nfds_t, timeout) };
+            if n == -1 {
+                let err = io::Error::last_os_error();
+                return if err.kind() == io::ErrorKind::Interrupted { Ok(0) } else { Err(err) };
+            }
+            fds.iter_mut().zip(pfds.iter()).for_each(|(x, p)| x.0.revents = p.revents);
+            fds.iter()
+                .filter(|(p, _)| p.revents != 0)
+                .map(|(p, gen)| {
+                    let mut events = 0;
+                    if p.revents & libc::POLLIN != 0 {
+                        events |= EV_READ;
+                    }
+                    if p.revents & libc::POLLOUT != 0 {
+                        events |= EV_WRITE;
+                    }
+                    if p.revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0 {
+                        events |= EV_ERROR;
+                    }
+                    (p.fd, events, *gen)
+                })
+                .collect()
+        };
+
+        let count = ready.len();
+        self.state().pending.extend(ready);
+        Ok(count)
+    }
+
+    /// Call the callbacks of the pending events, returns the number of calls.
+    /// The callbacks can register and unregister FDs.
+    pub unsafe fn dispatch(&self) -> usize {
+        let pending = std::mem::take(&mut self.state().pending);
+        let mut count = 0;
+
+        for (fd, events, gen) in pending {
+            // still registered? (the lock is not held during the call)
+            let reg = match self.state().regs.get(&fd) {
+                Some(reg) if reg.gen == gen => *reg,
+                _ => continue,
+            };
+            if let Some(cbk) = reg.cbk {
+                cbk(fd, events & (reg.events | EV_ERROR), reg.opt);
+                count += 1;
+            }
+        }
+
+        count
+    }
+}
+
+impl Drop for ev_loop {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        unsafe {
+            libc::close(self.epfd);
+        }
+    }
+}
+
+// Print an error of an event loop function
+fn ev_loop_report_error(func: &std::ffi::CStr, err: &io::Error) {
+    let msg = CString::new(err.to_string()).unwrap_or_default();
+    unsafe { libc::fprintf(c_stderr(), c"%s: %s\n".as_ptr(), func.as_ptr(), msg.as_ptr()) };
+}
+
+// Create an event loop
+#[no_mangle]
+pub extern "C" fn ev_loop_create() -> *mut ev_loop_t {
+    match ev_loop::new() {
+        Ok(ev_loop) => Box::into_raw(Box::new(ev_loop)),
+        Err(err) => {
+            ev_loop_report_error(c"ev_loop_create", &err);
+            null_mut()
+        }
+    }
+}
+
+// Free an event loop, the registered FDs are not closed
+#[no_mangle]
+pub unsafe extern "C" fn ev_loop_free(ev_loop: *mut ev_loop_t) {
+    if !ev_loop.is_null() {
+        drop(Box::from_raw(ev_loop));
+    }
+}
+
+// Register a FD with a callback (replaces a previous registration)
+#[no_mangle]
+pub unsafe extern "C" fn ev_loop_add(ev_loop: *mut ev_loop_t, fd: c_int, events: c_int, cbk: ev_callback_t, opt: *mut c_void) -> c_int {
+    match (*ev_loop).add(fd, events, cbk, opt) {
+        Ok(()) => 0,
+        Err(err) => {
+            let msg = CString::new(err.to_string()).unwrap_or_default();
+            libc::fprintf(c_stderr(), c"ev_loop_add: fd %d: %s\n".as_ptr(), fd, msg.as_ptr());
+            -1
+        }
+    }
+}
+
+// Change the events of a registered FD
+#[no_mangle]
+pub unsafe extern "C" fn ev_loop_mod(ev_loop: *mut ev_loop_t, fd: c_int, events: c_int) -> c_int {
+    match (*ev_loop).modify(fd, events) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+// Unregister a FD
+#[no_mangle]
+pub unsafe extern "C" fn ev_loop_del(ev_loop: *mut ev_loop_t, fd: c_int) -> c_int {
+    match (*ev_loop).del(fd) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+// Number of registered FDs
+#[no_mangle]
+pub unsafe extern "C" fn ev_loop_count(ev_loop: *mut ev_loop_t) -> size_t {
+    (*ev_loop).len()
+}
+
+// Wait for events (timeout in ms, -1 for infinite), returns the number of ready FDs or -1
+#[no_mangle]
+pub unsafe extern "C" fn ev_loop_wait(ev_loop: *mut ev_loop_t, timeout: c_int) -> c_int {
+    match (*ev_loop).wait(timeout) {
+        Ok(count) => count as c_int,
+        Err(err) => {
+            ev_loop_report_error(c"ev_loop_wait", &err);
+            -1
+        }
+    }
+}
+
+// Call the callbacks of the ready FDs, returns the number of calls
+#[no_mangle]
+pub unsafe extern "C" fn ev_loop_dispatch(ev_loop: *mut ev_loop_t) -> c_int {
+    (*ev_loop).dispatch() as c_int
+}
+
+// Wait for events and call the callbacks, returns the number of calls or -1
+#[no_mangle]
+pub unsafe extern "C" fn ev_loop_run_once(ev_loop: *mut ev_loop_t, timeout: c_int) -> c_int {
+    if ev_loop_wait(ev_loop, timeout) == -1 {
+        return -1;
+    }
+    ev_loop_dispatch(ev_loop)
+}
@@ -0,0 +1,495 @@
+//! Frame-Relay switch LMI (network side).
+//!
+//! Answers the status enquiries of the DTEs connected to a Frame-Relay
+//! switch with one of the three LMI variants:
+//!
+//! - ANSI T1.617 Annex D: DLCI 0, locking shift to codeset 5,
+//! - ITU-T Q.933 Annex A: DLCI 0, information elements 0x51/0x53/0x57,
+//! - Cisco ("gang of four"): DLCI 1023, protocol discriminator 0x09.
+//!
+//! The variant of a port is detected from its first enquiry, and changes
+//! only when the DTE has stopped polling with the current one (IOS autosense
+//! polls with the three variants and keeps the one that is answered).
+//!
+//! The full status reports the PVCs of the VC table: new if they were not
+//! in the previous full status, active unless the LMI of the port they are
+//! switched to is down, deleted (ANSI only, the others just omit them) once
+//! after being removed.
+//!
+//! Polling verification: the DTE must poll within T392, each expiry is an
+//! error event, the link is down with N392 errors in the last N393 events.
+//! A port which never polled (LMI disabled on the DTE) is not monitored.
+
+use crate::_extra::*;
+use crate::dynamips_common::*;
+use crate::timer::*;
+use crate::utils::*;
+use libc::size_t;
+use libc::ssize_t;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::c_void;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+/// DLCI of the ANSI and Q.933 Annex A LMI.
+const FR_LMI_DLCI_ANSI: u_int = 0;
+/// DLCI of the Cisco LMI.
+const FR_LMI_DLCI_CISCO: u_int = 1023;
+
+/// Polling verification timer (ms).
+pub const FR_LMI_T392: m_tmcnt_t = 15000;
+/// Error threshold.
+pub const FR_LMI_N392: usize = 3;
+/// Monitored events count.
+pub const FR_LMI_N393: usize = 4;
+
+/// Interval of the polling verification timer (ms).
+pub const FR_LMI_TICK_INTERVAL: m_tmcnt_t = 1000;
+
+// LMI variants (0 if not detected yet)
+pub const FR_LMI_TYPE_NONE: c_int = 0;
+pub const FR_LMI_TYPE_ANSI: c_int = 1;
+pub const FR_LMI_TYPE_Q933A: c_int = 2;
+pub const FR_LMI_TYPE_CISCO: c_int = 3;
+
+// Port states
+pub const FR_LMI_STATE_UNKNOWN: c_int = 0;
+pub const FR_LMI_STATE_UP: c_int = 1;
+pub const FR_LMI_STATE_DOWN: c_int = 2;
+
+const FR_LMI_CTRL_UI: u8 = 0x03;
+const FR_LMI_PD_Q933: u8 = 0x08;
+const FR_LMI_PD_CISCO: u8 = 0x09;
+const FR_LMI_STATUS_ENQUIRY: u8 = 0x75;
+const FR_LMI_STATUS: u8 = 0x7d;
+const FR_LMI_LOCKING_SHIFT_5: u8 = 0x95;
+
+// Report types
+const FR_LMI_REPORT_FULL: u8 = 0x00;
+const FR_LMI_REPORT_LIV: u8 = 0x01;
+
+// PVC status bits
+const FR_LMI_PVC_EXT: u8 = 0x80;
+const FR_LMI_PVC_NEW: u8 = 0x08;
+const FR_LMI_PVC_DELETE: u8 = 0x04;
+const FR_LMI_PVC_ACTIVE: u8 = 0x02;
+
+/// LMI variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrLmiType {
+    Ansi,
+    Q933A,
+    Cisco,
+}
+
+impl FrLmiType {
+    pub fn to_c(self) -> c_int {
+        match self {
+            FrLmiType::Ansi => FR_LMI_TYPE_ANSI,
+            FrLmiType::Q933A => FR_LMI_TYPE_Q933A,
+            FrLmiType::Cisco => FR_LMI_TYPE_CISCO,
+        }
+    }
+
+    fn dlci(self) -> u_int {
+        match self {
+            FrLmiType::Cisco => FR_LMI_DLCI_CISCO,
+            _ => FR_LMI_DLCI_ANSI,
+        }
+    }
+
+    /// Header up to the message type.
+    fn header(self, msg_type: u8) -> Vec<u8> {
+        let addr = fr_dlci_to_addr(self.dlci());
+        let pd = if self == FrLmiType::Cisco { FR_LMI_PD_CISCO } else { FR_LMI_PD_Q933 };
+        let mut hdr = vec![addr[0], addr[1], FR_LMI_CTRL_UI, pd, 0x00, msg_type];
+        if self == FrLmiType::Ansi {
+            hdr.push(FR_LMI_LOCKING_SHIFT_5);
+        }
+        hdr
+    }
+
+    /// Identifiers of the report type, link integrity and PVC status IEs.
+    fn ie_ids(self) -> (u8, u8, u8) {
+        match self {
+            FrLmiType::Q933A => (0x51, 0x53, 0x57),
+            _ => (0x01, 0x03, 0x07),
+        }
+    }
+}
+
+/// DLCI of a two bytes Q.922 address.
+pub fn fr_addr_to_dlci(addr: &[u8]) -> u_int {
+    ((((addr[0] & 0xfc) >> 2) as u_int) << 4) | ((addr[1] & 0xf0) >> 4) as u_int
+}
+
+/// Two bytes Q.922 address (C/R, FECN, BECN and DE cleared).
+pub fn fr_dlci_to_addr(dlci: u_int) -> [u8; 2] {
+    [((dlci >> 4) << 2) as u8, (((dlci & 0x0f) << 4) | 0x01) as u8]
+}
+
+/// Status enquiry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrLmiEnquiry {
+    pub kind: FrLmiType,
+    pub report: u8,
+    /// Send sequence number of the DTE.
+    pub ssn: u8,
+    /// Last sequence number received by the DTE.
+    pub rsn: u8,
+}
+
+impl FrLmiEnquiry {
+    /// Parse a status enquiry, None if the packet is not one.
+    pub fn parse(pkt: &[u8]) -> Option<Self> {
+        if pkt.len() < 6 || pkt[2] != FR_LMI_CTRL_UI || pkt[4] != 0x00 || pkt[5] != FR_LMI_STATUS_ENQUIRY {
+            return None;
+        }
+
+        let (kind, mut i) = match (fr_addr_to_dlci(pkt), pkt[3]) {
+            (FR_LMI_DLCI_ANSI, FR_LMI_PD_Q933) if pkt.get(6) == Some(&FR_LMI_LOCKING_SHIFT_5) => (FrLmiType::Ansi, 7),
+            (FR_LMI_DLCI_ANSI, FR_LMI_PD_Q933) => (FrLmiType::Q933A, 6),
+            (FR_LMI_DLCI_CISCO, FR_LMI_PD_CISCO) => (FrLmiType::Cisco, 6),
+            _ => return None,
+        };
+
+        let (report_id, li_id, _) = kind.ie_ids();
+        let (mut report, mut seq) = (None, None);
+
+        while i + 2 <= pkt.len() {
+            let (id, size) = (pkt[i], pkt[i + 1] as usize);
+            let data = pkt.get(i + 2..i + 2 + size)?;
+
+            match (id, size) {
+                (id, 1) if id == report_id => report = Some(data[0]),
+                (id, 2) if id == li_id => seq = Some((data[0], data[1])),
+                _ => {}
+            }
+            i += size + 2;
+        }
+
+        match (report, seq) {
+            (Some(report @ (FR_LMI_REPORT_FULL | FR_LMI_REPORT_LIV)), Some((ssn, rsn))) => Some(Self { kind, report, ssn, rsn }),
+            _ => None,
+        }
+    }
+}
+
+/// Counters and state of a port.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct fr_lmi_port_info {
+    /// FR_LMI_TYPE_* value.
+    pub r#type: c_int,
+    /// FR_LMI_STATE_* value.
+    pub state: c_int,
+    /// Last send sequence number.
+    pub seq: u_int,
+    pub enquiries: m_uint64_t,
+    pub full_status: m_uint64_t,
+    /// T392 expiries.
+    pub errors: m_uint64_t,
+    /// Enquiries of another variant than the port one.
+    pub ignored: m_uint64_t,
+}
+pub type fr_lmi_port_info_t = fr_lmi_port_info;
+
+/// LMI state of a port.
+#[derive(Debug, Default)]
+pub struct FrLmiPort {
+    kind: Option<FrLmiType>,
+    state: c_int,
+    seq: u8,
+    /// Start of the T392 timer.
+    t392: m_tmcnt_t,
+    /// Last enquiry of the port variant.
+    last_poll: m_tmcnt_t,
+    /// Last N393 events, true for errors.
+    events: VecDeque<bool>,
+    /// DLCIs in the last full status.
+    known: BTreeSet<u_int>,
+    info: fr_lmi_port_info_t,
+}
+
+impl FrLmiPort {
+    pub fn kind(&self) -> Option<FrLmiType> {
+        self.kind
+    }
+
+    pub fn state(&self) -> c_int {
+        self.state
+    }
+
+    pub fn info(&self) -> fr_lmi_port_info_t {
+        fr_lmi_port_info_t { r#type: self.kind.map_or(FR_LMI_TYPE_NONE, FrLmiType::to_c), state: self.state, seq: self.seq as u_int, ..self.info }
+    }
+
+    fn event(&mut self, error: bool) {
+        if self.events.len() == FR_LMI_N393 {
+            self.events.pop_front();
+        }
+        self.events.push_back(error);
+
+        if self.events.iter().filter(|&&e| e).count() >= FR_LMI_N392 {
+            if self.state != FR_LMI_STATE_DOWN {
+                // the variant is detected again when the DTE comes back
+                self.state = FR_LMI_STATE_DOWN;
+                self.kind = None;
+                self.known.clear();
+            }
+        } else {
+            self.state = FR_LMI_STATE_UP;
+        }
+    }
+}
+
+/// LMI of a Frame-Relay switch, the ports are identified by their NIO.
+#[derive(Debug, Default)]
+pub struct FrLmi {
+    ports: HashMap<*mut c_void, FrLmiPort>,
+}
+
+impl FrLmi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn port(&self, port: *mut c_void) -> Option<&FrLmiPort> {
+        self.ports.get(&port)
+    }
+
+    pub fn ports(&self) -> impl Iterator<Item = (*mut c_void, &FrLmiPort)> {
+        self.ports.iter().map(|(&p, state)| (p, state))
+    }
+
+    pub fn remove_port(&mut self, port: *mut c_void) {
+        self.ports.remove(&port);
+    }
+
+    /// Check if the PVCs switched to a port are active.
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn is_active(&self, port: *mut c_void) -> bool {
+        self.ports.get(&port).map_or(true, |p| p.state != FR_LMI_STATE_DOWN)
+    }
+
+    /// Handle a packet received on an LMI DLCI, returns the status to send.
+    /// `pvcs` are the (DLCI, output port) of the VCs of the port.
+    pub fn handle(&mut self, port: *mut c_void, pkt: &[u8], pvcs: &[(u_int, *mut c_void)], now: m_tmcnt_t) -> Option<Vec<u8>> {
+        let enq = FrLmiEnquiry::parse(pkt)?;
+
+        // PVC status, computed before borrowing the port
+        let mut pvcs: Vec<(u_int, bool)> = pvcs.iter().map(|&(dlci, output)| (dlci, self.is_active(output))).collect();
+        pvcs.sort_unstable();
+        pvcs.dedup_by_key(|pvc| pvc.0);
+
+        let p = self.ports.entry(port).or_default();
+        match p.kind {
+            Some(kind) if kind != enq.kind && now.saturating_sub(p.last_poll) <= FR_LMI_T392 => {
+                p.info.ignored += 1;
+                return None;
+            }
+            Some(kind) if kind == enq.kind => {}
+            _ => {
+                p.kind = Some(enq.kind);
+                p.known.clear();
+            }
+        }
+
+        p.info.enquiries += 1;
+        p.t392 = now;
+        p.last_poll = now;
+        p.event(false);
+
+        // the DTE resynchronizes on our sequence number
+        p.seq = enq.rsn.wrapping_add(1);
+        if p.seq == 0 {
+            p.seq = 1;
+        }
+
+        let (report_id, li_id, pvc_id) = enq.kind.ie_ids();
+        let mut resp = enq.kind.header(FR_LMI_STATUS);
+        resp.extend([report_id, 1, enq.report]);
+        resp.extend([li_id, 2, p.seq, enq.ssn]);
+
+        if enq.report == FR_LMI_REPORT_FULL {
+            p.info.full_status += 1;
+
+            let current: BTreeSet<u_int> = pvcs.iter().map(|pvc| pvc.0).collect();
+            let deleted: Vec<u_int> = if enq.kind == FrLmiType::Ansi { p.known.difference(&current).copied().collect() } else { Vec::new() };
+
+            let mut status: Vec<(u_int, u8)> = pvcs
+                .iter()
+                .map(|&(dlci, active)| {
+                    let new = if p.known.contains(&dlci) { 0 } else { FR_LMI_PVC_NEW };
+                    (dlci, new | if active { FR_LMI_PVC_ACTIVE } else { 0 })
+                })
+                .collect();
+            status.extend(deleted.iter().map(|&dlci| (dlci, FR_LMI_PVC_DELETE)));
+            status.sort_unstable();
+
+            for (dlci, bits) in status {
+                let addr = [((dlci >> 4) & 0x3f) as u8, FR_LMI_PVC_EXT | ((dlci & 0x0f) << 3) as u8];
+                if enq.kind == FrLmiType::Cisco {
+                    // with a null bandwidth
+                    resp.extend([pvc_id, 6, addr[0], addr[1], FR_LMI_PVC_EXT | bits, 0, 0, 0]);
+                } else {
+                    resp.extend([pvc_id, 3, addr[0], addr[1], FR_LMI_PVC_EXT | bits]);
+                }
+            }
+            p.known = current;
+        }
+
+        Some(resp)
+    }
+
+    /// Polling verification at time `now`.
+    pub fn tick(&mut self, now: m_tmcnt_t) {
+        for p in self.ports.values_mut() {
+            if p.state != FR_LMI_STATE_UNKNOWN && now.saturating_sub(p.t392) >= FR_LMI_T392 {
+                p.t392 = now;
+                p.info.errors += 1;
+                p.event(true);
+            }
+        }
+    }
+}
+
+/// PVC of a port.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fr_lmi_pvc {
+    pub dlci: u_int,
+    /// Port the PVC is switched to.
+    pub output: *mut c_void,
+}
+pub type fr_lmi_pvc_t = fr_lmi_pvc;
+
+pub type fr_lmi_port_cbk_t = Option<unsafe extern "C" fn(port: *mut c_void, info: *mut fr_lmi_port_info_t, opt: *mut c_void)>;
+
+pub struct fr_lmi {
+    state: Mutex<FrLmi>,
+    timer: timer_id,
+}
+pub type fr_lmi_t = fr_lmi;
+
+// the ports are only used as keys
+unsafe impl Send for fr_lmi {}
+unsafe impl Sync for fr_lmi {}
+
+impl fr_lmi {
+    fn lock(&self) -> MutexGuard<'_, FrLmi> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Polling verification
+unsafe extern "C" fn fr_lmi_timer(opt: *mut c_void, _timer: *mut timer_entry_t) -> c_int {
+    let lmi: *mut fr_lmi_t = opt.cast::<_>();
+    (*lmi).lock().tick(m_gettime());
+    TRUE
+}
+
+// Create the LMI of a Frame-Relay switch
+#[no_mangle]
+pub unsafe extern "C" fn fr_lmi_create() -> *mut fr_lmi_t {
+    let lmi = Box::into_raw(Box::new(fr_lmi { state: Mutex::new(FrLmi::new()), timer: 0 }));
+
+    (*lmi).timer = timer_create_entry(FR_LMI_TICK_INTERVAL, FALSE, 10, Some(fr_lmi_timer), lmi.cast::<_>());
+    if (*lmi).timer == 0 {
+        libc::fprintf(c_stderr(), c"fr_lmi_create: unable to create polling verification timer\n".as_ptr());
+        drop(Box::from_raw(lmi));
+        return std::ptr::null_mut();
+    }
+    lmi
+}
+
+// Free the LMI of a Frame-Relay switch
+#[no_mangle]
+pub unsafe extern "C" fn fr_lmi_free(lmi: *mut fr_lmi_t) {
+    if !lmi.is_null() {
+        timer_remove((*lmi).timer);
+        drop(Box::from_raw(lmi));
+    }
+}
+
+// Handle a packet received on an LMI DLCI, returns the length of the status to send (-1 if none)
+#[no_mangle]
+pub unsafe extern "C" fn fr_lmi_handle_pkt(
+    lmi: *mut fr_lmi_t,
+    port: *mut c_void,
+    pkt: *const u_char,
+    len: size_t,
+    pvcs: *const fr_lmi_pvc_t,
+    pvc_count: size_t,
+    resp: *mut u_char,
+    resp_size: size_t,
+) -> ssize_t {
+    let pkt = std::slice::from_raw_parts(pkt, len);
+    let pvcs: Vec<(u_int, *mut c_void)> = match pvc_count {
+        0 => Vec::new(),
+        n => std::slice::from_raw_parts(pvcs, n).iter().map(|pvc| (pvc.dlci, pvc.output)).collect(),
+    };
+
+    match (*lmi).lock().handle(port, pkt, &pvcs, m_gettime()) {
+        Some(status) if status.len() <= resp_size => {
+            std::ptr::copy_nonoverlapping(status.as_ptr(), resp, status.len());
+            status.len() as ssize_t
+        }
+        _ => -1,
+    }
+}
+
+// Forget a port
+#[no_mangle]
+pub unsafe extern "C" fn fr_lmi_remove_port(lmi: *mut fr_lmi_t, port: *mut c_void) {
+    (*lmi).lock().remove_port(port);
+}
+
+// Get the LMI state of a port, returns -1 if it never polled
+#[no_mangle]
+pub unsafe extern "C" fn fr_lmi_get_port_info(lmi: *mut fr_lmi_t, port: *mut c_void, info: *mut fr_lmi_port_info_t) -> c_int {
+    match (*lmi).lock().port(port) {
+        Some(p) => {
+            *info = p.info();
+            0
+        }
+        None => -1,
+    }
+}
+
+// Call a function for each port which polled
+#[no_mangle]
+pub unsafe extern "C" fn fr_lmi_foreach_port(lmi: *mut fr_lmi_t, cb: fr_lmi_port_cbk_t, opt: *mut c_void) {
+    let mut ports: Vec<(*mut c_void, fr_lmi_port_info_t)> = (*lmi).lock().ports().map(|(port, p)| (port, p.info())).collect();
+
+    if let Some(cb) = cb {
+        for (port, info) in ports.iter_mut() {
+            cb(*port, info, opt);
+        }
+    }
+}
+
+// Name of a LMI variant
+#[no_mangle]
+pub extern "C" fn fr_lmi_type_str(r#type: c_int) -> *const c_char {
+    match r#type {
+        FR_LMI_TYPE_ANSI => c"ansi".as_ptr(),
+        FR_LMI_TYPE_Q933A => c"q933a".as_ptr(),
+        FR_LMI_TYPE_CISCO => c"cisco".as_ptr(),
+        _ => c"none".as_ptr(),
+    }
+}
+
+// Name of a port state
+#[no_mangle]
+pub extern "C" fn fr_lmi_state_str(state: c_int) -> *const c_char {
+    match state {
+        FR_LMI_STATE_UP => c"up".as_ptr(),
+        FR_LMI_STATE_DOWN => c"down".as_ptr(),
+        _ => c"unknown".as_ptr(),
+    }
+}
@@ -9,6 +9,7 @@
 
 use crate::_extra::*;
 use crate::dynamips_common::*;
+use crate::endian::*;
 use libc::size_t;
 use std::ffi::c_char;
 use std::ffi::c_int;
@@ -145,7 +146,7 @@ pub struct fs_nvram_header {
     /// Padding.
     pub padding: [u_char; 6],
     /// Magic value 0xF0A5.
-    pub magic: m_uint16_t,
+    pub magic: Be16,
     // Following data:
     //  - nvram_header_startup_config
     //  - startup-config data
@@ -163,31 +164,31 @@ pub struct fs_nvram_header {
 #[derive(Debug, Copy, Clone)]
 pub struct fs_nvram_header_startup_config {
     /// Magic value 0xABCD.
-    pub magic: m_uint16_t,
+    pub magic: Be16,
     /// Format of the data.
     /// 0x0001 - raw data;
     /// 0x0002 - .Z compressed (12 bits);
-    pub format: m_uint16_t,
+    pub format: Be16,
     /// Checksum of filesystem data. (all data after the filesystem magic)
-    pub checksum: m_uint16_t,
+    pub checksum: Be16,
     /// 0x0C04 - maybe maximum amount of free space that will be reserved?
-    pub unk1: m_uint16_t,
+    pub unk1: Be16,
     /// Address of the data.
-    pub start: m_uint32_t,
+    pub start: Be32,
     /// Address right after the data.
-    pub end: m_uint32_t,
+    pub end: Be32,
     /// Length of block.
-    pub len: m_uint32_t,
+    pub len: Be32,
     /// 0x00000000
-    pub unk2: m_uint32_t,
+    pub unk2: Be32,
     /// 0x00000000 if raw data, 0x00000001 if compressed
-    pub unk3: m_uint32_t,
+    pub unk3: Be32,
     /// 0x0000 if raw data, 0x0001 if compressed
-    pub unk4: m_uint16_t,
+    pub unk4: Be16,
     /// 0x0000
-    pub unk5: m_uint16_t,
+    pub unk5: Be16,
     /// Length of uncompressed data, 0 if raw data.
-    pub uncompressed_len: m_uint32_t,
+    pub uncompressed_len: Be32,
     // startup-config data comes after this header
 }
 
@@ -197,16 +198,16 @@ pub struct fs_nvram_header_startup_config {
 #[derive(Debug, Copy, Clone)]
 pub struct fs_nvram_header_private_config {
     /// Magic value 0xFEDC.
-    pub magic: m_uint16_t,
+    pub magic: Be16,
     /// Format of the file.
     /// 0x0001 - raw data;
-    pub format: m_uint16_t,
+    pub format: Be16,
     /// Address of the data.
-    pub start: m_uint32_t,
+    pub start: Be32,
     /// Address right after the data.
-    pub end: m_uint32_t,
+    pub end: Be32,
     /// Length of block.
-    pub len: m_uint32_t,
+    pub len: Be32,
     // private-config data comes after this header
 }
 
@@ -215,16 +216,16 @@ pub struct fs_nvram_header_private_config {
 #[derive(Debug, Copy, Clone)]
 pub struct fs_nvram_file_sector {
     /// Magic value 0xDCBA
-    pub magic: m_uint16_t,
+    pub magic: Be16,
     /// Next sector with data, 0 by default
-    pub next_sector: m_uint16_t,
+    pub next_sector: Be16,
     /// Flags.
     /// @see FS_NVRAM_FLAG_FILE_START
     /// @see FS_NVRAM_FLAG_FILE_END
     /// @see FS_NVRAM_FLAG_FILE_NO_RW
-    pub flags: m_uint16_t,
+    pub flags: Be16,
     /// Amount of data in this sector.
-    pub length: m_uint16_t,
+    pub length: Be16,
     /// File name, always NUL-terminated.
     pub filename: [c_char; 24],
     /// File data.
@@ -258,100 +259,6 @@ pub struct fs_nvram {
 //=========================================================
 // Auxiliary
 
-/// Convert a 16 bit value from big endian to native.
-#[inline]
-unsafe fn be_to_native16(val: *mut m_uint16_t) {
-    union U {
-        val: m_uint16_t,
-        b: [m_uint8_t; 2],
-    }
-    let u = U { val: *val };
-    *val = ((u.b[0] as m_uint16_t) << 8) | u.b[1] as m_uint16_t;
-}
-
-/// Convert a 32 bit value from big endian to native.
-#[inline]
-unsafe fn be_to_native32(val: *mut m_uint32_t) {
-    union U {
-        val: m_uint32_t,
-        b: [m_uint8_t; 4],
-    }
-    let u = U { val: *val };
-    *val = ((u.b[0] as m_uint32_t) << 24) | ((u.b[1] as m_uint32_t) << 16) | ((u.b[2] as m_uint32_t) << 8) | u.b[3] as m_uint32_t;
-}
-
-/// Convert startup-config header values from big endian to native.
-unsafe fn be_to_native_header_startup(head: *mut fs_nvram_header_startup_config) {
-    be_to_native16(addr_of_mut!((*head).magic));
-    be_to_native16(addr_of_mut!((*head).format));
-    be_to_native16(addr_of_mut!((*head).checksum));
-    be_to_native16(addr_of_mut!((*head).unk1));
-    be_to_native32(addr_of_mut!((*head).start));
-    be_to_native32(addr_of_mut!((*head).end));
-    be_to_native32(addr_of_mut!((*head).len));
-    be_to_native32(addr_of_mut!((*head).unk2));
-    be_to_native32(addr_of_mut!((*head).unk3));
-    be_to_native16(addr_of_mut!((*head).unk4));
-    be_to_native16(addr_of_mut!((*head).unk5));
-    be_to_native32(addr_of_mut!((*head).uncompressed_len));
-}
-
-/// Convert private-config header values from big endian to native.
-unsafe fn be_to_native_header_private(head: *mut fs_nvram_header_private_config) {
-    be_to_native16(addr_of_mut!((*head).magic));
-    be_to_native16(addr_of_mut!((*head).format));
-    be_to_native32(addr_of_mut!((*head).start));
-    be_to_native32(addr_of_mut!((*head).end));
-    be_to_native32(addr_of_mut!((*head).len));
-}
-
-/// Convert a 16 bit value from native to big endian.
-#[inline]
-unsafe fn native_to_be16(val: *mut m_uint16_t) {
-    union U {
-        val: m_uint16_t,
-        b: [m_uint8_t; 2],
-    }
-    let u = U { b: [(*val >> 8) as m_uint8_t, (*val & 0xFF) as m_uint8_t] };
-    *val = u.val;
-}
-
-/// Convert a 32 bit value from native to big endian.
-#[inline]
-unsafe fn native_to_be32(val: *mut m_uint32_t) {
-    union U {
-        val: m_uint32_t,
-        b: [m_uint8_t; 4],
-    }
-    let u = U { b: [(*val >> 24) as m_uint8_t, (*val >> 16) as m_uint8_t, (*val >> 8) as m_uint8_t, (*val & 0xFF) as m_uint8_t] };
-    *val = u.val;
-}
-
-/// Convert startup-config header values from native to big endian.
-unsafe fn native_to_be_header_startup(head: *mut fs_nvram_header_startup_config) {
-    native_to_be16(addr_of_mut!((*head).magic));
-    native_to_be16(addr_of_mut!((*head).format));
-    native_to_be16(addr_of_mut!((*head).checksum));
-    native_to_be16(addr_of_mut!((*head).unk1));
-    native_to_be32(addr_of_mut!((*head).start));
-    native_to_be32(addr_of_mut!((*head).end));
-    native_to_be32(addr_of_mut!((*head).len));
-    native_to_be32(addr_of_mut!((*head).unk2));
-    native_to_be32(addr_of_mut!((*head).unk3));
-    native_to_be16(addr_of_mut!((*head).unk4));
-    native_to_be16(addr_of_mut!((*head).unk5));
-    native_to_be32(addr_of_mut!((*head).uncompressed_len));
-}
-
-/// Convert private-config header values from native to big endian.
-unsafe fn native_to_be_header_private(head: *mut fs_nvram_header_private_config) {
-    native_to_be16(addr_of_mut!((*head).magic));
-    native_to_be16(addr_of_mut!((*head).format));
-    native_to_be32(addr_of_mut!((*head).start));
-    native_to_be32(addr_of_mut!((*head).end));
-    native_to_be32(addr_of_mut!((*head).len));
-}
-
 /// Uncompress data in .Z file format.
 /// Adapted from 7zip's ZDecoder.cpp, which is licensed under LGPL 2.1.
 pub unsafe fn uncompress_LZC(in_data: *mut u_char, in_len: u_int, out_data: *mut u_char, out_len: u_int) -> c_int {
@@ -804,12 +711,11 @@ pub unsafe extern "C" fn fs_nvram_read_config(fs: *mut fs_nvram_t, startup_confi
     // read headers
     off = size_of::<fs_nvram_header>();
     fs_nvram_memcpy_from(fs, off as u_int, addr_of_mut!(startup_head).cast::<u_char>(), size_of::<fs_nvram_header_startup_config>() as u_int);
-    be_to_native_header_startup(addr_of_mut!(startup_head));
-    if FS_NVRAM_MAGIC_STARTUP_CONFIG != startup_head.magic {
+    if FS_NVRAM_MAGIC_STARTUP_CONFIG != startup_head.magic.get() {
         return 0; // done, no startup-config and no private-config
     }
 
-    off = fs_nvram_offset_of(fs, startup_head.start + startup_head.len) as size_t;
+    off = fs_nvram_offset_of(fs, startup_head.start.get() + startup_head.len.get()) as size_t;
     off += fs_nvram_padding_at(fs, off as u_int) as size_t;
 
     'no_err: {
@@ -819,13 +725,12 @@ pub unsafe extern "C" fn fs_nvram_read_config(fs: *mut fs_nvram_t, startup_confi
         }
 
         fs_nvram_memcpy_from(fs, off as u_int, addr_of_mut!(private_head).cast::<u_char>(), size_of::<fs_nvram_header_private_config>() as u_int);
-        be_to_native_header_private(addr_of_mut!(private_head));
 
         // read startup-config
-        if FS_NVRAM_FORMAT_RAW == startup_head.format {
+        if FS_NVRAM_FORMAT_RAW == startup_head.format.get() {
             if !startup_config.is_null() {
-                off = fs_nvram_offset_of(fs, startup_head.start) as size_t;
-                *startup_config = fs_nvram_read_data(fs, off as u_int, startup_head.len);
+                off = fs_nvram_offset_of(fs, startup_head.start.get()) as size_t;
+                *startup_config = fs_nvram_read_data(fs, off as u_int, startup_head.len.get());
                 if (*startup_config).is_null() {
                     err = libc::ENOMEM; // out of memory
                     break 'no_err;
@@ -833,35 +738,35 @@ pub unsafe extern "C" fn fs_nvram_read_config(fs: *mut fs_nvram_t, startup_confi
             }
 
             if !startup_len.is_null() {
-                *startup_len = startup_head.len as size_t;
+                *startup_len = startup_head.len.get() as size_t;
             }
-        } else if FS_NVRAM_FORMAT_LZC == startup_head.format {
+        } else if FS_NVRAM_FORMAT_LZC == startup_head.format.get() {
             if !startup_config.is_null() {
-                off = fs_nvram_offset_of(fs, startup_head.start) as size_t;
-                *startup_config = libc::malloc((startup_head.uncompressed_len + 1) as size_t).cast::<u_char>();
+                off = fs_nvram_offset_of(fs, startup_head.start.get()) as size_t;
+                *startup_config = libc::malloc((startup_head.uncompressed_len.get() + 1) as size_t).cast::<u_char>();
                 if (*startup_config).is_null() {
                     err = libc::ENOMEM; // out of memory
                     break 'no_err;
                 }
 
-                buf = fs_nvram_read_data(fs, off as u_int, startup_head.len);
+                buf = fs_nvram_read_data(fs, off as u_int, startup_head.len.get());
                 if buf.is_null() {
                     err = libc::ENOMEM; // out of memory
                     break 'no_err;
                 }
 
-                err = uncompress_LZC(buf, startup_head.len, *startup_config, startup_head.uncompressed_len);
+                err = uncompress_LZC(buf, startup_head.len.get(), *startup_config, startup_head.uncompressed_len.get());
                 if err != 0 {
                     libc::free(buf.cast::<_>());
                     break 'no_err;
                 }
 
-                *(*startup_config).add(startup_head.uncompressed_len as size_t) = 0;
+                *(*startup_config).add(startup_head.uncompressed_len.get() as size_t) = 0;
                 libc::free(buf.cast::<_>());
             }
 
             if !startup_len.is_null() {
-                *startup_len = startup_head.uncompressed_len as size_t;
+                *startup_len = startup_head.uncompressed_len.get() as size_t;
             }
         } else {
             err = libc::ENOTSUP; // unsupported format
@@ -869,14 +774,14 @@ pub unsafe extern "C" fn fs_nvram_read_config(fs: *mut fs_nvram_t, startup_confi
         }
 
         // read private-config
-        if fs_nvram_offset_of(fs, private_head.start + private_head.len) as size_t > (*fs).len || FS_NVRAM_MAGIC_PRIVATE_CONFIG != private_head.magic {
+        if fs_nvram_offset_of(fs, private_head.start.get() + private_head.len.get()) as size_t > (*fs).len || FS_NVRAM_MAGIC_PRIVATE_CONFIG != private_head.magic.get() {
             return 0; // done, no private-config
         }
 
-        if FS_NVRAM_FORMAT_RAW == private_head.format {
+        if FS_NVRAM_FORMAT_RAW == private_head.format.get() {
             if !private_config.is_null() {
-                off = fs_nvram_offset_of(fs, private_head.start) as size_t;
-                *private_config = fs_nvram_read_data(fs, off as u_int, private_head.len);
+                off = fs_nvram_offset_of(fs, private_head.start.get()) as size_t;
+                *private_config = fs_nvram_read_data(fs, off as u_int, private_head.len.get());
                 if (*private_config).is_null() {
                     err = libc::ENOMEM; // out of memory
                     break 'no_err;
@@ -884,7 +789,7 @@ pub unsafe extern "C" fn fs_nvram_read_config(fs: *mut fs_nvram_t, startup_confi
             }
 
             if !private_len.is_null() {
-                *private_len = private_head.len as size_t;
+                *private_len = private_head.len.get() as size_t;
             }
         } else {
             err = libc::ENOTSUP; // unsupported format
@@ -960,28 +865,25 @@ pub unsafe extern "C" fn fs_nvram_write_config(fs: *mut fs_nvram_t, startup_conf
 
     // prepare headers
     libc::memset(addr_of_mut!(startup_head).cast::<_>(), 0, size_of::<fs_nvram_header_startup_config>());
-    startup_head.magic = FS_NVRAM_MAGIC_STARTUP_CONFIG;
-    startup_head.format = FS_NVRAM_FORMAT_RAW;
-    startup_head.unk1 = if ((*fs).flags & FS_NVRAM_FLAGS_UNK1_0C01) != 0 {
+    startup_head.magic = Be16::new(FS_NVRAM_MAGIC_STARTUP_CONFIG);
+    startup_head.format = Be16::new(FS_NVRAM_FORMAT_RAW);
+    startup_head.unk1 = Be16::new(if ((*fs).flags & FS_NVRAM_FLAGS_UNK1_0C01) != 0 {
         0x0C01
     } else if ((*fs).flags & FS_NVRAM_FLAGS_UNK1_0C03) != 0 {
         0x0C03
     } else {
         0x0C04
-    };
-    startup_head.start = fs_nvram_address_of(fs, (size_of::<fs_nvram_header>() + size_of::<fs_nvram_header_startup_config>()) as m_uint32_t);
-    startup_head.end = startup_head.start + startup_len as m_uint32_t;
-    startup_head.len = startup_len as m_uint32_t;
+    });
+    startup_head.start = Be32::new(fs_nvram_address_of(fs, (size_of::<fs_nvram_header>() + size_of::<fs_nvram_header_startup_config>()) as m_uint32_t));
+    startup_head.end = Be32::new(startup_head.start.get() + startup_len as m_uint32_t);
+    startup_head.len = Be32::new(startup_len as m_uint32_t);
 
     libc::memset(addr_of_mut!(private_head).cast::<_>(), 0, size_of::<fs_nvram_header_private_config>());
-    private_head.magic = FS_NVRAM_MAGIC_PRIVATE_CONFIG;
-    private_head.format = FS_NVRAM_FORMAT_RAW;
-    private_head.start = startup_head.end + padding as m_uint32_t + size_of::<fs_nvram_header_private_config>() as m_uint32_t;
-    private_head.end = private_head.start + private_len as m_uint32_t;
-    private_head.len = private_len as m_uint32_t;
-
-    native_to_be_header_startup(addr_of_mut!(startup_head));
-    native_to_be_header_private(addr_of_mut!(private_head));
+    private_head.magic = Be16::new(FS_NVRAM_MAGIC_PRIVATE_CONFIG);
+    private_head.format = Be16::new(FS_NVRAM_FORMAT_RAW);
+    private_head.start = Be32::new(startup_head.end.get() + padding as m_uint32_t + size_of::<fs_nvram_header_private_config>() as m_uint32_t);
+    private_head.end = Be32::new(private_head.start.get() + private_len as m_uint32_t);
+    private_head.len = Be32::new(private_len as m_uint32_t);
 
     // write data
     off = size_of::<fs_nvram_header>();
@@ -1047,31 +949,29 @@ pub unsafe extern "C" fn fs_nvram_verify(fs: *mut fs_nvram_t, what: u_int) -> c_
 
         offset = size_of::<fs_nvram_header>();
         fs_nvram_memcpy_from(fs, offset as u_int, addr_of_mut!(startup_head).cast::<u_char>(), size_of::<fs_nvram_header_startup_config>() as u_int);
-        be_to_native_header_startup(addr_of_mut!(startup_head));
-        if FS_NVRAM_MAGIC_STARTUP_CONFIG == startup_head.magic {
-            if startup_head.end != startup_head.start + startup_head.len || startup_head.len as size_t > (*fs).len {
+        if FS_NVRAM_MAGIC_STARTUP_CONFIG == startup_head.magic.get() {
+            if startup_head.end.get() != startup_head.start.get() + startup_head.len.get() || startup_head.len.get() as size_t > (*fs).len {
                 return FS_NVRAM_ERR_INVALID_ADDRESS; // data is corrupted?
             }
-            if startup_head.start < (*fs).addr || startup_head.end as size_t > (*fs).addr as size_t + (*fs).len {
+            if startup_head.start.get() < (*fs).addr || startup_head.end.get() as size_t > (*fs).addr as size_t + (*fs).len {
                 return FS_NVRAM_ERR_INVALID_ADDRESS; // (*fs).addr has the wrong value?
             }
 
-            offset = fs_nvram_offset_of(fs, startup_head.end) as size_t;
+            offset = fs_nvram_offset_of(fs, startup_head.end.get()) as size_t;
             offset += fs_nvram_padding_at(fs, offset as u_int) as size_t;
             if (*fs).len < offset + size_of::<fs_nvram_header_private_config>() {
                 return FS_NVRAM_ERR_INVALID_ADDRESS; // data is corrupted?
             }
 
             fs_nvram_memcpy_from(fs, offset as u_int, addr_of_mut!(private_head).cast::<u_char>(), size_of::<fs_nvram_header_private_config>() as u_int);
-            be_to_native_header_private(addr_of_mut!(private_head));
-            if FS_NVRAM_MAGIC_PRIVATE_CONFIG == private_head.magic {
-                if private_head.end as size_t != private_head.start as size_t + private_head.len as size_t || private_head.len as size_t > (*fs).len {
+            if FS_NVRAM_MAGIC_PRIVATE_CONFIG == private_head.magic.get() {
+                if private_head.end.get() as size_t != private_head.start.get() as size_t + private_head.len.get() as size_t || private_head.len.get() as size_t > (*fs).len {
                     return FS_NVRAM_ERR_INVALID_ADDRESS; // data is corrupted?
                 }
-                if private_head.start < (*fs).addr || private_head.end as size_t > (*fs).addr as size_t + (*fs).len {
+                if private_head.start.get() < (*fs).addr || private_head.end.get() as size_t > (*fs).addr as size_t + (*fs).len {
                     return FS_NVRAM_ERR_INVALID_ADDRESS; // (*fs).addr has the wrong value?
                 }
-                if private_head.end != private_head.start + private_head.len {
+                if private_head.end.get() != private_head.start.get() + private_head.len.get() {
                     return FS_NVRAM_ERR_INVALID_ADDRESS; // data is corrupted?
                 }
             }
@@ -2,6 +2,9 @@
 //! Copyright (c) 2006 Christophe Fillot (cf@utc.fr)
 //!
 //! Generic Hash Tables.
+//!
+//! The *_hash_seeded functions use SipHash-1-3 with a random key per process,
+//! for tables keyed by data controlled by the guests (MAC addresses, ...).
 
 use crate::_extra::*;
 use crate::dynamips_common::*;
@@ -10,8 +13,10 @@ use std::ffi::c_char;
 use std::ffi::c_int;
 use std::ffi::c_long;
 use std::ffi::c_void;
+use std::hash::BuildHasher;
 use std::ptr::addr_of_mut;
 use std::ptr::null_mut;
+use std::sync::OnceLock;
 
 // Key computation function
 pub type hash_fcompute = Option<unsafe extern "C" fn(key: *mut c_void) -> u_int>;
@@ -72,6 +77,34 @@ macro_rules! hash_ptr_create {
 }
 pub(crate) use hash_ptr_create;
 
+macro_rules! hash_string_create_seeded {
+    ($hash_size:expr) => {
+        hash_table_create(Some(str_hash_seeded), Some(str_equal), $hash_size)
+    };
+}
+pub(crate) use hash_string_create_seeded;
+
+macro_rules! hash_int_create_seeded {
+    ($hash_size:expr) => {
+        hash_table_create(Some(int_hash_seeded), Some(int_equal), $hash_size)
+    };
+}
+pub(crate) use hash_int_create_seeded;
+
+macro_rules! hash_u64_create_seeded {
+    ($hash_size:expr) => {
+        hash_table_create(Some(u64_hash_seeded), Some(u64_equal), $hash_size)
+    };
+}
+pub(crate) use hash_u64_create_seeded;
+
+macro_rules! hash_ptr_create_seeded {
+    ($hash_size:expr) => {
+        hash_table_create(Some(ptr_hash_seeded), Some(ptr_equal), $hash_size)
+    };
+}
+pub(crate) use hash_ptr_create_seeded;
+
 macro_rules! HASH_TABLE_FOREACH {
     ($i:ident, $ht:expr, $hn:ident, $($tt:tt)*) => {
         for $i in 0..(*$ht).size {
@@ -153,6 +186,119 @@ pub unsafe extern "C" fn ptr_hash(i: *mut c_void) -> u_int {
     ((val & 0xFFFF) ^ ((val >> 24) & 0xFFFF) ^ ((val >> 48) & 0xFFFF)) as u_int
 }
 
+/// SipHash-1-3 (as used by the Rust standard library).
+pub fn siphash13(key: (u64, u64), data: &[u8]) -> u64 {
+    siphash::<1, 3>(key, data)
+}
+
+/// SipHash-c-d.
+pub fn siphash<const C: usize, const D: usize>(key: (u64, u64), data: &[u8]) -> u64 {
+    let mut v0 = key.0 ^ 0x736f6d6570736575;
+    let mut v1 = key.1 ^ 0x646f72616e646f6d;
+    let mut v2 = key.0 ^ 0x6c7967656e657261;
+    let mut v3 = key.1 ^ 0x7465646279746573;
+
+    macro_rules! round {
+        () => {
+            v0 = v0.wrapping_add(v1);
+            v1 = v1.rotate_left(13) ^ v0;
+            v0 = v0.rotate_left(32);
+            v2 = v2.wrapping_add(v3);
+            v3 = v3.rotate_left(16) ^ v2;
+            v0 = v0.wrapping_add(v3);
+            v3 = v3.rotate_left(21) ^ v0;
+            v2 = v2.wrapping_add(v1);
+            v1 = v1.rotate_left(17) ^ v2;
+            v2 = v2.rotate_left(32);
+        };
+    }
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let m = u64::from_le_bytes(chunk.try_into().unwrap());
+        v3 ^= m;
+        for _ in 0..C {
+            round!();
+        }
+        v0 ^= m;
+    }
+
+    let mut last = [0u8; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    let m = u64::from_le_bytes(last) | ((data.len() as u64) << 56);
+    v3 ^= m;
+    for _ in 0..C {
+        round!();
+    }
+    v0 ^= m;
+
+    v2 ^= 0xff;
+    for _ in 0..D {
+        round!();
+    }
+    v0 ^ v1 ^ v2 ^ v3
+}
+
+static HASH_SEED: OnceLock<(u64, u64)> = OnceLock::new();
+
+/// Key of the seeded hash functions, random unless set by hash_set_seed().
+pub fn hash_seed() -> (u64, u64) {
+    *HASH_SEED.get_or_init(|| {
+        // the keys of RandomState come from the OS random generator
+        let state = std::collections::hash_map::RandomState::new();
+        (state.hash_one(0u64), state.hash_one(1u64))
+    })
+}
+
+/// Seeded hash of a block of bytes.
+pub fn hash_seeded(data: &[u8]) -> u_int {
+    let h = siphash13(hash_seed(), data);
+    (h ^ (h >> 32)) as u_int
+}
+
+// Set the key of the seeded hash functions (for reproducible runs).
+// Fails if the seeded functions were already used.
+#[no_mangle]
+pub extern "C" fn hash_set_seed(k0: m_uint64_t, k1: m_uint64_t) -> c_int {
+    match HASH_SEED.set((k0, k1)) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+// Seeded hash function for a block of bytes
+#[no_mangle]
+pub unsafe extern "C" fn mem_hash_seeded(ptr: *const c_void, len: size_t) -> u_int {
+    if len == 0 {
+        return hash_seeded(&[]);
+    }
+    hash_seeded(std::slice::from_raw_parts(ptr.cast::<u8>(), len))
+}
+
+// Seeded hash function for a string
+#[no_mangle]
+pub unsafe extern "C" fn str_hash_seeded(str_: *mut c_void) -> u_int {
+    hash_seeded(std::ffi::CStr::from_ptr(str_.cast::<c_char>()).to_bytes())
+}
+
+// Seeded hash function for an integer
+#[no_mangle]
+pub unsafe extern "C" fn int_hash_seeded(i: *mut c_void) -> u_int {
+    hash_seeded(&(i as c_long as c_int).to_le_bytes())
+}
+
+// Seeded hash function for an u64
+#[no_mangle]
+pub unsafe extern "C" fn u64_hash_seeded(i: *mut c_void) -> u_int {
+    hash_seeded(&(*i.cast::<m_uint64_t>()).to_le_bytes())
+}
+
+// Seeded hash function for a pointer
+#[no_mangle]
+pub unsafe extern "C" fn ptr_hash_seeded(i: *mut c_void) -> u_int {
+    hash_seeded(&(i as m_iptr_t as m_uint64_t).to_le_bytes())
+}
+
 // Free memory used by a node
 #[inline]
 unsafe fn hash_node_free(node: *mut hash_node_t) {
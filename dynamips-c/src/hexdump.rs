@@ -0,0 +1,345 @@
+//! Hex dumps.
+//!
+//! Dumps are formatted like xxd: an offset, groups of bytes in hex and the
+//! printable ASCII characters, e.g. with half-word groups:
+//!
+//! ```text
+//! 00000000: 4500 0054 1c46 4000 4001 a0bd c0a8 0001  E..T.F@.@.......
+//! ```
+//!
+//! Groups of half-words and words are read in VM endianness (vmtoh16/vmtoh32),
+//! so the dump of guest memory shows the values seen by the guest. The decoder
+//! accepts this format back (and the old mem_dump one), ignoring the ASCII
+//! column, so a dump pasted in a bug report can be turned back into binary.
+
+use crate::_extra::*;
+use crate::dynamips_common::*;
+use crate::utils::*;
+use libc::size_t;
+use libc::ssize_t;
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::CStr;
+use std::fmt::Write as _;
+use std::io;
+
+// Hex dump format
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct hexdump_fmt {
+    /// Bytes per line.
+    pub line_len: c_int,
+    /// Group size: 1 (bytes), 2 (half-words) or 4 (words).
+    pub group: c_int,
+    /// Offset of the first byte.
+    pub offset_base: m_uint64_t,
+    /// Minimum number of hex digits of the offset, 0 to hide the offset.
+    pub offset_width: c_int,
+    /// Show the ASCII column.
+    pub ascii: c_int,
+}
+pub type hexdump_fmt_t = hexdump_fmt;
+
+impl Default for hexdump_fmt {
+    fn default() -> Self {
+        Self { line_len: 16, group: 1, offset_base: 0, offset_width: 8, ascii: TRUE }
+    }
+}
+
+impl hexdump_fmt {
+    fn line_len(&self) -> usize {
+        self.line_len.clamp(1, 256) as usize
+    }
+
+    fn group(&self) -> usize {
+        match self.group {
+            2 | 4 => self.group as usize,
+            _ => 1,
+        }
+    }
+}
+
+/// Printable ASCII rendering of a byte.
+fn hexdump_char(b: u8) -> char {
+    if (0x20..0x7f).contains(&b) {
+        b as char
+    } else {
+        '.'
+    }
+}
+
+/// Hex of a group, a full half-word/word is read in VM endianness.
+fn hexdump_group(out: &mut String, bytes: &[u8]) {
+    match bytes.len() {
+        2 => write!(out, "{:04x}", vmtoh16(u16::from_ne_bytes([bytes[0], bytes[1]]))),
+        4 => write!(out, "{:08x}", vmtoh32(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))),
+        _ => bytes.iter().try_for_each(|b| write!(out, "{:02x}", b)),
+    }
+    .unwrap();
+}
+
+/// Format one line of a dump (without end of line).
+#[allow(clippy::manual_repeat_n)]
+fn hexdump_line(out: &mut String, offset: u64, bytes: &[u8], fmt: &hexdump_fmt) {
+    let line_len = fmt.line_len();
+    let group = fmt.group();
+
+    if fmt.offset_width > 0 {
+        write!(out, "{:0width$x}: ", offset, width = fmt.offset_width as usize).unwrap();
+    }
+
+    let ngroups = line_len.div_ceil(group);
+    for (i, g) in bytes.chunks(group).enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        hexdump_group(out, g);
+    }
+
+    if fmt.ascii != FALSE {
+        // pad a partial line to keep the ASCII column aligned
+        out.extend(std::iter::repeat(' ').take((group - 1 - (bytes.len() + group - 1) % group) * 2));
+        let missing = ngroups - bytes.len().div_ceil(group);
+        out.extend(std::iter::repeat(' ').take(missing * (group * 2 + 1) + 2));
+        out.extend(bytes.iter().map(|&b| hexdump_char(b)));
+    }
+}
+
+/// Format a dump, each line ends with '\n'.
+pub fn hexdump_string(data: &[u8], fmt: &hexdump_fmt) -> String {
+    let mut out = String::new();
+
+    for (i, line) in data.chunks(fmt.line_len()).enumerate() {
+        hexdump_line(&mut out, fmt.offset_base.wrapping_add((i * fmt.line_len()) as u64), line, fmt);
+        out.push('\n');
+    }
+    out
+}
+
+/// Write a dump.
+pub fn hexdump_write<W: io::Write>(w: &mut W, data: &[u8], fmt: &hexdump_fmt) -> io::Result<()> {
+    let mut line_buf = String::new();
+
+    for (i, line) in data.chunks(fmt.line_len()).enumerate() {
+        line_buf.clear();
+        hexdump_line(&mut line_buf, fmt.offset_base.wrapping_add((i * fmt.line_len()) as u64), line, fmt);
+        line_buf.push('\n');
+        w.write_all(line_buf.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Error of the dump decoder (1-based line and column).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HexError {
+    /// Invalid hex digit or odd number of digits.
+    Digit { line: usize, col: usize },
+    /// Offset lower than the data already decoded.
+    Offset { line: usize, col: usize },
+    /// Output buffer too small.
+    Overflow { line: usize, col: usize },
+}
+
+impl HexError {
+    pub fn line(&self) -> usize {
+        match *self {
+            HexError::Digit { line, .. } | HexError::Offset { line, .. } | HexError::Overflow { line, .. } => line,
+        }
+    }
+
+    pub fn col(&self) -> usize {
+        match *self {
+            HexError::Digit { col, .. } | HexError::Offset { col, .. } | HexError::Overflow { col, .. } => col,
+        }
+    }
+}
+
+impl std::fmt::Display for HexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let what = match self {
+            HexError::Digit { .. } => "invalid hex digits",
+            HexError::Offset { .. } => "offset going backwards",
+            HexError::Overflow { .. } => "too much data",
+        };
+        write!(f, "{} at line {} column {}", what, self.line(), self.col())
+    }
+}
+
+impl std::error::Error for HexError {}
+
+/// Decoded dump.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HexDecoded {
+    /// Offset of the first line (0 without offsets).
+    pub start: u64,
+    pub data: Vec<u8>,
+}
+
+fn hex_bytes(token: &str) -> Option<Vec<u8>> {
+    if token.is_empty() || token.len() % 2 != 0 || !token.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..token.len()).step_by(2).map(|i| u8::from_str_radix(&token[i..i + 2], 16).ok()).collect()
+}
+
+/// Decode a dump produced with `fmt` (only line_len and group are used).
+///
+/// Each line is an optional "offset:", then the hex groups separated by one
+/// space. The groups end at two consecutive spaces (ASCII column), after
+/// line_len bytes or at the first token that isn't a group. A line made of a
+/// single token is taken as contiguous hex. Gaps between offsets are filled
+/// with zeroes, like xxd -r.
+pub fn hexdump_decode(text: &str, fmt: &hexdump_fmt, maxlen: Option<usize>) -> Result<HexDecoded, HexError> {
+    let mut res = HexDecoded::default();
+    let mut first = true;
+    let group = fmt.group();
+
+    for (n, line) in text.lines().enumerate() {
+        let lineno = n + 1;
+        let mut rest = line.trim_end();
+        let mut col = 1;
+
+        // skip leading spaces
+        let trimmed = rest.trim_start();
+        col += rest.len() - trimmed.len();
+        rest = trimmed;
+        if rest.is_empty() {
+            continue;
+        }
+
+        // offset
+        let mut line_offset = None;
+        if let Some(pos) = rest.find(':') {
+            if pos > 0 && rest[..pos].bytes().all(|c| c.is_ascii_hexdigit()) {
+                let offset = u64::from_str_radix(&rest[..pos], 16).map_err(|_| HexError::Digit { line: lineno, col })?;
+                line_offset = Some((offset, col));
+                col += pos + 1;
+                rest = &rest[pos + 1..];
+                let trimmed = rest.trim_start();
+                col += rest.len() - trimmed.len();
+                rest = trimmed;
+            }
+        }
+
+        // hex groups
+        let data_col = col;
+        let mut bytes = Vec::new();
+        let single = !rest.contains(char::is_whitespace);
+        while !rest.is_empty() && bytes.len() < fmt.line_len() {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let token = &rest[..end];
+            let group_bytes = match hex_bytes(token) {
+                Some(b) if single || b.len() <= group => b,
+                // ASCII column or comment
+                _ if !bytes.is_empty() => break,
+                _ => return Err(HexError::Digit { line: lineno, col }),
+            };
+            if group_bytes.len() == group {
+                // a full half-word/word was written in VM endianness
+                let value = match group {
+                    2 => htovm16(u16::from_str_radix(token, 16).unwrap()).to_ne_bytes().to_vec(),
+                    4 => htovm32(u32::from_str_radix(token, 16).unwrap()).to_ne_bytes().to_vec(),
+                    _ => group_bytes,
+                };
+                bytes.extend(value);
+            } else {
+                bytes.extend(group_bytes);
+            }
+
+            rest = &rest[end..];
+            col += end;
+            if rest.starts_with("  ") || rest.starts_with('\t') {
+                break;
+            }
+            let trimmed = rest.trim_start();
+            col += rest.len() - trimmed.len();
+            rest = trimmed;
+        }
+
+        // position of the line
+        if let Some((offset, ocol)) = line_offset {
+            if first {
+                res.start = offset;
+            }
+            let pos = offset.checked_sub(res.start).filter(|&pos| pos >= res.data.len() as u64).ok_or(HexError::Offset { line: lineno, col: ocol })?;
+            if maxlen.is_some_and(|max| pos as usize > max) {
+                return Err(HexError::Overflow { line: lineno, col: ocol });
+            }
+            res.data.resize(pos as usize, 0);
+        }
+        first = false;
+
+        if maxlen.is_some_and(|max| res.data.len() + bytes.len() > max) {
+            return Err(HexError::Overflow { line: lineno, col: data_col });
+        }
+        res.data.extend(bytes);
+    }
+
+    Ok(res)
+}
+
+// Initialize a hex dump format with the defaults (16 bytes per line, bytes, 8-digit offsets, ASCII)
+#[no_mangle]
+pub unsafe extern "C" fn hexdump_fmt_init(fmt: *mut hexdump_fmt_t) {
+    *fmt = hexdump_fmt::default();
+}
+
+unsafe fn hexdump_fmt_or_default(fmt: *const hexdump_fmt_t) -> hexdump_fmt {
+    if fmt.is_null() {
+        hexdump_fmt::default()
+    } else {
+        *fmt
+    }
+}
+
+// Dump a buffer to a FILE (fmt can be NULL for the defaults)
+#[no_mangle]
+pub unsafe extern "C" fn hexdump_fprint(f_output: *mut libc::FILE, data: *const u_char, len: size_t, fmt: *const hexdump_fmt_t) -> c_int {
+    let data = if len == 0 { &[][..] } else { std::slice::from_raw_parts(data, len) };
+    let text = hexdump_string(data, &hexdump_fmt_or_default(fmt));
+
+    if libc::fwrite(text.as_ptr().cast::<_>(), 1, text.len(), f_output) != text.len() {
+        return -1;
+    }
+    libc::fflush(f_output);
+    0
+}
+
+// Dump a buffer to a string allocated with malloc (fmt can be NULL for the defaults)
+#[no_mangle]
+pub unsafe extern "C" fn hexdump_sprint(data: *const u_char, len: size_t, fmt: *const hexdump_fmt_t) -> *mut c_char {
+    let data = if len == 0 { &[][..] } else { std::slice::from_raw_parts(data, len) };
+    let text = hexdump_string(data, &hexdump_fmt_or_default(fmt));
+    let p: *mut c_char = libc::malloc(text.len() + 1).cast::<_>();
+
+    if !p.is_null() {
+        libc::memcpy(p.cast::<_>(), text.as_ptr().cast::<_>(), text.len());
+        *p.add(text.len()) = 0;
+    }
+    p
+}
+
+// Decode a hex dump into out, returns the number of bytes or -1.
+// start (the offset of the first line) and err_line can be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn hexdump_decode_str(text: *const c_char, out: *mut u_char, maxlen: size_t, fmt: *const hexdump_fmt_t, start: *mut m_uint64_t, err_line: *mut c_int) -> ssize_t {
+    let text = CStr::from_ptr(text).to_string_lossy();
+
+    match hexdump_decode(&text, &hexdump_fmt_or_default(fmt), Some(maxlen)) {
+        Ok(res) => {
+            if !res.data.is_empty() {
+                libc::memcpy(out.cast::<_>(), res.data.as_ptr().cast::<_>(), res.data.len());
+            }
+            if !start.is_null() {
+                *start = res.start;
+            }
+            res.data.len() as ssize_t
+        }
+        Err(err) => {
+            if !err_line.is_null() {
+                *err_line = err.line() as c_int;
+            }
+            -1
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Deterministic hardware identifiers.
+//!
+//! Derives the base MAC address and the processor board id of a VM instance
+//! from a hypervisor-wide seed plus the instance name and id, so recreating
+//! a lab yields the same identifiers.
+//!
+//! Each instance owns the MAC addresses `xx:xx:xx:xx:00:00` to `xx:xx:xx:xx:ff:ff`,
+//! the first byte is the platform MSB. The other 3 bytes of the prefix are
+//! derived from the seed, a collision with another instance is resolved by
+//! rehashing, so the result only depends on the creation order in that case.
+//!
+//! Without a seed, the legacy scheme is used: instance id + process id.
+
+use crate::_extra::*;
+use crate::cisco_eeprom::*;
+use crate::dynamips_common::*;
+use crate::net::*;
+use libc::size_t;
+use std::collections::BTreeMap;
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::CStr;
+use std::sync::Mutex;
+
+/// Number of MAC addresses owned by an instance.
+pub const HWID_MAC_BLOCK_SIZE: usize = 1 << 16;
+
+/// MAC address block size written in the EEPROMs (field 0x43), the value of
+/// the default mainboard EEPROMs. It is well below `HWID_MAC_BLOCK_SIZE`.
+pub const HWID_EEPROM_MAC_BLOCK_SIZE: m_uint16_t = 0x20;
+
+/// Prefix of the generated board ids.
+const BOARD_ID_PREFIX: &[u8; 3] = b"DYN";
+
+/// Alphabet of the generated board ids.
+const BOARD_ID_CHARS: &[u8; 36] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// FNV-1a, stable across platforms and releases (unlike `DefaultHasher`).
+fn fnv1a(mut h: u64, data: &[u8]) -> u64 {
+    for b in data {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+/// splitmix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Derive a seed from a string.
+pub fn hwid_seed_from_str(s: &str) -> u64 {
+    mix(fnv1a(0xcbf29ce484222325, s.as_bytes()))
+}
+
+/// Allocator of the hardware identifiers.
+#[derive(Debug)]
+pub struct HwidAllocator {
+    seed: Option<u64>,
+    by_instance: BTreeMap<(String, c_int, m_uint8_t), [m_uint8_t; 4]>,
+    by_prefix: BTreeMap<[m_uint8_t; 4], (String, c_int)>,
+}
+
+impl HwidAllocator {
+    pub const fn new(seed: Option<u64>) -> Self {
+        HwidAllocator { seed, by_instance: BTreeMap::new(), by_prefix: BTreeMap::new() }
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Change the seed, forgets the previous allocations.
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        *self = HwidAllocator::new(seed);
+    }
+
+    fn instance_hash(&self, seed: u64, name: &str, instance_id: c_int) -> u64 {
+        let h = fnv1a(seed, name.as_bytes());
+        mix(fnv1a(h, &instance_id.to_le_bytes()))
+    }
+
+    /// Base MAC address of an instance, the 2 last bytes are zero.
+    pub fn alloc_mac(&mut self, name: &str, instance_id: c_int, msb: m_uint8_t) -> EthAddr {
+        let Some(seed) = self.seed else {
+            // legacy scheme
+            let pid = unsafe { libc::getpid() } as m_uint16_t;
+            let [p0, p1] = pid.to_be_bytes();
+            return EthAddr([msb, (instance_id & 0xFF) as m_uint8_t, p0, p1, 0x00, 0x00]);
+        };
+
+        let key = (name.to_string(), instance_id, msb);
+        let prefix = match self.by_instance.get(&key) {
+            Some(prefix) => *prefix,
+            None => {
+                let mut h = self.instance_hash(seed, name, instance_id);
+                let prefix = loop {
+                    let [_, _, _, _, _, b1, b2, b3] = h.to_be_bytes();
+                    let prefix = [msb, b1, b2, b3];
+                    if !self.by_prefix.contains_key(&prefix) {
+                        break prefix;
+                    }
+                    h = mix(h.wrapping_add(1));
+                };
+                self.by_prefix.insert(prefix, (name.to_string(), instance_id));
+                self.by_instance.insert(key, prefix);
+                prefix
+            }
+        };
+
+        EthAddr([prefix[0], prefix[1], prefix[2], prefix[3], 0x00, 0x00])
+    }
+
+    /// Forget the MAC addresses of an instance.
+    pub fn release(&mut self, name: &str, instance_id: c_int) {
+        self.by_instance.retain(|(n, id, _), prefix| {
+            let keep = !(n == name && *id == instance_id);
+            if !keep {
+                self.by_prefix.remove(prefix);
+            }
+            keep
+        });
+    }
+
+    /// Processor board id of an instance for the specified encoding (4, 9 or 11 bytes).
+    /// Returns None without a seed.
+    pub fn board_id(&self, name: &str, instance_id: c_int, encode: usize) -> Option<BoardId> {
+        let seed = self.seed?;
+        let mut h = mix(self.instance_hash(seed, name, instance_id) ^ 0x626f6172645f6964);
+        let hex = (mix(h) as m_uint16_t).to_be_bytes();
+        let mut chars = || {
+            let c = BOARD_ID_CHARS[(h % BOARD_ID_CHARS.len() as u64) as usize];
+            h /= BOARD_ID_CHARS.len() as u64;
+            c
+        };
+
+        match encode {
+            4 => Some(BoardId::Numeric((h as m_uint32_t).max(1))),
+            9 => {
+                let mut buf: [m_uint8_t; 9] = [0; 9];
+                buf[..3].copy_from_slice(BOARD_ID_PREFIX);
+                buf[3..5].copy_from_slice(&hex);
+                buf[5..].iter_mut().for_each(|c| *c = chars());
+                Some(BoardId::Packed(buf))
+            }
+            11 => {
+                let mut buf: [m_uint8_t; 11] = [0; 11];
+                buf[..3].copy_from_slice(BOARD_ID_PREFIX);
+                buf[3..].iter_mut().for_each(|c| *c = chars());
+                Some(BoardId::Text(buf))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Hypervisor-wide allocator.
+pub static HWID: Mutex<HwidAllocator> = Mutex::new(HwidAllocator::new(None));
+
+fn hwid() -> std::sync::MutexGuard<'static, HwidAllocator> {
+    HWID.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+unsafe fn c_str<'a>(s: *const c_char) -> &'a str {
+    if s.is_null() {
+        ""
+    } else {
+        CStr::from_ptr(s).to_str().unwrap_or("")
+    }
+}
+
+// Set the hypervisor-wide seed (NULL or empty to use the legacy scheme)
+#[no_mangle]
+pub unsafe extern "C" fn hwid_set_seed(seed: *const c_char) {
+    let seed = c_str(seed);
+    hwid().set_seed((!seed.is_empty()).then(|| hwid_seed_from_str(seed)));
+}
+
+// Get the base MAC address of an instance
+#[no_mangle]
+pub unsafe extern "C" fn hwid_alloc_mac(name: *const c_char, instance_id: c_int, msb: u_int, addr: *mut n_eth_addr_t) -> c_int {
+    *addr = hwid().alloc_mac(c_str(name), instance_id, msb as m_uint8_t).into();
+    0
+}
+
+// Release the MAC addresses of an instance
+#[no_mangle]
+pub unsafe extern "C" fn hwid_release(name: *const c_char, instance_id: c_int) {
+    hwid().release(c_str(name), instance_id);
+}
+
+// Store the board id string of an instance in buf, returns -1 if no seed is set
+#[no_mangle]
+pub unsafe extern "C" fn hwid_board_id(name: *const c_char, instance_id: c_int, encode: c_int, buf: *mut c_char, len: size_t) -> c_int {
+    let Some(board_id) = hwid().board_id(c_str(name), instance_id, encode as usize) else {
+        return -1;
+    };
+    let s = board_id.to_string();
+    if s.len() >= len {
+        return -1;
+    }
+    libc::memcpy(buf.cast::<_>(), s.as_ptr().cast::<_>(), s.len());
+    *buf.add(s.len()) = 0;
+    0
+}
+
+// Write the base MAC address and the MAC block size in an EEPROM
+#[no_mangle]
+pub unsafe extern "C" fn hwid_burn_eeprom(eeprom: *mut cisco_eeprom, addr: *const n_eth_addr_t, block_size: m_uint16_t) -> c_int {
+    if cisco_eeprom_set_mac_addr(eeprom, addr) == -1 {
+        return -1;
+    }
+    // not all EEPROMs have a block size field
+    let _ = cisco_eeprom_set_mac_block_size(eeprom, block_size);
+    0
+}
@@ -58,18 +58,33 @@ pub mod _extra;
 #[cfg(test)]
 pub mod _tests;
 
+pub mod atm_oam;
 pub mod base64;
 pub mod cisco_eeprom;
 pub mod crc;
 pub mod dynamips_common;
+pub mod endian;
+pub mod evloop;
+pub mod frame_relay;
 pub mod fs_fat;
 pub mod fs_mbr;
 pub mod fs_nvram;
 pub mod hash;
+pub mod hexdump;
+pub mod hwid;
+pub mod mac_table;
 pub mod mempool;
+pub mod metrics;
 pub mod net;
+pub mod netem;
+pub mod pkt_filter;
 pub mod rbtree;
 pub mod rommon_var;
+pub mod rstp;
 pub mod sbox;
+pub mod span;
 pub mod timer;
 pub mod utils;
+pub mod vlan;
+pub mod vtty_mux;
+pub mod vtty_rec;
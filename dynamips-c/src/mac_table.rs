@@ -0,0 +1,676 @@
+//! MAC address learning table of the Ethernet switch.
+//!
+//! Entries are keyed by MAC address and VLAN and point to an opaque port
+//! (the NIO of the switch). Dynamic entries expire after the aging time,
+//! static entries are configured and sticky entries are learned on a port
+//! with sticky port security: both stay until removed.
+//!
+//! Port security limits the number of addresses of a port. When a new
+//! address exceeds the limit, the violation action applies: protect drops
+//! the frame, restrict also counts the violation and reports it, shutdown
+//! puts the port in the err-disabled state until it is recovered.
+//!
+//! A dynamic address seen on another port moves to the new port (and is
+//! reported). Static and sticky addresses do not move, frames with such a
+//! source address on another port are dropped.
+//!
+//! The entries live in a std HashMap owned by the table (as the other Rust
+//! state tables) rather than in a hash.rs table, whose void pointer keys and
+//! values would need an allocation per entry and manual frees. The keys are
+//! still hashed with the seeded SipHash-1-3 of hash.rs (hash_seed), so the
+//! table has the same resistance to guest-chosen addresses and follows
+//! --hash-seed like the *_hash_seeded tables.
+
+use crate::_extra::*;
+use crate::dynamips_common::*;
+use crate::hash::*;
+use crate::net::*;
+use crate::timer::*;
+use crate::utils::*;
+use libc::size_t;
+use std::collections::HashMap;
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::c_void;
+use std::ffi::CStr;
+use std::hash::BuildHasherDefault;
+use std::hash::Hasher;
+use std::ptr::null_mut;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+/// Default aging time in seconds.
+pub const MAC_TABLE_DEFAULT_AGING: u_int = 300;
+
+/// Maximum number of entries of a table.
+pub const MAC_TABLE_MAX_ENTRIES: usize = 65536;
+
+/// Interval of the aging timer in milliseconds.
+pub const MAC_TABLE_AGING_INTERVAL: m_tmcnt_t = 1000;
+
+// Entry types
+pub const MAC_ENTRY_DYNAMIC: c_int = 0;
+pub const MAC_ENTRY_STATIC: c_int = 1;
+pub const MAC_ENTRY_STICKY: c_int = 2;
+
+// Port security violation actions
+pub const MAC_VIOLATION_PROTECT: c_int = 0;
+pub const MAC_VIOLATION_RESTRICT: c_int = 1;
+pub const MAC_VIOLATION_SHUTDOWN: c_int = 2;
+
+// Learning results
+/// Forward the frame.
+pub const MAC_LEARN_FORWARD: c_int = 0;
+/// Drop the frame (port security or address owned by another port).
+pub const MAC_LEARN_DROP: c_int = 1;
+
+// Events
+/// A dynamic address moved to another port.
+pub const MAC_EVENT_MOVE: c_int = 1;
+/// Port security violation (restrict or shutdown).
+pub const MAC_EVENT_VIOLATION: c_int = 2;
+/// A port was err-disabled by a violation.
+pub const MAC_EVENT_SHUTDOWN: c_int = 3;
+/// A dynamic address expired.
+pub const MAC_EVENT_AGED: c_int = 4;
+
+// MAC address table entry (copy given to callbacks)
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mac_table_entry {
+    pub mac_addr: n_eth_addr_t,
+    pub vlan_id: m_uint16_t,
+    pub port: *mut c_void,
+    /// MAC_ENTRY_*
+    pub r#type: c_int,
+    /// Seconds since the address was last seen.
+    pub age: u_int,
+}
+pub type mac_table_entry_t = mac_table_entry;
+
+// Port security status
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mac_port_security {
+    /// Maximum number of addresses, 0 for no limit.
+    pub max_macs: u_int,
+    /// MAC_VIOLATION_*
+    pub action: c_int,
+    pub sticky: c_int,
+    /// Number of addresses of the port.
+    pub count: u_int,
+    pub violations: m_uint64_t,
+    /// Port err-disabled.
+    pub shutdown: c_int,
+}
+pub type mac_port_security_t = mac_port_security;
+
+// Event callback, old_port is the previous port of a moved address
+pub type mac_table_event_cbk_t = Option<unsafe extern "C" fn(event: c_int, entry: *const mac_table_entry_t, old_port: *mut c_void, opt: *mut c_void)>;
+
+// "foreach" callback
+pub type mac_table_foreach_cbk_t = Option<unsafe extern "C" fn(entry: *const mac_table_entry_t, opt: *mut c_void)>;
+
+/// Seeded hash of the packed MAC address and VLAN, the guests choose the addresses.
+#[derive(Debug, Default)]
+struct MacKeyHasher(u64);
+
+impl Hasher for MacKeyHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0 ^= siphash13(hash_seed(), bytes);
+    }
+
+    fn write_u64(&mut self, x: u64) {
+        self.write(&x.to_ne_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// Key of an entry: MAC address in the high 48 bits, VLAN in the low 16 bits
+fn mac_key(mac: &EthAddr, vlan_id: m_uint16_t) -> u64 {
+    let mut b = [0u8; 8];
+    b[..N_ETH_ALEN].copy_from_slice(&mac.0);
+    u64::from_be_bytes(b) | vlan_id as u64
+}
+
+fn mac_key_split(key: u64) -> (EthAddr, m_uint16_t) {
+    let mut mac = EthAddr::default();
+    mac.0.copy_from_slice(&key.to_be_bytes()[..N_ETH_ALEN]);
+    (mac, key as m_uint16_t)
+}
+
+#[derive(Debug, Copy, Clone)]
+struct MacEntry {
+    port: *mut c_void,
+    kind: c_int,
+    last_seen: m_tmcnt_t,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct MacPort {
+    max_macs: u_int,
+    action: c_int,
+    sticky: bool,
+    count: u_int,
+    violations: m_uint64_t,
+    shutdown: bool,
+}
+
+impl Default for MacPort {
+    fn default() -> Self {
+        MacPort { max_macs: 0, action: MAC_VIOLATION_SHUTDOWN, sticky: false, count: 0, violations: 0, shutdown: false }
+    }
+}
+
+/// Result of learning a source address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MacLearn {
+    /// Already known on this port.
+    Known,
+    /// New address.
+    Learned,
+    /// Not learned, the table is full (the frame is forwarded).
+    Full,
+    /// Dynamic address moved from the given port.
+    Moved(*mut c_void),
+    /// Static or sticky address of another port.
+    Conflict,
+    /// Over the port limit, action protect.
+    Protect,
+    /// Over the port limit, action restrict.
+    Restrict,
+    /// Over the port limit, the port is now err-disabled.
+    Shutdown,
+    /// The port is err-disabled.
+    Disabled,
+}
+
+impl MacLearn {
+    /// MAC_LEARN_FORWARD or MAC_LEARN_DROP.
+    pub fn code(&self) -> c_int {
+        match self {
+            MacLearn::Known | MacLearn::Learned | MacLearn::Full | MacLearn::Moved(_) => MAC_LEARN_FORWARD,
+            _ => MAC_LEARN_DROP,
+        }
+    }
+}
+
+/// MAC learning table, times are in milliseconds (m_gettime).
+#[derive(Debug, Default)]
+pub struct MacTable {
+    entries: HashMap<u64, MacEntry, BuildHasherDefault<MacKeyHasher>>,
+    ports: HashMap<usize, MacPort>,
+    /// Aging time in milliseconds, 0 to disable aging.
+    aging: m_tmcnt_t,
+    moves: m_uint64_t,
+}
+
+impl MacTable {
+    /// Table with an aging time in seconds.
+    pub fn new(aging: u_int) -> Self {
+        MacTable { aging: aging as m_tmcnt_t * 1000, ..Default::default() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Aging time in seconds.
+    pub fn aging(&self) -> u_int {
+        (self.aging / 1000) as u_int
+    }
+
+    /// Set the aging time in seconds, 0 to disable aging.
+    pub fn set_aging(&mut self, aging: u_int) {
+        self.aging = aging as m_tmcnt_t * 1000;
+    }
+
+    /// Number of address moves.
+    pub fn moves(&self) -> m_uint64_t {
+        self.moves
+    }
+
+    fn port_mut(&mut self, port: *mut c_void) -> &mut MacPort {
+        self.ports.entry(port as usize).or_default()
+    }
+
+    fn port_count_add(&mut self, port: *mut c_void, delta: i32) {
+        let p = self.port_mut(port);
+        p.count = p.count.saturating_add_signed(delta);
+    }
+
+    fn entry_copy(key: u64, e: &MacEntry, now: m_tmcnt_t) -> mac_table_entry_t {
+        let (mac, vlan_id) = mac_key_split(key);
+        mac_table_entry_t { mac_addr: mac.into(), vlan_id, port: e.port, r#type: e.kind, age: (now.saturating_sub(e.last_seen) / 1000) as u_int }
+    }
+
+    /// Learn the source address of a frame received on a port.
+    pub fn learn(&mut self, mac: &EthAddr, vlan_id: m_uint16_t, port: *mut c_void, now: m_tmcnt_t) -> MacLearn {
+        let key = mac_key(mac, vlan_id);
+        let mut old_port = null_mut();
+
+        if self.ports.get(&(port as usize)).is_some_and(|p| p.shutdown) {
+            return MacLearn::Disabled;
+        }
+
+        if let Some(e) = self.entries.get_mut(&key) {
+            if e.port == port {
+                e.last_seen = now;
+                return MacLearn::Known;
+            }
+            if e.kind != MAC_ENTRY_DYNAMIC {
+                return MacLearn::Conflict;
+            }
+            old_port = e.port;
+        } else if self.entries.len() >= MAC_TABLE_MAX_ENTRIES {
+            return MacLearn::Full;
+        }
+
+        // new address for this port: check the port limit
+        let p = self.port_mut(port);
+        if p.max_macs != 0 && p.count >= p.max_macs {
+            match p.action {
+                MAC_VIOLATION_PROTECT => return MacLearn::Protect,
+                MAC_VIOLATION_RESTRICT => {
+                    p.violations += 1;
+                    return MacLearn::Restrict;
+                }
+                _ => {
+                    p.violations += 1;
+                    p.shutdown = true;
+                    self.flush_port(port, false);
+                    return MacLearn::Shutdown;
+                }
+            }
+        }
+        let kind = if p.sticky { MAC_ENTRY_STICKY } else { MAC_ENTRY_DYNAMIC };
+        p.count += 1;
+
+        self.entries.insert(key, MacEntry { port, kind, last_seen: now });
+        if old_port.is_null() {
+            MacLearn::Learned
+        } else {
+            self.port_count_add(old_port, -1);
+            self.moves += 1;
+            MacLearn::Moved(old_port)
+        }
+    }
+
+    /// Port of an address.
+    pub fn lookup(&self, mac: &EthAddr, vlan_id: m_uint16_t) -> Option<*mut c_void> {
+        self.entries.get(&mac_key(mac, vlan_id)).map(|e| e.port)
+    }
+
+    /// Entry of an address.
+    pub fn get(&self, mac: &EthAddr, vlan_id: m_uint16_t, now: m_tmcnt_t) -> Option<mac_table_entry_t> {
+        let key = mac_key(mac, vlan_id);
+        self.entries.get(&key).map(|e| Self::entry_copy(key, e, now))
+    }
+
+    fn add_configured(&mut self, mac: &EthAddr, vlan_id: m_uint16_t, port: *mut c_void, kind: c_int, now: m_tmcnt_t) {
+        let key = mac_key(mac, vlan_id);
+        if let Some(old) = self.entries.insert(key, MacEntry { port, kind, last_seen: now }) {
+            self.port_count_add(old.port, -1);
+        }
+        self.port_count_add(port, 1);
+    }
+
+    /// Add a static address, replacing any entry of this address.
+    pub fn add_static(&mut self, mac: &EthAddr, vlan_id: m_uint16_t, port: *mut c_void, now: m_tmcnt_t) {
+        self.add_configured(mac, vlan_id, port, MAC_ENTRY_STATIC, now);
+    }
+
+    /// Add a sticky address (restored from a saved configuration), replacing any entry of this address.
+    pub fn add_sticky(&mut self, mac: &EthAddr, vlan_id: m_uint16_t, port: *mut c_void, now: m_tmcnt_t) {
+        self.add_configured(mac, vlan_id, port, MAC_ENTRY_STICKY, now);
+    }
+
+    /// Remove an address of any type.
+    pub fn remove(&mut self, mac: &EthAddr, vlan_id: m_uint16_t) -> bool {
+        match self.entries.remove(&mac_key(mac, vlan_id)) {
+            Some(old) => {
+                self.port_count_add(old.port, -1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the dynamic addresses (and the configured ones if all is set).
+    pub fn flush(&mut self, all: bool) {
+        self.entries.retain(|_, e| !all && e.kind != MAC_ENTRY_DYNAMIC);
+        for p in self.ports.values_mut() {
+            p.count = 0;
+        }
+        let ports: Vec<*mut c_void> = self.entries.values().map(|e| e.port).collect();
+        for port in ports {
+            self.port_count_add(port, 1);
+        }
+    }
+
+    /// Remove the dynamic addresses of a port (and the configured ones if all is set).
+    pub fn flush_port(&mut self, port: *mut c_void, all: bool) {
+        let mut removed = 0;
+        self.entries.retain(|_, e| {
+            let keep = e.port != port || (!all && e.kind != MAC_ENTRY_DYNAMIC);
+            if !keep {
+                removed += 1;
+            }
+            keep
+        });
+        self.port_count_add(port, -removed);
+    }
+
+    /// Forget a port: its addresses and its port security settings.
+    pub fn remove_port(&mut self, port: *mut c_void) {
+        self.flush_port(port, true);
+        self.ports.remove(&(port as usize));
+    }
+
+    /// Remove the expired dynamic addresses, returns them.
+    pub fn age(&mut self, now: m_tmcnt_t) -> Vec<mac_table_entry_t> {
+        let mut aged = Vec::new();
+
+        if self.aging == 0 {
+            return aged;
+        }
+
+        let aging = self.aging;
+        self.entries.retain(|&key, e| {
+            let keep = e.kind != MAC_ENTRY_DYNAMIC || now.saturating_sub(e.last_seen) < aging;
+            if !keep {
+                aged.push(Self::entry_copy(key, e, now));
+            }
+            keep
+        });
+        for e in &aged {
+            self.port_count_add(e.port, -1);
+        }
+        aged
+    }
+
+    /// Configure port security, max_macs 0 disables the limit.
+    /// Enabling sticky turns the dynamic addresses of the port into sticky ones, disabling it does the opposite.
+    pub fn set_port_security(&mut self, port: *mut c_void, max_macs: u_int, action: c_int, sticky: bool) -> bool {
+        if !matches!(action, MAC_VIOLATION_PROTECT | MAC_VIOLATION_RESTRICT | MAC_VIOLATION_SHUTDOWN) {
+            return false;
+        }
+
+        let p = self.port_mut(port);
+        p.max_macs = max_macs;
+        p.action = action;
+        p.sticky = sticky;
+
+        let (from, to) = if sticky { (MAC_ENTRY_DYNAMIC, MAC_ENTRY_STICKY) } else { (MAC_ENTRY_STICKY, MAC_ENTRY_DYNAMIC) };
+        for e in self.entries.values_mut() {
+            if e.port == port && e.kind == from {
+                e.kind = to;
+            }
+        }
+        true
+    }
+
+    /// Port security status of a port.
+    pub fn port_security(&self, port: *mut c_void) -> mac_port_security_t {
+        let p = self.ports.get(&(port as usize)).copied().unwrap_or_default();
+        mac_port_security_t { max_macs: p.max_macs, action: p.action, sticky: p.sticky as c_int, count: p.count, violations: p.violations, shutdown: p.shutdown as c_int }
+    }
+
+    pub fn port_is_shutdown(&self, port: *mut c_void) -> bool {
+        self.ports.get(&(port as usize)).is_some_and(|p| p.shutdown)
+    }
+
+    /// Recover an err-disabled port, returns false if it was not err-disabled.
+    pub fn port_recover(&mut self, port: *mut c_void) -> bool {
+        match self.ports.get_mut(&(port as usize)) {
+            Some(p) if p.shutdown => {
+                p.shutdown = false;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Copy of all entries.
+    pub fn entries(&self, now: m_tmcnt_t) -> Vec<mac_table_entry_t> {
+        self.entries.iter().map(|(&key, e)| Self::entry_copy(key, e, now)).collect()
+    }
+}
+
+// MAC address table with an aging timer
+#[derive(Debug)]
+pub struct mac_table {
+    table: Mutex<MacTable>,
+    timer: timer_id,
+    event: Mutex<(mac_table_event_cbk_t, *mut c_void)>,
+}
+pub type mac_table_t = mac_table;
+
+// the ports and the event context are owned by the caller
+unsafe impl Send for mac_table {}
+unsafe impl Sync for mac_table {}
+
+impl mac_table {
+    fn lock(&self) -> MutexGuard<'_, MacTable> {
+        self.table.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    unsafe fn event(&self, event: c_int, entry: &mac_table_entry_t, old_port: *mut c_void) {
+        let (cbk, opt) = *self.event.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(cbk) = cbk {
+            cbk(event, entry, old_port, opt);
+        }
+    }
+}
+
+// Aging timer
+unsafe extern "C" fn mac_table_timer(opt: *mut c_void, _timer: *mut timer_entry_t) -> c_int {
+    let t: *mut mac_table_t = opt.cast::<_>();
+    let aged = (*t).lock().age(m_gettime());
+    for entry in &aged {
+        (*t).event(MAC_EVENT_AGED, entry, null_mut());
+    }
+    TRUE
+}
+
+// Create a MAC address table (aging time in seconds, 0 to disable aging)
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_create(aging: u_int) -> *mut mac_table_t {
+    let t = Box::into_raw(Box::new(mac_table { table: Mutex::new(MacTable::new(aging)), timer: 0, event: Mutex::new((None, null_mut())) }));
+    (*t).timer = timer_create_entry(MAC_TABLE_AGING_INTERVAL, FALSE, 10, Some(mac_table_timer), t.cast::<_>());
+    if (*t).timer == 0 {
+        libc::fprintf(c_stderr(), c"mac_table_create: unable to create aging timer\n".as_ptr());
+    }
+    t
+}
+
+// Free a MAC address table
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_free(t: *mut mac_table_t) {
+    if !t.is_null() {
+        if (*t).timer != 0 {
+            timer_remove((*t).timer);
+        }
+        drop(Box::from_raw(t));
+    }
+}
+
+// Set the event callback (called without the table lock held)
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_set_event_cbk(t: *mut mac_table_t, cbk: mac_table_event_cbk_t, opt: *mut c_void) {
+    *(*t).event.lock().unwrap_or_else(|e| e.into_inner()) = (cbk, opt);
+}
+
+// Set the aging time in seconds, 0 to disable aging
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_set_aging(t: *mut mac_table_t, aging: u_int) {
+    (*t).lock().set_aging(aging);
+}
+
+// Get the aging time in seconds
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_get_aging(t: *mut mac_table_t) -> u_int {
+    (*t).lock().aging()
+}
+
+// Learn the source address of a frame received on a port, returns MAC_LEARN_FORWARD or MAC_LEARN_DROP
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_learn(t: *mut mac_table_t, mac: *const n_eth_addr_t, vlan_id: m_uint16_t, port: *mut c_void) -> c_int {
+    let mac = EthAddr::from(*mac);
+    let res = (*t).lock().learn(&mac, vlan_id, port, m_gettime());
+    let entry = mac_table_entry_t { mac_addr: mac.into(), vlan_id, port, r#type: MAC_ENTRY_DYNAMIC, age: 0 };
+
+    match res {
+        MacLearn::Moved(old_port) => (*t).event(MAC_EVENT_MOVE, &entry, old_port),
+        MacLearn::Restrict => (*t).event(MAC_EVENT_VIOLATION, &entry, null_mut()),
+        MacLearn::Shutdown => {
+            (*t).event(MAC_EVENT_VIOLATION, &entry, null_mut());
+            (*t).event(MAC_EVENT_SHUTDOWN, &entry, null_mut());
+        }
+        _ => {}
+    }
+    res.code()
+}
+
+// Port of an address, NULL if unknown
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_lookup(t: *mut mac_table_t, mac: *const n_eth_addr_t, vlan_id: m_uint16_t) -> *mut c_void {
+    (*t).lock().lookup(&EthAddr::from(*mac), vlan_id).unwrap_or(null_mut())
+}
+
+// Add a static address
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_add_static(t: *mut mac_table_t, mac: *const n_eth_addr_t, vlan_id: m_uint16_t, port: *mut c_void) {
+    (*t).lock().add_static(&EthAddr::from(*mac), vlan_id, port, m_gettime());
+}
+
+// Add a sticky address
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_add_sticky(t: *mut mac_table_t, mac: *const n_eth_addr_t, vlan_id: m_uint16_t, port: *mut c_void) {
+    (*t).lock().add_sticky(&EthAddr::from(*mac), vlan_id, port, m_gettime());
+}
+
+// Remove an address, returns -1 if not found
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_remove(t: *mut mac_table_t, mac: *const n_eth_addr_t, vlan_id: m_uint16_t) -> c_int {
+    if (*t).lock().remove(&EthAddr::from(*mac), vlan_id) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Remove the dynamic addresses (and the static/sticky ones if all is set)
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_flush(t: *mut mac_table_t, all: c_int) {
+    (*t).lock().flush(all != 0);
+}
+
+// Remove the dynamic addresses of a port (and the static/sticky ones if all is set)
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_flush_port(t: *mut mac_table_t, port: *mut c_void, all: c_int) {
+    (*t).lock().flush_port(port, all != 0);
+}
+
+// Forget a port (addresses and port security)
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_remove_port(t: *mut mac_table_t, port: *mut c_void) {
+    (*t).lock().remove_port(port);
+}
+
+// Configure port security (max_macs 0 for no limit), returns -1 if the action is invalid
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_set_port_security(t: *mut mac_table_t, port: *mut c_void, max_macs: u_int, action: c_int, sticky: c_int) -> c_int {
+    if (*t).lock().set_port_security(port, max_macs, action, sticky != 0) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Get the port security status of a port
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_get_port_security(t: *mut mac_table_t, port: *mut c_void, status: *mut mac_port_security_t) {
+    *status = (*t).lock().port_security(port);
+}
+
+// Returns TRUE if a port is err-disabled
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_port_is_shutdown(t: *mut mac_table_t, port: *mut c_void) -> c_int {
+    (*t).lock().port_is_shutdown(port) as c_int
+}
+
+// Recover an err-disabled port, returns -1 if it was not err-disabled
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_port_recover(t: *mut mac_table_t, port: *mut c_void) -> c_int {
+    if (*t).lock().port_recover(port) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Number of address moves
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_get_moves(t: *mut mac_table_t) -> m_uint64_t {
+    (*t).lock().moves()
+}
+
+// Number of entries
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_count(t: *mut mac_table_t) -> size_t {
+    (*t).lock().len()
+}
+
+// Call a function for each entry (on a copy, the table is not locked during the calls)
+#[no_mangle]
+pub unsafe extern "C" fn mac_table_foreach(t: *mut mac_table_t, cbk: mac_table_foreach_cbk_t, opt: *mut c_void) -> c_int {
+    let mut entries = (*t).lock().entries(m_gettime());
+    entries.sort_by_key(|e| (e.vlan_id, e.mac_addr.eth_addr_byte));
+    if let Some(cbk) = cbk {
+        for entry in &entries {
+            cbk(entry, opt);
+        }
+    }
+    entries.len() as c_int
+}
+
+// Name of an entry type
+#[no_mangle]
+pub extern "C" fn mac_table_entry_type_str(r#type: c_int) -> *const c_char {
+    match r#type {
+        MAC_ENTRY_DYNAMIC => c"dynamic".as_ptr(),
+        MAC_ENTRY_STATIC => c"static".as_ptr(),
+        MAC_ENTRY_STICKY => c"sticky".as_ptr(),
+        _ => c"unknown".as_ptr(),
+    }
+}
+
+// Name of a violation action
+#[no_mangle]
+pub extern "C" fn mac_violation_str(action: c_int) -> *const c_char {
+    match action {
+        MAC_VIOLATION_PROTECT => c"protect".as_ptr(),
+        MAC_VIOLATION_RESTRICT => c"restrict".as_ptr(),
+        MAC_VIOLATION_SHUTDOWN => c"shutdown".as_ptr(),
+        _ => c"unknown".as_ptr(),
+    }
+}
+
+// Parse a violation action name, returns -1 if unknown
+#[no_mangle]
+pub unsafe extern "C" fn mac_violation_parse(s: *const c_char) -> c_int {
+    match CStr::from_ptr(s).to_bytes() {
+        b"protect" => MAC_VIOLATION_PROTECT,
+        b"restrict" => MAC_VIOLATION_RESTRICT,
+        b"shutdown" => MAC_VIOLATION_SHUTDOWN,
+        _ => -1,
+    }
+}
@@ -11,6 +11,7 @@ use std::ffi::c_int;
 use std::ffi::c_void;
 use std::ptr::addr_of_mut;
 use std::ptr::null_mut;
+use std::sync::Mutex;
 
 // Memory Pool "Fixed" Flag
 pub const MEMPOOL_FIXED: c_int = 1;
@@ -60,6 +61,25 @@ macro_rules! MEMPOOL_UNLOCK {
 }
 pub(crate) use MEMPOOL_UNLOCK;
 
+// Live memory pools, for the statistics (addresses of the mempool_t)
+static mempool_list: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+/// Name, number of blocks and allocated bytes of each live memory pool.
+pub fn mp_pool_stats() -> Vec<(String, c_int, size_t)> {
+    let list = mempool_list.lock().unwrap_or_else(|e| e.into_inner());
+    let mut stats = Vec::with_capacity(list.len());
+    for &addr in list.iter() {
+        let pool = addr as *mut mempool_t;
+        unsafe {
+            MEMPOOL_LOCK!(pool);
+            let name = if (*pool).name.is_null() { String::new() } else { std::ffi::CStr::from_ptr((*pool).name).to_string_lossy().into_owned() };
+            stats.push((name, (*pool).nr_blocks, (*pool).total_size));
+            MEMPOOL_UNLOCK!(pool);
+        }
+    }
+    stats
+}
+
 // Callback function for use with mp_foreach
 pub type mp_foreach_cbk = Option<unsafe extern "C" fn(block: *mut memblock_t, user_arg: *mut c_void)>;
 
@@ -263,6 +283,7 @@ pub unsafe extern "C" fn mp_free_all_blocks(pool: *mut mempool_t) {
 // Free specified memory pool
 #[no_mangle]
 pub unsafe extern "C" fn mp_free_pool(pool: *mut mempool_t) {
+    mempool_list.lock().unwrap_or_else(|e| e.into_inner()).retain(|&addr| addr != pool as usize);
     mp_free_all_blocks(pool);
 
     if 0 == !((*pool).flags & MEMPOOL_FIXED) {
@@ -282,6 +303,7 @@ pub unsafe extern "C" fn mp_create_fixed_pool(mp: *mut mempool_t, name: *mut c_c
     (*mp).name = name;
     (*mp).block_list = null_mut();
     (*mp).flags = MEMPOOL_FIXED;
+    mempool_list.lock().unwrap_or_else(|e| e.into_inner()).push(mp as usize);
     mp
 }
 
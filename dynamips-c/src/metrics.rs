@@ -0,0 +1,396 @@
+//! Metrics exporter (Prometheus text format over HTTP).
+//!
+//! The exporter is opt-in: a thread serves `GET /metrics` on the sockets
+//! returned by `ip_listen`, in the text exposition format version 0.0.4.
+//! At each scrape the collect callback of the owner adds the samples of its
+//! objects (NIOs, switches, VMs) with `metrics_add`, then the exporter adds
+//! the load of the timer queues and the usage of the memory pools.
+//!
+//! Samples are grouped by family (one `# HELP` and `# TYPE` per name, in the
+//! order of the first sample), samples of a family with the same labels are
+//! summed. Invalid names and type mismatches are rejected.
+//!
+//! Requests are served one at a time, with "Connection: close".
+
+use crate::_extra::*;
+use crate::dynamips_common::*;
+use crate::mempool::*;
+use crate::net::*;
+use crate::timer::*;
+use std::collections::HashMap;
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::c_void;
+use std::ffi::CStr;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::os::fd::FromRawFd;
+use std::ptr::addr_of_mut;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// Metric types
+pub const METRICS_COUNTER: c_int = 0;
+pub const METRICS_GAUGE: c_int = 1;
+
+// Maximum number of listening sockets
+pub const METRICS_MAX_FD: usize = 8;
+
+// Maximum size of a request head
+pub const METRICS_MAX_REQUEST: usize = 8192;
+
+// Socket timeout for the clients (in msecs)
+pub const METRICS_CLIENT_TIMEOUT: u64 = 2000;
+
+/// Samples of a metric family.
+#[derive(Debug)]
+struct MetricFamily {
+    name: String,
+    help: String,
+    kind: c_int,
+    samples: Vec<(String, f64)>,
+}
+
+/// Set of metric samples collected for a scrape.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    families: Vec<MetricFamily>,
+    index: HashMap<String, usize>,
+}
+
+fn is_metric_name(name: &str, colon: bool) -> bool {
+    let ok = |c: char| c.is_ascii_alphanumeric() || c == '_' || (colon && c == ':');
+    match name.chars().next() {
+        Some(c) if c.is_ascii_digit() => false,
+        Some(_) => name.chars().all(ok),
+        None => false,
+    }
+}
+
+fn escape(s: &str, quote: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '"' if quote => out.push_str("\\\""),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a sample. Returns false if a name is invalid, a label name is
+    /// reserved (`__` prefix) or the family already exists with another type.
+    pub fn add(&mut self, kind: c_int, name: &str, help: &str, labels: &[(&str, &str)], value: f64) -> bool {
+        if !is_metric_name(name, true) || (kind != METRICS_COUNTER && kind != METRICS_GAUGE) {
+            return false;
+        }
+
+        let mut rendered = String::new();
+        for (i, (label, val)) in labels.iter().enumerate() {
+            if !is_metric_name(label, false) || label.starts_with("__") {
+                return false;
+            }
+            rendered.push(if i == 0 { '{' } else { ',' });
+            rendered.push_str(&format!("{}=\"{}\"", label, escape(val, true)));
+        }
+        if !rendered.is_empty() {
+            rendered.push('}');
+        }
+
+        let i = match self.index.get(name) {
+            Some(&i) => i,
+            None => {
+                self.families.push(MetricFamily { name: name.to_string(), help: help.to_string(), kind, samples: Vec::new() });
+                self.index.insert(name.to_string(), self.families.len() - 1);
+                self.families.len() - 1
+            }
+        };
+
+        let family = &mut self.families[i];
+        if family.kind != kind {
+            return false;
+        }
+        match family.samples.iter_mut().find(|(l, _)| *l == rendered) {
+            Some((_, v)) => *v += value,
+            None => family.samples.push((rendered, value)),
+        }
+        true
+    }
+
+    /// Number of samples.
+    pub fn len(&self) -> usize {
+        self.families.iter().map(|f| f.samples.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add the load of the timer queues and the usage of the memory pools.
+    pub fn add_builtin(&mut self) {
+        for (i, (count, level)) in timer_queue_stats().into_iter().enumerate() {
+            let queue = i.to_string();
+            self.add(METRICS_GAUGE, "dynamips_timer_queue_timers", "Number of timers in a timer queue.", &[("queue", &queue)], count as f64);
+            self.add(METRICS_GAUGE, "dynamips_timer_queue_level", "Sum of the criticity levels of the timers of a timer queue.", &[("queue", &queue)], level as f64);
+        }
+        for (name, blocks, bytes) in mp_pool_stats() {
+            self.add(METRICS_GAUGE, "dynamips_mempool_blocks", "Number of blocks allocated in memory pools.", &[("pool", &name)], blocks as f64);
+            self.add(METRICS_GAUGE, "dynamips_mempool_bytes", "Number of bytes allocated in memory pools.", &[("pool", &name)], bytes as f64);
+        }
+    }
+
+    /// Render in the text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for family in &self.families {
+            let kind = if family.kind == METRICS_COUNTER { "counter" } else { "gauge" };
+            out.push_str(&format!("# HELP {} {}\n", family.name, escape(&family.help, false)));
+            out.push_str(&format!("# TYPE {} {}\n", family.name, kind));
+            for (labels, value) in &family.samples {
+                out.push_str(&format!("{}{} {}\n", family.name, labels, format_value(*value)));
+            }
+        }
+        out
+    }
+}
+
+/// Build the HTTP response to a request head, `body` is called for the scrapes.
+pub fn metrics_http_response(head: &str, body: impl FnOnce() -> String) -> String {
+    let response = |status: &str, extra: &str, body: &str, head_only: bool| {
+        let mut r = format!("HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n", status, extra, body.len());
+        if !head_only {
+            r.push_str(body);
+        }
+        r
+    };
+
+    let line = head.lines().next().unwrap_or("");
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() != 3 || !parts[2].starts_with("HTTP/1.") {
+        return response("400 Bad Request", "", "Bad Request\n", false);
+    }
+
+    let (method, path) = (parts[0], parts[1].split('?').next().unwrap_or(""));
+    if path != "/metrics" {
+        return response("404 Not Found", "", "Not Found\n", false);
+    }
+    if method != "GET" && method != "HEAD" {
+        return response("405 Method Not Allowed", "Allow: GET, HEAD\r\n", "Method Not Allowed\n", false);
+    }
+    response("200 OK", "Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n", &body(), method == "HEAD")
+}
+
+/// HTTP server thread of the exporter.
+pub struct MetricsServer {
+    fds: Vec<c_int>,
+    port: c_int,
+    running: Arc<AtomicBool>,
+    scrapes: Arc<AtomicU64>,
+    thread: Option<JoinHandle<()>>,
+}
+
+// Read a request head and send the response
+fn metrics_serve_client(fd: c_int, collect: &(dyn Fn(&mut Metrics) + Send + Sync)) {
+    let mut stream = unsafe { TcpStream::from_raw_fd(fd) };
+    let timeout = Some(Duration::from_millis(METRICS_CLIENT_TIMEOUT));
+    let _ = stream.set_read_timeout(timeout);
+    let _ = stream.set_write_timeout(timeout);
+
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while head.len() < METRICS_MAX_REQUEST && !head.windows(4).any(|w| w == b"\r\n\r\n") && !head.windows(2).any(|w| w == b"\n\n") {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => head.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    let response = metrics_http_response(&String::from_utf8_lossy(&head), || {
+        let mut m = Metrics::new();
+        collect(&mut m);
+        m.add_builtin();
+        m.render()
+    });
+    let _ = stream.write_all(response.as_bytes());
+}
+
+impl MetricsServer {
+    /// Listen on the specified address (all addresses if None) and port,
+    /// then serve the scrapes from a thread.
+    pub fn start(ip_addr: Option<&CStr>, port: c_int, collect: impl Fn(&mut Metrics) + Send + Sync + 'static) -> Result<Self, String> {
+        let mut fds = vec![-1; METRICS_MAX_FD];
+        let ip_addr = ip_addr.map_or(std::ptr::null_mut(), |s| s.as_ptr().cast_mut());
+        let count = unsafe { ip_listen(ip_addr, port, libc::SOCK_STREAM, METRICS_MAX_FD as c_int, fds.as_mut_ptr()) };
+        if count <= 0 {
+            return Err(unsafe { CStr::from_ptr(net_last_error_str()) }.to_string_lossy().into_owned());
+        }
+        fds.truncate(count as usize);
+
+        let port = unsafe {
+            let mut st: libc::sockaddr_storage = std::mem::zeroed();
+            let mut st_len = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            libc::getsockname(fds[0], addr_of_mut!(st).cast::<_>(), &mut st_len);
+            ip_socket_get_port(addr_of_mut!(st).cast::<_>())
+        };
+
+        let running = Arc::new(AtomicBool::new(true));
+        let scrapes = Arc::new(AtomicU64::new(0));
+        let (thread_fds, thread_running, thread_scrapes) = (fds.clone(), running.clone(), scrapes.clone());
+        let thread = std::thread::Builder::new().name("metrics".to_string()).spawn(move || {
+            let mut pfds: Vec<libc::pollfd> = thread_fds.iter().map(|&fd| libc::pollfd { fd, events: libc::POLLIN, revents: 0 }).collect();
+            while thread_running.load(Ordering::Relaxed) {
+                let res = unsafe { libc::poll(pfds.as_mut_ptr(), pfds.len() as libc::nfds_t, 500) };
+                if res <= 0 {
+                    continue;
+                }
+                for pfd in pfds.iter_mut().filter(|p| p.revents & libc::POLLIN != 0) {
+                    let clnt = unsafe { libc::accept(pfd.fd, std::ptr::null_mut(), std::ptr::null_mut()) };
+                    if clnt >= 0 {
+                        thread_scrapes.fetch_add(1, Ordering::Relaxed);
+                        metrics_serve_client(clnt, &collect);
+                    }
+                }
+            }
+        });
+
+        match thread {
+            Ok(thread) => Ok(Self { fds, port, running, scrapes, thread: Some(thread) }),
+            Err(err) => {
+                for &fd in &fds {
+                    unsafe { libc::close(fd) };
+                }
+                Err(err.to_string())
+            }
+        }
+    }
+
+    /// Port of the first listening socket (useful with port 0).
+    pub fn port(&self) -> c_int {
+        self.port
+    }
+
+    /// Number of requests served.
+    pub fn scrapes(&self) -> u64 {
+        self.scrapes.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        for &fd in &self.fds {
+            unsafe {
+                libc::shutdown(fd, libc::SHUT_RDWR);
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+pub type metrics_t = Metrics;
+pub type metrics_server_t = MetricsServer;
+
+// Collect callback, called from the exporter thread for each scrape
+pub type metrics_collect_cbk_t = Option<unsafe extern "C" fn(m: *mut metrics_t, opt: *mut c_void)>;
+
+// The collect context is owned by the caller
+struct MetricsCollector {
+    collect: metrics_collect_cbk_t,
+    opt: *mut c_void,
+}
+unsafe impl Send for MetricsCollector {}
+unsafe impl Sync for MetricsCollector {}
+
+impl MetricsCollector {
+    fn collect(&self, m: &mut Metrics) {
+        if let Some(collect) = self.collect {
+            unsafe { collect(m, self.opt) };
+        }
+    }
+}
+
+// Add a sample. "labels" is a NULL terminated list of name/value pairs (or NULL).
+// Returns 0 on success, -1 if a name is invalid or the family has another type.
+#[no_mangle]
+pub unsafe extern "C" fn metrics_add(m: *mut metrics_t, r#type: c_int, name: *mut c_char, help: *mut c_char, labels: *mut *mut c_char, value: f64) -> c_int {
+    let mut pairs = Vec::new();
+    let mut p = labels;
+    while !p.is_null() && !(*p).is_null() {
+        if (*p.add(1)).is_null() {
+            return -1;
+        }
+        pairs.push((CStr::from_ptr(*p).to_string_lossy(), CStr::from_ptr(*p.add(1)).to_string_lossy()));
+        p = p.add(2);
+    }
+    let pairs: Vec<(&str, &str)> = pairs.iter().map(|(l, v)| (l.as_ref(), v.as_ref())).collect();
+
+    let name = CStr::from_ptr(name).to_string_lossy();
+    let help = CStr::from_ptr(help).to_string_lossy();
+    if (*m).add(r#type, &name, &help, &pairs, value) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Start the exporter on the specified address (NULL for all) and port, returns NULL on error
+#[no_mangle]
+pub unsafe extern "C" fn metrics_server_start(ip_addr: *mut c_char, port: c_int, collect: metrics_collect_cbk_t, opt: *mut c_void) -> *mut metrics_server_t {
+    let collector = MetricsCollector { collect, opt };
+    let ip_addr = if ip_addr.is_null() { None } else { Some(CStr::from_ptr(ip_addr)) };
+    let res = MetricsServer::start(ip_addr, port, move |m| collector.collect(m));
+
+    match res {
+        Ok(server) => Box::into_raw(Box::new(server)),
+        Err(msg) => {
+            let msg = std::ffi::CString::new(msg).unwrap_or_default();
+            libc::fprintf(c_stderr(), c"metrics: %s\n".as_ptr(), msg.as_ptr());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+// Stop the exporter (waits for the scrape in progress)
+#[no_mangle]
+pub unsafe extern "C" fn metrics_server_stop(s: *mut metrics_server_t) {
+    if !s.is_null() {
+        drop(Box::from_raw(s));
+    }
+}
+
+// Port of the first listening socket
+#[no_mangle]
+pub unsafe extern "C" fn metrics_server_get_port(s: *mut metrics_server_t) -> c_int {
+    (*s).port()
+}
+
+// Number of requests served
+#[no_mangle]
+pub unsafe extern "C" fn metrics_server_get_scrapes(s: *mut metrics_server_t) -> m_uint64_t {
+    (*s).scrapes()
+}
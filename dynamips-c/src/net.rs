@@ -8,6 +8,7 @@
 use crate::_extra::*;
 use crate::crc::*;
 use crate::dynamips_common::*;
+use crate::endian::*;
 use crate::utils::*;
 use libc::size_t;
 use std::ffi::c_char;
@@ -149,6 +150,10 @@ pub const N_ETH_PROTO_MPLS: m_uint16_t = 0x8847;
 pub const N_ETH_PROTO_MPLS_MC: m_uint16_t = 0x8848;
 pub const N_ETH_PROTO_LOOP: m_uint16_t = 0x9000;
 
+// LLC SAP and control field of the 802.1D BPDUs
+pub const N_LLC_SAP_STP: m_uint8_t = 0x42;
+pub const N_LLC_CTRL_UI: m_uint8_t = 0x03;
+
 // size needed for a string buffer
 pub const N_ETH_SLEN: usize = N_ETH_ALEN * 3;
 
@@ -169,7 +174,7 @@ pub struct n_eth_addr {
 pub struct n_eth_hdr {
     pub daddr: n_eth_addr_t, // destination eth addr
     pub saddr: n_eth_addr_t, // source ether addr
-    pub r#type: m_uint16_t,  // packet type ID field
+    pub r#type: Be16,        // packet type ID field
 }
 
 // 802.1Q Ethernet Header
@@ -178,8 +183,8 @@ pub struct n_eth_hdr {
 pub struct n_eth_dot1q_hdr {
     pub daddr: n_eth_addr_t, // destination eth addr
     pub saddr: n_eth_addr_t, // source ether addr
-    pub r#type: m_uint16_t,  // packet type ID field (0x8100)
-    pub vlan_id: m_uint16_t, // VLAN id + CoS
+    pub r#type: Be16,        // packet type ID field (0x8100)
+    pub vlan_id: Be16,       // VLAN id + CoS
 }
 
 // LLC header
@@ -196,18 +201,18 @@ pub struct n_eth_llc_hdr {
 #[derive(Debug, Copy, Clone)]
 pub struct n_eth_snap_hdr {
     pub oui: [m_uint8_t; 3],
-    pub r#type: m_uint16_t,
+    pub r#type: Be16,
 }
 
 // Cisco ISL header
 #[repr(C, packed)]
 #[derive(Debug, Copy, Clone)]
 pub struct n_eth_isl_hdr {
-    pub hsa1: m_uint16_t,  // High bits of source MAC address
-    pub hsa2: m_uint8_t,   // (in theory: 0x00-00-0c)
-    pub vlan: m_uint16_t,  // VLAN + BPDU
-    pub index: m_uint16_t, // Index port of source
-    pub res: m_uint16_t,   // Reserved for TokenRing and FDDI
+    pub hsa1: Be16,      // High bits of source MAC address
+    pub hsa2: m_uint8_t, // (in theory: 0x00-00-0c)
+    pub vlan: Be16,      // VLAN + BPDU
+    pub index: Be16,     // Index port of source
+    pub res: Be16,       // Reserved for TokenRing and FDDI
 }
 
 pub const N_ISL_HDR_SIZE: usize = 3 + 9;
@@ -217,32 +222,32 @@ const _: () = assert!(N_ISL_HDR_SIZE == size_of::<n_eth_llc_hdr_t>() + size_of::
 #[repr(C, packed)]
 #[derive(Debug, Copy, Clone)]
 pub struct n_scp_hdr {
-    pub sa: m_uint8_t,      // Source Address
-    pub da: m_uint8_t,      // Destination Address
-    pub len: m_uint16_t,    // Data Length
-    pub dsap: m_uint8_t,    // Destination Service Access Point
-    pub ssap: m_uint8_t,    // Source Service Access Point
-    pub opcode: m_uint16_t, // Opcode
-    pub seqno: m_uint16_t,  // Sequence Number
-    pub flags: m_uint8_t,   // Flags: command/response
-    pub unk1: m_uint8_t,    // Unknown
-    pub unk2: m_uint16_t,   // Unknown
-    pub unk3: m_uint16_t,   // Unknown
+    pub sa: m_uint8_t,    // Source Address
+    pub da: m_uint8_t,    // Destination Address
+    pub len: Be16,        // Data Length
+    pub dsap: m_uint8_t,  // Destination Service Access Point
+    pub ssap: m_uint8_t,  // Source Service Access Point
+    pub opcode: Be16,     // Opcode
+    pub seqno: Be16,      // Sequence Number
+    pub flags: m_uint8_t, // Flags: command/response
+    pub unk1: m_uint8_t,  // Unknown
+    pub unk2: Be16,       // Unknown
+    pub unk3: Be16,       // Unknown
 }
 
 // ----- ARP Header for the IPv4 protocol over Ethernet ------------------
 #[repr(C, packed)]
 #[derive(Debug, Copy, Clone)]
 pub struct n_arp_hdr {
-    pub hw_type: m_uint16_t,     // Hardware type
-    pub proto_type: m_uint16_t,  // L3 protocol
+    pub hw_type: Be16,           // Hardware type
+    pub proto_type: Be16,        // L3 protocol
     pub hw_len: m_uint8_t,       // Length of hardware address
     pub proto_len: m_uint8_t,    // Length of L3 address
-    pub opcode: m_uint16_t,      // ARP Opcode
+    pub opcode: Be16,            // ARP Opcode
     pub eth_saddr: n_eth_addr_t, // Source hardware address
-    pub ip_saddr: m_uint32_t,    // Source IP address
+    pub ip_saddr: Be32,          // Source IP address
     pub eth_daddr: n_eth_addr_t, // Dest. hardware address
-    pub ip_daddr: m_uint32_t,    // Dest. IP address
+    pub ip_daddr: Be32,          // Dest. IP address
 }
 
 // ----- IP Header -------------------------------------------------------
@@ -251,39 +256,39 @@ pub struct n_arp_hdr {
 pub struct n_ip_hdr {
     pub ihl: m_uint8_t,
     pub tos: m_uint8_t,
-    pub tot_len: m_uint16_t,
-    pub id: m_uint16_t,
-    pub frag_off: m_uint16_t,
+    pub tot_len: Be16,
+    pub id: Be16,
+    pub frag_off: Be16,
     pub ttl: m_uint8_t,
     pub proto: m_uint8_t,
-    pub cksum: m_uint16_t,
-    pub saddr: m_uint32_t,
-    pub daddr: m_uint32_t,
+    pub cksum: Be16,
+    pub saddr: Be32,
+    pub daddr: Be32,
 }
 
 // ----- UDP Header ------------------------------------------------------
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct n_udp_hdr {
-    pub sport: m_uint16_t,
-    pub dport: m_uint16_t,
-    pub len: m_uint16_t,
-    pub cksum: m_uint16_t,
+    pub sport: Be16,
+    pub dport: Be16,
+    pub len: Be16,
+    pub cksum: Be16,
 }
 
 // ----- TCP Header ------------------------------------------------------
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct n_tcp_hdr {
-    pub sport: m_uint16_t,
-    pub dport: m_uint16_t,
-    pub seq: m_uint32_t,
-    pub ack_seq: m_uint32_t,
+    pub sport: Be16,
+    pub dport: Be16,
+    pub seq: Be32,
+    pub ack_seq: Be32,
     pub offset: m_uint8_t,
     pub flags: m_uint8_t,
-    pub window: m_uint16_t,
-    pub cksum: m_uint16_t,
-    pub urg_ptr: m_uint16_t,
+    pub window: Be16,
+    pub cksum: Be16,
+    pub urg_ptr: Be16,
 }
 
 // ----- Packet Context --------------------------------------------------
@@ -357,6 +362,14 @@ pub unsafe extern "C" fn eth_addr_is_cisco_isl(addr: *mut n_eth_addr_t) -> c_int
     (0 == libc::memcmp(addr.cast::<_>(), isl_addr.as_ptr().cast::<_>(), 5)) as c_int
 }
 
+// Check for the bridge group address (802.1D BPDUs)
+#[inline]
+#[no_mangle]
+pub unsafe extern "C" fn eth_addr_is_stp(addr: *mut n_eth_addr_t) -> c_int {
+    static stp_addr: &[u8; 6] = b"\x01\x80\xc2\x00\x00\x00";
+    (0 == libc::memcmp(addr.cast::<_>(), stp_addr.as_ptr().cast::<_>(), 6)) as c_int
+}
+
 // Check for a SNAP header
 #[inline]
 #[no_mangle]
@@ -364,6 +377,13 @@ pub unsafe extern "C" fn eth_llc_check_snap(llc_hdr: *mut n_eth_llc_hdr_t) -> c_
     (((*llc_hdr).dsap == 0xAA) && ((*llc_hdr).ssap == 0xAA) && ((*llc_hdr).ctrl == 0x03)) as c_int
 }
 
+// Check for a spanning tree LLC header
+#[inline]
+#[no_mangle]
+pub unsafe extern "C" fn eth_llc_check_stp(llc_hdr: *mut n_eth_llc_hdr_t) -> c_int {
+    (((*llc_hdr).dsap == N_LLC_SAP_STP) && ((*llc_hdr).ssap == N_LLC_SAP_STP) && ((*llc_hdr).ctrl == N_LLC_CTRL_UI)) as c_int
+}
+
 // Number of bits in a contiguous netmask
 #[inline]
 #[no_mangle]
@@ -458,86 +478,29 @@ pub unsafe extern "C" fn n_ipv6_aton(ipv6_addr: *mut n_ipv6_addr_t, ip_str: *mut
 // Parse an IPv4 CIDR prefix
 #[no_mangle]
 pub unsafe extern "C" fn ip_parse_cidr(token: *mut c_char, net_addr: *mut n_ip_addr_t, net_mask: *mut n_ip_addr_t) -> c_int {
-    let mut err: *mut c_char = null_mut();
-
-    // Find separator
-    let sl: *mut c_char = libc::strchr(token, b'/' as c_int);
-    if sl.is_null() {
-        return -1;
-    }
-
-    // Get mask
-    let mask: u_long = libc::strtoul(sl.add(1), addr_of_mut!(err), 0);
-    if *err != 0 {
-        return -1;
-    }
-
-    // Ensure that mask has a correct value
-    if mask > N_IP_ADDR_BITS as u_long {
-        return -1;
-    }
-
-    let tmp: *mut c_char = libc::strdup(token);
-    if tmp.is_null() {
-        return -1;
-    }
-
-    *tmp.add(sl.offset_from(token).try_into().expect("isize->usize")) = b'\0' as c_char;
-
-    // Parse IP Address
-    if n_ip_aton(net_addr, tmp) == -1 {
-        libc::free(tmp.cast::<_>());
-        return -1;
+    match std::ffi::CStr::from_ptr(token).to_str().map(str::parse::<Ipv4Net>) {
+        Ok(Ok(net)) => {
+            *net_addr = net.addr().into();
+            *net_mask = net.mask();
+            0
+        }
+        _ => -1,
     }
-
-    // Set netmask
-    *net_mask = ip_masks[mask as usize];
-
-    libc::free(tmp.cast::<_>());
-    0
 }
 
 // Parse an IPv6 CIDR prefix
 #[cfg(feature = "ENABLE_IPV6")]
 #[no_mangle]
 pub unsafe extern "C" fn ipv6_parse_cidr(token: *mut c_char, net_addr: *mut n_ipv6_addr_t, net_mask: *mut u_int) -> c_int {
-    let mut err: *mut c_char = null_mut();
-
-    // Find separator
-    let sl: *mut c_char = libc::strchr(token, b'/' as c_int);
-    if sl.is_null() {
-        return -1;
-    }
-
-    // Get mask
-    let mask: u_long = libc::strtoul(sl.add(1), addr_of_mut!(err), 0);
-    if *err != 0 {
-        return -1;
-    }
-
-    // Ensure that mask has a correct value
-    if mask > N_IPV6_ADDR_BITS as u_long {
-        return -1;
-    }
-
-    let tmp: *mut c_char = libc::strdup(token);
-    if tmp.is_null() {
-        return -1;
-    }
-
-    *tmp.add(sl.offset_from(token).try_into().expect("isize->usize")) = b'\0' as c_char;
-
-    // Parse IP Address
-    if n_ipv6_aton(net_addr, tmp) <= 0 {
-        libc::free(tmp.cast::<_>());
-        return -1;
+    match std::ffi::CStr::from_ptr(token).to_str().map(str::parse::<Ipv6Net>) {
+        Ok(Ok(net)) => {
+            let net = n_ipv6_network_t::from(net);
+            *net_addr = net.net_addr;
+            *net_mask = net.net_mask;
+            0
+        }
+        _ => -1,
     }
-
-    // Set netmask
-    *net_mask = mask as u_int;
-
-    libc::free(tmp.cast::<_>());
-    0
 }
 
 // Parse a processor board id and return the eeprom settings in a buffer
@@ -549,421 +512,355 @@ pub unsafe extern "C" fn parse_board_id(buf: *mut m_uint8_t, id: *const c_char,
     //   encode 11 maps into 11 bytes
 
     libc::memset(buf.cast::<_>(), 0, 11);
-    if encode == 4 {
-        let mut v: c_int = 0;
-        let res: c_int = libc::sscanf(id, c"%d".as_ptr(), addr_of_mut!(v));
-        if res != 1 {
-            return -1;
-        }
-        *buf.add(3) = (v & 0xFF) as m_uint8_t;
-        v >>= 8;
-        *buf.add(2) = (v & 0xFF) as m_uint8_t;
-        v >>= 8;
-        *buf.add(1) = (v & 0xFF) as m_uint8_t;
-        v >>= 8;
-        *buf.add(0) = (v & 0xFF) as m_uint8_t;
-        #[allow(unused_assignments)]
-        {
-            v >>= 8;
-        }
-        if false {
-            libc::printf(c"%x %x %x %x \n".as_ptr(), *buf.add(0) as c_uint, *buf.add(1) as c_uint, *buf.add(2) as c_uint, *buf.add(3) as c_uint);
-        }
-        return 0;
-    } else if encode == 9 {
-        // FIXME each short overlaps other data and produces an endianess-dependent result
-        let res: c_int = libc::sscanf(
-            id,
-            c"%c%c%c%2hx%2hx%c%c%c%c".as_ptr(),
-            buf.add(0),
-            buf.add(1),
-            buf.add(2),
-            buf.add(3).cast::<c_ushort>(),
-            buf.add(4).cast::<c_ushort>(),
-            buf.add(5),
-            buf.add(6),
-            buf.add(7),
-            buf.add(8),
-        );
-        if res != 9 {
-            return -1;
-        }
-        if false {
-            libc::printf(
-                c"%x %x %x %x %x %x %x %x .. %x\n".as_ptr(),
-                *buf.add(0) as c_uint,
-                *buf.add(1) as c_uint,
-                *buf.add(2) as c_uint,
-                *buf.add(3) as c_uint,
-                *buf.add(4) as c_uint,
-                *buf.add(5) as c_uint,
-                *buf.add(6) as c_uint,
-                *buf.add(7) as c_uint,
-                *buf.add(8) as c_uint,
-            );
-        }
-        return 0;
-    } else if encode == 11 {
-        let res: c_int =
-            libc::sscanf(id, c"%c%c%c%c%c%c%c%c%c%c%c".as_ptr(), buf.add(0), buf.add(1), buf.add(2), buf.add(3), buf.add(4), buf.add(5), buf.add(6), buf.add(7), buf.add(8), buf.add(9), buf.add(10));
-        if res != 11 {
-            return -1;
-        }
-        if false {
-            libc::printf(
-                c"%x %x %x %x %x %x %x %x %x %x .. %x\n".as_ptr(),
-                *buf.add(0) as c_uint,
-                *buf.add(1) as c_uint,
-                *buf.add(2) as c_uint,
-                *buf.add(3) as c_uint,
-                *buf.add(4) as c_uint,
-                *buf.add(5) as c_uint,
-                *buf.add(6) as c_uint,
-                *buf.add(7) as c_uint,
-                *buf.add(8) as c_uint,
-                *buf.add(9) as c_uint,
-                *buf.add(10) as c_uint,
-            );
+    let Ok(id) = std::ffi::CStr::from_ptr(id).to_str() else {
+        return -1;
+    };
+    match BoardId::parse(id, encode as usize) {
+        Ok(board_id) => {
+            let enc = board_id.encode();
+            libc::memcpy(buf.cast::<_>(), enc.as_ptr().cast::<_>(), board_id.encoded_len());
+            0
         }
-        return 0;
+        Err(_) => -1,
     }
-    -1
 }
 
-// Parse a MAC address
+// Parse a MAC address (00:01:02:03:04:05, 0001.0203.0405 or 00-01-02-03-04-05)
 #[no_mangle]
 pub unsafe extern "C" fn parse_mac_addr(addr: *mut n_eth_addr_t, str_: *mut c_char) -> c_int {
-    let mut v: [u_int; N_ETH_ALEN] = [0; N_ETH_ALEN];
-    let mut res: c_int;
-
-    // First try, standard format (00:01:02:03:04:05)
-    res = libc::sscanf(str_, c"%x:%x:%x:%x:%x:%x".as_ptr(), addr_of_mut!(v[0]), addr_of_mut!(v[1]), addr_of_mut!(v[2]), addr_of_mut!(v[3]), addr_of_mut!(v[4]), addr_of_mut!(v[5]));
-
-    if res == 6 {
-        for i in 0..N_ETH_ALEN {
-            (*addr).eth_addr_byte[i] = v[i] as m_uint8_t;
+    match std::ffi::CStr::from_ptr(str_).to_str().map(str::parse::<EthAddr>) {
+        Ok(Ok(mac)) => {
+            *addr = mac.into();
+            0
         }
-        return 0;
-    }
-
-    // Second try, Cisco format (0001.0002.0003)
-    res = libc::sscanf(str_, c"%x.%x.%x".as_ptr(), addr_of_mut!(v[0]), addr_of_mut!(v[1]), addr_of_mut!(v[2]));
-
-    if res == 3 {
-        (*addr).eth_addr_byte[0] = ((v[0] >> 8) & 0xFF) as m_uint8_t;
-        (*addr).eth_addr_byte[1] = (v[0] & 0xFF) as m_uint8_t;
-        (*addr).eth_addr_byte[2] = ((v[1] >> 8) & 0xFF) as m_uint8_t;
-        (*addr).eth_addr_byte[3] = (v[1] & 0xFF) as m_uint8_t;
-        (*addr).eth_addr_byte[4] = ((v[2] >> 8) & 0xFF) as m_uint8_t;
-        (*addr).eth_addr_byte[5] = (v[2] & 0xFF) as m_uint8_t;
-        return 0;
+        _ => -1,
     }
-
-    -1
 }
 
 // Convert an Ethernet address into a string
 #[no_mangle]
 pub unsafe extern "C" fn n_eth_ntoa(buffer: *mut c_char, addr: *mut n_eth_addr_t, format: c_int) -> *mut c_char {
-    let str_format: *const c_char = if format == 0 { c"%2.2x:%2.2x:%2.2x:%2.2x:%2.2x:%2.2x".as_ptr() } else { c"%2.2x%2.2x.%2.2x%2.2x.%2.2x%2.2x".as_ptr() };
-
-    libc::sprintf(
-        buffer,
-        str_format,
-        (*addr).eth_addr_byte[0] as c_uint,
-        (*addr).eth_addr_byte[1] as c_uint,
-        (*addr).eth_addr_byte[2] as c_uint,
-        (*addr).eth_addr_byte[3] as c_uint,
-        (*addr).eth_addr_byte[4] as c_uint,
-        (*addr).eth_addr_byte[5] as c_uint,
-    );
+    let format = if format == 0 { EthAddrFormat::Colon } else { EthAddrFormat::Cisco };
+    let s = EthAddr::from(*addr).format(format);
+    libc::memcpy(buffer.cast::<_>(), s.as_ptr().cast::<_>(), s.len());
+    *buffer.add(s.len()) = 0;
     buffer
 }
 
-// Create a new socket to connect to specified host
-#[cfg(feature = "ENABLE_IPV6")]
-unsafe fn udp_connect_ipv4_ipv6(local_port: c_int, remote_host: *mut c_char, remote_port: c_int) -> c_int {
-    let mut hints: libc::addrinfo = zeroed();
-    let mut res: *mut libc::addrinfo;
-    let mut res0: *mut libc::addrinfo = null_mut();
-    let mut st: libc::sockaddr_storage = zeroed();
-    let mut sck: c_int = -1;
-    let yes: c_int = 1;
-    let mut port_str: [c_char; 20] = [0; 20];
-
-    libc::memset(addr_of_mut!(hints).cast::<_>(), 0, size_of::<libc::addrinfo>());
-    hints.ai_family = libc::PF_UNSPEC;
-    hints.ai_socktype = libc::SOCK_DGRAM;
-
-    libc::snprintf(port_str.as_mut_ptr(), port_str.len(), c"%d".as_ptr(), remote_port);
-
-    let error: c_int = libc::getaddrinfo(remote_host, port_str.as_ptr(), addr_of!(hints), addr_of_mut!(res0));
-    if error != 0 {
-        libc::fprintf(c_stderr(), c"%s\n".as_ptr(), libc::gai_strerror(error));
-        return -1;
+/// Error codes of the socket layer, as returned by net_last_error().
+pub const NET_ERR_NONE: c_int = 0;
+pub const NET_ERR_RESOLVE: c_int = 1;
+pub const NET_ERR_NO_ADDRESS: c_int = 2;
+pub const NET_ERR_ADDR_IN_USE: c_int = 3;
+pub const NET_ERR_PORT_RANGE: c_int = 4;
+pub const NET_ERR_SYS: c_int = 5;
+
+/// Error of the socket layer.
+#[derive(Debug)]
+pub enum NetError {
+    /// getaddrinfo() failed, `code` is the EAI_* error code.
+    Resolve { host: String, code: c_int },
+    /// The host resolved to no usable IPv4/IPv6 address.
+    NoAddress { host: String },
+    /// A system call failed.
+    Sys { op: &'static str, err: std::io::Error },
+    /// No port of the range could be bound, `err` is the last failure.
+    PortRange { start: c_int, end: c_int, err: std::io::Error },
+}
+
+impl NetError {
+    /// Capture errno after a failed system call.
+    fn sys(op: &'static str) -> Self {
+        NetError::Sys { op, err: std::io::Error::last_os_error() }
     }
 
-    res = res0;
-    while !res.is_null() {
-        // We want only IPv4 or IPv6
-        if ((*res).ai_family != libc::PF_INET) && ((*res).ai_family != libc::PF_INET6) {
-            res = (*res).ai_next;
-            continue;
+    /// Error code for the C API (NET_ERR_*).
+    pub fn code(&self) -> c_int {
+        match self {
+            NetError::Resolve { .. } => NET_ERR_RESOLVE,
+            NetError::NoAddress { .. } => NET_ERR_NO_ADDRESS,
+            NetError::Sys { err, .. } if err.raw_os_error() == Some(libc::EADDRINUSE) => NET_ERR_ADDR_IN_USE,
+            NetError::Sys { .. } => NET_ERR_SYS,
+            NetError::PortRange { .. } => NET_ERR_PORT_RANGE,
         }
+    }
+}
 
-        // create new socket
-        sck = libc::socket((*res).ai_family, libc::SOCK_DGRAM, (*res).ai_protocol);
-        if sck < 0 {
-            libc::perror(c"udp_connect: socket".as_ptr());
-            res = (*res).ai_next;
-            continue;
+impl std::fmt::Display for NetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetError::Resolve { host, code } => {
+                let msg = unsafe { std::ffi::CStr::from_ptr(libc::gai_strerror(*code)) };
+                write!(f, "unable to resolve '{}': {}", host, msg.to_string_lossy())
+            }
+            NetError::NoAddress { host } => write!(f, "no usable address for '{}'", host),
+            NetError::Sys { op, err } => write!(f, "{}: {}", op, err),
+            NetError::PortRange { start, end, err } => write!(f, "no port available in range {}-{}: {}", start, end, err),
         }
+    }
+}
 
-        // bind to the local port
-        libc::memset(addr_of_mut!(st).cast::<_>(), 0, size_of::<libc::sockaddr_storage>());
+impl std::error::Error for NetError {}
+
+/// Options applied to a socket before it is bound.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SockOpts {
+    /// Set SO_REUSEADDR.
+    pub reuse_addr: bool,
+    /// Set SO_REUSEPORT (several sockets bound to the same port).
+    pub reuse_port: bool,
+    /// IPV6_V6ONLY for IPv6 sockets, None keeps the system default.
+    /// Best effort: ignored if not supported by the system.
+    pub v6only: Option<bool>,
+    /// Put the socket in non-blocking mode.
+    pub non_block: bool,
+}
 
-        match (*res).ai_family {
-            libc::PF_INET => {
-                let sin: *mut libc::sockaddr_in = addr_of_mut!(st).cast::<_>();
-                (*sin).sin_family = libc::PF_INET as _;
-                (*sin).sin_port = libc::htons(local_port as _);
-            }
+/// Result list of getaddrinfo(), freed on drop.
+pub struct AddrInfo {
+    res0: *mut libc::addrinfo,
+}
 
-            libc::PF_INET6 => {
-                let sin6: *mut libc::sockaddr_in6 = addr_of_mut!(st).cast::<_>();
-                #[cfg(has_libc_sockaddr_in6_sin6_len)]
-                {
-                    (*sin6).sin6_len = (*res).ai_addrlen as _;
-                }
-                (*sin6).sin6_family = libc::PF_INET6 as _;
-                (*sin6).sin6_port = libc::htons(local_port as _);
-            }
+impl AddrInfo {
+    /// Resolve `host` (NULL or empty for the wildcard address when `passive`).
+    /// Only IPv4 is requested if IPv6 support is disabled.
+    pub unsafe fn resolve(host: *const c_char, port: c_int, sock_type: c_int, passive: bool) -> Result<AddrInfo, NetError> {
+        let mut hints: libc::addrinfo = zeroed();
+        let mut res0: *mut libc::addrinfo = null_mut();
+        let mut port_str: [c_char; 20] = [0; 20];
 
-            _ => {
-                // shouldn't happen
-                libc::close(sck);
-                sck = -1;
-                res = (*res).ai_next;
-                continue;
-            }
+        #[cfg(feature = "ENABLE_IPV6")]
+        {
+            hints.ai_family = libc::PF_UNSPEC;
         }
-
-        // try to connect to remote host
-        if libc::setsockopt(sck, libc::SOL_SOCKET, libc::SO_REUSEADDR, addr_of!(yes).cast::<_>(), size_of::<c_int>() as _) != 0 {
-            libc::perror(c"Warning: upd_connect: setsockopt(SO_REUSEADDR)".as_ptr());
+        #[cfg(not(feature = "ENABLE_IPV6"))]
+        {
+            hints.ai_family = libc::PF_INET;
         }
-
-        if 0 == libc::bind(sck, addr_of!(st).cast::<_>(), (*res).ai_addrlen) && 0 == libc::connect(sck, (*res).ai_addr, (*res).ai_addrlen) {
-            libc::perror(c"udp_connect: bind/connect".as_ptr());
-            break;
+        hints.ai_socktype = sock_type;
+        if passive {
+            hints.ai_flags = libc::AI_PASSIVE;
         }
 
-        libc::close(sck);
-        sck = -1;
-        res = (*res).ai_next;
-    }
+        libc::snprintf(port_str.as_mut_ptr(), port_str.len(), c"%d".as_ptr(), port);
+        let node: *const c_char = if !host.is_null() && *host != 0 { host } else { null_mut() };
 
-    libc::freeaddrinfo(res0);
+        let code: c_int = libc::getaddrinfo(node, port_str.as_ptr(), addr_of!(hints), addr_of_mut!(res0));
+        if code != 0 {
+            return Err(NetError::Resolve { host: host_str(node), code });
+        }
 
-    if sck >= 0 && m_fd_set_non_block(sck) < 0 {
-        libc::perror(c"Warning: udp_connect: m_fd_set_non_block".as_ptr());
+        let list = AddrInfo { res0 };
+        if list.iter().next().is_none() {
+            return Err(NetError::NoAddress { host: host_str(node) });
+        }
+        Ok(list)
     }
 
-    sck
+    /// Iterate over the IPv4 and IPv6 entries.
+    pub fn iter(&self) -> impl Iterator<Item = &libc::addrinfo> {
+        let mut res: *const libc::addrinfo = self.res0;
+        std::iter::from_fn(move || unsafe {
+            while !res.is_null() {
+                let ai = &*res;
+                res = ai.ai_next;
+                // We want only IPv4 or IPv6
+                if ai.ai_family == libc::PF_INET || ai.ai_family == libc::PF_INET6 {
+                    return Some(ai);
+                }
+            }
+            None
+        })
+    }
 }
 
-// Create a new socket to connect to specified host.
-// Version for old systems that do not support RFC 2553 (getaddrinfo())
-//
-// See http://www.faqs.org/rfcs/rfc2553.html for more info.
-#[allow(dead_code)]
-unsafe fn udp_connect_ipv4(local_port: c_int, remote_host: *mut c_char, remote_port: c_int) -> c_int {
-    let mut sin: libc::sockaddr_in = zeroed();
-    let yes: c_int = 1;
-
-    let hp: *mut libc::hostent = _sys::gethostbyname(remote_host).cast::<_>();
-    if hp.is_null() {
-        libc::fprintf(c_stderr(), c"udp_connect: unable to resolve '%s'\n".as_ptr(), remote_host);
-        return -1;
+impl Drop for AddrInfo {
+    fn drop(&mut self) {
+        unsafe { libc::freeaddrinfo(self.res0) };
     }
+}
 
-    let sck: c_int = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
-    if sck < 0 {
-        libc::perror(c"udp_connect: socket".as_ptr());
-        return -1;
+// Printable host name, "*" for the wildcard address
+unsafe fn host_str(host: *const c_char) -> String {
+    if host.is_null() {
+        "*".to_string()
+    } else {
+        std::ffi::CStr::from_ptr(host).to_string_lossy().into_owned()
     }
+}
 
-    // bind local port
-    libc::memset(addr_of_mut!(sin).cast::<_>(), 0, size_of::<libc::sockaddr_in>());
-    sin.sin_family = libc::PF_INET as _;
-    sin.sin_port = libc::htons(local_port as _);
-
-    if libc::setsockopt(sck, libc::SOL_SOCKET, libc::SO_REUSEADDR, addr_of!(yes).cast::<_>(), size_of::<c_int>() as _) != 0 {
-        libc::perror(c"Warning: upd_connect: setsockopt(SO_REUSEADDR)".as_ptr());
-    }
+/// Socket that is closed on drop unless released.
+struct SockGuard(c_int);
 
-    if libc::bind(sck, addr_of!(sin).cast::<_>(), size_of::<libc::sockaddr_in>() as _) < 0 {
-        libc::perror(c"udp_connect: bind".as_ptr());
-        libc::close(sck);
-        return -1;
+impl SockGuard {
+    fn release(self) -> c_int {
+        let fd = self.0;
+        std::mem::forget(self);
+        fd
     }
+}
 
-    // try to connect to remote host
-    libc::memset(addr_of_mut!(sin).cast::<_>(), 0, size_of::<libc::sockaddr_in>());
-    libc::memcpy(addr_of_mut!(sin.sin_addr).cast::<_>(), (*(*hp).h_addr_list).cast::<_>(), size_of::<libc::in_addr>());
-    sin.sin_family = libc::PF_INET as _;
-    sin.sin_port = libc::htons(remote_port as _);
-
-    if libc::connect(sck, addr_of!(sin).cast::<_>(), size_of::<libc::sockaddr_in>() as _) < 0 {
-        libc::perror(c"udp_connect: connect".as_ptr());
-        libc::close(sck);
-        return -1;
+impl Drop for SockGuard {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
     }
+}
 
-    if m_fd_set_non_block(sck) < 0 {
-        libc::perror(c"Warning: udp_connect: m_fd_set_non_block".as_ptr());
+unsafe fn sock_setopt(fd: c_int, level: c_int, name: c_int, value: bool, op: &'static str) -> Result<(), NetError> {
+    let val: c_int = value as c_int;
+    if libc::setsockopt(fd, level, name, addr_of!(val).cast::<_>(), size_of::<c_int>() as _) != 0 {
+        return Err(NetError::sys(op));
     }
+    Ok(())
+}
 
-    sck
+/// Report the failure of a socket option, the socket is still usable.
+unsafe fn sock_warning(err: &NetError) {
+    let msg = std::ffi::CString::new(format!("Warning: {}\n", err)).unwrap_or_default();
+    libc::fputs(msg.as_ptr(), c_stderr());
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn udp_connect(local_port: c_int, remote_host: *mut c_char, remote_port: c_int) -> c_int {
-    #[cfg(feature = "ENABLE_IPV6")]
-    {
-        udp_connect_ipv4_ipv6(local_port, remote_host, remote_port)
+/// Create a socket for the specified address family and apply the options.
+pub unsafe fn sock_open(family: c_int, sock_type: c_int, protocol: c_int, opts: &SockOpts) -> Result<c_int, NetError> {
+    let fd: c_int = libc::socket(family, sock_type, protocol);
+    if fd < 0 {
+        return Err(NetError::sys("socket"));
+    }
+    let sck = SockGuard(fd);
+
+    if opts.reuse_addr {
+        if let Err(err) = sock_setopt(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, true, "setsockopt(SO_REUSEADDR)") {
+            sock_warning(&err);
+        }
+    }
+    if opts.reuse_port {
+        sock_setopt(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, true, "setsockopt(SO_REUSEPORT)")?;
     }
-    #[cfg(not(feature = "ENABLE_IPV6"))]
+    #[cfg(has_libc_ipv6_v6only)]
     {
-        udp_connect_ipv4(local_port, remote_host, remote_port)
+        if let (Some(v6only), libc::AF_INET6) = (opts.v6only, family) {
+            // if supported, allow packets to/from IPv4-mapped IPv6 addresses
+            let _ = sock_setopt(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, v6only, "setsockopt(IPV6_V6ONLY)");
+        }
     }
-}
-
-// Listen on the specified port
-#[cfg(feature = "ENABLE_IPV6")]
-unsafe fn ip_listen_ipv4_ipv6(ip_addr: *mut c_char, port: c_int, sock_type: c_int, max_fd: c_int, fd_array: *mut c_int) -> c_int {
-    let mut hints: libc::addrinfo = zeroed();
-    let mut res: *mut libc::addrinfo;
-    let mut res0: *mut libc::addrinfo = null_mut();
-    let mut port_str: [c_char; 20] = [0; 20];
-    let mut nsock: c_int;
-    let reuse: c_int = 1;
-
-    for i in 0..max_fd {
-        *fd_array.add(i as usize) = -1;
+    if opts.non_block && m_fd_set_non_block(fd) < 0 {
+        sock_warning(&NetError::sys("fcntl(O_NONBLOCK)"));
     }
 
-    libc::memset(addr_of_mut!(hints).cast::<_>(), 0, size_of::<libc::addrinfo>());
-    hints.ai_family = libc::PF_UNSPEC;
-    hints.ai_socktype = sock_type;
-    hints.ai_flags = libc::AI_PASSIVE;
+    Ok(sck.release())
+}
 
-    libc::snprintf(port_str.as_mut_ptr(), port_str.len(), c"%d".as_ptr(), port);
-    let addr: *mut c_char = if !ip_addr.is_null() && libc::strlen(ip_addr) != 0 { ip_addr } else { null_mut() };
+/// Create a socket bound to the specified address, listening if it is a stream socket.
+pub unsafe fn sock_bind(ai: &libc::addrinfo, opts: &SockOpts) -> Result<c_int, NetError> {
+    let sck = SockGuard(sock_open(ai.ai_family, ai.ai_socktype, ai.ai_protocol, opts)?);
 
-    let error: c_int = libc::getaddrinfo(addr, port_str.as_ptr(), addr_of!(hints), addr_of_mut!(res0));
-    if error != 0 {
-        libc::fprintf(c_stderr(), c"ip_listen: %s".as_ptr(), libc::gai_strerror(error));
-        return -1;
+    if libc::bind(sck.0, ai.ai_addr, ai.ai_addrlen) < 0 {
+        return Err(NetError::sys("bind"));
+    }
+    if ai.ai_socktype == libc::SOCK_STREAM && libc::listen(sck.0, 5) < 0 {
+        return Err(NetError::sys("listen"));
     }
 
-    nsock = 0;
-    res = res0;
-    while !res.is_null() && nsock < max_fd {
-        if ((*res).ai_family != libc::PF_INET) && ((*res).ai_family != libc::PF_INET6) {
-            res = (*res).ai_next;
-            continue;
-        }
+    Ok(sck.release())
+}
 
-        *fd_array.add(nsock as usize) = libc::socket((*res).ai_family, (*res).ai_socktype, (*res).ai_protocol);
+/// Create an UDP socket bound to `local_addr`:`local_port` and connected to `remote_host`:`remote_port`.
+/// A NULL or empty `local_addr` binds to the wildcard address of the remote address family.
+pub unsafe fn sock_udp_connect(local_addr: *const c_char, local_port: c_int, remote_host: *const c_char, remote_port: c_int, opts: &SockOpts) -> Result<c_int, NetError> {
+    let remote = AddrInfo::resolve(remote_host, remote_port, libc::SOCK_DGRAM, false)?;
+    let local = AddrInfo::resolve(local_addr, local_port, libc::SOCK_DGRAM, true)?;
+    let mut last_err: Option<NetError> = None;
 
-        if *fd_array.add(nsock as usize) < 0 {
-            res = (*res).ai_next;
+    for ai in remote.iter() {
+        let Some(lai) = local.iter().find(|lai| lai.ai_family == ai.ai_family) else {
             continue;
-        }
+        };
 
-        if libc::setsockopt(*fd_array.add(nsock as usize), libc::SOL_SOCKET, libc::SO_REUSEADDR, addr_of!(reuse).cast::<_>(), size_of::<c_int>() as _) != 0 {
-            libc::perror(c"Warning: ip_listen: setsockopt(SO_REUSEADDR): The same address-port combination can be retried after the TCP TIME_WAIT state expires.".as_ptr());
-        }
+        let res = sock_open(ai.ai_family, libc::SOCK_DGRAM, ai.ai_protocol, opts).and_then(|fd| {
+            let sck = SockGuard(fd);
+            if libc::bind(fd, lai.ai_addr, lai.ai_addrlen) < 0 {
+                return Err(NetError::sys("bind"));
+            }
+            if libc::connect(fd, ai.ai_addr, ai.ai_addrlen) < 0 {
+                return Err(NetError::sys("connect"));
+            }
+            Ok(sck.release())
+        });
 
-        if (libc::bind(*fd_array.add(nsock as usize), (*res).ai_addr, (*res).ai_addrlen) < 0) || ((sock_type == libc::SOCK_STREAM) && (libc::listen(*fd_array.add(nsock as usize), 5) < 0)) {
-            libc::perror(c"ip_listen: bind/listen".as_ptr());
-            libc::close(*fd_array.add(nsock as usize));
-            *fd_array.add(nsock as usize) = -1;
-            res = (*res).ai_next;
-            continue;
+        match res {
+            Ok(fd) => return Ok(fd),
+            Err(err) => last_err = Some(err),
         }
-
-        nsock += 1;
-        res = (*res).ai_next;
     }
 
-    libc::freeaddrinfo(res0);
-    nsock
+    Err(last_err.unwrap_or_else(|| NetError::NoAddress { host: host_str(local_addr) }))
 }
 
-// Listen on the specified port
-#[allow(dead_code)]
-unsafe fn ip_listen_ipv4(ip_addr: *mut c_char, port: c_int, sock_type: c_int, max_fd: c_int, fd_array: *mut c_int) -> c_int {
-    let mut sin: libc::sockaddr_in = zeroed();
-    let reuse: c_int = 1;
+/// Listen on the specified port, with one socket per local address (at most `max_fd`).
+pub unsafe fn sock_listen(ip_addr: *const c_char, port: c_int, sock_type: c_int, max_fd: usize, opts: &SockOpts) -> Result<Vec<c_int>, NetError> {
+    let list = AddrInfo::resolve(ip_addr, port, sock_type, true)?;
+    let mut fds: Vec<c_int> = Vec::new();
+    let mut last_err: Option<NetError> = None;
 
-    for i in 0..max_fd {
-        *fd_array.add(i as usize) = -1;
+    for ai in list.iter().take(max_fd) {
+        match sock_bind(ai, opts) {
+            Ok(fd) => fds.push(fd),
+            Err(err) => last_err = Some(err),
+        }
     }
 
-    let sck: c_int = libc::socket(libc::AF_INET, sock_type, 0);
-    if sck < 0 {
-        libc::perror(c"ip_listen: socket".as_ptr());
-        return -1;
+    match (fds.is_empty(), last_err) {
+        (true, Some(err)) => Err(err),
+        _ => Ok(fds),
     }
+}
 
-    // bind local port
-    libc::memset(addr_of_mut!(sin).cast::<_>(), 0, size_of::<libc::sockaddr_in>());
-    sin.sin_family = libc::PF_INET as _;
-    sin.sin_port = libc::htons(port as _);
+/// Listen on a TCP/UDP port chosen in the specified range, returns the socket and the port.
+pub unsafe fn sock_listen_range(ip_addr: *const c_char, port_start: c_int, port_end: c_int, sock_type: c_int, opts: &SockOpts) -> Result<(c_int, c_int), NetError> {
+    let list = AddrInfo::resolve(ip_addr, port_start, sock_type, true)?;
+    let mut last_err = std::io::Error::from_raw_os_error(libc::EINVAL);
 
-    if !ip_addr.is_null() && libc::strlen(ip_addr) != 0 {
-        sin.sin_addr.s_addr = _sys::inet_addr(ip_addr);
+    for i in port_start..=port_end {
+        for ai in list.iter() {
+            ip_socket_set_port(ai.ai_addr, i);
+
+            match sock_bind(ai, opts) {
+                Ok(fd) => {
+                    let sck = SockGuard(fd);
+                    let mut st: libc::sockaddr_storage = zeroed();
+                    let mut st_len: libc::socklen_t = size_of::<libc::sockaddr_storage>() as _;
+                    if libc::getsockname(fd, addr_of_mut!(st).cast::<_>(), addr_of_mut!(st_len)) != 0 {
+                        return Err(NetError::sys("getsockname"));
+                    }
+                    let port: c_int = ip_socket_get_port(addr_of_mut!(st).cast::<_>());
+                    return Ok((sck.release(), port));
+                }
+                Err(NetError::Sys { err, .. }) => last_err = err,
+                Err(err) => return Err(err),
+            }
+        }
     }
 
-    if libc::setsockopt(sck, libc::SOL_SOCKET, libc::SO_REUSEADDR, addr_of!(reuse).cast::<_>(), size_of::<c_int>() as _) != 0 {
-        libc::perror(c"Warning: ip_listen: setsockopt(SO_REUSEADDR): The same address-port combination can be retried after the TCP TIME_WAIT state expires.".as_ptr());
-    }
+    Err(NetError::PortRange { start: port_start, end: port_end, err: last_err })
+}
 
-    let error = || {
-        libc::close(sck);
-        -1
-    };
-    if libc::bind(sck, addr_of!(sin).cast::<_>(), size_of::<libc::sockaddr_in>() as _) < 0 {
-        libc::perror(c"ip_listen: bind".as_ptr());
-        return error();
-    }
+/// Connect an existing socket to the specified host.
+pub unsafe fn sock_connect_fd(fd: c_int, remote_host: *const c_char, remote_port: c_int) -> Result<(), NetError> {
+    let list = AddrInfo::resolve(remote_host, remote_port, 0, false)?;
+    let mut last_err: Option<NetError> = None;
 
-    if (sock_type == libc::SOCK_STREAM) && (libc::listen(sck, 5) < 0) {
-        libc::perror(c"ip_listen: listen".as_ptr());
-        return error();
+    for ai in list.iter() {
+        if libc::connect(fd, ai.ai_addr, ai.ai_addrlen) == 0 {
+            return Ok(());
+        }
+        last_err = Some(NetError::sys("connect"));
     }
 
-    *fd_array.add(0) = sck;
-    1
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn ip_listen(ip_addr: *mut c_char, port: c_int, sock_type: c_int, max_fd: c_int, fd_array: *mut c_int) -> c_int {
-    #[cfg(feature = "ENABLE_IPV6")]
-    {
-        ip_listen_ipv4_ipv6(ip_addr, port, sock_type, max_fd, fd_array)
-    }
-    #[cfg(not(feature = "ENABLE_IPV6"))]
-    {
-        ip_listen_ipv4(ip_addr, port, sock_type, max_fd, fd_array)
-    }
+    Err(last_err.unwrap_or_else(|| NetError::NoAddress { host: host_str(remote_host) }))
 }
 
 // Get port in an address info structure
-#[cfg(feature = "ENABLE_IPV6")]
-unsafe fn ip_socket_get_port_ipv4_ipv6(addr: *mut libc::sockaddr) -> c_int {
+pub(crate) unsafe fn ip_socket_get_port(addr: *mut libc::sockaddr) -> c_int {
     match (*addr).sa_family as _ {
         libc::AF_INET => libc::ntohs((*addr.cast::<libc::sockaddr_in>()).sin_port) as c_int,
         libc::AF_INET6 => libc::ntohs((*addr.cast::<libc::sockaddr_in6>()).sin6_port) as c_int,
@@ -975,8 +872,7 @@ unsafe fn ip_socket_get_port_ipv4_ipv6(addr: *mut libc::sockaddr) -> c_int {
 }
 
 // Set port in an address info structure
-#[cfg(feature = "ENABLE_IPV6")]
-unsafe fn ip_socket_set_port_ipv4_ipv6(addr: *mut libc::sockaddr, port: c_int) -> c_int {
+unsafe fn ip_socket_set_port(addr: *mut libc::sockaddr, port: c_int) -> c_int {
     if addr.is_null() {
         return -1;
     }
@@ -999,217 +895,91 @@ unsafe fn ip_socket_set_port_ipv4_ipv6(addr: *mut libc::sockaddr, port: c_int) -
     }
 }
 
-// Try to create a socket and bind to the specified address info
-#[cfg(feature = "ENABLE_IPV6")]
-unsafe fn ip_socket_bind_ipv4_ipv6(addr: *mut libc::addrinfo) -> c_int {
-    let fd: c_int = libc::socket((*addr).ai_family, (*addr).ai_socktype, (*addr).ai_protocol);
-    if fd < 0 {
-        return -1;
-    }
-
-    #[cfg(has_libc_ipv6_v6only)]
-    {
-        let off: c_int = 0;
-        if (*addr).ai_family == libc::AF_INET6 {
-            // if supported, allow packets to/from IPv4-mapped IPv6 addresses
-            let _ = libc::setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, addr_of!(off).cast::<_>(), size_of::<c_int>() as _);
-        }
-    }
-
-    if (libc::bind(fd, (*addr).ai_addr, (*addr).ai_addrlen) < 0) || (((*addr).ai_socktype == libc::SOCK_STREAM) && (libc::listen(fd, 5) < 0)) {
-        libc::close(fd);
-        return -1;
-    }
-
-    fd
+thread_local! {
+    static NET_LAST_ERROR: std::cell::RefCell<(c_int, std::ffi::CString)> = std::cell::RefCell::new((NET_ERR_NONE, std::ffi::CString::default()));
 }
 
-// Listen on a TCP/UDP port - port is choosen in the specified range
-#[cfg(feature = "ENABLE_IPV6")]
-unsafe fn ip_listen_range_ipv4_ipv6(ip_addr: *mut c_char, port_start: c_int, port_end: c_int, port: *mut c_int, sock_type: c_int) -> c_int {
-    let mut hints: libc::addrinfo = zeroed();
-    let mut res: *mut libc::addrinfo;
-    let mut res0: *mut libc::addrinfo = null_mut();
-    let mut st: libc::sockaddr_storage = zeroed();
-    let mut st_len: libc::socklen_t;
-    let mut port_str: [c_char; 20] = [0; 20];
-    let mut fd: c_int = -1;
-
-    libc::memset(addr_of_mut!(hints).cast::<_>(), 0, size_of::<libc::addrinfo>());
-    hints.ai_family = libc::PF_UNSPEC;
-    hints.ai_socktype = sock_type;
-    hints.ai_flags = libc::AI_PASSIVE;
-
-    libc::snprintf(port_str.as_mut_ptr(), port_str.len(), c"%d".as_ptr(), port_start);
-    let addr: *mut c_char = if !ip_addr.is_null() && libc::strlen(ip_addr) != 0 { ip_addr } else { null_mut() };
-
-    let error: c_int = libc::getaddrinfo(addr, port_str.as_ptr(), addr_of!(hints), addr_of_mut!(res0));
-    if error != 0 {
-        libc::fprintf(c_stderr(), c"ip_listen_range: %s".as_ptr(), libc::gai_strerror(error));
-        return -1;
-    }
-
-    'not_done: {
-        for i in port_start..=port_end {
-            res = res0;
-            while !res.is_null() {
-                ip_socket_set_port_ipv4_ipv6((*res).ai_addr, i);
-
-                fd = ip_socket_bind_ipv4_ipv6(res);
-                if fd >= 0 {
-                    st_len = size_of::<libc::socklen_t>() as _;
-                    if libc::getsockname(fd, addr_of_mut!(st).cast::<_>(), addr_of_mut!(st_len)) != 0 {
-                        libc::close(fd);
-                        res = (*res).ai_next;
-                        continue;
-                    }
-                    *port = ip_socket_get_port_ipv4_ipv6(addr_of_mut!(st).cast::<_>());
-                    break 'not_done;
-                }
-                res = (*res).ai_next;
-            }
-        }
-    }
-
-    // done:
-    libc::freeaddrinfo(res0);
-    fd
+// Record the error of a C API call and report it on stderr
+fn net_set_last_error(func: &str, err: &NetError) {
+    let msg = std::ffi::CString::new(format!("{}: {}", func, err)).unwrap_or_default();
+    unsafe { libc::fprintf(c_stderr(), c"%s\n".as_ptr(), msg.as_ptr()) };
+    NET_LAST_ERROR.with_borrow_mut(|last| *last = (err.code(), msg));
 }
 
-// Connect an existing socket to connect to specified host
-#[cfg(feature = "ENABLE_IPV6")]
-unsafe fn ip_connect_fd_ipv4_ipv6(fd: c_int, remote_host: *mut c_char, remote_port: c_int) -> c_int {
-    let mut hints: libc::addrinfo = zeroed();
-    let mut res: *mut libc::addrinfo;
-    let mut res0: *mut libc::addrinfo = null_mut();
-    let mut port_str: [c_char; 20] = [0; 20];
-
-    libc::memset(addr_of_mut!(hints).cast::<_>(), 0, size_of::<libc::addrinfo>());
-    hints.ai_family = libc::PF_UNSPEC;
-
-    libc::snprintf(port_str.as_mut_ptr(), port_str.len(), c"%d".as_ptr(), remote_port);
-
-    let error: c_int = libc::getaddrinfo(remote_host, port_str.as_ptr(), addr_of!(hints), addr_of_mut!(res0));
-    if error != 0 {
-        libc::fprintf(c_stderr(), c"%s\n".as_ptr(), libc::gai_strerror(error));
-        return -1;
-    }
-
-    res = res0;
-    while !res.is_null() {
-        if ((*res).ai_family != libc::PF_INET) && ((*res).ai_family != libc::PF_INET6) {
-            res = (*res).ai_next;
-            continue;
+// Convert the result of the socket layer to a C return value
+fn net_c_result<T>(func: &str, res: Result<T, NetError>, f: impl FnOnce(T) -> c_int) -> c_int {
+    match res {
+        Ok(val) => {
+            NET_LAST_ERROR.with_borrow_mut(|last| last.0 = NET_ERR_NONE);
+            f(val)
         }
-
-        if libc::connect(fd, (*res).ai_addr, (*res).ai_addrlen) == 0 {
-            break;
+        Err(err) => {
+            net_set_last_error(func, &err);
+            -1
         }
-        res = (*res).ai_next;
     }
-
-    libc::freeaddrinfo(res0);
-    0
 }
 
-// Try to create a socket and bind to the specified address info
-#[allow(dead_code)]
-unsafe fn ip_socket_bind_ipv4(sin: *mut libc::sockaddr_in, sock_type: c_int) -> c_int {
-    let fd: c_int = libc::socket((*sin).sin_family as _, sock_type, 0);
-
-    if fd < 0 {
-        return -1;
-    }
-
-    if (libc::bind(fd, sin.cast::<_>(), size_of::<libc::sockaddr_in>() as _) < 0) || ((sock_type == libc::SOCK_STREAM) && (libc::listen(fd, 5) < 0)) {
-        libc::close(fd);
-        return -1;
-    }
-
-    fd
+// Get the error code (NET_ERR_*) of the last failed socket function of this thread
+#[no_mangle]
+pub extern "C" fn net_last_error() -> c_int {
+    NET_LAST_ERROR.with_borrow(|last| last.0)
 }
 
-// Listen on a TCP/UDP port - port is choosen in the specified range
-#[allow(dead_code)]
-unsafe fn ip_listen_range_ipv4(ip_addr: *mut c_char, port_start: c_int, port_end: c_int, port: *mut c_int, sock_type: c_int) -> c_int {
-    let hp: *mut libc::hostent;
-    let mut sin: libc::sockaddr_in = zeroed();
-    let mut len: libc::socklen_t;
-    let mut fd: c_int;
-
-    libc::memset(addr_of_mut!(sin).cast::<_>(), 0, size_of::<libc::sockaddr_in>());
-    sin.sin_family = libc::PF_INET as _;
-
-    if !ip_addr.is_null() && libc::strlen(ip_addr) != 0 {
-        hp = _sys::gethostbyname(ip_addr).cast::<_>();
-        if hp.is_null() {
-            libc::fprintf(c_stderr(), c"ip_listen_range: unable to resolve '%s'\n".as_ptr(), ip_addr);
-            return -1;
-        }
-
-        libc::memcpy(addr_of_mut!(sin.sin_addr).cast::<_>(), (*(*hp).h_addr_list).cast::<_>(), size_of::<libc::in_addr>());
-    }
+// Clear the error of the last failed socket function of this thread
+#[no_mangle]
+pub extern "C" fn net_clear_error() {
+    NET_LAST_ERROR.with_borrow_mut(|last| *last = (NET_ERR_NONE, std::ffi::CString::default()));
+}
 
-    for i in port_start..=port_end {
-        sin.sin_port = libc::htons(i as _);
-
-        fd = ip_socket_bind_ipv4(addr_of_mut!(sin), sock_type);
-        if fd >= 0 {
-            len = size_of::<libc::sockaddr_in>() as _;
-            if libc::getsockname(fd, addr_of_mut!(sin).cast::<_>(), addr_of_mut!(len)) != 0 {
-                libc::close(fd);
-                continue;
-            }
-            *port = libc::ntohs(sin.sin_port) as _;
-            return fd;
-        }
-    }
+// Get the message of the last failed socket function of this thread.
+// The string is valid until the next socket function call of the thread.
+#[no_mangle]
+pub extern "C" fn net_last_error_str() -> *const c_char {
+    NET_LAST_ERROR.with_borrow(|last| last.1.as_ptr())
+}
 
-    -1
+// Create a new socket to connect to specified host
+#[no_mangle]
+pub unsafe extern "C" fn udp_connect(local_port: c_int, remote_host: *mut c_char, remote_port: c_int) -> c_int {
+    udp_connect_bind(null_mut(), local_port, remote_host, remote_port)
 }
 
-// Connect an existing socket to connect to specified host
-#[allow(dead_code)]
-unsafe fn ip_connect_fd_ipv4(fd: c_int, remote_host: *mut c_char, remote_port: c_int) -> c_int {
-    let mut sin: libc::sockaddr_in = zeroed();
+// Create a new socket bound to the specified local address to connect to specified host
+#[no_mangle]
+pub unsafe extern "C" fn udp_connect_bind(local_addr: *mut c_char, local_port: c_int, remote_host: *mut c_char, remote_port: c_int) -> c_int {
+    let opts = SockOpts { reuse_addr: true, non_block: true, ..Default::default() };
+    net_c_result("udp_connect", sock_udp_connect(local_addr, local_port, remote_host, remote_port, &opts), |fd| fd)
+}
 
-    let hp: *mut libc::hostent = _sys::gethostbyname(remote_host).cast::<_>();
-    if hp.is_null() {
-        libc::fprintf(c_stderr(), c"ip_connect_fd: unable to resolve '%s'\n".as_ptr(), remote_host);
-        return -1;
+// Listen on the specified port
+#[no_mangle]
+pub unsafe extern "C" fn ip_listen(ip_addr: *mut c_char, port: c_int, sock_type: c_int, max_fd: c_int, fd_array: *mut c_int) -> c_int {
+    for i in 0..max_fd {
+        *fd_array.add(i as usize) = -1;
     }
 
-    // try to connect to remote host
-    libc::memset(addr_of_mut!(sin).cast::<_>(), 0, size_of::<libc::sockaddr_in>());
-    libc::memcpy(addr_of_mut!(sin.sin_addr).cast::<_>(), (*(*hp).h_addr_list).cast::<_>(), size_of::<libc::in_addr>());
-    sin.sin_family = libc::PF_INET as _;
-    sin.sin_port = libc::htons(remote_port as _);
-
-    libc::connect(fd, addr_of!(sin).cast::<_>(), size_of::<libc::sockaddr_in>() as _)
+    let opts = SockOpts { reuse_addr: true, ..Default::default() };
+    net_c_result("ip_listen", sock_listen(ip_addr, port, sock_type, max_fd.max(0) as usize, &opts), |fds| {
+        libc::memcpy(fd_array.cast::<_>(), fds.as_ptr().cast::<_>(), fds.len() * size_of::<c_int>());
+        fds.len() as c_int
+    })
 }
 
+// Listen on a TCP/UDP port - port is choosen in the specified range
 #[no_mangle]
 pub unsafe extern "C" fn ip_listen_range(ip_addr: *mut c_char, port_start: c_int, port_end: c_int, port: *mut c_int, sock_type: c_int) -> c_int {
-    #[cfg(feature = "ENABLE_IPV6")]
-    {
-        ip_listen_range_ipv4_ipv6(ip_addr, port_start, port_end, port, sock_type)
-    }
-    #[cfg(not(feature = "ENABLE_IPV6"))]
-    {
-        ip_listen_range_ipv4(ip_addr, port_start, port_end, port, sock_type)
-    }
+    let opts = SockOpts { v6only: Some(false), ..Default::default() };
+    net_c_result("ip_listen_range", sock_listen_range(ip_addr, port_start, port_end, sock_type, &opts), |(fd, p)| {
+        *port = p;
+        fd
+    })
 }
 
+// Connect an existing socket to connect to specified host
 #[no_mangle]
 pub unsafe extern "C" fn ip_connect_fd(fd: c_int, remote_host: *mut c_char, remote_port: c_int) -> c_int {
-    #[cfg(feature = "ENABLE_IPV6")]
-    {
-        ip_connect_fd_ipv4_ipv6(fd, remote_host, remote_port)
-    }
-    #[cfg(not(feature = "ENABLE_IPV6"))]
-    {
-        ip_connect_fd_ipv4(fd, remote_host, remote_port)
-    }
+    net_c_result("ip_connect_fd", sock_connect_fd(fd, remote_host, remote_port), |()| 0)
 }
 
 // Create a socket UDP listening in a port of specified range
@@ -1232,7 +1002,7 @@ pub unsafe extern "C" fn cisco_isl_rewrite(pkt: *mut m_uint8_t, tot_len: m_uint3
     let hdr: *mut n_eth_hdr_t = pkt.cast::<_>();
     if 0 == libc::memcmp(addr_of_mut!((*hdr).daddr).cast::<_>(), isl_xaddr.as_ptr().cast::<_>(), N_ETH_ALEN) {
         real_offset = (N_ETH_HLEN + N_ISL_HDR_SIZE) as u_int;
-        real_len = libc::ntohs((*hdr).r#type) as u_int;
+        real_len = (*hdr).r#type.get() as u_int;
         real_len -= (N_ISL_HDR_SIZE + 4) as u_int;
 
         if (real_offset + real_len) > tot_len {
@@ -1278,7 +1048,7 @@ pub unsafe extern "C" fn ip_compute_cksum(hdr: *mut n_ip_hdr_t) {
     let mut sum: m_uint32_t = 0;
     let mut len: u_int;
 
-    (*hdr).cksum = 0;
+    (*hdr).cksum = Be16::default();
 
     len = (((*hdr).ihl as u_int) & 0x0F) << 1;
     while len > 0 {
@@ -1291,7 +1061,7 @@ pub unsafe extern "C" fn ip_compute_cksum(hdr: *mut n_ip_hdr_t) {
         sum = (sum & 0xFFFF) + (sum >> 16);
     }
 
-    (*hdr).cksum = libc::htons(!(sum as m_uint16_t));
+    (*hdr).cksum = Be16::new(!(sum as m_uint16_t));
 }
 
 // Partial checksum (for UDP/TCP)
@@ -1316,24 +1086,24 @@ pub unsafe fn ip_cksum_partial(mut buf: *mut m_uint8_t, mut len: c_int) -> m_uin
 #[no_mangle]
 pub unsafe extern "C" fn pkt_ctx_tcp_cksum(ctx: *mut n_pkt_ctx_t, ph: c_int) -> m_uint16_t {
     let mut sum: m_uint32_t;
-    let mut old_cksum: m_uint16_t = 0;
+    let mut old_cksum = Be16::default();
 
     // replace the actual checksum value with 0 to recompute it
     if 0 == ((*ctx).flags & N_PKT_CTX_FLAG_IP_FRAG) {
         match (*ctx).ip_l4_proto {
             N_IP_PROTO_TCP => {
                 old_cksum = (*(*ctx).l4.tcp).cksum;
-                (*(*ctx).l4.tcp).cksum = 0;
+                (*(*ctx).l4.tcp).cksum = Be16::default();
             }
             N_IP_PROTO_UDP => {
                 old_cksum = (*(*ctx).l4.udp).cksum;
-                (*(*ctx).l4.udp).cksum = 0;
+                (*(*ctx).l4.udp).cksum = Be16::default();
             }
             _ => {}
         }
     }
 
-    let len: u_int = libc::ntohs((*(*ctx).l3.ip).tot_len) as m_uint32_t - (((*(*ctx).l3.ip).ihl & 0x0F) << 2) as m_uint32_t;
+    let len: u_int = (*(*ctx).l3.ip).tot_len.get() as m_uint32_t - (((*(*ctx).l3.ip).ihl & 0x0F) << 2) as m_uint32_t;
     sum = ip_cksum_partial((*ctx).l4.ptr.cast::<_>(), len as c_int);
 
     // include pseudo-header
@@ -1395,17 +1165,17 @@ pub unsafe extern "C" fn pkt_ctx_analyze(ctx: *mut n_pkt_ctx_t, pkt: *mut m_uint
     (*ctx).l3.ptr = null_mut();
     (*ctx).l4.ptr = null_mut();
 
-    eth_type = libc::ntohs((*eth).r#type);
+    eth_type = (*eth).r#type.get();
     p = PTR_ADJUST!(*mut m_uint8_t, eth, N_ETH_HLEN);
 
     #[allow(clippy::collapsible_if)]
     if eth_type >= N_ETH_MTU {
         if eth_type == N_ETH_PROTO_DOT1Q {
             (*ctx).flags |= N_PKT_CTX_FLAG_VLAN;
-            (*ctx).vlan_id = libc::htons((*eth).vlan_id);
+            (*ctx).vlan_id = (*eth).vlan_id.get();
 
             // override the ethernet type
-            eth_type = libc::ntohs(*p.add(2).cast::<m_uint16_t>());
+            eth_type = (*p.add(2).cast::<Be16>()).get();
 
             // skip 802.1Q header info
             p = p.add(size_of::<m_uint32_t>());
@@ -1427,7 +1197,7 @@ pub unsafe extern "C" fn pkt_ctx_analyze(ctx: *mut n_pkt_ctx_t, pkt: *mut m_uint
 
             // Check header
             let len: u_int = ((*ip).ihl & 0x0F) as _;
-            if (((*ip).ihl & 0xF0) != 0x40) || (len < N_IP_MIN_HLEN) || ((len << 2) > libc::ntohs((*ip).tot_len) as _) || 0 == ip_verify_cksum((*ctx).l3.ip) {
+            if (((*ip).ihl & 0xF0) != 0x40) || (len < N_IP_MIN_HLEN) || ((len << 2) > (*ip).tot_len.get() as _) || 0 == ip_verify_cksum((*ctx).l3.ip) {
                 return TRUE;
             }
 
@@ -1436,7 +1206,7 @@ pub unsafe extern "C" fn pkt_ctx_analyze(ctx: *mut n_pkt_ctx_t, pkt: *mut m_uint
             (*ctx).l4.ptr = PTR_ADJUST!(*mut c_void, ip, (len << 2) as _);
 
             // Check if the packet is a fragment
-            let offset: u_int = libc::ntohs((*ip).frag_off as _) as _;
+            let offset: u_int = (*ip).frag_off.get() as _;
 
             if ((offset & N_IP_OFFMASK) != 0) || (offset & N_IP_FLAG_MF) != 0 {
                 (*ctx).flags |= N_PKT_CTX_FLAG_IP_FRAG;
@@ -1471,3 +1241,333 @@ pub unsafe extern "C" fn pkt_ctx_dump(ctx: *mut n_pkt_ctx_t) {
         (*ctx).l4.ptr,
     );
 }
+
+/// Error returned when parsing an address, network or board id fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseAddrError {
+    kind: &'static str,
+    input: String,
+}
+
+impl ParseAddrError {
+    fn new(kind: &'static str, input: &str) -> Self {
+        ParseAddrError { kind, input: input.to_string() }
+    }
+}
+
+impl std::fmt::Display for ParseAddrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid {} '{}'", self.kind, self.input)
+    }
+}
+
+impl std::error::Error for ParseAddrError {}
+
+/// Text formats of a MAC address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EthAddrFormat {
+    /// 00:01:02:03:04:05
+    Colon,
+    /// 0001.0203.0405
+    Cisco,
+    /// 00-01-02-03-04-05
+    Hyphen,
+}
+
+/// MAC address.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EthAddr(pub [m_uint8_t; N_ETH_ALEN]);
+
+impl EthAddr {
+    /// Format the address, `Display` uses the colon format.
+    pub fn format(&self, format: EthAddrFormat) -> String {
+        let b = &self.0;
+        match format {
+            EthAddrFormat::Colon => format!("{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", b[0], b[1], b[2], b[3], b[4], b[5]),
+            EthAddrFormat::Cisco => format!("{:02x}{:02x}.{:02x}{:02x}.{:02x}{:02x}", b[0], b[1], b[2], b[3], b[4], b[5]),
+            EthAddrFormat::Hyphen => format!("{:02x}-{:02x}-{:02x}-{:02x}-{:02x}-{:02x}", b[0], b[1], b[2], b[3], b[4], b[5]),
+        }
+    }
+}
+
+// Parse groups of 1 to max_digits hex digits
+fn parse_hex_groups(s: &str, sep: char, max_digits: usize, out: &mut [u16]) -> bool {
+    let mut n = 0;
+    for group in s.split(sep) {
+        if n == out.len() || group.is_empty() || group.len() > max_digits || !group.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return false;
+        }
+        out[n] = u16::from_str_radix(group, 16).unwrap_or(0);
+        n += 1;
+    }
+    n == out.len()
+}
+
+impl std::str::FromStr for EthAddr {
+    type Err = ParseAddrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut v: [u16; N_ETH_ALEN] = [0; N_ETH_ALEN];
+        let mut addr = EthAddr::default();
+
+        if parse_hex_groups(s, ':', 2, &mut v) || parse_hex_groups(s, '-', 2, &mut v) {
+            for i in 0..N_ETH_ALEN {
+                addr.0[i] = v[i] as m_uint8_t;
+            }
+            return Ok(addr);
+        }
+        if parse_hex_groups(s, '.', 4, &mut v[..3]) {
+            for i in 0..3 {
+                addr.0[2 * i..2 * i + 2].copy_from_slice(&v[i].to_be_bytes());
+            }
+            return Ok(addr);
+        }
+        Err(ParseAddrError::new("MAC address", s))
+    }
+}
+
+impl std::fmt::Display for EthAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.format(EthAddrFormat::Colon))
+    }
+}
+
+impl From<n_eth_addr_t> for EthAddr {
+    fn from(addr: n_eth_addr_t) -> Self {
+        EthAddr(addr.eth_addr_byte)
+    }
+}
+
+impl From<EthAddr> for n_eth_addr_t {
+    fn from(addr: EthAddr) -> Self {
+        n_eth_addr_t { eth_addr_byte: addr.0 }
+    }
+}
+
+/// IPv4 network in CIDR notation (a.b.c.d/len), the host bits are kept.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Ipv4Net {
+    addr: std::net::Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Ipv4Net {
+    /// Returns None if the prefix length is larger than 32.
+    pub fn new(addr: std::net::Ipv4Addr, prefix_len: u8) -> Option<Self> {
+        (prefix_len as usize <= N_IP_ADDR_BITS).then_some(Ipv4Net { addr, prefix_len })
+    }
+
+    pub fn addr(&self) -> std::net::Ipv4Addr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Network mask (host order).
+    pub fn mask(&self) -> n_ip_addr_t {
+        ip_masks[self.prefix_len as usize]
+    }
+
+    /// Network address, without the host bits.
+    pub fn network(&self) -> std::net::Ipv4Addr {
+        std::net::Ipv4Addr::from(n_ip_addr_t::from(self.addr) & self.mask())
+    }
+}
+
+impl std::str::FromStr for Ipv4Net {
+    type Err = ParseAddrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseAddrError::new("IPv4 network", s);
+        let (addr, len) = s.split_once('/').ok_or_else(err)?;
+        let addr = addr.parse::<std::net::Ipv4Addr>().map_err(|_| err())?;
+        let len = len.parse::<u8>().map_err(|_| err())?;
+        Ipv4Net::new(addr, len).ok_or_else(err)
+    }
+}
+
+impl std::fmt::Display for Ipv4Net {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl From<Ipv4Net> for n_ip_network_t {
+    fn from(net: Ipv4Net) -> Self {
+        n_ip_network_t { net_addr: net.addr.into(), net_mask: net.mask() }
+    }
+}
+
+impl TryFrom<n_ip_network_t> for Ipv4Net {
+    type Error = ParseAddrError;
+
+    /// Fails if the network mask is not contiguous.
+    fn try_from(net: n_ip_network_t) -> Result<Self, Self::Error> {
+        let prefix_len = net.net_mask.leading_ones();
+        if net.net_mask != ip_masks[prefix_len as usize] {
+            return Err(ParseAddrError::new("IPv4 network mask", &std::net::Ipv4Addr::from(net.net_mask).to_string()));
+        }
+        Ok(Ipv4Net { addr: net.net_addr.into(), prefix_len: prefix_len as u8 })
+    }
+}
+
+/// IPv6 network in CIDR notation (addr/len), the host bits are kept.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Ipv6Net {
+    addr: std::net::Ipv6Addr,
+    prefix_len: u8,
+}
+
+impl Ipv6Net {
+    /// Returns None if the prefix length is larger than 128.
+    pub fn new(addr: std::net::Ipv6Addr, prefix_len: u8) -> Option<Self> {
+        (prefix_len as usize <= N_IPV6_ADDR_BITS).then_some(Ipv6Net { addr, prefix_len })
+    }
+
+    pub fn addr(&self) -> std::net::Ipv6Addr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Network address, without the host bits.
+    pub fn network(&self) -> std::net::Ipv6Addr {
+        let mask = u128::MAX.checked_shl(N_IPV6_ADDR_BITS as u32 - self.prefix_len as u32).unwrap_or(0);
+        std::net::Ipv6Addr::from(u128::from(self.addr) & mask)
+    }
+}
+
+impl std::str::FromStr for Ipv6Net {
+    type Err = ParseAddrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseAddrError::new("IPv6 network", s);
+        let (addr, len) = s.split_once('/').ok_or_else(err)?;
+        let addr = addr.parse::<std::net::Ipv6Addr>().map_err(|_| err())?;
+        let len = len.parse::<u8>().map_err(|_| err())?;
+        Ipv6Net::new(addr, len).ok_or_else(err)
+    }
+}
+
+impl std::fmt::Display for Ipv6Net {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl From<Ipv6Net> for n_ipv6_network_t {
+    fn from(net: Ipv6Net) -> Self {
+        let net_addr = n_ipv6_addr_t { ip6: n_ipv6_addr_ip6 { u6_addr8: net.addr.octets() } };
+        n_ipv6_network_t { net_addr, net_mask: net.prefix_len as u_int }
+    }
+}
+
+impl TryFrom<n_ipv6_network_t> for Ipv6Net {
+    type Error = ParseAddrError;
+
+    /// Fails if the prefix length is larger than 128.
+    fn try_from(net: n_ipv6_network_t) -> Result<Self, Self::Error> {
+        let addr = std::net::Ipv6Addr::from(unsafe { net.net_addr.ip6.u6_addr8 });
+        u8::try_from(net.net_mask).ok().and_then(|len| Ipv6Net::new(addr, len)).ok_or_else(|| ParseAddrError::new("IPv6 prefix length", &net.net_mask.to_string()))
+    }
+}
+
+/// Processor board id, in one of the EEPROM encodings of `parse_board_id`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BoardId {
+    /// Decimal number, encoded in 4 bytes (big endian).
+    Numeric(m_uint32_t),
+    /// 3 chars, 2 hex bytes and 4 chars (ex: FOC0a1bXYZW), encoded in 9 bytes.
+    Packed([m_uint8_t; 9]),
+    /// 11 chars, encoded in 11 bytes.
+    Text([m_uint8_t; 11]),
+}
+
+impl BoardId {
+    /// Parse a board id with the specified encoding (4, 9 or 11 bytes).
+    pub fn parse(s: &str, encode: usize) -> Result<Self, ParseAddrError> {
+        let err = || ParseAddrError::new("board id", s);
+        let b = s.as_bytes();
+
+        match encode {
+            4 => {
+                // like "%d", negative values keep their two's complement bits
+                let v = s.parse::<m_uint32_t>().or_else(|_| s.parse::<i32>().map(|v| v as m_uint32_t)).map_err(|_| err())?;
+                Ok(BoardId::Numeric(v))
+            }
+            9 => {
+                if b.len() != 11 || !b[3..7].iter().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(err());
+                }
+                let mut buf: [m_uint8_t; 9] = [0; 9];
+                buf[..3].copy_from_slice(&b[..3]);
+                buf[3] = u8::from_str_radix(&s[3..5], 16).map_err(|_| err())?;
+                buf[4] = u8::from_str_radix(&s[5..7], 16).map_err(|_| err())?;
+                buf[5..].copy_from_slice(&b[7..]);
+                Ok(BoardId::Packed(buf))
+            }
+            11 => Ok(BoardId::Text(b.try_into().map_err(|_| err())?)),
+            _ => Err(err()),
+        }
+    }
+
+    /// Number of bytes of the encoding.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            BoardId::Numeric(_) => 4,
+            BoardId::Packed(_) => 9,
+            BoardId::Text(_) => 11,
+        }
+    }
+
+    /// EEPROM encoding, padded with zeros to 11 bytes.
+    pub fn encode(&self) -> [m_uint8_t; 11] {
+        let mut buf: [m_uint8_t; 11] = [0; 11];
+        match self {
+            BoardId::Numeric(v) => buf[..4].copy_from_slice(&v.to_be_bytes()),
+            BoardId::Packed(b) => buf[..9].copy_from_slice(b),
+            BoardId::Text(b) => buf.copy_from_slice(b),
+        }
+        buf
+    }
+
+    /// Decode the EEPROM encoding with the specified length (4, 9 or 11 bytes).
+    pub fn decode(buf: &[m_uint8_t], encode: usize) -> Option<Self> {
+        let buf = buf.get(..encode)?;
+        match encode {
+            4 => Some(BoardId::Numeric(m_uint32_t::from_be_bytes(buf.try_into().ok()?))),
+            9 => Some(BoardId::Packed(buf.try_into().ok()?)),
+            11 => Some(BoardId::Text(buf.try_into().ok()?)),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for BoardId {
+    type Err = ParseAddrError;
+
+    /// Decimal numbers are `Numeric`, other ids are `Text`.
+    /// A number that does not format back to the same text (leading zeros) is `Text`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(id) = s.parse::<m_uint32_t>() {
+            if id.to_string() == s {
+                return Ok(BoardId::Numeric(id));
+            }
+        }
+        BoardId::parse(s, 11)
+    }
+}
+
+impl std::fmt::Display for BoardId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BoardId::Numeric(v) => write!(f, "{}", v),
+            BoardId::Packed(b) => write!(f, "{}{:02x}{:02x}{}", String::from_utf8_lossy(&b[..3]), b[3], b[4], String::from_utf8_lossy(&b[5..])),
+            BoardId::Text(b) => write!(f, "{}", String::from_utf8_lossy(b)),
+        }
+    }
+}
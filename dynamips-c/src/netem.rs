@@ -0,0 +1,525 @@
+//! Link impairments ("netem" packet filter).
+//!
+//! Emulates a WAN link on a NIO, like the Linux netem queueing discipline:
+//! fixed or distributed delay with jitter, random or bursty (Gilbert-Elliott)
+//! loss, duplication, corruption and reordering.
+//!
+//! The filter is configured with `nio setup_filter`, each call changes only
+//! the parameters it specifies:
+//!
+//! ```text
+//! delay <ms> [<jitter_ms> [uniform|normal]]
+//! loss [random] <percent>
+//! loss gemodel <p> [<r> [<1-h> [<1-k>]]]
+//! duplicate <percent>
+//! corrupt <percent>
+//! reorder <percent>
+//! limit <packets>
+//! seed <n>
+//! match <expression...>
+//! reset
+//! ```
+//!
+//! The impairments are applied in this order: loss, duplication, corruption,
+//! then delay. Delayed packets are consumed by the filter and delivered later
+//! from a timer, sorted by due time (so a large jitter reorders packets). With
+//! reordering, the selected packets are sent immediately while the others are
+//! delayed. Immediate duplicates use NETIO_FILTER_ACTION_DUPLICATE, delayed
+//! packets NETIO_FILTER_ACTION_QUEUED.
+//!
+//! With "match", which takes the rest of the arguments, only the packets
+//! matching a filter expression (see `pkt_filter`) are impaired, the others
+//! pass untouched and are not counted.
+//!
+//! All the random decisions come from a per filter generator, with the same
+//! seed and the same traffic a run is reproducible.
+
+use crate::_extra::*;
+use crate::dynamips_common::*;
+use crate::pkt_filter::*;
+use crate::timer::*;
+use crate::utils::*;
+use libc::size_t;
+use std::cmp::Reverse;
+use std::collections::hash_map::RandomState;
+use std::collections::BinaryHeap;
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::c_void;
+use std::ffi::CStr;
+use std::hash::BuildHasher;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+/// Resolution of the delayed deliveries in milliseconds.
+pub const NETEM_TICK_INTERVAL: m_tmcnt_t = 1;
+
+/// Default number of delayed packets.
+pub const NETEM_DEFAULT_LIMIT: u_int = 1000;
+
+// Verdicts of the packet handler (same values as NETIO_FILTER_ACTION_*)
+pub const NETEM_ACTION_DROP: c_int = 0;
+pub const NETEM_ACTION_PASS: c_int = 1;
+pub const NETEM_ACTION_ALTER: c_int = 2;
+pub const NETEM_ACTION_DUPLICATE: c_int = 3;
+pub const NETEM_ACTION_QUEUED: c_int = 4;
+
+// Jitter distribution
+pub const NETEM_DIST_UNIFORM: c_int = 0;
+pub const NETEM_DIST_NORMAL: c_int = 1;
+
+/// Seedable pseudo random generator (splitmix64).
+#[derive(Debug, Clone)]
+pub struct NetemRng(u64);
+
+impl NetemRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut x = self.0;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+        x ^ (x >> 31)
+    }
+
+    /// Uniform value in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// True with probability `p`.
+    pub fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && self.next_f64() < p
+    }
+
+    /// Standard normal value (Box-Muller).
+    pub fn next_normal(&mut self) -> f64 {
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+/// Loss model, the probabilities are in [0, 1].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NetemLoss {
+    /// Independent losses.
+    Random(f64),
+    /// Gilbert-Elliott: `p` good to bad, `r` bad to good, and the loss
+    /// probabilities in the bad (1-h) and good (1-k) states.
+    GilbertElliott { p: f64, r: f64, loss_bad: f64, loss_good: f64 },
+}
+
+/// Configuration of the filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetemConfig {
+    /// Delay in milliseconds.
+    pub delay: u_int,
+    /// Jitter in milliseconds.
+    pub jitter: u_int,
+    pub dist: c_int,
+    pub loss: NetemLoss,
+    pub duplicate: f64,
+    pub corrupt: f64,
+    pub reorder: f64,
+    /// Maximum number of delayed packets, the packets beyond are dropped.
+    pub limit: u_int,
+    /// Packets impaired, all if not set.
+    pub filter: Option<PktFilter>,
+}
+
+impl Default for NetemConfig {
+    fn default() -> Self {
+        Self { delay: 0, jitter: 0, dist: NETEM_DIST_UNIFORM, loss: NetemLoss::Random(0.0), duplicate: 0.0, corrupt: 0.0, reorder: 0.0, limit: NETEM_DEFAULT_LIMIT, filter: None }
+    }
+}
+
+/// Counters of the filter.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct netem_stats {
+    pub pkts: m_uint64_t,
+    pub lost: m_uint64_t,
+    pub overlimit: m_uint64_t,
+    pub duplicated: m_uint64_t,
+    pub corrupted: m_uint64_t,
+    pub reordered: m_uint64_t,
+    pub delayed: m_uint64_t,
+}
+pub type netem_stats_t = netem_stats;
+
+/// Parse a percentage ("12.5" or "12.5%") into a probability.
+fn parse_percent(s: &str) -> Result<f64, String> {
+    match s.strip_suffix('%').unwrap_or(s).parse::<f64>() {
+        Ok(x) if (0.0..=100.0).contains(&x) => Ok(x / 100.0),
+        _ => Err(format!("invalid percentage '{}'", s)),
+    }
+}
+
+fn parse_num<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse::<T>().map_err(|_| format!("invalid number '{}'", s))
+}
+
+impl NetemConfig {
+    /// Apply setup arguments on top of the current configuration.
+    pub fn parse(&mut self, args: &[&str], seed: &mut Option<u64>) -> Result<(), String> {
+        let mut i = 0;
+        let arg = |i: usize| args.get(i).copied().ok_or_else(|| format!("missing value after '{}'", args[i - 1]));
+
+        while i < args.len() {
+            match args[i] {
+                "delay" => {
+                    self.delay = parse_num(arg(i + 1)?)?;
+                    self.jitter = 0;
+                    i += 2;
+                    if let Some(jitter) = args.get(i).and_then(|s| s.parse::<u_int>().ok()) {
+                        self.jitter = jitter;
+                        i += 1;
+                        match args.get(i).copied() {
+                            Some("uniform") => self.dist = NETEM_DIST_UNIFORM,
+                            Some("normal") => self.dist = NETEM_DIST_NORMAL,
+                            _ => continue,
+                        }
+                        i += 1;
+                    }
+                }
+                "loss" => match arg(i + 1)? {
+                    "random" => {
+                        self.loss = NetemLoss::Random(parse_percent(arg(i + 2)?)?);
+                        i += 3;
+                    }
+                    "gemodel" => {
+                        let p = parse_percent(arg(i + 2)?)?;
+                        i += 3;
+                        let mut opt = [1.0 - p, 1.0, 0.0];
+                        for val in opt.iter_mut() {
+                            match args.get(i).and_then(|s| parse_percent(s).ok()) {
+                                Some(x) => *val = x,
+                                None => break,
+                            }
+                            i += 1;
+                        }
+                        self.loss = NetemLoss::GilbertElliott { p, r: opt[0], loss_bad: opt[1], loss_good: opt[2] };
+                    }
+                    s => {
+                        self.loss = NetemLoss::Random(parse_percent(s)?);
+                        i += 2;
+                    }
+                },
+                "duplicate" => {
+                    self.duplicate = parse_percent(arg(i + 1)?)?;
+                    i += 2;
+                }
+                "corrupt" => {
+                    self.corrupt = parse_percent(arg(i + 1)?)?;
+                    i += 2;
+                }
+                "reorder" => {
+                    self.reorder = parse_percent(arg(i + 1)?)?;
+                    i += 2;
+                }
+                "limit" => {
+                    self.limit = parse_num(arg(i + 1)?)?;
+                    i += 2;
+                }
+                "seed" => {
+                    *seed = Some(parse_num(arg(i + 1)?)?);
+                    i += 2;
+                }
+                "match" => {
+                    self.filter = Some(PktFilter::compile(&args[i + 1..].join(" "))?);
+                    i = args.len();
+                }
+                "reset" => {
+                    *self = Self::default();
+                    i += 1;
+                }
+                s => return Err(format!("unknown parameter '{}'", s)),
+            }
+        }
+
+        if self.reorder > 0.0 && self.delay == 0 {
+            return Err("reorder needs a delay".to_string());
+        }
+        Ok(())
+    }
+
+    /// Check if some packets can be delayed.
+    pub fn has_delay(&self) -> bool {
+        self.delay != 0 || self.jitter != 0
+    }
+}
+
+/// Verdict on a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetemVerdict {
+    Drop,
+    Pass,
+    /// Passed, modified in place.
+    Alter,
+    /// Passed twice.
+    Duplicate,
+    /// Consumed, delivered later.
+    Delayed,
+}
+
+impl NetemVerdict {
+    /// NETEM_ACTION_* value of the verdict.
+    pub fn code(&self) -> c_int {
+        match self {
+            NetemVerdict::Drop => NETEM_ACTION_DROP,
+            NetemVerdict::Pass => NETEM_ACTION_PASS,
+            NetemVerdict::Alter => NETEM_ACTION_ALTER,
+            NetemVerdict::Duplicate => NETEM_ACTION_DUPLICATE,
+            NetemVerdict::Delayed => NETEM_ACTION_QUEUED,
+        }
+    }
+}
+
+/// Impairment state of a NIO direction.
+#[derive(Debug)]
+pub struct Netem {
+    config: NetemConfig,
+    rng: NetemRng,
+    /// Gilbert-Elliott state.
+    bad: bool,
+    queue: BinaryHeap<Reverse<(m_tmcnt_t, u64, Vec<u8>)>>,
+    seq: u64,
+    stats: netem_stats_t,
+}
+
+impl Netem {
+    pub fn new(seed: u64) -> Self {
+        Self { config: NetemConfig::default(), rng: NetemRng::new(seed), bad: false, queue: BinaryHeap::new(), seq: 0, stats: netem_stats_t::default() }
+    }
+
+    pub fn config(&self) -> &NetemConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> netem_stats_t {
+        self.stats
+    }
+
+    /// Number of delayed packets.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Apply setup arguments, the configuration is unchanged on error.
+    pub fn setup(&mut self, args: &[&str]) -> Result<(), String> {
+        let mut config = self.config.clone();
+        let mut seed = None;
+
+        config.parse(args, &mut seed)?;
+        self.config = config;
+        if let Some(seed) = seed {
+            self.rng = NetemRng::new(seed);
+            self.bad = false;
+        }
+        Ok(())
+    }
+
+    fn lost(&mut self) -> bool {
+        match self.config.loss {
+            NetemLoss::Random(p) => self.rng.chance(p),
+            NetemLoss::GilbertElliott { p, r, loss_bad, loss_good } => {
+                let flip = self.rng.chance(if self.bad { r } else { p });
+                if flip {
+                    self.bad = !self.bad;
+                }
+                self.rng.chance(if self.bad { loss_bad } else { loss_good })
+            }
+        }
+    }
+
+    fn sample_delay(&mut self) -> m_tmcnt_t {
+        let (delay, jitter) = (self.config.delay as f64, self.config.jitter as f64);
+        let d = match self.config.dist {
+            NETEM_DIST_NORMAL => delay + self.rng.next_normal() * jitter,
+            _ => delay + (self.rng.next_f64() * 2.0 - 1.0) * jitter,
+        };
+        d.max(0.0).round() as m_tmcnt_t
+    }
+
+    fn enqueue(&mut self, due: m_tmcnt_t, pkt: &[u8]) -> bool {
+        if self.queue.len() >= self.config.limit as usize {
+            self.stats.overlimit += 1;
+            return false;
+        }
+        self.seq += 1;
+        self.queue.push(Reverse((due, self.seq, pkt.to_vec())));
+        true
+    }
+
+    /// Process a packet at time `now` (ms).
+    pub fn handle(&mut self, pkt: &mut [u8], now: m_tmcnt_t) -> NetemVerdict {
+        if self.config.filter.as_ref().is_some_and(|f| !f.matches_frame(pkt)) {
+            return NetemVerdict::Pass;
+        }
+        self.stats.pkts += 1;
+
+        if self.lost() {
+            self.stats.lost += 1;
+            return NetemVerdict::Drop;
+        }
+
+        let dup = self.rng.chance(self.config.duplicate);
+        if dup {
+            self.stats.duplicated += 1;
+        }
+
+        let mut altered = false;
+        if !pkt.is_empty() && self.rng.chance(self.config.corrupt) {
+            let bit = self.rng.next_u64() % (pkt.len() as u64 * 8);
+            pkt[(bit / 8) as usize] ^= 1 << (bit % 8);
+            self.stats.corrupted += 1;
+            altered = true;
+        }
+
+        let immediate = if !self.config.has_delay() {
+            true
+        } else if self.rng.chance(self.config.reorder) {
+            self.stats.reordered += 1;
+            true
+        } else {
+            false
+        };
+
+        if immediate {
+            return match (dup, altered) {
+                (true, _) => NetemVerdict::Duplicate,
+                (false, true) => NetemVerdict::Alter,
+                (false, false) => NetemVerdict::Pass,
+            };
+        }
+
+        let due = now + self.sample_delay();
+        if !self.enqueue(due, pkt) {
+            return NetemVerdict::Drop;
+        }
+        if dup {
+            self.enqueue(due, pkt);
+        }
+        self.stats.delayed += 1;
+        NetemVerdict::Delayed
+    }
+
+    /// Take the delayed packets due at time `now`, in delivery order.
+    pub fn take_due(&mut self, now: m_tmcnt_t) -> Vec<Vec<u8>> {
+        let mut out = Vec::new();
+
+        while let Some(Reverse((due, _, _))) = self.queue.peek() {
+            if *due > now {
+                break;
+            }
+            let Reverse((_, _, pkt)) = self.queue.pop().unwrap();
+            out.push(pkt);
+        }
+        out
+    }
+}
+
+// Delivery of a delayed packet
+pub type netem_deliver_cbk_t = Option<unsafe extern "C" fn(pkt: *mut u_char, len: size_t, opt: *mut c_void)>;
+
+// Impairment filter with its delivery callback
+#[derive(Debug)]
+pub struct netem {
+    state: Mutex<Netem>,
+    timer: Mutex<timer_id>,
+    deliver: netem_deliver_cbk_t,
+    opt: *mut c_void,
+}
+pub type netem_t = netem;
+
+// the callback context is owned by the caller
+unsafe impl Send for netem {}
+unsafe impl Sync for netem {}
+
+impl netem {
+    fn lock(&self) -> MutexGuard<'_, Netem> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Deliver the delayed packets that are due
+unsafe extern "C" fn netem_timer(opt: *mut c_void, _timer: *mut timer_entry_t) -> c_int {
+    let n: *mut netem_t = opt.cast::<_>();
+    let due = (*n).lock().take_due(m_gettime());
+
+    if let Some(deliver) = (*n).deliver {
+        for mut pkt in due {
+            deliver(pkt.as_mut_ptr(), pkt.len(), (*n).opt);
+        }
+    }
+    TRUE
+}
+
+// Create an impairment filter (no impairment, random seed)
+#[no_mangle]
+pub unsafe extern "C" fn netem_create(deliver: netem_deliver_cbk_t, opt: *mut c_void) -> *mut netem_t {
+    let seed = RandomState::new().hash_one(m_gettime_usec());
+    Box::into_raw(Box::new(netem { state: Mutex::new(Netem::new(seed)), timer: Mutex::new(0), deliver, opt }))
+}
+
+// Free an impairment filter, the delayed packets are lost
+#[no_mangle]
+pub unsafe extern "C" fn netem_free(n: *mut netem_t) {
+    if !n.is_null() {
+        let timer = *(*n).timer.lock().unwrap_or_else(|e| e.into_inner());
+        if timer != 0 {
+            timer_remove(timer);
+        }
+        drop(Box::from_raw(n));
+    }
+}
+
+// Apply the setup arguments, returns -1 if they are invalid
+#[no_mangle]
+pub unsafe extern "C" fn netem_setup(n: *mut netem_t, argc: c_int, argv: *mut *mut c_char) -> c_int {
+    let mut args = Vec::new();
+    for i in 0..argc.max(0) as usize {
+        match CStr::from_ptr(*argv.add(i)).to_str() {
+            Ok(s) => args.push(s),
+            Err(_) => return -1,
+        }
+    }
+
+    let has_delay = {
+        let mut state = (*n).lock();
+        if let Err(msg) = state.setup(&args) {
+            let msg = std::ffi::CString::new(msg).unwrap_or_default();
+            libc::fprintf(c_stderr(), c"netem: %s\n".as_ptr(), msg.as_ptr());
+            return -1;
+        }
+        state.config().has_delay() || state.queued() != 0
+    };
+
+    // the delivery timer is created with the first delay
+    let mut timer = (*n).timer.lock().unwrap_or_else(|e| e.into_inner());
+    if has_delay && *timer == 0 {
+        *timer = timer_create_entry(NETEM_TICK_INTERVAL, FALSE, 10, Some(netem_timer), n.cast::<_>());
+        if *timer == 0 {
+            libc::fprintf(c_stderr(), c"netem_setup: unable to create delivery timer\n".as_ptr());
+            return -1;
+        }
+    }
+    0
+}
+
+// Packet handler, returns a NETEM_ACTION_* verdict (NETEM_ACTION_QUEUED for delayed packets)
+#[no_mangle]
+pub unsafe extern "C" fn netem_pkt_handler(n: *mut netem_t, pkt: *mut u_char, len: size_t) -> c_int {
+    let pkt = std::slice::from_raw_parts_mut(pkt, len);
+    (*n).lock().handle(pkt, m_gettime()).code()
+}
+
+// Get the counters of the filter
+#[no_mangle]
+pub unsafe extern "C" fn netem_get_stats(n: *mut netem_t, stats: *mut netem_stats_t) {
+    *stats = (*n).lock().stats();
+}
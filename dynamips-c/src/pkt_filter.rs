@@ -0,0 +1,523 @@
+//! Packet filter expressions.
+//!
+//! A small tcpdump-like language compiled to a matcher over the fields of a
+//! packet context (`n_pkt_ctx_t`):
+//!
+//! ```text
+//! expr  := and { ("or" | "||") and }
+//! and   := not { ("and" | "&&") not }
+//! not   := ("not" | "!") not | "(" expr ")" | test
+//! test  := "vlan" [<id>]
+//!        | "ether" ("proto" | "type") <ethertype>
+//!        | "ip" | "arp" | "ip6"
+//!        | "proto" <ip_proto>  |  <ip_proto name>
+//!        | ["src" | "dst"] "host" <ip>  |  ("src" | "dst") <ip>
+//!        | ["src" | "dst"] "net" <cidr>
+//!        | ["tcp" | "udp"] ["src" | "dst"] "port" <port>
+//!        | ["tcp" | "udp"] ["src" | "dst"] "portrange" <port>-<port>
+//!        | "tcpflags" <flags>[/<mask>]
+//!        | "greater" <len>  |  "less" <len>
+//! ```
+//!
+//! Without "src" or "dst", an address or a port matches either one. The IP
+//! protocol names are icmp, igmp, tcp, udp, gre, esp, ah, eigrp, ospf, pim
+//! and sctp. TCP flags are written like "syn+ack": "tcpflags syn" matches
+//! packets with SYN set, "tcpflags syn/syn+ack" packets with SYN set and ACK
+//! cleared.
+//!
+//! Examples: "ospf", "src host 10.0.0.3 and tcp port 179",
+//! "vlan 10 and not arp".
+
+use crate::_extra::*;
+use crate::dynamips_common::*;
+use crate::net::*;
+use libc::size_t;
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::CStr;
+use std::ptr::read_unaligned;
+
+/// Address or port selector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PktDir {
+    Src,
+    Dst,
+    /// Source or destination.
+    Any,
+}
+
+/// Elementary test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PktTest {
+    /// 802.1Q tagged, in the VLAN if specified.
+    Vlan(Option<m_uint16_t>),
+    EtherType(m_uint16_t),
+    IpProto(m_uint8_t),
+    Net {
+        dir: PktDir,
+        addr: n_ip_addr_t,
+        mask: n_ip_addr_t,
+    },
+    /// TCP or UDP port in [lo, hi], restricted to a protocol if specified.
+    Port {
+        dir: PktDir,
+        proto: Option<m_uint8_t>,
+        lo: m_uint16_t,
+        hi: m_uint16_t,
+    },
+    TcpFlags {
+        value: m_uint8_t,
+        mask: m_uint8_t,
+    },
+    /// Frame length >= len.
+    Greater(usize),
+    /// Frame length <= len.
+    Less(usize),
+}
+
+/// Compiled expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PktFilter {
+    Test(PktTest),
+    Not(Box<PktFilter>),
+    And(Box<PktFilter>, Box<PktFilter>),
+    Or(Box<PktFilter>, Box<PktFilter>),
+}
+
+/// Fields of a packet used by the tests.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PktFields {
+    pub len: usize,
+    pub vlan: Option<m_uint16_t>,
+    pub ethertype: Option<m_uint16_t>,
+    /// Source, destination and protocol of a valid IP header.
+    pub ip: Option<(n_ip_addr_t, n_ip_addr_t, m_uint8_t)>,
+    /// Ports of TCP and UDP packets (first fragment only).
+    pub ports: Option<(m_uint16_t, m_uint16_t)>,
+    pub tcp_flags: Option<m_uint8_t>,
+}
+
+fn be16(pkt: &[u8], off: usize) -> Option<m_uint16_t> {
+    Some(m_uint16_t::from_be_bytes(pkt.get(off..off + 2)?.try_into().ok()?))
+}
+
+impl PktFields {
+    /// Fields of an analyzed packet (see `pkt_ctx_analyze`).
+    ///
+    /// # Safety
+    /// `ctx.pkt` must be valid for `ctx.pkt_len` bytes and the header pointers
+    /// must point into the packet.
+    pub unsafe fn from_ctx(ctx: &n_pkt_ctx_t) -> Self {
+        let pkt = std::slice::from_raw_parts(ctx.pkt, ctx.pkt_len);
+        let mut f = Self { len: pkt.len(), ..Self::default() };
+
+        if (ctx.flags & N_PKT_CTX_FLAG_VLAN) != 0 {
+            f.vlan = Some(ctx.vlan_id & 0xfff);
+        }
+        if (ctx.flags & N_PKT_CTX_FLAG_ETHV2) != 0 {
+            f.ethertype = be16(pkt, if f.vlan.is_some() { N_ETH_HLEN + 2 } else { N_ETH_HLEN - 2 });
+        }
+        if (ctx.flags & (N_PKT_CTX_FLAG_L3_IP | N_PKT_CTX_FLAG_IPH_OK)) != (N_PKT_CTX_FLAG_L3_IP | N_PKT_CTX_FLAG_IPH_OK) {
+            return f;
+        }
+
+        let l3 = ctx.l3.ptr.cast::<u8>().offset_from(ctx.pkt) as usize;
+        if l3 + size_of::<n_ip_hdr_t>() > pkt.len() {
+            return f;
+        }
+        let ip: n_ip_hdr_t = read_unaligned(ctx.l3.ip);
+        f.ip = Some((ip.saddr.get(), ip.daddr.get(), ip.proto));
+
+        // only the first fragment has the L4 header
+        if (ip.frag_off.get() as u_int & N_IP_OFFMASK) != 0 {
+            return f;
+        }
+        let l4 = ctx.l4.ptr.cast::<u8>().offset_from(ctx.pkt) as usize;
+        match ip.proto as u_int {
+            N_IP_PROTO_TCP => {
+                if let (Some(sport), Some(dport), Some(&flags)) = (be16(pkt, l4), be16(pkt, l4 + 2), pkt.get(l4 + 13)) {
+                    f.ports = Some((sport, dport));
+                    f.tcp_flags = Some(flags);
+                }
+            }
+            N_IP_PROTO_UDP => {
+                if let (Some(sport), Some(dport)) = (be16(pkt, l4), be16(pkt, l4 + 2)) {
+                    f.ports = Some((sport, dport));
+                }
+            }
+            _ => {}
+        }
+        f
+    }
+
+    /// Fields of an Ethernet frame.
+    pub fn from_frame(pkt: &[u8]) -> Self {
+        if pkt.len() < N_ETH_HLEN {
+            return Self { len: pkt.len(), ..Self::default() };
+        }
+
+        // pkt_ctx_analyze() trusts the headers, give it room to read them
+        let mut buf = Vec::with_capacity(pkt.len() + 128);
+        buf.extend_from_slice(pkt);
+        buf.resize(pkt.len() + 128, 0);
+
+        unsafe {
+            let mut ctx: n_pkt_ctx_t = std::mem::zeroed();
+            pkt_ctx_analyze(&mut ctx, buf.as_mut_ptr(), pkt.len());
+            Self::from_ctx(&ctx)
+        }
+    }
+}
+
+fn dir_match<T: Copy>(dir: PktDir, (src, dst): (T, T), f: impl Fn(T) -> bool) -> bool {
+    match dir {
+        PktDir::Src => f(src),
+        PktDir::Dst => f(dst),
+        PktDir::Any => f(src) || f(dst),
+    }
+}
+
+impl PktTest {
+    fn matches(&self, f: &PktFields) -> bool {
+        match *self {
+            PktTest::Vlan(vlan) => f.vlan.is_some() && (vlan.is_none() || vlan == f.vlan),
+            PktTest::EtherType(ethertype) => f.ethertype == Some(ethertype),
+            PktTest::IpProto(proto) => matches!(f.ip, Some((_, _, p)) if p == proto),
+            PktTest::Net { dir, addr, mask } => match f.ip {
+                Some((src, dst, _)) => dir_match(dir, (src, dst), |a| (a & mask) == addr),
+                None => false,
+            },
+            PktTest::Port { dir, proto, lo, hi } => match (f.ip, f.ports) {
+                (Some((_, _, p)), Some(ports)) if proto.is_none() || proto == Some(p) => dir_match(dir, ports, |port| (lo..=hi).contains(&port)),
+                _ => false,
+            },
+            PktTest::TcpFlags { value, mask } => matches!(f.tcp_flags, Some(flags) if (flags & mask) == value),
+            PktTest::Greater(len) => f.len >= len,
+            PktTest::Less(len) => f.len <= len,
+        }
+    }
+}
+
+const IP_PROTO_NAMES: [(&str, u_int); 11] = [
+    ("icmp", N_IP_PROTO_ICMP),
+    ("igmp", N_IP_PROTO_IGMP),
+    ("tcp", N_IP_PROTO_TCP),
+    ("udp", N_IP_PROTO_UDP),
+    ("gre", N_IP_PROTO_GRE),
+    ("esp", N_IP_PROTO_ESP),
+    ("ah", N_IP_PROTO_AH),
+    ("eigrp", N_IP_PROTO_EIGRP),
+    ("ospf", N_IP_PROTO_OSPF),
+    ("pim", N_IP_PROTO_PIM),
+    ("sctp", N_IP_PROTO_SCTP),
+];
+
+const ETHERTYPE_NAMES: [(&str, m_uint16_t); 3] = [("ip", N_ETH_PROTO_IP), ("arp", N_ETH_PROTO_ARP), ("ip6", 0x86dd)];
+
+const TCP_FLAG_NAMES: [(&str, c_int); 6] = [("fin", N_TCP_FIN), ("syn", N_TCP_SYN), ("rst", N_TCP_RST), ("psh", N_TCP_PUSH), ("ack", N_TCP_ACK), ("urg", N_TCP_URG)];
+
+fn ip_proto(s: &str) -> Option<m_uint8_t> {
+    IP_PROTO_NAMES.iter().find(|(name, _)| *name == s).map(|&(_, p)| p as m_uint8_t)
+}
+
+/// Number in decimal or hexadecimal (0x prefix).
+fn parse_uint<T: TryFrom<u64>>(s: &str) -> Result<T, String> {
+    let val = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse::<u64>(),
+    };
+    val.ok().and_then(|x| T::try_from(x).ok()).ok_or_else(|| format!("invalid number '{}'", s))
+}
+
+fn parse_ip(s: &str) -> Result<n_ip_addr_t, String> {
+    s.parse::<std::net::Ipv4Addr>().map(|a| a.to_bits()).map_err(|_| format!("invalid IP address '{}'", s))
+}
+
+fn parse_cidr(s: &str) -> Result<(n_ip_addr_t, n_ip_addr_t), String> {
+    let err = || format!("invalid network '{}'", s);
+    let token = std::ffi::CString::new(s).map_err(|_| err())?;
+    let (mut addr, mut mask) = (0, 0);
+
+    if unsafe { ip_parse_cidr(token.as_ptr().cast_mut(), &mut addr, &mut mask) } == -1 {
+        return Err(err());
+    }
+    Ok((addr & mask, mask))
+}
+
+fn parse_tcp_flags(s: &str) -> Result<m_uint8_t, String> {
+    let mut flags = 0;
+    for name in s.split('+') {
+        match TCP_FLAG_NAMES.iter().find(|(n, _)| *n == name) {
+            Some(&(_, flag)) => flags |= flag as m_uint8_t,
+            None => return Err(format!("unknown TCP flag '{}'", name)),
+        }
+    }
+    Ok(flags)
+}
+
+/// Split an expression into words, parentheses and operators.
+fn tokenize(s: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (i, c) in s.char_indices() {
+        let op = match c {
+            '(' | ')' => Some(&s[i..i + 1]),
+            '!' if !s[i..].starts_with("!=") => Some(&s[i..i + 1]),
+            _ => None,
+        };
+        if c.is_whitespace() || op.is_some() {
+            if let Some(st) = start.take() {
+                tokens.push(&s[st..i]);
+            }
+            tokens.extend(op);
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(st) = start {
+        tokens.push(&s[st..]);
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, n: usize) -> Option<&'a str> {
+        self.tokens.get(self.pos + n).copied()
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let tok = self.peek();
+        self.pos += tok.is_some() as usize;
+        tok
+    }
+
+    fn value(&mut self, what: &str) -> Result<&'a str, String> {
+        self.next().ok_or_else(|| format!("missing {}", what))
+    }
+
+    fn or(&mut self) -> Result<PktFilter, String> {
+        let mut left = self.and()?;
+        while matches!(self.peek(), Some("or" | "||")) {
+            self.pos += 1;
+            left = PktFilter::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<PktFilter, String> {
+        let mut left = self.not()?;
+        while matches!(self.peek(), Some("and" | "&&")) {
+            self.pos += 1;
+            left = PktFilter::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<PktFilter, String> {
+        match self.peek() {
+            Some("not" | "!") => {
+                self.pos += 1;
+                Ok(PktFilter::Not(Box::new(self.not()?)))
+            }
+            Some("(") => {
+                self.pos += 1;
+                let expr = self.or()?;
+                match self.next() {
+                    Some(")") => Ok(expr),
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            _ => Ok(PktFilter::Test(self.test()?)),
+        }
+    }
+
+    fn port(&mut self, dir: PktDir, proto: Option<m_uint8_t>) -> Result<PktTest, String> {
+        let (lo, hi) = match self.next() {
+            Some("port") => {
+                let port = parse_uint(self.value("port")?)?;
+                (port, port)
+            }
+            Some("portrange") => {
+                let range = self.value("port range")?;
+                let (lo, hi) = range.split_once('-').ok_or_else(|| format!("invalid port range '{}'", range))?;
+                (parse_uint(lo)?, parse_uint(hi)?)
+            }
+            _ => unreachable!(),
+        };
+        Ok(PktTest::Port { dir, proto, lo, hi })
+    }
+
+    fn test(&mut self) -> Result<PktTest, String> {
+        let tok = self.value("test")?;
+
+        let dir = match tok {
+            "src" => PktDir::Src,
+            "dst" => PktDir::Dst,
+            _ => PktDir::Any,
+        };
+        let word = if dir == PktDir::Any { tok } else { self.value("address or port")? };
+
+        match word {
+            "host" => {
+                let addr = parse_ip(self.value("address")?)?;
+                return Ok(PktTest::Net { dir, addr, mask: 0xffff_ffff });
+            }
+            "net" => {
+                let (addr, mask) = parse_cidr(self.value("network")?)?;
+                return Ok(PktTest::Net { dir, addr, mask });
+            }
+            "port" | "portrange" => {
+                self.pos -= 1;
+                return self.port(dir, None);
+            }
+            _ if dir != PktDir::Any => {
+                let addr = parse_ip(word)?;
+                return Ok(PktTest::Net { dir, addr, mask: 0xffff_ffff });
+            }
+            _ => {}
+        }
+
+        match word {
+            "vlan" => match self.peek().map(parse_uint::<m_uint16_t>) {
+                Some(Ok(vlan)) => {
+                    self.pos += 1;
+                    Ok(PktTest::Vlan(Some(vlan)))
+                }
+                _ => Ok(PktTest::Vlan(None)),
+            },
+            "ether" => {
+                match self.next() {
+                    Some("proto" | "type") => {}
+                    _ => return Err("expected 'proto' after 'ether'".to_string()),
+                }
+                let val = self.value("ethertype")?;
+                match ETHERTYPE_NAMES.iter().find(|(name, _)| *name == val) {
+                    Some(&(_, ethertype)) => Ok(PktTest::EtherType(ethertype)),
+                    None => Ok(PktTest::EtherType(parse_uint(val)?)),
+                }
+            }
+            "proto" => {
+                let val = self.value("protocol")?;
+                match ip_proto(val) {
+                    Some(proto) => Ok(PktTest::IpProto(proto)),
+                    None => Ok(PktTest::IpProto(parse_uint(val)?)),
+                }
+            }
+            "tcpflags" => {
+                let val = self.value("TCP flags")?;
+                let (value, mask) = match val.split_once('/') {
+                    Some((value, mask)) => (parse_tcp_flags(value)?, parse_tcp_flags(mask)?),
+                    None => (parse_tcp_flags(val)?, parse_tcp_flags(val)?),
+                };
+                Ok(PktTest::TcpFlags { value: value & mask, mask })
+            }
+            "greater" => Ok(PktTest::Greater(parse_uint(self.value("length")?)?)),
+            "less" => Ok(PktTest::Less(parse_uint(self.value("length")?)?)),
+            "tcp" | "udp" => {
+                let proto = ip_proto(word);
+                match (self.peek(), self.peek_at(1)) {
+                    (Some("port" | "portrange"), _) => self.port(PktDir::Any, proto),
+                    (Some("src"), Some("port" | "portrange")) => {
+                        self.pos += 1;
+                        self.port(PktDir::Src, proto)
+                    }
+                    (Some("dst"), Some("port" | "portrange")) => {
+                        self.pos += 1;
+                        self.port(PktDir::Dst, proto)
+                    }
+                    _ => Ok(PktTest::IpProto(proto.unwrap())),
+                }
+            }
+            _ => {
+                if let Some(&(_, ethertype)) = ETHERTYPE_NAMES.iter().find(|(name, _)| *name == word) {
+                    return Ok(PktTest::EtherType(ethertype));
+                }
+                match ip_proto(word) {
+                    Some(proto) => Ok(PktTest::IpProto(proto)),
+                    None => Err(format!("unknown test '{}'", word)),
+                }
+            }
+        }
+    }
+}
+
+impl PktFilter {
+    /// Compile an expression.
+    pub fn compile(expr: &str) -> Result<Self, String> {
+        let mut parser = Parser { tokens: tokenize(expr), pos: 0 };
+
+        if parser.tokens.is_empty() {
+            return Err("empty expression".to_string());
+        }
+        let filter = parser.or()?;
+        match parser.peek() {
+            Some(tok) => Err(format!("unexpected '{}'", tok)),
+            None => Ok(filter),
+        }
+    }
+
+    /// Evaluate the expression on the fields of a packet.
+    pub fn matches(&self, f: &PktFields) -> bool {
+        match self {
+            PktFilter::Test(test) => test.matches(f),
+            PktFilter::Not(e) => !e.matches(f),
+            PktFilter::And(a, b) => a.matches(f) && b.matches(f),
+            PktFilter::Or(a, b) => a.matches(f) || b.matches(f),
+        }
+    }
+
+    /// Evaluate the expression on an Ethernet frame.
+    pub fn matches_frame(&self, pkt: &[u8]) -> bool {
+        self.matches(&PktFields::from_frame(pkt))
+    }
+}
+
+pub type pkt_filter_t = PktFilter;
+
+// Compile an expression made of the arguments joined with spaces, returns NULL on error
+#[no_mangle]
+pub unsafe extern "C" fn pkt_filter_compile(argc: c_int, argv: *mut *mut c_char) -> *mut pkt_filter_t {
+    let mut words = Vec::new();
+    for i in 0..argc.max(0) as usize {
+        words.push(CStr::from_ptr(*argv.add(i)).to_string_lossy());
+    }
+
+    match PktFilter::compile(&words.join(" ")) {
+        Ok(filter) => Box::into_raw(Box::new(filter)),
+        Err(msg) => {
+            let msg = std::ffi::CString::new(msg).unwrap_or_default();
+            libc::fprintf(c_stderr(), c"pkt_filter: %s\n".as_ptr(), msg.as_ptr());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+// Free a compiled expression
+#[no_mangle]
+pub unsafe extern "C" fn pkt_filter_free(f: *mut pkt_filter_t) {
+    if !f.is_null() {
+        drop(Box::from_raw(f));
+    }
+}
+
+// Returns TRUE if an analyzed packet matches the expression
+#[no_mangle]
+pub unsafe extern "C" fn pkt_filter_match(f: *mut pkt_filter_t, ctx: *mut n_pkt_ctx_t) -> c_int {
+    (*f).matches(&PktFields::from_ctx(&*ctx)) as c_int
+}
+
+// Returns TRUE if an Ethernet frame matches the expression
+#[no_mangle]
+pub unsafe extern "C" fn pkt_filter_match_pkt(f: *mut pkt_filter_t, pkt: *const u_char, len: size_t) -> c_int {
+    (*f).matches_frame(std::slice::from_raw_parts(pkt, len)) as c_int
+}
@@ -0,0 +1,1236 @@
+//! Rapid Spanning Tree Protocol (802.1D-2004) for the Ethernet switch.
+//!
+//! A single spanning tree for all the VLANs, with untagged BPDUs sent to the
+//! bridge group address. A port falls back to the legacy 802.1D protocol
+//! (configuration and TCN BPDUs, timer based transitions) when it receives a
+//! legacy BPDU, so the tree can include routers bridging with `bridge-group`
+//! and `bridge <n> protocol ieee`.
+//!
+//! The state machine does not send anything by itself: the switch feeds it
+//! the received BPDUs with `receive` and calls `tick` every second, the frames
+//! to transmit, the MAC address flushes and the port role/state changes are
+//! queued as `RstpOutput` and taken by the caller.
+//!
+//! Not implemented: shared media (all links are point-to-point), disputes,
+//! MSTP (MST BPDUs are handled as RST BPDUs, ie. only the CIST is used).
+
+use crate::_extra::*;
+use crate::dynamips_common::*;
+use crate::endian::*;
+use crate::net::*;
+use libc::size_t;
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::c_void;
+use std::ffi::CStr;
+use std::ptr::read_unaligned;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+/// Interval of the protocol tick in milliseconds.
+pub const RSTP_TICK_INTERVAL: m_tmcnt_t = 1000;
+
+// Default bridge and port parameters
+pub const RSTP_DEFAULT_BRIDGE_PRIORITY: u_int = 32768;
+pub const RSTP_DEFAULT_PORT_PRIORITY: u_int = 128;
+/// Short method path cost, as IOS for a FastEthernet port.
+pub const RSTP_DEFAULT_PATH_COST: u_int = 19;
+pub const RSTP_DEFAULT_HELLO_TIME: u_int = 2;
+pub const RSTP_DEFAULT_MAX_AGE: u_int = 20;
+pub const RSTP_DEFAULT_FWD_DELAY: u_int = 15;
+
+/// Maximum path cost of a port.
+pub const RSTP_MAX_PATH_COST: u_int = 200000000;
+
+/// Protocol migration and edge detection delay in seconds.
+const RSTP_MIGRATE_TIME: u16 = 3;
+
+/// Maximum number of BPDUs sent per second on a port.
+const RSTP_TX_HOLD_COUNT: u_int = 6;
+
+// Port roles
+pub const RSTP_ROLE_DISABLED: c_int = 0;
+pub const RSTP_ROLE_ROOT: c_int = 1;
+pub const RSTP_ROLE_DESIGNATED: c_int = 2;
+pub const RSTP_ROLE_ALTERNATE: c_int = 3;
+pub const RSTP_ROLE_BACKUP: c_int = 4;
+
+// Port states
+pub const RSTP_STATE_DISCARDING: c_int = 0;
+pub const RSTP_STATE_LEARNING: c_int = 1;
+pub const RSTP_STATE_FORWARDING: c_int = 2;
+
+// Edge port modes
+pub const RSTP_EDGE_NO: c_int = 0;
+pub const RSTP_EDGE_YES: c_int = 1;
+/// Edge port while no BPDU is received (default).
+pub const RSTP_EDGE_AUTO: c_int = 2;
+
+// BPDU types
+const BPDU_TYPE_CONFIG: m_uint8_t = 0x00;
+const BPDU_TYPE_RST: m_uint8_t = 0x02;
+const BPDU_TYPE_TCN: m_uint8_t = 0x80;
+
+// BPDU flags
+const BPDU_FLAG_TC: m_uint8_t = 0x01;
+const BPDU_FLAG_PROPOSAL: m_uint8_t = 0x02;
+const BPDU_FLAG_LEARNING: m_uint8_t = 0x10;
+const BPDU_FLAG_FORWARDING: m_uint8_t = 0x20;
+const BPDU_FLAG_AGREEMENT: m_uint8_t = 0x40;
+const BPDU_FLAG_TCA: m_uint8_t = 0x80;
+
+// Port role in the BPDU flags
+const BPDU_ROLE_SHIFT: u32 = 2;
+const BPDU_ROLE_MASK: m_uint8_t = 0x03;
+const BPDU_ROLE_ALTERNATE: m_uint8_t = 1;
+const BPDU_ROLE_ROOT: m_uint8_t = 2;
+const BPDU_ROLE_DESIGNATED: m_uint8_t = 3;
+
+/// Bridge group address.
+const BPDU_DADDR: EthAddr = EthAddr([0x01, 0x80, 0xc2, 0x00, 0x00, 0x00]);
+
+// Lengths of the BPDUs (without the LLC header)
+const BPDU_TCN_LEN: usize = 4;
+const BPDU_CONFIG_LEN: usize = 35;
+const BPDU_RST_LEN: usize = 36;
+
+// BPDU header
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct rstp_bpdu_hdr {
+    pub proto_id: Be16,
+    pub version: m_uint8_t,
+    pub r#type: m_uint8_t,
+}
+pub type rstp_bpdu_hdr_t = rstp_bpdu_hdr;
+
+// Configuration and RST BPDU body, times in 1/256 seconds
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct rstp_bpdu_cfg {
+    pub flags: m_uint8_t,
+    pub root_id: Be64,
+    pub root_path_cost: Be32,
+    pub bridge_id: Be64,
+    pub port_id: Be16,
+    pub msg_age: Be16,
+    pub max_age: Be16,
+    pub hello_time: Be16,
+    pub fwd_delay: Be16,
+}
+pub type rstp_bpdu_cfg_t = rstp_bpdu_cfg;
+
+const _: () = assert!(size_of::<rstp_bpdu_hdr_t>() + size_of::<rstp_bpdu_cfg_t>() == BPDU_CONFIG_LEN);
+
+/// Spanning tree priority vector, smaller is better.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PriorityVector {
+    pub root_id: u64,
+    pub root_cost: u32,
+    pub bridge_id: u64,
+    pub port_id: u16,
+}
+
+/// Protocol times in seconds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RstpTimes {
+    pub msg_age: u16,
+    pub max_age: u16,
+    pub hello: u16,
+    pub fwd_delay: u16,
+}
+
+impl Default for RstpTimes {
+    fn default() -> Self {
+        RstpTimes { msg_age: 0, max_age: RSTP_DEFAULT_MAX_AGE as u16, hello: RSTP_DEFAULT_HELLO_TIME as u16, fwd_delay: RSTP_DEFAULT_FWD_DELAY as u16 }
+    }
+}
+
+/// Decoded BPDU.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Bpdu {
+    /// Configuration BPDU (legacy) or RST BPDU.
+    Config { rst: bool, flags: m_uint8_t, vector: PriorityVector, times: RstpTimes },
+    /// Topology change notification (legacy).
+    Tcn,
+}
+
+impl Bpdu {
+    /// Decode a frame (Ethernet and LLC headers included), None if it is not a valid BPDU.
+    pub fn parse(frame: &[u8]) -> Option<Bpdu> {
+        let llc = N_ETH_HLEN;
+        let off = llc + size_of::<n_eth_llc_hdr_t>();
+
+        if frame.len() < off + BPDU_TCN_LEN {
+            return None;
+        }
+
+        unsafe {
+            if eth_llc_check_stp(frame.as_ptr().add(llc).cast_mut().cast::<_>()) == 0 {
+                return None;
+            }
+
+            let hdr: rstp_bpdu_hdr_t = read_unaligned(frame.as_ptr().add(off).cast::<_>());
+            if hdr.proto_id.get() != 0 {
+                return None;
+            }
+
+            let rst = match hdr.r#type {
+                BPDU_TYPE_TCN => return Some(Bpdu::Tcn),
+                BPDU_TYPE_CONFIG if frame.len() >= off + BPDU_CONFIG_LEN => false,
+                BPDU_TYPE_RST if hdr.version >= 2 && frame.len() >= off + BPDU_RST_LEN => true,
+                _ => return None,
+            };
+
+            let cfg: rstp_bpdu_cfg_t = read_unaligned(frame.as_ptr().add(off + size_of::<rstp_bpdu_hdr_t>()).cast::<_>());
+            let vector = PriorityVector { root_id: cfg.root_id.get(), root_cost: cfg.root_path_cost.get(), bridge_id: cfg.bridge_id.get(), port_id: cfg.port_id.get() };
+            let times = RstpTimes { msg_age: cfg.msg_age.get() / 256, max_age: cfg.max_age.get() / 256, hello: cfg.hello_time.get() / 256, fwd_delay: cfg.fwd_delay.get() / 256 };
+            Some(Bpdu::Config { rst, flags: cfg.flags, vector, times })
+        }
+    }
+
+    /// Encode the BPDU in a frame sent by `saddr`.
+    pub fn build(&self, saddr: &EthAddr) -> Vec<u8> {
+        let (version, r#type, len) = match self {
+            Bpdu::Tcn => (0, BPDU_TYPE_TCN, BPDU_TCN_LEN),
+            Bpdu::Config { rst: false, .. } => (0, BPDU_TYPE_CONFIG, BPDU_CONFIG_LEN),
+            Bpdu::Config { rst: true, .. } => (2, BPDU_TYPE_RST, BPDU_RST_LEN),
+        };
+        let llc_len = size_of::<n_eth_llc_hdr_t>() + len;
+        let off = N_ETH_HLEN + size_of::<n_eth_llc_hdr_t>();
+        let mut frame = vec![0; (N_ETH_HLEN + llc_len).max(N_ETH_MIN_FRAME_LEN)];
+        let mut buf = GuestBuf::new(&mut frame);
+
+        // 802.3 frame: the type field is the length
+        let eth = n_eth_hdr_t { daddr: BPDU_DADDR.into(), saddr: (*saddr).into(), r#type: Be16::new(llc_len as m_uint16_t) };
+        let llc = n_eth_llc_hdr_t { dsap: N_LLC_SAP_STP, ssap: N_LLC_SAP_STP, ctrl: N_LLC_CTRL_UI };
+        let hdr = rstp_bpdu_hdr_t { proto_id: Be16::new(0), version, r#type };
+
+        unsafe {
+            buf.write(0, eth);
+            buf.write(N_ETH_HLEN, llc);
+            buf.write(off, hdr);
+        }
+
+        // the version 1 length of the RST BPDUs stays 0
+        if let Bpdu::Config { flags, vector, times, .. } = *self {
+            let cfg = rstp_bpdu_cfg_t {
+                flags,
+                root_id: Be64::new(vector.root_id),
+                root_path_cost: Be32::new(vector.root_cost),
+                bridge_id: Be64::new(vector.bridge_id),
+                port_id: Be16::new(vector.port_id),
+                msg_age: Be16::new(times.msg_age << 8),
+                max_age: Be16::new(times.max_age << 8),
+                hello_time: Be16::new(times.hello << 8),
+                fwd_delay: Be16::new(times.fwd_delay << 8),
+            };
+            unsafe { buf.write(off + size_of::<rstp_bpdu_hdr_t>(), cfg) };
+        }
+        frame
+    }
+}
+
+/// Action requested from the switch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RstpOutput {
+    /// Send a frame on a port.
+    Tx(*mut c_void, Vec<u8>),
+    /// Flush the dynamic MAC addresses learned on a port.
+    Flush(*mut c_void),
+    /// New role and state of a port.
+    State(*mut c_void, c_int, c_int),
+}
+
+// Origin of the port priority vector
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum InfoIs {
+    Aged,
+    Mine,
+    Received,
+}
+
+#[derive(Debug)]
+struct Port {
+    handle: usize,
+    port_no: u16,
+    priority: u8,
+    path_cost: u32,
+    edge: c_int,
+    oper_edge: bool,
+    role: c_int,
+    state: c_int,
+    reported: (c_int, c_int),
+    info_is: InfoIs,
+    vector: PriorityVector,
+    times: RstpTimes,
+    /// RSTP peer (or no peer yet), legacy 802.1D otherwise.
+    send_rstp: bool,
+    proposing: bool,
+    agreed: bool,
+    /// Send an agreement.
+    agree: bool,
+    /// Send a topology change acknowledgment (legacy).
+    tc_ack: bool,
+    /// Send TCNs until acknowledged (legacy).
+    tcn_pending: bool,
+    new_info: bool,
+    rcvd_info_while: u16,
+    fd_while: u16,
+    hello_when: u16,
+    mdelay_while: u16,
+    edge_delay_while: u16,
+    tc_while: u16,
+    tx_count: u_int,
+    rx_bpdus: m_uint64_t,
+    tx_bpdus: m_uint64_t,
+}
+
+impl Port {
+    fn new(handle: usize, port_no: u16) -> Self {
+        Port {
+            handle,
+            port_no,
+            priority: RSTP_DEFAULT_PORT_PRIORITY as u8,
+            path_cost: RSTP_DEFAULT_PATH_COST,
+            edge: RSTP_EDGE_AUTO,
+            oper_edge: false,
+            role: RSTP_ROLE_DISABLED,
+            state: RSTP_STATE_FORWARDING,
+            reported: (RSTP_ROLE_DISABLED, RSTP_STATE_FORWARDING),
+            info_is: InfoIs::Aged,
+            vector: PriorityVector::default(),
+            times: RstpTimes::default(),
+            send_rstp: true,
+            proposing: false,
+            agreed: false,
+            agree: false,
+            tc_ack: false,
+            tcn_pending: false,
+            new_info: false,
+            rcvd_info_while: 0,
+            fd_while: 0,
+            hello_when: 0,
+            mdelay_while: 0,
+            edge_delay_while: 0,
+            tc_while: 0,
+            tx_count: 0,
+            rx_bpdus: 0,
+            tx_bpdus: 0,
+        }
+    }
+
+    /// Port identifier: 4 bits of priority and 12 bits of port number.
+    fn id(&self) -> u16 {
+        ((self.priority as u16 & 0xf0) << 8) | (self.port_no & 0x0fff)
+    }
+
+    // Start the protocol on the port
+    fn init(&mut self) {
+        self.state = RSTP_STATE_DISCARDING;
+        self.info_is = InfoIs::Aged;
+        self.oper_edge = self.edge == RSTP_EDGE_YES;
+        self.send_rstp = true;
+        self.proposing = false;
+        self.agreed = false;
+        self.agree = false;
+        self.tc_ack = false;
+        self.tcn_pending = false;
+        self.new_info = true;
+        self.hello_when = 0;
+        self.mdelay_while = RSTP_MIGRATE_TIME;
+        self.edge_delay_while = RSTP_MIGRATE_TIME;
+        self.tc_while = 0;
+    }
+}
+
+/// RSTP bridge.
+#[derive(Debug)]
+pub struct Rstp {
+    enabled: bool,
+    addr: EthAddr,
+    priority: u16,
+    times: RstpTimes,
+    /// Ports sorted by port number.
+    ports: Vec<Port>,
+    root: PriorityVector,
+    root_times: RstpTimes,
+    root_port: Option<usize>,
+    ticks: u64,
+    tc_count: u64,
+    tc_tick: u64,
+    out: Vec<RstpOutput>,
+}
+
+impl Rstp {
+    /// Bridge with the specified address, disabled.
+    pub fn new(addr: EthAddr) -> Self {
+        let mut rstp = Rstp {
+            enabled: false,
+            addr,
+            priority: RSTP_DEFAULT_BRIDGE_PRIORITY as u16,
+            times: RstpTimes::default(),
+            ports: Vec::new(),
+            root: PriorityVector::default(),
+            root_times: RstpTimes::default(),
+            root_port: None,
+            ticks: 0,
+            tc_count: 0,
+            tc_tick: 0,
+            out: Vec::new(),
+        };
+        rstp.root = rstp.own_vector();
+        rstp
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Bridge identifier: 16 bits of priority and the bridge address.
+    pub fn bridge_id(&self) -> u64 {
+        ((self.priority as u64) << 48) | self.addr_id()
+    }
+
+    fn addr_id(&self) -> u64 {
+        let a = self.addr.0;
+        u64::from_be_bytes([0, 0, a[0], a[1], a[2], a[3], a[4], a[5]])
+    }
+
+    fn own_vector(&self) -> PriorityVector {
+        PriorityVector { root_id: self.bridge_id(), root_cost: 0, bridge_id: self.bridge_id(), port_id: 0 }
+    }
+
+    pub fn priority(&self) -> u16 {
+        self.priority
+    }
+
+    /// Configured times.
+    pub fn times(&self) -> RstpTimes {
+        self.times
+    }
+
+    /// Root priority vector, the bridge is the root if `root_id` is its own identifier.
+    pub fn root(&self) -> PriorityVector {
+        self.root
+    }
+
+    /// Times used by the bridge (the ones of the root).
+    pub fn root_times(&self) -> RstpTimes {
+        self.root_times
+    }
+
+    /// Root port, None if the bridge is the root.
+    pub fn root_port(&self) -> Option<*mut c_void> {
+        self.root_port.map(|i| self.ports[i].handle as *mut c_void)
+    }
+
+    /// Number of topology changes and seconds since the last one.
+    pub fn topology_changes(&self) -> (u64, u64) {
+        (self.tc_count, self.ticks - self.tc_tick)
+    }
+
+    fn index(&self, port: *mut c_void) -> Option<usize> {
+        self.ports.iter().position(|p| p.handle == port as usize)
+    }
+
+    /// Take the pending actions.
+    pub fn take_output(&mut self) -> Vec<RstpOutput> {
+        std::mem::take(&mut self.out)
+    }
+
+    /// Start or stop the protocol, the ports forward everything when it is stopped.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if self.enabled == enabled {
+            return;
+        }
+
+        self.enabled = enabled;
+
+        if enabled {
+            for p in &mut self.ports {
+                p.init();
+                self.out.push(RstpOutput::Flush(p.handle as *mut c_void));
+            }
+            self.select_roles();
+            self.update();
+        } else {
+            for p in &mut self.ports {
+                p.role = RSTP_ROLE_DISABLED;
+                p.state = RSTP_STATE_FORWARDING;
+            }
+            self.root = self.own_vector();
+            self.root_port = None;
+            self.report();
+        }
+    }
+
+    /// Set the bridge priority.
+    pub fn set_priority(&mut self, priority: u16) {
+        self.priority = priority;
+        if self.enabled {
+            self.select_roles();
+            self.update();
+        } else {
+            self.root = self.own_vector();
+        }
+    }
+
+    /// Set the times of the bridge, used when it is the root. Returns false if they are inconsistent.
+    pub fn set_times(&mut self, hello: u16, max_age: u16, fwd_delay: u16) -> bool {
+        if !(1..=10).contains(&hello) || !(6..=40).contains(&max_age) || !(4..=30).contains(&fwd_delay) || max_age < 2 * (hello + 1) || max_age > 2 * (fwd_delay - 1) {
+            return false;
+        }
+
+        self.times = RstpTimes { msg_age: 0, max_age, hello, fwd_delay };
+        if self.enabled {
+            self.select_roles();
+            self.update();
+        }
+        true
+    }
+
+    /// Add a port, returns false if it already exists.
+    pub fn add_port(&mut self, port: *mut c_void, port_no: u16) -> bool {
+        if self.index(port).is_some() {
+            return false;
+        }
+
+        let mut p = Port::new(port as usize, port_no);
+        if self.enabled {
+            p.init();
+        }
+
+        let pos = self.ports.partition_point(|p| p.port_no <= port_no);
+        self.ports.insert(pos, p);
+        if self.enabled {
+            self.select_roles();
+            self.update();
+        }
+        true
+    }
+
+    /// Remove a port, returns false if it does not exist.
+    pub fn remove_port(&mut self, port: *mut c_void) -> bool {
+        let Some(i) = self.index(port) else {
+            return false;
+        };
+
+        self.ports.remove(i);
+        if self.enabled {
+            self.select_roles();
+            self.update();
+        }
+        true
+    }
+
+    /// Configure a port (priority 0-255, only the 4 high bits are used). Returns false if invalid.
+    pub fn set_port(&mut self, port: *mut c_void, priority: u_int, path_cost: u_int, edge: c_int) -> bool {
+        let Some(i) = self.index(port) else {
+            return false;
+        };
+
+        if priority > 255 || !(1..=RSTP_MAX_PATH_COST).contains(&path_cost) || !matches!(edge, RSTP_EDGE_NO | RSTP_EDGE_YES | RSTP_EDGE_AUTO) {
+            return false;
+        }
+
+        let p = &mut self.ports[i];
+        p.priority = priority as u8;
+        p.path_cost = path_cost;
+        if p.edge != edge {
+            p.edge = edge;
+            p.oper_edge = edge == RSTP_EDGE_YES;
+        }
+
+        if self.enabled {
+            self.select_roles();
+            self.update();
+        }
+        true
+    }
+
+    /// State of a port, forwarding if the protocol is stopped.
+    pub fn port_state(&self, port: *mut c_void) -> c_int {
+        match self.index(port) {
+            Some(i) if self.enabled => self.ports[i].state,
+            _ => RSTP_STATE_FORWARDING,
+        }
+    }
+
+    /// Process a frame sent to the bridge group address, returns false if it is not a BPDU.
+    pub fn receive(&mut self, port: *mut c_void, frame: &[u8]) -> bool {
+        let Some(i) = self.index(port) else {
+            return false;
+        };
+        let Some(bpdu) = Bpdu::parse(frame) else {
+            return false;
+        };
+
+        if !self.enabled {
+            return true;
+        }
+
+        let mut reselect = false;
+        let mut proposed = false;
+        let mut tc = false;
+
+        let p = &mut self.ports[i];
+        p.rx_bpdus += 1;
+        p.oper_edge = false;
+        p.edge_delay_while = RSTP_MIGRATE_TIME;
+
+        // protocol migration
+        let rst = matches!(bpdu, Bpdu::Config { rst: true, .. });
+        if p.mdelay_while == 0 && p.send_rstp != rst {
+            p.send_rstp = rst;
+            p.mdelay_while = RSTP_MIGRATE_TIME;
+            p.new_info = true;
+        }
+
+        match bpdu {
+            Bpdu::Tcn => {
+                if p.role == RSTP_ROLE_DESIGNATED {
+                    p.tc_ack = true;
+                    p.new_info = true;
+                    tc = true;
+                }
+            }
+            Bpdu::Config { rst, flags, vector, times } => {
+                // aged out on its way
+                if times.msg_age >= times.max_age {
+                    return true;
+                }
+
+                let role = if rst { (flags >> BPDU_ROLE_SHIFT) & BPDU_ROLE_MASK } else { BPDU_ROLE_DESIGNATED };
+
+                if role == BPDU_ROLE_DESIGNATED {
+                    let same = p.info_is == InfoIs::Received && p.vector.bridge_id == vector.bridge_id && p.vector.port_id == vector.port_id;
+
+                    if vector < p.vector || same {
+                        if p.info_is != InfoIs::Received || p.vector != vector || p.times != times {
+                            reselect = true;
+                        }
+                        p.info_is = InfoIs::Received;
+                        p.vector = vector;
+                        p.times = times;
+                        p.rcvd_info_while = 3 * times.hello.max(1);
+                        proposed = rst && (flags & BPDU_FLAG_PROPOSAL) != 0;
+                    } else if p.role == RSTP_ROLE_DESIGNATED {
+                        // inferior information, answer with ours
+                        p.new_info = true;
+                    }
+                } else if p.role == RSTP_ROLE_DESIGNATED && (flags & BPDU_FLAG_AGREEMENT) != 0 && vector.root_id == p.vector.root_id {
+                    p.agreed = true;
+                    p.proposing = false;
+                }
+
+                if (flags & BPDU_FLAG_TCA) != 0 {
+                    p.tcn_pending = false;
+                }
+                tc = (flags & BPDU_FLAG_TC) != 0;
+            }
+        }
+
+        if reselect {
+            self.select_roles();
+        }
+
+        let role = self.ports[i].role;
+        if tc && matches!(role, RSTP_ROLE_ROOT | RSTP_ROLE_DESIGNATED) {
+            self.rcvd_tc(i);
+        }
+
+        // proposal: put the designated ports in sync, then agree
+        if proposed {
+            if role == RSTP_ROLE_ROOT {
+                self.sync(Some(i));
+            }
+            if matches!(role, RSTP_ROLE_ROOT | RSTP_ROLE_ALTERNATE | RSTP_ROLE_BACKUP) {
+                self.ports[i].agree = true;
+                self.ports[i].new_info = true;
+            }
+        }
+
+        self.update();
+        true
+    }
+
+    /// One second tick.
+    pub fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        self.ticks += 1;
+
+        let hello = self.times.hello;
+        let mut reselect = false;
+
+        for i in 0..self.ports.len() {
+            let p = &mut self.ports[i];
+
+            p.tx_count = 0;
+            p.mdelay_while = p.mdelay_while.saturating_sub(1);
+            p.tc_while = p.tc_while.saturating_sub(1);
+
+            if p.info_is == InfoIs::Received {
+                p.rcvd_info_while = p.rcvd_info_while.saturating_sub(1);
+                if p.rcvd_info_while == 0 {
+                    p.info_is = InfoIs::Aged;
+                    reselect = true;
+                }
+            }
+
+            // no BPDU from a bridge: edge port
+            p.edge_delay_while = p.edge_delay_while.saturating_sub(1);
+            if p.edge_delay_while == 0 && p.edge == RSTP_EDGE_AUTO && p.role == RSTP_ROLE_DESIGNATED && p.send_rstp && p.proposing {
+                p.oper_edge = true;
+            }
+
+            if p.hello_when > 0 {
+                p.hello_when -= 1;
+            }
+            if p.hello_when == 0 {
+                p.hello_when = hello;
+                if p.role == RSTP_ROLE_DESIGNATED || (p.role == RSTP_ROLE_ROOT && (p.tcn_pending || (p.send_rstp && p.tc_while > 0))) {
+                    p.new_info = true;
+                }
+            }
+
+            // timer based transitions (legacy peer or no agreement)
+            if matches!(p.role, RSTP_ROLE_ROOT | RSTP_ROLE_DESIGNATED) && p.state != RSTP_STATE_FORWARDING {
+                p.fd_while = p.fd_while.saturating_sub(1);
+                if p.fd_while == 0 {
+                    let state = if p.state == RSTP_STATE_DISCARDING { RSTP_STATE_LEARNING } else { RSTP_STATE_FORWARDING };
+                    self.set_state(i, state);
+                }
+            }
+        }
+
+        if reselect {
+            self.select_roles();
+        }
+        self.update();
+    }
+
+    // Port role selection
+    fn select_roles(&mut self) {
+        let bid = self.bridge_id();
+        let own = self.addr_id();
+        let old_root = (self.root.root_id, self.root_port.map(|i| self.ports.get(i).map(|p| p.handle)));
+
+        let mut best = (self.own_vector(), 0);
+        let mut root_port = None;
+        for (i, p) in self.ports.iter().enumerate() {
+            // our own BPDUs looped back are not candidates
+            if p.info_is != InfoIs::Received || (p.vector.bridge_id & 0xffff_ffff_ffff) == own {
+                continue;
+            }
+            let v = PriorityVector { root_cost: p.vector.root_cost.saturating_add(p.path_cost), ..p.vector };
+            if (v, p.id()) < best {
+                best = (v, p.id());
+                root_port = Some(i);
+            }
+        }
+
+        self.root = best.0;
+        self.root_port = root_port;
+        self.root_times = match root_port {
+            Some(i) => RstpTimes { msg_age: self.ports[i].times.msg_age + 1, ..self.ports[i].times },
+            None => self.times,
+        };
+
+        for i in 0..self.ports.len() {
+            let p = &mut self.ports[i];
+            let designated = PriorityVector { root_id: self.root.root_id, root_cost: self.root.root_cost, bridge_id: bid, port_id: p.id() };
+
+            let role = if Some(i) == root_port {
+                RSTP_ROLE_ROOT
+            } else if p.info_is == InfoIs::Received && p.vector <= designated {
+                if (p.vector.bridge_id & 0xffff_ffff_ffff) == own {
+                    RSTP_ROLE_BACKUP
+                } else {
+                    RSTP_ROLE_ALTERNATE
+                }
+            } else {
+                RSTP_ROLE_DESIGNATED
+            };
+
+            if role == RSTP_ROLE_DESIGNATED {
+                if p.info_is != InfoIs::Mine || p.vector != designated {
+                    p.info_is = InfoIs::Mine;
+                    p.vector = designated;
+                    p.agreed = false;
+                    p.new_info = true;
+                }
+                if p.times != self.root_times {
+                    p.times = self.root_times;
+                    p.new_info = true;
+                }
+            }
+
+            if p.role != role {
+                p.role = role;
+                p.fd_while = self.root_times.fwd_delay;
+                p.proposing = false;
+                p.agreed = false;
+                p.agree = false;
+                p.tc_ack = false;
+                p.tcn_pending = false;
+                p.new_info = true;
+            }
+        }
+
+        // new root or root port: the designated ports must not forward before agreeing
+        let new_root = (self.root.root_id, self.root_port.map(|i| Some(self.ports[i].handle)));
+        if new_root != old_root {
+            self.sync(None);
+        }
+    }
+
+    // Put the designated ports that did not agree in the discarding state
+    fn sync(&mut self, except: Option<usize>) {
+        for i in 0..self.ports.len() {
+            let p = &self.ports[i];
+            if Some(i) != except && p.role == RSTP_ROLE_DESIGNATED && !p.oper_edge && !p.agreed {
+                self.set_state(i, RSTP_STATE_DISCARDING);
+            }
+        }
+    }
+
+    fn set_state(&mut self, i: usize, state: c_int) {
+        let fwd_delay = self.root_times.fwd_delay;
+        let p = &mut self.ports[i];
+
+        if p.state == state {
+            return;
+        }
+
+        let old = p.state;
+        p.state = state;
+        p.fd_while = fwd_delay;
+        p.new_info = true;
+
+        if old == RSTP_STATE_FORWARDING {
+            self.out.push(RstpOutput::Flush(p.handle as *mut c_void));
+        }
+        if state == RSTP_STATE_FORWARDING && !p.oper_edge {
+            self.topology_change(i, true);
+        }
+    }
+
+    // Topology change detected on a port (going forwarding) or received from it
+    fn topology_change(&mut self, i: usize, detected: bool) {
+        self.tc_count += 1;
+        self.tc_tick = self.ticks;
+
+        for j in 0..self.ports.len() {
+            let p = &mut self.ports[j];
+            if p.oper_edge || (j == i && !detected) {
+                continue;
+            }
+            if j != i {
+                self.out.push(RstpOutput::Flush(p.handle as *mut c_void));
+            }
+            if matches!(p.role, RSTP_ROLE_ROOT | RSTP_ROLE_DESIGNATED) && p.tc_while == 0 {
+                p.tc_while = if p.send_rstp { self.times.hello + 1 } else { self.root_times.max_age + self.root_times.fwd_delay };
+                p.tcn_pending = p.role == RSTP_ROLE_ROOT && !p.send_rstp;
+                p.new_info = true;
+            }
+        }
+    }
+
+    fn rcvd_tc(&mut self, i: usize) {
+        self.topology_change(i, false);
+    }
+
+    // Port states, transmission and events after a change
+    fn update(&mut self) {
+        for i in 0..self.ports.len() {
+            let p = &mut self.ports[i];
+            match p.role {
+                RSTP_ROLE_ROOT => {
+                    if p.send_rstp {
+                        self.set_state(i, RSTP_STATE_FORWARDING);
+                    }
+                }
+                RSTP_ROLE_DESIGNATED => {
+                    if p.oper_edge || p.agreed {
+                        self.set_state(i, RSTP_STATE_FORWARDING);
+                    } else if p.state != RSTP_STATE_FORWARDING && p.send_rstp && !p.proposing {
+                        p.proposing = true;
+                        p.edge_delay_while = p.edge_delay_while.max(RSTP_MIGRATE_TIME);
+                        p.new_info = true;
+                    }
+                }
+                _ => self.set_state(i, RSTP_STATE_DISCARDING),
+            }
+        }
+
+        for i in 0..self.ports.len() {
+            self.transmit(i);
+        }
+        self.report();
+    }
+
+    fn transmit(&mut self, i: usize) {
+        let bid = self.bridge_id();
+        let p = &mut self.ports[i];
+
+        if !p.new_info || p.tx_count >= RSTP_TX_HOLD_COUNT {
+            return;
+        }
+
+        let vector = PriorityVector { root_id: self.root.root_id, root_cost: self.root.root_cost, bridge_id: bid, port_id: p.id() };
+        let tc = if p.tc_while > 0 { BPDU_FLAG_TC } else { 0 };
+        let state = match p.state {
+            RSTP_STATE_FORWARDING => BPDU_FLAG_LEARNING | BPDU_FLAG_FORWARDING,
+            RSTP_STATE_LEARNING => BPDU_FLAG_LEARNING,
+            _ => 0,
+        };
+        let rst = |role: m_uint8_t, flags: m_uint8_t| Bpdu::Config { rst: true, flags: (role << BPDU_ROLE_SHIFT) | state | tc | flags, vector, times: self.root_times };
+
+        let bpdu = match p.role {
+            RSTP_ROLE_DESIGNATED if p.send_rstp => Some(rst(BPDU_ROLE_DESIGNATED, if p.proposing { BPDU_FLAG_PROPOSAL } else { 0 })),
+            RSTP_ROLE_DESIGNATED => {
+                let tca = if p.tc_ack { BPDU_FLAG_TCA } else { 0 };
+                Some(Bpdu::Config { rst: false, flags: tc | tca, vector, times: self.root_times })
+            }
+            RSTP_ROLE_ROOT if p.send_rstp && (p.agree || tc != 0) => Some(rst(BPDU_ROLE_ROOT, if p.agree { BPDU_FLAG_AGREEMENT } else { 0 })),
+            RSTP_ROLE_ROOT if p.tcn_pending => Some(Bpdu::Tcn),
+            RSTP_ROLE_ALTERNATE | RSTP_ROLE_BACKUP if p.send_rstp && p.agree => Some(rst(BPDU_ROLE_ALTERNATE, BPDU_FLAG_AGREEMENT)),
+            _ => None,
+        };
+
+        p.new_info = false;
+        if let Some(bpdu) = bpdu {
+            p.agree = false;
+            p.tc_ack = false;
+            p.tx_count += 1;
+            p.tx_bpdus += 1;
+            if p.role == RSTP_ROLE_DESIGNATED {
+                p.hello_when = self.times.hello;
+            }
+            self.out.push(RstpOutput::Tx(p.handle as *mut c_void, bpdu.build(&self.addr)));
+        }
+    }
+
+    // Queue the role/state changes
+    fn report(&mut self) {
+        for p in &mut self.ports {
+            if p.reported != (p.role, p.state) {
+                p.reported = (p.role, p.state);
+                self.out.push(RstpOutput::State(p.handle as *mut c_void, p.role, p.state));
+            }
+        }
+    }
+
+    /// Status of a port.
+    pub fn port_info(&self, port: *mut c_void) -> Option<rstp_port_info_t> {
+        let p = &self.ports[self.index(port)?];
+        Some(rstp_port_info_t {
+            port_no: p.port_no as u_int,
+            priority: p.priority as u_int,
+            path_cost: p.path_cost,
+            edge: p.edge,
+            oper_edge: p.oper_edge as c_int,
+            role: p.role,
+            state: if self.enabled { p.state } else { RSTP_STATE_FORWARDING },
+            rstp: p.send_rstp as c_int,
+            designated_root: p.vector.root_id,
+            designated_cost: p.vector.root_cost,
+            designated_bridge: p.vector.bridge_id,
+            designated_port: p.vector.port_id,
+            rx_bpdus: p.rx_bpdus,
+            tx_bpdus: p.tx_bpdus,
+        })
+    }
+
+    /// Status of the bridge.
+    pub fn bridge_info(&self) -> rstp_bridge_info_t {
+        let (tc_count, tc_last) = self.topology_changes();
+        rstp_bridge_info_t {
+            enabled: self.enabled as c_int,
+            priority: self.priority as u_int,
+            bridge_id: self.bridge_id(),
+            root_id: self.root.root_id,
+            root_path_cost: self.root.root_cost,
+            root_port: self.root_port.map_or(0, |i| self.ports[i].port_no as u_int),
+            hello_time: self.times.hello as u_int,
+            max_age: self.times.max_age as u_int,
+            fwd_delay: self.times.fwd_delay as u_int,
+            tc_count,
+            tc_last,
+        }
+    }
+}
+
+// Bridge status
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct rstp_bridge_info {
+    pub enabled: c_int,
+    pub priority: u_int,
+    pub bridge_id: m_uint64_t,
+    pub root_id: m_uint64_t,
+    pub root_path_cost: m_uint32_t,
+    /// Port number of the root port, 0 if the bridge is the root.
+    pub root_port: u_int,
+    /// Configured times (seconds).
+    pub hello_time: u_int,
+    pub max_age: u_int,
+    pub fwd_delay: u_int,
+    /// Number of topology changes.
+    pub tc_count: m_uint64_t,
+    /// Seconds since the last topology change.
+    pub tc_last: m_uint64_t,
+}
+pub type rstp_bridge_info_t = rstp_bridge_info;
+
+// Port status
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct rstp_port_info {
+    pub port_no: u_int,
+    pub priority: u_int,
+    pub path_cost: u_int,
+    /// RSTP_EDGE_*
+    pub edge: c_int,
+    pub oper_edge: c_int,
+    /// RSTP_ROLE_*
+    pub role: c_int,
+    /// RSTP_STATE_*
+    pub state: c_int,
+    /// RSTP peer, legacy 802.1D peer otherwise.
+    pub rstp: c_int,
+    /// Port priority vector.
+    pub designated_root: m_uint64_t,
+    pub designated_cost: m_uint32_t,
+    pub designated_bridge: m_uint64_t,
+    pub designated_port: m_uint16_t,
+    pub rx_bpdus: m_uint64_t,
+    pub tx_bpdus: m_uint64_t,
+}
+pub type rstp_port_info_t = rstp_port_info;
+
+// Transmit a frame on a port
+pub type rstp_tx_cbk_t = Option<unsafe extern "C" fn(port: *mut c_void, pkt: *mut u_char, len: size_t, opt: *mut c_void)>;
+
+// Flush the dynamic MAC addresses of a port
+pub type rstp_flush_cbk_t = Option<unsafe extern "C" fn(port: *mut c_void, opt: *mut c_void)>;
+
+// New role and state of a port
+pub type rstp_state_cbk_t = Option<unsafe extern "C" fn(port: *mut c_void, role: c_int, state: c_int, opt: *mut c_void)>;
+
+// RSTP bridge with the callbacks of its switch
+#[derive(Debug)]
+pub struct rstp {
+    bridge: Mutex<Rstp>,
+    tx: rstp_tx_cbk_t,
+    flush: rstp_flush_cbk_t,
+    state: rstp_state_cbk_t,
+    opt: *mut c_void,
+}
+pub type rstp_t = rstp;
+
+// the ports and the callback context are owned by the caller
+unsafe impl Send for rstp {}
+unsafe impl Sync for rstp {}
+
+impl rstp {
+    fn lock(&self) -> MutexGuard<'_, Rstp> {
+        self.bridge.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Run an operation, then carry out its output without the lock held
+    unsafe fn run<R>(&self, f: impl FnOnce(&mut Rstp) -> R) -> R {
+        let (res, out) = {
+            let mut bridge = self.lock();
+            let res = f(&mut bridge);
+            (res, bridge.take_output())
+        };
+
+        for o in out {
+            match o {
+                RstpOutput::Tx(port, mut frame) => {
+                    if let Some(tx) = self.tx {
+                        tx(port, frame.as_mut_ptr(), frame.len(), self.opt);
+                    }
+                }
+                RstpOutput::Flush(port) => {
+                    if let Some(flush) = self.flush {
+                        flush(port, self.opt);
+                    }
+                }
+                RstpOutput::State(port, role, state) => {
+                    if let Some(cbk) = self.state {
+                        cbk(port, role, state, self.opt);
+                    }
+                }
+            }
+        }
+        res
+    }
+}
+
+// Create a RSTP bridge (disabled), the callbacks are called synchronously by the other functions
+#[no_mangle]
+pub unsafe extern "C" fn rstp_create(addr: *const n_eth_addr_t, tx: rstp_tx_cbk_t, flush: rstp_flush_cbk_t, state: rstp_state_cbk_t, opt: *mut c_void) -> *mut rstp_t {
+    let bridge = Mutex::new(Rstp::new(EthAddr::from(*addr)));
+    Box::into_raw(Box::new(rstp { bridge, tx, flush, state, opt }))
+}
+
+// Free a RSTP bridge
+#[no_mangle]
+pub unsafe extern "C" fn rstp_free(r: *mut rstp_t) {
+    if !r.is_null() {
+        drop(Box::from_raw(r));
+    }
+}
+
+// Start or stop the protocol
+#[no_mangle]
+pub unsafe extern "C" fn rstp_set_enabled(r: *mut rstp_t, enabled: c_int) {
+    (*r).run(|b| b.set_enabled(enabled != 0));
+}
+
+// Returns TRUE if the protocol is running
+#[no_mangle]
+pub unsafe extern "C" fn rstp_is_enabled(r: *mut rstp_t) -> c_int {
+    (*r).lock().enabled() as c_int
+}
+
+// Set the bridge priority (0-65535)
+#[no_mangle]
+pub unsafe extern "C" fn rstp_set_bridge_priority(r: *mut rstp_t, priority: u_int) -> c_int {
+    if priority > 0xffff {
+        return -1;
+    }
+    (*r).run(|b| b.set_priority(priority as u16));
+    0
+}
+
+// Set the bridge times in seconds, returns -1 if they are inconsistent
+#[no_mangle]
+pub unsafe extern "C" fn rstp_set_times(r: *mut rstp_t, hello_time: u_int, max_age: u_int, fwd_delay: u_int) -> c_int {
+    if hello_time > 0xffff || max_age > 0xffff || fwd_delay > 0xffff || !(*r).run(|b| b.set_times(hello_time as u16, max_age as u16, fwd_delay as u16)) {
+        return -1;
+    }
+    0
+}
+
+// Add a port (port number 1-4095)
+#[no_mangle]
+pub unsafe extern "C" fn rstp_add_port(r: *mut rstp_t, port: *mut c_void, port_no: u_int) -> c_int {
+    if !(1..=0xfff).contains(&port_no) || !(*r).run(|b| b.add_port(port, port_no as u16)) {
+        return -1;
+    }
+    0
+}
+
+// Remove a port
+#[no_mangle]
+pub unsafe extern "C" fn rstp_remove_port(r: *mut rstp_t, port: *mut c_void) -> c_int {
+    if (*r).run(|b| b.remove_port(port)) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Configure a port: priority (0-255), path cost and edge mode (RSTP_EDGE_*)
+#[no_mangle]
+pub unsafe extern "C" fn rstp_set_port(r: *mut rstp_t, port: *mut c_void, priority: u_int, path_cost: u_int, edge: c_int) -> c_int {
+    if (*r).run(|b| b.set_port(port, priority, path_cost, edge)) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Process a frame sent to the bridge group address, returns -1 if it is not a BPDU
+#[no_mangle]
+pub unsafe extern "C" fn rstp_receive(r: *mut rstp_t, port: *mut c_void, pkt: *const u_char, len: size_t) -> c_int {
+    let frame = std::slice::from_raw_parts(pkt, len);
+    if (*r).run(|b| b.receive(port, frame)) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Protocol tick, to call every RSTP_TICK_INTERVAL ms
+#[no_mangle]
+pub unsafe extern "C" fn rstp_tick(r: *mut rstp_t) {
+    (*r).run(|b| b.tick());
+}
+
+// State of a port (RSTP_STATE_*), forwarding if the protocol is stopped
+#[no_mangle]
+pub unsafe extern "C" fn rstp_port_state(r: *mut rstp_t, port: *mut c_void) -> c_int {
+    (*r).lock().port_state(port)
+}
+
+// Get the bridge status
+#[no_mangle]
+pub unsafe extern "C" fn rstp_get_bridge_info(r: *mut rstp_t, info: *mut rstp_bridge_info_t) {
+    *info = (*r).lock().bridge_info();
+}
+
+// Get the status of a port, returns -1 if unknown
+#[no_mangle]
+pub unsafe extern "C" fn rstp_get_port_info(r: *mut rstp_t, port: *mut c_void, info: *mut rstp_port_info_t) -> c_int {
+    match (*r).lock().port_info(port) {
+        Some(i) => {
+            *info = i;
+            0
+        }
+        None => -1,
+    }
+}
+
+// Name of a port role
+#[no_mangle]
+pub extern "C" fn rstp_role_str(role: c_int) -> *const c_char {
+    match role {
+        RSTP_ROLE_DISABLED => c"disabled".as_ptr(),
+        RSTP_ROLE_ROOT => c"root".as_ptr(),
+        RSTP_ROLE_DESIGNATED => c"designated".as_ptr(),
+        RSTP_ROLE_ALTERNATE => c"alternate".as_ptr(),
+        RSTP_ROLE_BACKUP => c"backup".as_ptr(),
+        _ => c"unknown".as_ptr(),
+    }
+}
+
+// Name of a port state
+#[no_mangle]
+pub extern "C" fn rstp_state_str(state: c_int) -> *const c_char {
+    match state {
+        RSTP_STATE_DISCARDING => c"discarding".as_ptr(),
+        RSTP_STATE_LEARNING => c"learning".as_ptr(),
+        RSTP_STATE_FORWARDING => c"forwarding".as_ptr(),
+        _ => c"unknown".as_ptr(),
+    }
+}
+
+// Name of an edge mode
+#[no_mangle]
+pub extern "C" fn rstp_edge_str(edge: c_int) -> *const c_char {
+    match edge {
+        RSTP_EDGE_NO => c"no".as_ptr(),
+        RSTP_EDGE_YES => c"yes".as_ptr(),
+        RSTP_EDGE_AUTO => c"auto".as_ptr(),
+        _ => c"unknown".as_ptr(),
+    }
+}
+
+// Parse an edge mode name, returns -1 if unknown
+#[no_mangle]
+pub unsafe extern "C" fn rstp_edge_parse(s: *const c_char) -> c_int {
+    match CStr::from_ptr(s).to_bytes() {
+        b"no" => RSTP_EDGE_NO,
+        b"yes" => RSTP_EDGE_YES,
+        b"auto" => RSTP_EDGE_AUTO,
+        _ => -1,
+    }
+}
@@ -0,0 +1,429 @@
+//! Port mirroring (SPAN/RSPAN) for the Ethernet switch.
+//!
+//! A session copies the frames received (rx) and/or sent (tx) on a set of
+//! source ports, or switched in a set of source VLANs, to a destination
+//! port. With a RSPAN VLAN, the copies get an additional 802.1Q tag so they
+//! can be carried over a trunk to a remote switch.
+//!
+//! A frame is copied at most once per session, even if it matches several
+//! sources (ie. rx on a port and tx on another one): the switch keeps a mask
+//! of the sessions that already got the frame.
+//!
+//! The destination port of a session is dedicated to it: the switch doesn't
+//! forward frames to it and drops the frames it receives.
+
+use crate::_extra::*;
+use crate::dynamips_common::*;
+use crate::net::*;
+use crate::vlan::*;
+use libc::size_t;
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::c_void;
+use std::ffi::CStr;
+use std::ptr::null_mut;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+/// Number of sessions of a switch, sessions are numbered from 1.
+pub const SPAN_MAX_SESSIONS: u_int = 16;
+
+// Mirrored direction of a source
+pub const SPAN_DIR_RX: c_int = 1;
+pub const SPAN_DIR_TX: c_int = 2;
+pub const SPAN_DIR_BOTH: c_int = 3;
+
+/// Mirroring session.
+#[derive(Debug, Default, Clone)]
+pub struct SpanSession {
+    /// Destination port.
+    pub dst: Option<*mut c_void>,
+    /// VLAN of the copies (0 = copies sent as they are).
+    pub rspan_vlan: u_int,
+    /// Source ports with their direction.
+    pub ports: Vec<(*mut c_void, c_int)>,
+    /// Source VLANs with their direction.
+    pub vlans: Vec<(u_int, c_int)>,
+    pub pkts: m_uint64_t,
+    pub bytes: m_uint64_t,
+}
+
+impl SpanSession {
+    /// Check if a frame of `vlan` going in direction `dir` on `port` matches a source.
+    fn is_source(&self, port: *mut c_void, vlan: u_int, dir: c_int) -> bool {
+        self.ports.iter().any(|&(p, d)| p == port && (d & dir) != 0) || self.vlans.iter().any(|&(v, d)| v == vlan && (d & dir) != 0)
+    }
+}
+
+/// Mirroring sessions of a switch.
+#[derive(Debug)]
+pub struct SpanTable {
+    sessions: Vec<Option<SpanSession>>,
+}
+
+impl Default for SpanTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpanTable {
+    pub fn new() -> Self {
+        Self { sessions: vec![None; SPAN_MAX_SESSIONS as usize] }
+    }
+
+    fn slot(&mut self, id: u_int) -> Option<&mut Option<SpanSession>> {
+        if id == 0 {
+            return None;
+        }
+        self.sessions.get_mut(id as usize - 1)
+    }
+
+    /// Session `id`, if configured.
+    pub fn session(&self, id: u_int) -> Option<&SpanSession> {
+        self.sessions.get((id as usize).wrapping_sub(1))?.as_ref()
+    }
+
+    /// Configured sessions with their ID.
+    pub fn sessions(&self) -> impl Iterator<Item = (u_int, &SpanSession)> {
+        self.sessions.iter().enumerate().filter_map(|(i, s)| Some((i as u_int + 1, s.as_ref()?)))
+    }
+
+    /// Check if a port is the destination of a session.
+    pub fn is_destination(&self, port: *mut c_void) -> bool {
+        self.sessions().any(|(_, s)| s.dst == Some(port))
+    }
+
+    fn is_source_port(&self, port: *mut c_void) -> bool {
+        self.sessions().any(|(_, s)| s.ports.iter().any(|&(p, _)| p == port))
+    }
+
+    /// Set the destination of a session (created if needed).
+    /// Returns false if the port is a source or the destination of another session.
+    pub fn set_destination(&mut self, id: u_int, port: *mut c_void, rspan_vlan: u_int) -> bool {
+        if rspan_vlan > 4095 || self.is_source_port(port) || self.sessions().any(|(i, s)| i != id && s.dst == Some(port)) {
+            return false;
+        }
+        let Some(slot) = self.slot(id) else {
+            return false;
+        };
+        let s = slot.get_or_insert_with(SpanSession::default);
+        s.dst = Some(port);
+        s.rspan_vlan = rspan_vlan;
+        true
+    }
+
+    /// Add a source port to a session (created if needed), or change its direction.
+    /// Returns false if the port is the destination of a session.
+    pub fn add_port(&mut self, id: u_int, port: *mut c_void, dir: c_int) -> bool {
+        if !(SPAN_DIR_RX..=SPAN_DIR_BOTH).contains(&dir) || self.is_destination(port) {
+            return false;
+        }
+        let Some(slot) = self.slot(id) else {
+            return false;
+        };
+        let s = slot.get_or_insert_with(SpanSession::default);
+        match s.ports.iter_mut().find(|(p, _)| *p == port) {
+            Some(src) => src.1 = dir,
+            None => s.ports.push((port, dir)),
+        }
+        true
+    }
+
+    /// Add a source VLAN to a session (created if needed), or change its direction.
+    pub fn add_vlan(&mut self, id: u_int, vlan: u_int, dir: c_int) -> bool {
+        if !(SPAN_DIR_RX..=SPAN_DIR_BOTH).contains(&dir) || !(1..=4095).contains(&vlan) {
+            return false;
+        }
+        let Some(slot) = self.slot(id) else {
+            return false;
+        };
+        let s = slot.get_or_insert_with(SpanSession::default);
+        match s.vlans.iter_mut().find(|(v, _)| *v == vlan) {
+            Some(src) => src.1 = dir,
+            None => s.vlans.push((vlan, dir)),
+        }
+        true
+    }
+
+    /// Remove a source port from a session.
+    pub fn remove_port(&mut self, id: u_int, port: *mut c_void) -> bool {
+        let Some(Some(s)) = self.slot(id) else {
+            return false;
+        };
+        let count = s.ports.len();
+        s.ports.retain(|&(p, _)| p != port);
+        s.ports.len() != count
+    }
+
+    /// Remove a source VLAN from a session.
+    pub fn remove_vlan(&mut self, id: u_int, vlan: u_int) -> bool {
+        let Some(Some(s)) = self.slot(id) else {
+            return false;
+        };
+        let count = s.vlans.len();
+        s.vlans.retain(|&(v, _)| v != vlan);
+        s.vlans.len() != count
+    }
+
+    /// Delete a session.
+    pub fn delete(&mut self, id: u_int) -> bool {
+        self.slot(id).and_then(Option::take).is_some()
+    }
+
+    /// Forget a port removed from the switch (as a source and as a destination).
+    pub fn forget_port(&mut self, port: *mut c_void) {
+        for s in self.sessions.iter_mut().flatten() {
+            s.ports.retain(|&(p, _)| p != port);
+            if s.dst == Some(port) {
+                s.dst = None;
+            }
+        }
+    }
+
+    /// Sessions (destination, RSPAN VLAN) that get a copy of a frame of `len`
+    /// bytes in `vlan` going in direction `dir` on `port`.
+    ///
+    /// `mask` holds the sessions that already got the frame, it is updated.
+    pub fn mirror(&mut self, port: *mut c_void, vlan: u_int, dir: c_int, len: usize, mask: &mut m_uint32_t) -> Vec<(*mut c_void, u_int)> {
+        let mut out = Vec::new();
+
+        for (i, s) in self.sessions.iter_mut().enumerate() {
+            let Some(s) = s else {
+                continue;
+            };
+            let Some(dst) = s.dst else {
+                continue;
+            };
+            if (*mask & (1 << i)) != 0 || dst == port || !s.is_source(port, vlan, dir) {
+                continue;
+            }
+            *mask |= 1 << i;
+            s.pkts += 1;
+            s.bytes += len as m_uint64_t;
+            out.push((dst, s.rspan_vlan));
+        }
+        out
+    }
+}
+
+/// Session status.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct span_session_info {
+    pub id: u_int,
+    /// Destination port, NULL if not set.
+    pub dst: *mut c_void,
+    pub rspan_vlan: u_int,
+    pub src_ports: u_int,
+    pub src_vlans: u_int,
+    pub pkts: m_uint64_t,
+    pub bytes: m_uint64_t,
+}
+pub type span_session_info_t = span_session_info;
+
+impl span_session_info {
+    fn new(id: u_int, s: &SpanSession) -> Self {
+        Self { id, dst: s.dst.unwrap_or(null_mut()), rspan_vlan: s.rspan_vlan, src_ports: s.ports.len() as u_int, src_vlans: s.vlans.len() as u_int, pkts: s.pkts, bytes: s.bytes }
+    }
+}
+
+// Send a copy of a frame on a destination port
+pub type span_tx_cbk_t = Option<unsafe extern "C" fn(port: *mut c_void, pkt: *mut u_char, len: size_t, opt: *mut c_void)>;
+
+// "foreach" callback for sessions
+pub type span_session_cbk_t = Option<unsafe extern "C" fn(info: *const span_session_info_t, opt: *mut c_void)>;
+
+// "foreach" callback for the sources of a session (port is NULL for a VLAN)
+pub type span_source_cbk_t = Option<unsafe extern "C" fn(port: *mut c_void, vlan: u_int, dir: c_int, opt: *mut c_void)>;
+
+// SPAN sessions with the transmit callback of their switch
+#[derive(Debug)]
+pub struct span {
+    table: Mutex<SpanTable>,
+    tx: span_tx_cbk_t,
+    opt: *mut c_void,
+}
+pub type span_t = span;
+
+// the ports and the callback context are owned by the caller
+unsafe impl Send for span {}
+unsafe impl Sync for span {}
+
+impl span {
+    fn lock(&self) -> MutexGuard<'_, SpanTable> {
+        self.table.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Create an empty session table
+#[no_mangle]
+pub unsafe extern "C" fn span_create(tx: span_tx_cbk_t, opt: *mut c_void) -> *mut span_t {
+    Box::into_raw(Box::new(span { table: Mutex::new(SpanTable::new()), tx, opt }))
+}
+
+// Free a session table
+#[no_mangle]
+pub unsafe extern "C" fn span_free(s: *mut span_t) {
+    if !s.is_null() {
+        drop(Box::from_raw(s));
+    }
+}
+
+// Set the destination port of a session, with a RSPAN VLAN (0 = none)
+#[no_mangle]
+pub unsafe extern "C" fn span_set_destination(s: *mut span_t, id: u_int, port: *mut c_void, rspan_vlan: u_int) -> c_int {
+    if (*s).lock().set_destination(id, port, rspan_vlan) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Add a source port to a session (direction: SPAN_DIR_*)
+#[no_mangle]
+pub unsafe extern "C" fn span_add_source_port(s: *mut span_t, id: u_int, port: *mut c_void, dir: c_int) -> c_int {
+    if (*s).lock().add_port(id, port, dir) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Remove a source port from a session
+#[no_mangle]
+pub unsafe extern "C" fn span_remove_source_port(s: *mut span_t, id: u_int, port: *mut c_void) -> c_int {
+    if (*s).lock().remove_port(id, port) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Add a source VLAN to a session (direction: SPAN_DIR_*)
+#[no_mangle]
+pub unsafe extern "C" fn span_add_source_vlan(s: *mut span_t, id: u_int, vlan: u_int, dir: c_int) -> c_int {
+    if (*s).lock().add_vlan(id, vlan, dir) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Remove a source VLAN from a session
+#[no_mangle]
+pub unsafe extern "C" fn span_remove_source_vlan(s: *mut span_t, id: u_int, vlan: u_int) -> c_int {
+    if (*s).lock().remove_vlan(id, vlan) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Delete a session
+#[no_mangle]
+pub unsafe extern "C" fn span_delete_session(s: *mut span_t, id: u_int) -> c_int {
+    if (*s).lock().delete(id) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Remove a port from all the sessions (port removed from the switch)
+#[no_mangle]
+pub unsafe extern "C" fn span_remove_port(s: *mut span_t, port: *mut c_void) {
+    (*s).lock().forget_port(port);
+}
+
+// Returns TRUE if the port is the destination of a session
+#[no_mangle]
+pub unsafe extern "C" fn span_is_destination(s: *mut span_t, port: *mut c_void) -> c_int {
+    (*s).lock().is_destination(port) as c_int
+}
+
+// Mirror a frame received (SPAN_DIR_RX) or sent (SPAN_DIR_TX) on a port.
+// mask holds the sessions that already got the frame, it must be 0 for a new frame.
+#[no_mangle]
+pub unsafe extern "C" fn span_mirror(s: *mut span_t, port: *mut c_void, vlan: u_int, dir: c_int, pkt: *mut u_char, len: size_t, mask: *mut m_uint32_t) {
+    let out = (*s).lock().mirror(port, vlan, dir, len, &mut *mask);
+    let Some(tx) = (*s).tx else {
+        return;
+    };
+
+    for (dst, rspan_vlan) in out {
+        if rspan_vlan == 0 {
+            tx(dst, pkt, len, (*s).opt);
+            continue;
+        }
+
+        let mut frame = vec![0u8; len + VLAN_TAG_LEN];
+        let n = vlan_push_tag(pkt, len, rspan_vlan as m_uint16_t, N_ETH_PROTO_DOT1Q, frame.as_mut_ptr(), frame.len());
+        if n > 0 {
+            tx(dst, frame.as_mut_ptr(), n as size_t, (*s).opt);
+        }
+    }
+}
+
+// Get the status of a session, returns -1 if it is not configured
+#[no_mangle]
+pub unsafe extern "C" fn span_get_session(s: *mut span_t, id: u_int, info: *mut span_session_info_t) -> c_int {
+    match (*s).lock().session(id) {
+        Some(session) => {
+            *info = span_session_info::new(id, session);
+            0
+        }
+        None => -1,
+    }
+}
+
+// Call a function for each configured session, returns the number of sessions
+#[no_mangle]
+pub unsafe extern "C" fn span_foreach_session(s: *mut span_t, cbk: span_session_cbk_t, opt: *mut c_void) -> c_int {
+    let sessions: Vec<span_session_info_t> = (*s).lock().sessions().map(|(id, session)| span_session_info::new(id, session)).collect();
+    if let Some(cbk) = cbk {
+        for info in &sessions {
+            cbk(info, opt);
+        }
+    }
+    sessions.len() as c_int
+}
+
+// Call a function for each source of a session (ports first), returns -1 if the session is not configured
+#[no_mangle]
+pub unsafe extern "C" fn span_foreach_source(s: *mut span_t, id: u_int, cbk: span_source_cbk_t, opt: *mut c_void) -> c_int {
+    let Some(session) = (*s).lock().session(id).cloned() else {
+        return -1;
+    };
+    if let Some(cbk) = cbk {
+        for (port, dir) in session.ports {
+            cbk(port, 0, dir, opt);
+        }
+        for (vlan, dir) in session.vlans {
+            cbk(null_mut(), vlan, dir, opt);
+        }
+    }
+    0
+}
+
+// Name of a direction
+#[no_mangle]
+pub extern "C" fn span_dir_str(dir: c_int) -> *const c_char {
+    match dir {
+        SPAN_DIR_RX => c"rx".as_ptr(),
+        SPAN_DIR_TX => c"tx".as_ptr(),
+        SPAN_DIR_BOTH => c"both".as_ptr(),
+        _ => c"unknown".as_ptr(),
+    }
+}
+
+// Parse a direction name, returns -1 if unknown
+#[no_mangle]
+pub unsafe extern "C" fn span_dir_parse(s: *const c_char) -> c_int {
+    match CStr::from_ptr(s).to_bytes() {
+        b"rx" => SPAN_DIR_RX,
+        b"tx" => SPAN_DIR_TX,
+        b"both" => SPAN_DIR_BOTH,
+        _ => -1,
+    }
+}
@@ -432,7 +432,7 @@ unsafe fn timer_enable(mut timer: *mut timer_entry_t) -> timer_id {
 pub unsafe extern "C" fn timer_create_entry(interval: m_tmcnt_t, boundary: c_int, level: c_int, callback: timer_proc, user_arg: *mut c_void) -> timer_id {
     // Allocate memory for new timer entry
     let timer: *mut timer_entry_t = libc::malloc(size_of::<timer_entry_t>()).cast::<_>();
-    if !timer.is_null() {
+    if timer.is_null() {
         return 0;
     }
 
@@ -515,6 +515,7 @@ pub unsafe extern "C" fn timer_create_queue() -> *mut timer_queue_t {
 
     (*queue).running.set(TRUE);
     (*queue).list = null_mut();
+    (*queue).timer_count = 0;
     (*queue).level = 0;
 
     // Create mutex
@@ -608,6 +609,22 @@ pub unsafe extern "C" fn timer_pool_add_queues(nr_queues: c_int) -> c_int {
     0
 }
 
+/// Number of timers and sum of criticity levels of each queue of the pool.
+/// The queues are not locked (the timer callbacks run with the queue lock held).
+pub fn timer_queue_stats() -> Vec<(c_int, c_int)> {
+    let mut stats = Vec::new();
+    unsafe {
+        TIMER_LOCK!();
+        let mut queue = timer_queue_pool;
+        while !queue.is_null() {
+            stats.push((addr_of!((*queue).timer_count).read_volatile(), addr_of!((*queue).level).read_volatile()));
+            queue = (*queue).next;
+        }
+        TIMER_UNLOCK!();
+    }
+    stats
+}
+
 // Terminate timer sub-sytem
 extern "C" fn timer_terminate() {
     unsafe {
@@ -6,19 +6,36 @@
 
 use crate::_extra::*;
 use crate::dynamips_common::*;
+use crate::evloop::*;
+use crate::hexdump::*;
 use libc::off_t;
 use libc::size_t;
 use libc::ssize_t;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::ffi::c_char;
 use std::ffi::c_int;
 use std::ffi::c_uchar;
 use std::ffi::c_uint;
 use std::ffi::c_void;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
 use std::mem::zeroed;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
 use std::ptr::addr_of_mut;
 use std::ptr::null_mut;
 use std::ptr::read_unaligned;
 use std::ptr::write_unaligned;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
 use unixstring::UnixString;
 
 // Host CPU Types
@@ -88,12 +105,19 @@ pub extern "C" fn vmtoh64(x: m_uint64_t) -> m_uint64_t {
 // FD pool
 pub const FD_POOL_MAX: usize = 16;
 
+// FD pool callback
+pub type fd_pool_cbk_t = Option<unsafe extern "C" fn(fd_slot: *mut c_int, opt: *mut c_void)>;
+
 pub type fd_pool_t = fd_pool;
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct fd_pool {
     pub fd: [c_int; FD_POOL_MAX],
     pub next: *mut fd_pool,
+    /// Event loop of the FDs (first pool only), see fd_pool_attach.
+    pub ev_loop: *mut ev_loop_t,
+    pub cbk: fd_pool_cbk_t,
+    pub opt: *mut c_void,
 }
 
 // Translated block function pointer
@@ -556,6 +580,253 @@ pub unsafe extern "C" fn m_strquote(buffer: *mut c_char, buf_len: size_t, str_:
     buffer
 }
 
+// Tokenizer flags
+/// Consecutive delimiters count as one (like m_strtok).
+pub const M_TOK_SKIP_EMPTY: c_int = 0x01;
+
+// Tokenizer errors
+pub const M_TOK_ERR_NONE: c_int = 0;
+pub const M_TOK_ERR_QUOTE: c_int = 1;
+pub const M_TOK_ERR_BRACKET: c_int = 2;
+pub const M_TOK_ERR_AFTER_BRACKET: c_int = 3;
+pub const M_TOK_ERR_COUNT: c_int = 4;
+
+/// Error of the tokenizer, `col` is the 1-based column in the string.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TokError {
+    /// Quote without its closing quote.
+    Quote { col: usize },
+    /// '[' without its closing ']'.
+    Bracket { col: usize },
+    /// Something else than a delimiter after ']'.
+    AfterBracket { col: usize },
+    /// More tokens than allowed.
+    Count { col: usize },
+}
+
+impl TokError {
+    /// Error code for the C API (M_TOK_ERR_*).
+    pub fn code(&self) -> c_int {
+        match self {
+            TokError::Quote { .. } => M_TOK_ERR_QUOTE,
+            TokError::Bracket { .. } => M_TOK_ERR_BRACKET,
+            TokError::AfterBracket { .. } => M_TOK_ERR_AFTER_BRACKET,
+            TokError::Count { .. } => M_TOK_ERR_COUNT,
+        }
+    }
+
+    pub fn col(&self) -> usize {
+        match *self {
+            TokError::Quote { col } | TokError::Bracket { col } | TokError::AfterBracket { col } | TokError::Count { col } => col,
+        }
+    }
+}
+
+impl std::fmt::Display for TokError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at column {}", unsafe { CStr::from_ptr(m_tok_strerror(self.code())) }.to_string_lossy(), self.col())
+    }
+}
+
+impl std::error::Error for TokError {}
+
+/// Characters escaped by a backslash, a backslash before anything else is
+/// kept as is (for Windows device names like \Device\NPF_{...}).
+fn tok_is_special(c: u8, delim: u8) -> bool {
+    matches!(c, b'\\' | b'"' | b'\'' | b'[' | b']') || c == delim
+}
+
+/// Split a descriptor on `delim`, understanding:
+/// - bracketed literals at the start of a token ("[::1]", brackets removed),
+/// - double quotes (with \" and \\ escapes) and single quotes (no escapes),
+/// - backslash escapes of the delimiter, quotes, brackets and backslash.
+pub fn m_tokenize(s: &[u8], delim: u8, flags: c_int, max_count: usize) -> Result<Vec<Vec<u8>>, TokError> {
+    let mut tokens: Vec<Vec<u8>> = Vec::new();
+    let mut i = 0;
+
+    loop {
+        let start = i;
+        let mut token = Vec::new();
+        let mut literal = false;
+
+        if s.get(i) == Some(&b'[') {
+            match s[i + 1..].iter().position(|&c| c == b']') {
+                Some(len) => {
+                    token.extend_from_slice(&s[i + 1..i + 1 + len]);
+                    i += len + 2;
+                    literal = true;
+                    if i < s.len() && s[i] != delim {
+                        return Err(TokError::AfterBracket { col: i + 1 });
+                    }
+                }
+                None => return Err(TokError::Bracket { col: i + 1 }),
+            }
+        }
+
+        while i < s.len() && s[i] != delim {
+            match s[i] {
+                b'\\' if i + 1 < s.len() && tok_is_special(s[i + 1], delim) => {
+                    token.push(s[i + 1]);
+                    i += 2;
+                }
+                q @ (b'"' | b'\'') => {
+                    let open = i;
+                    i += 1;
+                    loop {
+                        match s.get(i) {
+                            None => return Err(TokError::Quote { col: open + 1 }),
+                            Some(&c) if c == q => break,
+                            Some(b'\\') if q == b'"' && matches!(s.get(i + 1), Some(b'"' | b'\\')) => {
+                                token.push(s[i + 1]);
+                                i += 2;
+                            }
+                            Some(&c) => {
+                                token.push(c);
+                                i += 1;
+                            }
+                        }
+                    }
+                    i += 1;
+                    literal = true;
+                }
+                c => {
+                    token.push(c);
+                    i += 1;
+                }
+            }
+        }
+
+        let skip = (flags & M_TOK_SKIP_EMPTY) != 0 && token.is_empty() && !literal && !tokens.is_empty();
+        if !skip {
+            if tokens.len() == max_count {
+                return Err(TokError::Count { col: start + 1 });
+            }
+            tokens.push(token);
+        }
+
+        if i >= s.len() {
+            break;
+        }
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+/// Quote a token so that m_tokenize() gives it back unchanged.
+/// IPv6 addresses are bracketed, other strings with special characters
+/// are double-quoted.
+pub fn m_quote_token(s: &[u8], delim: u8) -> Vec<u8> {
+    if !s.is_empty() && s[0] != b'[' && !s.iter().any(|&c| c == delim || matches!(c, b'"' | b'\'' | b'\\')) {
+        return s.to_vec();
+    }
+
+    // IPv6 address, with an optional zone ("fe80::1%eth0")
+    if let Ok(text) = std::str::from_utf8(s) {
+        let addr = text.split('%').next().unwrap_or_default();
+        if addr.parse::<std::net::Ipv6Addr>().is_ok() && !text.contains(']') {
+            return [b"[", s, b"]"].concat();
+        }
+    }
+
+    let mut out = vec![b'"'];
+    for &c in s {
+        if matches!(c, b'"' | b'\\') {
+            out.push(b'\\');
+        }
+        out.push(c);
+    }
+    out.push(b'"');
+    out
+}
+
+// Tokenizer error
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct m_tok_error {
+    /// M_TOK_ERR_*
+    pub code: c_int,
+    /// 1-based column of the error
+    pub col: c_int,
+}
+pub type m_tok_error_t = m_tok_error;
+
+// Split a descriptor like m_strsplit, with support of brackets ("[::1]"),
+// quotes and backslash escapes. The tokens are allocated with malloc.
+// Returns the number of tokens or -1 (err is filled if not NULL).
+#[no_mangle]
+pub unsafe extern "C" fn m_strsplit_tok(str_: *const c_char, delim: c_char, flags: c_int, array: *mut *mut c_char, max_count: c_int, err: *mut m_tok_error_t) -> c_int {
+    for i in 0..max_count {
+        *array.add(i as usize) = null_mut();
+    }
+    if !err.is_null() {
+        *err = m_tok_error::default();
+    }
+
+    let tokens = match m_tokenize(CStr::from_ptr(str_).to_bytes(), delim as u8, flags, max_count.max(0) as usize) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            if !err.is_null() {
+                (*err).code = e.code();
+                (*err).col = e.col() as c_int;
+            }
+            return -1;
+        }
+    };
+
+    for (i, token) in tokens.iter().enumerate() {
+        let p: *mut c_char = libc::malloc(token.len() + 1).cast::<_>();
+        if p.is_null() {
+            for j in 0..i {
+                libc::free((*array.add(j)).cast::<_>());
+                *array.add(j) = null_mut();
+            }
+            return -1;
+        }
+        libc::memcpy(p.cast::<_>(), token.as_ptr().cast::<_>(), token.len());
+        *p.add(token.len()) = 0;
+        *array.add(i) = p;
+    }
+
+    tokens.len() as c_int
+}
+
+// Free the tokens of m_strsplit_tok
+#[no_mangle]
+pub unsafe extern "C" fn m_strsplit_free(array: *mut *mut c_char, count: c_int) {
+    for i in 0..count.max(0) as usize {
+        libc::free((*array.add(i)).cast::<_>());
+        *array.add(i) = null_mut();
+    }
+}
+
+// Quote a token for m_strsplit_tok, the result is allocated with malloc
+#[no_mangle]
+pub unsafe extern "C" fn m_strquote_tok(str_: *const c_char, delim: c_char) -> *mut c_char {
+    let quoted = m_quote_token(CStr::from_ptr(str_).to_bytes(), delim as u8);
+    let p: *mut c_char = libc::malloc(quoted.len() + 1).cast::<_>();
+
+    if !p.is_null() {
+        libc::memcpy(p.cast::<_>(), quoted.as_ptr().cast::<_>(), quoted.len());
+        *p.add(quoted.len()) = 0;
+    }
+    p
+}
+
+// Get the message of a tokenizer error
+#[no_mangle]
+pub extern "C" fn m_tok_strerror(code: c_int) -> *const c_char {
+    let msg = match code {
+        M_TOK_ERR_NONE => c"no error",
+        M_TOK_ERR_QUOTE => c"unterminated quote",
+        M_TOK_ERR_BRACKET => c"unterminated bracket",
+        M_TOK_ERR_AFTER_BRACKET => c"unexpected character after bracket",
+        M_TOK_ERR_COUNT => c"too many fields",
+        _ => c"unknown error",
+    };
+    msg.as_ptr()
+}
+
 // Decode from hex.
 //
 // hex to raw bytes, returning count of bytes.
@@ -595,95 +866,455 @@ pub unsafe extern "C" fn hex_decode(mut out: *mut c_uchar, mut in_: *const c_uch
     len
 }
 
-// Ugly function that dumps a structure in hexa and ascii.
+// Dump a structure in hexa and ascii.
 #[no_mangle]
 pub unsafe extern "C" fn mem_dump(f_output: *mut libc::FILE, pkt: *mut u_char, len: u_int) {
-    let mut x: u_int;
-    let mut i: u_int = 0;
+    let fmt = hexdump_fmt { offset_width: 4, ..Default::default() };
 
-    while i < len {
-        if (len - i) > 16 {
-            x = 16;
-        } else {
-            x = len - i;
+    hexdump_fprint(f_output, pkt, len as size_t, &fmt);
+    libc::fprintf(f_output, c"\n".as_ptr());
+    libc::fflush(f_output);
+}
+
+// Log levels
+pub const M_LOG_DEBUG: c_int = 0;
+pub const M_LOG_INFO: c_int = 1;
+pub const M_LOG_WARN: c_int = 2;
+pub const M_LOG_ERROR: c_int = 3;
+/// Only for filters, disables the module.
+pub const M_LOG_NONE: c_int = 4;
+
+// Log output formats
+pub const M_LOG_FORMAT_TEXT: c_int = 0;
+pub const M_LOG_FORMAT_JSON: c_int = 1;
+
+/// Name of a log level.
+pub fn m_log_level_name(level: c_int) -> &'static str {
+    match level {
+        M_LOG_DEBUG => "debug",
+        M_LOG_INFO => "info",
+        M_LOG_WARN => "warn",
+        M_LOG_ERROR => "error",
+        _ => "none",
+    }
+}
+
+/// Parse a log level name or number.
+pub fn m_log_parse_level(s: &str) -> Option<c_int> {
+    match s.to_ascii_lowercase().as_str() {
+        "debug" => Some(M_LOG_DEBUG),
+        "info" => Some(M_LOG_INFO),
+        "warn" | "warning" => Some(M_LOG_WARN),
+        "error" => Some(M_LOG_ERROR),
+        "none" | "off" => Some(M_LOG_NONE),
+        s => s.parse::<c_int>().ok().filter(|x| (M_LOG_DEBUG..=M_LOG_NONE).contains(x)),
+    }
+}
+
+/// Timestamp of a log record, ISO 8601 in UTC with milliseconds.
+fn m_log_timestamp() -> String {
+    m_log_timestamp_usec(unsafe { m_gettime_usec() })
+}
+
+/// ISO 8601 timestamp in UTC with milliseconds of a time in usecs since epoch (see `m_gettime_usec`).
+pub fn m_log_timestamp_usec(usec: m_tmcnt_t) -> String {
+    unsafe {
+        let mut secs: libc::time_t = (usec / 1000000) as libc::time_t;
+        let mut tmn: libc::tm = zeroed();
+        libc::gmtime_r(addr_of_mut!(secs), addr_of_mut!(tmn));
+
+        // NOTE never use strftime for timestamps, it is crashy
+        format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", tmn.tm_year + 1900, tmn.tm_mon + 1, tmn.tm_mday, tmn.tm_hour, tmn.tm_min, tmn.tm_sec, (usec % 1000000) / 1000)
+    }
+}
+
+/// Shift the old files of a log (name.1 is the most recent) and move the file to name.1.
+/// The oldest file beyond max_files is removed, nothing is done if max_files is 0.
+pub fn m_log_rotate_files(filename: &str, max_files: u_int) {
+    if max_files == 0 {
+        return;
+    }
+    let old = |i: u_int| CString::new(format!("{}.{}", filename, i)).unwrap_or_default();
+    let Ok(name) = CString::new(filename) else {
+        return;
+    };
+    unsafe {
+        libc::unlink(old(max_files).as_ptr());
+        for i in (1..max_files).rev() {
+            libc::rename(old(i).as_ptr(), old(i + 1).as_ptr());
         }
+        libc::rename(name.as_ptr(), old(1).as_ptr());
+    }
+}
 
-        libc::fprintf(f_output, c"%4.4x: ".as_ptr(), i);
+/// Append a JSON string literal.
+pub fn m_log_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Logging subsystem: levels, per-module filters, output format and rotation of the main log file.
+#[derive(Debug)]
+pub struct Logger {
+    level: c_int,
+    /// Module filters, a trailing '*' matches a prefix.
+    modules: BTreeMap<String, c_int>,
+    format: c_int,
+    file: *mut libc::FILE,
+    filename: Option<CString>,
+    max_size: size_t,
+    max_files: u_int,
+}
+// the FILE is only used with the lock held
+unsafe impl Send for Logger {}
+
+impl Logger {
+    pub const fn new() -> Self {
+        Logger { level: M_LOG_INFO, modules: BTreeMap::new(), format: M_LOG_FORMAT_TEXT, file: null_mut(), filename: None, max_size: 0, max_files: 0 }
+    }
 
-        for tmp in 0..x {
-            libc::fprintf(f_output, c"%2.2x ".as_ptr(), *pkt.add((i + tmp) as usize) as c_int);
+    pub fn level(&self) -> c_int {
+        self.level
+    }
+
+    pub fn set_level(&mut self, level: c_int) {
+        self.level = level;
+    }
+
+    /// Set the level of a module, None removes the filter.
+    pub fn set_module_level(&mut self, module: &str, level: Option<c_int>) {
+        match level {
+            Some(level) => self.modules.insert(module.to_string(), level),
+            None => self.modules.remove(module),
+        };
+    }
+
+    /// Apply a filter specification: "level,MODULE=level,PREFIX*=level,...".
+    /// Nothing is changed on error.
+    pub fn set_filters(&mut self, spec: &str) -> Result<(), String> {
+        let mut level = self.level;
+        let mut modules = self.modules.clone();
+        for item in spec.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            match item.split_once('=') {
+                Some((module, s)) => {
+                    let module = module.trim();
+                    let s = s.trim();
+                    if module.is_empty() {
+                        return Err(format!("missing module in '{}'", item));
+                    }
+                    if s.eq_ignore_ascii_case("default") {
+                        modules.remove(module);
+                    } else {
+                        modules.insert(module.to_string(), m_log_parse_level(s).ok_or_else(|| format!("invalid level '{}'", s))?);
+                    }
+                }
+                None => level = m_log_parse_level(item).ok_or_else(|| format!("invalid level '{}'", item))?,
+            }
+        }
+        self.level = level;
+        self.modules = modules;
+        Ok(())
+    }
+
+    /// Filter specification, can be fed back to set_filters.
+    pub fn filters(&self) -> String {
+        let mut spec = m_log_level_name(self.level).to_string();
+        for (module, level) in &self.modules {
+            spec += &format!(",{}={}", module, m_log_level_name(*level));
+        }
+        spec
+    }
+
+    /// Level of a module: exact match, else the longest prefix match, else the default level.
+    pub fn module_level(&self, module: &str) -> c_int {
+        if let Some(level) = self.modules.get(module) {
+            return *level;
         }
-        for _ in x..16 {
-            libc::fprintf(f_output, c"   ".as_ptr());
+        self.modules
+            .iter()
+            .filter_map(|(m, level)| m.strip_suffix('*').filter(|prefix| module.starts_with(prefix)).map(|prefix| (prefix.len(), *level)))
+            .max_by_key(|(len, _)| *len)
+            .map_or(self.level, |(_, level)| level)
+    }
+
+    pub fn enabled(&self, level: c_int, module: &str) -> bool {
+        level < M_LOG_NONE && level >= self.module_level(module)
+    }
+
+    pub fn format(&self) -> c_int {
+        self.format
+    }
+
+    pub fn set_format(&mut self, format: c_int) -> bool {
+        if format != M_LOG_FORMAT_TEXT && format != M_LOG_FORMAT_JSON {
+            return false;
         }
+        self.format = format;
+        true
+    }
 
-        for tmp in 0..x {
-            let c: c_char = *pkt.add((i + tmp) as usize) as c_char;
+    /// Rotate the log file when it reaches max_size bytes (0 to disable), keeping max_files old files.
+    pub fn set_rotation(&mut self, max_size: size_t, max_files: u_int) {
+        self.max_size = max_size;
+        self.max_files = max_files;
+    }
 
-            if ((c >= b'A' as c_char) && (c <= b'Z' as c_char)) || ((c >= b'a' as c_char) && (c <= b'z' as c_char)) || ((c >= b'0' as c_char) && (c <= b'9' as c_char)) {
-                libc::fprintf(f_output, c"%c".as_ptr(), c as c_int);
-            } else {
-                libc::fputs(c".".as_ptr(), f_output);
+    /// Build a record.
+    /// Text: "<timestamp> [<LEVEL>] <module>: <msg>", the level is omitted for info (legacy format).
+    /// JSON: {"ts":..,"level":..,"module":..,"vm":..,"instance":..,"msg":..}, one per line.
+    pub fn record(&self, level: c_int, vm: Option<(&str, c_int)>, module: &str, msg: &str) -> String {
+        let ts = m_log_timestamp();
+        match self.format {
+            M_LOG_FORMAT_JSON => {
+                let mut out = String::with_capacity(msg.len() + 96);
+                out.push_str("{\"ts\":\"");
+                out.push_str(&ts);
+                out.push_str("\",\"level\":\"");
+                out.push_str(m_log_level_name(level));
+                out.push_str("\",\"module\":");
+                m_log_json_str(&mut out, module);
+                if let Some((name, instance_id)) = vm {
+                    out.push_str(",\"vm\":");
+                    m_log_json_str(&mut out, name);
+                    out.push_str(&format!(",\"instance\":{}", instance_id));
+                }
+                out.push_str(",\"msg\":");
+                m_log_json_str(&mut out, msg.trim_end_matches('\n'));
+                out.push_str("}\n");
+                out
             }
+            _ if level == M_LOG_INFO => format!("{} {}: {}", ts, module, msg),
+            _ => format!("{} [{}] {}: {}", ts, m_log_level_name(level).to_ascii_uppercase(), module, msg),
         }
+    }
 
-        i += x;
-        libc::fprintf(f_output, c"\n".as_ptr());
+    /// Write a record in a file, rotates the main log file if needed.
+    pub unsafe fn write(&mut self, fd: *mut libc::FILE, level: c_int, vm: Option<(&str, c_int)>, module: &str, msg: &str) {
+        if fd.is_null() || !self.enabled(level, module) {
+            return;
+        }
+        let rec = self.record(level, vm, module, msg);
+        libc::fwrite(rec.as_ptr().cast::<_>(), 1, rec.len(), fd);
+        libc::fflush(fd);
+
+        if fd == self.file && self.max_size > 0 && libc::ftell(fd) >= self.max_size as libc::c_long {
+            self.rotate();
+        }
     }
 
-    libc::fprintf(f_output, c"\n".as_ptr());
-    libc::fflush(f_output);
+    /// Open the main log file.
+    pub unsafe fn open(&mut self, filename: &CStr) -> bool {
+        self.close();
+        self.file = libc::fopen(filename.as_ptr(), c"w".as_ptr());
+        if self.file.is_null() {
+            return false;
+        }
+        self.filename = Some(filename.to_owned());
+        true
+    }
+
+    /// Close the main log file.
+    pub unsafe fn close(&mut self) {
+        if !self.file.is_null() && self.file != c_stderr() {
+            libc::fclose(self.file);
+            self.file = null_mut();
+        }
+        self.filename = None;
+    }
+
+    pub fn file(&self) -> *mut libc::FILE {
+        self.file
+    }
+
+    /// Shift the old files (name.1 is the most recent) and restart the main log file.
+    /// The FILE is reopened in place, so copies of the pointer stay valid.
+    /// If the file cannot be reopened, the log goes to stderr (also `log_file`).
+    pub unsafe fn rotate(&mut self) {
+        let Some(filename) = &self.filename else {
+            return;
+        };
+        m_log_rotate_files(&filename.to_string_lossy(), self.max_files);
+        if libc::freopen(filename.as_ptr(), c"w".as_ptr(), self.file).is_null() {
+            // the stream has been closed by freopen
+            libc::perror(c"m_log_rotate: freopen".as_ptr());
+            libc::fprintf(c_stderr(), c"m_log_rotate: logging to stderr from now on\n".as_ptr());
+            if log_file == self.file {
+                log_file = c_stderr();
+            }
+            self.file = c_stderr();
+            self.filename = None;
+        }
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Hypervisor-wide logger.
+pub static LOGGER: Mutex<Logger> = Mutex::new(Logger::new());
+
+pub fn logger() -> MutexGuard<'static, Logger> {
+    LOGGER.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+unsafe fn m_log_str<'a>(s: *const c_char) -> Cow<'a, str> {
+    if s.is_null() {
+        Cow::Borrowed("")
+    } else {
+        CStr::from_ptr(s).to_string_lossy()
+    }
 }
 
 // Logging function
 pub unsafe fn m_flog(fd: *mut libc::FILE, module: *const c_char, fmt: *const c_char, args: &[&dyn sprintf::Printf]) {
-    let mut spec: libc::timespec = zeroed();
-    let mut tmn: libc::tm = zeroed();
-
-    if !fd.is_null() {
-        libc::clock_gettime(libc::CLOCK_REALTIME, addr_of_mut!(spec));
-        libc::gmtime_r(addr_of_mut!(spec.tv_sec), addr_of_mut!(tmn));
+    m_flog_lvl(fd, M_LOG_INFO, module, fmt, args);
+}
 
-        // NOTE never use strftime for timestamps, it is crashy
-        libc::fprintf(
-            fd,
-            c"%d-%02d-%02dT%02d:%02d:%02d.%03dZ %s: ".as_ptr(),
-            tmn.tm_year + 1900,
-            tmn.tm_mon + 1,
-            tmn.tm_mday,
-            tmn.tm_hour,
-            tmn.tm_min,
-            tmn.tm_sec,
-            (spec.tv_nsec / 1000000) as c_int,
-            module,
-        );
-        let fmt = UnixString::from_ptr(fmt);
-        match sprintf::vsprintf(fmt.as_c_str().to_str().expect("fmt"), args) {
-            Ok(s) => {
-                let s = UnixString::from_string(s).expect("UnixString").into_cstring();
-                libc::fprintf(fd, c"%s".as_ptr(), s.as_ptr());
-            }
-            Err(err) => {
-                panic!("m_flog({:?} {}): {}", fmt, args.len(), err);
-            }
+// Logging function with a level
+pub unsafe fn m_flog_lvl(fd: *mut libc::FILE, level: c_int, module: *const c_char, fmt: *const c_char, args: &[&dyn sprintf::Printf]) {
+    if fd.is_null() || m_log_enabled(level, module) == 0 {
+        return;
+    }
+    let fmt = UnixString::from_ptr(fmt);
+    match sprintf::vsprintf(fmt.as_c_str().to_str().expect("fmt"), args) {
+        Ok(s) => {
+            logger().write(fd, level, None, &m_log_str(module), &s);
+        }
+        Err(err) => {
+            panic!("m_flog({:?} {}): {}", fmt, args.len(), err);
         }
-        libc::fflush(fd);
     }
 }
 
 // Logging function
 macro_rules! m_log {
     ($module:expr, $fmt:expr$(, $arg:expr)*) => {{
+        m_log_lvl!(M_LOG_INFO, $module, $fmt$(, $arg)*);
+    }};
+}
+pub(crate) use m_log;
+
+// Logging function with a level
+macro_rules! m_log_lvl {
+    ($level:expr, $module:expr, $fmt:expr$(, $arg:expr)*) => {{
         let module: *const c_char = $module;
-        println!("module {:?}", module);
         let fmt: *const c_char = $fmt;
-        println!("fmt {:?}", fmt);
         let args: &[&dyn sprintf::Printf] = &[$(&crate::_extra::Printf($arg)),*];
-        $( println!("arg {:?}", $arg); )*
-        m_flog(log_file, module, fmt, args);
+        m_flog_lvl(log_file, $level, module, fmt, args);
     }};
 }
-pub(crate) use m_log;
+pub(crate) use m_log_lvl;
+
+// Write a formatted message in a log file, the VM fields are omitted if vm_name is NULL
+#[no_mangle]
+pub unsafe extern "C" fn m_flog_msg(fd: *mut libc::FILE, level: c_int, vm_name: *const c_char, instance_id: c_int, module: *const c_char, msg: *const c_char) {
+    let vm_name = (!vm_name.is_null()).then(|| m_log_str(vm_name));
+    let vm = vm_name.as_deref().map(|name| (name, instance_id));
+    logger().write(fd, level, vm, &m_log_str(module), &m_log_str(msg));
+}
+
+// Returns TRUE if a message of this level is logged for this module
+#[no_mangle]
+pub unsafe extern "C" fn m_log_enabled(level: c_int, module: *const c_char) -> c_int {
+    logger().enabled(level, &m_log_str(module)) as c_int
+}
+
+// Set the default log level
+#[no_mangle]
+pub extern "C" fn m_log_set_level(level: c_int) -> c_int {
+    if !(M_LOG_DEBUG..=M_LOG_NONE).contains(&level) {
+        return -1;
+    }
+    logger().set_level(level);
+    0
+}
+
+// Get the default log level
+#[no_mangle]
+pub extern "C" fn m_log_get_level() -> c_int {
+    logger().level()
+}
+
+// Set the log level of a module (-1 to use the default level)
+#[no_mangle]
+pub unsafe extern "C" fn m_log_set_module_level(module: *const c_char, level: c_int) -> c_int {
+    if module.is_null() || !(-1..=M_LOG_NONE).contains(&level) {
+        return -1;
+    }
+    logger().set_module_level(&m_log_str(module), (level != -1).then_some(level));
+    0
+}
+
+// Apply a filter specification ("info,VM=debug,NIO*=warn")
+#[no_mangle]
+pub unsafe extern "C" fn m_log_set_filters(spec: *const c_char) -> c_int {
+    match logger().set_filters(&m_log_str(spec)) {
+        Ok(()) => 0,
+        Err(err) => {
+            let msg = CString::new(err.to_string()).unwrap_or_default();
+            libc::fprintf(c_stderr(), c"m_log_set_filters: %s\n".as_ptr(), msg.as_ptr());
+            -1
+        }
+    }
+}
+
+// Get the filter specification (must be freed with free())
+#[no_mangle]
+pub extern "C" fn m_log_get_filters() -> *mut c_char {
+    let spec = CString::new(logger().filters()).expect("CString");
+    unsafe { libc::strdup(spec.as_ptr()) }
+}
+
+// Set the log output format (M_LOG_FORMAT_TEXT or M_LOG_FORMAT_JSON)
+#[no_mangle]
+pub extern "C" fn m_log_set_format(format: c_int) -> c_int {
+    if logger().set_format(format) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Set the size based rotation of the log file (max_size 0 to disable)
+#[no_mangle]
+pub extern "C" fn m_log_set_rotation(max_size: size_t, max_files: u_int) {
+    logger().set_rotation(max_size, max_files);
+}
+
+// Open the general log file
+#[no_mangle]
+pub unsafe extern "C" fn m_log_open(filename: *const c_char) -> c_int {
+    let mut logger = logger();
+    let ok = !filename.is_null() && logger.open(CStr::from_ptr(filename));
+    log_file = logger.file();
+    if ok {
+        0
+    } else {
+        -1
+    }
+}
+
+// Close the general log file
+#[no_mangle]
+pub unsafe extern "C" fn m_log_close() {
+    let mut logger = logger();
+    logger.close();
+    log_file = null_mut();
+}
 
 // Write an array of string to a logfile
 #[no_mangle]
@@ -831,6 +1462,210 @@ pub unsafe extern "C" fn memzone_unmap(addr: *mut c_void, len: size_t) -> c_int
     libc::munmap(addr, len)
 }
 
+// Huge page modes of the memory zones
+pub const MEMZONE_HUGE_NONE: c_int = 0;
+/// Transparent huge pages (madvise).
+pub const MEMZONE_HUGE_THP: c_int = 1;
+/// Explicit huge pages (MAP_HUGETLB), falls back to transparent huge pages.
+pub const MEMZONE_HUGE_HUGETLB: c_int = 2;
+
+// No NUMA binding
+pub const MEMZONE_NUMA_ANY: c_int = -1;
+
+// Kinds of memory zones
+pub const MEMZONE_KIND_RAM: c_int = 0;
+pub const MEMZONE_KIND_EXEC: c_int = 1;
+pub const MEMZONE_KIND_MAX: c_int = 2;
+
+// Memory zone options, also used to report the mode actually used
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct memzone_opts {
+    pub huge_pages: c_int,
+    pub numa_node: c_int,
+}
+pub type memzone_opts_t = memzone_opts;
+
+impl memzone_opts {
+    pub const DEFAULT: memzone_opts = memzone_opts { huge_pages: MEMZONE_HUGE_NONE, numa_node: MEMZONE_NUMA_ANY };
+}
+
+impl Default for memzone_opts {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+// Requested and last used options of each kind of memory zone
+static MEMZONE_OPTS: Mutex<[(memzone_opts, memzone_opts); MEMZONE_KIND_MAX as usize]> = Mutex::new([(memzone_opts::DEFAULT, memzone_opts::DEFAULT); MEMZONE_KIND_MAX as usize]);
+
+fn memzone_opts_lock() -> MutexGuard<'static, [(memzone_opts, memzone_opts); MEMZONE_KIND_MAX as usize]> {
+    MEMZONE_OPTS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Name of a huge page mode.
+pub fn memzone_huge_name(huge_pages: c_int) -> &'static str {
+    match huge_pages {
+        MEMZONE_HUGE_THP => "thp",
+        MEMZONE_HUGE_HUGETLB => "hugetlb",
+        _ => "none",
+    }
+}
+
+/// Parse a huge page mode name.
+pub fn memzone_parse_huge(s: &str) -> Option<c_int> {
+    match s {
+        "none" | "0" => Some(MEMZONE_HUGE_NONE),
+        "thp" | "1" => Some(MEMZONE_HUGE_THP),
+        "hugetlb" | "2" => Some(MEMZONE_HUGE_HUGETLB),
+        _ => None,
+    }
+}
+
+/// Size of the default huge pages.
+#[cfg(target_os = "linux")]
+fn memzone_huge_page_size() -> usize {
+    fs::read_to_string("/proc/meminfo")
+        .ok()
+        .and_then(|info| info.lines().find_map(|line| line.strip_prefix("Hugepagesize:").and_then(|x| x.trim().trim_end_matches("kB").trim().parse::<usize>().ok())))
+        .map_or(2 << 20, |kb| kb << 10)
+}
+
+/// Returns true if the transparent huge pages can be used with madvise.
+#[cfg(target_os = "linux")]
+fn memzone_thp_enabled() -> bool {
+    fs::read_to_string("/sys/kernel/mm/transparent_hugepage/enabled").is_ok_and(|x| !x.contains("[never]"))
+}
+
+/// Bind a memory area to a NUMA node, move_pages moves the pages already allocated.
+#[cfg(target_os = "linux")]
+unsafe fn memzone_mbind(ptr: *mut c_void, len: usize, node: c_int, move_pages: bool) -> bool {
+    const MPOL_BIND: libc::c_long = 2;
+    const MPOL_MF_MOVE: libc::c_long = 1 << 1;
+    const BITS: usize = libc::c_ulong::BITS as usize;
+
+    if node < 0 || node as usize >= 64 * BITS {
+        return false;
+    }
+    let mut nodemask: [libc::c_ulong; 64] = [0; 64];
+    nodemask[node as usize / BITS] |= 1 << (node as usize % BITS);
+    let flags = if move_pages { MPOL_MF_MOVE } else { 0 };
+    libc::syscall(libc::SYS_mbind, ptr, len, MPOL_BIND, nodemask.as_ptr(), (64 * BITS + 1) as libc::c_ulong, flags) == 0
+}
+
+/// Apply the transparent huge pages and NUMA options to a mapped area.
+/// Transparent huge pages are only reported for anonymous and tmpfs memory.
+#[cfg(target_os = "linux")]
+unsafe fn memzone_advise_area(ptr: *mut c_void, len: usize, fd: c_int, opts: &memzone_opts, used: &mut memzone_opts, move_pages: bool) {
+    const TMPFS_MAGIC: libc::c_long = 0x01021994;
+
+    if opts.huge_pages != MEMZONE_HUGE_NONE && used.huge_pages == MEMZONE_HUGE_NONE && memzone_thp_enabled() && libc::madvise(ptr, len, libc::MADV_HUGEPAGE) == 0 {
+        let mut sfs: libc::statfs = zeroed();
+        if fd == -1 || (libc::fstatfs(fd, addr_of_mut!(sfs)) == 0 && sfs.f_type as libc::c_long == TMPFS_MAGIC) {
+            used.huge_pages = MEMZONE_HUGE_THP;
+        }
+    }
+    if opts.numa_node != MEMZONE_NUMA_ANY && memzone_mbind(ptr, len, opts.numa_node, move_pages) {
+        used.numa_node = opts.numa_node;
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+unsafe fn memzone_advise_area(_ptr: *mut c_void, _len: usize, _fd: c_int, _opts: &memzone_opts, _used: &mut memzone_opts, _move_pages: bool) {}
+
+/// Map a memory zone with the options of its kind, falling back to normal pages and no NUMA binding.
+/// Returns the zone (or NULL) and the options actually used.
+pub unsafe fn memzone_map_opts(len: size_t, prot: c_int, flags: c_int, fd: c_int, kind: c_int) -> (*mut c_void, memzone_opts) {
+    let opts = memzone_get_kind_opts(kind);
+    let mut used = memzone_opts::DEFAULT;
+    let mut ptr: *mut c_void = null_mut();
+
+    // explicit huge pages are only available for anonymous memory
+    #[cfg(target_os = "linux")]
+    if opts.huge_pages == MEMZONE_HUGE_HUGETLB && fd == -1 && len % memzone_huge_page_size() == 0 {
+        ptr = mmap_or_null(null_mut(), len, prot, flags | libc::MAP_HUGETLB, fd, 0);
+        if !ptr.is_null() {
+            used.huge_pages = MEMZONE_HUGE_HUGETLB;
+        }
+    }
+    if ptr.is_null() {
+        ptr = mmap_or_null(null_mut(), len, prot, flags, fd, 0);
+    }
+    if !ptr.is_null() {
+        memzone_advise_area(ptr, len, fd, &opts, &mut used, false);
+        memzone_set_used(kind, len, &used);
+    }
+
+    (ptr, used)
+}
+
+fn memzone_get_kind_opts(kind: c_int) -> memzone_opts {
+    usize::try_from(kind).ok().and_then(|kind| memzone_opts_lock().get(kind).map(|x| x.0)).unwrap_or_default()
+}
+
+// Record and log the mode actually used
+fn memzone_set_used(kind: c_int, len: size_t, used: &memzone_opts) {
+    if usize::try_from(kind).ok().and_then(|kind| memzone_opts_lock().get_mut(kind).map(|x| x.1 = *used)).is_none() {
+        return;
+    }
+    let kind = if kind == MEMZONE_KIND_EXEC { "exec" } else { "ram" };
+    let msg = format!("{} zone of {} bytes: huge_pages={}, numa_node={}\n", kind, len, memzone_huge_name(used.huge_pages), used.numa_node);
+    unsafe { logger().write(log_file, M_LOG_DEBUG, None, "MEMZONE", &msg) };
+}
+
+// Set the options of a kind of memory zone
+#[no_mangle]
+pub extern "C" fn memzone_set_opts(kind: c_int, huge_pages: c_int, numa_node: c_int) -> c_int {
+    if !(MEMZONE_HUGE_NONE..=MEMZONE_HUGE_HUGETLB).contains(&huge_pages) || numa_node < MEMZONE_NUMA_ANY {
+        return -1;
+    }
+    match usize::try_from(kind).ok().and_then(|kind| memzone_opts_lock().get_mut(kind).map(|x| x.0 = memzone_opts { huge_pages, numa_node })) {
+        Some(()) => 0,
+        None => -1,
+    }
+}
+
+// Get the requested options of a kind of memory zone and the options used by the last zone
+#[no_mangle]
+pub unsafe extern "C" fn memzone_get_opts(kind: c_int, opts: *mut memzone_opts_t, used: *mut memzone_opts_t) -> c_int {
+    let Some((o, u)) = usize::try_from(kind).ok().and_then(|kind| memzone_opts_lock().get(kind).copied()) else {
+        return -1;
+    };
+    if !opts.is_null() {
+        *opts = o;
+    }
+    if !used.is_null() {
+        *used = u;
+    }
+    0
+}
+
+// Map anonymous memory with the options of a kind of memory zone (used can be NULL)
+#[no_mangle]
+pub unsafe extern "C" fn memzone_map_anon(len: size_t, kind: c_int, used: *mut memzone_opts_t) -> *mut u_char {
+    let (ptr, u) = memzone_map_opts(len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, kind);
+    if !used.is_null() {
+        *used = u;
+    }
+    ptr.cast::<_>()
+}
+
+// Apply the transparent huge pages and NUMA options of a kind of memory zone to an existing mapping (used can be NULL)
+#[no_mangle]
+pub unsafe extern "C" fn memzone_advise(ptr: *mut u_char, len: size_t, fd: c_int, kind: c_int, used: *mut memzone_opts_t) -> c_int {
+    let opts = memzone_get_kind_opts(kind);
+    let mut u = memzone_opts::DEFAULT;
+    if ptr.is_null() {
+        return -1;
+    }
+    memzone_advise_area(ptr.cast::<_>(), len, fd, &opts, &mut u, true);
+    memzone_set_used(kind, len, &u);
+    if !used.is_null() {
+        *used = u;
+    }
+    0
+}
+
 // Return a memory zone or NULL on error
 unsafe fn mmap_or_null(addr: *mut c_void, length: size_t, prot: c_int, flags: c_int, fd: c_int, offset: off_t) -> *mut c_void {
     let ptr: *mut c_void = libc::mmap(addr, length, prot, flags, fd, offset);
@@ -844,7 +1679,7 @@ unsafe fn mmap_or_null(addr: *mut c_void, length: size_t, prot: c_int, flags: c_
 // Map a memory zone as an executable area
 #[no_mangle]
 pub unsafe extern "C" fn memzone_map_exec_area(len: size_t) -> *mut u_char {
-    mmap_or_null(null_mut(), len, libc::PROT_EXEC | libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED | libc::MAP_ANONYMOUS, -1, 0).cast::<_>()
+    memzone_map_opts(len, libc::PROT_EXEC | libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED | libc::MAP_ANONYMOUS, -1, MEMZONE_KIND_EXEC).0.cast::<_>()
 }
 
 // Map a memory zone from a file
@@ -963,6 +1798,234 @@ pub unsafe extern "C" fn memzone_open_file_ro(filename: *mut c_char, ptr: *mut *
     fd
 }
 
+// Page size used for the sparse files and the deltas
+fn memzone_page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+// Returns TRUE if a memory area only contains zeros
+fn memzone_is_zero(data: &[u8]) -> bool {
+    let (head, words, tail) = unsafe { data.align_to::<u64>() };
+    head.iter().all(|x| *x == 0) && words.iter().all(|x| *x == 0) && tail.iter().all(|x| *x == 0)
+}
+
+// Returns the disk space used by a file
+#[no_mangle]
+pub unsafe extern "C" fn memzone_allocated_size(fd: c_int) -> off_t {
+    let mut fprop: libc::stat = zeroed();
+
+    if libc::fstat(fd, addr_of_mut!(fprop)) == -1 {
+        return -1;
+    }
+
+    fprop.st_blocks as off_t * 512
+}
+
+/// Release the blocks of the zeroed pages of a file mapped with memzone_map_file.
+/// Only the data extents are scanned. Returns the number of released pages.
+#[cfg(target_os = "linux")]
+pub unsafe fn memzone_punch_holes(fd: c_int, ptr: *const u_char, len: usize) -> io::Result<usize> {
+    let page_size = memzone_page_size();
+    let mut count = 0;
+    let mut pos: usize = 0;
+
+    while pos < len {
+        // next data extent, the whole file if SEEK_DATA is not supported
+        let (start, end) = match libc::lseek(fd, pos as off_t, libc::SEEK_DATA) {
+            -1 if io::Error::last_os_error().raw_os_error() == Some(libc::ENXIO) => break,
+            -1 => (pos, len),
+            start => (start as usize, (libc::lseek(fd, start, libc::SEEK_HOLE).max(start) as usize).min(len)),
+        };
+        let mut page = start / page_size * page_size;
+        let mut hole_start: Option<usize> = None;
+
+        while page < end {
+            let size = page_size.min(len - page);
+            let zero = memzone_is_zero(std::slice::from_raw_parts(ptr.add(page), size));
+            match (zero, hole_start) {
+                (true, None) => hole_start = Some(page),
+                (false, Some(hole)) => {
+                    count += memzone_punch_range(fd, hole, page, page_size)?;
+                    hole_start = None;
+                }
+                _ => {}
+            }
+            page += size;
+        }
+        if let Some(hole) = hole_start {
+            count += memzone_punch_range(fd, hole, page, page_size)?;
+        }
+        pos = page.max(end);
+    }
+
+    Ok(count)
+}
+
+/// Hole punching is only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+pub unsafe fn memzone_punch_holes(_fd: c_int, _ptr: *const u_char, _len: usize) -> io::Result<usize> {
+    Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+}
+
+// Punch a hole in a file, returns the number of pages
+#[cfg(target_os = "linux")]
+unsafe fn memzone_punch_range(fd: c_int, start: usize, end: usize, page_size: usize) -> io::Result<usize> {
+    if libc::fallocate(fd, libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE, start as off_t, (end - start) as off_t) == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((end - start).div_ceil(page_size))
+}
+
+// Print an I/O error of a memory zone function
+fn memzone_report_error(func: &CStr, err: &io::Error) {
+    let msg = CString::new(err.to_string()).unwrap_or_default();
+    unsafe { libc::fprintf(c_stderr(), c"%s: %s\n".as_ptr(), func.as_ptr(), msg.as_ptr()) };
+}
+
+// Release the blocks of the zeroed pages of a memory zone file, returns the number of released pages
+#[no_mangle]
+pub unsafe extern "C" fn memzone_punch_zero_pages(fd: c_int, ptr: *mut u_char, len: size_t) -> ssize_t {
+    match memzone_punch_holes(fd, ptr, len) {
+        Ok(count) => count as ssize_t,
+        Err(err) => {
+            memzone_report_error(c"memzone_punch_zero_pages", &err);
+            -1
+        }
+    }
+}
+
+/// Magic number of the delta files.
+pub const MEMZONE_DELTA_MAGIC: &[u8; 8] = b"DYNDELTA";
+/// Version of the delta files.
+pub const MEMZONE_DELTA_VERSION: u32 = 1;
+
+/// Write the pages of a memory zone which differ from the base file in a delta file.
+///
+/// The delta file is a header (magic, version, page size, zone length, page count, big-endian)
+/// followed by the pages (page index then data). A base_fd of -1 stands for a zeroed base, the
+/// part of the zone beyond the end of the base file is compared with zeros.
+/// Returns the number of saved pages.
+pub unsafe fn memzone_delta_save(ptr: *const u_char, len: usize, base_fd: c_int, filename: &Path) -> io::Result<usize> {
+    let page_size = memzone_page_size();
+    let mut base = vec![0_u8; page_size];
+    let mut out = io::BufWriter::new(fs::File::create(filename)?);
+    let mut count: u64 = 0;
+
+    out.write_all(MEMZONE_DELTA_MAGIC)?;
+    out.write_all(&MEMZONE_DELTA_VERSION.to_be_bytes())?;
+    out.write_all(&(page_size as u32).to_be_bytes())?;
+    out.write_all(&(len as u64).to_be_bytes())?;
+    out.write_all(&count.to_be_bytes())?;
+
+    for (index, offset) in (0..len).step_by(page_size).enumerate() {
+        let page = std::slice::from_raw_parts(ptr.add(offset), page_size.min(len - offset));
+        let same = if base_fd == -1 {
+            memzone_is_zero(page)
+        } else {
+            let base = &mut base[..page.len()];
+            let n = libc::pread(base_fd, base.as_mut_ptr().cast::<_>(), base.len(), offset as off_t);
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            base[n as usize..].fill(0);
+            page == base
+        };
+        if !same {
+            out.write_all(&(index as u64).to_be_bytes())?;
+            out.write_all(page)?;
+            count += 1;
+        }
+    }
+
+    // page count
+    let mut file = out.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(24))?;
+    file.write_all(&count.to_be_bytes())?;
+    file.sync_all()?;
+    Ok(count as usize)
+}
+
+/// Apply a delta file written by memzone_delta_save to a memory zone mapping the base file.
+///
+/// All the page records are checked before the first one is applied, an invalid file leaves
+/// the memory zone untouched. Returns the number of restored pages.
+pub unsafe fn memzone_delta_restore(ptr: *mut u_char, len: usize, filename: &Path) -> io::Result<usize> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", filename.display(), msg));
+    let mut input = io::BufReader::new(fs::File::open(filename)?);
+    let file_len = input.get_ref().metadata()?.len();
+    let mut header = [0_u8; 32];
+
+    input.read_exact(&mut header)?;
+    let field = |range: std::ops::Range<usize>| header[range].iter().fold(0_u64, |acc, x| (acc << 8) | *x as u64);
+    if &header[..8] != MEMZONE_DELTA_MAGIC || field(8..12) != MEMZONE_DELTA_VERSION as u64 {
+        return Err(invalid("not a delta file"));
+    }
+    let page_size = field(12..16);
+    if page_size == 0 || field(16..24) != len as u64 {
+        return Err(invalid("size mismatch"));
+    }
+    let count = field(24..32);
+
+    // check the page records
+    let mut offsets: Vec<usize> = Vec::new();
+    let mut pos = header.len() as u64;
+    let mut index = [0_u8; 8];
+    for _ in 0..count {
+        if file_len - pos < index.len() as u64 {
+            return Err(invalid("truncated file"));
+        }
+        input.read_exact(&mut index)?;
+        let offset = match u64::from_be_bytes(index).checked_mul(page_size) {
+            Some(offset) if offset < len as u64 => offset as usize,
+            _ => return Err(invalid("page out of range")),
+        };
+        let size = page_size.min((len - offset) as u64);
+        pos += index.len() as u64;
+        if file_len - pos < size {
+            return Err(invalid("truncated file"));
+        }
+        input.seek_relative(size as i64)?;
+        pos += size;
+        offsets.push(offset);
+    }
+
+    // apply them
+    input.seek(SeekFrom::Start(header.len() as u64))?;
+    for &offset in &offsets {
+        input.seek_relative(index.len() as i64)?;
+        input.read_exact(std::slice::from_raw_parts_mut(ptr.add(offset), (page_size as usize).min(len - offset)))?;
+    }
+
+    Ok(offsets.len())
+}
+
+// Save the pages of a memory zone which differ from the base file (-1 for zeros) in a delta file,
+// returns the number of saved pages
+#[no_mangle]
+pub unsafe extern "C" fn memzone_save_delta(ptr: *mut u_char, len: size_t, base_fd: c_int, filename: *mut c_char) -> ssize_t {
+    let filename = OsStr::from_bytes(CStr::from_ptr(filename).to_bytes());
+    match memzone_delta_save(ptr, len, base_fd, Path::new(filename)) {
+        Ok(count) => count as ssize_t,
+        Err(err) => {
+            memzone_report_error(c"memzone_save_delta", &err);
+            -1
+        }
+    }
+}
+
+// Apply a delta file to a memory zone, returns the number of restored pages
+#[no_mangle]
+pub unsafe extern "C" fn memzone_restore_delta(ptr: *mut u_char, len: size_t, filename: *mut c_char) -> ssize_t {
+    let filename = OsStr::from_bytes(CStr::from_ptr(filename).to_bytes());
+    match memzone_delta_restore(ptr, len, Path::new(filename)) {
+        Ok(count) => count as ssize_t,
+        Err(err) => {
+            memzone_report_error(c"memzone_restore_delta", &err);
+            -1
+        }
+    }
+}
+
 // Compute NVRAM checksum
 #[no_mangle]
 pub unsafe extern "C" fn nvram_cksum(mut ptr: *mut m_uint16_t, mut count: size_t) -> m_uint16_t {
@@ -1025,6 +2088,8 @@ pub unsafe extern "C" fn fd_pool_free(pool: *mut fd_pool_t) {
     let mut p: *mut fd_pool_t;
     let mut next: *mut fd_pool_t;
 
+    fd_pool_detach(pool);
+
     p = pool;
     while !p.is_null() {
         next = (*p).next;
@@ -1051,6 +2116,9 @@ pub unsafe extern "C" fn fd_pool_init(pool: *mut fd_pool_t) {
     }
 
     (*pool).next = null_mut();
+    (*pool).ev_loop = null_mut();
+    (*pool).cbk = None;
+    (*pool).opt = null_mut();
 }
 
 // Get a free slot for a FD in a pool
@@ -1083,7 +2151,95 @@ pub unsafe extern "C" fn fd_pool_get_free_slot(pool: *mut fd_pool_t, slot: *mut
     0
 }
 
-// Fill a FD set and get the maximum FD in order to use with select
+// Event loop callback of the FDs of a pool
+unsafe extern "C" fn fd_pool_ev_cbk(fd: c_int, _events: c_int, opt: *mut c_void) {
+    let pool: *mut fd_pool_t = opt.cast::<_>();
+    let mut p: *mut fd_pool_t = pool;
+
+    while !p.is_null() {
+        for i in 0..FD_POOL_MAX {
+            if (*p).fd[i] == fd {
+                let slot = addr_of_mut!((*p).fd[i]);
+                (*pool).cbk.expect("cbk")(slot, (*pool).opt);
+
+                // closed by the callback
+                if *slot != fd {
+                    ev_loop_del((*pool).ev_loop, fd);
+                }
+                return;
+            }
+        }
+        p = (*p).next;
+    }
+
+    // not in the pool anymore
+    ev_loop_del((*pool).ev_loop, fd);
+}
+
+// Attach a pool to an event loop, cbk is called for each FD having incoming data
+#[no_mangle]
+pub unsafe extern "C" fn fd_pool_attach(pool: *mut fd_pool_t, ev_loop: *mut ev_loop_t, cbk: fd_pool_cbk_t, opt: *mut c_void) -> c_int {
+    let mut p: *mut fd_pool_t;
+
+    fd_pool_detach(pool);
+    (*pool).ev_loop = ev_loop;
+    (*pool).cbk = cbk;
+    (*pool).opt = opt;
+
+    p = pool;
+    while !p.is_null() {
+        for i in 0..FD_POOL_MAX {
+            if (*p).fd[i] != -1 && ev_loop_add(ev_loop, (*p).fd[i], EV_READ, Some(fd_pool_ev_cbk), pool.cast::<_>()) == -1 {
+                return -1;
+            }
+        }
+        p = (*p).next;
+    }
+
+    0
+}
+
+// Detach a pool from its event loop
+#[no_mangle]
+pub unsafe extern "C" fn fd_pool_detach(pool: *mut fd_pool_t) {
+    let mut p: *mut fd_pool_t;
+
+    if (*pool).ev_loop.is_null() {
+        return;
+    }
+
+    p = pool;
+    while !p.is_null() {
+        for i in 0..FD_POOL_MAX {
+            if (*p).fd[i] != -1 {
+                ev_loop_del((*pool).ev_loop, (*p).fd[i]);
+            }
+        }
+        p = (*p).next;
+    }
+
+    (*pool).ev_loop = null_mut();
+}
+
+// Add a FD to a pool and to its event loop, returns the slot or NULL
+#[no_mangle]
+pub unsafe extern "C" fn fd_pool_add(pool: *mut fd_pool_t, fd: c_int) -> *mut c_int {
+    let mut slot: *mut c_int = null_mut();
+
+    if fd_pool_get_free_slot(pool, addr_of_mut!(slot)) == -1 {
+        return null_mut();
+    }
+
+    if !(*pool).ev_loop.is_null() && ev_loop_add((*pool).ev_loop, fd, EV_READ, Some(fd_pool_ev_cbk), pool.cast::<_>()) == -1 {
+        return null_mut();
+    }
+
+    *slot = fd;
+    slot
+}
+
+// Fill a FD set and get the maximum FD in order to use with select.
+// Limited to FD_SETSIZE, use fd_pool_attach instead.
 #[no_mangle]
 pub unsafe extern "C" fn fd_pool_set_fds(pool: *mut fd_pool_t, fds: *mut libc::fd_set) -> c_int {
     let mut p: *mut fd_pool_t;
@@ -1124,6 +2280,9 @@ pub unsafe extern "C" fn fd_pool_send(pool: *mut fd_pool_t, buffer: *mut c_void,
             res = libc::send((*p).fd[i], buffer, len, flags);
 
             if res as size_t != len {
+                if !(*pool).ev_loop.is_null() {
+                    ev_loop_del((*pool).ev_loop, (*p).fd[i]);
+                }
                 libc::shutdown((*p).fd[i], 2);
                 libc::close((*p).fd[i]);
                 (*p).fd[i] = -1;
@@ -1136,9 +2295,10 @@ pub unsafe extern "C" fn fd_pool_send(pool: *mut fd_pool_t, buffer: *mut c_void,
     err
 }
 
-// Call a function for each FD having incoming data
+// Call a function for each FD having incoming data.
+// Limited to FD_SETSIZE, use fd_pool_attach instead.
 #[no_mangle]
-pub unsafe extern "C" fn fd_pool_check_input(pool: *mut fd_pool_t, fds: *mut libc::fd_set, cbk: Option<extern "C" fn(fd_slot: *mut c_int, opt: *mut c_void)>, opt: *mut c_void) -> c_int {
+pub unsafe extern "C" fn fd_pool_check_input(pool: *mut fd_pool_t, fds: *mut libc::fd_set, cbk: fd_pool_cbk_t, opt: *mut c_void) -> c_int {
     let mut p: *mut fd_pool_t;
     let mut count: c_int;
 
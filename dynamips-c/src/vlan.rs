@@ -0,0 +1,351 @@
+//! VLAN tagging.
+//!
+//! Conversion of Ethernet frames between untagged, 802.1Q, QinQ and Cisco ISL encapsulations.
+//!
+//! Frames are handled without the Ethernet FCS, except for ISL frames which carry the
+//! FCS of the encapsulated frame at the end (see `cisco_isl_rewrite`).
+//!
+//! See: http://www.cisco.com/en/US/tech/tk389/tk390/technologies_tech_note09186a0080094665.shtml
+
+use crate::crc::*;
+use crate::dynamips_common::*;
+use crate::endian::*;
+use crate::net::*;
+use libc::size_t;
+use libc::ssize_t;
+use std::ffi::c_int;
+use std::ptr::read_unaligned;
+
+pub type vlan_tag_t = vlan_tag;
+
+/// Untagged frame.
+pub const VLAN_ENCAP_NONE: c_int = 0;
+/// 802.1Q frame, one tag.
+pub const VLAN_ENCAP_DOT1Q: c_int = 1;
+/// QinQ frame, outer tag (configurable ethertype) + 802.1Q inner tag.
+pub const VLAN_ENCAP_QINQ: c_int = 2;
+/// Cisco ISL frame.
+pub const VLAN_ENCAP_ISL: c_int = 3;
+
+/// Size of a 802.1Q tag.
+pub const VLAN_TAG_LEN: usize = 4;
+/// Size of the ISL encapsulation header (Ethernet + LLC + ISL headers).
+pub const VLAN_ISL_HDR_LEN: usize = N_ETH_HLEN + N_ISL_HDR_SIZE;
+/// Size of the ISL trailer (FCS of the encapsulated frame).
+pub const VLAN_ISL_FCS_LEN: usize = N_ETH_CRC_LEN;
+
+/// Size of the destination and source addresses.
+const ADDRS_LEN: usize = 2 * N_ETH_ALEN;
+
+/// ISL destination address, the last byte holds the frame type (0 = Ethernet) and user bits.
+const ISL_DADDR: [m_uint8_t; N_ETH_ALEN - 1] = [0x01, 0x00, 0x0c, 0x00, 0x00];
+
+/// SNAP LLC header of the ISL frames.
+const ISL_LLC: n_eth_llc_hdr_t = n_eth_llc_hdr { dsap: 0xAA, ssap: 0xAA, ctrl: 0x03 };
+
+/// Offset of the ISL header in an ISL frame.
+const ISL_HDR_OFFSET: usize = N_ETH_HLEN + size_of::<n_eth_llc_hdr_t>();
+
+/// Destination addresses of the BPDU/CDP frames that have the ISL BPDU bit set.
+const ISL_BPDU_ADDRS: [[m_uint8_t; N_ETH_ALEN]; 3] = [
+    [0x01, 0x80, 0xc2, 0x00, 0x00, 0x00], // IEEE STP
+    [0x01, 0x00, 0x0c, 0xcc, 0xcc, 0xcc], // CDP/VTP
+    [0x01, 0x00, 0x0c, 0xcc, 0xcc, 0xcd], // PVST+
+];
+
+/// VLAN information of a frame.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct vlan_tag {
+    /// Encapsulation (VLAN_ENCAP_*).
+    pub encap: c_int,
+    /// Ethertype of the outer tag (802.1Q and QinQ), 0 means N_ETH_PROTO_DOT1Q.
+    pub ethertype: m_uint16_t,
+    /// VLAN id, the outer (service) VLAN for QinQ.
+    pub vlan_id: m_uint16_t,
+    /// Inner (customer) VLAN id of QinQ frames.
+    pub inner_vlan_id: m_uint16_t,
+    /// Priority, 802.1Q PCP (0-7). ISL only carries the 2 high bits.
+    pub cos: m_uint8_t,
+}
+
+/// Parsed frame, the untagged frame is `addrs` followed by `payload`.
+#[derive(Debug, Copy, Clone)]
+pub struct VlanFrame<'a> {
+    pub tag: vlan_tag_t,
+    /// Destination and source addresses.
+    pub addrs: &'a [m_uint8_t],
+    /// Untagged payload, starting at the type/length field.
+    pub payload: &'a [m_uint8_t],
+}
+
+impl VlanFrame<'_> {
+    /// Length of the untagged frame.
+    pub fn untagged_len(&self) -> usize {
+        self.addrs.len() + self.payload.len()
+    }
+}
+
+#[inline]
+fn get_be16(pkt: &[m_uint8_t], off: usize) -> m_uint16_t {
+    m_uint16_t::from_be_bytes([pkt[off], pkt[off + 1]])
+}
+
+/// Read a packed header at `off` (the caller checks the length).
+fn read_hdr<T: Copy>(pkt: &[m_uint8_t], off: usize) -> T {
+    assert!(off + size_of::<T>() <= pkt.len());
+    unsafe { read_unaligned(pkt[off..].as_ptr().cast::<T>()) }
+}
+
+/// Bytes of a packed header.
+fn hdr_bytes<T: Copy>(hdr: &T) -> &[m_uint8_t] {
+    unsafe { std::slice::from_raw_parts((hdr as *const T).cast::<m_uint8_t>(), size_of::<T>()) }
+}
+
+#[inline]
+fn tci(vlan_id: m_uint16_t, cos: m_uint8_t) -> m_uint16_t {
+    ((cos as m_uint16_t & 0x7) << 13) | (vlan_id & 0xFFF)
+}
+
+/// Check if the ethertype is one of the tag ethertypes accepted on trunks.
+pub fn vlan_is_tag_ethertype(ethertype: m_uint16_t) -> bool {
+    matches!(ethertype, N_ETH_PROTO_DOT1Q | N_ETH_PROTO_DOT1Q_2 | N_ETH_PROTO_DOT1Q_3 | N_ETH_PROTO_DOT1Q_4)
+}
+
+/// Parse the encapsulation of a frame.
+/// Returns None if the frame is a runt or a malformed ISL frame.
+pub fn vlan_parse(pkt: &[m_uint8_t]) -> Option<VlanFrame<'_>> {
+    if pkt.len() < N_ETH_HLEN {
+        return None;
+    }
+    let mut tag = vlan_tag_t::default();
+
+    // ISL: 01:00:0c:00:00:xx + SNAP LLC
+    if pkt[..ISL_DADDR.len()] == ISL_DADDR && pkt.len() >= VLAN_ISL_HDR_LEN && hdr_bytes(&read_hdr::<n_eth_llc_hdr_t>(pkt, N_ETH_HLEN)) == hdr_bytes(&ISL_LLC) {
+        if (pkt[N_ETH_ALEN - 1] >> 4) != 0 {
+            return None; // not an Ethernet frame (Token Ring, FDDI, ATM)
+        }
+        let len = get_be16(pkt, ADDRS_LEN) as usize;
+        if len < N_ISL_HDR_SIZE + VLAN_ISL_FCS_LEN + N_ETH_HLEN || N_ETH_HLEN + len > pkt.len() {
+            return None;
+        }
+        let inner = &pkt[VLAN_ISL_HDR_LEN..N_ETH_HLEN + len - VLAN_ISL_FCS_LEN];
+
+        tag.encap = VLAN_ENCAP_ISL;
+        tag.vlan_id = read_hdr::<n_eth_isl_hdr_t>(pkt, ISL_HDR_OFFSET).vlan.get() >> 1;
+        tag.cos = (pkt[N_ETH_ALEN - 1] & 0x03) << 1;
+        return Some(VlanFrame { tag, addrs: &inner[..ADDRS_LEN], payload: &inner[ADDRS_LEN..] });
+    }
+
+    let ethertype = get_be16(pkt, ADDRS_LEN);
+    if !vlan_is_tag_ethertype(ethertype) || pkt.len() < N_ETH_HLEN + VLAN_TAG_LEN {
+        return Some(VlanFrame { tag, addrs: &pkt[..ADDRS_LEN], payload: &pkt[ADDRS_LEN..] });
+    }
+
+    let outer = get_be16(pkt, ADDRS_LEN + 2);
+    tag.ethertype = ethertype;
+    tag.vlan_id = outer & 0xFFF;
+    tag.cos = (outer >> 13) as m_uint8_t;
+
+    if pkt.len() >= N_ETH_HLEN + 2 * VLAN_TAG_LEN && get_be16(pkt, ADDRS_LEN + VLAN_TAG_LEN) == N_ETH_PROTO_DOT1Q {
+        tag.encap = VLAN_ENCAP_QINQ;
+        tag.inner_vlan_id = get_be16(pkt, ADDRS_LEN + VLAN_TAG_LEN + 2) & 0xFFF;
+        return Some(VlanFrame { tag, addrs: &pkt[..ADDRS_LEN], payload: &pkt[ADDRS_LEN + 2 * VLAN_TAG_LEN..] });
+    }
+
+    tag.encap = VLAN_ENCAP_DOT1Q;
+    Some(VlanFrame { tag, addrs: &pkt[..ADDRS_LEN], payload: &pkt[ADDRS_LEN + VLAN_TAG_LEN..] })
+}
+
+/// Size of the encapsulated frame for an untagged frame of `untagged_len` bytes.
+pub fn vlan_encap_len(untagged_len: usize, tag: &vlan_tag_t) -> usize {
+    match tag.encap {
+        VLAN_ENCAP_DOT1Q => untagged_len + VLAN_TAG_LEN,
+        VLAN_ENCAP_QINQ => untagged_len + 2 * VLAN_TAG_LEN,
+        VLAN_ENCAP_ISL => VLAN_ISL_HDR_LEN + untagged_len + VLAN_ISL_FCS_LEN,
+        _ => untagged_len,
+    }
+}
+
+/// Build a frame with the specified encapsulation from the untagged frame `addrs` + `payload`.
+/// Returns the frame length or None if `out` is too small or the encapsulation is unknown.
+pub fn vlan_build(out: &mut [m_uint8_t], addrs: &[m_uint8_t], payload: &[m_uint8_t], tag: &vlan_tag_t) -> Option<usize> {
+    let untagged_len = addrs.len() + payload.len();
+    let total = vlan_encap_len(untagged_len, tag);
+    if addrs.len() != ADDRS_LEN || total > out.len() || !(VLAN_ENCAP_NONE..=VLAN_ENCAP_ISL).contains(&tag.encap) {
+        return None;
+    }
+    let ethertype = if tag.ethertype != 0 { tag.ethertype } else { N_ETH_PROTO_DOT1Q };
+
+    let mut off = 0;
+    let mut put = |data: &[m_uint8_t]| {
+        out[off..off + data.len()].copy_from_slice(data);
+        off += data.len();
+    };
+
+    match tag.encap {
+        VLAN_ENCAP_DOT1Q | VLAN_ENCAP_QINQ => {
+            put(addrs);
+            put(&ethertype.to_be_bytes());
+            put(&tci(tag.vlan_id, tag.cos).to_be_bytes());
+            if tag.encap == VLAN_ENCAP_QINQ {
+                put(&N_ETH_PROTO_DOT1Q.to_be_bytes());
+                put(&tci(tag.inner_vlan_id, tag.cos).to_be_bytes());
+            }
+            put(payload);
+        }
+        VLAN_ENCAP_ISL => {
+            let bpdu = ISL_BPDU_ADDRS.iter().any(|a| a[..] == addrs[..N_ETH_ALEN]) as m_uint16_t;
+            let saddr = &addrs[N_ETH_ALEN..];
+            let isl = n_eth_isl_hdr_t { hsa1: Be16::new(get_be16(saddr, 0)), hsa2: saddr[2], vlan: Be16::new(((tag.vlan_id & 0x7FFF) << 1) | bpdu), index: Be16::new(0), res: Be16::new(0) };
+
+            put(&ISL_DADDR);
+            put(&[(tag.cos >> 1) & 0x03]); // type 0 (Ethernet) + user bits
+            put(saddr);
+            put(&((N_ISL_HDR_SIZE + untagged_len + VLAN_ISL_FCS_LEN) as m_uint16_t).to_be_bytes());
+            put(hdr_bytes(&ISL_LLC));
+            put(hdr_bytes(&isl));
+            put(addrs);
+            put(payload);
+
+            let start = VLAN_ISL_HDR_LEN;
+            let ifcs = unsafe { crc32_compute(0xFFFFFFFF, out[start..].as_mut_ptr(), untagged_len as c_int) };
+            out[start + untagged_len..total].copy_from_slice(&ifcs.to_le_bytes());
+        }
+        _ => {
+            out[..ADDRS_LEN].copy_from_slice(addrs);
+            out[ADDRS_LEN..untagged_len].copy_from_slice(payload);
+        }
+    }
+
+    Some(total)
+}
+
+/// Compute the target tag of a translation, see `vlan_translate`.
+pub fn vlan_translate_tag(from: &vlan_tag_t, to: &vlan_tag_t) -> vlan_tag_t {
+    // VLAN seen by the customer side
+    let customer_vlan = match from.encap {
+        VLAN_ENCAP_DOT1Q | VLAN_ENCAP_ISL => Some(from.vlan_id),
+        VLAN_ENCAP_QINQ => Some(from.inner_vlan_id),
+        _ => None,
+    };
+    let mut tag = vlan_tag_t { encap: to.encap, ethertype: to.ethertype, cos: from.cos, ..Default::default() };
+
+    match (to.encap, customer_vlan) {
+        (VLAN_ENCAP_QINQ, Some(vlan_id)) => {
+            tag.vlan_id = to.vlan_id;
+            tag.inner_vlan_id = vlan_id;
+        }
+        (VLAN_ENCAP_QINQ, None) => {
+            // untagged customer traffic only gets the outer tag
+            tag.encap = VLAN_ENCAP_DOT1Q;
+            tag.vlan_id = to.vlan_id;
+        }
+        (VLAN_ENCAP_DOT1Q | VLAN_ENCAP_ISL, vlan_id) => {
+            tag.vlan_id = vlan_id.unwrap_or(to.vlan_id);
+        }
+        _ => {}
+    }
+    tag
+}
+
+unsafe fn slice<'a>(pkt: *const m_uint8_t, len: size_t) -> &'a [m_uint8_t] {
+    if pkt.is_null() || len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(pkt, len)
+    }
+}
+
+unsafe fn slice_mut<'a>(pkt: *mut m_uint8_t, len: size_t) -> &'a mut [m_uint8_t] {
+    if pkt.is_null() || len == 0 {
+        &mut []
+    } else {
+        std::slice::from_raw_parts_mut(pkt, len)
+    }
+}
+
+// Get the VLAN information of a frame
+#[no_mangle]
+pub unsafe extern "C" fn vlan_decode(pkt: *const m_uint8_t, len: size_t, tag: *mut vlan_tag_t) -> c_int {
+    match vlan_parse(slice(pkt, len)) {
+        Some(frame) => {
+            *tag = frame.tag;
+            0
+        }
+        None => -1,
+    }
+}
+
+// Encapsulate an untagged frame, returns the new length or -1
+#[no_mangle]
+pub unsafe extern "C" fn vlan_encap(pkt: *const m_uint8_t, len: size_t, tag: *const vlan_tag_t, out: *mut m_uint8_t, out_size: size_t) -> ssize_t {
+    let pkt = slice(pkt, len);
+    if pkt.len() < N_ETH_HLEN {
+        return -1;
+    }
+    match vlan_build(slice_mut(out, out_size), &pkt[..ADDRS_LEN], &pkt[ADDRS_LEN..], &*tag) {
+        Some(n) => n as ssize_t,
+        None => -1,
+    }
+}
+
+// Remove the encapsulation of a frame, returns the untagged length or -1.
+// The VLAN information is stored in tag if not NULL.
+#[no_mangle]
+pub unsafe extern "C" fn vlan_decap(pkt: *const m_uint8_t, len: size_t, tag: *mut vlan_tag_t, out: *mut m_uint8_t, out_size: size_t) -> ssize_t {
+    let Some(frame) = vlan_parse(slice(pkt, len)) else {
+        return -1;
+    };
+    if !tag.is_null() {
+        *tag = frame.tag;
+    }
+    match vlan_build(slice_mut(out, out_size), frame.addrs, frame.payload, &vlan_tag_t::default()) {
+        Some(n) => n as ssize_t,
+        None => -1,
+    }
+}
+
+// Translate the encapsulation of a frame, returns the new length or -1.
+//
+// The customer VLAN (the 802.1Q/ISL VLAN, or the inner VLAN of QinQ) is kept.
+// to->vlan_id is the outer VLAN for QinQ, or the VLAN of untagged frames.
+#[no_mangle]
+pub unsafe extern "C" fn vlan_translate(pkt: *const m_uint8_t, len: size_t, to: *const vlan_tag_t, out: *mut m_uint8_t, out_size: size_t) -> ssize_t {
+    let Some(frame) = vlan_parse(slice(pkt, len)) else {
+        return -1;
+    };
+    let tag = vlan_translate_tag(&frame.tag, &*to);
+    match vlan_build(slice_mut(out, out_size), frame.addrs, frame.payload, &tag) {
+        Some(n) => n as ssize_t,
+        None => -1,
+    }
+}
+
+// Push a 802.1Q tag (tci = VLAN id + CoS) in front of the existing tags, returns the new length or -1
+#[no_mangle]
+pub unsafe extern "C" fn vlan_push_tag(pkt: *const m_uint8_t, len: size_t, tci: m_uint16_t, ethertype: m_uint16_t, out: *mut m_uint8_t, out_size: size_t) -> ssize_t {
+    let pkt = slice(pkt, len);
+    let out = slice_mut(out, out_size);
+    if pkt.len() < N_ETH_HLEN || out.len() < pkt.len() + VLAN_TAG_LEN {
+        return -1;
+    }
+    out[..ADDRS_LEN].copy_from_slice(&pkt[..ADDRS_LEN]);
+    out[ADDRS_LEN..ADDRS_LEN + 2].copy_from_slice(&ethertype.to_be_bytes());
+    out[ADDRS_LEN + 2..ADDRS_LEN + 4].copy_from_slice(&tci.to_be_bytes());
+    out[ADDRS_LEN + VLAN_TAG_LEN..pkt.len() + VLAN_TAG_LEN].copy_from_slice(&pkt[ADDRS_LEN..]);
+    (pkt.len() + VLAN_TAG_LEN) as ssize_t
+}
+
+// Pop the outer 802.1Q tag, returns the new length or -1
+#[no_mangle]
+pub unsafe extern "C" fn vlan_pop_tag(pkt: *const m_uint8_t, len: size_t, out: *mut m_uint8_t, out_size: size_t) -> ssize_t {
+    let pkt = slice(pkt, len);
+    let out = slice_mut(out, out_size);
+    if pkt.len() < N_ETH_HLEN + VLAN_TAG_LEN || out.len() < pkt.len() - VLAN_TAG_LEN {
+        return -1;
+    }
+    out[..ADDRS_LEN].copy_from_slice(&pkt[..ADDRS_LEN]);
+    out[ADDRS_LEN..pkt.len() - VLAN_TAG_LEN].copy_from_slice(&pkt[ADDRS_LEN + VLAN_TAG_LEN..]);
+    (pkt.len() - VLAN_TAG_LEN) as ssize_t
+}
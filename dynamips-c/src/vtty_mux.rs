@@ -0,0 +1,316 @@
+//! Console multiplexer for the TCP virtual TTYs.
+//!
+//! Several telnet clients can be connected to a console port:
+//!  * one read-write owner, whose keystrokes are sent to the VM;
+//!  * any number of read-only observers, who get the console output but
+//!    whose keystrokes are dropped.
+//!
+//! The first client connected to a free console becomes the owner. An
+//! observer takes over the console by pressing Ctrl-T and confirming with
+//! 'y', the previous owner becomes an observer. A notice is sent to the
+//! clients when a client connects or disconnects and when control changes.
+//!
+//! The sockets stay in the FD pool of the vtty (output is sent to every
+//! client), the multiplexer keeps the role and the telnet input state of
+//! each client. It is only modified by the vtty thread, so the input state
+//! returned by `vtty_mux_get_input` stays valid while the client is there.
+
+use crate::_extra::*;
+use crate::utils::*;
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::c_void;
+use std::ffi::CString;
+use std::mem::ManuallyDrop;
+use std::net::TcpStream;
+use std::os::fd::FromRawFd;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+// Client roles
+pub const VTTY_MUX_OWNER: c_int = 0;
+pub const VTTY_MUX_OBSERVER: c_int = 1;
+
+// Take-over request of an observer (Ctrl-T)
+pub const VTTY_MUX_TAKEOVER_KEY: u_char = 0x14;
+
+/// Telnet input state of a client (see the VTTY_INPUT_* states of dev_vtty.h).
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct vtty_input {
+    pub state: c_int,
+    pub telnet_cmd: c_int,
+    pub telnet_opt: c_int,
+    pub telnet_qual: c_int,
+}
+pub type vtty_input_t = vtty_input;
+
+/// A client of a console.
+#[derive(Debug)]
+struct MuxClient {
+    fd: c_int,
+    role: c_int,
+    peer: String,
+    /// Boxed to keep its address when the client list changes
+    input: Box<vtty_input_t>,
+    /// Waiting for the take-over confirmation
+    takeover: bool,
+}
+
+/// Console multiplexer state.
+#[derive(Debug, Default)]
+pub struct VttyMux {
+    clients: Vec<MuxClient>,
+}
+
+/// Address of the peer of a socket ("fd N" if not a TCP socket).
+fn vtty_mux_peer_name(fd: c_int) -> String {
+    // borrow the socket, it is closed by the FD pool
+    let sock = ManuallyDrop::new(unsafe { TcpStream::from_raw_fd(fd) });
+    match sock.peer_addr() {
+        Ok(addr) => addr.to_string(),
+        Err(_) => format!("fd {}", fd),
+    }
+}
+
+/// Send a message to a client, errors are detected by the FD pool.
+fn vtty_mux_send(fd: c_int, msg: &str) {
+    unsafe { libc::send(fd, msg.as_ptr().cast::<c_void>(), msg.len(), 0) };
+}
+
+impl VttyMux {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn find(&self, fd: c_int) -> Option<usize> {
+        self.clients.iter().position(|cl| cl.fd == fd)
+    }
+
+    fn owner(&self) -> Option<usize> {
+        self.clients.iter().position(|cl| cl.role == VTTY_MUX_OWNER)
+    }
+
+    /// Send a notice to every client, except the one using `skip`.
+    fn notice(&self, skip: c_int, msg: &str) {
+        let msg = format!("\r\n*** {} ***\r\n", msg);
+        for cl in self.clients.iter().filter(|cl| cl.fd != skip) {
+            vtty_mux_send(cl.fd, &msg);
+        }
+    }
+
+    /// Number of clients.
+    pub fn count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Role of a client.
+    pub fn role(&self, fd: c_int) -> Option<c_int> {
+        self.find(fd).map(|i| self.clients[i].role)
+    }
+
+    /// Clients (FD, role, peer address), in connection order.
+    pub fn clients(&self) -> Vec<(c_int, c_int, String)> {
+        self.clients.iter().map(|cl| (cl.fd, cl.role, cl.peer.clone())).collect()
+    }
+
+    /// Input state of a client.
+    pub fn input(&mut self, fd: c_int) -> Option<&mut vtty_input_t> {
+        let i = self.find(fd)?;
+        Some(&mut self.clients[i].input)
+    }
+
+    /// Add a client, it is the owner if the console is free. Returns the role.
+    pub fn add(&mut self, fd: c_int, peer: &str) -> c_int {
+        // the FD of a client closed by the FD pool can be reused
+        self.remove(fd);
+
+        let owner = self.owner();
+        let role = if owner.is_some() { VTTY_MUX_OBSERVER } else { VTTY_MUX_OWNER };
+        let kind = if role == VTTY_MUX_OWNER { "read-write" } else { "read-only" };
+        self.notice(fd, &format!("{} connected ({})", peer, kind));
+
+        if let Some(i) = owner {
+            vtty_mux_send(fd, &format!("\r\n*** Read-only console, {} has control. Press Ctrl-T to take over ***\r\n", self.clients[i].peer));
+        }
+
+        self.clients.push(MuxClient { fd, role, peer: peer.to_string(), input: Box::default(), takeover: false });
+        role
+    }
+
+    /// Remove a client, returns false if unknown.
+    pub fn remove(&mut self, fd: c_int) -> bool {
+        let Some(i) = self.find(fd) else {
+            return false;
+        };
+        let cl = self.clients.remove(i);
+        if cl.role == VTTY_MUX_OWNER {
+            self.notice(-1, &format!("Console released by {}. Press Ctrl-T to take control", cl.peer));
+        } else {
+            self.notice(-1, &format!("{} disconnected", cl.peer));
+        }
+        true
+    }
+
+    /// Remove the clients for which `is_open` returns false.
+    pub fn sync(&mut self, is_open: impl Fn(c_int) -> bool) {
+        let closed: Vec<c_int> = self.clients.iter().filter(|cl| !is_open(cl.fd)).map(|cl| cl.fd).collect();
+        for fd in closed {
+            self.remove(fd);
+        }
+    }
+
+    /// Give the control of the console to a client.
+    pub fn take_over(&mut self, fd: c_int) -> bool {
+        let Some(i) = self.find(fd) else {
+            return false;
+        };
+        if self.clients[i].role == VTTY_MUX_OWNER {
+            return true;
+        }
+
+        let peer = &self.clients[i].peer;
+        let msg = match self.owner() {
+            Some(o) => format!("Console control taken over by {} from {}", peer, self.clients[o].peer),
+            None => format!("Console control taken by {}", peer),
+        };
+
+        if let Some(o) = self.owner() {
+            let old = &mut self.clients[o];
+            old.role = VTTY_MUX_OBSERVER;
+            // drop an escape sequence being received
+            *old.input = vtty_input_t::default();
+        }
+
+        let cl = &mut self.clients[i];
+        cl.role = VTTY_MUX_OWNER;
+        cl.takeover = false;
+        self.notice(-1, &msg);
+        true
+    }
+
+    /// Handle a keystroke of an observer (take-over handshake).
+    pub fn observer_input(&mut self, fd: c_int, c: u_char) {
+        let Some(i) = self.find(fd) else {
+            return;
+        };
+        if self.clients[i].role == VTTY_MUX_OWNER {
+            return;
+        }
+
+        if self.clients[i].takeover {
+            if c == b'y' || c == b'Y' {
+                vtty_mux_send(fd, "y\r\n");
+                self.take_over(fd);
+            } else {
+                self.clients[i].takeover = false;
+                vtty_mux_send(fd, "\r\n*** Take-over cancelled ***\r\n");
+            }
+            return;
+        }
+
+        match c {
+            VTTY_MUX_TAKEOVER_KEY => {
+                let msg = match self.owner() {
+                    Some(o) => format!("\r\nTake over the console from {}? [y/n] ", self.clients[o].peer),
+                    None => "\r\nTake control of the console? [y/n] ".to_string(),
+                };
+                self.clients[i].takeover = true;
+                vtty_mux_send(fd, &msg);
+            }
+            b'\r' => vtty_mux_send(fd, "\r\n*** Read-only console. Press Ctrl-T to take over ***\r\n"),
+            _ => {}
+        }
+    }
+}
+
+/// C wrapper, used by the vtty thread and by the hypervisor.
+pub struct vtty_mux {
+    mux: Mutex<VttyMux>,
+}
+pub type vtty_mux_t = vtty_mux;
+
+impl vtty_mux {
+    fn lock(&self) -> MutexGuard<'_, VttyMux> {
+        self.mux.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Client list callback
+pub type vtty_mux_client_cbk_t = Option<unsafe extern "C" fn(fd: c_int, role: c_int, peer: *const c_char, opt: *mut c_void)>;
+
+// Create a console multiplexer
+#[no_mangle]
+pub extern "C" fn vtty_mux_create() -> *mut vtty_mux_t {
+    Box::into_raw(Box::new(vtty_mux { mux: Mutex::new(VttyMux::new()) }))
+}
+
+// Free a console multiplexer (the sockets belong to the FD pool)
+#[no_mangle]
+pub unsafe extern "C" fn vtty_mux_free(mux: *mut vtty_mux_t) {
+    if !mux.is_null() {
+        drop(Box::from_raw(mux));
+    }
+}
+
+// Add a client, returns its role
+#[no_mangle]
+pub unsafe extern "C" fn vtty_mux_add(mux: *mut vtty_mux_t, fd: c_int) -> c_int {
+    let peer = vtty_mux_peer_name(fd);
+    (*mux).lock().add(fd, &peer)
+}
+
+// Remove a client
+#[no_mangle]
+pub unsafe extern "C" fn vtty_mux_remove(mux: *mut vtty_mux_t, fd: c_int) {
+    (*mux).lock().remove(fd);
+}
+
+// Remove the clients closed by the FD pool (send errors)
+#[no_mangle]
+pub unsafe extern "C" fn vtty_mux_sync(mux: *mut vtty_mux_t, pool: *mut fd_pool_t) {
+    (*mux).lock().sync(|fd| {
+        let mut p = pool;
+        while !p.is_null() {
+            if (*p).fd.contains(&fd) {
+                return true;
+            }
+            p = (*p).next;
+        }
+        false
+    });
+}
+
+// Get the telnet input state of a client (NULL if unknown)
+#[no_mangle]
+pub unsafe extern "C" fn vtty_mux_get_input(mux: *mut vtty_mux_t, fd: c_int) -> *mut vtty_input_t {
+    match (*mux).lock().input(fd) {
+        Some(input) => input,
+        None => std::ptr::null_mut(),
+    }
+}
+
+// Returns TRUE if the client has the control of the console
+#[no_mangle]
+pub unsafe extern "C" fn vtty_mux_is_owner(mux: *mut vtty_mux_t, fd: c_int) -> c_int {
+    ((*mux).lock().role(fd) == Some(VTTY_MUX_OWNER)) as c_int
+}
+
+// Handle a keystroke of a read-only client
+#[no_mangle]
+pub unsafe extern "C" fn vtty_mux_observer_input(mux: *mut vtty_mux_t, fd: c_int, c: u_char) {
+    (*mux).lock().observer_input(fd, c);
+}
+
+// Call a function for each client, returns the number of clients
+#[no_mangle]
+pub unsafe extern "C" fn vtty_mux_get_clients(mux: *mut vtty_mux_t, cbk: vtty_mux_client_cbk_t, opt: *mut c_void) -> c_int {
+    let clients = (*mux).lock().clients();
+    if let Some(cbk) = cbk {
+        for (fd, role, peer) in &clients {
+            let peer = CString::new(peer.as_str()).unwrap_or_default();
+            cbk(*fd, *role, peer.as_ptr(), opt);
+        }
+    }
+    clients.len() as c_int
+}
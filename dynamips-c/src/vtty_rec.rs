@@ -0,0 +1,457 @@
+//! Console recorder for the virtual TTYs.
+//!
+//! The recorder is fed with the bytes written to a console port and keeps:
+//!  * a scrollback buffer (the last bytes), replayed to the telnet clients
+//!    when they connect and used to get the last lines of the console;
+//!  * optional recordings in files, in asciicast v2 format (playable with
+//!    asciinema) and/or as a plain log with a timestamp at the start of each
+//!    line, both with size based rotation.
+//!
+//! Output is batched and written when the vtty thread flushes the recorder,
+//! when a batch reaches `VTTY_REC_BATCH_SIZE` bytes or when it is older than
+//! `VTTY_REC_BATCH_ITV`. The time of a batch is the time of its first byte,
+//! taken from `m_gettime_usec` (usecs since epoch).
+//!
+//! The recorder belongs to the VM, so the scrollback and the recordings are
+//! kept when the VM is restarted.
+
+use crate::_extra::*;
+use crate::dynamips_common::*;
+use crate::utils::*;
+use libc::size_t;
+use libc::ssize_t;
+use std::collections::VecDeque;
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::c_void;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+// Recording formats
+pub const VTTY_REC_ASCIICAST: c_int = 0;
+pub const VTTY_REC_LOG: c_int = 1;
+pub const VTTY_REC_FORMAT_MAX: c_int = 2;
+
+// Default scrollback size (bytes)
+pub const VTTY_REC_DEFAULT_SCROLLBACK: size_t = 4096;
+
+// Maximum scrollback size (bytes)
+pub const VTTY_REC_MAX_SCROLLBACK: size_t = 16 * 1048576;
+
+// Maximum size of a batch (bytes)
+pub const VTTY_REC_BATCH_SIZE: usize = 4096;
+
+// Maximum age of a batch (usecs)
+pub const VTTY_REC_BATCH_ITV: m_tmcnt_t = 1000000;
+
+// Terminal size announced in the asciicast header
+pub const VTTY_REC_WIDTH: c_int = 80;
+pub const VTTY_REC_HEIGHT: c_int = 24;
+
+/// Name of a recording format.
+pub fn vtty_rec_format_name(format: c_int) -> &'static str {
+    match format {
+        VTTY_REC_ASCIICAST => "asciicast",
+        VTTY_REC_LOG => "log",
+        _ => "unknown",
+    }
+}
+
+/// A recording in a file.
+#[derive(Debug)]
+struct RecFile {
+    format: c_int,
+    filename: String,
+    title: String,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: u_int,
+    /// Time base of the asciicast events
+    start: m_tmcnt_t,
+    /// Incomplete UTF-8 sequence of the previous batch (asciicast)
+    utf8_tail: Vec<u8>,
+    /// Next byte starts a line (log)
+    line_start: bool,
+}
+
+impl RecFile {
+    fn open(format: c_int, filename: &str, title: &str, max_size: u64, max_files: u_int, now: m_tmcnt_t) -> io::Result<Self> {
+        // a cast needs its header, a log is continued
+        let file = OpenOptions::new().create(true).write(true).append(format == VTTY_REC_LOG).truncate(format == VTTY_REC_ASCIICAST).open(filename)?;
+        let size = file.metadata()?.len();
+        let mut rf = Self { format, filename: filename.to_string(), title: title.to_string(), file, size, max_size, max_files, start: now, utf8_tail: Vec::new(), line_start: true };
+        rf.header(now)?;
+        Ok(rf)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    fn header(&mut self, now: m_tmcnt_t) -> io::Result<()> {
+        self.start = now;
+        match self.format {
+            VTTY_REC_ASCIICAST => {
+                let mut hdr = format!("{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {}, \"title\": ", VTTY_REC_WIDTH, VTTY_REC_HEIGHT, now / 1000000);
+                m_log_json_str(&mut hdr, &self.title);
+                hdr.push_str("}\n");
+                self.write(hdr.as_bytes())
+            }
+            _ => {
+                let line = format!("{} --- {} recording started ---\n", m_log_timestamp_usec(now), self.title);
+                self.write(line.as_bytes())
+            }
+        }
+    }
+
+    fn event(&mut self, time: m_tmcnt_t, code: &str, data: &str) -> io::Result<()> {
+        let secs = time.saturating_sub(self.start);
+        let mut ev = format!("[{}.{:06}, \"{}\", ", secs / 1000000, secs % 1000000, code);
+        m_log_json_str(&mut ev, data);
+        ev.push_str("]\n");
+        self.write(ev.as_bytes())
+    }
+
+    /// Record console output.
+    fn output(&mut self, time: m_tmcnt_t, data: &[u8]) -> io::Result<()> {
+        match self.format {
+            VTTY_REC_ASCIICAST => {
+                let mut buf = std::mem::take(&mut self.utf8_tail);
+                buf.extend_from_slice(data);
+
+                // keep an incomplete sequence at the end for the next batch
+                let keep = match std::str::from_utf8(&buf) {
+                    Err(err) if err.error_len().is_none() => buf.len() - err.valid_up_to(),
+                    _ => 0,
+                };
+                self.utf8_tail = buf.split_off(buf.len() - keep);
+                if buf.is_empty() {
+                    return Ok(());
+                }
+                self.event(time, "o", &String::from_utf8_lossy(&buf))
+            }
+            _ => {
+                let ts = m_log_timestamp_usec(time);
+                let mut out = Vec::with_capacity(data.len() + 32);
+                for &c in data {
+                    if c == b'\r' || c == 0 || c == 0x07 {
+                        continue;
+                    }
+                    if self.line_start {
+                        out.extend_from_slice(ts.as_bytes());
+                        out.push(b' ');
+                        self.line_start = false;
+                    }
+                    out.push(c);
+                    if c == b'\n' {
+                        self.line_start = true;
+                    }
+                }
+                self.write(&out)
+            }
+        }
+    }
+
+    /// Record a marker (VM started, stopped...).
+    fn marker(&mut self, time: m_tmcnt_t, msg: &str) -> io::Result<()> {
+        match self.format {
+            VTTY_REC_ASCIICAST => self.event(time, "m", msg),
+            _ => {
+                let mut line = String::new();
+                if !self.line_start {
+                    line.push('\n');
+                    self.line_start = true;
+                }
+                line.push_str(&format!("{} --- {} ---\n", m_log_timestamp_usec(time), msg));
+                self.write(line.as_bytes())
+            }
+        }
+    }
+
+    /// Rotate the file if it reached the maximum size.
+    fn check_rotation(&mut self, now: m_tmcnt_t) -> io::Result<()> {
+        if self.max_size == 0 || self.size < self.max_size {
+            return Ok(());
+        }
+        m_log_rotate_files(&self.filename, self.max_files);
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.filename)?;
+        self.size = 0;
+        self.line_start = true;
+        self.header(now)
+    }
+}
+
+/// Console recorder state.
+#[derive(Debug)]
+pub struct VttyRec {
+    scrollback: VecDeque<u8>,
+    scrollback_size: usize,
+    pending: Vec<u8>,
+    pending_time: m_tmcnt_t,
+    files: Vec<RecFile>,
+    bytes: u64,
+}
+
+impl VttyRec {
+    pub fn new(scrollback_size: usize) -> Self {
+        Self { scrollback: VecDeque::with_capacity(scrollback_size), scrollback_size, pending: Vec::new(), pending_time: 0, files: Vec::new(), bytes: 0 }
+    }
+
+    /// Change the scrollback size, the oldest bytes are dropped.
+    pub fn set_scrollback_size(&mut self, size: usize) {
+        self.scrollback_size = size;
+        let excess = self.scrollback.len().saturating_sub(size);
+        self.scrollback.drain(..excess);
+        self.scrollback.shrink_to(size);
+    }
+
+    pub fn scrollback_size(&self) -> usize {
+        self.scrollback_size
+    }
+
+    /// Content of the scrollback.
+    pub fn scrollback(&self) -> Vec<u8> {
+        self.scrollback.iter().copied().collect()
+    }
+
+    /// Last lines of the scrollback (carriage returns and control characters
+    /// other than tabs removed), the current line is included if not empty.
+    pub fn tail(&self, count: usize) -> Vec<String> {
+        let text: Vec<u8> = self.scrollback.iter().copied().filter(|&c| c == b'\n' || c == b'\t' || c >= 0x20).collect();
+        let mut lines: Vec<String> = text.split(|&c| c == b'\n').map(|l| String::from_utf8_lossy(l).into_owned()).collect();
+        if lines.last().is_some_and(|l| l.is_empty()) {
+            lines.pop();
+        }
+        let skip = lines.len().saturating_sub(count);
+        lines.split_off(skip)
+    }
+
+    /// Number of bytes written to the console.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Record console output.
+    pub fn put(&mut self, data: &[u8], now: m_tmcnt_t) {
+        self.bytes += data.len() as u64;
+
+        if self.scrollback_size > 0 {
+            let data = &data[data.len().saturating_sub(self.scrollback_size)..];
+            let excess = (self.scrollback.len() + data.len()).saturating_sub(self.scrollback_size);
+            self.scrollback.drain(..excess);
+            self.scrollback.extend(data);
+        }
+
+        if self.files.is_empty() {
+            return;
+        }
+        if self.pending.is_empty() {
+            self.pending_time = now;
+        }
+        self.pending.extend_from_slice(data);
+        if self.pending.len() >= VTTY_REC_BATCH_SIZE || now.saturating_sub(self.pending_time) >= VTTY_REC_BATCH_ITV {
+            self.flush(now);
+        }
+    }
+
+    // Apply an operation to the recordings, a recording that fails is stopped
+    fn each_file(&mut self, mut f: impl FnMut(&mut RecFile) -> io::Result<()>) {
+        self.files.retain_mut(|rf| match f(rf) {
+            Ok(()) => true,
+            Err(err) => {
+                let msg = CString::new(format!("vtty_rec: {}: {}, recording stopped\n", rf.filename, err)).unwrap_or_default();
+                unsafe { libc::fputs(msg.as_ptr(), c_stderr()) };
+                false
+            }
+        });
+    }
+
+    /// Write the pending output to the recordings.
+    pub fn flush(&mut self, now: m_tmcnt_t) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let (data, time) = (std::mem::take(&mut self.pending), self.pending_time);
+        self.each_file(|rf| {
+            rf.output(time, &data)?;
+            rf.check_rotation(now)
+        });
+    }
+
+    /// Write a marker in the recordings.
+    pub fn marker(&mut self, msg: &str, now: m_tmcnt_t) {
+        self.flush(now);
+        self.each_file(|rf| rf.marker(now, msg));
+    }
+
+    /// Start a recording, replaces the one of the same format.
+    pub fn start(&mut self, format: c_int, filename: &str, title: &str, max_size: u64, max_files: u_int, now: m_tmcnt_t) -> io::Result<()> {
+        if !(0..VTTY_REC_FORMAT_MAX).contains(&format) {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        self.stop(format, now);
+        self.files.push(RecFile::open(format, filename, title, max_size, max_files, now)?);
+        Ok(())
+    }
+
+    /// Stop a recording, returns false if there was none.
+    pub fn stop(&mut self, format: c_int, now: m_tmcnt_t) -> bool {
+        self.flush(now);
+        let count = self.files.len();
+        self.files.retain(|rf| rf.format != format);
+        count != self.files.len()
+    }
+
+    /// File name of a recording.
+    pub fn filename(&self, format: c_int) -> Option<&str> {
+        self.files.iter().find(|rf| rf.format == format).map(|rf| rf.filename.as_str())
+    }
+}
+
+/// C wrapper, the recorder is fed by a CPU thread and flushed by the vtty thread.
+pub struct vtty_rec {
+    rec: Mutex<VttyRec>,
+}
+pub type vtty_rec_t = vtty_rec;
+
+impl vtty_rec {
+    fn lock(&self) -> MutexGuard<'_, VttyRec> {
+        self.rec.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// "tail" callback
+pub type vtty_rec_line_cbk_t = Option<unsafe extern "C" fn(line: *const c_char, opt: *mut c_void)>;
+
+// Create a recorder with the specified scrollback size
+#[no_mangle]
+pub extern "C" fn vtty_rec_create(scrollback: size_t) -> *mut vtty_rec_t {
+    let scrollback = scrollback.min(VTTY_REC_MAX_SCROLLBACK);
+    Box::into_raw(Box::new(vtty_rec { rec: Mutex::new(VttyRec::new(scrollback)) }))
+}
+
+// Free a recorder (the pending output is written)
+#[no_mangle]
+pub unsafe extern "C" fn vtty_rec_free(rec: *mut vtty_rec_t) {
+    if !rec.is_null() {
+        (*rec).lock().flush(m_gettime_usec());
+        drop(Box::from_raw(rec));
+    }
+}
+
+// Record console output
+#[no_mangle]
+pub unsafe extern "C" fn vtty_rec_put(rec: *mut vtty_rec_t, data: *const u_char, len: size_t) {
+    (*rec).lock().put(std::slice::from_raw_parts(data, len), m_gettime_usec());
+}
+
+// Write the pending output to the recordings
+#[no_mangle]
+pub unsafe extern "C" fn vtty_rec_flush(rec: *mut vtty_rec_t) {
+    (*rec).lock().flush(m_gettime_usec());
+}
+
+// Write a marker in the recordings
+#[no_mangle]
+pub unsafe extern "C" fn vtty_rec_marker(rec: *mut vtty_rec_t, msg: *const c_char) {
+    (*rec).lock().marker(&CStr::from_ptr(msg).to_string_lossy(), m_gettime_usec());
+}
+
+// Get a recording format given its name (-1 if unknown)
+#[no_mangle]
+pub unsafe extern "C" fn vtty_rec_get_format(name: *const c_char) -> c_int {
+    match CStr::from_ptr(name).to_bytes() {
+        b"asciicast" => VTTY_REC_ASCIICAST,
+        b"log" => VTTY_REC_LOG,
+        _ => -1,
+    }
+}
+
+// Start a recording in a file (max_size 0 to disable the rotation), returns -1 on error
+#[no_mangle]
+pub unsafe extern "C" fn vtty_rec_start(rec: *mut vtty_rec_t, format: c_int, filename: *const c_char, title: *const c_char, max_size: size_t, max_files: u_int) -> c_int {
+    let filename = CStr::from_ptr(filename).to_string_lossy();
+    let title = CStr::from_ptr(title).to_string_lossy();
+    match (*rec).lock().start(format, &filename, &title, max_size as u64, max_files, m_gettime_usec()) {
+        Ok(()) => 0,
+        Err(err) => {
+            let msg = CString::new(format!("vtty_rec: {}: {}\n", filename, err)).unwrap_or_default();
+            libc::fputs(msg.as_ptr(), c_stderr());
+            -1
+        }
+    }
+}
+
+// Stop a recording, returns -1 if there was none
+#[no_mangle]
+pub unsafe extern "C" fn vtty_rec_stop(rec: *mut vtty_rec_t, format: c_int) -> c_int {
+    if (*rec).lock().stop(format, m_gettime_usec()) {
+        0
+    } else {
+        -1
+    }
+}
+
+// Change the scrollback size, returns -1 if too large
+#[no_mangle]
+pub unsafe extern "C" fn vtty_rec_set_scrollback(rec: *mut vtty_rec_t, size: size_t) -> c_int {
+    if size > VTTY_REC_MAX_SCROLLBACK {
+        return -1;
+    }
+    (*rec).lock().set_scrollback_size(size);
+    0
+}
+
+// Send the scrollback to a socket, returns the number of bytes sent or -1
+#[no_mangle]
+pub unsafe extern "C" fn vtty_rec_replay(rec: *mut vtty_rec_t, fd: c_int) -> ssize_t {
+    let data = (*rec).lock().scrollback();
+    let mut sent = 0;
+    while sent < data.len() {
+        let n = libc::send(fd, data[sent..].as_ptr().cast::<_>(), data.len() - sent, 0);
+        if n < 0 {
+            return -1;
+        }
+        sent += n as usize;
+    }
+    sent as ssize_t
+}
+
+// Call a function for each of the last lines of the scrollback, returns the number of lines
+#[no_mangle]
+pub unsafe extern "C" fn vtty_rec_tail(rec: *mut vtty_rec_t, count: u_int, cbk: vtty_rec_line_cbk_t, opt: *mut c_void) -> c_int {
+    let lines = (*rec).lock().tail(count as usize);
+    if let Some(cbk) = cbk {
+        for line in &lines {
+            let line = CString::new(line.as_str()).unwrap_or_default();
+            cbk(line.as_ptr(), opt);
+        }
+    }
+    lines.len() as c_int
+}
+
+// Get information about the recorder. The file names are copied (empty if
+// not recording) in buffers of size "len".
+#[no_mangle]
+pub unsafe extern "C" fn vtty_rec_get_info(rec: *mut vtty_rec_t, scrollback: *mut size_t, bytes: *mut m_uint64_t, cast_name: *mut c_char, log_name: *mut c_char, len: size_t) {
+    let r = (*rec).lock();
+    *scrollback = r.scrollback_size();
+    *bytes = r.bytes();
+    for (format, buf) in [(VTTY_REC_ASCIICAST, cast_name), (VTTY_REC_LOG, log_name)] {
+        if !buf.is_null() && len > 0 {
+            let name = r.filename(format).unwrap_or("").as_bytes();
+            let n = name.len().min(len - 1);
+            std::ptr::copy_nonoverlapping(name.as_ptr(), buf.cast::<u8>(), n);
+            *buf.add(n) = 0;
+        }
+    }
+}
@@ -667,6 +667,57 @@ static int cmd_set_aux_tcp_port(hypervisor_conn_t *conn,int argc,char *argv[])
    return(0);
 }
 
+/* Encode data in base64 (NUL-terminated string allocated with malloc) */
+static u_char *hv_vm_base64_encode(const u_char *data,size_t len)
+{
+   base64_enc_t enc;
+   u_char *buffer;
+   size_t pos;
+
+   if (!(buffer = malloc(base64_encoded_len(len,0,0) + 1)))
+      return NULL;
+
+   base64_enc_init(&enc,0,0);
+   pos = base64_enc_update(&enc,buffer,data,len);
+   pos += base64_enc_finish(&enc,buffer+pos);
+   buffer[pos] = 0;
+   return buffer;
+}
+
+/* Decode base64 data (NUL-terminated buffer allocated with malloc) */
+static u_char *hv_vm_base64_decode(hypervisor_conn_t *conn,char *str,
+                                   size_t *len)
+{
+   size_t inlen = strlen(str);
+   base64_dec_t dec;
+   m_uint64_t pos;
+   u_char *buffer;
+   ssize_t res,fin;
+   int err;
+
+   base64_dec_init(&dec,0);
+
+   if (!(buffer = malloc(base64_dec_update_len(&dec,inlen) + 3))) {
+      hypervisor_send_reply(conn,HSC_ERR_CREATE,1,"unable to decode base64");
+      return NULL;
+   }
+
+   if (((res = base64_dec_update(&dec,buffer,(u_char *)str,inlen)) < 0) ||
+       ((fin = base64_dec_finish(&dec,buffer+res)) < 0))
+   {
+      free(buffer);
+      err = base64_dec_error(&dec,&pos);
+      hypervisor_send_reply(conn,HSC_ERR_CREATE,1,
+                            "unable to decode base64 (%s at offset %llu)",
+                            base64_strerror(err),pos);
+      return NULL;
+   }
+
+   *len = res + fin;
+   buffer[*len] = 0;
+   return buffer;
+}
+
 /* Read IOS configuration files from a given router */
 static int cmd_extract_config(hypervisor_conn_t *conn,int argc,char *argv[])
 {
@@ -688,16 +739,11 @@ static int cmd_extract_config(hypervisor_conn_t *conn,int argc,char *argv[])
    if ((vm->platform->nvram_extract_config(vm,&startup_config,&startup_len,&private_config,&private_len)))
       goto err_nvram_extract;
 
-   /* 
-    * Convert config to base64. base64 generates 4 bytes for each group of 3 bytes.
-    */
-   if (!(startup_base64 = malloc(1 + (startup_len + 2) / 3 * 4)) ||
-       !(private_base64 = malloc(1 + (private_len + 2) / 3 * 4)))
+   /* Convert config to base64 */
+   if (!(startup_base64 = hv_vm_base64_encode(startup_config,startup_len)) ||
+       !(private_base64 = hv_vm_base64_encode(private_config,private_len)))
       goto err_alloc_base64;
 
-   base64_encode(startup_base64,startup_config,startup_len);
-   base64_encode(private_base64,private_config,private_len);
-
    vm_release(vm);
    hypervisor_send_reply(conn,HSC_INFO_OK,1,"conf '%s' '%s' '%s'",argv[0],startup_base64,private_base64);
 
@@ -726,8 +772,8 @@ static int cmd_push_config(hypervisor_conn_t *conn,int argc,char *argv[])
    vm_instance_t *vm;
    u_char *startup_config = NULL;
    u_char *private_config = NULL;
-   int startup_len = 0;
-   int private_len = 0;
+   size_t startup_len = 0;
+   size_t private_len = 0;
 
    if (!(vm = hypervisor_find_object(conn,argv[0],OBJ_TYPE_VM)))
       return(-1);
@@ -735,33 +781,19 @@ static int cmd_push_config(hypervisor_conn_t *conn,int argc,char *argv[])
    if (!vm->platform->nvram_push_config)
       goto err_no_push_method;
 
-   /*
-    * Convert base64 input to standard text. base64 uses 4 bytes for each group of 3 bytes.
-    */
+   /* Convert base64 input to standard text */
    if (strcmp(argv[1],"(keep)") != 0) {
-      startup_len = (strlen(argv[1]) + 3) / 4 * 3;
-      if (!(startup_config = malloc(1 + startup_len)))
-         goto err_alloc_base64;
-
-      if ((startup_len = base64_decode(startup_config,(u_char *)argv[1],startup_len)) < 0)
+      if (!(startup_config = hv_vm_base64_decode(conn,argv[1],&startup_len)))
          goto err_decode_base64;
-
-      startup_config[startup_len] = '\0';
    }
 
    if (argc > 2 && strcmp(argv[2],"(keep)") != 0) {
-      private_len = (strlen(argv[2]) + 3) / 4 * 3;
-      if (!(private_config = malloc(1 + private_len)))
-         goto err_alloc_base64;
-
-      if ((private_len = base64_decode(private_config,(u_char *)argv[2],private_len)) < 0)
+      if (!(private_config = hv_vm_base64_decode(conn,argv[2],&private_len)))
          goto err_decode_base64;
-
-      private_config[private_len] = '\0';
    }
 
    /* Push configuration */
-   if (vm->platform->nvram_push_config(vm,startup_config,(size_t)startup_len,private_config,(size_t)private_len) < 0)
+   if (vm->platform->nvram_push_config(vm,startup_config,startup_len,private_config,private_len) < 0)
       goto err_nvram_push;
 
    free(private_config);
@@ -772,9 +804,12 @@ static int cmd_push_config(hypervisor_conn_t *conn,int argc,char *argv[])
                          argv[0]);
    return(0);
 
- err_nvram_push:
  err_decode_base64:
- err_alloc_base64:
+   free(startup_config);
+   vm_release(vm);
+   return(-1);
+
+ err_nvram_push:
    free(private_config);
    free(startup_config);
  err_no_push_method:
@@ -855,6 +890,131 @@ static int cmd_resume(hypervisor_conn_t *conn,int argc,char *argv[])
    return(0);
 }
 
+/* Get the memory mapped RAM device of a VM instance */
+static struct vdevice *hv_vm_get_ram_dev(hypervisor_conn_t *conn,
+                                         vm_instance_t *vm)
+{
+   struct vdevice *dev;
+
+   /* The CPUs must not modify the RAM while it is accessed */
+   if ((vm->status != VM_STATUS_SUSPENDED) &&
+       (vm->status != VM_STATUS_HALTED))
+   {
+      hypervisor_send_reply(conn,HSC_ERR_UNSPECIFIED,1,
+                            "VM '%s' must be suspended or halted",
+                            vm->name);
+      return NULL;
+   }
+
+   if (!(dev = dev_get_by_name(vm,"ram")) || (dev->fd == -1) ||
+       !dev->host_addr || (dev->flags & VDEVICE_FLAG_SPARSE))
+   {
+      hypervisor_send_reply(conn,HSC_ERR_UNSPECIFIED,1,
+                            "VM '%s': RAM is not mapped from a file",
+                            vm->name);
+      return NULL;
+   }
+
+   return dev;
+}
+
+/* Save the RAM pages which differ from the ghost file (or from zeros) */
+static int cmd_save_ram_delta(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   vm_instance_t *vm;
+   struct vdevice *dev;
+   ssize_t count;
+   int base_fd;
+
+   if (!(vm = hypervisor_find_object(conn,argv[0],OBJ_TYPE_VM)))
+      return(-1);
+
+   if (!(dev = hv_vm_get_ram_dev(conn,vm))) {
+      vm_release(vm);
+      return(-1);
+   }
+
+   base_fd = (dev->flags & VDEVICE_FLAG_GHOST) ? dev->fd : -1;
+   count = memzone_save_delta((u_char *)dev->host_addr,dev->phys_len,
+                              base_fd,argv[1]);
+   vm_release(vm);
+
+   if (count == -1) {
+      hypervisor_send_reply(conn,HSC_ERR_FILE,1,
+                            "unable to save RAM delta in '%s'",argv[1]);
+      return(-1);
+   }
+
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"%ld pages saved",(long)count);
+   return(0);
+}
+
+/* Apply a RAM delta file to the RAM */
+static int cmd_restore_ram_delta(hypervisor_conn_t *conn,
+                                 int argc,char *argv[])
+{
+   vm_instance_t *vm;
+   struct vdevice *dev;
+   ssize_t count;
+
+   if (!(vm = hypervisor_find_object(conn,argv[0],OBJ_TYPE_VM)))
+      return(-1);
+
+   if (!(dev = hv_vm_get_ram_dev(conn,vm))) {
+      vm_release(vm);
+      return(-1);
+   }
+
+   count = memzone_restore_delta((u_char *)dev->host_addr,dev->phys_len,
+                                 argv[1]);
+   vm_release(vm);
+
+   if (count == -1) {
+      hypervisor_send_reply(conn,HSC_ERR_FILE,1,
+                            "unable to restore RAM delta from '%s'",argv[1]);
+      return(-1);
+   }
+
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"%ld pages restored",(long)count);
+   return(0);
+}
+
+/* Release the disk blocks of the zeroed RAM pages */
+static int cmd_punch_ram(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   vm_instance_t *vm;
+   struct vdevice *dev;
+   ssize_t count;
+
+   if (!(vm = hypervisor_find_object(conn,argv[0],OBJ_TYPE_VM)))
+      return(-1);
+
+   if (!(dev = hv_vm_get_ram_dev(conn,vm))) {
+      vm_release(vm);
+      return(-1);
+   }
+
+   /* A ghost file is shared and mapped privately, it is left untouched */
+   if (dev->flags & VDEVICE_FLAG_GHOST) {
+      vm_release(vm);
+      hypervisor_send_reply(conn,HSC_ERR_UNSPECIFIED,1,
+                            "VM '%s': RAM is a ghost image",argv[0]);
+      return(-1);
+   }
+
+   count = memzone_punch_zero_pages(dev->fd,(u_char *)dev->host_addr,
+                                    dev->phys_len);
+   vm_release(vm);
+
+   if (count == -1) {
+      hypervisor_send_reply(conn,HSC_ERR_FILE,1,"unable to punch RAM file");
+      return(-1);
+   }
+
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"%ld pages released",(long)count);
+   return(0);
+}
+
 /* Send a message on the console */
 static int cmd_send_con_msg(hypervisor_conn_t *conn,int argc,char *argv[])
 {
@@ -938,6 +1098,231 @@ static int cmd_send_aux_msg(hypervisor_conn_t *conn,int argc,char *argv[])
 }
 
 
+/* Get the recorder of a console port ("con" or "aux") */
+static vtty_rec_t *hv_vm_get_vtty_rec(hypervisor_conn_t *conn,
+                                      vm_instance_t *vm,char *port)
+{
+   if (!strcmp(port,"con"))
+      return(vm->vtty_con_rec);
+
+   if (!strcmp(port,"aux"))
+      return(vm->vtty_aux_rec);
+
+   hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                         "Invalid console port '%s'",port);
+   return NULL;
+}
+
+/* Start recording a console port in a file */
+static int cmd_console_record(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   vm_instance_t *vm;
+   vtty_rec_t *rec;
+   size_t max_size = 0;
+   u_int max_files = 5;
+   char title[256];
+   int format;
+
+   if (!(vm = hypervisor_find_object(conn,argv[0],OBJ_TYPE_VM)))
+      return(-1);
+
+   if (!(rec = hv_vm_get_vtty_rec(conn,vm,argv[1]))) {
+      vm_release(vm);
+      return(-1);
+   }
+
+   if ((format = vtty_rec_get_format(argv[2])) == -1) {
+      vm_release(vm);
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "Invalid recording format '%s'",argv[2]);
+      return(-1);
+   }
+
+   if (argc > 4)
+      max_size = strtoul(argv[4],NULL,0);
+
+   if (argc > 5)
+      max_files = atoi(argv[5]);
+
+   snprintf(title,sizeof(title),"%s %s port",vm->name,
+            !strcmp(argv[1],"con") ? "Console" : "AUX");
+
+   if (vtty_rec_start(rec,format,argv[3],title,max_size,max_files) == -1) {
+      vm_release(vm);
+      hypervisor_send_reply(conn,HSC_ERR_FILE,1,
+                            "unable to record in file '%s'",argv[3]);
+      return(-1);
+   }
+
+   vm_release(vm);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Stop recording a console port */
+static int cmd_console_record_stop(hypervisor_conn_t *conn,
+                                   int argc,char *argv[])
+{
+   vm_instance_t *vm;
+   vtty_rec_t *rec;
+   int format;
+
+   if (!(vm = hypervisor_find_object(conn,argv[0],OBJ_TYPE_VM)))
+      return(-1);
+
+   if (!(rec = hv_vm_get_vtty_rec(conn,vm,argv[1]))) {
+      vm_release(vm);
+      return(-1);
+   }
+
+   if ((format = vtty_rec_get_format(argv[2])) == -1) {
+      vm_release(vm);
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "Invalid recording format '%s'",argv[2]);
+      return(-1);
+   }
+
+   if (vtty_rec_stop(rec,format) == -1) {
+      vm_release(vm);
+      hypervisor_send_reply(conn,HSC_ERR_STOP,1,"not recording");
+      return(-1);
+   }
+
+   vm_release(vm);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Set the scrollback size of a console port */
+static int cmd_console_scrollback(hypervisor_conn_t *conn,
+                                  int argc,char *argv[])
+{
+   vm_instance_t *vm;
+   vtty_rec_t *rec;
+
+   if (!(vm = hypervisor_find_object(conn,argv[0],OBJ_TYPE_VM)))
+      return(-1);
+
+   if (!(rec = hv_vm_get_vtty_rec(conn,vm,argv[1]))) {
+      vm_release(vm);
+      return(-1);
+   }
+
+   if (vtty_rec_set_scrollback(rec,strtoul(argv[2],NULL,0)) == -1) {
+      vm_release(vm);
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "Invalid scrollback size '%s'",argv[2]);
+      return(-1);
+   }
+
+   vm_release(vm);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Send a line of the console scrollback */
+static void hv_vm_console_line(const char *line,void *opt)
+{
+   hypervisor_send_reply((hypervisor_conn_t *)opt,HSC_INFO_MSG,0,"%s",line);
+}
+
+/* Show the last lines of a console port */
+static int cmd_console_tail(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   vm_instance_t *vm;
+   vtty_rec_t *rec;
+
+   if (!(vm = hypervisor_find_object(conn,argv[0],OBJ_TYPE_VM)))
+      return(-1);
+
+   if (!(rec = hv_vm_get_vtty_rec(conn,vm,argv[1]))) {
+      vm_release(vm);
+      return(-1);
+   }
+
+   vtty_rec_tail(rec,atoi(argv[2]),hv_vm_console_line,conn);
+
+   vm_release(vm);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Show the recording status of a console port */
+static int cmd_show_console_rec(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   char cast_name[256],log_name[256];
+   m_uint64_t bytes;
+   size_t scrollback;
+   vm_instance_t *vm;
+   vtty_rec_t *rec;
+
+   if (!(vm = hypervisor_find_object(conn,argv[0],OBJ_TYPE_VM)))
+      return(-1);
+
+   if (!(rec = hv_vm_get_vtty_rec(conn,vm,argv[1]))) {
+      vm_release(vm);
+      return(-1);
+   }
+
+   vtty_rec_get_info(rec,&scrollback,&bytes,cast_name,log_name,
+                     sizeof(cast_name));
+
+   hypervisor_send_reply(conn,HSC_INFO_MSG,0,"scrollback %lu bytes",
+                         (u_long)scrollback);
+   hypervisor_send_reply(conn,HSC_INFO_MSG,0,"%llu bytes received",bytes);
+
+   if (cast_name[0])
+      hypervisor_send_reply(conn,HSC_INFO_MSG,0,"asciicast %s",cast_name);
+
+   if (log_name[0])
+      hypervisor_send_reply(conn,HSC_INFO_MSG,0,"log %s",log_name);
+
+   vm_release(vm);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Send the description of a console client */
+static void hv_vm_console_client(int fd,int role,const char *peer,void *opt)
+{
+   hypervisor_send_reply((hypervisor_conn_t *)opt,HSC_INFO_MSG,0,"%s %s",peer,
+                         (role == VTTY_MUX_OWNER) ? "read-write" : "read-only");
+}
+
+/* Show the clients connected to a TCP console port */
+static int cmd_console_clients(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   vm_instance_t *vm;
+   vtty_t *vtty;
+
+   if (!(vm = hypervisor_find_object(conn,argv[0],OBJ_TYPE_VM)))
+      return(-1);
+
+   if (!strcmp(argv[1],"con"))
+      vtty = vm->vtty_con;
+   else if (!strcmp(argv[1],"aux"))
+      vtty = vm->vtty_aux;
+   else {
+      vm_release(vm);
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "Invalid console port '%s'",argv[1]);
+      return(-1);
+   }
+
+   if (!vtty || !vtty->mux) {
+      vm_release(vm);
+      hypervisor_send_reply(conn,HSC_ERR_UNSPECIFIED,1,
+                            "VM '%s' has no TCP %s port",argv[0],argv[1]);
+      return(-1);
+   }
+
+   vtty_mux_get_clients(vtty->mux,hv_vm_console_client,conn);
+
+   vm_release(vm);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
 /* Show slot bindings */
 static int cmd_slot_bindings(hypervisor_conn_t *conn,int argc,char *argv[])
 {
@@ -1283,6 +1668,9 @@ static hypervisor_cmd_t vm_cmd_array[] = {
    { "show_timer_drift", 2, 2, cmd_show_timer_drift, NULL },
    { "set_ghost_file", 2, 2, cmd_set_ghost_file, NULL },
    { "set_ghost_status", 2, 2, cmd_set_ghost_status, NULL },
+   { "save_ram_delta", 2, 2, cmd_save_ram_delta, NULL },
+   { "restore_ram_delta", 2, 2, cmd_restore_ram_delta, NULL },
+   { "punch_ram", 1, 1, cmd_punch_ram, NULL },
    { "set_con_tcp_port", 2, 2, cmd_set_con_tcp_port, NULL },
    { "set_aux_tcp_port", 2, 2, cmd_set_aux_tcp_port, NULL },
    { "extract_config", 1, 1, cmd_extract_config, NULL },
@@ -1293,6 +1681,12 @@ static hypervisor_cmd_t vm_cmd_array[] = {
    { "resume", 1, 1, cmd_resume, NULL },
    { "send_con_msg", 2, 3, cmd_send_con_msg, NULL },
    { "send_aux_msg", 2, 3, cmd_send_aux_msg, NULL },
+   { "console_record", 4, 6, cmd_console_record, NULL },
+   { "console_record_stop", 3, 3, cmd_console_record_stop, NULL },
+   { "console_scrollback", 3, 3, cmd_console_scrollback, NULL },
+   { "console_tail", 3, 3, cmd_console_tail, NULL },
+   { "show_console_rec", 2, 2, cmd_show_console_rec, NULL },
+   { "console_clients", 2, 2, cmd_console_clients, NULL },
    { "slot_bindings", 1, 1, cmd_slot_bindings, NULL },
    { "slot_nio_bindings", 2, 2, cmd_slot_nio_bindings, NULL },
    { "slot_add_binding", 4, 4, cmd_slot_add_binding, NULL },
@@ -127,6 +127,9 @@ struct vm_instance {
    /* Memory chunks */
    vm_chunk_t *chunks;
 
+   /* Protects the CPU group against its deletion (metrics exporter) */
+   pthread_mutex_t lock;
+
    /* Basic hardware: system CPU, PCI busses and PCI I/O space */
    cpu_group_t *cpu_group;
    cpu_gen_t *boot_cpu;
@@ -175,6 +178,9 @@ struct vm_instance {
    /* Virtual TTY for Console and AUX ports */
    vtty_t *vtty_con,*vtty_aux;
 
+   /* Console and AUX port recorders (kept when the VM is restarted) */
+   vtty_rec_t *vtty_con_rec,*vtty_aux_rec;
+
    /* Space reserved in NVRAM by ROM monitor */
    u_int nvram_rom_space;
 
@@ -214,6 +220,9 @@ struct vm_platform_list {
    struct vm_platform *platform;
 };
 
+#define VM_LOCK(vm)   pthread_mutex_lock(&(vm)->lock)
+#define VM_UNLOCK(vm) pthread_mutex_unlock(&(vm)->lock)
+
 extern int vm_file_naming_type;
 
 /* Set an IRQ for a VM */
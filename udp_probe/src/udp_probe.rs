@@ -0,0 +1,482 @@
+//! Scripted peer for an emulated interface that is connected to a UDP NIO.
+//!
+//! Resolves the target with ARP, sends ICMP echo requests, matches the replies
+//! with `pkt_ctx_analyze` and reports loss and latency.
+//! ARP requests for the source address are answered so the target can reply.
+//!
+//! Frames are sent on a socket connected to the NIO with `udp_connect` and
+//! received on it. With `-r`, the frames sent by the NIO to another port are
+//! also received, on the sockets opened by `ip_listen` for this port.
+//!
+//! Exit status:
+//!  * 0 - the loss is within the limit
+//!  * 1 - the loss is above the limit
+//!  * 2 - the target did not answer ARP or any echo request
+//!  * 3 - usage or socket error
+
+use dynamips_c::dynamips_common::*;
+use dynamips_c::endian::*;
+use dynamips_c::net::*;
+use dynamips_c::utils::*;
+use std::env;
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::mem::zeroed;
+use std::ptr::null_mut;
+use std::ptr::read_unaligned;
+use std::ptr::write_unaligned;
+
+const MAX_PKT_SIZE: usize = 2048;
+
+/// Receive buffer, the frame starts at offset 2 so the IP header is 4-byte aligned
+#[repr(C, align(4))]
+struct RxBuf {
+    pad: [u8; 2],
+    pkt: [u8; MAX_PKT_SIZE],
+}
+
+const EXIT_LOSS: c_int = 1;
+const EXIT_NO_REPLY: c_int = 2;
+const EXIT_ERROR: c_int = 3;
+
+// ICMP types
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+// ICMP echo header
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct IcmpEchoHdr {
+    r#type: u8,
+    code: u8,
+    cksum: u16,
+    id: u16,
+    seq: u16,
+}
+
+// Size of the echo payload (timestamp + pattern)
+const ICMP_PAYLOAD_LEN: usize = 56;
+
+const BCAST_ADDR: n_eth_addr_t = n_eth_addr_t { eth_addr_byte: [0xff; N_ETH_ALEN] };
+
+struct Options {
+    local_port: c_int,
+    remote_host: CString,
+    remote_port: c_int,
+    src_mac: n_eth_addr_t,
+    src_ip: n_ip_addr_t,
+    dst_ip: n_ip_addr_t,
+    rx_port: Option<c_int>,
+    count: u16,
+    interval_ms: u64,
+    timeout_ms: u64,
+    max_loss: f64,
+}
+
+struct Probe {
+    sck: c_int,
+    rx_fds: Vec<c_int>,
+    opts: Options,
+    dst_mac: n_eth_addr_t,
+    ident: u16,
+}
+
+fn usage(prog: &str) -> ! {
+    eprintln!("Usage: {prog} [options] <local_port> <remote_host> <remote_port> <src_ip> <dst_ip>");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  -m <mac>      : source MAC address (default 00:00:ab:cd:00:01)");
+    eprintln!("  -r <port>     : also receive the frames sent to this local port");
+    eprintln!("  -c <count>    : number of echo requests, 1 to 65535 (default 5)");
+    eprintln!("  -i <msec>     : interval between echo requests (default 1000)");
+    eprintln!("  -w <msec>     : timeout for each reply (default 1000)");
+    eprintln!("  -l <percent>  : maximum loss accepted for a successful exit (default 0)");
+    std::process::exit(EXIT_ERROR);
+}
+
+fn parse_ip(prog: &str, s: &str) -> n_ip_addr_t {
+    let mut ip: n_ip_addr_t = 0;
+    let c_str = CString::new(s).unwrap_or_else(|_| usage(prog));
+    if unsafe { n_ip_aton(&mut ip, c_str.as_ptr().cast_mut()) } == -1 {
+        eprintln!("{prog}: invalid IP address '{s}'");
+        usage(prog);
+    }
+    ip
+}
+
+fn parse_num<T: std::str::FromStr>(prog: &str, s: &str) -> T {
+    s.parse::<T>().unwrap_or_else(|_| {
+        eprintln!("{prog}: invalid number '{s}'");
+        usage(prog)
+    })
+}
+
+fn parse_options() -> Options {
+    let args: Vec<String> = env::args().collect();
+    let prog = args.first().map(String::as_str).unwrap_or("udp_probe").to_owned();
+    let mut src_mac = n_eth_addr_t { eth_addr_byte: [0x00, 0x00, 0xab, 0xcd, 0x00, 0x01] };
+    let mut rx_port: Option<c_int> = None;
+    let mut count: u32 = 5;
+    let mut interval_ms: u64 = 1000;
+    let mut timeout_ms: u64 = 1000;
+    let mut max_loss: f64 = 0.0;
+    let mut positional: Vec<&str> = Vec::new();
+
+    let mut it = args.iter().skip(1);
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().map(String::as_str).unwrap_or_else(|| usage(&prog));
+        match arg.as_str() {
+            "-m" => {
+                let s = value();
+                let c_str = CString::new(s).unwrap_or_else(|_| usage(&prog));
+                if unsafe { parse_mac_addr(&mut src_mac, c_str.as_ptr().cast_mut()) } == -1 {
+                    eprintln!("{prog}: invalid MAC address '{s}'");
+                    usage(&prog);
+                }
+            }
+            "-r" => rx_port = Some(parse_num(&prog, value())),
+            "-c" => count = parse_num(&prog, value()),
+            "-i" => interval_ms = parse_num(&prog, value()),
+            "-w" => timeout_ms = parse_num(&prog, value()),
+            "-l" => max_loss = parse_num(&prog, value()),
+            "-h" | "--help" => usage(&prog),
+            s if s.starts_with('-') && s.len() > 1 => usage(&prog),
+            s => positional.push(s),
+        }
+    }
+
+    if positional.len() != 5 {
+        usage(&prog);
+    }
+
+    // the count is also the last ICMP sequence number
+    let count = match u16::try_from(count) {
+        Ok(count) if count != 0 => count,
+        _ => {
+            eprintln!("{prog}: invalid count {count} (1 to 65535)");
+            usage(&prog)
+        }
+    };
+
+    Options {
+        local_port: parse_num(&prog, positional[0]),
+        remote_host: CString::new(positional[1]).unwrap_or_else(|_| usage(&prog)),
+        remote_port: parse_num(&prog, positional[2]),
+        src_mac,
+        src_ip: parse_ip(&prog, positional[3]),
+        dst_ip: parse_ip(&prog, positional[4]),
+        rx_port,
+        count,
+        interval_ms,
+        timeout_ms,
+        max_loss,
+    }
+}
+
+// Format an IPv4 address (host order)
+fn ip_str(ip: n_ip_addr_t) -> String {
+    let mut buffer: [c_char; 16] = [0; 16];
+    unsafe { CStr::from_ptr(n_ip_ntoa(buffer.as_mut_ptr(), ip.to_be())) }.to_string_lossy().into_owned()
+}
+
+// Format an Ethernet address
+fn mac_str(mut addr: n_eth_addr_t) -> String {
+    let mut buffer: [c_char; N_ETH_SLEN] = [0; N_ETH_SLEN];
+    unsafe { CStr::from_ptr(n_eth_ntoa(buffer.as_mut_ptr(), &mut addr, 0)) }.to_string_lossy().into_owned()
+}
+
+// Fold a partial checksum
+fn cksum_fold(mut sum: m_uint32_t) -> m_uint16_t {
+    while (sum >> 16) != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as m_uint16_t)
+}
+
+// Write an Ethernet header, returns the L3 offset
+unsafe fn build_eth_hdr(pkt: &mut [u8], daddr: n_eth_addr_t, saddr: n_eth_addr_t, eth_type: m_uint16_t) -> usize {
+    let hdr = n_eth_hdr_t { daddr, saddr, r#type: Be16::new(eth_type) };
+    write_unaligned(pkt.as_mut_ptr().cast::<n_eth_hdr_t>(), hdr);
+    N_ETH_HLEN
+}
+
+// Build an ARP packet, returns the frame length
+unsafe fn build_arp(pkt: &mut [u8], opcode: c_int, src_mac: n_eth_addr_t, src_ip: n_ip_addr_t, dst_mac: n_eth_addr_t, dst_ip: n_ip_addr_t) -> usize {
+    let eth_daddr = if opcode == N_ARP_REQUEST { BCAST_ADDR } else { dst_mac };
+    let offset = build_eth_hdr(pkt, eth_daddr, src_mac, N_ETH_PROTO_ARP);
+    let arp = n_arp_hdr_t {
+        hw_type: Be16::new(1),
+        proto_type: Be16::new(N_ETH_PROTO_IP),
+        hw_len: N_ETH_ALEN as m_uint8_t,
+        proto_len: N_IP_ADDR_LEN as m_uint8_t,
+        opcode: Be16::new(opcode as m_uint16_t),
+        eth_saddr: src_mac,
+        ip_saddr: Be32::new(src_ip),
+        eth_daddr: if opcode == N_ARP_REQUEST { n_eth_addr_t { eth_addr_byte: [0; N_ETH_ALEN] } } else { dst_mac },
+        ip_daddr: Be32::new(dst_ip),
+    };
+    write_unaligned(pkt.as_mut_ptr().add(offset).cast::<n_arp_hdr_t>(), arp);
+
+    // pad to the minimum frame size
+    let len = offset + size_of::<n_arp_hdr_t>();
+    pkt[len..N_ETH_MIN_FRAME_LEN].fill(0);
+    N_ETH_MIN_FRAME_LEN
+}
+
+// Build an ICMP echo request, returns the frame length
+unsafe fn build_icmp_echo(probe: &Probe, pkt: &mut [u8], seq: u16) -> usize {
+    let offset = build_eth_hdr(pkt, probe.dst_mac, probe.opts.src_mac, N_ETH_PROTO_IP);
+    let ip_len = size_of::<n_ip_hdr_t>() + size_of::<IcmpEchoHdr>() + ICMP_PAYLOAD_LEN;
+
+    let mut ip = n_ip_hdr_t {
+        ihl: 0x45,
+        tos: 0,
+        tot_len: Be16::new(ip_len as m_uint16_t),
+        id: Be16::new(seq),
+        frag_off: Be16::default(),
+        ttl: 64,
+        proto: N_IP_PROTO_ICMP as m_uint8_t,
+        cksum: Be16::default(),
+        saddr: Be32::new(probe.opts.src_ip),
+        daddr: Be32::new(probe.opts.dst_ip),
+    };
+    ip_compute_cksum(&mut ip);
+    write_unaligned(pkt.as_mut_ptr().add(offset).cast::<n_ip_hdr_t>(), ip);
+
+    // payload: send timestamp followed by an incrementing pattern
+    let icmp_offset = offset + size_of::<n_ip_hdr_t>();
+    let data_offset = icmp_offset + size_of::<IcmpEchoHdr>();
+    let payload = &mut pkt[data_offset..data_offset + ICMP_PAYLOAD_LEN];
+    payload[..8].copy_from_slice(&m_gettime_usec().to_be_bytes());
+    for (i, b) in payload[8..].iter_mut().enumerate() {
+        *b = i as u8;
+    }
+
+    let mut icmp = IcmpEchoHdr { r#type: ICMP_ECHO_REQUEST, code: 0, cksum: 0, id: probe.ident.to_be(), seq: seq.to_be() };
+    write_unaligned(pkt.as_mut_ptr().add(icmp_offset).cast::<IcmpEchoHdr>(), icmp);
+    icmp.cksum = cksum_fold(ip_cksum_partial(pkt.as_mut_ptr().add(icmp_offset), (size_of::<IcmpEchoHdr>() + ICMP_PAYLOAD_LEN) as c_int)).to_be();
+    write_unaligned(pkt.as_mut_ptr().add(icmp_offset).cast::<IcmpEchoHdr>(), icmp);
+
+    offset + ip_len
+}
+
+unsafe fn send_pkt(probe: &Probe, pkt: &[u8]) -> bool {
+    if libc::send(probe.sck, pkt.as_ptr().cast::<_>(), pkt.len(), 0) < 0 {
+        libc::perror(c"udp_probe: send".as_ptr());
+        return false;
+    }
+    true
+}
+
+// Received packet of interest
+enum Reply {
+    Arp(n_eth_addr_t),
+    Echo(u16),
+}
+
+// Analyze a received packet. ARP requests for the source address are answered.
+unsafe fn handle_pkt(probe: &Probe, pkt: &mut [u8]) -> Option<Reply> {
+    let mut ctx: n_pkt_ctx_t = zeroed();
+
+    if pkt.len() < N_ETH_HLEN {
+        return None;
+    }
+    pkt_ctx_analyze(&mut ctx, pkt.as_mut_ptr(), pkt.len());
+
+    if (ctx.flags & N_PKT_CTX_FLAG_L3_ARP) != 0 {
+        if (ctx.l3.ptr as usize) + size_of::<n_arp_hdr_t>() > (pkt.as_ptr() as usize) + pkt.len() {
+            return None;
+        }
+        let arp: n_arp_hdr_t = read_unaligned(ctx.l3.arp);
+        let opcode = arp.opcode.get() as c_int;
+
+        if opcode == N_ARP_REQUEST && arp.ip_daddr.get() == probe.opts.src_ip {
+            let mut reply: [u8; N_ETH_MIN_FRAME_LEN] = [0; N_ETH_MIN_FRAME_LEN];
+            build_arp(&mut reply, N_ARP_REPLY, probe.opts.src_mac, probe.opts.src_ip, arp.eth_saddr, arp.ip_saddr.get());
+            send_pkt(probe, &reply);
+        } else if opcode == N_ARP_REPLY && arp.ip_saddr.get() == probe.opts.dst_ip {
+            return Some(Reply::Arp(arp.eth_saddr));
+        }
+        return None;
+    }
+
+    if (ctx.flags & N_PKT_CTX_FLAG_IPH_OK) == 0 || (ctx.flags & N_PKT_CTX_FLAG_IP_FRAG) != 0 || ctx.ip_l4_proto != N_IP_PROTO_ICMP {
+        return None;
+    }
+    pkt_ctx_ip_analyze_l4(&mut ctx);
+
+    let ip: n_ip_hdr_t = read_unaligned(ctx.l3.ip);
+    if ip.saddr.get() != probe.opts.dst_ip || ip.daddr.get() != probe.opts.src_ip {
+        return None;
+    }
+    if (ctx.l4.ptr as usize) + size_of::<IcmpEchoHdr>() > (pkt.as_ptr() as usize) + pkt.len() {
+        return None;
+    }
+    let icmp: IcmpEchoHdr = read_unaligned(ctx.l4.ptr.cast::<IcmpEchoHdr>());
+    if icmp.r#type != ICMP_ECHO_REPLY || m_uint16_t::from_be(icmp.id) != probe.ident {
+        return None;
+    }
+
+    Some(Reply::Echo(m_uint16_t::from_be(icmp.seq)))
+}
+
+// Wait for a matching reply until the deadline (usec), returns the reception time
+unsafe fn wait_reply<F: FnMut(&Reply) -> bool>(probe: &Probe, deadline: m_tmcnt_t, mut matcher: F) -> Option<(Reply, m_tmcnt_t)> {
+    let mut rx: RxBuf = RxBuf { pad: [0; 2], pkt: [0; MAX_PKT_SIZE] };
+    let pkt = &mut rx.pkt;
+
+    loop {
+        let now = m_gettime_usec();
+        if now >= deadline {
+            return None;
+        }
+
+        let mut pfds: Vec<libc::pollfd> = probe.rx_fds.iter().map(|&fd| libc::pollfd { fd, events: libc::POLLIN, revents: 0 }).collect();
+        let timeout = (deadline - now).div_ceil(1000) as c_int;
+        let res = libc::poll(pfds.as_mut_ptr(), pfds.len() as libc::nfds_t, timeout);
+        if res < 0 {
+            if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            libc::perror(c"udp_probe: poll".as_ptr());
+            return None;
+        }
+        if res == 0 {
+            continue;
+        }
+
+        for pfd in pfds.iter().filter(|pfd| (pfd.revents & libc::POLLIN) != 0) {
+            let len = libc::recv(pfd.fd, pkt.as_mut_ptr().cast::<_>(), pkt.len(), 0);
+            if len <= 0 {
+                continue;
+            }
+            let rx_time = m_gettime_usec();
+
+            if let Some(reply) = handle_pkt(probe, &mut pkt[..len as usize]) {
+                if matcher(&reply) {
+                    return Some((reply, rx_time));
+                }
+            }
+        }
+    }
+}
+
+// Resolve the target MAC address
+unsafe fn resolve(probe: &mut Probe) -> bool {
+    let mut pkt: [u8; N_ETH_MIN_FRAME_LEN] = [0; N_ETH_MIN_FRAME_LEN];
+
+    for _ in 0..probe.opts.count {
+        build_arp(&mut pkt, N_ARP_REQUEST, probe.opts.src_mac, probe.opts.src_ip, BCAST_ADDR, probe.opts.dst_ip);
+        let tx_time = m_gettime_usec();
+        if !send_pkt(probe, &pkt) {
+            return false;
+        }
+
+        if let Some((Reply::Arp(mac), rx_time)) = wait_reply(probe, tx_time + probe.opts.timeout_ms * 1000, |r| matches!(r, Reply::Arp(_))) {
+            println!("ARP {} is at {} ({:.3} ms)", ip_str(probe.opts.dst_ip), mac_str(mac), (rx_time - tx_time) as f64 / 1000.0);
+            probe.dst_mac = mac;
+            return true;
+        }
+    }
+
+    println!("ARP {}: no reply", ip_str(probe.opts.dst_ip));
+    false
+}
+
+// Close the sockets
+unsafe fn close_probe(probe: &Probe) {
+    for &fd in &probe.rx_fds {
+        libc::close(fd);
+    }
+}
+
+// Send the echo requests and collect the round trip times (usec)
+unsafe fn ping(probe: &Probe) -> Vec<m_tmcnt_t> {
+    let mut pkt: [u8; MAX_PKT_SIZE] = [0; MAX_PKT_SIZE];
+    let mut rtts: Vec<m_tmcnt_t> = Vec::new();
+
+    for seq in 1..=probe.opts.count {
+        let len = build_icmp_echo(probe, &mut pkt, seq);
+        let tx_time = m_gettime_usec();
+        if !send_pkt(probe, &pkt[..len]) {
+            break;
+        }
+
+        let deadline = tx_time + probe.opts.timeout_ms * 1000;
+        match wait_reply(probe, deadline, |r| matches!(r, Reply::Echo(s) if *s == seq)) {
+            Some((_, rx_time)) => {
+                let rtt = rx_time - tx_time;
+                println!("ICMP echo reply from {}: seq={} time={:.3} ms", ip_str(probe.opts.dst_ip), seq, rtt as f64 / 1000.0);
+                rtts.push(rtt);
+            }
+            None => {
+                println!("ICMP echo request to {}: seq={} timeout", ip_str(probe.opts.dst_ip), seq);
+            }
+        }
+
+        // wait for the rest of the interval (late replies are discarded)
+        if seq < probe.opts.count {
+            let next = tx_time + probe.opts.interval_ms * 1000;
+            wait_reply(probe, next, |_| false);
+        }
+    }
+
+    rtts
+}
+
+fn main() {
+    let opts = parse_options();
+
+    unsafe {
+        // Connect to the NIO
+        let sck: c_int = udp_connect(opts.local_port, opts.remote_host.as_ptr().cast_mut(), opts.remote_port);
+        if sck < 0 {
+            libc::exit(EXIT_ERROR);
+        }
+
+        let mut rx_fds: Vec<c_int> = vec![sck];
+
+        // Listen on the receive port (IPv4 and IPv6)
+        if let Some(rx_port) = opts.rx_port {
+            let mut fds: [c_int; 2] = [-1; 2];
+            let nfd = ip_listen(null_mut(), rx_port, libc::SOCK_DGRAM, fds.len() as c_int, fds.as_mut_ptr());
+            if nfd <= 0 {
+                libc::close(sck);
+                libc::exit(EXIT_ERROR);
+            }
+            rx_fds.extend_from_slice(&fds[..nfd as usize]);
+        }
+
+        let mut probe = Probe { sck, rx_fds, opts, dst_mac: BCAST_ADDR, ident: (libc::getpid() & 0xFFFF) as u16 };
+
+        if !resolve(&mut probe) {
+            close_probe(&probe);
+            libc::exit(EXIT_NO_REPLY);
+        }
+
+        let rtts = ping(&probe);
+        close_probe(&probe);
+
+        let sent = u32::from(probe.opts.count);
+        let received = rtts.len() as u32;
+        let loss = 100.0 * (sent - received) as f64 / sent as f64;
+        print!("--- {}: {} sent, {} received, {:.1}% loss", ip_str(probe.opts.dst_ip), sent, received, loss);
+        if received > 0 {
+            let min = *rtts.iter().min().expect("min") as f64 / 1000.0;
+            let max = *rtts.iter().max().expect("max") as f64 / 1000.0;
+            let avg = rtts.iter().sum::<m_tmcnt_t>() as f64 / received as f64 / 1000.0;
+            print!(", rtt min/avg/max = {min:.3}/{avg:.3}/{max:.3} ms");
+        }
+        println!();
+
+        if received == 0 {
+            libc::exit(EXIT_NO_REPLY);
+        }
+        if loss > probe.opts.max_loss {
+            libc::exit(EXIT_LOSS);
+        }
+        libc::exit(0);
+    }
+}
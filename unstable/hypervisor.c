@@ -118,6 +118,141 @@ static int cmd_set_working_dir(hypervisor_conn_t *conn,int argc,char *argv[])
    return(0);
 }
 
+/* Set the seed of the hardware identifiers (MAC addresses, board ids) */
+static int cmd_set_hwid_seed(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   hwid_set_seed(argv[0]);
+   m_log("GENERAL","hwid_seed=%s\n",argv[0]);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Set the key of the seeded hash functions */
+static int cmd_set_hash_seed(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   m_uint64_t k0,k1;
+
+   k0 = strtoull(argv[0],NULL,0);
+   k1 = strtoull(argv[1],NULL,0);
+
+   if (hash_set_seed(k0,k1) == -1) {
+      hypervisor_send_reply(conn,HSC_ERR_UNSPECIFIED,1,
+                            "hash seed already in use");
+      return(-1);
+   }
+
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Show or set the log filters ("info,VM=debug,NIO*=warn") */
+static int cmd_log_filter(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   char *spec;
+
+   if ((argc == 1) && (m_log_set_filters(argv[0]) == -1)) {
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "invalid log filter '%s'",argv[0]);
+      return(-1);
+   }
+
+   if ((spec = m_log_get_filters()) != NULL) {
+      hypervisor_send_reply(conn,HSC_INFO_OK,1,"%s",spec);
+      free(spec);
+   }
+   return(0);
+}
+
+/* Set the log output format ("text" or "json") */
+static int cmd_log_format(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   int format;
+
+   if (!strcmp(argv[0],"text"))
+      format = M_LOG_FORMAT_TEXT;
+   else if (!strcmp(argv[0],"json"))
+      format = M_LOG_FORMAT_JSON;
+   else {
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "invalid log format '%s'",argv[0]);
+      return(-1);
+   }
+
+   m_log_set_format(format);
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Set the size based rotation of the log file (max size 0 to disable) */
+static int cmd_log_rotate(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   m_log_set_rotation(strtoul(argv[0],NULL,0),atoi(argv[1]));
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Get a kind of memory zone from its name */
+static int hv_memzone_kind(char *name)
+{
+   if (!strcmp(name,"ram"))
+      return(MEMZONE_KIND_RAM);
+
+   if (!strcmp(name,"exec"))
+      return(MEMZONE_KIND_EXEC);
+
+   return(-1);
+}
+
+/* Set the huge pages and NUMA options of the RAM or JIT exec areas */
+static int cmd_memzone_opts(hypervisor_conn_t *conn,int argc,char *argv[])
+{
+   int kind,huge_pages,numa_node = MEMZONE_NUMA_ANY;
+
+   if (!strcmp(argv[1],"none"))
+      huge_pages = MEMZONE_HUGE_NONE;
+   else if (!strcmp(argv[1],"thp"))
+      huge_pages = MEMZONE_HUGE_THP;
+   else if (!strcmp(argv[1],"hugetlb"))
+      huge_pages = MEMZONE_HUGE_HUGETLB;
+   else
+      huge_pages = -1;
+
+   if (argc == 3)
+      numa_node = atoi(argv[2]);
+
+   if (((kind = hv_memzone_kind(argv[0])) == -1) ||
+       (memzone_set_opts(kind,huge_pages,numa_node) == -1))
+   {
+      hypervisor_send_reply(conn,HSC_ERR_INV_PARAM,1,
+                            "invalid memory zone options");
+      return(-1);
+   }
+
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
+/* Show the requested and actually used memory zone options */
+static int cmd_show_memzone_opts(hypervisor_conn_t *conn,
+                                 int argc,char *argv[])
+{
+   static char *names[] = { "none", "thp", "hugetlb" };
+   static char *kinds[MEMZONE_KIND_MAX] = { "ram", "exec" };
+   memzone_opts_t opts,used;
+   int kind;
+
+   for(kind=0;kind<MEMZONE_KIND_MAX;kind++) {
+      memzone_get_opts(kind,&opts,&used);
+      hypervisor_send_reply(conn,HSC_INFO_MSG,0,
+                            "%s: requested %s/%d, used %s/%d",kinds[kind],
+                            names[opts.huge_pages],opts.numa_node,
+                            names[used.huge_pages],used.numa_node);
+   }
+
+   hypervisor_send_reply(conn,HSC_INFO_OK,1,"OK");
+   return(0);
+}
+
 /* Save the hypervisor configuration in the specified file */
 static int cmd_save_config(hypervisor_conn_t *conn,int argc,char *argv[])
 {
@@ -181,6 +316,13 @@ static hypervisor_cmd_t hypervisor_cmd_array[] = {
    { "module_list", 0, 0, cmd_mod_list, NULL },
    { "cmd_list", 1, 1, cmd_modcmd_list, NULL },
    { "working_dir", 1, 1, cmd_set_working_dir, NULL },
+   { "hwid_seed", 1, 1, cmd_set_hwid_seed, NULL },
+   { "hash_seed", 2, 2, cmd_set_hash_seed, NULL },
+   { "log_filter", 0, 1, cmd_log_filter, NULL },
+   { "log_format", 1, 1, cmd_log_format, NULL },
+   { "log_rotate", 2, 2, cmd_log_rotate, NULL },
+   { "memzone_opts", 2, 3, cmd_memzone_opts, NULL },
+   { "show_memzone_opts", 0, 0, cmd_show_memzone_opts, NULL },
    { "save_config", 1, 1, cmd_save_config, NULL },
    { "reset", 0, 0, cmd_reset, NULL },
    { "close", 0, 0, cmd_close, NULL },
@@ -552,6 +694,29 @@ static hypervisor_conn_t *hypervisor_create_conn(int client_fd)
    return NULL;
 }
 
+/* Event loop callback of the control sockets: accept a connection */
+static void hypervisor_ev_accept(int fd,int events,void *opt)
+{
+   struct sockaddr_storage remote_addr;
+   socklen_t remote_len;
+   int clnt;
+
+   remote_len = sizeof(remote_addr);
+   clnt = accept(fd,(struct sockaddr *)&remote_addr,&remote_len);
+
+   if (clnt < 0) {
+      perror("hypervisor_tcp_server: accept");
+      return;
+   }
+
+   /* create a new connection and start a thread to handle it */
+   if (!hypervisor_create_conn(clnt)) {
+      fprintf(stderr,"hypervisor_tcp_server: unable to create new "
+              "connection for FD %d\n",clnt);
+      close(clnt);
+   }
+}
+
 /* Stop hypervisor from sighandler */
 int hypervisor_stopsig(void)
 {
@@ -563,11 +728,8 @@ int hypervisor_stopsig(void)
 int hypervisor_tcp_server(char *ip_addr,int tcp_port)
 {
    int fd_array[HYPERVISOR_MAX_FD];
-   struct sockaddr_storage remote_addr;
-   socklen_t remote_len;
-   int i,res,clnt,fd_count,fd_max;
-   struct timeval tv;
-   fd_set fds;
+   ev_loop_t *ev_loop;
+   int i,fd_count;
 
    /* Initialize all hypervisor modules */
    hypervisor_init();
@@ -580,6 +742,7 @@ int hypervisor_tcp_server(char *ip_addr,int tcp_port)
    hypervisor_vm_init();
    hypervisor_vm_debug_init();
    hypervisor_store_init();
+   hypervisor_metrics_init();
 
    signal(SIGPIPE,sigpipe_handler);
 
@@ -590,10 +753,22 @@ int hypervisor_tcp_server(char *ip_addr,int tcp_port)
                         HYPERVISOR_MAX_FD,fd_array);
 
    if (fd_count <= 0) {
-      fprintf(stderr,"Hypervisor: unable to create TCP sockets.\n");
+      fprintf(stderr,"Hypervisor: unable to create TCP sockets (%s).\n",
+              net_last_error_str());
       return(-1);
    }
 
+   if (!(ev_loop = ev_loop_create())) {
+      fprintf(stderr,"Hypervisor: unable to create the event loop.\n");
+      for(i=0;i<fd_count;i++)
+         close(fd_array[i]);
+      return(-1);
+   }
+
+   for(i=0;i<fd_count;i++)
+      if (fd_array[i] != -1)
+         ev_loop_add(ev_loop,fd_array[i],EV_READ,hypervisor_ev_accept,NULL);
+
    /* Start accepting connections */
    m_log("HYPERVISOR","Release %s/%s (tag %s)\n",
          sw_version,os_name,sw_version_tag);
@@ -611,52 +786,9 @@ int hypervisor_tcp_server(char *ip_addr,int tcp_port)
    hypervisor_running = TRUE;
 
    while(hypervisor_running) {
-      FD_ZERO(&fds);
-      fd_max = -1;
-
-      for(i=0;i<fd_count;i++)
-         if (fd_array[i] != -1) {
-            FD_SET(fd_array[i],&fds);
-            if (fd_array[i] > fd_max)
-               fd_max = fd_array[i];
-         }
-
-      /* Wait for incoming connections */
-      tv.tv_sec  = 0;
-      tv.tv_usec = 500 * 1000;  /* 500 ms */
-      res = select(fd_max+1,&fds,NULL,NULL,&tv);
-
-      if (res == -1) {
-         if (errno == EINTR)
-            continue;
-         else
-            perror("hypervisor_tcp_server: select");
-      }
-
-      /* Accept connections on signaled sockets */
-      for(i=0;i<fd_count;i++) {
-         if (fd_array[i] == -1)
-            continue;
-         
-         if (!FD_ISSET(fd_array[i],&fds))
-            continue;
-
-         remote_len = sizeof(remote_addr);
-         clnt = accept(fd_array[i],(struct sockaddr *)&remote_addr,
-                       &remote_len);
-
-         if (clnt < 0) {
-            perror("hypervisor_tcp_server: accept");
-            continue;
-         }
-            
-         /* create a new connection and start a thread to handle it */
-         if (!hypervisor_create_conn(clnt)) {
-            fprintf(stderr,"hypervisor_tcp_server: unable to create new "
-                    "connection for FD %d\n",clnt);
-            close(clnt);
-         }
-      }
+      /* Wait for incoming connections and accept them */
+      if (ev_loop_run_once(ev_loop,500) == -1)
+         usleep(500000);
 
       /* Walk through the connection list to eliminate dead connections */
       hypervisor_close_conn_list(TRUE);
@@ -664,6 +796,7 @@ int hypervisor_tcp_server(char *ip_addr,int tcp_port)
 
    /* Close all control sockets */
    printf("Hypervisor: closing control sockets.\n");
+   ev_loop_free(ev_loop);
    for(i=0;i<fd_count;i++) {
       if (fd_array[i] != -1) {
          shutdown(fd_array[i],2);
@@ -675,6 +808,9 @@ int hypervisor_tcp_server(char *ip_addr,int tcp_port)
    printf("Hypervisor: closing remote client connections.\n");
    hypervisor_close_conn_list(FALSE);
 
+   /* Stop the metrics exporter */
+   hypervisor_metrics_shutdown();
+
    m_log("HYPERVISOR","Stopped.\n");
    return(0);
 }
@@ -260,7 +260,8 @@ int vm_get_lock(vm_instance_t *vm)
 void vm_flog(vm_instance_t *vm,char *module,char *format,va_list ap)
 {
    if (vm->log_fd)
-      m_flog(vm->log_fd,module,format,ap);
+      m_flog_lvl(vm->log_fd,M_LOG_INFO,vm->name,vm->instance_id,
+                 module,format,ap);
 }
 
 /* Log a message */
@@ -355,6 +356,7 @@ static vm_instance_t *vm_create(char *name,int instance_id,
    }
    
    memset(vm,0,sizeof(*vm));
+   pthread_mutex_init(&vm->lock,NULL);
 
    if (!(vm->name = strdup(name))) {
       fprintf(stderr,"VM %s: unable to store instance name!\n",name);
@@ -376,6 +378,9 @@ static vm_instance_t *vm_create(char *name,int instance_id,
    if (!vm->rommon_vars.filename)
       goto err_rommon;
 
+   vm->vtty_con_rec = vtty_rec_create(VTTY_REC_DEFAULT_SCROLLBACK);
+   vm->vtty_aux_rec = vtty_rec_create(VTTY_REC_DEFAULT_SCROLLBACK);
+
    /* XXX */
    rommon_load_file(&vm->rommon_vars);
 
@@ -401,6 +406,8 @@ static vm_instance_t *vm_create(char *name,int instance_id,
  err_log:
    free(vm->lock_file);
  err_lock:
+   vtty_rec_free(vm->vtty_con_rec);
+   vtty_rec_free(vm->vtty_aux_rec);
    free(vm->rommon_vars.filename);
  err_rommon:
    free(vm->name);
@@ -455,9 +462,11 @@ int vm_hardware_shutdown(vm_instance_t *vm)
 
    /* Delete system CPU group */
    vm_log(vm,"VM","deleting system CPUs.\n");
+   VM_LOCK(vm);
    cpu_group_delete(vm->cpu_group);
    vm->cpu_group = NULL;
    vm->boot_cpu = NULL;
+   VM_UNLOCK(vm);
 
    vm_log(vm,"VM","shutdown procedure completed.\n");
    m_log("VM","VM %s shutdown.\n",vm->name);
@@ -473,6 +482,10 @@ void vm_free(vm_instance_t *vm)
 
       m_log("VM","VM %s destroyed.\n",vm->name);
 
+      /* Free the console recorders */
+      vtty_rec_free(vm->vtty_con_rec);
+      vtty_rec_free(vm->vtty_aux_rec);
+
       /* Close log file */
       vm_close_log(vm);
 
@@ -482,6 +495,9 @@ void vm_free(vm_instance_t *vm)
       /* Free all chunks */
       vm_chunk_free_all(vm);
 
+      /* Release the hardware identifiers */
+      hwid_release(vm->name,vm->instance_id);
+
       /* Free various elements */
       free(vm->rommon_vars.filename);
       free(vm->ghost_ram_filename);
@@ -491,6 +507,7 @@ void vm_free(vm_instance_t *vm)
       free(vm->ios_private_config);
       free(vm->rom_filename);
       free(vm->name);
+      pthread_mutex_destroy(&vm->lock);
       free(vm);
    }
 }
@@ -535,12 +552,29 @@ int vm_init_vtty(vm_instance_t *vm)
    vm->vtty_aux = vtty_create(vm,"AUX port",
                               vm->vtty_aux_type,vm->vtty_aux_tcp_port,
                               &vm->vtty_aux_serial_option);
+
+   /* Attach the recorders */
+   if (vm->vtty_con) {
+      vm->vtty_con->rec = vm->vtty_con_rec;
+      vtty_rec_marker(vm->vtty_con_rec,"VM started");
+   }
+
+   if (vm->vtty_aux) {
+      vm->vtty_aux->rec = vm->vtty_aux_rec;
+      vtty_rec_marker(vm->vtty_aux_rec,"VM started");
+   }
    return(0);
 }
 
 /* Delete VTTY */
 void vm_delete_vtty(vm_instance_t *vm)
 {
+   if (vm->vtty_con)
+      vtty_rec_marker(vm->vtty_con_rec,"VM stopped");
+
+   if (vm->vtty_aux)
+      vtty_rec_marker(vm->vtty_aux_rec,"VM stopped");
+
    vtty_delete(vm->vtty_con);
    vtty_delete(vm->vtty_aux);
    vm->vtty_con = vm->vtty_aux = NULL;